@@ -0,0 +1,395 @@
+window.addEventListener('DOMContentLoaded', function () {
+  if (window.renderMathInElement) {
+    window.renderMathInElement(document.body, {
+      delimiters: [
+        { left: '$$', right: '$$', display: true },
+        { left: '$', right: '$', display: false },
+        { left: '\\(', right: '\\)', display: false },
+        { left: '\\[', right: '\\]', display: true }
+      ],
+      throwOnError: false
+    });
+  }
+
+  wrapTables();
+  installCodeCopyButtons();
+  installToolbar();
+  installImageLightbox();
+  installLiveDashboard();
+  installNewPasteForm();
+});
+
+function copyText(value) {
+  if (!value) {
+    return Promise.resolve(false);
+  }
+  if (navigator.clipboard && navigator.clipboard.writeText) {
+    return navigator.clipboard.writeText(value).then(function () { return true; }).catch(function () { return fallbackCopy(value); });
+  }
+  return Promise.resolve(fallbackCopy(value));
+}
+
+function fallbackCopy(value) {
+  var ta = document.createElement('textarea');
+  ta.value = value;
+  ta.style.position = 'fixed';
+  ta.style.opacity = '0';
+  document.body.appendChild(ta);
+  ta.focus();
+  ta.select();
+  var ok = false;
+  try {
+    ok = document.execCommand('copy');
+  } catch (_err) {
+    ok = false;
+  }
+  ta.remove();
+  return ok;
+}
+
+function setButtonTextTemporarily(button, message) {
+  if (!button) {
+    return;
+  }
+  var original = button.textContent;
+  button.textContent = message;
+  window.setTimeout(function () {
+    button.textContent = original;
+  }, 1200);
+}
+
+function installToolbar() {
+  var plainButton = document.getElementById('copy-plain');
+  var rawButton = document.getElementById('copy-raw');
+  var renderedButton = document.getElementById('copy-rendered');
+  var linkButton = document.getElementById('copy-link');
+  var source = document.getElementById('raw-markdown');
+  var rendered = document.getElementById('paste-content');
+  var canonical = document.body.getAttribute('data-canonical-url');
+
+  if (plainButton) {
+    plainButton.addEventListener('click', function () {
+      fetch(plainButton.getAttribute('data-src'))
+        .then(function (res) { return res.ok ? res.text() : Promise.reject(res.status); })
+        .catch(function () { return source ? source.value : ''; })
+        .then(copyText)
+        .then(function (ok) {
+          setButtonTextTemporarily(plainButton, ok ? 'Copied' : 'Failed');
+        });
+    });
+  }
+
+  if (rawButton) {
+    rawButton.addEventListener('click', function () {
+      copyText(source ? source.value : '').then(function (ok) {
+        setButtonTextTemporarily(rawButton, ok ? 'Copied' : 'Failed');
+      });
+    });
+  }
+
+  if (renderedButton) {
+    renderedButton.addEventListener('click', function () {
+      copyText(rendered ? rendered.innerText : '').then(function (ok) {
+        setButtonTextTemporarily(renderedButton, ok ? 'Copied' : 'Failed');
+      });
+    });
+  }
+
+  if (linkButton) {
+    linkButton.addEventListener('click', function () {
+      var path = canonical || window.location.pathname;
+      copyText(window.location.origin + path).then(function (ok) {
+        setButtonTextTemporarily(linkButton, ok ? 'Copied' : 'Failed');
+      });
+    });
+  }
+}
+
+function installCodeCopyButtons() {
+  var codeBlocks = document.querySelectorAll('pre > code');
+  codeBlocks.forEach(function (code) {
+    var pre = code.parentElement;
+    if (!pre || pre.querySelector(':scope > .code-copy')) {
+      return;
+    }
+
+    var button = document.createElement('button');
+    button.type = 'button';
+    button.className = 'code-copy';
+    button.textContent = 'Copy';
+    button.addEventListener('click', function () {
+      copyText(code.textContent || '').then(function (ok) {
+        setButtonTextTemporarily(button, ok ? 'Copied' : 'Failed');
+      });
+    });
+    pre.appendChild(button);
+  });
+}
+
+function wrapTables() {
+  var root = document.getElementById('paste-content') || document.body;
+  var tables = root.querySelectorAll('table');
+  tables.forEach(function (table) {
+    if (table.parentElement && table.parentElement.classList.contains('table-wrap')) {
+      return;
+    }
+    var wrapper = document.createElement('div');
+    wrapper.className = 'table-wrap';
+    table.parentNode.insertBefore(wrapper, table);
+    wrapper.appendChild(table);
+  });
+}
+
+function installImageLightbox() {
+  var root = document.getElementById('paste-content');
+  if (!root) {
+    return;
+  }
+
+  var images = root.querySelectorAll('img');
+  if (!images.length) {
+    return;
+  }
+
+  var lightbox = document.createElement('div');
+  lightbox.id = 'lightbox';
+  lightbox.innerHTML = '<img alt="Expanded image">';
+  var lightboxImg = lightbox.querySelector('img');
+  lightbox.addEventListener('click', function () {
+    lightbox.classList.remove('open');
+  });
+  document.body.appendChild(lightbox);
+
+  images.forEach(function (img) {
+    img.style.cursor = 'zoom-in';
+    img.addEventListener('click', function () {
+      lightboxImg.src = img.currentSrc || img.src;
+      lightboxImg.alt = img.alt || 'Expanded image';
+      lightbox.classList.add('open');
+    });
+  });
+}
+
+function installLiveDashboard() {
+  var rows = document.getElementById('recent-rows');
+  if (!rows || !window.WebSocket) {
+    return;
+  }
+
+  // Set inside a namespace, whose pages live under `/ns/{name}`.
+  var base = rows.getAttribute('data-base') || '';
+  var lastSeq = null;
+  var retryMs = 1000;
+
+  function connect() {
+    var scheme = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+    var url = scheme + '//' + window.location.host + rows.getAttribute('data-live');
+    if (lastSeq !== null) {
+      url += (url.indexOf('?') === -1 ? '?' : '&') + 'since=' + lastSeq;
+    }
+    var socket = new WebSocket(url);
+    socket.addEventListener('open', function () {
+      retryMs = 1000;
+    });
+    socket.addEventListener('message', function (msg) {
+      var event;
+      try {
+        event = JSON.parse(msg.data);
+      } catch (_err) {
+        return;
+      }
+      lastSeq = event.seq;
+      if (event.type === 'created') {
+        addRow(event);
+      } else if (event.type === 'deleted') {
+        removeRow(event.id);
+      }
+    });
+    socket.addEventListener('close', function () {
+      window.setTimeout(connect, retryMs);
+      retryMs = Math.min(retryMs * 2, 30000);
+    });
+  }
+
+  function findRow(id) {
+    var found = null;
+    rows.querySelectorAll('tr[data-id]').forEach(function (row) {
+      if (row.getAttribute('data-id') === id) {
+        found = row;
+      }
+    });
+    return found;
+  }
+
+  function removeRow(id) {
+    var row = findRow(id);
+    if (row) {
+      row.remove();
+    }
+  }
+
+  function addRow(paste) {
+    if (findRow(paste.id)) {
+      return;
+    }
+    var empty = rows.querySelector('tr.empty');
+    if (empty) {
+      empty.remove();
+    }
+    var id = encodeURIComponent(paste.id);
+    var file = paste.path.split('/').pop();
+    var stem = file.lastIndexOf('.') > 0 ? file.slice(0, file.lastIndexOf('.')) : file;
+    var slug = stem.indexOf('__') === -1 ? '' : stem.slice(stem.indexOf('__') + 2);
+
+    var row = document.createElement('tr');
+    row.setAttribute('data-id', paste.id);
+    row.className = 'live-new';
+    var idCell = document.createElement('td');
+    idCell.appendChild(link(base + '/p/' + id, paste.id));
+    row.appendChild(idCell);
+    [paste.created_at, paste.tag || '-', paste.content_type, String(paste.size), String(paste.stars)]
+      .forEach(function (text) {
+        var cell = document.createElement('td');
+        cell.textContent = text;
+        row.appendChild(cell);
+      });
+    var links = document.createElement('td');
+    links.className = 'links';
+    links.appendChild(link(base + '/api/v1/p/' + id, 'meta'));
+    links.appendChild(document.createTextNode(' · '));
+    links.appendChild(link(base + '/p/' + id + '/md', 'md'));
+    links.appendChild(document.createTextNode(' · '));
+    links.appendChild(link(base + '/p/' + id + '/' + encodeURIComponent(slug || 'paste'), 'legacy'));
+    links.appendChild(document.createTextNode(' · '));
+    var path = document.createElement('code');
+    path.textContent = paste.path;
+    links.appendChild(path);
+    row.appendChild(links);
+    rows.insertBefore(row, rows.firstChild);
+  }
+
+  function link(href, text) {
+    var a = document.createElement('a');
+    a.href = href;
+    a.textContent = text;
+    return a;
+  }
+
+  connect();
+}
+
+// The `/new` page: posts the text, or a picked or dropped file in its place,
+// to `/api/v1/paste` and opens the paste it created.
+function installNewPasteForm() {
+  var form = document.getElementById('new-paste');
+  if (!form || !window.fetch) {
+    return;
+  }
+
+  var fields = form.elements;
+  var contentField = fields.namedItem('content');
+  var fileField = fields.namedItem('file');
+  var nameField = fields.namedItem('name');
+  var tagField = fields.namedItem('tag');
+  var credentialField = fields.namedItem('credential');
+  var rememberField = fields.namedItem('remember');
+  var base = form.getAttribute('data-base') || '';
+  var authHeader = form.getAttribute('data-auth-header');
+  var status = document.getElementById('new-paste-status');
+  var fileNote = document.getElementById('new-paste-file');
+  var storageKey = 'lanpaste.credential';
+  var file = null;
+
+  if (credentialField) {
+    try {
+      var saved = window.localStorage.getItem(storageKey);
+      if (saved) {
+        credentialField.value = saved;
+        rememberField.checked = true;
+      }
+    } catch (_err) {
+      // Storage can be disabled; the field is just left empty.
+    }
+  }
+
+  function useFile(picked) {
+    file = picked || null;
+    if (!file) {
+      return;
+    }
+    fileNote.textContent = 'Uploading ' + file.name + ' (' + file.size + ' bytes) in place of the text.';
+    if (!nameField.value) {
+      nameField.value = file.name;
+    }
+  }
+
+  fileField.addEventListener('change', function () {
+    useFile(fileField.files[0]);
+  });
+  form.addEventListener('dragover', function (event) {
+    event.preventDefault();
+    form.classList.add('dragging');
+  });
+  form.addEventListener('dragleave', function () {
+    form.classList.remove('dragging');
+  });
+  form.addEventListener('drop', function (event) {
+    event.preventDefault();
+    form.classList.remove('dragging');
+    if (event.dataTransfer && event.dataTransfer.files.length) {
+      useFile(event.dataTransfer.files[0]);
+    }
+  });
+
+  form.addEventListener('submit', function (event) {
+    event.preventDefault();
+    var body = file || contentField.value;
+    if (!file && !body.trim()) {
+      status.textContent = 'Nothing to paste yet.';
+      return;
+    }
+
+    var params = new URLSearchParams();
+    if (nameField.value.trim()) {
+      params.set('name', nameField.value.trim());
+    }
+    if (tagField.value.trim()) {
+      params.set('tag', tagField.value.trim());
+    }
+    var headers = {
+      'Content-Type': file ? (file.type || 'application/octet-stream') : 'text/plain; charset=utf-8'
+    };
+    if (authHeader && credentialField) {
+      headers[authHeader] = credentialField.value;
+      try {
+        if (rememberField.checked) {
+          window.localStorage.setItem(storageKey, credentialField.value);
+        } else {
+          window.localStorage.removeItem(storageKey);
+        }
+      } catch (_err) {
+        // Not remembered; the paste still goes out.
+      }
+    }
+
+    var query = params.toString();
+    var button = form.querySelector('button[type="submit"]');
+    button.disabled = true;
+    status.textContent = 'Creating…';
+    fetch(base + '/api/v1/paste' + (query ? '?' + query : ''), {
+      method: 'POST',
+      headers: headers,
+      body: body
+    }).then(function (res) {
+      return res.json().catch(function () { return {}; }).then(function (data) {
+        if (!res.ok) {
+          throw new Error(data.message || ('HTTP ' + res.status));
+        }
+        window.location.assign(data.view_url || (base + '/p/' + data.id));
+      });
+    }).catch(function (err) {
+      button.disabled = false;
+      status.textContent = 'Could not create the paste: ' + err.message;
+    });
+  });
+}
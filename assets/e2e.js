@@ -0,0 +1,35 @@
+// Decrypts client-side: fetches the ciphertext from `raw.txt` and opens it
+// with the AES-256-GCM key in the URL fragment (`#key=<base64url>`), which
+// browsers never send to the server. Layout is `iv (12 bytes) || ciphertext`.
+(function () {
+  var shell = document.getElementById('e2e-paste');
+  var status = document.getElementById('e2e-status');
+  var out = document.getElementById('e2e-plain');
+  var match = window.location.hash.match(/(?:^#|&)key=([A-Za-z0-9_-]+)/);
+  if (!match) {
+    status.textContent = 'This paste is end-to-end encrypted. Open the full link including #key=... to read it.';
+    return;
+  }
+  if (!window.crypto || !window.crypto.subtle) {
+    status.textContent = 'Decryption needs a secure context (HTTPS or localhost).';
+    return;
+  }
+  var b64 = match[1].replace(/-/g, '+').replace(/_/g, '/');
+  b64 += '==='.slice((b64.length + 3) % 4);
+  var rawKey = Uint8Array.from(atob(b64), function (c) { return c.charCodeAt(0); });
+  Promise.all([
+    crypto.subtle.importKey('raw', rawKey, 'AES-GCM', false, ['decrypt']),
+    fetch(shell.getAttribute('data-src')).then(function (res) {
+      return res.ok ? res.arrayBuffer() : Promise.reject(res.status);
+    })
+  ]).then(function (parts) {
+    var data = new Uint8Array(parts[1]);
+    return crypto.subtle.decrypt({ name: 'AES-GCM', iv: data.slice(0, 12) }, parts[0], data.slice(12));
+  }).then(function (plain) {
+    out.textContent = new TextDecoder().decode(plain);
+    out.hidden = false;
+    status.textContent = '';
+  }).catch(function () {
+    status.textContent = 'Could not decrypt this paste: wrong key or damaged data.';
+  });
+})();
@@ -0,0 +1,5 @@
+// Renders ```mermaid fences; loaded as a module so the import needs no
+// inline script.
+import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@11/dist/mermaid.esm.min.mjs';
+
+mermaid.initialize({ startOnLoad: true, securityLevel: 'strict' });
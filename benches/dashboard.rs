@@ -0,0 +1,80 @@
+//! Dashboard render time from the in-memory index with 50k pastes.
+//!
+//! `cargo bench --bench dashboard` fails if p99 exceeds the budget. The page
+//! is kept open all day, so it has to stay cheap as the repo grows. Debug
+//! builds (`cargo test --all-targets`) only report the numbers.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use lanpaste::{
+    index::MetaIndex,
+    render,
+    types::{PasteMeta, Visibility},
+};
+use time::OffsetDateTime;
+
+const PASTES: usize = 50_000;
+const RUNS: usize = 2_000;
+const P99_BUDGET: Duration = Duration::from_millis(3);
+
+fn synthetic(i: usize, start: OffsetDateTime) -> PasteMeta {
+    let id = format!("01J{i:023}");
+    PasteMeta {
+        created_at: start + time::Duration::seconds(i as i64),
+        path: format!("pastes/2026/10/16/{id}__paste-{i}.md"),
+        slug: Some(format!("paste-{i}")),
+        size: 100 + i % 4096,
+        content_type: "text/markdown; charset=utf-8".to_string(),
+        sha256: format!("{i:064x}"),
+        tag: Some(format!("tag-{}", i % 40)),
+        visibility: if i.is_multiple_of(10) {
+            Visibility::Unlisted
+        } else {
+            Visibility::Listed
+        },
+        superseded_by: i.is_multiple_of(25).then(|| "01J0".to_string()),
+        ..PasteMeta::for_test(&id)
+    }
+}
+
+fn p99(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() * 99 / 100]
+}
+
+fn measure(index: &MetaIndex, tag: Option<&str>) -> Duration {
+    let samples = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            let (items, tags) = index.recent(50, tag, false);
+            let html = render::render_dashboard("", &items, &tags, tag);
+            std::hint::black_box(html);
+            started.elapsed()
+        })
+        .collect();
+    p99(samples)
+}
+
+fn main() {
+    let start = OffsetDateTime::now_utc() - time::Duration::days(30);
+    let metas = (0..PASTES).map(|i| synthetic(i, start)).collect();
+    let stars = (0..PASTES)
+        .step_by(7)
+        .map(|i| (format!("01J{i:023}"), i % 5 + 1))
+        .collect::<HashMap<_, _>>();
+    let index = MetaIndex::new(metas, stars);
+
+    let mut failed = false;
+    for (label, tag) in [("all", None), ("tag", Some("tag-7"))] {
+        let p99 = measure(&index, tag);
+        println!("dashboard/{label}: p99 {p99:?} over {RUNS} renders of {PASTES} pastes");
+        failed |= p99 > P99_BUDGET;
+    }
+    if cfg!(debug_assertions) {
+        return;
+    }
+    assert!(!failed, "dashboard p99 is over {P99_BUDGET:?}");
+}
@@ -0,0 +1,34 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+/// Generates the tonic server and client for `proto/lanpaste.proto`. The
+/// messages are hand-written in `src/grpc.rs`, so no `protoc` is needed.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    println!("cargo:rerun-if-changed=build.rs");
+    let method = |name: &str, route: &str, input: &str, output: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::proto::{input}"))
+            .output_type(format!("crate::grpc::proto::{output}"))
+            .codec_path("tonic_prost::ProstCodec")
+    };
+    let service = Service::builder()
+        .name("Pastes")
+        .package("lanpaste.v1")
+        .method(method("create", "Create", "CreateRequest", "CreateResponse").build())
+        .method(method("get", "Get", "GetRequest", "Paste").build())
+        .method(method("recent", "Recent", "RecentRequest", "RecentResponse").build())
+        .method(
+            method("watch", "Watch", "WatchRequest", "PasteEvent")
+                .server_streaming()
+                .build(),
+        )
+        .build();
+    Builder::new().build_transport(false).compile(&[service]);
+}
@@ -0,0 +1,312 @@
+//! Runtime counters and the status snapshot behind `/admin`.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    auth::KeyUsage,
+    config::PushMode,
+    errors::{AppError, AppResult},
+    gitops::{self, FileLock},
+    selftest::SelfTestReport,
+    types::{AppState, GitCommitResult, PasteMeta, push_mode_label},
+};
+
+const RECENT_ERRORS: usize = 50;
+
+/// Live operational state shared by every request.
+#[derive(Debug)]
+pub struct Ops {
+    started_at: OffsetDateTime,
+    maintenance: AtomicBool,
+    writes_in_flight: AtomicUsize,
+    uploads_staging: AtomicUsize,
+    push: Mutex<PushHealth>,
+    errors: Mutex<VecDeque<RecordedError>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PushHealth {
+    pub last_success: Option<OffsetDateTime>,
+    pub last_failure: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+    pub failures: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordedError {
+    pub at: OffsetDateTime,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub message: String,
+}
+
+/// Decrements its counter when dropped.
+pub struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Ops {
+    fn default() -> Self {
+        Self {
+            started_at: OffsetDateTime::now_utc(),
+            maintenance: AtomicBool::new(false),
+            writes_in_flight: AtomicUsize::new(0),
+            uploads_staging: AtomicUsize::new(0),
+            push: Mutex::new(PushHealth::default()),
+            errors: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl Ops {
+    pub fn started_at(&self) -> OffsetDateTime {
+        self.started_at
+    }
+
+    pub fn maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maintenance(&self, on: bool) {
+        self.maintenance.store(on, Ordering::Relaxed);
+    }
+
+    pub fn track_write(&self) -> InFlight<'_> {
+        self.writes_in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(&self.writes_in_flight)
+    }
+
+    pub fn track_staging(&self) -> InFlight<'_> {
+        self.uploads_staging.fetch_add(1, Ordering::Relaxed);
+        InFlight(&self.uploads_staging)
+    }
+
+    /// Notes the push outcome of a commit; nothing to record with push off.
+    pub fn record_commit(&self, mode: PushMode, commit: &GitCommitResult) {
+        if mode == PushMode::Off {
+            return;
+        }
+        match &commit.push_error {
+            None => self.record_push(Ok(())),
+            Some(err) => self.record_push(Err(err)),
+        }
+    }
+
+    pub fn record_push(&self, outcome: Result<(), &str>) {
+        let Ok(mut push) = self.push.lock() else {
+            return;
+        };
+        let now = OffsetDateTime::now_utc();
+        match outcome {
+            Ok(()) => push.last_success = Some(now),
+            Err(err) => {
+                push.last_failure = Some(now);
+                push.last_error = Some(err.to_string());
+                push.failures += 1;
+            }
+        }
+    }
+
+    pub fn record_error(&self, error: RecordedError) {
+        if let Ok(mut errors) = self.errors.lock() {
+            if errors.len() == RECENT_ERRORS {
+                errors.pop_back();
+            }
+            errors.push_front(error);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueDepths {
+    pub writes_in_flight: usize,
+    pub uploads_staging: usize,
+    /// Writes waiting behind the one being committed.
+    pub commits_queued: usize,
+    pub pending_enrollments: usize,
+    pub renders_running: usize,
+    pub renders_queued: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageStats {
+    pub pastes: usize,
+    pub paste_bytes: u64,
+    pub oldest: Option<OffsetDateTime>,
+    pub newest: Option<OffsetDateTime>,
+    pub files: usize,
+    pub file_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminStatus {
+    pub version: &'static str,
+    pub started_at: OffsetDateTime,
+    pub uptime_secs: i64,
+    pub maintenance: bool,
+    pub push_mode: &'static str,
+    pub remote: String,
+    pub push: PushHealth,
+    pub queues: QueueDepths,
+    pub storage: StorageStats,
+    pub keys: Vec<KeyUsage>,
+    pub self_test: Option<SelfTestReport>,
+    pub recent_errors: Vec<RecordedError>,
+}
+
+pub fn status(state: &AppState) -> AppResult<AdminStatus> {
+    let ops = &state.ops;
+    let now = OffsetDateTime::now_utc();
+    Ok(AdminStatus {
+        version: env!("CARGO_PKG_VERSION"),
+        started_at: ops.started_at,
+        uptime_secs: (now - ops.started_at).whole_seconds(),
+        maintenance: ops.maintenance(),
+        push_mode: push_mode_label(state.cfg.push),
+        remote: state.cfg.remote.clone(),
+        push: ops.push.lock().map(|p| p.clone()).unwrap_or_default(),
+        queues: QueueDepths {
+            writes_in_flight: ops.writes_in_flight.load(Ordering::Relaxed),
+            uploads_staging: ops.uploads_staging.load(Ordering::Relaxed),
+            commits_queued: state.committer.queued(),
+            pending_enrollments: crate::enroll::pending(&state.kv)?.len(),
+            renders_running: state.renders.running(),
+            renders_queued: state.renders.queued(),
+        },
+        storage: storage_stats(&state.paths.repo, &state.paths.files)?,
+        keys: state.api_keys.usage(),
+        self_test: state.self_test.lock().ok().and_then(|last| last.clone()),
+        recent_errors: ops
+            .errors
+            .lock()
+            .map(|e| e.iter().cloned().collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// Pushes `HEAD` to the configured remote now, whatever `--push` says.
+pub fn sync_now(state: &AppState) -> AppResult<()> {
+    let _git_lock = FileLock::acquire(&state.paths.git_lock).map_err(|err| match err {
+        AppError::Conflict(_) => {
+            AppError::Conflict("a write is in progress; try again".to_string())
+        }
+        other => other,
+    })?;
+    let pushed = gitops::run_git(
+        &state.paths.repo,
+        &["push", &state.cfg.remote, "HEAD"],
+        &state.cfg,
+    );
+    match pushed {
+        Ok(_) => {
+            state.ops.record_push(Ok(()));
+            Ok(())
+        }
+        Err(err) => {
+            let message = format!("{err:?}");
+            state.ops.record_push(Err(&message));
+            Err(AppError::ServiceUnavailable(format!(
+                "push failed: {message}"
+            )))
+        }
+    }
+}
+
+fn storage_stats(repo: &Path, files: &Path) -> AppResult<StorageStats> {
+    let mut stats = StorageStats::default();
+    if let Ok(entries) = fs::read_dir(repo.join("meta")) {
+        for entry in entries.flatten() {
+            let Ok(data) = fs::read(entry.path()) else {
+                continue;
+            };
+            let Ok(meta) = serde_json::from_slice::<PasteMeta>(&data) else {
+                continue;
+            };
+            stats.pastes += 1;
+            stats.paste_bytes += meta.size as u64;
+            stats.oldest = Some(
+                stats
+                    .oldest
+                    .map_or(meta.created_at, |t| t.min(meta.created_at)),
+            );
+            stats.newest = Some(
+                stats
+                    .newest
+                    .map_or(meta.created_at, |t| t.max(meta.created_at)),
+            );
+        }
+    }
+    let entries = match fs::read_dir(files) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stats),
+        Err(e) => return Err(AppError::io("read files dir", e)),
+    };
+    for entry in entries.flatten() {
+        if let Ok(meta) = entry.metadata()
+            && meta.is_file()
+        {
+            stats.files += 1;
+            stats.file_bytes += meta.len();
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_log_keeps_newest_first_and_is_bounded() {
+        let ops = Ops::default();
+        for status in 0..(RECENT_ERRORS as u16 + 5) {
+            ops.record_error(RecordedError {
+                at: OffsetDateTime::now_utc(),
+                method: "GET".to_string(),
+                path: "/".to_string(),
+                status,
+                message: String::new(),
+            });
+        }
+        let errors = ops.errors.lock().expect("errors");
+        assert_eq!(errors.len(), RECENT_ERRORS);
+        assert_eq!(errors[0].status, RECENT_ERRORS as u16 + 4);
+    }
+
+    #[test]
+    fn push_health_ignores_push_off() {
+        let ops = Ops::default();
+        let failed = GitCommitResult {
+            commit: "abc".to_string(),
+            pushed: false,
+            push_error: Some("no remote".to_string()),
+        };
+        ops.record_commit(PushMode::Off, &failed);
+        assert_eq!(ops.push.lock().expect("push").failures, 0);
+        ops.record_commit(PushMode::BestEffort, &failed);
+        let push = ops.push.lock().expect("push").clone();
+        assert_eq!(push.failures, 1);
+        assert_eq!(push.last_error.as_deref(), Some("no remote"));
+
+        let _write = ops.track_write();
+        assert_eq!(ops.writes_in_flight.load(Ordering::Relaxed), 1);
+        drop(_write);
+        assert_eq!(ops.writes_in_flight.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,159 @@
+//! Archival: moves pastes older than a cutoff out of the live repo into a
+//! dated `.tar.gz` and removes them from the working tree in one commit,
+//! keeping `/recent` and the checkout small. Git history still has them.
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, write::GzEncoder};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::{
+    config::{ArchiveCmd, ServeCmd},
+    errors::{AppError, AppResult},
+    events::EventKind,
+    gitops::{self, FileLock},
+    preflight, store,
+    types::{AppPaths, AppState, PasteMeta},
+};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ArchiveReport {
+    pub archived: Vec<String>,
+    pub bytes: u64,
+    pub tarball: Option<PathBuf>,
+    pub commit: Option<String>,
+}
+
+impl ArchiveReport {
+    pub fn summary(&self) -> String {
+        match &self.tarball {
+            None => "nothing to archive\n".to_string(),
+            Some(path) => format!(
+                "archived {} pastes ({} bytes) to {}\n  commit: {}\n",
+                self.archived.len(),
+                self.bytes,
+                path.display(),
+                self.commit.as_deref().unwrap_or("none"),
+            ),
+        }
+    }
+}
+
+/// `--archive-dir`, else `<dir>/archive`.
+pub fn default_dir(cfg: &ServeCmd, paths: &AppPaths) -> PathBuf {
+    cfg.archive_dir
+        .clone()
+        .unwrap_or_else(|| paths.base.join("archive"))
+}
+
+/// Archives every paste created more than `older_than_days` ago into a new
+/// tarball under `dest`. The tarball is written and synced before anything
+/// is removed. Blocks on git and disk I/O.
+pub fn run(state: &AppState, older_than_days: u64, dest: &Path) -> AppResult<ArchiveReport> {
+    let _git_lock = FileLock::acquire_waiting(&state.paths.git_lock)?;
+    let repo = &state.paths.repo;
+    let now = OffsetDateTime::now_utc();
+    let max_age = time::Duration::days(older_than_days.min(i64::MAX as u64 / 86_400) as i64);
+    let mut old: Vec<PasteMeta> = store::read_all_metas(repo)?
+        .into_iter()
+        .filter(|meta| now - meta.created_at > max_age)
+        .collect();
+    if old.is_empty() {
+        return Ok(ArchiveReport::default());
+    }
+    old.sort_by_key(|meta| meta.created_at);
+
+    let mut report = ArchiveReport::default();
+    let mut files = Vec::new();
+    for meta in &old {
+        files.extend(store::paste_files(repo, meta)?);
+        report.archived.push(meta.id.clone());
+        report.bytes += meta.size as u64;
+    }
+    let tarball = write_tarball(repo, dest, &files, now)?;
+    let name = tarball
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let files: Vec<&str> = files.iter().map(String::as_str).collect();
+    let subject = format!("archive: move {} pastes to {name}", old.len());
+    let commit = gitops::commit_removal(
+        repo,
+        &state.cfg,
+        &files,
+        &subject,
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.ops.record_commit(state.cfg.push, &commit);
+    state.index.remove(&report.archived);
+    for meta in &old {
+        state
+            .events
+            .publish(EventKind::Deleted, meta, &commit.commit);
+    }
+    if let Some(err) = &commit.push_error {
+        warn!("best-effort push failed: {err}");
+    }
+    report.tarball = Some(tarball);
+    report.commit = Some(commit.commit);
+    Ok(report)
+}
+
+/// `lanpaste archive`: the same job against a stopped server's directory.
+pub fn run_offline(cmd: &ArchiveCmd) -> AppResult<ArchiveReport> {
+    let cfg = ServeCmd::offline(&cmd.dir, &cmd.git_author_name, &cmd.git_author_email)
+        .map_err(|e| AppError::internal(format!("build serve config: {e}")))?;
+    preflight::run_preflight(&cfg)?;
+    let state = preflight::build_state(cfg)?;
+    let dest = cmd
+        .to
+        .clone()
+        .unwrap_or_else(|| default_dir(&state.cfg, &state.paths));
+    run(&state, cmd.older_than_days, &dest)
+}
+
+/// Writes `files` (repo-relative, kept as the entry names) to
+/// `dest/pastes-<timestamp>.tar.gz` via a temporary name, so a crash never
+/// leaves a truncated tarball under the final name.
+fn write_tarball(
+    repo: &Path,
+    dest: &Path,
+    files: &[String],
+    now: OffsetDateTime,
+) -> AppResult<PathBuf> {
+    fs::create_dir_all(dest).map_err(|e| AppError::io("create archive dir", e))?;
+    let format = time::format_description::parse("[year]-[month]-[day]T[hour][minute][second]Z")
+        .map_err(|e| AppError::internal(format!("archive name format: {e}")))?;
+    let stamp = now
+        .format(&format)
+        .map_err(|e| AppError::internal(format!("archive name: {e}")))?;
+    let name = format!("pastes-{stamp}.tar.gz");
+    let path = dest.join(&name);
+    if path.exists() {
+        return Err(AppError::Conflict(format!(
+            "archive {} already exists",
+            path.display()
+        )));
+    }
+    let partial = dest.join(format!(".{name}.partial"));
+    let written = (|| {
+        let file = File::create(&partial)?;
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        for rel in files {
+            tar.append_path_with_name(repo.join(rel), rel)?;
+        }
+        tar.into_inner()?.finish()?.sync_all()
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(AppError::io("write archive", e));
+    }
+    fs::rename(&partial, &path).map_err(|e| AppError::io("finish archive", e))?;
+    Ok(path)
+}
@@ -0,0 +1,130 @@
+//! Static assets compiled into the binary and served at fingerprinted paths
+//! (`/assets/app.<hash>.css`). The hash covers the content, so responses can
+//! be cached as immutable; pages link through [`url`], which always names
+//! the current build's fingerprint.
+
+use std::sync::OnceLock;
+
+use sha2::{Digest, Sha256};
+
+/// Hex digits of the content sha256 kept in the file name.
+const FINGERPRINT_LEN: usize = 10;
+
+const SOURCES: [(&str, &str, &str); 5] = [
+    (
+        "app.css",
+        "text/css; charset=utf-8",
+        include_str!("../assets/app.css"),
+    ),
+    (
+        "print.css",
+        "text/css; charset=utf-8",
+        include_str!("../assets/print.css"),
+    ),
+    (
+        "app.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../assets/app.js"),
+    ),
+    (
+        "e2e.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../assets/e2e.js"),
+    ),
+    (
+        "mermaid.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../assets/mermaid.js"),
+    ),
+];
+
+#[derive(Debug)]
+pub struct Asset {
+    /// Unversioned name, e.g. `app.css`.
+    pub name: &'static str,
+    /// Fingerprinted file name, e.g. `app.0123456789.css`.
+    pub file_name: String,
+    pub fingerprint: String,
+    pub content_type: &'static str,
+    pub body: &'static str,
+}
+
+fn all() -> &'static [Asset] {
+    static ASSETS: OnceLock<Vec<Asset>> = OnceLock::new();
+    ASSETS.get_or_init(|| {
+        SOURCES
+            .iter()
+            .map(|&(name, content_type, body)| {
+                let fingerprint =
+                    hex::encode(Sha256::digest(body.as_bytes()))[..FINGERPRINT_LEN].to_string();
+                let (stem, ext) = name.split_once('.').unwrap_or((name, ""));
+                Asset {
+                    name,
+                    file_name: format!("{stem}.{fingerprint}.{ext}"),
+                    fingerprint,
+                    content_type,
+                    body,
+                }
+            })
+            .collect()
+    })
+}
+
+/// Path to the current version of `name`.
+///
+/// # Panics
+/// If `name` is not a bundled asset; callers pass literals.
+pub fn url(name: &str) -> String {
+    format!("/assets/{}", find(name).file_name)
+}
+
+/// Content of `name`, for pages that inline it.
+///
+/// # Panics
+/// If `name` is not a bundled asset; callers pass literals.
+pub fn body(name: &str) -> &'static str {
+    find(name).body
+}
+
+fn find(name: &str) -> &'static Asset {
+    all()
+        .iter()
+        .find(|a| a.name == name)
+        .unwrap_or_else(|| panic!("unknown asset {name}"))
+}
+
+/// Looks up a request path segment. `Some((asset, true))` for the current
+/// fingerprinted name; `Some((asset, false))` for the bare name, which is
+/// served uncached. A stale fingerprint is not found.
+pub fn lookup(file_name: &str) -> Option<(&'static Asset, bool)> {
+    all().iter().find_map(|a| {
+        if a.file_name == file_name {
+            Some((a, true))
+        } else if a.name == file_name {
+            Some((a, false))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_carry_a_content_fingerprint() {
+        let css = url("app.css");
+        let file = css.strip_prefix("/assets/").expect("prefix");
+        let (asset, immutable) = lookup(file).expect("fingerprinted");
+        assert!(immutable);
+        assert_eq!(asset.name, "app.css");
+        assert!(file.starts_with("app.") && file.ends_with(".css"));
+        assert_eq!(asset.fingerprint.len(), FINGERPRINT_LEN);
+        assert!(asset.body.contains("--bg"));
+
+        assert!(matches!(lookup("app.css"), Some((_, false))));
+        assert!(lookup("app.0000000000.css").is_none());
+        assert_ne!(url("app.js"), url("e2e.js"));
+    }
+}
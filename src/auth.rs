@@ -1,18 +1,59 @@
 use std::{
+    cell::Cell,
     collections::{HashMap, HashSet},
     fs,
-    path::Path,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 
-use axum::http::HeaderMap;
-use serde::Deserialize;
+use axum::http::{HeaderMap, header};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
 use subtle::ConstantTimeEq;
 use time::OffsetDateTime;
+use tracing::warn;
+use ulid::Ulid;
 
-use crate::errors::{AppError, AppResult};
+use crate::{
+    errors::{AppError, AppResult},
+    kv::Kv,
+    token::SharedToken,
+    types::{PasteMeta, Visibility},
+};
 
 pub const API_KEY_HEADER: &str = "X-API-Key";
+pub const SESSION_COOKIE: &str = "lanpaste_admin";
+const SESSIONS_NS: &str = "sessions";
+/// Reader sessions from `/login`, for `--protect-ui`.
+pub const UI_SESSION_COOKIE: &str = "lanpaste_ui";
+const UI_SESSIONS_NS: &str = "ui_sessions";
+const USAGE_NS: &str = "key_usage";
+pub const SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+tokio::task_local! {
+    /// Set once a request's key or token has been checked and found good;
+    /// see [`watch_verification`].
+    static VERIFIED: Cell<bool>;
+}
+
+/// Runs `call` and says whether it verified a credential on the way. A
+/// request can carry a junk token to a route that never looks at it, so
+/// only this, not a good status, clears `--auth-fail-limit` failures.
+pub async fn watch_verification<F: Future>(call: F) -> (F::Output, bool) {
+    VERIFIED
+        .scope(Cell::new(false), async move {
+            let out = call.await;
+            (out, VERIFIED.with(Cell::get))
+        })
+        .await
+}
+
+/// Records a verified credential for [`watch_verification`], if it is
+/// watching.
+pub(crate) fn mark_verified() {
+    let _ = VERIFIED.try_with(|verified| verified.set(true));
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum Scope {
@@ -20,17 +61,76 @@ pub enum Scope {
     PasteCreate,
     PasteRead,
     RecentRead,
+    /// Clone and fetch the whole repo over `--git-http`, private pastes and
+    /// all, so it is never implied by the read scopes.
+    RepoRead,
+    Admin,
 }
 
 impl Scope {
-    fn as_str(self) -> &'static str {
+    pub fn as_str(self) -> &'static str {
         match self {
             Scope::ApiIndex => "api:index",
             Scope::PasteCreate => "paste:create",
             Scope::PasteRead => "paste:read",
             Scope::RecentRead => "recent:read",
+            Scope::RepoRead => "repo:read",
+            Scope::Admin => "admin",
         }
     }
+
+    /// Scopes a device may ask for through enrollment (never `admin`).
+    pub const ENROLLABLE: [Scope; 4] = [
+        Scope::ApiIndex,
+        Scope::PasteCreate,
+        Scope::PasteRead,
+        Scope::RecentRead,
+    ];
+
+    /// Every scope the server checks. A new endpoint with a scope of its own
+    /// adds it here, and keys holding a matching wildcard get it too.
+    pub const ALL: [Scope; 6] = [
+        Scope::ApiIndex,
+        Scope::PasteCreate,
+        Scope::PasteRead,
+        Scope::RecentRead,
+        Scope::RepoRead,
+        Scope::Admin,
+    ];
+
+    pub fn parse_enrollable(raw: &str) -> Option<Scope> {
+        Self::ENROLLABLE.into_iter().find(|s| s.as_str() == raw)
+    }
+
+    /// What the scope lets a key do, for listings and error messages.
+    pub fn description(self) -> &'static str {
+        match self {
+            Scope::ApiIndex => "read the /api endpoint index",
+            Scope::PasteCreate => "create, append to and upload pastes",
+            Scope::PasteRead => "read pastes: meta, raw, tail, follow, export",
+            Scope::RecentRead => "list recent pastes and watch events",
+            Scope::RepoRead => "clone the whole repo over --git-http",
+            Scope::Admin => "admin pages and /api/v1/admin",
+        }
+    }
+
+    /// Whether a scope written in a key file grants this one: `*` grants
+    /// everything, `paste:*` every scope starting with `paste:`, and any
+    /// other pattern only the scope of that name.
+    pub fn granted_by(self, pattern: &str) -> bool {
+        let name = self.as_str();
+        match pattern.strip_suffix('*') {
+            Some("") => true,
+            Some(prefix) if prefix.ends_with(':') => name.starts_with(prefix),
+            _ => name == pattern,
+        }
+    }
+
+    /// A scope as written in the key file: `*`, a scope name, or a
+    /// `prefix:*` wildcard that grants at least one scope.
+    pub fn is_valid(raw: &str) -> bool {
+        Self::ALL.iter().any(|s| s.granted_by(raw))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -38,15 +138,27 @@ pub struct ApiKeysFile {
     pub keys: Vec<ApiKeyEntry>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiKeyEntry {
     #[serde(default)]
     pub name: Option<String>,
     pub key: String,
     #[serde(default)]
     pub scopes: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_requests_per_minute: Option<u32>,
+    /// A disabled key stays in the file, with its name and usage, but
+    /// authorizes nothing.
+    #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
 }
 
 #[derive(Debug, Clone)]
@@ -55,10 +167,41 @@ struct RateWindow {
     count: u32,
 }
 
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct UsageCount {
+    requests: u64,
+    #[serde(default)]
+    bytes_uploaded: u64,
+    last_used: Option<OffsetDateTime>,
+}
+
+/// A key's [`UsageCount`] as kept in the kv store.
+#[derive(Debug, Serialize, Deserialize)]
+struct UsageRecord {
+    key: String,
+    #[serde(flatten)]
+    count: UsageCount,
+}
+
+/// Requests served and bytes uploaded per key.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyUsage {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub requests: u64,
+    pub bytes_uploaded: u64,
+    pub last_used: Option<OffsetDateTime>,
+    pub enabled: bool,
+}
+
 #[derive(Clone, Default)]
 pub struct ApiKeyStore {
-    entries: Arc<Vec<ApiKeyEntry>>,
+    path: Option<PathBuf>,
+    entries: Arc<RwLock<Arc<Vec<ApiKeyEntry>>>>,
     counters: Arc<Mutex<HashMap<String, RateWindow>>>,
+    usage: Arc<Mutex<HashMap<String, UsageCount>>>,
+    /// Where usage is kept across restarts; in memory only without it.
+    usage_kv: Option<Kv>,
 }
 
 impl ApiKeyStore {
@@ -66,48 +209,198 @@ impl ApiKeyStore {
         let Some(path) = path else {
             return Ok(Self::default());
         };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            entries: Arc::new(RwLock::new(Arc::new(load_entries(path)?))),
+            counters: Arc::new(Mutex::new(HashMap::new())),
+            usage: Arc::new(Mutex::new(HashMap::new())),
+            usage_kv: None,
+        })
+    }
 
-        let raw = fs::read(path).map_err(|e| AppError::io("read api key file", e))?;
-        let file: ApiKeysFile = serde_json::from_slice(&raw)
-            .map_err(|e| AppError::internal(format!("parse api key file: {e}")))?;
+    /// Keeps usage counts in `kv` from now on, starting from the ones an
+    /// earlier run left there.
+    pub fn persist_usage(mut self, kv: Kv) -> AppResult<Self> {
+        let saved: HashMap<String, UsageCount> = kv
+            .list::<UsageRecord>(USAGE_NS)?
+            .into_iter()
+            .map(|record| (record.key, record.count))
+            .collect();
+        self.usage = Arc::new(Mutex::new(saved));
+        self.usage_kv = Some(kv);
+        Ok(self)
+    }
 
-        let mut seen = HashSet::new();
-        for entry in &file.keys {
-            if entry.key.trim().is_empty() {
-                return Err(AppError::internal("api key entry has empty key"));
-            }
-            if entry.scopes.is_empty() {
-                return Err(AppError::internal(format!(
-                    "api key '{}' must include at least one scope",
-                    entry.name.as_deref().unwrap_or("unnamed")
-                )));
-            }
-            if entry.max_requests_per_minute == Some(0) {
-                return Err(AppError::internal(format!(
-                    "api key '{}' has invalid max_requests_per_minute=0",
-                    entry.name.as_deref().unwrap_or("unnamed")
-                )));
-            }
-            if !seen.insert(entry.key.clone()) {
-                return Err(AppError::internal("duplicate api key in api key file"));
-            }
-        }
+    /// Re-reads the key file; on error the current keys stay in effect.
+    pub fn reload(&self) -> AppResult<usize> {
+        let Some(path) = &self.path else {
+            return Ok(0);
+        };
+        let entries = load_entries(path)?;
+        let count = entries.len();
+        *self
+            .entries
+            .write()
+            .map_err(|_| AppError::internal("api key store lock poisoned"))? = Arc::new(entries);
+        Ok(count)
+    }
 
-        Ok(Self {
-            entries: Arc::new(file.keys),
-            counters: Arc::new(Mutex::new(HashMap::new())),
+    /// Adds a key to the key file (keeping any other content in it) and
+    /// makes it live.
+    pub fn add_key(&self, entry: ApiKeyEntry) -> AppResult<()> {
+        let entry = serde_json::to_value(&entry)
+            .map_err(|e| AppError::internal(format!("serialize api key: {e}")))?;
+        self.edit_file(|keys| {
+            keys.push(entry);
+            Ok(())
         })
     }
 
+    /// Turns the key named `name` (as [`KeyUsage`] names it) off or back on,
+    /// in the key file and live, and returns its usage.
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> AppResult<KeyUsage> {
+        self.edit_file(|keys| {
+            let entry = keys
+                .iter_mut()
+                .find(|raw| {
+                    serde_json::from_value::<ApiKeyEntry>((*raw).clone())
+                        .is_ok_and(|entry| key_id(&entry) == name)
+                })
+                .and_then(|raw| raw.as_object_mut())
+                .ok_or_else(|| AppError::NotFound(format!("no api key named '{name}'")))?;
+            if enabled {
+                entry.remove("enabled");
+            } else {
+                entry.insert("enabled".to_string(), serde_json::Value::Bool(false));
+            }
+            Ok(())
+        })?;
+        self.usage()
+            .into_iter()
+            .find(|usage| usage.name == name)
+            .ok_or_else(|| AppError::NotFound(format!("no api key named '{name}'")))
+    }
+
+    /// Rewrites the `keys` array of the key file, keeping any other content
+    /// in it, then reloads.
+    fn edit_file(
+        &self,
+        edit: impl FnOnce(&mut Vec<serde_json::Value>) -> AppResult<()>,
+    ) -> AppResult<()> {
+        let Some(path) = &self.path else {
+            return Err(AppError::Conflict(
+                "no --api-keys-file is configured".to_string(),
+            ));
+        };
+        let raw = fs::read(path).map_err(|e| AppError::io("read api key file", e))?;
+        let mut doc: serde_json::Value = serde_json::from_slice(&raw)
+            .map_err(|e| AppError::internal(format!("parse api key file: {e}")))?;
+        let keys = doc
+            .get_mut("keys")
+            .and_then(|k| k.as_array_mut())
+            .ok_or_else(|| AppError::internal("api key file has no keys array"))?;
+        edit(keys)?;
+        let bytes = serde_json::to_vec_pretty(&doc)
+            .map_err(|e| AppError::internal(format!("serialize api key file: {e}")))?;
+        let tmp = path.with_extension(format!("{}.tmp", Ulid::new()));
+        fs::write(&tmp, bytes).map_err(|e| AppError::io("write api key file", e))?;
+        // The file holds secrets; keep its mode rather than the umask's.
+        if let Ok(meta) = fs::metadata(path) {
+            let _ = fs::set_permissions(&tmp, meta.permissions());
+        }
+        fs::rename(&tmp, path).map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            AppError::io("replace api key file", e)
+        })?;
+        self.reload().map(|_| ())
+    }
+
     pub fn enabled(&self) -> bool {
-        !self.entries.is_empty()
+        !self.snapshot().is_empty()
     }
 
-    fn resolve_key(&self, provided: &str) -> Option<ApiKeyEntry> {
+    fn snapshot(&self) -> Arc<Vec<ApiKeyEntry>> {
         self.entries
+            .read()
+            .map(|entries| entries.clone())
+            .unwrap_or_default()
+    }
+
+    /// The enabled key matching `provided`.
+    fn resolve_key(&self, provided: &str) -> Option<ApiKeyEntry> {
+        let entry = self
+            .snapshot()
             .iter()
             .find(|entry| entry.key.as_bytes().ct_eq(provided.as_bytes()).into())
-            .cloned()
+            .filter(|entry| entry.enabled)
+            .cloned();
+        if entry.is_some() {
+            mark_verified();
+        }
+        entry
+    }
+
+    /// Whether the session of a key named `name` should end: the key was
+    /// disabled or removed since the login.
+    fn session_revoked(&self, name: Option<&str>) -> bool {
+        name.is_some_and(|name| {
+            !self
+                .snapshot()
+                .iter()
+                .any(|entry| entry.enabled && entry.name.as_deref() == Some(name))
+        })
+    }
+
+    /// Per-key usage for the current key set, in file order.
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        let usage = self.usage.lock().map(|u| u.clone()).unwrap_or_default();
+        self.snapshot()
+            .iter()
+            .map(|entry| {
+                let id = key_id(entry);
+                let count = usage.get(&id).copied().unwrap_or_default();
+                KeyUsage {
+                    name: id,
+                    scopes: entry.scopes.clone(),
+                    requests: count.requests,
+                    bytes_uploaded: count.bytes_uploaded,
+                    last_used: count.last_used,
+                    enabled: entry.enabled,
+                }
+            })
+            .collect()
+    }
+
+    fn record_use(&self, entry: &ApiKeyEntry) {
+        self.update_usage(&key_id(entry), |count| {
+            count.requests += 1;
+            count.last_used = Some(OffsetDateTime::now_utc());
+        });
+    }
+
+    /// Counts `bytes` of paste content stored for the key named `key` (as
+    /// [`authorize_caller`] names it).
+    pub fn record_upload(&self, key: &str, bytes: usize) {
+        self.update_usage(key, |count| count.bytes_uploaded += bytes as u64);
+    }
+
+    fn update_usage(&self, key: &str, update: impl FnOnce(&mut UsageCount)) {
+        let Ok(mut usage) = self.usage.lock() else {
+            return;
+        };
+        let count = usage.entry(key.to_string()).or_default();
+        update(count);
+        // Written under the lock, so a slower write cannot overwrite a
+        // newer count.
+        if let Some(kv) = &self.usage_kv {
+            let record = UsageRecord {
+                key: key.to_string(),
+                count: *count,
+            };
+            if let Err(err) = kv.put(USAGE_NS, key, &record, None) {
+                warn!("save usage of api key {key}: {err:?}");
+            }
+        }
     }
 
     fn enforce_rate_limit(&self, entry: &ApiKeyEntry) -> AppResult<()> {
@@ -116,10 +409,7 @@ impl ApiKeyStore {
         };
 
         let now_minute = OffsetDateTime::now_utc().unix_timestamp() / 60;
-        let key_id = entry
-            .name
-            .clone()
-            .unwrap_or_else(|| format!("key:{}", entry.key.chars().take(8).collect::<String>()));
+        let key_id = key_id(entry);
         let mut counters = self
             .counters
             .lock()
@@ -144,6 +434,44 @@ impl ApiKeyStore {
     }
 }
 
+fn load_entries(path: &Path) -> AppResult<Vec<ApiKeyEntry>> {
+    let raw = fs::read(path).map_err(|e| AppError::io("read api key file", e))?;
+    let file: ApiKeysFile = serde_json::from_slice(&raw)
+        .map_err(|e| AppError::internal(format!("parse api key file: {e}")))?;
+
+    let mut seen = HashSet::new();
+    for entry in &file.keys {
+        if entry.key.trim().is_empty() {
+            return Err(AppError::internal("api key entry has empty key"));
+        }
+        if entry.scopes.is_empty() {
+            return Err(AppError::internal(format!(
+                "api key '{}' must include at least one scope",
+                entry.name.as_deref().unwrap_or("unnamed")
+            )));
+        }
+        if entry.max_requests_per_minute == Some(0) {
+            return Err(AppError::internal(format!(
+                "api key '{}' has invalid max_requests_per_minute=0",
+                entry.name.as_deref().unwrap_or("unnamed")
+            )));
+        }
+        if !seen.insert(entry.key.clone()) {
+            return Err(AppError::internal("duplicate api key in api key file"));
+        }
+        // Kept rather than refused, so a key file written for a newer
+        // server still loads; the key just gets nothing from that entry.
+        for unknown in entry.scopes.iter().filter(|s| !Scope::is_valid(s)) {
+            warn!(
+                "api key '{}' has scope '{unknown}', which grants nothing on this server",
+                key_id(entry)
+            );
+        }
+    }
+
+    Ok(file.keys)
+}
+
 pub fn authorize(store: &ApiKeyStore, headers: &HeaderMap, scope: Scope) -> AppResult<()> {
     if !store.enabled() {
         return Ok(());
@@ -159,11 +487,92 @@ pub fn authorize(store: &ApiKeyStore, headers: &HeaderMap, scope: Scope) -> AppR
         ));
     }
 
+    authorize_key(store, provided, scope).map(|_| ())
+}
+
+/// Like [`authorize`], but also takes the key as the password of HTTP Basic
+/// credentials (any user name), for clients such as WebDAV mounts that cannot
+/// send custom headers.
+pub fn authorize_basic(store: &ApiKeyStore, headers: &HeaderMap, scope: Scope) -> AppResult<()> {
+    if !store.enabled() || headers.contains_key(API_KEY_HEADER) {
+        return authorize(store, headers, scope);
+    }
+    let password = basic_password(headers).unwrap_or_default();
+    if password.is_empty() {
+        return Err(AppError::Unauthorized(
+            "missing or invalid API key".to_string(),
+        ));
+    }
+    authorize_key(store, &password, scope).map(|_| ())
+}
+
+/// The password of HTTP Basic credentials; the user name is ignored.
+pub fn basic_password(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|encoded| BASE64_STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|pair| {
+            pair.split_once(':')
+                .map(|(_, password)| password.to_string())
+        })
+}
+
+/// Like [`authorize`], but also names the calling key. Per-key features
+/// (stars) need a key file: a shared token cannot tell callers apart.
+pub fn authorize_caller(
+    store: &ApiKeyStore,
+    headers: &HeaderMap,
+    scope: Scope,
+) -> AppResult<String> {
+    if !store.enabled() {
+        return Err(AppError::Forbidden(
+            "this endpoint needs --api-keys-file to identify the caller".to_string(),
+        ));
+    }
+    let provided = headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    authorize_key(store, provided, scope).map(|entry| key_id(&entry))
+}
+
+/// Per-paste access on top of scopes: a private paste is readable only with
+/// one of the keys it names. Other callers are told it does not exist.
+pub fn authorize_paste(
+    store: &ApiKeyStore,
+    headers: &HeaderMap,
+    meta: &PasteMeta,
+) -> AppResult<()> {
+    if meta.visibility != Visibility::Private {
+        return Ok(());
+    }
+    let provided = headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if provided.is_empty() {
+        return Err(AppError::Unauthorized(
+            "this paste needs an API key".to_string(),
+        ));
+    }
+    let allowed = store
+        .resolve_key(provided)
+        .is_some_and(|entry| meta.allow_keys.contains(&key_id(&entry)));
+    if !allowed {
+        return Err(AppError::NotFound("paste not found".to_string()));
+    }
+    Ok(())
+}
+
+fn authorize_key(store: &ApiKeyStore, provided: &str, scope: Scope) -> AppResult<ApiKeyEntry> {
     let key = store
         .resolve_key(provided)
         .ok_or_else(|| AppError::Unauthorized("missing or invalid API key".to_string()))?;
     let needed = scope.as_str();
-    let allowed = key.scopes.iter().any(|s| s == "*" || s == needed);
+    let allowed = key.scopes.iter().any(|s| scope.granted_by(s));
     if !allowed {
         return Err(AppError::Forbidden(format!(
             "api key lacks required scope '{needed}'"
@@ -171,9 +580,166 @@ pub fn authorize(store: &ApiKeyStore, headers: &HeaderMap, scope: Scope) -> AppR
     }
 
     store.enforce_rate_limit(&key)?;
+    store.record_use(&key);
+    Ok(key)
+}
+
+/// Display id for a key: its name, or the first characters of the secret.
+fn key_id(entry: &ApiKeyEntry) -> String {
+    entry
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("key:{}", entry.key.chars().take(8).collect::<String>()))
+}
+
+/// Random token of `bytes` bytes, hex encoded.
+pub fn random_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    getrandom::getrandom(&mut buf).expect("os random source");
+    hex::encode(buf)
+}
+
+/// A new API key: `lp_` and 24 random bytes, hex encoded.
+pub fn new_api_key() -> String {
+    format!("lp_{}", random_token(24))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Session {
+    key_name: Option<String>,
+    created_at: OffsetDateTime,
+}
+
+/// Admin access: an `admin`-scoped API key, or a session cookie from
+/// `/admin/login`. Without an API key file there is no way to tell an
+/// operator from anyone else on the LAN, so admin access is refused.
+pub fn authorize_admin(store: &ApiKeyStore, kv: &Kv, headers: &HeaderMap) -> AppResult<()> {
+    if !store.enabled() {
+        return Err(AppError::Forbidden(
+            "admin access requires --api-keys-file with an admin-scoped key".to_string(),
+        ));
+    }
+    if headers.contains_key(API_KEY_HEADER) {
+        return authorize(store, headers, Scope::Admin);
+    }
+    let session = match session_token(headers, SESSION_COOKIE) {
+        Some(token) => kv.get::<Session>(SESSIONS_NS, &token)?,
+        None => None,
+    };
+    match session {
+        Some(session) if !store.session_revoked(session.key_name.as_deref()) => Ok(()),
+        _ => Err(AppError::Unauthorized("admin login required".to_string())),
+    }
+}
+
+/// Checks an admin key presented on the login form and opens a session,
+/// returning the cookie token.
+pub fn start_admin_session(store: &ApiKeyStore, kv: &Kv, provided: &str) -> AppResult<String> {
+    if !store.enabled() {
+        return Err(AppError::Forbidden(
+            "admin access requires --api-keys-file with an admin-scoped key".to_string(),
+        ));
+    }
+    let key = authorize_key(store, provided.trim(), Scope::Admin)?;
+    let token = random_token(32);
+    kv.put(
+        SESSIONS_NS,
+        &token,
+        &Session {
+            key_name: key.name,
+            created_at: OffsetDateTime::now_utc(),
+        },
+        Some(SESSION_TTL),
+    )?;
+    Ok(token)
+}
+
+pub fn end_admin_session(kv: &Kv, headers: &HeaderMap) -> AppResult<()> {
+    if let Some(token) = session_token(headers, SESSION_COOKIE) {
+        kv.delete(SESSIONS_NS, &token)?;
+    }
     Ok(())
 }
 
+/// `--protect-ui` access to pages: what the API would take for a read (a
+/// `paste:read` key, or the paste token when there is no key file), or a
+/// session cookie from `/login`.
+pub fn authorize_ui(
+    store: &ApiKeyStore,
+    kv: &Kv,
+    token: &SharedToken,
+    headers: &HeaderMap,
+) -> AppResult<()> {
+    if store.enabled() && headers.contains_key(API_KEY_HEADER) {
+        return authorize(store, headers, Scope::PasteRead);
+    }
+    if !store.enabled()
+        && token.is_set()
+        && let Some(provided) = headers.get("X-Paste-Token")
+    {
+        return token.verify(provided.to_str().ok());
+    }
+    let session = match session_token(headers, UI_SESSION_COOKIE) {
+        Some(token) => kv.get::<Session>(UI_SESSIONS_NS, &token)?,
+        None => None,
+    };
+    match session {
+        Some(session) if !store.session_revoked(session.key_name.as_deref()) => Ok(()),
+        _ => Err(AppError::Unauthorized("login required".to_string())),
+    }
+}
+
+/// Checks the key or token typed into `/login` and opens a reader session,
+/// returning the cookie token.
+pub fn start_ui_session(
+    store: &ApiKeyStore,
+    kv: &Kv,
+    token: &SharedToken,
+    provided: &str,
+) -> AppResult<String> {
+    let provided = provided.trim();
+    let key_name = if store.enabled() {
+        authorize_key(store, provided, Scope::PasteRead)?.name
+    } else if token.is_set() {
+        token.verify(Some(provided))?;
+        None
+    } else {
+        return Err(AppError::Forbidden(
+            "--protect-ui needs --token or --api-keys-file".to_string(),
+        ));
+    };
+    let session = random_token(32);
+    kv.put(
+        UI_SESSIONS_NS,
+        &session,
+        &Session {
+            key_name,
+            created_at: OffsetDateTime::now_utc(),
+        },
+        Some(SESSION_TTL),
+    )?;
+    Ok(session)
+}
+
+pub fn end_ui_session(kv: &Kv, headers: &HeaderMap) -> AppResult<()> {
+    if let Some(session) = session_token(headers, UI_SESSION_COOKIE) {
+        kv.delete(UI_SESSIONS_NS, &session)?;
+    }
+    Ok(())
+}
+
+fn session_token(headers: &HeaderMap, cookie: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == cookie)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,4 +749,215 @@ mod tests {
         let headers = HeaderMap::new();
         assert!(authorize(&ApiKeyStore::default(), &headers, Scope::ApiIndex).is_ok());
     }
+
+    #[test]
+    fn wildcards_grant_a_scope_family() {
+        assert!(Scope::PasteRead.granted_by("*"));
+        assert!(Scope::PasteRead.granted_by("paste:*"));
+        assert!(Scope::PasteCreate.granted_by("paste:*"));
+        assert!(Scope::PasteRead.granted_by("paste:read"));
+        assert!(!Scope::RecentRead.granted_by("paste:*"));
+        assert!(!Scope::PasteRead.granted_by("paste*"));
+        assert!(!Scope::PasteRead.granted_by("paste:"));
+        assert!(!Scope::Admin.granted_by("admin:*"));
+
+        assert!(Scope::is_valid("recent:*") && Scope::is_valid("admin"));
+        assert!(!Scope::is_valid("pastes:*") && !Scope::is_valid("paste:delete"));
+
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("keys.json");
+        fs::write(
+            &path,
+            r#"{"keys":[{"name":"paster","key":"paste-key","scopes":["paste:*","paste:delete"]}]}"#,
+        )
+        .expect("write");
+        let store = ApiKeyStore::from_file(Some(&path)).expect("unknown scopes still load");
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "paste-key".parse().expect("key"));
+        assert!(authorize(&store, &headers, Scope::PasteCreate).is_ok());
+        assert!(authorize(&store, &headers, Scope::PasteRead).is_ok());
+        assert!(matches!(
+            authorize(&store, &headers, Scope::RecentRead),
+            Err(AppError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn admin_needs_admin_scope_or_session() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("keys.json");
+        fs::write(
+            &path,
+            r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]},
+                {"name":"bot","key":"bot-key","scopes":["paste:create"]}]}"#,
+        )
+        .expect("write");
+        let store = ApiKeyStore::from_file(Some(&path)).expect("store");
+        let kv = Kv::open(td.path().join("kv"));
+
+        assert!(authorize_admin(&store, &kv, &HeaderMap::new()).is_err());
+        assert!(start_admin_session(&store, &kv, "bot-key").is_err());
+        let token = start_admin_session(&store, &kv, "ops-key").expect("session");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            format!("theme=dark; {SESSION_COOKIE}={token}")
+                .parse()
+                .expect("cookie"),
+        );
+        assert!(authorize_admin(&store, &kv, &headers).is_ok());
+        end_admin_session(&kv, &headers).expect("logout");
+        assert!(authorize_admin(&store, &kv, &headers).is_err());
+
+        assert!(
+            authorize_admin(&ApiKeyStore::default(), &kv, &headers).is_err(),
+            "admin must stay closed without a key file"
+        );
+    }
+
+    #[test]
+    fn ui_takes_a_read_key_token_or_session() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("keys.json");
+        fs::write(
+            &path,
+            r#"{"keys":[{"name":"reader","key":"read-key","scopes":["paste:read"]},
+                {"name":"bot","key":"bot-key","scopes":["paste:create"]}]}"#,
+        )
+        .expect("write");
+        let store = ApiKeyStore::from_file(Some(&path)).expect("store");
+        let kv = Kv::open(td.path().join("kv"));
+        let no_token = SharedToken::default();
+
+        let mut keyed = HeaderMap::new();
+        keyed.insert(API_KEY_HEADER, "read-key".parse().expect("key"));
+        assert!(authorize_ui(&store, &kv, &no_token, &keyed).is_ok());
+        assert!(authorize_ui(&store, &kv, &no_token, &HeaderMap::new()).is_err());
+        assert!(start_ui_session(&store, &kv, &no_token, "bot-key").is_err());
+        let session = start_ui_session(&store, &kv, &no_token, " read-key ").expect("session");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            format!("{UI_SESSION_COOKIE}={session}")
+                .parse()
+                .expect("cookie"),
+        );
+        assert!(authorize_ui(&store, &kv, &no_token, &headers).is_ok());
+        assert!(
+            authorize_admin(&store, &kv, &headers).is_err(),
+            "a reader session is not an admin session"
+        );
+        end_ui_session(&kv, &headers).expect("logout");
+        assert!(authorize_ui(&store, &kv, &no_token, &headers).is_err());
+
+        let open = ApiKeyStore::default();
+        let tok = SharedToken::new(vec!["tok".to_string()]);
+        let mut tokened = HeaderMap::new();
+        tokened.insert("X-Paste-Token", "tok".parse().expect("token"));
+        assert!(authorize_ui(&open, &kv, &tok, &tokened).is_ok());
+        assert!(start_ui_session(&open, &kv, &tok, "nope").is_err());
+        assert!(start_ui_session(&open, &kv, &no_token, "anything").is_err());
+    }
+
+    #[test]
+    fn add_key_persists_and_goes_live() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("keys.json");
+        fs::write(
+            &path,
+            r#"{"comment":"kept","keys":[{"key":"a","scopes":["admin"]}]}"#,
+        )
+        .expect("write");
+        let store = ApiKeyStore::from_file(Some(&path)).expect("store");
+        store
+            .add_key(ApiKeyEntry {
+                name: Some("laptop".to_string()),
+                key: "b".to_string(),
+                scopes: vec!["paste:read".to_string()],
+                max_requests_per_minute: None,
+                enabled: true,
+            })
+            .expect("add");
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "b".parse().expect("key"));
+        assert!(authorize(&store, &headers, Scope::PasteRead).is_ok());
+        let usage = store.usage();
+        assert_eq!(usage[1].name, "laptop");
+        assert_eq!(usage[1].requests, 1);
+        assert_eq!(usage[0].requests, 0);
+        let doc: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).expect("read")).expect("json");
+        assert_eq!(doc["comment"], "kept");
+        assert_eq!(doc["keys"][1]["name"], "laptop");
+    }
+
+    #[test]
+    fn disabled_key_is_refused_and_ends_its_sessions() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("keys.json");
+        fs::write(
+            &path,
+            r#"{"keys":[{"name":"ops","key":"a","scopes":["admin"]},{"name":"old","key":"b","scopes":["paste:read"],"enabled":false}]}"#,
+        )
+        .expect("write");
+        let store = ApiKeyStore::from_file(Some(&path)).expect("store");
+        let kv = Kv::open(td.path().join("kv"));
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "b".parse().expect("key"));
+        assert!(matches!(
+            authorize(&store, &headers, Scope::PasteRead),
+            Err(AppError::Unauthorized(_))
+        ));
+
+        let session = start_admin_session(&store, &kv, "a").expect("login");
+        let mut browser = HeaderMap::new();
+        browser.insert(
+            header::COOKIE,
+            format!("{SESSION_COOKIE}={session}")
+                .parse()
+                .expect("cookie"),
+        );
+        assert!(authorize_admin(&store, &kv, &browser).is_ok());
+        let usage = store.set_enabled("ops", false).expect("disable");
+        assert!(!usage.enabled);
+        assert!(authorize_admin(&store, &kv, &browser).is_err());
+
+        store.set_enabled("old", true).expect("enable");
+        assert!(authorize(&store, &headers, Scope::PasteRead).is_ok());
+        let doc: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).expect("read")).expect("json");
+        assert_eq!(doc["keys"][0]["enabled"], false);
+        assert!(doc["keys"][1].get("enabled").is_none());
+        assert!(matches!(
+            store.set_enabled("nobody", false),
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn usage_survives_a_restart() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("keys.json");
+        fs::write(
+            &path,
+            r#"{"keys":[{"name":"ci","key":"a","scopes":["paste:create"]}]}"#,
+        )
+        .expect("write");
+        let kv = Kv::open(td.path().join("kv"));
+        let store = ApiKeyStore::from_file(Some(&path))
+            .and_then(|s| s.persist_usage(kv.clone()))
+            .expect("store");
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "a".parse().expect("key"));
+        let caller = authorize_caller(&store, &headers, Scope::PasteCreate).expect("auth");
+        store.record_upload(&caller, 42);
+
+        let reopened = ApiKeyStore::from_file(Some(&path))
+            .and_then(|s| s.persist_usage(kv))
+            .expect("store");
+        let usage = reopened.usage();
+        assert_eq!(usage[0].requests, 1);
+        assert_eq!(usage[0].bytes_uploaded, 42);
+        assert!(usage[0].last_used.is_some());
+    }
 }
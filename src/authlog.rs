@@ -0,0 +1,136 @@
+//! Authentication failures and lockouts in a fixed, one-line format, for
+//! fail2ban and similar tools:
+//!
+//! ```text
+//! 2026-03-01T09:30:00Z lanpaste auth failure ip=192.168.1.9 reason=bad-token path=/api/v1/paste
+//! 2026-03-01T09:30:05Z lanpaste auth lockout ip=192.168.1.9 seconds=60
+//! ```
+//!
+//! Every line goes to the `lanpaste::auth` tracing target at `warn`, and
+//! with `--auth-log` is appended to that file too, where no other output
+//! gets in the way of a filter. Fields are only ever added at the end.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::warn;
+
+use crate::errors::{AppError, AppResult};
+
+/// The credential a failed request carried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    BadToken,
+    BadApiKey,
+    BadBasic,
+    BadLogin,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::BadToken => "bad-token",
+            Self::BadApiKey => "bad-api-key",
+            Self::BadBasic => "bad-basic",
+            Self::BadLogin => "bad-login",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuthLog {
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl AuthLog {
+    /// Appends to `path` when given; otherwise lines only go to tracing.
+    pub fn open(path: Option<&Path>) -> AppResult<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| AppError::io("open auth log", e))?;
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(file))),
+        })
+    }
+
+    pub fn failure(&self, ip: IpAddr, reason: Reason, path: &str) {
+        self.write(&format!(
+            "failure ip={ip} reason={} path={path}",
+            reason.as_str()
+        ));
+    }
+
+    pub fn lockout(&self, ip: IpAddr, ban: Duration) {
+        self.write(&format!("lockout ip={ip} seconds={}", ban.as_secs()));
+    }
+
+    fn write(&self, event: &str) {
+        warn!(target: "lanpaste::auth", "{event}");
+        let Some(file) = &self.file else {
+            return;
+        };
+        let line = line(OffsetDateTime::now_utc(), event);
+        let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        // One write per line, so concurrent appends never interleave.
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            warn!("write auth log: {err}");
+        }
+    }
+}
+
+fn line(at: OffsetDateTime, event: &str) -> String {
+    let at = at
+        .replace_nanosecond(0)
+        .unwrap_or(at)
+        .format(&Rfc3339)
+        .unwrap_or_default();
+    format!("{at} lanpaste auth {event}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_appended_in_the_documented_format() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("auth.log");
+        let log = AuthLog::open(Some(&path)).expect("open");
+        let ip: IpAddr = "192.168.1.9".parse().expect("ip");
+        log.failure(ip, Reason::BadToken, "/api/v1/paste");
+        log.lockout(ip, Duration::from_secs(60));
+
+        let written = std::fs::read_to_string(&path).expect("read");
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let (at, rest) = lines[0].split_once(' ').expect("timestamp");
+        assert!(OffsetDateTime::parse(at, &Rfc3339).is_ok(), "{at}");
+        assert_eq!(
+            rest,
+            "lanpaste auth failure ip=192.168.1.9 reason=bad-token path=/api/v1/paste"
+        );
+        assert!(lines[1].ends_with(" lanpaste auth lockout ip=192.168.1.9 seconds=60"));
+    }
+
+    #[test]
+    fn timestamps_are_utc_to_the_second() {
+        let at = OffsetDateTime::from_unix_timestamp(1_772_357_400).expect("time")
+            + Duration::from_millis(250);
+        assert_eq!(
+            line(at, "lockout ip=10.0.0.1 seconds=60"),
+            "2026-03-01T09:30:00Z lanpaste auth lockout ip=10.0.0.1 seconds=60\n"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Off-box backups: a git bundle (or a tarball of the repo and uploaded
+//! files) is built under `tmp/` and PUT to an S3-compatible bucket such as
+//! MinIO, signed with AWS Signature V4 and path-style URLs. The outcome of
+//! each run is kept in the runtime kv store for the admin endpoint.
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use flate2::{Compression, write::GzEncoder};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    gitops::{self, FileLock},
+    kv::Kv,
+    types::AppState,
+};
+
+const BACKUP_NS: &str = "backup";
+const STATUS_KEY: &str = "status";
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupFormat {
+    /// `git bundle --all`: full history, restorable with `git clone`.
+    Bundle,
+    /// `repo/` (including `.git`) and `files/` as a `.tar.gz`.
+    Tarball,
+}
+
+impl BackupFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            BackupFormat::Bundle => "bundle",
+            BackupFormat::Tarball => "tar.gz",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    endpoint: reqwest::Url,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    pub format: BackupFormat,
+    pub interval: Duration,
+    running: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupStatus {
+    pub last_attempt: Option<OffsetDateTime>,
+    pub last_success: Option<OffsetDateTime>,
+    pub last_failure: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+    /// Object key of the last successful upload.
+    pub last_key: Option<String>,
+    pub last_bytes: Option<u64>,
+    pub failures: u64,
+}
+
+impl S3Target {
+    /// `None` unless `--backup-s3-endpoint` is set; then the bucket, access
+    /// key and secret key file are required.
+    pub fn from_config(cfg: &ServeCmd) -> AppResult<Option<Self>> {
+        let Some(endpoint) = &cfg.backup_s3_endpoint else {
+            return Ok(None);
+        };
+        let endpoint = reqwest::Url::parse(endpoint)
+            .ok()
+            .filter(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some())
+            .ok_or_else(|| {
+                AppError::internal(format!("invalid --backup-s3-endpoint '{endpoint}'"))
+            })?;
+        let (Some(bucket), Some(access_key), Some(secret_file)) = (
+            &cfg.backup_s3_bucket,
+            &cfg.backup_s3_access_key,
+            &cfg.backup_s3_secret_key_file,
+        ) else {
+            return Err(AppError::internal(
+                "--backup-s3-endpoint needs --backup-s3-bucket, --backup-s3-access-key and --backup-s3-secret-key-file",
+            ));
+        };
+        if !cfg
+            .backup_s3_prefix
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_./".contains(&b))
+        {
+            return Err(AppError::internal(
+                "--backup-s3-prefix may only contain letters, digits, '-', '_', '.' and '/'",
+            ));
+        }
+        let secret_key = fs::read_to_string(secret_file)
+            .map_err(|e| AppError::io("read backup secret key file", e))?
+            .trim()
+            .to_string();
+        Ok(Some(Self {
+            endpoint,
+            bucket: bucket.clone(),
+            prefix: cfg.backup_s3_prefix.clone(),
+            region: cfg.backup_s3_region.clone(),
+            access_key: access_key.clone(),
+            secret_key,
+            format: cfg.backup_s3_format,
+            interval: cfg.backup_s3_interval,
+            running: Arc::new(tokio::sync::Mutex::new(())),
+        }))
+    }
+
+    /// `s3://bucket/prefix`, for status output.
+    pub fn location(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.prefix)
+    }
+
+    async fn put_object(&self, key: &str, body: Vec<u8>) -> AppResult<()> {
+        let mut url = self.endpoint.clone();
+        url.set_path(&format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.bucket, false),
+            uri_encode(key, true)
+        ));
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let now = OffsetDateTime::now_utc();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let authorization = sign_v4(
+            &SigningInput {
+                method: "PUT",
+                path: url.path(),
+                host: &host,
+                payload_hash: &payload_hash,
+                region: &self.region,
+                access_key: &self.access_key,
+                secret_key: &self.secret_key,
+            },
+            now,
+        )?;
+
+        let client = reqwest::Client::builder()
+            .timeout(UPLOAD_TIMEOUT)
+            .build()
+            .map_err(|e| AppError::internal(format!("build http client: {e}")))?;
+        let response = client
+            .put(url)
+            .header("x-amz-date", amz_date(now)?)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| AppError::ServiceUnavailable(format!("backup upload failed: {e}")))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let detail = response.text().await.unwrap_or_default();
+            return Err(AppError::ServiceUnavailable(format!(
+                "backup upload returned {status}: {}",
+                detail.chars().take(300).collect::<String>()
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn read_status(kv: &Kv) -> AppResult<BackupStatus> {
+    Ok(kv.get(BACKUP_NS, STATUS_KEY)?.unwrap_or_default())
+}
+
+/// Builds a snapshot and uploads it. Fails with `409` if a backup is
+/// already running. The outcome is recorded either way.
+pub async fn run_once(state: &Arc<AppState>) -> AppResult<BackupStatus> {
+    let target = state
+        .backup
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("backups are not configured".to_string()))?;
+    let _running = target
+        .running
+        .try_lock()
+        .map_err(|_| AppError::Conflict("a backup is already running".to_string()))?;
+
+    let started = OffsetDateTime::now_utc();
+    let result = upload_snapshot(state, target, started).await;
+    let mut status = read_status(&state.kv)?;
+    status.last_attempt = Some(started);
+    match &result {
+        Ok((key, bytes)) => {
+            status.last_success = Some(started);
+            status.last_error = None;
+            status.last_key = Some(key.clone());
+            status.last_bytes = Some(*bytes);
+        }
+        Err(err) => {
+            status.last_failure = Some(started);
+            status.last_error = Some(format!("{err:?}"));
+            status.failures += 1;
+        }
+    }
+    state.kv.put(BACKUP_NS, STATUS_KEY, &status, None)?;
+    result.map(|_| status)
+}
+
+async fn upload_snapshot(
+    state: &Arc<AppState>,
+    target: &S3Target,
+    at: OffsetDateTime,
+) -> AppResult<(String, u64)> {
+    let format = time::format_description::parse("[year]-[month]-[day]T[hour][minute][second]Z")
+        .map_err(|e| AppError::internal(format!("backup name format: {e}")))?;
+    let stamp = at
+        .format(&format)
+        .map_err(|e| AppError::internal(format!("backup name: {e}")))?;
+    let key = format!("{}{stamp}.{}", target.prefix, target.format.extension());
+
+    let snapshot = {
+        let state = state.clone();
+        let format = target.format;
+        tokio::task::spawn_blocking(move || build_snapshot(&state, format))
+            .await
+            .map_err(|e| AppError::internal(format!("backup task failed: {e}")))??
+    };
+    let body = tokio::fs::read(&snapshot).await;
+    let _ = tokio::fs::remove_file(&snapshot).await;
+    let body = body.map_err(|e| AppError::io("read backup snapshot", e))?;
+    let bytes = body.len() as u64;
+    target.put_object(&key, body).await?;
+    Ok((key, bytes))
+}
+
+/// Writes the snapshot to `tmp/` while holding the git lock, so it never
+/// captures a half-made commit.
+fn build_snapshot(state: &AppState, format: BackupFormat) -> AppResult<PathBuf> {
+    let _git_lock = FileLock::acquire_waiting(&state.paths.git_lock)?;
+    let path = state.paths.tmp.join(format!(
+        "backup-{}.{}",
+        ulid::Ulid::new(),
+        format.extension()
+    ));
+    let built = match format {
+        BackupFormat::Bundle => {
+            let out = path.to_string_lossy().into_owned();
+            gitops::run_git(
+                &state.paths.repo,
+                &["bundle", "create", "--quiet", &out, "--all"],
+                &state.cfg,
+            )
+            .map(|_| ())
+        }
+        BackupFormat::Tarball => write_tarball(&state.paths.base, &path)
+            .map_err(|e| AppError::io("write backup tarball", e)),
+    };
+    if let Err(err) = built {
+        let _ = fs::remove_file(&path);
+        return Err(err);
+    }
+    Ok(path)
+}
+
+fn write_tarball(base: &Path, path: &Path) -> std::io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    tar.follow_symlinks(false);
+    tar.append_dir_all("repo", base.join("repo"))?;
+    tar.append_dir_all("files", base.join("files"))?;
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Backs up every `interval`, counted from the last successful run so a
+/// restart does not trigger an extra upload.
+pub fn spawn_periodic(state: Arc<AppState>) {
+    let Some(interval) = state.backup.as_ref().map(|t| t.interval) else {
+        return;
+    };
+    tokio::spawn(async move {
+        loop {
+            let last = read_status(&state.kv)
+                .ok()
+                .and_then(|s| s.last_success.or(s.last_failure));
+            let wait = last.map_or(Duration::ZERO, |at| {
+                Duration::try_from(at + interval - OffsetDateTime::now_utc())
+                    .unwrap_or(Duration::ZERO)
+            });
+            tokio::time::sleep(wait).await;
+            match run_once(&state).await {
+                Ok(status) => info!(
+                    "backup uploaded {} ({} bytes)",
+                    status.last_key.unwrap_or_default(),
+                    status.last_bytes.unwrap_or_default()
+                ),
+                Err(AppError::Conflict(_)) => {}
+                Err(err) => warn!("backup failed: {err:?}"),
+            }
+            // Also covers a manual run that started just before this one.
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+struct SigningInput<'a> {
+    method: &'a str,
+    path: &'a str,
+    host: &'a str,
+    payload_hash: &'a str,
+    region: &'a str,
+    access_key: &'a str,
+    secret_key: &'a str,
+}
+
+fn amz_date(at: OffsetDateTime) -> AppResult<String> {
+    let format = time::format_description::parse("[year][month][day]T[hour][minute][second]Z")
+        .map_err(|e| AppError::internal(format!("amz date format: {e}")))?;
+    at.format(&format)
+        .map_err(|e| AppError::internal(format!("amz date: {e}")))
+}
+
+/// The `Authorization` header for a request with no query string, signing
+/// `host`, `x-amz-content-sha256` and `x-amz-date`.
+fn sign_v4(input: &SigningInput<'_>, at: OffsetDateTime) -> AppResult<String> {
+    let amz_date = amz_date(at)?;
+    let day = &amz_date[..8];
+    let scope = format!("{day}/{}/s3/aws4_request", input.region);
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{}",
+        input.method, input.path, input.host, input.payload_hash, input.payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical.as_bytes()))
+    );
+    let key = signing_key(input.secret_key, day, input.region, "s3");
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+    Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        input.access_key
+    ))
+}
+
+fn signing_key(secret: &str, day: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac(format!("AWS4{secret}").as_bytes(), day.as_bytes());
+    let k_region = hmac(&k_date, region.as_bytes());
+    let k_service = hmac(&k_region, service.as_bytes());
+    hmac(&k_service, b"aws4_request")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// S3's URI encoding: everything but unreserved characters is `%XX`, and
+/// `/` is kept only inside object keys.
+fn uri_encode(raw: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for b in raw.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) || (keep_slash && b == b'/') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        // From the AWS Signature Version 4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(uri_encode("a b/c+d", true), "a%20b/c%2Bd");
+        assert_eq!(uri_encode("a/b", false), "a%2Fb");
+    }
+}
@@ -0,0 +1,83 @@
+//! Content-addressed lookup: paste sha256 -> paste id, kept in the runtime
+//! kv store. Entries are hints; every hit is checked against the paste's
+//! current meta, and a miss falls back to the in-memory [`MetaIndex`] so
+//! pastes written before the kv entries (or by the digest and self-test
+//! jobs) are still found without touching the disk.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    index::MetaIndex,
+    kv::Kv,
+    store,
+    types::PasteMeta,
+};
+
+const BLOBS_NS: &str = "blobs";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlobRecord {
+    sha256: String,
+    id: String,
+}
+
+/// Pastes sealed with the server key hash their ciphertext, so a client's
+/// hash of what it sent would never match; they are not addressable here.
+fn addressable(meta: &PasteMeta) -> bool {
+    !meta.sealed_at_rest()
+}
+
+/// Normalises a client-supplied digest to lowercase hex.
+pub fn parse_sha256(raw: &str) -> AppResult<String> {
+    if raw.len() != 64 || !raw.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(AppError::BadRequest(
+            "expected a sha256 as 64 hex characters".to_string(),
+        ));
+    }
+    Ok(raw.to_ascii_lowercase())
+}
+
+/// Indexes the paste's current content. Called after its commit lands.
+pub fn record(kv: &Kv, meta: &PasteMeta) -> AppResult<()> {
+    if !addressable(meta) {
+        return Ok(());
+    }
+    let record = BlobRecord {
+        sha256: meta.sha256.clone(),
+        id: meta.id.clone(),
+    };
+    kv.put(BLOBS_NS, &meta.sha256, &record, None)
+}
+
+/// A paste whose current bytes hash to `sha256`, if any.
+pub fn find(
+    repo: &Path,
+    cfg: &ServeCmd,
+    index: &MetaIndex,
+    kv: &Kv,
+    sha256: &str,
+) -> AppResult<Option<PasteMeta>> {
+    let hint = kv.get::<BlobRecord>(BLOBS_NS, sha256)?;
+    if let Some(record) = &hint {
+        match store::read_meta(repo, cfg, &record.id) {
+            Ok(meta) if meta.sha256 == sha256 && addressable(&meta) => return Ok(Some(meta)),
+            // Appended to since, or gone: the index settles it.
+            Ok(_) | Err(AppError::NotFound(_)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    index.sync(repo, cfg);
+    let Some(id) = index.newest_with_sha256(sha256, addressable) else {
+        if hint.is_some() {
+            kv.delete(BLOBS_NS, sha256)?;
+        }
+        return Ok(None);
+    };
+    let meta = store::read_meta(repo, cfg, &id)?;
+    record(kv, &meta)?;
+    Ok(Some(meta))
+}
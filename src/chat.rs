@@ -0,0 +1,424 @@
+//! Chat messages for new pastes: a Slack-compatible incoming webhook, a
+//! Discord webhook or a Matrix room gets a one-line notice with the view
+//! link whenever a listed paste is created, optionally only for some tags.
+//!
+//! Unlike `--webhook-url` these are a courtesy, not a feed: each message is
+//! sent once, and a failure is logged and dropped.
+
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use serde_json::json;
+use tokio::sync::broadcast;
+use tracing::warn;
+use ulid::Ulid;
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    events::{EventKind, PasteEvent},
+    render,
+    types::AppState,
+    zst,
+};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+enum Target {
+    /// Slack, Mattermost, Rocket.Chat and anything else that takes
+    /// `{"text": ...}` in Slack's mrkdwn.
+    Slack(reqwest::Url),
+    Discord(reqwest::Url),
+    Matrix {
+        homeserver: reqwest::Url,
+        room: String,
+        token: Arc<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Chat {
+    targets: Vec<Target>,
+    /// Only pastes with one of these tags are announced; empty means all.
+    tags: Vec<String>,
+    /// Prefix for view links, e.g. `http://paste.lan:8090`.
+    link_base: String,
+}
+
+/// The parts of a paste a message mentions.
+struct Notice {
+    name: String,
+    url: String,
+    tag: Option<String>,
+    size: usize,
+}
+
+impl Chat {
+    pub fn from_config(cfg: &ServeCmd) -> AppResult<Option<Self>> {
+        let mut targets = Vec::new();
+        for raw in &cfg.chat_slack_url {
+            targets.push(Target::Slack(parse_url("--chat-slack-url", raw)?));
+        }
+        for raw in &cfg.chat_discord_url {
+            targets.push(Target::Discord(parse_url("--chat-discord-url", raw)?));
+        }
+        match (&cfg.chat_matrix_homeserver, cfg.chat_matrix_room.is_empty()) {
+            (None, true) => {}
+            (Some(_), true) | (None, false) => {
+                return Err(AppError::internal(
+                    "--chat-matrix-homeserver and --chat-matrix-room go together",
+                ));
+            }
+            (Some(homeserver), false) => {
+                let Some(token_file) = &cfg.chat_matrix_token_file else {
+                    return Err(AppError::internal(
+                        "--chat-matrix-homeserver needs --chat-matrix-token-file",
+                    ));
+                };
+                let homeserver = parse_url("--chat-matrix-homeserver", homeserver)?;
+                let token = Arc::new(read_token(token_file)?);
+                for room in &cfg.chat_matrix_room {
+                    if !room.starts_with('!') || !room.contains(':') {
+                        return Err(AppError::internal(format!(
+                            "invalid --chat-matrix-room '{room}' (expected !id:server)"
+                        )));
+                    }
+                    targets.push(Target::Matrix {
+                        homeserver: homeserver.clone(),
+                        room: room.clone(),
+                        token: token.clone(),
+                    });
+                }
+            }
+        }
+        if targets.is_empty() {
+            return Ok(None);
+        }
+        let Some(link_base) = &cfg.chat_link_base else {
+            return Err(AppError::internal(
+                "chat notifications need --chat-link-base for their links",
+            ));
+        };
+        parse_url("--chat-link-base", link_base)?;
+        Ok(Some(Self {
+            targets,
+            tags: cfg.chat_tag.clone(),
+            link_base: link_base.trim_end_matches('/').to_string(),
+        }))
+    }
+
+    /// The notice for `event`, if it should be announced.
+    fn notice(&self, event: &PasteEvent) -> Option<Notice> {
+        if event.kind != EventKind::Created {
+            return None;
+        }
+        let paste = &event.paste;
+        if !self.tags.is_empty()
+            && !paste
+                .tag
+                .as_ref()
+                .is_some_and(|tag| self.tags.contains(tag))
+        {
+            return None;
+        }
+        let file = paste.path.rsplit('/').next().unwrap_or(&paste.path);
+        let file = file.strip_suffix(zst::SUFFIX).unwrap_or(file);
+        let name = file.split_once("__").map_or(file, |(_, name)| name);
+        Some(Notice {
+            name: name.to_string(),
+            url: format!("{}/p/{}", self.link_base, paste.id),
+            tag: paste.tag.clone(),
+            size: paste.size,
+        })
+    }
+
+    /// Sends `notice` to every target, logging the ones that fail.
+    async fn announce(&self, client: &reqwest::Client, notice: &Notice) {
+        for target in &self.targets {
+            if let Err(err) = send(client, target, notice).await {
+                warn!("chat notification for {} failed: {err:?}", notice.url);
+            }
+        }
+    }
+}
+
+async fn send(client: &reqwest::Client, target: &Target, notice: &Notice) -> AppResult<()> {
+    let request = match target {
+        Target::Slack(url) => client.post(url.clone()).json(&json!({
+            "text": slack_text(notice),
+        })),
+        Target::Discord(url) => client.post(url.clone()).json(&json!({
+            "content": discord_text(notice),
+            "allowed_mentions": {"parse": []},
+        })),
+        Target::Matrix {
+            homeserver,
+            room,
+            token,
+        } => {
+            let mut url = homeserver.clone();
+            url.path_segments_mut()
+                .map_err(|_| AppError::internal("matrix homeserver cannot be a base"))?
+                .pop_if_empty()
+                .extend([
+                    "_matrix",
+                    "client",
+                    "v3",
+                    "rooms",
+                    room,
+                    "send",
+                    "m.room.message",
+                    &Ulid::new().to_string(),
+                ]);
+            client
+                .put(url)
+                .bearer_auth(token.as_str())
+                .json(&matrix_content(notice))
+        }
+    };
+    let response = request
+        .send()
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("chat request failed: {e}")))?;
+    if !response.status().is_success() {
+        return Err(AppError::ServiceUnavailable(format!(
+            "chat endpoint returned {}",
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+/// The tag and size after the link, shared by every format.
+fn details(notice: &Notice, code: impl Fn(&str) -> String) -> String {
+    let size = render::human_size(notice.size);
+    match &notice.tag {
+        Some(tag) => format!("{} · {size}", code(tag)),
+        None => size,
+    }
+}
+
+fn slack_text(notice: &Notice) -> String {
+    // mrkdwn wants `&`, `<` and `>` escaped; a `|` would split the link.
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('|', "¦")
+    };
+    format!(
+        "New paste <{}|{}> ({})",
+        notice.url,
+        escape(&notice.name),
+        details(notice, |tag| format!("`{}`", escape(tag).replace('`', "'")))
+    )
+}
+
+fn discord_text(notice: &Notice) -> String {
+    let escape = |s: &str| {
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            if matches!(
+                c,
+                '\\' | '[' | ']' | '(' | ')' | '*' | '_' | '~' | '`' | '|' | '<' | '>' | '@' | '#'
+            ) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    };
+    format!(
+        "New paste [{}](<{}>) ({})",
+        escape(&notice.name),
+        notice.url,
+        details(notice, |tag| format!("`{}`", tag.replace('`', "'")))
+    )
+}
+
+fn matrix_content(notice: &Notice) -> serde_json::Value {
+    let plain = format!(
+        "New paste {} {} ({})",
+        notice.name,
+        notice.url,
+        details(notice, str::to_string)
+    );
+    let html = format!(
+        "New paste <a href=\"{}\">{}</a> ({})",
+        render::html_escape(&notice.url),
+        render::html_escape(&notice.name),
+        details(notice, |tag| format!(
+            "<code>{}</code>",
+            render::html_escape(tag)
+        ))
+    );
+    json!({
+        "msgtype": "m.notice",
+        "body": plain,
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    })
+}
+
+fn parse_url(flag: &str, raw: &str) -> AppResult<reqwest::Url> {
+    reqwest::Url::parse(raw)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some())
+        .ok_or_else(|| AppError::internal(format!("invalid {flag} '{raw}'")))
+}
+
+fn read_token(path: &Path) -> AppResult<String> {
+    let token = fs::read_to_string(path)
+        .map_err(|e| AppError::io("read matrix token file", e))?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        return Err(AppError::internal("matrix token file is empty"));
+    }
+    Ok(token)
+}
+
+/// Announces new pastes from the event stream until the server stops.
+pub fn spawn_worker(state: Arc<AppState>, chat: Chat) {
+    let (_, mut rx) = state.events.subscribe(None);
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(SEND_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("chat notifications disabled: build http client: {err}");
+                return;
+            }
+        };
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Some(notice) = chat.notice(&event) {
+                        chat.announce(&client, &notice).await;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("chat notifier fell behind; {skipped} events skipped");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{Router, extract::Request, http::StatusCode, routing::any};
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::types::RecentItem;
+
+    fn event(kind: EventKind, tag: Option<&str>) -> PasteEvent {
+        PasteEvent {
+            seq: 1,
+            kind,
+            sha256: String::new(),
+            paste: RecentItem {
+                id: "01CHAT".to_string(),
+                created_at: OffsetDateTime::UNIX_EPOCH,
+                path: "pastes/2026/10/16/01CHAT__build <log>.txt".to_string(),
+                commit: String::new(),
+                tag: tag.map(str::to_string),
+                size: 2048,
+                content_type: "text/plain".to_string(),
+                stars: 0,
+                owner: None,
+            },
+        }
+    }
+
+    #[test]
+    fn only_new_pastes_under_selected_tags_are_announced() {
+        let chat = Chat {
+            targets: Vec::new(),
+            tags: vec!["ci".to_string()],
+            link_base: "http://paste.lan:8090".to_string(),
+        };
+        assert!(chat.notice(&event(EventKind::Created, None)).is_none());
+        assert!(
+            chat.notice(&event(EventKind::Deleted, Some("ci")))
+                .is_none()
+        );
+        let notice = chat
+            .notice(&event(EventKind::Created, Some("ci")))
+            .expect("notice");
+        assert_eq!(notice.url, "http://paste.lan:8090/p/01CHAT");
+        assert_eq!(
+            slack_text(&notice),
+            "New paste <http://paste.lan:8090/p/01CHAT|build &lt;log&gt;.txt> (`ci` · 2.0 KiB)"
+        );
+        assert_eq!(
+            discord_text(&notice),
+            "New paste [build \\<log\\>.txt](<http://paste.lan:8090/p/01CHAT>) (`ci` · 2.0 KiB)"
+        );
+    }
+
+    #[tokio::test]
+    async fn each_target_gets_its_own_format() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = seen.clone();
+        let app = Router::new().fallback(any(move |request: Request| {
+            let record = record.clone();
+            async move {
+                let (parts, body) = request.into_parts();
+                let body = axum::body::to_bytes(body, 1 << 16).await.expect("body");
+                let auth = parts
+                    .headers
+                    .get("authorization")
+                    .map(|v| v.to_str().expect("auth").to_string());
+                record.lock().expect("lock").push((
+                    parts.method.to_string(),
+                    parts.uri.path().to_string(),
+                    auth,
+                    serde_json::from_slice::<serde_json::Value>(&body).expect("json"),
+                ));
+                StatusCode::OK
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let base = format!("http://{}", listener.local_addr().expect("addr"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = |path: &str| reqwest::Url::parse(&format!("{base}{path}")).expect("url");
+        let chat = Chat {
+            targets: vec![
+                Target::Slack(url("/slack")),
+                Target::Discord(url("/discord")),
+                Target::Matrix {
+                    homeserver: url("/"),
+                    room: "!room:lan".to_string(),
+                    token: Arc::new("mx-token".to_string()),
+                },
+            ],
+            tags: Vec::new(),
+            link_base: "http://paste.lan".to_string(),
+        };
+        let notice = chat
+            .notice(&event(EventKind::Created, None))
+            .expect("notice");
+        chat.announce(&reqwest::Client::new(), &notice).await;
+
+        let seen = seen.lock().expect("lock");
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0].3["text"], slack_text(&notice));
+        assert_eq!(seen[1].3["allowed_mentions"]["parse"], json!([]));
+        let (method, path, auth, body) = &seen[2];
+        assert_eq!(method, "PUT");
+        assert!(path.starts_with("/_matrix/client/v3/rooms/!room:lan/send/m.room.message/"));
+        assert_eq!(auth.as_deref(), Some("Bearer mx-token"));
+        assert_eq!(body["msgtype"], "m.notice");
+        assert_eq!(
+            body["formatted_body"],
+            "New paste <a href=\"http://paste.lan/p/01CHAT\">build &lt;log&gt;.txt</a> (2.0 KiB)"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Typed client for the HTTP API, for Rust tools that talk to a lanpaste
+//! server, and the CLI subcommands built on it. `lanpaste paste` sends stdin
+//! or a file to a server as a new paste and returns its URL, so the binary
+//! that serves pastes is also the natural way to make them from a shell.
+//!
+//! Requests and responses use the server's own types from [`crate::types`].
+//! A non-2xx response becomes the [`AppError`] variant for its status.
+
+use std::{path::Path, time::Duration};
+
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    auth::API_KEY_HEADER,
+    config::{GcCmd, PasteCmd, RotateTokenCmd},
+    enroll::{self, ClientConfig},
+    errors::{AppError, AppResult},
+    gc::GcReport,
+    token::Rotated,
+    types::{
+        CreatePasteParams, CreatePasteResponse, MetaResponse, MineParams, RecentItem, RecentParams,
+    },
+};
+
+/// Requests that do not set their own timeout give up after this long.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One server, and the credentials to send it.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: String,
+    api_key: Option<String>,
+    token: Option<String>,
+}
+
+impl Client {
+    /// `base` is the server's root URL, such as `http://paste.lan:8090`.
+    pub fn new(base: &str, timeout: Duration) -> AppResult<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| AppError::internal(format!("build http client: {e}")))?;
+        Ok(Self {
+            http,
+            base: base.trim_end_matches('/').to_string(),
+            api_key: None,
+            token: None,
+        })
+    }
+
+    /// The server and key that `lanpaste enroll` saved.
+    pub fn from_config(config: &ClientConfig, timeout: Duration) -> AppResult<Self> {
+        Ok(Self::new(&config.server, timeout)?.with_api_key(config.api_key.clone()))
+    }
+
+    /// Sent as `X-API-Key` on every request.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Sent as `X-Paste-Token` when creating pastes on a server that uses
+    /// `--token` instead of API keys.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Absolute URL of a route, such as `/api/v1/paste`, under `base`.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+
+    /// Absolute URL of a link the server handed out, such as a response's
+    /// `view_url`. Those already start with the server's `--base-url` path,
+    /// so they are resolved against the host of `base` rather than appended.
+    pub fn link(&self, href: &str) -> String {
+        reqwest::Url::parse(&self.base)
+            .and_then(|base| base.join(href))
+            .map_or_else(|_| self.url(href), String::from)
+    }
+
+    /// `POST /api/v1/paste`.
+    pub async fn create_paste(
+        &self,
+        params: &CreatePasteParams,
+        body: impl Into<reqwest::Body>,
+    ) -> AppResult<CreatePasteResponse> {
+        let mut request = self
+            .request(reqwest::Method::POST, "/api/v1/paste")
+            .query(params)
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.header("X-Paste-Token", token);
+        }
+        send(request, "paste").await
+    }
+
+    /// `GET /api/v1/p/{id}`; `id` may be the short id.
+    pub async fn get_meta(&self, id: &str) -> AppResult<MetaResponse> {
+        let path = format!("/api/v1/p/{id}");
+        send(self.request(reqwest::Method::GET, &path), "meta").await
+    }
+
+    /// `GET /api/v1/recent`.
+    pub async fn recent(&self, params: &RecentParams) -> AppResult<Vec<RecentItem>> {
+        let request = self
+            .request(reqwest::Method::GET, "/api/v1/recent")
+            .query(params);
+        send(request, "recent").await
+    }
+
+    /// `GET /api/v1/mine`: pastes created with the client's API key.
+    pub async fn mine(&self, params: &MineParams) -> AppResult<Vec<RecentItem>> {
+        let request = self
+            .request(reqwest::Method::GET, "/api/v1/mine")
+            .query(params);
+        send(request, "mine").await
+    }
+
+    /// `POST /api/v1/admin/gc`. It holds the git lock and repacks, so it is
+    /// allowed up to `timeout` rather than the client's default.
+    pub async fn admin_gc(&self, timeout: Duration) -> AppResult<GcReport> {
+        let request = self
+            .request(reqwest::Method::POST, "/api/v1/admin/gc")
+            .timeout(timeout);
+        send(request, "gc").await
+    }
+
+    /// `POST /api/v1/admin/token/rotate`: replaces `replace` (by default
+    /// the client's token, or the server's only one) with `new`, or a token
+    /// the server picks, and keeps the old one working for `grace`.
+    pub async fn rotate_token(
+        &self,
+        replace: Option<&str>,
+        new: Option<&str>,
+        grace: Option<Duration>,
+    ) -> AppResult<Rotated> {
+        let body = serde_json::json!({
+            "replace": replace,
+            "token": new,
+            "grace": grace.map(|g| format!("{}s", g.as_secs())),
+        });
+        let mut request = self
+            .request(reqwest::Method::POST, "/api/v1/admin/token/rotate")
+            .json(&body);
+        if let Some(token) = &self.token {
+            request = request.header("X-Paste-Token", token);
+        }
+        send(request, "rotate token").await
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, self.url(path));
+        match &self.api_key {
+            Some(key) => request.header(API_KEY_HEADER, key),
+            None => request,
+        }
+    }
+}
+
+async fn send<T: serde::de::DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    what: &str,
+) -> AppResult<T> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("{what} request failed: {e}")))?;
+    enroll::expect_json(response).await
+}
+
+/// Posts the paste and returns the absolute view URL, or the raw URL with
+/// `--raw`. The server and API key fall back to the client config that
+/// `lanpaste enroll` saved, when there is one.
+pub async fn run_paste(cmd: PasteCmd) -> AppResult<String> {
+    let config = client_config(cmd.config.as_deref())?;
+    let base = cmd
+        .to
+        .clone()
+        .or_else(|| config.as_ref().map(|c| c.server.clone()))
+        .ok_or_else(|| {
+            AppError::BadRequest("no server: pass --to or run `lanpaste enroll` first".to_string())
+        })?;
+    let mut client = Client::new(&base, cmd.timeout)?;
+    if let Some(key) = cmd.api_key.clone().or_else(|| config.map(|c| c.api_key)) {
+        client = client.with_api_key(key);
+    }
+    if let Some(token) = cmd.token.clone() {
+        client = client.with_token(token);
+    }
+
+    let file = cmd.file.as_deref().filter(|p| *p != Path::new("-"));
+    let body = match file {
+        Some(path) => tokio::fs::read(path)
+            .await
+            .map_err(|e| AppError::io(&format!("read {}", path.display()), e))?,
+        None => {
+            let mut buf = Vec::new();
+            tokio::io::stdin()
+                .read_to_end(&mut buf)
+                .await
+                .map_err(|e| AppError::io("read stdin", e))?;
+            buf
+        }
+    };
+    let name = cmd.name.clone().or_else(|| {
+        file.and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().into_owned())
+    });
+    let params = CreatePasteParams {
+        name,
+        tag: cmd.tag,
+        msg: cmd.msg,
+        visibility: cmd.visibility,
+        dedupe: cmd.dedupe.then_some(true),
+        lang: cmd.lang,
+        ..CreatePasteParams::default()
+    };
+    let created = client.create_paste(&params, body).await?;
+    let path = if cmd.raw {
+        created.raw_url
+    } else {
+        created.view_url
+    };
+    Ok(client.link(&path))
+}
+
+/// `lanpaste admin gc --server`: runs gc on a live server and returns its
+/// report.
+pub async fn run_remote_gc(cmd: &GcCmd, server: &str) -> AppResult<GcReport> {
+    let config = client_config(cmd.config.as_deref())?;
+    let api_key = cmd
+        .api_key
+        .clone()
+        .or_else(|| config.map(|c| c.api_key))
+        .ok_or_else(|| AppError::BadRequest("gc needs an admin key: pass --api-key".to_string()))?;
+    let client = Client::new(server, DEFAULT_TIMEOUT)?.with_api_key(api_key);
+    client.admin_gc(Duration::from_secs(30 * 60)).await
+}
+
+/// `lanpaste admin rotate-token`: authorized by the token in effect or an
+/// admin key, whichever the server takes.
+pub async fn run_rotate_token(cmd: &RotateTokenCmd) -> AppResult<Rotated> {
+    let config = client_config(cmd.config.as_deref())?;
+    let mut client = Client::new(&cmd.server, DEFAULT_TIMEOUT)?;
+    if let Some(key) = cmd.api_key.clone().or_else(|| config.map(|c| c.api_key)) {
+        client = client.with_api_key(key);
+    }
+    if let Some(token) = cmd.token.clone() {
+        client = client.with_token(token);
+    }
+    client
+        .rotate_token(cmd.replace.as_deref(), cmd.new_token.as_deref(), cmd.grace)
+        .await
+}
+
+/// `--config`, which must exist, or the default path when it does.
+fn client_config(path: Option<&Path>) -> AppResult<Option<ClientConfig>> {
+    match path {
+        Some(path) => enroll::read_client_config(path).map(Some),
+        None => match enroll::default_client_config_path() {
+            Some(path) if path.exists() => enroll::read_client_config(&path).map(Some),
+            _ => Ok(None),
+        },
+    }
+}
@@ -0,0 +1,156 @@
+//! Collections: a titled list of pastes, such as a bug's logs, configs and
+//! notes, shared as one page at `/c/{id}`. Each collection is a JSON file
+//! under `collections/` in the repo and is committed like a paste, so the
+//! history of who grouped what travels with the pastes themselves.
+//!
+//! Members are stored by full paste id. A member that is deleted later stays
+//! in the file and is skipped when the collection is read.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+use crate::{
+    errors::{AppError, AppResult},
+    store,
+    types::{Collection, CollectionDraft},
+};
+
+/// Members one collection may hold.
+pub const MAX_MEMBERS: usize = 500;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 2000;
+
+pub fn rel_path(id: &str) -> String {
+    format!("collections/{id}.json")
+}
+
+/// The stored collection; unknown ids are `NotFound`.
+pub fn read(repo: &Path, id: &str) -> AppResult<Collection> {
+    read_raw(repo, id)?
+        .map(|(collection, _)| collection)
+        .ok_or_else(|| AppError::NotFound("collection not found".to_string()))
+}
+
+/// Writes a new collection file; the caller holds the git lock and commits
+/// the draft with [`crate::gitops::commit_collection`].
+pub fn build_create_draft(
+    repo: &Path,
+    title: &str,
+    description: Option<&str>,
+    pastes: &[String],
+) -> AppResult<CollectionDraft> {
+    let title = check_text("title", title, MAX_TITLE_CHARS)?;
+    let description = description
+        .map(|d| check_text("description", d, MAX_DESCRIPTION_CHARS))
+        .transpose()?;
+    let now = OffsetDateTime::now_utc();
+    let mut collection = Collection {
+        id: Ulid::new().to_string(),
+        created_at: now,
+        updated_at: now,
+        title,
+        description,
+        pastes: Vec::new(),
+    };
+    add_members(repo, &mut collection, pastes)?;
+    let subject = format!("collection: create {}", collection.id);
+    write_draft(repo, collection, None, subject)
+}
+
+/// Appends pastes to an existing collection. Ids already in it are skipped,
+/// so adding the same paste twice is harmless.
+pub fn build_add_draft(repo: &Path, id: &str, pastes: &[String]) -> AppResult<CollectionDraft> {
+    let (mut collection, previous) = read_raw(repo, id)?
+        .ok_or_else(|| AppError::NotFound("collection not found".to_string()))?;
+    if pastes.is_empty() {
+        return Err(AppError::BadRequest("no pastes to add".to_string()));
+    }
+    let before = collection.pastes.len();
+    add_members(repo, &mut collection, pastes)?;
+    collection.updated_at = OffsetDateTime::now_utc();
+    let added = collection.pastes.len() - before;
+    let subject = format!("collection: add {added} to {id}");
+    write_draft(repo, collection, Some(previous), subject)
+}
+
+/// Puts the previous file back, or removes a new one.
+pub fn rollback(draft: &CollectionDraft) {
+    let _ = match &draft.previous {
+        Some(previous) => fs::write(&draft.abs_path, previous),
+        None => fs::remove_file(&draft.abs_path),
+    };
+}
+
+fn add_members(repo: &Path, collection: &mut Collection, pastes: &[String]) -> AppResult<()> {
+    for key in pastes {
+        let id = store::resolve_short_id(repo, key)?.unwrap_or_else(|| key.clone());
+        match store::read_stored_meta(repo, &id) {
+            Ok(_) => {}
+            Err(AppError::NotFound(_)) => {
+                return Err(AppError::BadRequest(format!("paste {key} not found")));
+            }
+            Err(err) => return Err(err),
+        }
+        if !collection.pastes.contains(&id) {
+            collection.pastes.push(id);
+        }
+    }
+    if collection.pastes.len() > MAX_MEMBERS {
+        return Err(AppError::BadRequest(format!(
+            "a collection holds at most {MAX_MEMBERS} pastes"
+        )));
+    }
+    Ok(())
+}
+
+fn check_text(field: &str, value: &str, max_chars: usize) -> AppResult<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(AppError::BadRequest(format!("{field} must not be empty")));
+    }
+    if value.chars().count() > max_chars {
+        return Err(AppError::BadRequest(format!(
+            "{field} is longer than {max_chars} characters"
+        )));
+    }
+    Ok(value.to_string())
+}
+
+fn write_draft(
+    repo: &Path,
+    collection: Collection,
+    previous: Option<Vec<u8>>,
+    subject: String,
+) -> AppResult<CollectionDraft> {
+    let rel_path = rel_path(&collection.id);
+    let abs_path = repo.join(&rel_path);
+    fs::create_dir_all(repo.join("collections"))
+        .map_err(|e| AppError::io("create collections", e))?;
+    let data = serde_json::to_vec_pretty(&collection)
+        .map_err(|e| AppError::internal(format!("serialize collection: {e}")))?;
+    fs::write(&abs_path, data).map_err(|e| AppError::io("write collection", e))?;
+    Ok(CollectionDraft {
+        collection,
+        rel_path,
+        abs_path,
+        previous,
+        subject,
+    })
+}
+
+fn read_raw(repo: &Path, id: &str) -> AppResult<Option<(Collection, Vec<u8>)>> {
+    // Collection ids are ULIDs, like paste ids.
+    if !store::is_valid_paste_id(id) {
+        return Ok(None);
+    }
+    let raw = match fs::read(repo.join(rel_path(id))) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(AppError::io("read collection", e)),
+    };
+    let collection = serde_json::from_slice(&raw)
+        .map_err(|e| AppError::internal(format!("parse collection: {e}")))?;
+    Ok(Some((collection, raw)))
+}
@@ -0,0 +1,145 @@
+//! A single thread that makes the commits requests ask for, in arrival
+//! order. Creates, appends and metadata edits queue here instead of racing
+//! each other for the git lock, so under load they are served first come,
+//! first served and none fails because another happened to be committing.
+//!
+//! Each job still runs under the git lock: retention, archival, digests,
+//! backups and edit leases take it directly.
+
+use std::{
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tokio::sync::oneshot;
+use tracing::error;
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    gitops::{self, FileLock},
+    types::AppPaths,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug)]
+pub struct Committer {
+    jobs: mpsc::Sender<Job>,
+    queued: Arc<AtomicUsize>,
+    git_lock: PathBuf,
+    lock_timeout: Duration,
+}
+
+impl Committer {
+    /// Starts the thread. It exits once the last handle is dropped and the
+    /// queue has drained.
+    pub fn start(cfg: &ServeCmd, paths: &AppPaths) -> AppResult<Self> {
+        Self::with_lock(paths.git_lock.clone(), cfg.lock_timeout)
+    }
+
+    fn with_lock(git_lock: PathBuf, lock_timeout: Duration) -> AppResult<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("lanpaste-commit".to_string())
+            .spawn(move || {
+                for job in queue {
+                    if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("commit job panicked; its request gets a 500");
+                    }
+                }
+            })
+            .map_err(|e| AppError::io("start commit thread", e))?;
+        Ok(Self {
+            jobs,
+            queued: Arc::default(),
+            git_lock,
+            lock_timeout,
+        })
+    }
+
+    /// Jobs waiting for their turn, not counting the one running.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Queues `job` behind every earlier one and waits for its result. The
+    /// job runs even if the caller stops waiting, so a client that hangs up
+    /// mid-request does not leave a half-made commit behind.
+    pub async fn run<T, F>(&self, job: F) -> AppResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> AppResult<T> + Send + 'static,
+    {
+        let (reply, answer) = oneshot::channel();
+        let queued = self.queued.clone();
+        let git_lock = self.git_lock.clone();
+        let wait = self.lock_timeout;
+        queued.fetch_add(1, Ordering::Relaxed);
+        let wrapped: Job = Box::new(move || {
+            queued.fetch_sub(1, Ordering::Relaxed);
+            let result = match FileLock::acquire_within(&git_lock, wait) {
+                Ok(_git_lock) => job(),
+                Err(AppError::Conflict(_)) => Err(gitops::lock_busy(wait)),
+                Err(err) => Err(err),
+            };
+            let _ = reply.send(result);
+        });
+        if self.jobs.send(wrapped).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(AppError::internal("commit thread has stopped"));
+        }
+        answer
+            .await
+            .map_err(|_| AppError::internal("commit job failed without a result"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn jobs_never_overlap_and_a_held_lock_means_busy() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let git_lock = dir.path().join("git.lock");
+        let committer = Arc::new(
+            Committer::with_lock(git_lock.clone(), Duration::from_millis(200)).expect("start"),
+        );
+        let inside = Arc::new(AtomicBool::new(false));
+        let tasks: Vec<_> = (0..20)
+            .map(|n| {
+                let (committer, inside) = (committer.clone(), inside.clone());
+                tokio::spawn(async move {
+                    committer
+                        .run(move || {
+                            assert!(!inside.swap(true, Ordering::SeqCst), "overlapping jobs");
+                            thread::sleep(Duration::from_millis(2));
+                            inside.store(false, Ordering::SeqCst);
+                            Ok(n)
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for (n, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.await.expect("task").expect("job"), n);
+        }
+        assert_eq!(committer.queued(), 0);
+
+        let failed = committer.run(|| -> AppResult<()> { panic!("boom") }).await;
+        assert!(matches!(failed, Err(AppError::Internal(_))));
+        let _held = FileLock::acquire(&git_lock).expect("lock");
+        let err = committer.run(|| Ok(())).await.expect_err("lock held");
+        assert!(matches!(err, AppError::Busy(_)));
+    }
+}
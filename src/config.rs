@@ -1,6 +1,16 @@
 use clap::{Parser, Subcommand, ValueEnum};
 use ipnet::IpNet;
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    backup::BackupFormat,
+    soak::{OpMix, SizeDist},
+    types::Visibility,
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "lanpaste")]
@@ -11,33 +21,598 @@ pub struct Cli {
 
 #[derive(Debug, Clone, Subcommand)]
 pub enum Commands {
-    Serve(ServeCmd),
+    Serve(Box<ServeCmd>),
+    /// Check that `serve` with the same flags would start, and how to fix
+    /// what would stop it.
+    Doctor(Box<ServeCmd>),
+    /// Create a data directory with its repo, a starter config file, an API
+    /// key file and a systemd unit, without starting the server.
+    Init(InitCmd),
+    /// Print a random API key or token, optionally adding the key to an API
+    /// key file.
+    Keygen(KeygenCmd),
+    /// Generate synthetic load against a running server and check invariants.
+    Soak(SoakCmd),
+    /// Request an API key from a server and wait for an operator to approve it.
+    Enroll(EnrollCmd),
+    /// Upgrade a data directory written by an older release, in place.
+    Migrate(MigrateCmd),
+    /// Move old pastes out of the live repo into a tarball.
+    Archive(ArchiveCmd),
+    /// Send stdin or a file to a server as a new paste and print its URL.
+    Paste(PasteCmd),
+    /// Maintenance jobs for a running or stopped server.
+    #[command(subcommand)]
+    Admin(AdminCmd),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum AdminCmd {
+    /// Repack the repo, remove expired records and stale lock and staging
+    /// files, and check that metas and paste files agree.
+    Gc(GcCmd),
+    /// Replace a running server's shared `--token`, keeping the old one
+    /// working for a grace window. Prints the new token.
+    RotateToken(RotateTokenCmd),
 }
 
 #[derive(Debug, Clone, Parser)]
 pub struct ServeCmd {
     #[arg(long)]
     pub dir: PathBuf,
+    /// Listen address; repeat it to listen on several, such as a LAN and a
+    /// VPN address.
     #[arg(long, default_value = "0.0.0.0:8090")]
-    pub bind: SocketAddr,
+    pub bind: Vec<SocketAddr>,
+    /// Also serve the gRPC API (`proto/lanpaste.proto`) on this address.
+    #[arg(long, value_name = "ADDR")]
+    pub grpc_bind: Option<SocketAddr>,
+    /// Shared write token; repeat it to give each team or script its own.
     #[arg(long)]
-    pub token: Option<String>,
+    pub token: Vec<String>,
+    /// File with more tokens, one per line (blank lines and `#` comments
+    /// skipped). Re-read on reload, so removing a line revokes that token.
+    #[arg(long)]
+    pub token_file: Option<PathBuf>,
     #[arg(long)]
     pub api_keys_file: Option<PathBuf>,
+    /// With `--token` and no key file, require the token for API reads
+    /// (meta, raw, recent, ...) as well as writes.
+    #[arg(long)]
+    pub require_token_for_read: bool,
+    /// Require the token or a `paste:read` key for the dashboard and paste
+    /// pages too, through a login form and session cookie.
+    #[arg(long)]
+    pub protect_ui: bool,
     #[arg(long, default_value_t = 1_048_576)]
     pub max_bytes: usize,
+    /// Largest size a paste may grow to through appends.
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub max_total_bytes: usize,
+    /// Store pastes larger than this many bytes zstd-compressed in the repo
+    /// (`<file>.zst`); reads decompress them. Off by default.
+    #[arg(long, value_name = "BYTES")]
+    pub zstd_above: Option<usize>,
+    /// Answer an upload whose content and options match an existing paste
+    /// with that paste instead of a new commit; `?dedupe=` overrides it.
+    #[arg(long)]
+    pub dedupe: bool,
+    /// Compress HTML, JSON and text responses (gzip, br or zstd) when the client accepts it.
+    #[arg(long)]
+    pub compress: bool,
     #[arg(long, default_value = "off")]
     pub push: PushMode,
     #[arg(long, default_value = "origin")]
     pub remote: String,
-    #[arg(long)]
+    /// Client networks allowed to write: CIDRs or bare addresses, optionally with a `%zone`.
+    #[arg(long, value_parser = parse_cidr)]
     pub allow_cidr: Vec<IpNet>,
+    /// Web origins (e.g. `http://wiki.lan:3000`) whose pages may call the
+    /// API from the browser; repeatable, or `*` for any. Off by default.
+    #[arg(long, value_name = "ORIGIN", value_parser = parse_origin)]
+    pub cors_origin: Vec<String>,
+    /// Host names (`paste.lan`, `10.0.0.5:8090`) requests must be addressed
+    /// to; comma-separated or repeatable. Others get `400`, which keeps a
+    /// DNS-rebinding page from reaching the server. Off by default.
+    #[arg(
+        long,
+        value_name = "HOST[:PORT]",
+        value_delimiter = ',',
+        value_parser = parse_allowed_host
+    )]
+    pub allowed_hosts: Vec<AllowedHost>,
+    /// Methods allowed in cross-origin requests; repeatable.
+    #[arg(long, value_name = "METHOD", default_values = ["GET", "POST", "PATCH", "DELETE"], value_parser = parse_method)]
+    pub cors_method: Vec<String>,
+    /// Request headers allowed in cross-origin requests; repeatable.
+    #[arg(
+        long,
+        value_name = "HEADER",
+        default_values = ["content-type", "x-api-key", "x-paste-token", "idempotency-key", "x-paste-lease"],
+        value_parser = parse_header_name
+    )]
+    pub cors_header: Vec<String>,
+    /// `Content-Security-Policy` for HTML pages instead of the built-in one,
+    /// or `off` to send none.
+    #[arg(long, value_name = "POLICY", value_parser = parse_csp)]
+    pub csp: Option<String>,
+    /// Pages that may show this server's HTML in a frame
+    /// (`frame-ancestors`); repeatable.
+    #[arg(long, value_name = "SOURCE", default_values = ["'none'"], value_parser = parse_frame_ancestor)]
+    pub frame_ancestors: Vec<String>,
+    /// `Referrer-Policy` for HTML pages.
+    #[arg(long, value_name = "POLICY", default_value = "same-origin", value_parser = parse_referrer_policy)]
+    pub referrer_policy: String,
+    /// Reverse proxies (CIDRs or addresses; repeatable) whose `Forwarded` or
+    /// `X-Forwarded-For` header names the real client.
+    #[arg(long, value_name = "CIDR", value_parser = parse_cidr)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Also allow loopback, RFC1918, fc00::/7 and link-local clients.
+    #[arg(long)]
+    pub allow_private: bool,
+    /// JSON file of hostname allow/deny rules, checked before the CIDR allowlist.
+    #[arg(long)]
+    pub host_rules_file: Option<PathBuf>,
+    #[arg(long, default_value = "LAN Paste")]
+    pub git_author_name: String,
+    #[arg(long, default_value = "paste@lan")]
+    pub git_author_email: String,
+    /// 32-byte key (raw or hex) used to encrypt new paste bodies at rest with AES-256-GCM.
+    #[arg(long)]
+    pub encrypt_key_file: Option<PathBuf>,
+    /// Re-hash paste bytes on read and fail with `integrity_error` on mismatch.
+    #[arg(long, default_value = "off")]
+    pub verify_reads: VerifyMode,
+    /// With `--verify-reads sampled`, verify one in every N reads.
+    #[arg(long, default_value_t = 10)]
+    pub verify_sample_every: u64,
+    /// Create, read, render and delete a canary paste before serving; exit on failure.
+    #[arg(long)]
+    pub self_test: bool,
+    /// Repeat the self-test on this interval while serving, e.g. `1h`.
+    #[arg(long, value_parser = parse_duration)]
+    pub self_test_interval: Option<Duration>,
+    /// Build, store and send yesterday's digest every day at this UTC time (`HH:MM`).
+    #[arg(long, value_parser = parse_time_of_day)]
+    pub digest_at: Option<time::Time>,
+    /// POST notifications (e.g. the daily digest) as JSON to this URL.
+    #[arg(long)]
+    pub notify_webhook: Option<String>,
+    /// SMTP relay (`host:port`) used to email notifications; plain SMTP, no auth.
+    #[arg(long)]
+    pub notify_smtp: Option<String>,
+    #[arg(long, default_value = "lanpaste@localhost")]
+    pub notify_email_from: String,
+    /// Notification recipient; repeatable.
+    #[arg(long)]
+    pub notify_email_to: Vec<String>,
+    /// Delete pastes older than this many days.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub retain_days: Option<u64>,
+    /// Keep at most this many pastes, deleting the oldest first.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub retain_count: Option<u64>,
+    /// Keep the pastes' total size under this many bytes, deleting the oldest first.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub retain_max_bytes: Option<u64>,
+    /// How long a request waits for another write to release the git lock
+    /// before giving up with `503`.
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    pub lock_timeout: Duration,
+    /// Refuse to start, and refuse new pastes with `507`, while fewer bytes
+    /// than this are free on `--dir`; `0` turns the check off.
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub min_free_bytes: u64,
+    /// Render at most this many bytes of a paste in the HTML view.
+    #[arg(long, default_value_t = 1_048_576)]
+    pub render_max_input_bytes: usize,
+    /// Give up on rendering a view after this long and show plain text.
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub render_timeout: Duration,
+    /// Show plain text instead when rendered HTML exceeds this many bytes.
+    #[arg(long, default_value_t = 8 * 1_048_576)]
+    pub render_max_output_bytes: usize,
+    /// Blocking threads that render views; defaults to one per CPU.
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub render_workers: Option<usize>,
+    /// Views allowed to wait for a render worker before new ones get `503`.
+    #[arg(long, default_value_t = 64)]
+    pub render_queue: usize,
+    /// Keep up to this many bytes of rendered view HTML; `0` turns it off.
+    #[arg(long, default_value_t = 32 * 1_048_576)]
+    pub render_cache_bytes: usize,
+    /// Program that turns a paste's print page into a PDF for
+    /// `/p/{id}/pdf`: HTML on stdin, the PDF on stdout, e.g.
+    /// `weasyprint - -`. Split on whitespace; off by default.
+    #[arg(long, value_name = "CMD")]
+    pub pdf_command: Option<String>,
+    /// Where `POST /api/v1/admin/archive` writes tarballs; defaults to `<dir>/archive`.
+    #[arg(long)]
+    pub archive_dir: Option<PathBuf>,
+    /// S3-compatible endpoint to upload backups to, e.g. `http://minio.lan:9000`.
+    #[arg(long)]
+    pub backup_s3_endpoint: Option<String>,
+    #[arg(long)]
+    pub backup_s3_bucket: Option<String>,
+    /// Key prefix for backup objects.
+    #[arg(long, default_value = "lanpaste/")]
+    pub backup_s3_prefix: String,
+    #[arg(long, default_value = "us-east-1")]
+    pub backup_s3_region: String,
+    #[arg(long)]
+    pub backup_s3_access_key: Option<String>,
+    /// File holding the secret key for `--backup-s3-access-key`.
+    #[arg(long)]
+    pub backup_s3_secret_key_file: Option<PathBuf>,
+    #[arg(long, value_enum, default_value = "bundle")]
+    pub backup_s3_format: BackupFormat,
+    /// Time between backups, e.g. `24h`.
+    #[arg(long, default_value = "24h", value_parser = parse_duration)]
+    pub backup_s3_interval: Duration,
+    /// Serve the pastes tree read-only over WebDAV at `/dav/`.
+    #[arg(long)]
+    pub webdav: bool,
+    /// Serve the repo read-only over git smart HTTP at `/git`, for
+    /// `git clone`.
+    #[arg(long)]
+    pub git_http: bool,
+    /// Also serve an isolated repo under `/ns/NAME/`, as `NAME=DIR`;
+    /// repeatable. Each namespace has its own repo, lock, `api-keys.json`
+    /// and `config.json` in `DIR`.
+    #[arg(long, value_name = "NAME=DIR", value_parser = parse_namespace)]
+    pub namespace: Vec<Namespace>,
+    /// Sub-path a reverse proxy mounts the server under, such as `/paste`,
+    /// or the external URL it is reached at; every generated link starts
+    /// with its path. Inside a namespace the mount is appended, as in
+    /// `/paste/ns/team-a`. Empty at the root.
+    #[arg(
+        long = "base-url",
+        value_name = "PATH|URL",
+        default_value = "",
+        hide_default_value = true,
+        value_parser = parse_base_url
+    )]
+    pub base_path: String,
+    /// Scheme, host and port clients reach the server at, such as
+    /// `http://paste.lan:8090`. Makes the URLs in a create response
+    /// absolute; a sub-path belongs in `--base-url`.
+    #[arg(long, value_name = "URL", value_parser = parse_public_url)]
+    pub public_url: Option<String>,
+    /// POST a signed JSON payload to this URL whenever a listed paste is
+    /// created or deleted; repeatable. Needs `--webhook-secret-file`.
+    #[arg(long = "webhook-url", value_name = "URL")]
+    pub webhook_url: Vec<String>,
+    /// File holding the secret that signs webhook payloads (HMAC-SHA256).
+    #[arg(long)]
+    pub webhook_secret_file: Option<PathBuf>,
+    /// Post a message for each new listed paste to this Slack-compatible
+    /// incoming webhook (Slack, Mattermost, Rocket.Chat); repeatable.
+    #[arg(long, value_name = "URL")]
+    pub chat_slack_url: Vec<String>,
+    /// Post new pastes to this Discord webhook; repeatable.
+    #[arg(long, value_name = "URL")]
+    pub chat_discord_url: Vec<String>,
+    /// Matrix homeserver to post new pastes through, e.g. `https://matrix.lan`.
+    #[arg(long, value_name = "URL")]
+    pub chat_matrix_homeserver: Option<String>,
+    /// Matrix room id (`!id:server`) to post to; repeatable.
+    #[arg(long, value_name = "ROOM")]
+    pub chat_matrix_room: Vec<String>,
+    /// File holding the Matrix access token of the posting account.
+    #[arg(long)]
+    pub chat_matrix_token_file: Option<PathBuf>,
+    /// Only announce pastes with this tag; repeatable (default: every paste).
+    #[arg(long, value_name = "TAG")]
+    pub chat_tag: Vec<String>,
+    /// External address used in chat links, e.g. `http://paste.lan:8090`.
+    #[arg(long, value_name = "URL")]
+    pub chat_link_base: Option<String>,
+    /// Advertise the server on the LAN as a `_lanpaste._tcp` mDNS service.
+    #[arg(long)]
+    pub mdns: bool,
+    /// mDNS instance name (default: `lanpaste on <hostname>`).
+    #[arg(long, value_name = "NAME")]
+    pub mdns_name: Option<String>,
+    /// Per-client-IP limit for requests without an API key (the shared
+    /// `--token` and anonymous reads), e.g. `10/s` or `300/m`. Off by default.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub ip_rate: Option<f64>,
+    /// Requests a client may make at once before `--ip-rate` applies.
+    #[arg(long, value_name = "N", default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ip_burst: u32,
+    /// Total write rate (creates, uploads, appends, edits) across all
+    /// clients, e.g. `2/s`; excess writes get `429`. Off by default.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub write_rate: Option<f64>,
+    /// Writes allowed back to back before `--write-rate` applies.
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub write_burst: u32,
+    /// Failed token or API key attempts from one client IP before it is
+    /// locked out; `0` turns the lockout off.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub auth_fail_limit: u32,
+    /// First lockout after `--auth-fail-limit` failures; each lockout after
+    /// it doubles, up to a day.
+    #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration)]
+    pub auth_ban: Duration,
+    /// Also append authentication failures and lockouts to this file, one
+    /// line each, for fail2ban.
+    #[arg(long, value_name = "PATH")]
+    pub auth_log: Option<PathBuf>,
+    /// Requests handled at once; more get `503` until one finishes. Off by default.
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_in_flight: Option<usize>,
+    /// Once listening, print the startup summary (also written to
+    /// `run/instance.json`) to stdout as one line of JSON.
+    #[arg(long)]
+    pub startup_json: bool,
+    /// Once listening, write the process id to this file; a clean shutdown
+    /// removes it.
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+    /// JSON file of settings that `SIGHUP` or `POST /api/v1/admin/reload`
+    /// re-reads: `max_bytes`, `ip_rate`, `webhook_url`, `retain_*` and more.
+    #[arg(long, value_name = "PATH")]
+    pub config_file: Option<PathBuf>,
+    /// Log filter in `RUST_LOG` syntax, e.g. `lanpaste=debug` (default: `RUST_LOG`, else `lanpaste=info`).
+    #[arg(long, value_name = "FILTER")]
+    pub log_level: Option<String>,
+}
+
+impl ServeCmd {
+    /// Serve defaults for offline commands working on `dir`, so the shared
+    /// repo code sees the same settings and git identity a server would.
+    pub fn offline(
+        dir: &std::path::Path,
+        git_author_name: &str,
+        git_author_email: &str,
+    ) -> Result<Self, clap::Error> {
+        Self::try_parse_from([
+            "serve".into(),
+            "--dir".into(),
+            dir.as_os_str().to_owned(),
+            "--git-author-name".into(),
+            git_author_name.into(),
+            "--git-author-email".into(),
+            git_author_email.into(),
+        ])
+    }
+
+    /// `--allow-cidr` entries plus the `--allow-private` ranges, without duplicates.
+    pub fn allowlist(&self) -> Vec<IpNet> {
+        let mut nets = self.allow_cidr.clone();
+        if self.allow_private {
+            for net in PRIVATE_NETS {
+                let net: IpNet = net.parse().expect("valid private net");
+                if !nets.contains(&net) {
+                    nets.push(net);
+                }
+            }
+        }
+        nets
+    }
+
+    /// Whether writers authenticate with a shared token (`--token` or
+    /// `--token-file`).
+    pub fn has_token(&self) -> bool {
+        !self.token.is_empty() || self.token_file.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct SoakCmd {
+    /// Base URL of the server under test, e.g. http://127.0.0.1:8090
+    #[arg(long)]
+    pub target: String,
+    /// Operations per second, as `N/s`, `N/m`, `N/h` or a bare number.
+    #[arg(long, default_value = "20/s", value_parser = parse_rate)]
+    pub rate: f64,
+    /// How long to run, e.g. `30s`, `5m`, `1h`.
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    pub duration: Duration,
+    /// Weighted paste sizes, e.g. `1k=70,64k=25,512k=5`.
+    #[arg(long, default_value = "1k=70,64k=25,512k=5", value_parser = crate::soak::parse_size_dist)]
+    pub size_dist: SizeDist,
+    /// Weighted operation mix, e.g. `create=1,read=3,recent=1`.
+    #[arg(long, default_value = "create=1,read=3,recent=1", value_parser = crate::soak::parse_mix)]
+    pub mix: OpMix,
+    #[arg(long, default_value_t = 16)]
+    pub concurrency: usize,
+    #[arg(long, default_value = "soak")]
+    pub tag: String,
+    #[arg(long)]
+    pub token: Option<String>,
+    #[arg(long)]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct EnrollCmd {
+    /// Base URL of the server, e.g. http://paste.lan:8090
+    #[arg(long)]
+    pub server: String,
+    /// Device name recorded with the key; defaults to the hostname.
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Scope to request (repeatable); defaults to paste create/read and recent.
+    #[arg(long)]
+    pub scope: Vec<String>,
+    /// Where to write the issued key; defaults to ~/.config/lanpaste/client.json.
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+    /// How long to wait for approval.
+    #[arg(long, default_value = "10m", value_parser = parse_duration)]
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct PasteCmd {
+    /// File to send; stdin when absent or `-`.
+    pub file: Option<PathBuf>,
+    /// Base URL of the server, e.g. http://paste.lan:8090; defaults to the
+    /// one saved by `lanpaste enroll`.
+    #[arg(long)]
+    pub to: Option<String>,
+    /// File name for the paste; defaults to the name of `FILE`.
+    #[arg(long)]
+    pub name: Option<String>,
+    #[arg(long)]
+    pub tag: Option<String>,
+    /// Commit message.
+    #[arg(long)]
+    pub msg: Option<String>,
+    /// `listed`, `unlisted` or `private`.
+    #[arg(long, value_parser = parse_visibility)]
+    pub visibility: Option<Visibility>,
+    /// Language to highlight the paste as, e.g. `rust` or `py`.
+    #[arg(long)]
+    pub lang: Option<String>,
+    /// Shared write token (`--token` on the server).
+    #[arg(long)]
+    pub token: Option<String>,
+    /// API key; defaults to the one saved by `lanpaste enroll`.
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// Client config written by `lanpaste enroll`; defaults to ~/.config/lanpaste/client.json.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Print the raw URL instead of the view URL.
+    #[arg(long)]
+    pub raw: bool,
+    /// Reuse an existing paste with the same content instead of adding a
+    /// copy, e.g. when a CI job is retried.
+    #[arg(long)]
+    pub dedupe: bool,
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(group = clap::ArgGroup::new("target").required(true).args(["dir", "server"]))]
+pub struct GcCmd {
+    /// Data directory of a stopped server.
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+    /// Base URL of a running server; gc runs there through the admin API.
+    #[arg(long)]
+    pub server: Option<String>,
+    /// Admin API key for `--server`; defaults to the one saved by `lanpaste enroll`.
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// Client config written by `lanpaste enroll`; defaults to ~/.config/lanpaste/client.json.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    #[arg(long, default_value = "LAN Paste")]
+    pub git_author_name: String,
+    #[arg(long, default_value = "paste@lan")]
+    pub git_author_email: String,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct RotateTokenCmd {
+    /// Base URL of the server, e.g. http://paste.lan:8090
+    #[arg(long)]
+    pub server: String,
+    /// The token in effect, which authorizes the rotation on a server
+    /// without a key file.
+    #[arg(long)]
+    pub token: Option<String>,
+    /// Admin API key, for a server with a key file; defaults to the one
+    /// saved by `lanpaste enroll`.
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// Client config written by `lanpaste enroll`; defaults to ~/.config/lanpaste/client.json.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// The token to replace, when the server has several; defaults to
+    /// `--token`.
+    #[arg(long)]
+    pub replace: Option<String>,
+    /// The token to switch to; a random one when absent.
+    #[arg(long)]
+    pub new_token: Option<String>,
+    /// How long the old token keeps working; the server's default is 1h.
+    #[arg(long, value_parser = parse_duration)]
+    pub grace: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct MigrateCmd {
+    /// Data directory (the server's `--dir`) to upgrade.
+    #[arg(long)]
+    pub from: PathBuf,
+    /// Where to copy the directory first; defaults to `<from>.pre-migrate-<unix time>`.
+    #[arg(long)]
+    pub backup: Option<PathBuf>,
+    #[arg(long, default_value = "LAN Paste")]
+    pub git_author_name: String,
+    #[arg(long, default_value = "paste@lan")]
+    pub git_author_email: String,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ArchiveCmd {
+    /// Data directory (the server's `--dir`); the server must be stopped.
+    #[arg(long)]
+    pub dir: PathBuf,
+    /// Archive pastes created more than this many days ago.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub older_than_days: u64,
+    /// Directory for the tarball; defaults to `<dir>/archive`.
+    #[arg(long)]
+    pub to: Option<PathBuf>,
     #[arg(long, default_value = "LAN Paste")]
     pub git_author_name: String,
     #[arg(long, default_value = "paste@lan")]
     pub git_author_email: String,
 }
 
+#[derive(Debug, Clone, Parser)]
+pub struct InitCmd {
+    /// Data directory to create; files already in it are kept.
+    #[arg(long)]
+    pub dir: PathBuf,
+    /// `--bind` for the unit's `serve` command.
+    #[arg(long, default_value = "0.0.0.0:8090")]
+    pub bind: SocketAddr,
+    /// Account the unit runs the server as.
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Where to write the systemd unit; defaults to `<dir>/lanpaste.service`.
+    #[arg(long)]
+    pub unit_path: Option<PathBuf>,
+    /// Binary the unit starts; defaults to this one.
+    #[arg(long)]
+    pub exe: Option<PathBuf>,
+    #[arg(long, default_value = "LAN Paste")]
+    pub git_author_name: String,
+    #[arg(long, default_value = "paste@lan")]
+    pub git_author_email: String,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct KeygenCmd {
+    /// Random bytes in the secret.
+    #[arg(long, default_value_t = 24, value_parser = clap::value_parser!(u16).range(16..=128))]
+    pub bytes: u16,
+    /// Print only hex, without the `lp_` key prefix; for `--token` or a
+    /// webhook secret.
+    #[arg(long, conflicts_with = "add_to")]
+    pub raw: bool,
+    /// API key file to add the key to; created if missing.
+    #[arg(long, requires = "scope")]
+    pub add_to: Option<PathBuf>,
+    /// Name recorded with the key.
+    #[arg(long, requires = "add_to")]
+    pub name: Option<String>,
+    /// Scope granted to the key (repeatable); `*` grants all.
+    #[arg(long, requires = "add_to")]
+    pub scope: Vec<String>,
+    /// Per-key limit, as `max_requests_per_minute`.
+    #[arg(long, requires = "add_to", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_requests_per_minute: Option<u32>,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
 pub enum PushMode {
     Off,
@@ -45,6 +620,13 @@ pub enum PushMode {
     Strict,
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum VerifyMode {
+    Off,
+    Always,
+    Sampled,
+}
+
 impl std::fmt::Display for PushMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -55,6 +637,311 @@ impl std::fmt::Display for PushMode {
     }
 }
 
+/// Networks added to the allowlist by `--allow-private`.
+pub const PRIVATE_NETS: &[&str] = &[
+    "127.0.0.0/8",
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// Parses a CIDR or bare address. Zone ids (`fe80::/10%eth0`, `fe80::1%2`)
+/// are accepted and dropped, since clients are matched on address alone.
+pub fn parse_cidr(raw: &str) -> Result<IpNet, String> {
+    let raw = raw.trim();
+    let unscoped = match raw.find('%') {
+        Some(start) => {
+            let end = raw[start..].find('/').map_or(raw.len(), |i| start + i);
+            format!("{}{}", &raw[..start], &raw[end..])
+        }
+        None => raw.to_string(),
+    };
+    if let Ok(net) = unscoped.parse::<IpNet>() {
+        return Ok(net);
+    }
+    unscoped
+        .parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|_| format!("invalid CIDR '{raw}'"))
+}
+
+/// `*`, or an origin such as `https://wiki.lan:8443` (no path).
+pub fn parse_origin(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    if raw == "*" {
+        return Ok(raw.to_string());
+    }
+    let url = reqwest::Url::parse(raw).map_err(|_| format!("invalid origin '{raw}'"))?;
+    let bare = url.path() == "/" && url.query().is_none() && url.fragment().is_none();
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() || !bare {
+        return Err(format!(
+            "invalid origin '{raw}'; expected scheme://host[:port]"
+        ));
+    }
+    Ok(url.origin().ascii_serialization())
+}
+
+/// `/paste`, or a URL such as `https://nas.lan/paste`, as the path links
+/// start with: a leading `/` and no trailing one, or empty for the root.
+pub fn parse_base_url(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    let path = if raw.contains("://") {
+        let url = reqwest::Url::parse(raw).map_err(|_| format!("invalid base URL '{raw}'"))?;
+        if !matches!(url.scheme(), "http" | "https")
+            || url.host_str().is_none()
+            || url.query().is_some()
+            || url.fragment().is_some()
+        {
+            return Err(format!(
+                "invalid base URL '{raw}'; expected scheme://host[:port][/path]"
+            ));
+        }
+        url.path().to_string()
+    } else {
+        raw.to_string()
+    };
+    let trimmed = path.trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    let valid = trimmed.split('/').all(|segment| {
+        !matches!(segment, "" | "." | "..")
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~%+,;=:@".contains(c))
+    });
+    if !valid {
+        return Err(format!("invalid base path '{raw}'; expected /path"));
+    }
+    Ok(format!("/{trimmed}"))
+}
+
+/// An origin such as `http://paste.lan:8090`, which `--public-url` prepends
+/// to links.
+pub fn parse_public_url(raw: &str) -> Result<String, String> {
+    match parse_origin(raw) {
+        Ok(origin) if origin != "*" => Ok(origin),
+        _ => Err(format!(
+            "invalid public URL '{}'; expected scheme://host[:port], with any sub-path in --base-url",
+            raw.trim()
+        )),
+    }
+}
+
+pub fn parse_method(raw: &str) -> Result<String, String> {
+    http::Method::from_bytes(raw.trim().to_ascii_uppercase().as_bytes())
+        .map(|m| m.to_string())
+        .map_err(|_| format!("invalid method '{raw}'"))
+}
+
+/// One `--allowed-hosts` entry. Without a port it matches any port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedHost {
+    /// Lowercase, with IPv6 addresses in brackets.
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl AllowedHost {
+    /// Whether `authority`, a `Host` header such as `Paste.lan:8090`,
+    /// names this host.
+    pub fn matches(&self, authority: &str) -> bool {
+        let Ok(authority) = authority.parse::<http::uri::Authority>() else {
+            return false;
+        };
+        let host = authority.host().trim_end_matches('.');
+        !authority.as_str().contains('@')
+            && host.eq_ignore_ascii_case(&self.host)
+            && self
+                .port
+                .is_none_or(|port| authority.port_u16() == Some(port))
+    }
+}
+
+/// `HOST` or `HOST:PORT`, with an IPv6 address in brackets.
+pub fn parse_allowed_host(raw: &str) -> Result<AllowedHost, String> {
+    let raw = raw.trim();
+    let invalid = || format!("invalid host '{raw}'; expected HOST[:PORT]");
+    let authority = raw.parse::<http::uri::Authority>().map_err(|_| invalid())?;
+    let host = authority.host();
+    let port = authority.port_u16();
+    // Rules out user info and ports that are not numbers.
+    let rebuilt = match port {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    if host.is_empty() || rebuilt != authority.as_str() {
+        return Err(invalid());
+    }
+    Ok(AllowedHost {
+        host: host.trim_end_matches('.').to_ascii_lowercase(),
+        port,
+    })
+}
+
+/// One `--namespace NAME=DIR`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Namespace {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+/// `NAME=DIR`, where `NAME` is 1-32 lowercase letters, digits or `-`.
+pub fn parse_namespace(raw: &str) -> Result<Namespace, String> {
+    let (name, dir) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("invalid namespace '{raw}'; expected NAME=DIR"))?;
+    let name = name.trim();
+    let valid = (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(format!(
+            "invalid namespace name '{name}'; use 1-32 of a-z, 0-9 and -"
+        ));
+    }
+    if dir.trim().is_empty() {
+        return Err(format!("namespace '{name}' needs a directory"));
+    }
+    Ok(Namespace {
+        name: name.to_string(),
+        dir: PathBuf::from(dir.trim()),
+    })
+}
+
+pub fn parse_visibility(raw: &str) -> Result<Visibility, String> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "listed" => Ok(Visibility::Listed),
+        "unlisted" => Ok(Visibility::Unlisted),
+        "private" => Ok(Visibility::Private),
+        _ => Err(format!(
+            "invalid visibility '{raw}'; use listed, unlisted or private"
+        )),
+    }
+}
+
+pub fn parse_header_name(raw: &str) -> Result<String, String> {
+    http::HeaderName::from_bytes(raw.trim().as_bytes())
+        .map(|h| h.to_string())
+        .map_err(|_| format!("invalid header name '{raw}'"))
+}
+
+pub fn parse_csp(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    if raw.is_empty() || http::HeaderValue::from_str(raw).is_err() {
+        return Err(format!("invalid Content-Security-Policy '{raw}'"));
+    }
+    Ok(raw.to_string())
+}
+
+/// `'none'`, `'self'`, a scheme such as `https:`, or a host source such as
+/// `https://*.wiki.lan`.
+pub fn parse_frame_ancestor(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    let quoted = raw.starts_with('\'');
+    let valid = if quoted {
+        matches!(raw, "'none'" | "'self'")
+    } else {
+        !raw.is_empty()
+            && raw
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~:/*[]%".contains(c))
+    };
+    if !valid {
+        return Err(format!("invalid frame-ancestors source '{raw}'"));
+    }
+    Ok(raw.to_string())
+}
+
+pub fn parse_referrer_policy(raw: &str) -> Result<String, String> {
+    const POLICIES: [&str; 8] = [
+        "no-referrer",
+        "no-referrer-when-downgrade",
+        "origin",
+        "origin-when-cross-origin",
+        "same-origin",
+        "strict-origin",
+        "strict-origin-when-cross-origin",
+        "unsafe-url",
+    ];
+    let policy = raw.trim().to_ascii_lowercase();
+    if POLICIES.contains(&policy.as_str()) {
+        Ok(policy)
+    } else {
+        Err(format!(
+            "invalid referrer policy '{raw}'; expected one of {}",
+            POLICIES.join(", ")
+        ))
+    }
+}
+
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let split = raw
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(raw.len());
+    let (num, unit) = raw.split_at(split);
+    let n: u64 = num
+        .parse()
+        .map_err(|_| format!("invalid duration '{raw}'"))?;
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "ms" => return Ok(Duration::from_millis(n)),
+        _ => return Err(format!("invalid duration unit in '{raw}'")),
+    };
+    let secs = n
+        .checked_mul(scale)
+        .ok_or_else(|| "duration too large".to_string())?;
+    Ok(Duration::from_secs(secs))
+}
+
+pub fn parse_time_of_day(raw: &str) -> Result<time::Time, String> {
+    let (h, m) = raw
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| format!("invalid time '{raw}' (expected HH:MM)"))?;
+    let parse = |v: &str| v.parse::<u8>().map_err(|_| format!("invalid time '{raw}'"));
+    time::Time::from_hms(parse(h)?, parse(m)?, 0).map_err(|e| format!("invalid time '{raw}': {e}"))
+}
+
+const MIN_RATE: f64 = 1.0 / 3600.0;
+const MAX_RATE: f64 = 1_000_000.0;
+
+pub fn parse_rate(raw: &str) -> Result<f64, String> {
+    let (num, per) = raw.split_once('/').unwrap_or((raw, "s"));
+    let n: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid rate '{raw}'"))?;
+    let per_secs = match per.trim() {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("invalid rate unit in '{raw}'")),
+    };
+    if n.is_nan() || n <= 0.0 {
+        return Err("rate must be positive".to_string());
+    }
+    // Rates become intervals of `1 / rate` seconds, which must be neither
+    // zero nor too long for a `Duration`.
+    let rate = n / per_secs;
+    if !rate.is_finite() || rate > MAX_RATE {
+        return Err(format!("rate must be at most {MAX_RATE}/s"));
+    }
+    if rate < MIN_RATE {
+        return Err("rate must be at least 1/h".to_string());
+    }
+    Ok(rate)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,13 +949,238 @@ mod tests {
     #[test]
     fn parse_defaults() {
         let cli = Cli::try_parse_from(["lanpaste", "serve", "--dir", "/tmp/x"]).expect("parse");
-        let Commands::Serve(cmd) = cli.command;
-        assert_eq!(cmd.bind, "0.0.0.0:8090".parse().expect("bind"));
+        let Commands::Serve(cmd) = cli.command else {
+            panic!("expected serve");
+        };
+        assert_eq!(cmd.bind, ["0.0.0.0:8090".parse().expect("bind")]);
         assert_eq!(cmd.max_bytes, 1_048_576);
+        assert_eq!(cmd.max_total_bytes, 64 * 1024 * 1024);
+        assert!(!cmd.allow_private);
+        assert!(cmd.trusted_proxies.is_empty());
+        assert!(cmd.allowed_hosts.is_empty());
+        assert!(cmd.cors_origin.is_empty());
+        assert_eq!(cmd.cors_method, ["GET", "POST", "PATCH", "DELETE"]);
+        assert!(cmd.cors_header.contains(&"x-api-key".to_string()));
+        assert!(cmd.csp.is_none());
+        assert_eq!(cmd.frame_ancestors, ["'none'"]);
+        assert_eq!(cmd.referrer_policy, "same-origin");
+        assert!(cmd.host_rules_file.is_none());
+        assert!(cmd.encrypt_key_file.is_none());
+        assert!(!cmd.compress);
+        assert!(!cmd.self_test);
+        assert!(cmd.self_test_interval.is_none());
+        assert!(cmd.digest_at.is_none());
+        assert!(cmd.notify_webhook.is_none() && cmd.notify_smtp.is_none());
+        assert_eq!(cmd.notify_email_from, "lanpaste@localhost");
+        assert!(cmd.retain_days.is_none() && cmd.retain_count.is_none());
+        assert!(cmd.retain_max_bytes.is_none());
+        assert_eq!(cmd.min_free_bytes, 64 * 1024 * 1024);
+        assert_eq!(cmd.lock_timeout, Duration::from_secs(5));
+        assert!(cmd.archive_dir.is_none());
+        assert_eq!(cmd.render_max_input_bytes, 1_048_576);
+        assert_eq!(cmd.render_timeout, Duration::from_secs(2));
+        assert!(cmd.render_workers.is_none());
+        assert_eq!(cmd.render_queue, 64);
+        assert_eq!(cmd.render_cache_bytes, 32 * 1_048_576);
+        assert!(cmd.backup_s3_endpoint.is_none());
+        assert_eq!(cmd.backup_s3_format, BackupFormat::Bundle);
+        assert_eq!(cmd.backup_s3_interval, Duration::from_secs(86_400));
+        assert!(!cmd.webdav);
+        assert!(!cmd.git_http);
+        assert!(!cmd.dedupe);
+        assert!(cmd.zstd_above.is_none());
+        assert!(cmd.pdf_command.is_none());
+        assert!(!cmd.protect_ui);
+        assert!(!cmd.require_token_for_read);
+        assert!(cmd.token.is_empty());
+        assert!(cmd.token_file.is_none());
+        assert!(cmd.webhook_url.is_empty() && cmd.webhook_secret_file.is_none());
+        assert!(cmd.chat_slack_url.is_empty() && cmd.chat_tag.is_empty());
+        assert!(!cmd.mdns && cmd.mdns_name.is_none());
+        assert!(cmd.config_file.is_none() && cmd.log_level.is_none());
+        assert!(cmd.ip_rate.is_none());
+        assert_eq!(cmd.ip_burst, 20);
+        assert_eq!(cmd.auth_fail_limit, 10);
+        assert_eq!(cmd.auth_ban, Duration::from_secs(60));
+        assert!(cmd.auth_log.is_none());
+        assert!(cmd.write_rate.is_none() && cmd.max_in_flight.is_none());
+        assert_eq!(cmd.write_burst, 10);
+        assert!(!cmd.startup_json);
+        assert!(cmd.pid_file.is_none());
+        assert!(cmd.grpc_bind.is_none());
+        assert!(cmd.namespace.is_empty() && cmd.base_path.is_empty());
+        assert!(cmd.public_url.is_none());
+        assert!(
+            Cli::try_parse_from(["lanpaste", "serve", "--dir", "/x", "--retain-count", "0"])
+                .is_err()
+        );
         assert_eq!(cmd.push, PushMode::Off);
         assert_eq!(cmd.remote, "origin");
         assert!(cmd.api_keys_file.is_none());
         assert_eq!(cmd.git_author_name, "LAN Paste");
         assert_eq!(cmd.git_author_email, "paste@lan");
+        assert_eq!(cmd.verify_reads, VerifyMode::Off);
+    }
+
+    #[test]
+    fn parse_namespace_splits_name_and_dir() {
+        assert_eq!(
+            parse_namespace("team-a=/data/a").expect("namespace"),
+            Namespace {
+                name: "team-a".to_string(),
+                dir: PathBuf::from("/data/a"),
+            }
+        );
+        assert!(parse_namespace("/data/a").is_err());
+        assert!(parse_namespace("Team=/data/a").is_err());
+        assert!(parse_namespace("a/b=/data/a").is_err());
+        assert!(parse_namespace("team-a=").is_err());
+    }
+
+    #[test]
+    fn parse_rate_and_duration() {
+        assert_eq!(parse_rate("20/s").expect("rate"), 20.0);
+        assert_eq!(parse_rate("120/m").expect("rate"), 2.0);
+        assert!(parse_rate("0").is_err());
+        assert_eq!(parse_rate("1/h").expect("rate"), 1.0 / 3600.0);
+        for raw in ["inf", "1e300", "0.5/h", "1e-300"] {
+            assert!(parse_rate(raw).is_err(), "{raw}");
+        }
+        assert_eq!(parse_duration("90").expect("dur"), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").expect("dur"), Duration::from_secs(300));
+        assert!(parse_duration("5x").is_err());
+        assert_eq!(
+            parse_duration("999999999999999999d"),
+            Err("duration too large".to_string())
+        );
+        assert_eq!(
+            parse_time_of_day("07:30").expect("time"),
+            time::Time::from_hms(7, 30, 0).expect("time")
+        );
+        assert!(parse_time_of_day("24:00").is_err());
+        assert!(parse_time_of_day("7").is_err());
+    }
+
+    #[test]
+    fn parse_origin_keeps_scheme_host_and_port() {
+        assert_eq!(
+            parse_origin("http://wiki.lan:3000").expect("origin"),
+            "http://wiki.lan:3000"
+        );
+        assert_eq!(
+            parse_origin("https://Wiki.LAN/").expect("origin"),
+            "https://wiki.lan"
+        );
+        assert_eq!(parse_origin("*").expect("any"), "*");
+        assert!(parse_origin("http://wiki.lan/app").is_err());
+        assert!(parse_origin("wiki.lan").is_err());
+        assert_eq!(parse_method("patch").expect("method"), "PATCH");
+        assert!(parse_header_name("bad header").is_err());
+    }
+
+    #[test]
+    fn allowed_hosts_match_by_name_and_optional_port() {
+        let cmd = ServeCmd::try_parse_from([
+            "serve",
+            "--dir",
+            "d",
+            "--allowed-hosts",
+            "Paste.LAN,10.0.0.5:8090",
+            "--allowed-hosts",
+            "[fd00::1]",
+        ])
+        .expect("parse");
+        let [name, ip, v6] = cmd.allowed_hosts.as_slice() else {
+            panic!("{:?}", cmd.allowed_hosts);
+        };
+        assert!(name.matches("paste.lan") && name.matches("PASTE.lan.:8443"));
+        assert!(ip.matches("10.0.0.5:8090"));
+        assert!(!ip.matches("10.0.0.5") && !ip.matches("10.0.0.5:80"));
+        assert!(v6.matches("[fd00::1]:8090"));
+        assert!(!name.matches("paste.lan.evil.example") && !name.matches("evil@paste.lan"));
+        for bad in ["", "http://paste.lan", "user@paste.lan", "paste.lan:x"] {
+            assert!(parse_allowed_host(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn base_url_is_reduced_to_its_path() {
+        for (raw, path) in [
+            ("", ""),
+            ("/", ""),
+            ("/paste", "/paste"),
+            ("paste/", "/paste"),
+            ("/tools/paste/", "/tools/paste"),
+            ("https://nas.lan/paste", "/paste"),
+            ("http://nas.lan:8080", ""),
+        ] {
+            assert_eq!(parse_base_url(raw).expect(raw), path, "{raw}");
+        }
+        assert_eq!(
+            parse_public_url("http://Paste.LAN:8090/").expect("public url"),
+            "http://paste.lan:8090"
+        );
+        assert!(parse_public_url("http://paste.lan/paste").is_err());
+        assert!(parse_public_url("*").is_err());
+        for bad in [
+            "/a//b",
+            "/../x",
+            "/a b",
+            "/a\"b",
+            "ftp://nas.lan/x",
+            "https://nas.lan/x?y",
+        ] {
+            assert!(parse_base_url(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn security_header_flags_are_validated() {
+        assert_eq!(
+            parse_frame_ancestor("https://*.wiki.lan:8443").expect("host"),
+            "https://*.wiki.lan:8443"
+        );
+        assert_eq!(parse_frame_ancestor("'self'").expect("self"), "'self'");
+        assert!(parse_frame_ancestor("'unsafe-inline'").is_err());
+        assert!(parse_frame_ancestor("https://a.lan; script-src *").is_err());
+        assert_eq!(
+            parse_referrer_policy("No-Referrer").expect("policy"),
+            "no-referrer"
+        );
+        assert!(parse_referrer_policy("never").is_err());
+        assert!(parse_csp("default-src 'self'\n").is_ok());
+        assert!(parse_csp("default-src\u{7f}").is_err());
+        assert!(parse_csp(" ").is_err());
+    }
+
+    #[test]
+    fn parse_cidr_accepts_zones_and_bare_addresses() {
+        let net = |s: &str| parse_cidr(s).expect(s).to_string();
+        assert_eq!(net("192.168.1.0/24"), "192.168.1.0/24");
+        assert_eq!(net("10.0.0.7"), "10.0.0.7/32");
+        assert_eq!(net("fe80::/10%eth0"), "fe80::/10");
+        assert_eq!(net("fe80::1%2/64"), "fe80::1/64");
+        assert_eq!(net("fe80::1%eth0"), "fe80::1/128");
+        assert!(parse_cidr("not-a-net").is_err());
+    }
+
+    #[test]
+    fn allow_private_expands_allowlist() {
+        let cli = Cli::try_parse_from([
+            "lanpaste",
+            "serve",
+            "--dir",
+            "/tmp/x",
+            "--allow-cidr",
+            "10.0.0.0/8",
+            "--allow-private",
+        ])
+        .expect("parse");
+        let Commands::Serve(cmd) = cli.command else {
+            panic!("expected serve");
+        };
+        let nets = cmd.allowlist();
+        assert_eq!(nets.len(), PRIVATE_NETS.len());
+        assert!(nets.contains(&"fc00::/7".parse().expect("net")));
     }
 }
@@ -0,0 +1,58 @@
+//! Cross-origin access for browser apps on other LAN hosts
+//! (`--cors-origin`). Credentials are never allowed: callers authenticate
+//! with `X-API-Key` or `X-Paste-Token`, not with the admin session cookie.
+
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::ServeCmd;
+
+/// How long browsers may cache a preflight answer.
+const MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Response headers scripts may read besides the CORS-safelisted ones.
+const EXPOSED: [HeaderName; 6] = [
+    header::ETAG,
+    header::LAST_MODIFIED,
+    header::LOCATION,
+    header::RETRY_AFTER,
+    header::CONTENT_DISPOSITION,
+    HeaderName::from_static("x-paste-id"),
+];
+
+/// The layer for `cfg`, or `None` when no origin is allowed. The values were
+/// checked when the flags were parsed.
+pub fn layer(cfg: &ServeCmd) -> Option<CorsLayer> {
+    if cfg.cors_origin.is_empty() {
+        return None;
+    }
+    let origins = if cfg.cors_origin.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            cfg.cors_origin
+                .iter()
+                .filter_map(|o| HeaderValue::from_str(o).ok()),
+        )
+    };
+    let methods: Vec<Method> = cfg
+        .cors_method
+        .iter()
+        .filter_map(|m| Method::from_bytes(m.as_bytes()).ok())
+        .collect();
+    let headers: Vec<HeaderName> = cfg
+        .cors_header
+        .iter()
+        .filter_map(|h| HeaderName::from_bytes(h.as_bytes()).ok())
+        .collect();
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(EXPOSED)
+            .max_age(MAX_AGE),
+    )
+}
@@ -0,0 +1,137 @@
+use std::{fs, path::Path, sync::Arc};
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+
+use crate::errors::{AppError, AppResult};
+
+/// Sealed files start with this tag so they are recognizable in a clone.
+const MAGIC: &[u8; 4] = b"LPE1";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Bytes a sealed file carries on top of its plaintext.
+pub const OVERHEAD: u64 = (MAGIC.len() + NONCE_LEN + TAG_LEN) as u64;
+
+/// AES-256-GCM key used to seal paste bodies at rest. The paste id is bound
+/// as associated data, so a sealed file cannot be swapped onto another paste.
+#[derive(Clone)]
+pub struct PasteCipher {
+    aead: Arc<Aes256Gcm>,
+}
+
+impl std::fmt::Debug for PasteCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PasteCipher(..)")
+    }
+}
+
+impl PasteCipher {
+    /// Loads a 32-byte key stored either raw or as 64 hex characters.
+    pub fn from_file(path: Option<&Path>) -> AppResult<Option<Self>> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        let raw = fs::read(path).map_err(|e| AppError::io("read encryption key file", e))?;
+        let text = std::str::from_utf8(&raw).map(str::trim).unwrap_or_default();
+        let key = if text.len() == 64 {
+            hex::decode(text).map_err(|_| AppError::internal("encryption key is not valid hex"))?
+        } else {
+            raw
+        };
+        Self::from_key(&key).map(Some)
+    }
+
+    pub fn from_key(key: &[u8]) -> AppResult<Self> {
+        let aead = Aes256Gcm::new_from_slice(key).map_err(|_| {
+            AppError::internal("encryption key must be 32 bytes (or 64 hex characters)")
+        })?;
+        Ok(Self {
+            aead: Arc::new(aead),
+        })
+    }
+
+    pub fn seal(&self, id: &str, plaintext: &[u8]) -> AppResult<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = self
+            .aead
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| AppError::internal("encrypt paste failed"))?;
+        let mut out = Vec::with_capacity(plaintext.len() + OVERHEAD as usize);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    pub fn open(&self, id: &str, stored: &[u8]) -> AppResult<Vec<u8>> {
+        let body = stored
+            .strip_prefix(MAGIC)
+            .filter(|rest| rest.len() >= NONCE_LEN + TAG_LEN)
+            .ok_or_else(|| AppError::Integrity("sealed paste is malformed".to_string()))?;
+        let (nonce, sealed) = body.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| AppError::Integrity("sealed paste failed authentication".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_round_trips_and_binds_the_paste_id() {
+        let cipher = PasteCipher::from_key(&[7u8; 32]).expect("key");
+        let sealed = cipher.seal("01A", b"secret").expect("seal");
+        assert_eq!(sealed.len() as u64, 6 + OVERHEAD);
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(cipher.open("01A", &sealed).expect("open"), b"secret");
+        assert!(matches!(
+            cipher.open("01B", &sealed),
+            Err(AppError::Integrity(_))
+        ));
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().expect("byte") ^= 1;
+        assert!(cipher.open("01A", &tampered).is_err());
+    }
+
+    #[test]
+    fn key_file_accepts_hex_or_raw() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let hex_path = dir.path().join("hex.key");
+        fs::write(&hex_path, format!("{}\n", "ab".repeat(32))).expect("write");
+        assert!(
+            PasteCipher::from_file(Some(&hex_path))
+                .expect("hex")
+                .is_some()
+        );
+
+        let raw_path = dir.path().join("raw.key");
+        fs::write(&raw_path, [1u8; 32]).expect("write");
+        assert!(
+            PasteCipher::from_file(Some(&raw_path))
+                .expect("raw")
+                .is_some()
+        );
+
+        fs::write(&raw_path, b"short").expect("write");
+        assert!(PasteCipher::from_file(Some(&raw_path)).is_err());
+        assert!(PasteCipher::from_file(None).expect("none").is_none());
+    }
+}
@@ -0,0 +1,309 @@
+//! Daily digest: a summary of one UTC day's pastes, committed as a paste of
+//! its own and delivered through the configured notifiers.
+
+use std::{collections::HashMap, fs, path::Path, sync::Arc, time::Duration};
+
+use serde::Serialize;
+use time::{Date, OffsetDateTime};
+use tracing::{info, warn};
+
+use crate::{
+    errors::{AppError, AppResult},
+    events::EventKind,
+    gitops::{self, FileLock},
+    notify::Notification,
+    store,
+    types::{AppState, CreatePasteInput, PasteBody, PasteMeta, Visibility},
+};
+
+pub const DIGEST_TAG: &str = "digest";
+const SKIPPED_TAGS: [&str; 2] = [DIGEST_TAG, "selftest"];
+const TOP_CREATORS: usize = 5;
+const DIGEST_NS: &str = "digest";
+const LAST_SENT_KEY: &str = "last-day";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Digest {
+    #[serde(serialize_with = "serialize_day")]
+    pub day: Date,
+    pub pastes: usize,
+    pub bytes: u64,
+    /// Tag -> paste count, most used first; untagged pastes count as
+    /// `(none)` and unlisted ones as `(unlisted)`.
+    pub by_tag: Vec<(String, usize)>,
+    /// Client address -> paste count, top five.
+    pub top_creators: Vec<(String, usize)>,
+}
+
+/// Summarises pastes created on `day` (UTC), leaving out self-test canaries
+/// and earlier digests.
+pub fn build(repo: &Path, day: Date) -> AppResult<Digest> {
+    let mut digest = Digest {
+        day,
+        pastes: 0,
+        bytes: 0,
+        by_tag: Vec::new(),
+        top_creators: Vec::new(),
+    };
+    let mut tags = HashMap::<String, usize>::new();
+    let mut creators = HashMap::<String, usize>::new();
+    let entries = match fs::read_dir(repo.join("meta")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(digest),
+        Err(e) => return Err(AppError::io("read meta dir", e)),
+    };
+    for entry in entries.flatten() {
+        let Ok(data) = fs::read(entry.path()) else {
+            continue;
+        };
+        let Ok(meta) = serde_json::from_slice::<PasteMeta>(&data) else {
+            continue;
+        };
+        if meta.created_at.date() != day
+            || meta
+                .tag
+                .as_deref()
+                .is_some_and(|t| SKIPPED_TAGS.contains(&t))
+        {
+            continue;
+        }
+        digest.pastes += 1;
+        digest.bytes += meta.size as u64;
+        // Unlisted pastes are counted, but their tags stay private.
+        let tag = if meta.visibility.is_listed() {
+            meta.tag.unwrap_or_else(|| "(none)".to_string())
+        } else {
+            "(unlisted)".to_string()
+        };
+        *tags.entry(tag).or_default() += 1;
+        *creators
+            .entry(
+                meta.client_ip
+                    .map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+            )
+            .or_default() += 1;
+    }
+    digest.by_tag = ranked(tags);
+    digest.top_creators = ranked(creators);
+    digest.top_creators.truncate(TOP_CREATORS);
+    Ok(digest)
+}
+
+fn serialize_day<S: serde::Serializer>(day: &Date, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(day)
+}
+
+/// Parses `YYYY-MM-DD`; `None` means yesterday (UTC).
+pub fn parse_day(raw: Option<&str>) -> AppResult<Date> {
+    let Some(raw) = raw else {
+        let today = OffsetDateTime::now_utc().date();
+        return today
+            .previous_day()
+            .ok_or_else(|| AppError::internal("no previous day"));
+    };
+    let format = time::format_description::parse("[year]-[month]-[day]")
+        .map_err(|e| AppError::internal(format!("date format: {e}")))?;
+    Date::parse(raw, &format)
+        .map_err(|_| AppError::BadRequest(format!("invalid day '{raw}'; expected YYYY-MM-DD")))
+}
+
+fn ranked(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut out: Vec<_> = counts.into_iter().collect();
+    out.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    out
+}
+
+pub fn to_markdown(digest: &Digest) -> String {
+    let mut out = format!(
+        "# lanpaste digest for {}\n\n- New pastes: {}\n- Total bytes: {}\n",
+        digest.day, digest.pastes, digest.bytes
+    );
+    if !digest.by_tag.is_empty() {
+        out.push_str("\n## By tag\n\n| Tag | Pastes |\n| --- | ---: |\n");
+        for (tag, count) in &digest.by_tag {
+            out.push_str(&format!("| {} | {count} |\n", tag.replace('|', "\\|")));
+        }
+    }
+    if !digest.top_creators.is_empty() {
+        out.push_str("\n## Top creators\n\n| Client | Pastes |\n| --- | ---: |\n");
+        for (client, count) in &digest.top_creators {
+            out.push_str(&format!("| {client} | {count} |\n"));
+        }
+    }
+    out
+}
+
+/// Builds the digest for `day`, commits it as a `digest`-tagged paste and
+/// sends it. Returns the digest and the id of the stored paste.
+pub async fn run(state: &Arc<AppState>, day: Date) -> AppResult<(Digest, String)> {
+    let stored = {
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || store_digest(&state, day))
+            .await
+            .map_err(|e| AppError::internal(format!("digest task failed: {e}")))??
+    };
+    let (digest, id) = stored;
+    if state.notifier.enabled() {
+        let view_url = format!("{}/p/{id}", state.cfg.base_path);
+        let note = Notification {
+            event: "digest",
+            subject: format!("lanpaste digest for {day}: {} new pastes", digest.pastes),
+            text: format!("{}\nStored as {view_url}\n", to_markdown(&digest)),
+            data: serde_json::json!({ "digest": digest, "paste_id": id, "view_url": view_url }),
+        };
+        state.notifier.send(&note).await?;
+    }
+    Ok((digest, id))
+}
+
+fn store_digest(state: &AppState, day: Date) -> AppResult<(Digest, String)> {
+    let _git_lock = FileLock::acquire_waiting(&state.paths.git_lock)?;
+    let digest = build(&state.paths.repo, day)?;
+    let input = CreatePasteInput {
+        name: Some(format!("digest-{day}.md")),
+        msg: Some(format!("digest: {day}")),
+        tag: Some(DIGEST_TAG.to_string()),
+        content_type: Some("text/markdown".to_string()),
+        body: PasteBody::Bytes(to_markdown(&digest).into_bytes()),
+        e2e: false,
+        visibility: Visibility::Listed,
+        allow_keys: Vec::new(),
+        slug: None,
+        client_ip: None,
+        user_agent: Some("lanpaste-digest".to_string()),
+        owner: None,
+        lang: None,
+    };
+    let draft =
+        store::build_paste_draft(&state.paths.repo, &state.cfg, state.cipher.as_ref(), input)?;
+    let commit = gitops::commit_paste(
+        &state.paths.repo,
+        &state.cfg,
+        &draft,
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.ops.record_commit(state.cfg.push, &commit);
+    state
+        .events
+        .publish(EventKind::Created, &draft.meta, &commit.commit);
+    state.index.upsert_committed(draft.meta, &commit.commit);
+    Ok((digest, draft.id))
+}
+
+/// Sends yesterday's digest every day at `at` (UTC). The last day sent is
+/// kept in the runtime kv store so a restart does not send it twice.
+pub fn spawn_daily(state: Arc<AppState>, at: time::Time) {
+    tokio::spawn(async move {
+        loop {
+            let now = OffsetDateTime::now_utc();
+            let mut next = now.replace_time(at);
+            if next <= now {
+                next += time::Duration::days(1);
+            }
+            let wait = Duration::try_from(next - now).unwrap_or(Duration::from_secs(60));
+            tokio::time::sleep(wait).await;
+
+            let Some(day) = next.date().previous_day() else {
+                continue;
+            };
+            let already = state
+                .kv
+                .get::<Date>(DIGEST_NS, LAST_SENT_KEY)
+                .ok()
+                .flatten();
+            if already.is_some_and(|last| last >= day) {
+                continue;
+            }
+            match run(&state, day).await {
+                Ok((digest, id)) => {
+                    info!("digest for {day} stored as {id} ({} pastes)", digest.pastes);
+                }
+                Err(err) => warn!("digest for {day} failed: {err:?}"),
+            }
+            // Recorded even if delivery failed: the digest paste exists, and
+            // retrying would commit a duplicate.
+            if let Err(err) = state.kv.put(DIGEST_NS, LAST_SENT_KEY, &day, None) {
+                warn!("record digest day: {err:?}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::format_description::well_known::Rfc3339;
+
+    fn write_meta(repo: &Path, id: &str, at: &str, tag: Option<&str>, ip: &str, size: usize) {
+        let meta = PasteMeta {
+            created_at: OffsetDateTime::parse(at, &Rfc3339).expect("timestamp"),
+            size,
+            tag: tag.map(str::to_string),
+            client_ip: Some(ip.parse().expect("ip")),
+            ..PasteMeta::for_test(id)
+        };
+        fs::write(
+            repo.join("meta").join(format!("{id}.json")),
+            serde_json::to_vec(&meta).expect("json"),
+        )
+        .expect("write meta");
+    }
+
+    #[test]
+    fn counts_one_day_and_skips_housekeeping() {
+        let td = tempfile::tempdir().expect("tempdir");
+        fs::create_dir_all(td.path().join("meta")).expect("meta dir");
+        let repo = td.path();
+        write_meta(
+            repo,
+            "A",
+            "2026-03-01T09:00:00Z",
+            Some("ci"),
+            "10.0.0.2",
+            100,
+        );
+        write_meta(
+            repo,
+            "B",
+            "2026-03-01T10:00:00Z",
+            Some("ci"),
+            "10.0.0.2",
+            50,
+        );
+        write_meta(repo, "C", "2026-03-01T23:59:00Z", None, "10.0.0.3", 5);
+        write_meta(repo, "D", "2026-03-02T00:01:00Z", Some("ci"), "10.0.0.3", 7);
+        write_meta(
+            repo,
+            "E",
+            "2026-03-01T08:00:00Z",
+            Some("selftest"),
+            "127.0.0.1",
+            9,
+        );
+        write_meta(
+            repo,
+            "F",
+            "2026-03-01T08:00:00Z",
+            Some("digest"),
+            "127.0.0.1",
+            9,
+        );
+
+        let day = Date::from_calendar_date(2026, time::Month::March, 1).expect("date");
+        let digest = build(repo, day).expect("digest");
+        assert_eq!(digest.pastes, 3);
+        assert_eq!(digest.bytes, 155);
+        assert_eq!(
+            digest.by_tag,
+            vec![("ci".to_string(), 2), ("(none)".to_string(), 1)]
+        );
+        assert_eq!(digest.top_creators[0], ("10.0.0.2".to_string(), 2));
+
+        let md = to_markdown(&digest);
+        assert!(md.starts_with("# lanpaste digest for 2026-03-01"));
+        assert!(md.contains("| ci | 2 |"));
+        assert_eq!(parse_day(Some("2026-03-01")).expect("day"), day);
+        assert!(parse_day(Some("03/01/2026")).is_err());
+    }
+}
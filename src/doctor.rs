@@ -0,0 +1,414 @@
+//! `lanpaste doctor`: checks what `serve` needs with the same flags, and
+//! more than preflight does, then says how to fix each problem instead of
+//! stopping at the first one. It leaves nothing behind on disk.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::ErrorKind,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use fs2::FileExt;
+
+use crate::{
+    auth::ApiKeyStore,
+    config::{PushMode, ServeCmd},
+    gitops,
+    readiness::CheckStatus,
+    render::human_size,
+    types::AppPaths,
+};
+
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a `warn` or `fail`.
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// No check failed; warnings do not stop `serve`.
+    pub fn ok(&self) -> bool {
+        self.findings.iter().all(|f| f.status != CheckStatus::Fail)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Finding> {
+        self.findings.iter().find(|f| f.name == name)
+    }
+
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        for finding in &self.findings {
+            let label = match finding.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warn => "warn",
+                CheckStatus::Fail => "FAIL",
+                CheckStatus::Skipped => "skip",
+            };
+            out.push_str(&format!(
+                "{label:<5} {:<12} {}\n",
+                finding.name, finding.detail
+            ));
+            if let Some(fix) = &finding.fix {
+                out.push_str(&format!("{:<19}fix: {fix}\n", ""));
+            }
+        }
+        let failed = self
+            .findings
+            .iter()
+            .filter(|f| f.status == CheckStatus::Fail)
+            .count();
+        out.push_str(&match failed {
+            0 => "serve should start with these flags\n".to_string(),
+            n => format!("{n} problem(s) would stop serve\n"),
+        });
+        out
+    }
+}
+
+pub async fn run(cfg: &ServeCmd) -> Report {
+    let local = {
+        let cfg = cfg.clone();
+        tokio::task::spawn_blocking(move || local(&cfg)).await
+    };
+    let mut findings = local.unwrap_or_else(|err| {
+        vec![Finding::new(
+            "doctor",
+            CheckStatus::Fail,
+            format!("check panicked: {err}"),
+        )]
+    });
+    findings.push(remote(cfg).await);
+    Report { findings }
+}
+
+/// Checks that touch only this host. Blocks the thread.
+fn local(cfg: &ServeCmd) -> Vec<Finding> {
+    let paths = AppPaths::from_base(cfg.dir.clone());
+    let running = server_running(&paths);
+    vec![
+        git(),
+        repo(cfg, &paths, running),
+        disk(cfg, &paths),
+        permissions(&paths),
+        api_keys(cfg),
+        port(cfg, running),
+    ]
+}
+
+fn git() -> Finding {
+    let out = match Command::new("git").arg("--version").output() {
+        Ok(out) if out.status.success() => out,
+        _ => {
+            return Finding::new("git", CheckStatus::Fail, "git not found on PATH")
+                .fix(gitops::INSTALL_GIT);
+        }
+    };
+    let version = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    let major = version
+        .strip_prefix("git version ")
+        .and_then(|v| v.split('.').next())
+        .and_then(|v| v.parse::<u32>().ok());
+    match major {
+        Some(major) if major >= 2 => Finding::new("git", CheckStatus::Ok, version),
+        _ => Finding::new(
+            "git",
+            CheckStatus::Warn,
+            format!("{version}: expected git 2.x"),
+        )
+        .fix(format!("upgrade git. {}", gitops::INSTALL_GIT)),
+    }
+}
+
+/// `git fsck` over the repo, and git lock files a crash left behind.
+fn repo(cfg: &ServeCmd, paths: &AppPaths, running: bool) -> Finding {
+    let repo = &paths.repo;
+    if !repo.join(".git").exists() {
+        return Finding::new(
+            "repo",
+            CheckStatus::Skipped,
+            format!("{} does not exist yet; serve creates it", repo.display()),
+        );
+    }
+    if let Err(err) = gitops::run_git(repo, &["fsck", "--no-progress", "--no-dangling"], cfg) {
+        return Finding::new("repo", CheckStatus::Fail, format!("git fsck: {err:?}")).fix(format!(
+            "see `git -C {} fsck --full`; restore missing or corrupt objects from a clone of the remote or a backup",
+            repo.display()
+        ));
+    }
+    let index_lock = repo.join(".git/index.lock");
+    if index_lock.exists() && !running {
+        return Finding::new(
+            "repo",
+            CheckStatus::Warn,
+            format!("{} is left over from a crash", index_lock.display()),
+        )
+        .fix(format!(
+            "run `lanpaste admin gc --dir {}`, or remove the file if no git command is running",
+            cfg.dir.display()
+        ));
+    }
+    Finding::new("repo", CheckStatus::Ok, "git fsck found no problems")
+}
+
+fn disk(cfg: &ServeCmd, paths: &AppPaths) -> Finding {
+    let dir = existing_ancestor(&paths.base);
+    let free = match fs2::available_space(&dir) {
+        Ok(free) => free,
+        Err(err) => {
+            return Finding::new(
+                "disk",
+                CheckStatus::Fail,
+                format!("statvfs {}: {err}", dir.display()),
+            );
+        }
+    };
+    let detail = format!("{} free on {}", human_size(free as usize), dir.display());
+    if free < cfg.min_free_bytes {
+        Finding::new("disk", CheckStatus::Fail, detail).fix(format!(
+            "free up space there, or lower --min-free-bytes (now {})",
+            cfg.min_free_bytes
+        ))
+    } else {
+        Finding::new("disk", CheckStatus::Ok, detail)
+    }
+}
+
+/// Every directory serve writes to, or the nearest parent it would create
+/// it in, takes a new file.
+fn permissions(paths: &AppPaths) -> Finding {
+    let dirs = [
+        &paths.base,
+        &paths.run,
+        &paths.kv,
+        &paths.tmp,
+        &paths.repo,
+        &paths.files,
+        &paths.files_meta,
+    ];
+    let mut checked = Vec::new();
+    for dir in dirs.map(|d| existing_ancestor(d)) {
+        if checked.contains(&dir) {
+            continue;
+        }
+        let probe = dir.join(format!(".doctor-{}", std::process::id()));
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .map(|_| fs::remove_file(&probe));
+        if let Err(err) = written {
+            return Finding::new(
+                "permissions",
+                CheckStatus::Fail,
+                format!("cannot write to {}: {err}", dir.display()),
+            )
+            .fix(format!(
+                "run lanpaste as the user that owns it, or `sudo chown -R $(id -un) {}`",
+                dir.display()
+            ));
+        }
+        checked.push(dir);
+    }
+    Finding::new(
+        "permissions",
+        CheckStatus::Ok,
+        format!("{} is writable", paths.base.display()),
+    )
+}
+
+fn api_keys(cfg: &ServeCmd) -> Finding {
+    let Some(path) = cfg.api_keys_file.as_deref() else {
+        return Finding::new("api_keys", CheckStatus::Skipped, "no --api-keys-file");
+    };
+    if let Err(err) = ApiKeyStore::from_file(Some(path)) {
+        return Finding::new("api_keys", CheckStatus::Fail, format!("{err:?}")).fix(format!(
+            "correct {}; the format is in the README under API keys",
+            path.display()
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)
+            .map(|m| m.permissions().mode())
+            .unwrap_or(0);
+        if mode & 0o077 != 0 {
+            return Finding::new(
+                "api_keys",
+                CheckStatus::Warn,
+                format!("{} is readable by other users", path.display()),
+            )
+            .fix(format!("chmod 600 {}", path.display()));
+        }
+    }
+    Finding::new(
+        "api_keys",
+        CheckStatus::Ok,
+        format!("{} is valid", path.display()),
+    )
+}
+
+/// Every `--bind`; the first one that cannot be bound is reported.
+fn port(cfg: &ServeCmd, running: bool) -> Finding {
+    if running {
+        return Finding::new(
+            "port",
+            CheckStatus::Skipped,
+            format!("a server is already running on {}", cfg.dir.display()),
+        );
+    }
+    // Held until all are checked, so two equal --bind values fail.
+    let mut held = Vec::with_capacity(cfg.bind.len());
+    for &bind in &cfg.bind {
+        match TcpListener::bind(bind) {
+            Ok(listener) => held.push(listener),
+            Err(err) => return bind_failed(bind, &err),
+        }
+    }
+    let free: Vec<String> = cfg.bind.iter().map(ToString::to_string).collect();
+    let verb = if free.len() == 1 { "is" } else { "are" };
+    Finding::new(
+        "port",
+        CheckStatus::Ok,
+        format!("{} {verb} free", free.join(", ")),
+    )
+}
+
+fn bind_failed(bind: SocketAddr, err: &std::io::Error) -> Finding {
+    match err.kind() {
+        ErrorKind::AddrInUse => {
+            Finding::new("port", CheckStatus::Fail, format!("{bind} is in use")).fix(format!(
+                "find the process with `ss -ltnp 'sport = :{}'`, or pick another --bind",
+                bind.port()
+            ))
+        }
+        ErrorKind::PermissionDenied => Finding::new(
+            "port",
+            CheckStatus::Fail,
+            format!("not allowed to bind {bind}"),
+        )
+        .fix("use a port above 1023, or `sudo setcap cap_net_bind_service=+ep $(command -v lanpaste)`"),
+        ErrorKind::AddrNotAvailable => Finding::new(
+            "port",
+            CheckStatus::Fail,
+            format!("{} is not an address of this host", bind.ip()),
+        )
+        .fix("bind 0.0.0.0, or an address listed by `ip addr`"),
+        _ => Finding::new("port", CheckStatus::Fail, format!("bind {bind}: {err}")),
+    }
+}
+
+/// `git ls-remote` against `--remote`. As in `/readyz`, only `--push strict`
+/// cannot serve without it.
+async fn remote(cfg: &ServeCmd) -> Finding {
+    if cfg.push == PushMode::Off {
+        return Finding::new("remote", CheckStatus::Skipped, "--push off");
+    }
+    let unreachable = if cfg.push == PushMode::Strict {
+        CheckStatus::Fail
+    } else {
+        CheckStatus::Warn
+    };
+    let repo = AppPaths::from_base(cfg.dir.clone()).repo;
+    if !repo.join(".git").exists() {
+        return Finding::new(
+            "remote",
+            unreachable,
+            "pushing needs a remote, and there is no repo yet",
+        )
+        .fix(format!(
+            "serve creates the repo on first start; then `git -C {} remote add {} <url>`",
+            repo.display(),
+            cfg.remote
+        ));
+    }
+    let git = |args: &[&str]| {
+        tokio::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .kill_on_drop(true)
+            .output()
+    };
+    let url = match git(&["remote", "get-url", &cfg.remote]).await {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).trim().to_string(),
+        _ => {
+            return Finding::new(
+                "remote",
+                unreachable,
+                format!("the repo has no remote named {}", cfg.remote),
+            )
+            .fix(format!(
+                "`git -C {} remote add {} <url>`, or run with --push off",
+                repo.display(),
+                cfg.remote
+            ));
+        }
+    };
+    let failure =
+        match tokio::time::timeout(REMOTE_TIMEOUT, git(&["ls-remote", "--heads", &url])).await {
+            Ok(Ok(out)) if out.status.success() => None,
+            Ok(Ok(out)) => Some(String::from_utf8_lossy(&out.stderr).trim().to_string()),
+            Ok(Err(err)) => Some(format!("git ls-remote: {err}")),
+            Err(_) => Some(format!("no answer within {}s", REMOTE_TIMEOUT.as_secs())),
+        };
+    match failure {
+        None => Finding::new("remote", CheckStatus::Ok, format!("{url} is reachable")),
+        Some(detail) => Finding::new("remote", unreachable, format!("{url}: {detail}")).fix(
+            "check the URL, the network, and that this user's SSH key or credential helper works without a prompt",
+        ),
+    }
+}
+
+/// Another process holds the daemon lock on this `--dir`.
+fn server_running(paths: &AppPaths) -> bool {
+    let Ok(lock) = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(paths.run.join("daemon.lock"))
+    else {
+        return false;
+    };
+    let held = lock.try_lock_exclusive().is_err();
+    if !held {
+        let _ = FileExt::unlock(&lock);
+    }
+    held
+}
+
+/// `path`, or its closest parent that exists.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."))
+        .to_path_buf()
+}
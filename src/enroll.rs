@@ -0,0 +1,513 @@
+//! Device enrollment: a client asks for an API key, the server shows the
+//! request to an operator, and the operator approves it by typing the short
+//! code the client printed. The client then picks up its key exactly once.
+
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+use crate::{
+    auth::{self, ApiKeyEntry, ApiKeyStore, Scope},
+    config::EnrollCmd,
+    errors::{AppError, AppResult},
+    kv::Kv,
+};
+
+pub const ENROLL_TOKEN_HEADER: &str = "X-Enroll-Token";
+const ENROLL_NS: &str = "enroll";
+const ENROLL_TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_PENDING: usize = 32;
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnrollStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enrollment {
+    pub id: String,
+    pub name: String,
+    pub code: String,
+    pub token_sha256: String,
+    pub scopes: Vec<String>,
+    pub client_ip: Option<IpAddr>,
+    pub requested_at: OffsetDateTime,
+    pub status: EnrollStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnrollRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrollTicket {
+    pub id: String,
+    pub code: String,
+    pub poll_token: String,
+    pub expires_at: OffsetDateTime,
+}
+
+/// What an operator sees for a pending request. The code is deliberately
+/// left out: it has to be read off the client's screen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingEnrollment {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub client_ip: Option<IpAddr>,
+    pub requested_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrollPoll {
+    pub status: EnrollStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+/// Opens a pending enrollment. Default scopes cover writing and reading
+/// pastes; `admin` can never be requested.
+pub fn request(
+    keys: &ApiKeyStore,
+    kv: &Kv,
+    req: EnrollRequest,
+    client_ip: Option<IpAddr>,
+) -> AppResult<EnrollTicket> {
+    if !keys.enabled() {
+        return Err(AppError::Conflict(
+            "enrollment requires --api-keys-file".to_string(),
+        ));
+    }
+    let name = req.name.trim();
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || !name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+    {
+        return Err(AppError::BadRequest(
+            "name must be 1-64 characters of [A-Za-z0-9._-]".to_string(),
+        ));
+    }
+    let scopes = if req.scopes.is_empty() {
+        vec![
+            Scope::PasteCreate.as_str().to_string(),
+            Scope::PasteRead.as_str().to_string(),
+            Scope::RecentRead.as_str().to_string(),
+        ]
+    } else {
+        let mut scopes = Vec::new();
+        for raw in &req.scopes {
+            let scope = Scope::parse_enrollable(raw).ok_or_else(|| {
+                AppError::BadRequest(format!("scope '{raw}' cannot be requested"))
+            })?;
+            if !scopes.iter().any(|s| s == scope.as_str()) {
+                scopes.push(scope.as_str().to_string());
+            }
+        }
+        scopes
+    };
+
+    let open = kv.list::<Enrollment>(ENROLL_NS)?;
+    if open.len() >= MAX_PENDING {
+        return Err(AppError::TooManyRequests(
+            "too many pending enrollments".to_string(),
+        ));
+    }
+    let code = loop {
+        let code = fresh_code();
+        if !open.iter().any(|e| e.code == code) {
+            break code;
+        }
+    };
+    let poll_token = auth::random_token(32);
+    let id = Ulid::new().to_string();
+    let requested_at = OffsetDateTime::now_utc();
+    kv.put(
+        ENROLL_NS,
+        &id,
+        &Enrollment {
+            id: id.clone(),
+            name: name.to_string(),
+            code: code.clone(),
+            token_sha256: hex::encode(Sha256::digest(poll_token.as_bytes())),
+            scopes,
+            client_ip,
+            requested_at,
+            status: EnrollStatus::Pending,
+            api_key: None,
+        },
+        Some(ENROLL_TTL),
+    )?;
+    Ok(EnrollTicket {
+        id,
+        code,
+        poll_token,
+        expires_at: requested_at + ENROLL_TTL,
+    })
+}
+
+pub fn pending(kv: &Kv) -> AppResult<Vec<PendingEnrollment>> {
+    let mut out: Vec<_> = kv
+        .list::<Enrollment>(ENROLL_NS)?
+        .into_iter()
+        .filter(|e| e.status == EnrollStatus::Pending)
+        .map(|e| PendingEnrollment {
+            id: e.id,
+            name: e.name,
+            scopes: e.scopes,
+            client_ip: e.client_ip,
+            requested_at: e.requested_at,
+        })
+        .collect();
+    out.sort_by_key(|e| e.requested_at);
+    Ok(out)
+}
+
+/// Approves the pending request showing `code`: mints a key, writes it to
+/// the key file and parks it for the client's next poll.
+pub fn approve(keys: &ApiKeyStore, kv: &Kv, code: &str) -> AppResult<PendingEnrollment> {
+    let code = code.trim();
+    let mut enrollment = kv
+        .list::<Enrollment>(ENROLL_NS)?
+        .into_iter()
+        .find(|e| {
+            e.status == EnrollStatus::Pending && e.code.as_bytes().ct_eq(code.as_bytes()).into()
+        })
+        .ok_or_else(|| AppError::NotFound("no pending enrollment with that code".to_string()))?;
+    let api_key = auth::new_api_key();
+    keys.add_key(ApiKeyEntry {
+        name: Some(enrollment.name.clone()),
+        key: api_key.clone(),
+        scopes: enrollment.scopes.clone(),
+        max_requests_per_minute: None,
+        enabled: true,
+    })?;
+    enrollment.status = EnrollStatus::Approved;
+    enrollment.api_key = Some(api_key);
+    kv.put(ENROLL_NS, &enrollment.id, &enrollment, Some(ENROLL_TTL))?;
+    Ok(PendingEnrollment {
+        id: enrollment.id,
+        name: enrollment.name,
+        scopes: enrollment.scopes,
+        client_ip: enrollment.client_ip,
+        requested_at: enrollment.requested_at,
+    })
+}
+
+pub fn reject(kv: &Kv, id: &str) -> AppResult<()> {
+    let mut enrollment = kv
+        .get::<Enrollment>(ENROLL_NS, id)?
+        .filter(|e| e.status == EnrollStatus::Pending)
+        .ok_or_else(|| AppError::NotFound("no pending enrollment".to_string()))?;
+    enrollment.status = EnrollStatus::Rejected;
+    kv.put(ENROLL_NS, id, &enrollment, Some(ENROLL_TTL))
+}
+
+/// Client poll. An approved key is handed out once and then forgotten;
+/// a rejection is reported once as well.
+pub fn poll(kv: &Kv, id: &str, token: &str) -> AppResult<EnrollPoll> {
+    let enrollment = kv
+        .get::<Enrollment>(ENROLL_NS, id)?
+        .ok_or_else(|| AppError::NotFound("enrollment not found or expired".to_string()))?;
+    let presented = hex::encode(Sha256::digest(token.as_bytes()));
+    if !bool::from(
+        presented
+            .as_bytes()
+            .ct_eq(enrollment.token_sha256.as_bytes()),
+    ) {
+        return Err(AppError::Unauthorized(
+            "invalid enrollment token".to_string(),
+        ));
+    }
+    match enrollment.status {
+        EnrollStatus::Pending => Ok(EnrollPoll {
+            status: EnrollStatus::Pending,
+            name: None,
+            api_key: None,
+            scopes: Vec::new(),
+        }),
+        EnrollStatus::Rejected => {
+            kv.delete(ENROLL_NS, id)?;
+            Err(AppError::Forbidden("enrollment was rejected".to_string()))
+        }
+        EnrollStatus::Approved => {
+            kv.delete(ENROLL_NS, id)?;
+            Ok(EnrollPoll {
+                status: EnrollStatus::Approved,
+                name: Some(enrollment.name),
+                api_key: enrollment.api_key,
+                scopes: enrollment.scopes,
+            })
+        }
+    }
+}
+
+fn fresh_code() -> String {
+    let mut buf = [0u8; 4];
+    getrandom::getrandom(&mut buf).expect("os random source");
+    format!("{:06}", u32::from_le_bytes(buf) % 1_000_000)
+}
+
+/// Where `lanpaste enroll` stores the issued key unless `--out` is given.
+pub fn default_client_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("lanpaste").join("client.json"))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientConfig {
+    pub server: String,
+    pub name: String,
+    pub api_key: String,
+    pub scopes: Vec<String>,
+}
+
+/// Runs the client side: request, print the code, poll until the operator
+/// decides, then save the key with owner-only permissions.
+pub async fn run_enroll(cmd: EnrollCmd) -> AppResult<PathBuf> {
+    let out = match cmd.out.clone() {
+        Some(path) => path,
+        None => default_client_config_path().ok_or_else(|| {
+            AppError::BadRequest("cannot locate a config directory; pass --out".to_string())
+        })?,
+    };
+    let base = cmd.server.trim_end_matches('/').to_string();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| AppError::internal(format!("build http client: {e}")))?;
+
+    let name = match cmd.name.clone() {
+        Some(name) => name,
+        None => default_device_name(),
+    };
+    let response = client
+        .post(format!("{base}/api/v1/enroll"))
+        .json(&serde_json::json!({ "name": name, "scopes": cmd.scope }))
+        .send()
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("enroll request failed: {e}")))?;
+    let ticket: EnrollTicket = expect_json(response).await?;
+    eprintln!(
+        "Enrollment code: {}\nConfirm it at {base}/admin/enroll (expires in {} minutes)",
+        ticket.code,
+        ENROLL_TTL.as_secs() / 60
+    );
+
+    let deadline = tokio::time::Instant::now() + cmd.timeout.min(ENROLL_TTL);
+    loop {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(AppError::ServiceUnavailable(
+                "timed out waiting for approval".to_string(),
+            ));
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let response = client
+            .get(format!("{base}/api/v1/enroll/{}", ticket.id))
+            .header(ENROLL_TOKEN_HEADER, &ticket.poll_token)
+            .send()
+            .await
+            .map_err(|e| AppError::ServiceUnavailable(format!("enroll poll failed: {e}")))?;
+        let poll: EnrollPoll = expect_json(response).await?;
+        if poll.status != EnrollStatus::Approved {
+            continue;
+        }
+        let config = ClientConfig {
+            server: base,
+            name: poll.name.unwrap_or(name),
+            api_key: poll
+                .api_key
+                .ok_or_else(|| AppError::internal("approved enrollment carried no key"))?,
+            scopes: poll.scopes,
+        };
+        write_client_config(&out, &config)?;
+        return Ok(out);
+    }
+}
+
+pub(crate) async fn expect_json<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+) -> AppResult<T> {
+    let status = response.status();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("read response: {e}")))?;
+    if !status.is_success() {
+        let message = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v["message"].as_str().map(ToString::to_string))
+            .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+        let message = format!("server returned {status}: {message}");
+        return Err(match status {
+            StatusCode::UNAUTHORIZED => AppError::Unauthorized(message),
+            StatusCode::FORBIDDEN => AppError::Forbidden(message),
+            StatusCode::NOT_FOUND | StatusCode::GONE => AppError::NotFound(message),
+            StatusCode::CONFLICT => AppError::Conflict(message),
+            StatusCode::LOCKED => AppError::Locked(message),
+            StatusCode::PAYLOAD_TOO_LARGE => AppError::TooLarge(message),
+            StatusCode::TOO_MANY_REQUESTS => AppError::TooManyRequests(message),
+            StatusCode::INSUFFICIENT_STORAGE => AppError::InsufficientStorage(message),
+            StatusCode::SERVICE_UNAVAILABLE => AppError::ServiceUnavailable(message),
+            status if status.is_server_error() => AppError::Internal(message),
+            _ => AppError::BadRequest(message),
+        });
+    }
+    serde_json::from_slice(&body)
+        .map_err(|e| AppError::internal(format!("unexpected response: {e}")))
+}
+
+fn default_device_name() -> String {
+    let raw = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .unwrap_or_else(|| "device".to_string());
+    let name: String = raw
+        .trim()
+        .chars()
+        .filter(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+        .take(MAX_NAME_LEN)
+        .collect();
+    if name.is_empty() {
+        "device".to_string()
+    } else {
+        name
+    }
+}
+
+pub fn read_client_config(path: &Path) -> AppResult<ClientConfig> {
+    let bytes = std::fs::read(path).map_err(|e| AppError::io("read client config", e))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| AppError::BadRequest(format!("client config {}: {e}", path.display())))
+}
+
+pub fn write_client_config(path: &Path, config: &ClientConfig) -> AppResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| AppError::io("create config dir", e))?;
+    }
+    let bytes = serde_json::to_vec_pretty(config)
+        .map_err(|e| AppError::internal(format!("serialize client config: {e}")))?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    use std::io::Write;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| AppError::io("write client config", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn approve_by_code_then_hand_out_key_once() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let keys_path = td.path().join("keys.json");
+        std::fs::write(&keys_path, r#"{"keys":[{"key":"ops","scopes":["admin"]}]}"#)
+            .expect("write");
+        let keys = ApiKeyStore::from_file(Some(&keys_path)).expect("keys");
+        let kv = Kv::open(td.path().join("kv"));
+
+        let bad = EnrollRequest {
+            name: "laptop".to_string(),
+            scopes: vec!["admin".to_string()],
+        };
+        assert!(request(&keys, &kv, bad, None).is_err());
+
+        let ticket = request(
+            &keys,
+            &kv,
+            EnrollRequest {
+                name: "laptop".to_string(),
+                scopes: Vec::new(),
+            },
+            None,
+        )
+        .expect("request");
+        assert_eq!(ticket.code.len(), 6);
+        assert_eq!(
+            poll(&kv, &ticket.id, &ticket.poll_token)
+                .expect("poll")
+                .status,
+            EnrollStatus::Pending
+        );
+        assert!(poll(&kv, &ticket.id, "wrong").is_err());
+        assert_eq!(pending(&kv).expect("pending").len(), 1);
+
+        assert!(approve(&keys, &kv, "not-it").is_err());
+        approve(&keys, &kv, &ticket.code).expect("approve");
+        assert!(pending(&kv).expect("pending").is_empty());
+
+        let issued = poll(&kv, &ticket.id, &ticket.poll_token).expect("poll");
+        assert_eq!(issued.status, EnrollStatus::Approved);
+        let key = issued.api_key.expect("key");
+        assert!(key.starts_with("lp_"));
+        assert!(
+            std::fs::read_to_string(&keys_path)
+                .expect("keys")
+                .contains(&key)
+        );
+        assert!(poll(&kv, &ticket.id, &ticket.poll_token).is_err());
+    }
+
+    #[test]
+    fn rejected_requests_report_once() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let keys_path = td.path().join("keys.json");
+        std::fs::write(&keys_path, r#"{"keys":[{"key":"ops","scopes":["admin"]}]}"#)
+            .expect("write");
+        let keys = ApiKeyStore::from_file(Some(&keys_path)).expect("keys");
+        let kv = Kv::open(td.path().join("kv"));
+        let ticket = request(
+            &keys,
+            &kv,
+            EnrollRequest {
+                name: "tablet".to_string(),
+                scopes: vec!["paste:read".to_string()],
+            },
+            None,
+        )
+        .expect("request");
+        reject(&kv, &ticket.id).expect("reject");
+        assert!(matches!(
+            poll(&kv, &ticket.id, &ticket.poll_token),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            poll(&kv, &ticket.id, &ticket.poll_token),
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 
@@ -13,10 +13,16 @@ pub enum AppError {
     Forbidden(String),
     NotFound(String),
     Conflict(String),
+    Locked(String),
     TooLarge(String),
     TooManyRequests(String),
+    /// Too little free disk space to commit safely.
+    InsufficientStorage(String),
     Internal(String),
+    Integrity(String),
     ServiceUnavailable(String),
+    /// Another write kept the git lock too long; a `503` with `Retry-After`.
+    Busy(String),
 }
 
 impl AppError {
@@ -29,29 +35,48 @@ impl AppError {
     }
 }
 
+/// The message of an error response, kept as a response extension so the
+/// admin error log can see it without parsing the body.
+#[derive(Debug, Clone)]
+pub struct ErrorMessage(pub String);
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let retry_soon = matches!(self, AppError::Busy(_));
         let (status, code, message) = match self {
             AppError::BadRequest(m) => (StatusCode::BAD_REQUEST, "bad_request", m),
             AppError::Unauthorized(m) => (StatusCode::UNAUTHORIZED, "unauthorized", m),
             AppError::Forbidden(m) => (StatusCode::FORBIDDEN, "forbidden", m),
             AppError::NotFound(m) => (StatusCode::NOT_FOUND, "not_found", m),
             AppError::Conflict(m) => (StatusCode::CONFLICT, "conflict", m),
+            AppError::Locked(m) => (StatusCode::LOCKED, "locked", m),
             AppError::TooLarge(m) => (StatusCode::PAYLOAD_TOO_LARGE, "too_large", m),
             AppError::TooManyRequests(m) => (StatusCode::TOO_MANY_REQUESTS, "too_many_requests", m),
+            AppError::InsufficientStorage(m) => {
+                (StatusCode::INSUFFICIENT_STORAGE, "insufficient_storage", m)
+            }
             AppError::Internal(m) => (StatusCode::INTERNAL_SERVER_ERROR, "internal", m),
+            AppError::Integrity(m) => (StatusCode::INTERNAL_SERVER_ERROR, "integrity_error", m),
             AppError::ServiceUnavailable(m) => {
                 (StatusCode::SERVICE_UNAVAILABLE, "service_unavailable", m)
             }
+            AppError::Busy(m) => (StatusCode::SERVICE_UNAVAILABLE, "busy", m),
         };
-        (
+        let mut response = (
             status,
             Json(ApiErrorBody {
                 error: code.to_string(),
-                message,
+                message: message.clone(),
             }),
         )
-            .into_response()
+            .into_response();
+        response.extensions_mut().insert(ErrorMessage(message));
+        if retry_soon {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+        }
+        response
     }
 }
 
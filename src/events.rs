@@ -0,0 +1,213 @@
+//! Paste lifecycle events behind `GET /api/v1/events`. Write paths publish
+//! once their commit has landed; subscribers get a broadcast receiver plus
+//! anything they missed from a short replay buffer, so an `EventSource` that
+//! reconnects with `Last-Event-ID` picks up where it left off.
+//!
+//! Only listed pastes produce events, matching what `/recent` shows.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::types::{PasteMeta, RecentItem};
+
+/// Events kept for replay, and the most a slow subscriber may fall behind
+/// before it starts missing some.
+const BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Created,
+    Deleted,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PasteEvent {
+    /// Sent as the SSE `id`. Starts from the clock in microseconds, so ids
+    /// keep increasing across restarts.
+    #[serde(skip)]
+    pub seq: u64,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    pub sha256: String,
+    /// The paste as `/api/v1/recent` lists it; for deletions `commit` is the
+    /// commit that removed it.
+    #[serde(flatten)]
+    pub paste: RecentItem,
+}
+
+impl PasteEvent {
+    /// Whether a subscriber filtering on `tag` (if any) wants this event.
+    pub fn matches_tag(&self, tag: Option<&str>) -> bool {
+        tag.is_none() || self.paste.tag.as_deref() == tag
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Events {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    tx: broadcast::Sender<PasteEvent>,
+    replay: Mutex<Replay>,
+}
+
+#[derive(Debug)]
+struct Replay {
+    next_seq: u64,
+    events: VecDeque<PasteEvent>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+        Self {
+            inner: Arc::new(Inner {
+                tx: broadcast::channel(BUFFER).0,
+                replay: Mutex::new(Replay {
+                    next_seq: start,
+                    events: VecDeque::with_capacity(BUFFER),
+                }),
+            }),
+        }
+    }
+}
+
+impl Events {
+    pub fn publish(&self, kind: EventKind, meta: &PasteMeta, commit: &str) {
+        if !meta.visibility.is_listed() {
+            return;
+        }
+        let mut paste = RecentItem::from_meta(meta.clone(), 0);
+        paste.commit = commit.to_string();
+        // Sending under the lock keeps the broadcast order and the replay
+        // buffer in step with `subscribe`.
+        let mut replay = self
+            .inner
+            .replay
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let event = PasteEvent {
+            seq: replay.next_seq,
+            kind,
+            sha256: meta.sha256.clone(),
+            paste,
+        };
+        replay.next_seq += 1;
+        if replay.events.len() == BUFFER {
+            replay.events.pop_front();
+        }
+        replay.events.push_back(event.clone());
+        let _ = self.inner.tx.send(event);
+    }
+
+    /// Buffered events after `last_seen` (none without one), then live ones.
+    pub fn subscribe(
+        &self,
+        last_seen: Option<u64>,
+    ) -> (Vec<PasteEvent>, broadcast::Receiver<PasteEvent>) {
+        let replay = self
+            .inner
+            .replay
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let missed = match last_seen {
+            Some(seq) => replay
+                .events
+                .iter()
+                .filter(|event| event.seq > seq)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (missed, self.inner.tx.subscribe())
+    }
+
+    /// [`Events::subscribe`] as one stream, keeping the events for `tag` (all
+    /// without one). A subscriber that falls behind skips what it missed.
+    pub fn stream(
+        &self,
+        last_seen: Option<u64>,
+        tag: Option<String>,
+    ) -> impl Stream<Item = PasteEvent> + Send + 'static {
+        let (missed, rx) = self.subscribe(last_seen);
+        let live = futures_util::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("event subscriber fell behind; skipped {skipped} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        futures_util::stream::iter(missed)
+            .chain(live)
+            .filter(move |event| std::future::ready(event.matches_tag(tag.as_deref())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Visibility;
+
+    fn meta(id: &str, visibility: Visibility) -> PasteMeta {
+        PasteMeta {
+            path: format!("pastes/{id}__x.txt"),
+            size: 1,
+            visibility,
+            ..PasteMeta::for_test(id)
+        }
+    }
+
+    #[test]
+    fn reconnects_replay_what_they_missed() {
+        let events = Events::default();
+        events.publish(EventKind::Created, &meta("A", Visibility::Listed), "c1");
+        let (missed, mut rx) = events.subscribe(None);
+        assert!(missed.is_empty());
+
+        events.publish(EventKind::Created, &meta("B", Visibility::Unlisted), "c2");
+        events.publish(EventKind::Deleted, &meta("A", Visibility::Listed), "c3");
+        let live = rx.try_recv().expect("live event");
+        assert_eq!(
+            (live.kind, live.paste.id.as_str()),
+            (EventKind::Deleted, "A")
+        );
+        assert_eq!(live.paste.commit, "c3");
+        assert!(rx.try_recv().is_err());
+
+        let (missed, _) = events.subscribe(Some(live.seq - 1));
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].seq, live.seq);
+        let json = serde_json::to_value(&missed[0]).expect("json");
+        assert_eq!(json["type"], "deleted");
+        assert_eq!(json["id"], "A");
+        assert!(missed[0].matches_tag(None));
+        assert!(!missed[0].matches_tag(Some("ci")));
+    }
+}
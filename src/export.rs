@@ -0,0 +1,336 @@
+//! `GET /api/v1/export`: every paste under a tag, or in a collection, as one
+//! zip or `.tar.gz`, so a day's CI logs come down in a single request.
+//!
+//! The archive holds each paste's content under `pastes/`, named like its
+//! file in the repo, plus `manifest.json` with the metadata of all of them.
+//! Sealed pastes are decrypted; end-to-end pastes stay ciphertext, as on
+//! every other read. The zip is written here rather than with a zip crate:
+//! deflated entries and a central directory are all a download needs.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use flate2::{Compression, Crc, write::DeflateEncoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    crypt::PasteCipher,
+    errors::{AppError, AppResult},
+    store,
+    types::PasteMeta,
+};
+
+/// Pastes one export may hold; a tag with more is cut to the newest.
+pub const MAX_PASTES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Zip => "zip",
+            ExportFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Zip => "application/zip",
+            ExportFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// `manifest.json` at the root of the archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    pub generated_at: OffsetDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    /// More pastes matched than [`MAX_PASTES`]; the oldest were left out.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    pub pastes: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestEntry {
+    /// Name of the paste's entry in the archive.
+    pub file: String,
+    #[serde(flatten)]
+    pub meta: PasteMeta,
+}
+
+impl Manifest {
+    pub fn new(tag: Option<String>, collection: Option<String>, metas: Vec<PasteMeta>) -> Self {
+        let truncated = metas.len() > MAX_PASTES;
+        let pastes = metas
+            .into_iter()
+            .take(MAX_PASTES)
+            .map(|meta| ManifestEntry {
+                file: entry_name(&meta),
+                meta,
+            })
+            .collect();
+        Self {
+            generated_at: OffsetDateTime::now_utc(),
+            tag,
+            collection,
+            truncated,
+            pastes,
+        }
+    }
+}
+
+/// Writes the archive for `manifest` to `dest`. Blocks on disk I/O.
+pub fn write(
+    repo: &Path,
+    cipher: Option<&PasteCipher>,
+    manifest: &Manifest,
+    format: ExportFormat,
+    dest: &Path,
+) -> AppResult<()> {
+    let file = File::create(dest).map_err(|e| AppError::io("create export", e))?;
+    let mut sink: Box<dyn EntrySink> = match format {
+        ExportFormat::Zip => Box::new(ZipWriter::new(BufWriter::new(file))),
+        ExportFormat::TarGz => Box::new(tar::Builder::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        ))),
+    };
+    let listing = serde_json::to_vec_pretty(manifest)
+        .map_err(|e| AppError::internal(format!("serialize manifest: {e}")))?;
+    sink.add("manifest.json", manifest.generated_at, &listing)
+        .map_err(|e| AppError::io("write export", e))?;
+    for entry in &manifest.pastes {
+        let content = store::unseal(cipher, &entry.meta, store::read_paste(repo, &entry.meta)?)?;
+        sink.add(&entry.file, entry.meta.created_at, &content)
+            .map_err(|e| AppError::io("write export", e))?;
+    }
+    sink.finish().map_err(|e| AppError::io("write export", e))
+}
+
+fn entry_name(meta: &PasteMeta) -> String {
+    let file = meta.content_path().rsplit('/').next().unwrap_or(&meta.id);
+    format!("pastes/{file}")
+}
+
+trait EntrySink {
+    fn add(&mut self, name: &str, modified: OffsetDateTime, data: &[u8]) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W: Write> EntrySink for tar::Builder<GzEncoder<W>> {
+    fn add(&mut self, name: &str, modified: OffsetDateTime, data: &[u8]) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(modified.unix_timestamp().max(0) as u64);
+        self.append_data(&mut header, name, data)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.into_inner()?.finish()?.flush()
+    }
+}
+
+/// Deflated entries, then the central directory. No zip64: an archive past
+/// 4 GiB is refused, and [`MAX_PASTES`] keeps the entry count in range.
+struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    central: Vec<u8>,
+    entries: u16,
+}
+
+const ZIP_VERSION: u16 = 20;
+/// Names are UTF-8.
+const ZIP_FLAGS: u16 = 1 << 11;
+const ZIP_DEFLATE: u16 = 8;
+
+impl<W: Write> ZipWriter<W> {
+    fn new(out: W) -> Self {
+        Self {
+            out,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write> EntrySink for ZipWriter<W> {
+    fn add(&mut self, name: &str, modified: OffsetDateTime, data: &[u8]) -> io::Result<()> {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(data)?;
+        let compressed = deflate.finish()?;
+        let (time, date) = dos_datetime(modified);
+        let offset = zip_u32(self.offset)?;
+        let sizes = [
+            crc.sum(),
+            zip_u32(compressed.len() as u64)?,
+            zip_u32(data.len() as u64)?,
+        ];
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| io::Error::other(format!("zip entry name too long: {name}")))?;
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        local.extend(0x0403_4b50u32.to_le_bytes());
+        for field in [ZIP_VERSION, ZIP_FLAGS, ZIP_DEFLATE, time, date] {
+            local.extend(field.to_le_bytes());
+        }
+        for field in sizes {
+            local.extend(field.to_le_bytes());
+        }
+        local.extend(name_len.to_le_bytes());
+        local.extend(0u16.to_le_bytes());
+        local.extend(name.as_bytes());
+        self.emit(&local)?;
+        self.emit(&compressed)?;
+
+        let central = &mut self.central;
+        central.extend(0x0201_4b50u32.to_le_bytes());
+        for field in [ZIP_VERSION, ZIP_VERSION, ZIP_FLAGS, ZIP_DEFLATE, time, date] {
+            central.extend(field.to_le_bytes());
+        }
+        for field in sizes {
+            central.extend(field.to_le_bytes());
+        }
+        // Name, extra and comment lengths, disk number, internal attributes.
+        for field in [name_len, 0, 0, 0, 0] {
+            central.extend(field.to_le_bytes());
+        }
+        // External attributes: a regular file, mode 0644.
+        central.extend((0o100_644u32 << 16).to_le_bytes());
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+        self.entries = self
+            .entries
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many zip entries"))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let start = zip_u32(self.offset)?;
+        let size = zip_u32(self.central.len() as u64)?;
+        let central = std::mem::take(&mut self.central);
+        self.emit(&central)?;
+        let mut end = Vec::with_capacity(22);
+        end.extend(0x0605_4b50u32.to_le_bytes());
+        for field in [0, 0, self.entries, self.entries] {
+            end.extend(field.to_le_bytes());
+        }
+        end.extend(size.to_le_bytes());
+        end.extend(start.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        self.emit(&end)?;
+        self.out.flush()
+    }
+}
+
+fn zip_u32(value: u64) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| io::Error::other("export exceeds 4 GiB; use format=tar.gz"))
+}
+
+/// MS-DOS time and date fields, which start in 1980 and count seconds in
+/// twos.
+fn dos_datetime(at: OffsetDateTime) -> (u16, u16) {
+    if at.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time =
+        (u16::from(at.hour()) << 11) | (u16::from(at.minute()) << 5) | u16::from(at.second() / 2);
+    let date = (((at.year() - 1980).min(127) as u16) << 9)
+        | (u16::from(u8::from(at.month())) << 5)
+        | u16::from(at.day());
+    (time, date)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::DeflateDecoder;
+
+    use super::*;
+
+    fn u16_at(buf: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([buf[at], buf[at + 1]]) as usize
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(buf[at..at + 4].try_into().expect("4 bytes")) as usize
+    }
+
+    #[test]
+    fn zip_entries_are_listed_and_inflate_back() {
+        let mut out = Vec::new();
+        let mut zip = Box::new(ZipWriter::new(&mut out));
+        let at = OffsetDateTime::from_unix_timestamp(1_792_155_600).expect("time");
+        zip.add("manifest.json", at, b"{}").expect("add");
+        zip.add("pastes/a.log", at, &b"line\n".repeat(100))
+            .expect("add");
+        zip.finish().expect("finish");
+
+        let end = out.len() - 22;
+        assert_eq!(u32_at(&out, end), 0x0605_4b50);
+        assert_eq!(u16_at(&out, end + 10), 2);
+        let mut at = u32_at(&out, end + 16);
+        let mut names = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(u32_at(&out, at), 0x0201_4b50);
+            let (compressed, size) = (u32_at(&out, at + 20), u32_at(&out, at + 24));
+            let name_len = u16_at(&out, at + 28);
+            let local = u32_at(&out, at + 42);
+            let name = String::from_utf8(out[at + 46..at + 46 + name_len].to_vec()).expect("name");
+
+            let data_at = local + 30 + u16_at(&out, local + 26);
+            let mut content = Vec::new();
+            DeflateDecoder::new(&out[data_at..data_at + compressed])
+                .read_to_end(&mut content)
+                .expect("inflate");
+            assert_eq!(content.len(), size);
+            let mut crc = Crc::new();
+            crc.update(&content);
+            assert_eq!(crc.sum() as usize, u32_at(&out, at + 16));
+            names.push(name);
+            at += 46 + name_len;
+        }
+        assert_eq!(names, ["manifest.json", "pastes/a.log"]);
+    }
+
+    #[test]
+    fn dos_dates_start_in_1980() {
+        let at = time::Date::from_calendar_date(2026, time::Month::October, 16)
+            .and_then(|day| day.with_hms(13, 45, 31))
+            .expect("date")
+            .assume_utc();
+        assert_eq!(
+            dos_datetime(at),
+            ((13 << 11) | (45 << 5) | 15, (46 << 9) | (10 << 5) | 16)
+        );
+        assert_eq!(dos_datetime(OffsetDateTime::UNIX_EPOCH), (0, 33));
+    }
+}
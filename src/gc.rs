@@ -0,0 +1,202 @@
+//! Maintenance: repacks the repo, drops expired runtime records and files
+//! left behind by crashes, and checks that every meta and paste file agree.
+//! Runs against a live server (`POST /api/v1/admin/gc`) or a stopped one
+//! (`lanpaste admin gc --dir`).
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{GcCmd, ServeCmd},
+    errors::{AppError, AppResult},
+    gitops::{self, FileLock},
+    preflight, stats, store,
+    types::{AppState, PasteMeta},
+};
+
+/// A git lock file this old was left by a process that died; every write of
+/// ours runs under the git lock, which gc holds.
+const STALE_GIT_LOCK: Duration = Duration::from_secs(10 * 60);
+/// Uploads still staging are minutes old at most.
+const STALE_UPLOAD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GcReport {
+    pub git_bytes_before: u64,
+    pub git_bytes_after: u64,
+    /// Idempotency records, leases and other runtime records past expiry.
+    pub expired_records: usize,
+    /// Lock and staging files removed, relative to `--dir`.
+    pub stale_files: Vec<String>,
+    pub pastes_checked: usize,
+    /// Metas and paste files that disagree; gc reports them, it does not
+    /// repair them.
+    pub problems: Vec<String>,
+}
+
+impl GcReport {
+    pub fn ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "git: {} -> {} bytes\nexpired records removed: {}\nstale files removed: {}\n",
+            self.git_bytes_before,
+            self.git_bytes_after,
+            self.expired_records,
+            self.stale_files.len(),
+        );
+        for file in &self.stale_files {
+            out.push_str(&format!("  {file}\n"));
+        }
+        out.push_str(&format!(
+            "pastes checked: {}, problems: {}\n",
+            self.pastes_checked,
+            self.problems.len()
+        ));
+        for problem in &self.problems {
+            out.push_str(&format!("  {problem}\n"));
+        }
+        out
+    }
+}
+
+/// Holds the git lock throughout, so writes wait until it is done. Blocks on
+/// git and disk I/O.
+pub fn run(state: &AppState) -> AppResult<GcReport> {
+    let _git_lock = FileLock::acquire_waiting(&state.paths.git_lock)?;
+    let paths = &state.paths;
+    let mut report = GcReport {
+        expired_records: state.kv.purge_expired()?,
+        ..GcReport::default()
+    };
+
+    let git_dir = paths.repo.join(".git");
+    for lock in ["index.lock", "HEAD.lock", "config.lock", "packed-refs.lock"] {
+        remove_if_stale(
+            &paths.base,
+            &git_dir.join(lock),
+            STALE_GIT_LOCK,
+            &mut report,
+        );
+    }
+    for entry in fs::read_dir(&paths.tmp).into_iter().flatten().flatten() {
+        remove_if_stale(&paths.base, &entry.path(), STALE_UPLOAD, &mut report);
+    }
+
+    report.git_bytes_before = stats::dir_size(&git_dir);
+    gitops::run_git(&paths.repo, &["gc", "--quiet"], &state.cfg)?;
+    report.git_bytes_after = stats::dir_size(&git_dir);
+
+    check_consistency(&paths.repo, &mut report);
+    Ok(report)
+}
+
+/// `lanpaste admin gc --dir`: the same job against a stopped server.
+pub fn run_offline(dir: &Path, cmd: &GcCmd) -> AppResult<GcReport> {
+    let cfg = ServeCmd::offline(dir, &cmd.git_author_name, &cmd.git_author_email)
+        .map_err(|e| AppError::internal(format!("build serve config: {e}")))?;
+    preflight::run_preflight(&cfg)?;
+    let state = preflight::build_state(cfg)?;
+    run(&state)
+}
+
+fn remove_if_stale(base: &Path, path: &Path, max_age: Duration, report: &mut GcReport) {
+    let age = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if age.is_some_and(|age| age >= max_age) && fs::remove_file(path).is_ok() {
+        let shown = path.strip_prefix(base).unwrap_or(path);
+        report.stale_files.push(shown.display().to_string());
+    }
+}
+
+/// Every meta parses, names a paste file that exists and hashes to its
+/// `sha256`, and every paste file has a meta.
+fn check_consistency(repo: &Path, report: &mut GcReport) {
+    let mut referenced = HashSet::new();
+    let metas = fs::read_dir(repo.join("meta"))
+        .into_iter()
+        .flatten()
+        .flatten();
+    for entry in metas {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let meta = match fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<PasteMeta>(&data).ok())
+        {
+            Some(meta) => meta,
+            None => {
+                report.problems.push(format!("meta/{name}: unreadable"));
+                continue;
+            }
+        };
+        report.pastes_checked += 1;
+        referenced.insert(meta.path.clone());
+        match store::read_paste(repo, &meta) {
+            Err(_) => report
+                .problems
+                .push(format!("meta/{name}: {} is missing", meta.path)),
+            Ok(bytes) if store::verify_integrity(&meta, &bytes).is_err() => report
+                .problems
+                .push(format!("{}: does not match its sha256", meta.path)),
+            Ok(_) => {}
+        }
+    }
+    let mut stack = vec![repo.join("pastes")];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let rel = path
+                .strip_prefix(repo)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            if !referenced.contains(&rel) {
+                report.problems.push(format!("{rel}: no meta refers to it"));
+            }
+        }
+    }
+    report.problems.sort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_old_files_count_as_stale() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let fresh = dir.path().join("tmp").join("upload-1.part");
+        fs::create_dir_all(fresh.parent().unwrap()).expect("mkdir");
+        fs::write(&fresh, b"x").expect("write");
+        let mut report = GcReport::default();
+        remove_if_stale(dir.path(), &fresh, STALE_UPLOAD, &mut report);
+        assert!(fresh.exists() && report.stale_files.is_empty());
+
+        remove_if_stale(dir.path(), &fresh, Duration::ZERO, &mut report);
+        assert!(!fresh.exists());
+        assert_eq!(report.stale_files, ["tmp/upload-1.part"]);
+        assert!(
+            report
+                .summary()
+                .contains("stale files removed: 1\n  tmp/upload-1.part\n")
+        );
+    }
+}
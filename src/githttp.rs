@@ -0,0 +1,247 @@
+//! Read-only git smart HTTP at `/git`, enabled by `--git-http`, so the paste
+//! repo can be cloned and fetched like any other:
+//! `git clone http://host:8090/git pastes`.
+//!
+//! Only the upload-pack service exists, run as `git upload-pack
+//! --stateless-rpc` the way `git http-backend` does, protocol v2 included.
+//! Pushes and the dumb protocol are refused. A clone carries everything in
+//! the repo, private pastes too, so with an API key file it takes the
+//! `repo:read` scope, sent as the password of HTTP Basic credentials.
+
+use std::{io::Read, process::Stdio, sync::Arc};
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{any, get, post},
+};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+use crate::{
+    auth::{self, Scope},
+    errors::{AppError, AppResult},
+    http as web,
+    types::AppState,
+};
+
+/// Largest negotiation request taken, after gzip: the `have` lines of a
+/// fetch into a very old clone.
+const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct InfoRefsParams {
+    service: Option<String>,
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/git/info/refs", get(info_refs))
+        .route("/git/git-upload-pack", post(upload_pack))
+        .route("/git/git-receive-pack", any(receive_pack))
+}
+
+async fn info_refs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<InfoRefsParams>,
+) -> Response {
+    challenge(advertise(&state, &headers, q.service.as_deref()).await)
+}
+
+async fn upload_pack(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    challenge(negotiate(&state, &headers, body).await)
+}
+
+async fn receive_pack() -> Response {
+    AppError::Forbidden("this repo is read-only over HTTP".to_string()).into_response()
+}
+
+/// `401` with a Basic challenge, so git asks for credentials and retries.
+fn challenge(result: AppResult<Response>) -> Response {
+    match result {
+        Ok(response) => response,
+        Err(err @ AppError::Unauthorized(_)) => {
+            let mut response = err.into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Basic realm=\"lanpaste\""),
+            );
+            response
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn advertise(
+    state: &AppState,
+    headers: &HeaderMap,
+    service: Option<&str>,
+) -> AppResult<Response> {
+    match service {
+        Some("git-upload-pack") => {}
+        Some("git-receive-pack") => {
+            return Err(AppError::Forbidden(
+                "this repo is read-only over HTTP".to_string(),
+            ));
+        }
+        _ => {
+            return Err(AppError::Forbidden(
+                "only the smart HTTP protocol is served".to_string(),
+            ));
+        }
+    }
+    auth::authorize_basic(&state.api_keys, headers, Scope::RepoRead)?;
+    web::check_read_token(state, headers)?;
+    let protocol = git_protocol(headers);
+    let out = upload_pack_command(state, protocol.as_deref())
+        .arg("--advertise-refs")
+        .arg(".")
+        .output()
+        .await
+        .map_err(|e| AppError::internal(format!("git upload-pack failed: {e}")))?;
+    if !out.status.success() {
+        return Err(AppError::internal(format!(
+            "git upload-pack: {}",
+            String::from_utf8_lossy(&out.stderr)
+        )));
+    }
+    // Version 2 starts with its capabilities; older clients expect the
+    // service announcement first.
+    let mut body = Vec::new();
+    if !protocol.as_deref().is_some_and(|p| p.contains("version=2")) {
+        body.extend(pkt_line("# service=git-upload-pack\n"));
+        body.extend(b"0000");
+    }
+    body.extend(out.stdout);
+    Ok(git_response(
+        "application/x-git-upload-pack-advertisement",
+        Body::from(body),
+    ))
+}
+
+async fn negotiate(state: &AppState, headers: &HeaderMap, body: Body) -> AppResult<Response> {
+    auth::authorize_basic(&state.api_keys, headers, Scope::RepoRead)?;
+    web::check_read_token(state, headers)?;
+    let request = axum::body::to_bytes(body, MAX_REQUEST_BYTES)
+        .await
+        .map_err(|_| AppError::TooLarge("git request body is too large".to_string()))?;
+    let gzipped = headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"gzip"));
+    let request = if gzipped { gunzip(&request)? } else { request };
+
+    let mut child = upload_pack_command(state, git_protocol(headers).as_deref())
+        .arg(".")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AppError::internal(format!("git upload-pack failed: {e}")))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let stdout = child.stdout.take().expect("piped stdout");
+    // The pack streams out while git still reads the request, so feed it and
+    // reap it off to the side.
+    tokio::spawn(async move {
+        if let Err(err) = stdin.write_all(&request).await {
+            warn!("git upload-pack stdin: {err}");
+        }
+        drop(stdin);
+        if let Err(err) = child.wait().await {
+            warn!("git upload-pack: {err}");
+        }
+    });
+    Ok(git_response(
+        "application/x-git-upload-pack-result",
+        Body::from_stream(ReaderStream::new(stdout)),
+    ))
+}
+
+fn upload_pack_command(state: &AppState, protocol: Option<&str>) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.args(["upload-pack", "--stateless-rpc"])
+        .current_dir(&state.paths.repo);
+    if let Some(protocol) = protocol {
+        cmd.env("GIT_PROTOCOL", protocol);
+    }
+    cmd
+}
+
+/// The client's `Git-Protocol` header, passed on to git like
+/// `git http-backend` does. Anything but plain `key=value` pairs is dropped.
+fn git_protocol(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("git-protocol")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| {
+            v.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '=' | ':' | '.' | '-' | '_'))
+        })
+        .map(str::to_string)
+}
+
+fn gunzip(body: &[u8]) -> AppResult<Bytes> {
+    let mut out = Vec::new();
+    GzDecoder::new(body)
+        .take(MAX_REQUEST_BYTES as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| AppError::BadRequest("invalid gzip request body".to_string()))?;
+    if out.len() > MAX_REQUEST_BYTES {
+        return Err(AppError::TooLarge(
+            "git request body is too large".to_string(),
+        ));
+    }
+    Ok(Bytes::from(out))
+}
+
+fn git_response(content_type: &'static str, body: Body) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// One pkt-line: four hex digits of length, the length included.
+fn pkt_line(data: &str) -> Vec<u8> {
+    let mut line = format!("{:04x}", data.len() + 4).into_bytes();
+    line.extend(data.as_bytes());
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkt_lines_count_their_own_header() {
+        assert_eq!(
+            pkt_line("# service=git-upload-pack\n"),
+            b"001e# service=git-upload-pack\n"
+        );
+    }
+
+    #[test]
+    fn only_plain_protocol_values_are_forwarded() {
+        let mut headers = HeaderMap::new();
+        headers.insert("git-protocol", "version=2".parse().expect("value"));
+        assert_eq!(git_protocol(&headers).as_deref(), Some("version=2"));
+        headers.insert("git-protocol", "version=2 $(id)".parse().expect("value"));
+        assert_eq!(git_protocol(&headers), None);
+    }
+}
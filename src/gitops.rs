@@ -1,17 +1,33 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader},
     path::Path,
-    process::Command,
+    process::{Command, Stdio},
+    time::Duration,
 };
 
 use fs2::FileExt;
+use tracing::warn;
 
 use crate::{
+    collections,
     config::{PushMode, ServeCmd},
     errors::{AppError, AppResult},
-    types::{GitCommitResult, PasteDraft},
+    journal::{self, Intent, Journal, Stage},
+    store,
+    types::{AppendDraft, CollectionDraft, GitCommitResult, MetaUpdateDraft, PasteDraft},
 };
 
+const LOCK_ATTEMPTS: u32 = 50;
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+/// Requests poll more often than background jobs; they are waiting on a
+/// client.
+const LOCK_POLL: Duration = Duration::from_millis(10);
+/// Prints non-ASCII paths as they are rather than octal-escaped, so they
+/// match the paths in the metas.
+const UNQUOTED_PATHS: &str = "core.quotepath=false";
+
 pub struct FileLock {
     file: File,
 }
@@ -32,6 +48,39 @@ impl FileLock {
             .map_err(|_| AppError::Conflict("already running".to_string()))?;
         Ok(Self { file })
     }
+
+    /// Like [`FileLock::acquire`], but retries for a few seconds so background
+    /// jobs can wait out a request holding the lock. Blocks the thread.
+    pub fn acquire_waiting(path: &Path) -> AppResult<Self> {
+        Self::acquire_within(path, LOCK_RETRY * LOCK_ATTEMPTS)
+    }
+
+    /// Retries [`FileLock::acquire`] until `wait` has passed. Blocks the
+    /// thread.
+    pub fn acquire_within(path: &Path, wait: Duration) -> AppResult<Self> {
+        for _ in 0..(wait.as_millis() / LOCK_RETRY.as_millis()).max(1) {
+            match Self::acquire(path) {
+                Err(AppError::Conflict(_)) => std::thread::sleep(LOCK_RETRY),
+                other => return other,
+            }
+        }
+        Err(AppError::Conflict("git lock busy".to_string()))
+    }
+
+    /// For request handlers: waits up to `wait` for the lock without
+    /// blocking the runtime, then gives up with [`AppError::Busy`].
+    pub async fn acquire_async(path: &Path, wait: Duration) -> AppResult<Self> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            match Self::acquire(path) {
+                Err(AppError::Conflict(_)) if tokio::time::Instant::now() < deadline => {
+                    tokio::time::sleep(LOCK_POLL).await;
+                }
+                Err(AppError::Conflict(_)) => return Err(lock_busy(wait)),
+                other => return other,
+            }
+        }
+    }
 }
 
 impl Drop for FileLock {
@@ -40,15 +89,22 @@ impl Drop for FileLock {
     }
 }
 
+/// What a request gets after waiting `wait` for the git lock in vain.
+pub fn lock_busy(wait: Duration) -> AppError {
+    AppError::Busy(format!(
+        "another write held the git lock for over {}ms; try again",
+        wait.as_millis()
+    ))
+}
+
+/// How to get git, per platform.
+pub const INSTALL_GIT: &str = "Install with: Debian/Ubuntu `sudo apt-get install git`, Fedora `sudo dnf install git`, Arch `sudo pacman -S git`, macOS `xcode-select --install`";
+
 pub fn check_git_installed() -> AppResult<()> {
     let out = Command::new("git")
         .arg("--version")
         .output()
-        .map_err(|_| {
-            AppError::ServiceUnavailable(
-                "git is required. Install with: Debian/Ubuntu `sudo apt-get install git`, Fedora `sudo dnf install git`, Arch `sudo pacman -S git`, macOS `xcode-select --install`".to_string(),
-            )
-        })?;
+        .map_err(|_| AppError::ServiceUnavailable(format!("git is required. {INSTALL_GIT}")))?;
     if out.status.success() {
         Ok(())
     } else {
@@ -93,6 +149,7 @@ pub fn bootstrap_repo(repo: &Path, cfg: &ServeCmd) -> AppResult<()> {
     fs::create_dir_all(repo.join("pastes")).map_err(|e| AppError::io("create pastes", e))?;
     fs::create_dir_all(repo.join("meta")).map_err(|e| AppError::io("create meta", e))?;
     fs::create_dir_all(repo.join("slugs")).map_err(|e| AppError::io("create slugs", e))?;
+    fs::create_dir_all(repo.join("short")).map_err(|e| AppError::io("create short", e))?;
 
     let readme = repo.join("README.md");
     if !readme.exists() {
@@ -122,6 +179,9 @@ pub fn bootstrap_repo(repo: &Path, cfg: &ServeCmd) -> AppResult<()> {
         "",
         "# Rust build artifacts",
         "target/",
+        "",
+        "# pastes keep the extension they were uploaded with, `.log` included",
+        "!/pastes/**",
     ];
 
     let mut content = if gitignore.exists() {
@@ -156,54 +216,320 @@ pub fn commit_paste(
     push_mode: PushMode,
     remote: &str,
 ) -> AppResult<GitCommitResult> {
-    run_git(
+    let mut files = vec![
+        draft.rel_path.as_str(),
+        draft.meta_rel_path.as_str(),
+        draft.slug_rel_path.as_str(),
+    ];
+    files.extend(draft.short_rel_path.as_deref());
+    commit_files(repo, cfg, &files, &draft.subject, push_mode, remote, || {})
+}
+
+pub fn commit_append(
+    repo: &Path,
+    cfg: &ServeCmd,
+    draft: &AppendDraft,
+    push_mode: PushMode,
+    remote: &str,
+) -> AppResult<GitCommitResult> {
+    commit_files(
         repo,
-        &["add", &draft.rel_path, &draft.meta_rel_path, &draft.slug_rel_path],
         cfg,
-    )?;
-    run_git(repo, &["commit", "-m", &draft.subject], cfg)?;
+        &[&draft.rel_path, &draft.meta_rel_path],
+        &draft.subject,
+        push_mode,
+        remote,
+        || {
+            let _ = run_git(repo, &["reset"], cfg);
+            rollback_append(draft);
+        },
+    )
+}
+
+/// Commits rewritten meta files; on failure they are restored.
+pub fn commit_meta_update(
+    repo: &Path,
+    cfg: &ServeCmd,
+    draft: &MetaUpdateDraft,
+    push_mode: PushMode,
+    remote: &str,
+) -> AppResult<GitCommitResult> {
+    let paths: Vec<&str> = draft.files.iter().map(|f| f.rel_path.as_str()).collect();
+    commit_files(repo, cfg, &paths, &draft.subject, push_mode, remote, || {
+        let _ = run_git(repo, &["reset"], cfg);
+        rollback_meta_update(draft);
+    })
+}
+
+/// Commits `rel_paths` and pushes according to `push_mode`. Outside strict
+/// mode a failed commit runs `undo`; strict writes go through the journal
+/// instead, which also rolls back a failed push.
+/// Commits a written collection file; on failure it is restored, or removed
+/// when it was new.
+pub fn commit_collection(
+    repo: &Path,
+    cfg: &ServeCmd,
+    draft: &CollectionDraft,
+    push_mode: PushMode,
+    remote: &str,
+) -> AppResult<GitCommitResult> {
+    commit_files(
+        repo,
+        cfg,
+        &[&draft.rel_path],
+        &draft.subject,
+        push_mode,
+        remote,
+        || {
+            let _ = run_git(repo, &["reset"], cfg);
+            collections::rollback(draft);
+        },
+    )
+}
+
+fn commit_files(
+    repo: &Path,
+    cfg: &ServeCmd,
+    rel_paths: &[&str],
+    subject: &str,
+    push_mode: PushMode,
+    remote: &str,
+    undo: impl FnOnce(),
+) -> AppResult<GitCommitResult> {
+    if push_mode == PushMode::Strict {
+        return commit_strict(repo, cfg, rel_paths, subject, remote);
+    }
+    let mut add = vec!["add", "--"];
+    add.extend_from_slice(rel_paths);
+    let committed =
+        run_git(repo, &add, cfg).and_then(|_| run_git(repo, &["commit", "-m", subject], cfg));
+    if let Err(err) = committed {
+        undo();
+        return Err(err);
+    }
     let commit = run_git(repo, &["rev-parse", "--short=12", "HEAD"], cfg)?;
+    let push_error = match push_mode {
+        PushMode::Off | PushMode::Strict => None,
+        PushMode::BestEffort => run_git(repo, &["push", remote, "HEAD"], cfg)
+            .err()
+            .map(|e| format!("{e:?}")),
+    };
+    Ok(GitCommitResult {
+        commit,
+        pushed: push_mode != PushMode::Off && push_error.is_none(),
+        push_error,
+    })
+}
 
-    match push_mode {
-        PushMode::Off => Ok(GitCommitResult {
-            commit,
-            pushed: false,
-            push_error: None,
-        }),
-        PushMode::BestEffort => {
-            let push_res = run_git(repo, &["push", remote, "HEAD"], cfg);
-            let push_error = push_res.err().map(|e| format!("{e:?}"));
-            Ok(GitCommitResult {
-                commit,
-                pushed: push_error.is_none(),
-                push_error,
-            })
-        }
-        PushMode::Strict => {
-            if let Err(push_err) = run_git(repo, &["push", remote, "HEAD"], cfg) {
-                let _ = run_git(repo, &["reset", "--soft", "HEAD~1"], cfg);
-                let _ = fs::remove_file(&draft.abs_path);
-                let _ = fs::remove_file(&draft.meta_path);
-                let _ = fs::remove_file(&draft.slug_path);
-                let _ = run_git(repo, &["reset"], cfg);
-                return Err(AppError::Internal(format!(
-                    "push failed in strict mode: {push_err:?}"
-                )));
-            }
+fn commit_strict(
+    repo: &Path,
+    cfg: &ServeCmd,
+    rel_paths: &[&str],
+    subject: &str,
+    remote: &str,
+) -> AppResult<GitCommitResult> {
+    let journal = Journal::for_config(cfg);
+    let mut intent = journal.begin(repo, cfg, subject, rel_paths)?;
+    match commit_and_push_journaled(repo, cfg, &journal, &mut intent, remote) {
+        Ok(commit) => {
+            journal.finish(&intent)?;
             Ok(GitCommitResult {
                 commit,
                 pushed: true,
                 push_error: None,
             })
         }
+        Err(err) => {
+            // A rollback that fails here stays in the journal and is
+            // finished by `journal::reconcile` on the next start.
+            if let Err(rollback_err) = journal::roll_back(repo, cfg, &journal, &mut intent) {
+                warn!(
+                    "strict rollback of '{subject}' incomplete, left in journal: {rollback_err:?}"
+                );
+            }
+            Err(err)
+        }
     }
 }
 
-pub fn ready(repo: &Path, _git_lock: &Path, cfg: &ServeCmd) -> AppResult<()> {
-    if !is_git_repo(repo, cfg) {
-        return Err(AppError::ServiceUnavailable("repo not ready".to_string()));
+fn commit_and_push_journaled(
+    repo: &Path,
+    cfg: &ServeCmd,
+    journal: &Journal,
+    intent: &mut Intent,
+    remote: &str,
+) -> AppResult<String> {
+    let mut add = vec!["add", "--"];
+    add.extend(intent.paths.iter().map(String::as_str));
+    run_git(repo, &add, cfg)?;
+    run_git(repo, &["commit", "-m", &intent.subject], cfg)?;
+    intent.commit = Some(run_git(repo, &["rev-parse", "HEAD"], cfg)?);
+    intent.stage = Stage::Committed;
+    journal.record(intent)?;
+    let commit = run_git(repo, &["rev-parse", "--short=12", "HEAD"], cfg)?;
+    run_git(repo, &["push", remote, "HEAD"], cfg).map_err(|push_err| {
+        AppError::Internal(format!("push failed in strict mode: {push_err:?}"))
+    })?;
+    Ok(commit)
+}
+
+/// Removes tracked files in one commit, pushing according to `push_mode`.
+pub fn commit_removal(
+    repo: &Path,
+    cfg: &ServeCmd,
+    rel_paths: &[&str],
+    subject: &str,
+    push_mode: PushMode,
+    remote: &str,
+) -> AppResult<GitCommitResult> {
+    // Untrack and delete separately: plain `git rm` would also prune the
+    // now-empty `meta/` and `slugs/` directories the layout relies on.
+    let mut args = vec!["rm", "-q", "--cached", "--ignore-unmatch", "--"];
+    args.extend_from_slice(rel_paths);
+    run_git(repo, &args, cfg)?;
+    for rel in rel_paths {
+        let _ = fs::remove_file(repo.join(rel));
     }
-    Ok(())
+    run_git(repo, &["commit", "-m", subject], cfg)?;
+    let commit = run_git(repo, &["rev-parse", "--short=12", "HEAD"], cfg)?;
+
+    let push_error = match push_mode {
+        PushMode::Off => None,
+        PushMode::BestEffort | PushMode::Strict => run_git(repo, &["push", remote, "HEAD"], cfg)
+            .err()
+            .map(|e| format!("{e:?}")),
+    };
+    if push_mode == PushMode::Strict
+        && let Some(err) = push_error
+    {
+        return Err(AppError::Internal(format!(
+            "push failed in strict mode: {err}"
+        )));
+    }
+    Ok(GitCommitResult {
+        commit,
+        pushed: push_mode != PushMode::Off && push_error.is_none(),
+        push_error,
+    })
+}
+
+fn rollback_append(draft: &AppendDraft) {
+    if let Some(previous) = &draft.previous_body {
+        let _ = store::replace_file(&draft.abs_path, previous);
+    } else if let Ok(file) = OpenOptions::new().write(true).open(&draft.abs_path) {
+        let _ = file.set_len(draft.previous_len);
+    }
+    let _ = fs::write(&draft.meta_path, &draft.previous_meta);
+}
+
+fn rollback_meta_update(draft: &MetaUpdateDraft) {
+    for file in &draft.files {
+        let _ = fs::write(&file.abs_path, &file.previous);
+    }
+}
+
+/// Whether `commit` is on `cfg.remote`, going by the remote-tracking branches
+/// that `git push` updates. A remote given as a URL has none, so its
+/// commits read as unpushed.
+/// The commit `HEAD` points at, read from `.git` without running git so it
+/// is cheap enough to check on every request. `None` before the first
+/// commit or when the ref cannot be read.
+pub fn head_oid(repo: &Path) -> Option<String> {
+    let git_dir = repo.join(".git");
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let Some(name) = head.trim().strip_prefix("ref: ") else {
+        return Some(head.trim().to_string());
+    };
+    if let Ok(oid) = fs::read_to_string(git_dir.join(name)) {
+        return Some(oid.trim().to_string());
+    }
+    let packed = fs::read_to_string(git_dir.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| {
+        let (oid, refname) = line.split_once(' ')?;
+        (refname == name).then(|| oid.to_string())
+    })
+}
+
+/// Repo-relative paths that differ between two commits, renames as a
+/// delete plus an add.
+pub fn changed_paths(repo: &Path, cfg: &ServeCmd, from: &str, to: &str) -> AppResult<Vec<String>> {
+    let out = run_git(
+        repo,
+        &[
+            "-c",
+            UNQUOTED_PATHS,
+            "diff",
+            "--name-only",
+            "--no-renames",
+            from,
+            to,
+            "--",
+        ],
+        cfg,
+    )?;
+    Ok(out.lines().map(str::to_string).collect())
+}
+
+/// The last commit, abbreviated like [`commit_paste`]'s, that touched each
+/// of `paths`, from one walk of the history that stops as soon as every path
+/// has turned up. Paths never committed are left out.
+pub fn last_commits(repo: &Path, paths: &HashSet<String>) -> AppResult<HashMap<String, String>> {
+    let mut child = Command::new("git")
+        .args([
+            "-c",
+            UNQUOTED_PATHS,
+            "log",
+            "--format=%x00%H",
+            "--name-only",
+            "--no-renames",
+        ])
+        .current_dir(repo)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| AppError::internal(format!("git log failed: {e}")))?;
+    let stdout = child.stdout.take().expect("piped stdout");
+    let mut found = HashMap::new();
+    let mut commit = String::new();
+    for line in BufReader::new(stdout).lines() {
+        let line = line.map_err(|e| AppError::io("read git log", e))?;
+        if let Some(hash) = line.strip_prefix('\0') {
+            commit = hash.chars().take(12).collect();
+        } else if paths.contains(&line) && !found.contains_key(&line) {
+            found.insert(line, commit.clone());
+            if found.len() == paths.len() {
+                break;
+            }
+        }
+    }
+    // Stopping early closes the pipe under git; it is done either way.
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(found)
+}
+
+pub fn is_pushed(repo: &Path, cfg: &ServeCmd, commit: &str) -> bool {
+    if cfg.push == PushMode::Off || commit.is_empty() {
+        return false;
+    }
+    let prefix = format!("{}/", cfg.remote);
+    run_git(repo, &["branch", "-r", "--contains", commit], cfg).is_ok_and(|out| {
+        out.lines()
+            .any(|line| line.trim_start().starts_with(&prefix))
+    })
+}
+
+/// Commits on `HEAD` that no remote-tracking branch of `cfg.remote` has,
+/// i.e. what the next push would send.
+pub fn unpushed_count(repo: &Path, cfg: &ServeCmd) -> AppResult<u64> {
+    let not_on_remote = format!("--remotes={}", cfg.remote);
+    let out = run_git(
+        repo,
+        &["rev-list", "--count", "HEAD", "--not", &not_on_remote],
+        cfg,
+    )?;
+    out.parse()
+        .map_err(|_| AppError::internal(format!("unexpected rev-list output '{out}'")))
 }
 
 #[cfg(test)]
@@ -0,0 +1,507 @@
+//! gRPC API (`serve --grpc-bind`), for services that already speak gRPC and
+//! want pastes pushed to them as a stream. `proto/lanpaste.proto` is the
+//! schema; the messages are written out here with prost, and `build.rs`
+//! generates the tonic service around them.
+//!
+//! Every call goes through the same checks and write path as its HTTP
+//! route, with credentials taken from the request metadata.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+};
+
+use futures_util::{Stream, StreamExt};
+use http::HeaderMap;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::net::TcpListener;
+use tonic::{Code, Request, Response, Status, transport::server::TcpIncoming};
+use tracing::info;
+
+use crate::{
+    auth::{self, Scope},
+    errors::{AppError, AppResult},
+    events::{self, EventKind},
+    gitops,
+    http::{self as web, NewPaste},
+    store,
+    types::{AppState, CreatePasteParams, PasteBody, RecentItem, RecentParams, Visibility},
+};
+
+/// Room for the fields around `content` in a `CreateRequest`.
+const MESSAGE_OVERHEAD: usize = 64 * 1024;
+
+/// The messages of `proto/lanpaste.proto`, and the generated service.
+pub mod proto {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Visibility {
+        Listed = 0,
+        Unlisted = 1,
+        Private = 2,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub content: Vec<u8>,
+        #[prost(string, optional, tag = "2")]
+        pub name: Option<String>,
+        #[prost(string, optional, tag = "3")]
+        pub tag: Option<String>,
+        #[prost(string, optional, tag = "4")]
+        pub msg: Option<String>,
+        #[prost(string, optional, tag = "5")]
+        pub content_type: Option<String>,
+        #[prost(enumeration = "Visibility", tag = "6")]
+        pub visibility: i32,
+        #[prost(string, repeated, tag = "7")]
+        pub allow_keys: Vec<String>,
+        #[prost(string, optional, tag = "8")]
+        pub slug: Option<String>,
+        #[prost(string, optional, tag = "9")]
+        pub supersedes: Option<String>,
+        #[prost(bool, tag = "10")]
+        pub encrypted: bool,
+        #[prost(bool, optional, tag = "11")]
+        pub dedupe: Option<bool>,
+        #[prost(string, optional, tag = "12")]
+        pub lang: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateResponse {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub path: String,
+        #[prost(string, tag = "3")]
+        pub commit: String,
+        #[prost(string, tag = "4")]
+        pub view_url: String,
+        #[prost(string, tag = "5")]
+        pub raw_url: String,
+        #[prost(string, tag = "6")]
+        pub meta_url: String,
+        #[prost(string, optional, tag = "7")]
+        pub short_id: Option<String>,
+        #[prost(enumeration = "Visibility", tag = "8")]
+        pub visibility: i32,
+        #[prost(bool, tag = "9")]
+        pub pushed: bool,
+        #[prost(uint64, tag = "10")]
+        pub size: u64,
+        #[prost(string, optional, tag = "11")]
+        pub expires_at: Option<String>,
+        #[prost(bool, tag = "12")]
+        pub replayed: bool,
+        #[prost(bool, tag = "13")]
+        pub deduplicated: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(bool, tag = "2")]
+        pub include_content: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Paste {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, optional, tag = "2")]
+        pub short_id: Option<String>,
+        #[prost(string, tag = "3")]
+        pub created_at: String,
+        #[prost(string, tag = "4")]
+        pub path: String,
+        #[prost(string, optional, tag = "5")]
+        pub slug: Option<String>,
+        #[prost(string, optional, tag = "6")]
+        pub tag: Option<String>,
+        #[prost(uint64, tag = "7")]
+        pub size: u64,
+        #[prost(string, tag = "8")]
+        pub content_type: String,
+        #[prost(string, tag = "9")]
+        pub commit: String,
+        #[prost(string, tag = "10")]
+        pub sha256: String,
+        #[prost(enumeration = "Visibility", tag = "11")]
+        pub visibility: i32,
+        #[prost(bool, tag = "12")]
+        pub encrypted: bool,
+        #[prost(bool, tag = "13")]
+        pub e2e: bool,
+        #[prost(string, optional, tag = "14")]
+        pub supersedes: Option<String>,
+        #[prost(string, optional, tag = "15")]
+        pub superseded_by: Option<String>,
+        #[prost(bool, tag = "16")]
+        pub pushed: bool,
+        #[prost(string, optional, tag = "17")]
+        pub expires_at: Option<String>,
+        #[prost(bytes = "vec", tag = "18")]
+        pub content: Vec<u8>,
+        #[prost(string, optional, tag = "19")]
+        pub owner: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RecentRequest {
+        #[prost(uint32, optional, tag = "1")]
+        pub n: Option<u32>,
+        #[prost(string, optional, tag = "2")]
+        pub tag: Option<String>,
+        #[prost(bool, tag = "3")]
+        pub superseded: bool,
+        #[prost(string, optional, tag = "4")]
+        pub owner: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RecentResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub items: Vec<PasteSummary>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PasteSummary {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub created_at: String,
+        #[prost(string, tag = "3")]
+        pub path: String,
+        #[prost(string, tag = "4")]
+        pub commit: String,
+        #[prost(string, optional, tag = "5")]
+        pub tag: Option<String>,
+        #[prost(uint64, tag = "6")]
+        pub size: u64,
+        #[prost(string, tag = "7")]
+        pub content_type: String,
+        #[prost(uint64, tag = "8")]
+        pub stars: u64,
+        #[prost(string, optional, tag = "9")]
+        pub owner: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WatchRequest {
+        #[prost(string, optional, tag = "1")]
+        pub tag: Option<String>,
+        #[prost(uint64, optional, tag = "2")]
+        pub since: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PasteEvent {
+        #[prost(uint64, tag = "1")]
+        pub seq: u64,
+        #[prost(enumeration = "paste_event::Kind", tag = "2")]
+        pub kind: i32,
+        #[prost(string, tag = "3")]
+        pub sha256: String,
+        #[prost(message, optional, tag = "4")]
+        pub paste: Option<PasteSummary>,
+    }
+
+    pub mod paste_event {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+        #[repr(i32)]
+        pub enum Kind {
+            Created = 0,
+            Deleted = 1,
+        }
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/lanpaste.v1.Pastes.rs"));
+}
+
+use proto::pastes_server::{Pastes, PastesServer};
+
+pub async fn bind(addr: SocketAddr) -> AppResult<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| AppError::internal(format!("bind gRPC {addr} failed: {e}")))
+}
+
+/// Serves until `stop` completes, then lets open calls finish.
+pub async fn serve(
+    state: Arc<AppState>,
+    listener: TcpListener,
+    stop: impl Future<Output = ()> + Send,
+) -> AppResult<()> {
+    if let Ok(addr) = listener.local_addr() {
+        info!("gRPC listening on {addr}");
+    }
+    // Reloading `max_bytes` still caps creates; only a raise past the
+    // startup value needs a restart to reach gRPC clients.
+    let limit = state.settings.current().max_bytes + MESSAGE_OVERHEAD;
+    let service = PastesServer::new(PasteService { state }).max_decoding_message_size(limit);
+    tonic::transport::Server::builder()
+        .serve_with_incoming_shutdown(service, TcpIncoming::from(listener), stop)
+        .await
+        .map_err(|e| AppError::internal(format!("gRPC server failed: {e}")))
+}
+
+struct PasteService {
+    state: Arc<AppState>,
+}
+
+/// The metadata as HTTP headers, for the checks the HTTP routes share.
+fn headers<T>(request: &Request<T>) -> HeaderMap {
+    request.metadata().clone().into_headers()
+}
+
+fn peer_ip<T>(request: &Request<T>) -> Option<IpAddr> {
+    request.remote_addr().map(|addr| addr.ip().to_canonical())
+}
+
+impl PasteService {
+    /// `--auth-fail-limit` around one call, as `guard_auth` does for HTTP:
+    /// a locked out client is refused, and a call that carried a key or
+    /// token counts as a failure or, once verified, a success.
+    async fn guarded<T>(
+        &self,
+        client: Option<IpAddr>,
+        headers: &HeaderMap,
+        method: &str,
+        call: impl Future<Output = Result<T, Status>>,
+    ) -> Result<T, Status> {
+        let state = &self.state;
+        let Some(client) = client else {
+            return call.await;
+        };
+        if web::auth_banned(state, client).is_some() {
+            return Err(web::locked_out().into());
+        }
+        let Some(reason) = web::credential_in(headers) else {
+            return call.await;
+        };
+        let (result, verified) = auth::watch_verification(call).await;
+        let unauthorized = matches!(&result, Err(status) if status.code() == Code::Unauthenticated);
+        let path = format!("/lanpaste.v1.Pastes/{method}");
+        web::account_attempt(state, client, reason, &path, unauthorized, verified);
+        result
+    }
+}
+
+#[tonic::async_trait]
+impl Pastes for PasteService {
+    async fn create(
+        &self,
+        request: Request<proto::CreateRequest>,
+    ) -> Result<Response<proto::CreateResponse>, Status> {
+        let peer = peer_ip(&request);
+        let headers = headers(&request);
+        self.guarded(peer, &headers, "Create", async {
+            let state = &self.state;
+            let client = peer.ok_or_else(|| Status::internal("peer address unknown"))?;
+            let req = request.into_inner();
+            let visibility = visibility_from_proto(req.visibility());
+            let params = CreatePasteParams {
+                name: req.name,
+                msg: req.msg,
+                tag: req.tag,
+                encrypted: Some(req.encrypted),
+                visibility: Some(visibility),
+                allow_keys: (!req.allow_keys.is_empty()).then(|| req.allow_keys.join(",")),
+                supersedes: req.supersedes,
+                slug: req.slug,
+                dedupe: req.dedupe,
+                lang: req.lang,
+            };
+            let mut create =
+                NewPaste::check(state, client, &headers, params, req.content_type).await?;
+            let _write = state.ops.track_write();
+            if req.content.len() > state.settings.current().max_bytes {
+                return Err(AppError::TooLarge("content exceeds max-bytes".to_string()).into());
+            }
+            if let Some(fingerprint) = create.fingerprint.as_mut() {
+                sha2::Digest::update(fingerprint, &req.content);
+            }
+            let (status, created) = create.commit(state, PasteBody::Bytes(req.content)).await?;
+            Ok(Response::new(proto::CreateResponse {
+                id: created.id,
+                path: created.path,
+                commit: created.commit,
+                view_url: created.view_url,
+                raw_url: created.raw_url,
+                meta_url: created.meta_url,
+                short_id: created.short_id,
+                visibility: visibility_to_proto(created.visibility) as i32,
+                pushed: created.pushed,
+                size: created.size as u64,
+                expires_at: created.expires_at.map(rfc3339),
+                replayed: status == http::StatusCode::OK && !created.deduplicated,
+                deduplicated: created.deduplicated,
+            }))
+        })
+        .await
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::Paste>, Status> {
+        let headers = headers(&request);
+        self.guarded(peer_ip(&request), &headers, "Get", async {
+            let state = &self.state;
+            let req = request.into_inner();
+            let id = store::resolve_short_id(&state.paths.repo, &req.id)?.unwrap_or(req.id);
+            web::check_read_token(state, &headers)?;
+            let meta = web::linked_meta(state, &headers, &id)?;
+            let content = if req.include_content {
+                let bytes = store::read_paste(&state.paths.repo, &meta)?;
+                web::verify_read(state, &meta, &bytes)?;
+                store::unseal(state.cipher.as_ref(), &meta, bytes)?
+            } else {
+                Vec::new()
+            };
+            Ok(Response::new(proto::Paste {
+                expires_at: state
+                    .settings
+                    .current()
+                    .retention()
+                    .expires_at(&meta)
+                    .map(rfc3339),
+                pushed: gitops::is_pushed(&state.paths.repo, &state.cfg, &meta.commit),
+                id: meta.id,
+                short_id: meta.short_id,
+                created_at: rfc3339(meta.created_at),
+                path: meta.path,
+                slug: meta.slug,
+                tag: meta.tag,
+                size: meta.size as u64,
+                content_type: meta.content_type,
+                commit: meta.commit,
+                sha256: meta.sha256,
+                visibility: visibility_to_proto(meta.visibility) as i32,
+                encrypted: meta.encrypted,
+                e2e: meta.e2e,
+                supersedes: meta.supersedes,
+                superseded_by: meta.superseded_by,
+                owner: meta.owner,
+                content,
+            }))
+        })
+        .await
+    }
+
+    async fn recent(
+        &self,
+        request: Request<proto::RecentRequest>,
+    ) -> Result<Response<proto::RecentResponse>, Status> {
+        let headers = headers(&request);
+        self.guarded(peer_ip(&request), &headers, "Recent", async {
+            auth::authorize(&self.state.api_keys, &headers, Scope::RecentRead)?;
+            web::check_read_token(&self.state, &headers)?;
+            let req = request.into_inner();
+            let items = web::recent_items(
+                &self.state,
+                &RecentParams {
+                    n: req.n.map(|n| n as usize),
+                    tag: req.tag,
+                    superseded: Some(req.superseded),
+                    owner: req.owner,
+                },
+            )?;
+            Ok(Response::new(proto::RecentResponse {
+                items: items.into_iter().map(summary).collect(),
+            }))
+        })
+        .await
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<proto::PasteEvent, Status>> + Send>>;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let headers = headers(&request);
+        self.guarded(peer_ip(&request), &headers, "Watch", async {
+            auth::authorize(&self.state.api_keys, &headers, Scope::RecentRead)?;
+            web::check_read_token(&self.state, &headers)?;
+            let req = request.into_inner();
+            let stream = self
+                .state
+                .events
+                .stream(req.since, req.tag)
+                .map(|event| Ok(event_to_proto(event)));
+            Ok(Response::new(Box::pin(stream) as Self::WatchStream))
+        })
+        .await
+    }
+}
+
+impl From<AppError> for Status {
+    fn from(err: AppError) -> Self {
+        match err {
+            AppError::BadRequest(m) => Status::invalid_argument(m),
+            AppError::Unauthorized(m) => Status::unauthenticated(m),
+            AppError::Forbidden(m) => Status::permission_denied(m),
+            AppError::NotFound(m) => Status::not_found(m),
+            AppError::Conflict(m) => Status::already_exists(m),
+            AppError::Locked(m) => Status::failed_precondition(m),
+            AppError::TooLarge(m)
+            | AppError::TooManyRequests(m)
+            | AppError::InsufficientStorage(m) => Status::resource_exhausted(m),
+            AppError::Internal(m) => Status::internal(m),
+            AppError::Integrity(m) => Status::data_loss(m),
+            AppError::ServiceUnavailable(m) | AppError::Busy(m) => Status::unavailable(m),
+        }
+    }
+}
+
+fn rfc3339(at: OffsetDateTime) -> String {
+    at.format(&Rfc3339).unwrap_or_default()
+}
+
+fn visibility_from_proto(visibility: proto::Visibility) -> Visibility {
+    match visibility {
+        proto::Visibility::Listed => Visibility::Listed,
+        proto::Visibility::Unlisted => Visibility::Unlisted,
+        proto::Visibility::Private => Visibility::Private,
+    }
+}
+
+fn visibility_to_proto(visibility: Visibility) -> proto::Visibility {
+    match visibility {
+        Visibility::Listed => proto::Visibility::Listed,
+        Visibility::Unlisted => proto::Visibility::Unlisted,
+        Visibility::Private => proto::Visibility::Private,
+    }
+}
+
+fn summary(item: RecentItem) -> proto::PasteSummary {
+    proto::PasteSummary {
+        id: item.id,
+        created_at: rfc3339(item.created_at),
+        path: item.path,
+        commit: item.commit,
+        tag: item.tag,
+        size: item.size as u64,
+        content_type: item.content_type,
+        stars: item.stars as u64,
+        owner: item.owner,
+    }
+}
+
+fn event_to_proto(event: events::PasteEvent) -> proto::PasteEvent {
+    let kind = match event.kind {
+        EventKind::Created => proto::paste_event::Kind::Created,
+        EventKind::Deleted => proto::paste_event::Kind::Deleted,
+    };
+    proto::PasteEvent {
+        seq: event.seq,
+        kind: kind as i32,
+        sha256: event.sha256,
+        paste: Some(summary(event.paste)),
+    }
+}
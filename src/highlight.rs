@@ -0,0 +1,110 @@
+//! Server-side highlighting of code pastes, for the `lang` hint given on
+//! create (`?lang=rust`). Sniffing a language from content is unreliable,
+//! so only pastes that name one are highlighted.
+//!
+//! Tokens come out as `<span class="hl-...">` named after syntect's scopes
+//! (`hl-keyword`, `hl-string`, ...), which `app.css` colours; no theme is
+//! baked into the HTML.
+
+use std::sync::OnceLock;
+
+use syntect::{
+    html::{ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::{
+    errors::{AppError, AppResult},
+    render,
+};
+
+/// Longest `lang` accepted.
+const MAX_LANG_LEN: usize = 32;
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// The `lang` a client sent, lowercased; `None` when blank. It ends up in a
+/// class attribute, so it is held to letters, digits and `+#._-`.
+pub fn normalize_lang(lang: Option<&str>) -> AppResult<Option<String>> {
+    let Some(lang) = lang.map(str::trim).filter(|l| !l.is_empty()) else {
+        return Ok(None);
+    };
+    let lang = lang.to_ascii_lowercase();
+    let valid = lang.len() <= MAX_LANG_LEN
+        && lang
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'#' | b'.' | b'_' | b'-'));
+    if !valid {
+        return Err(AppError::BadRequest(format!(
+            "invalid lang; use up to {MAX_LANG_LEN} letters, digits, '+', '#', '.', '_' or '-'"
+        )));
+    }
+    Ok(Some(lang))
+}
+
+/// Whether `lang` asks for the paste to be rendered as markdown.
+pub fn is_markdown(lang: &str) -> bool {
+    matches!(lang, "markdown" | "md")
+}
+
+/// `text` as a fenced code block, highlighted when syntect knows `lang` by
+/// name or file extension, and plain escaped text otherwise.
+pub fn render_code(text: &str, lang: &str) -> String {
+    let code = highlight(text, lang).unwrap_or_else(|| render::html_escape(text));
+    format!(
+        "<pre class=\"code\"><code class=\"language-{}\">{code}</code></pre>",
+        render::html_escape(lang)
+    )
+}
+
+fn highlight(text: &str, lang: &str) -> Option<String> {
+    let syntaxes = syntaxes();
+    let syntax = syntaxes.find_syntax_by_token(lang)?;
+    let mut html = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+    for line in LinesWithEndings::from(text) {
+        html.parse_html_for_line_which_includes_newline(line).ok()?;
+    }
+    Some(html.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn langs_are_lowercased_and_checked() {
+        assert_eq!(
+            normalize_lang(Some(" Rust ")).expect("lang").as_deref(),
+            Some("rust")
+        );
+        assert_eq!(
+            normalize_lang(Some("c++")).expect("lang").as_deref(),
+            Some("c++")
+        );
+        assert_eq!(normalize_lang(Some("")).expect("lang"), None);
+        assert!(normalize_lang(Some("rust\" onclick=\"x")).is_err());
+        assert!(normalize_lang(Some(&"a".repeat(MAX_LANG_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn known_languages_get_token_classes() {
+        let html = render_code("fn main() { let s = \"<b>\"; }\n", "rs");
+        assert!(html.starts_with("<pre class=\"code\"><code class=\"language-rs\">"));
+        assert!(html.contains("hl-keyword"), "{html}");
+        assert!(html.contains("&lt;b&gt;"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn unknown_languages_fall_back_to_escaped_text() {
+        assert_eq!(
+            render_code("a < b\n", "klingon"),
+            "<pre class=\"code\"><code class=\"language-klingon\">a &lt; b\n</code></pre>"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    errors::{AppError, AppResult},
+    store,
+};
+
+const REVERSE_DNS_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostRulesFile {
+    /// Static hostname -> addresses table, consulted before reverse DNS.
+    #[serde(default)]
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+    /// Also look up PTR records for clients (forward-confirmed).
+    #[serde(default)]
+    pub reverse_dns: bool,
+    pub rules: Vec<HostRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostRule {
+    pub action: RuleAction,
+    /// Exact hostname, or `*.zone` to match every host under `zone`.
+    pub host: String,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Deny(String),
+    NoMatch,
+}
+
+struct Inner {
+    by_addr: HashMap<IpAddr, Vec<String>>,
+    reverse_dns: bool,
+    rules: Vec<HostRule>,
+    cache: Mutex<HashMap<IpAddr, (Instant, Vec<String>)>>,
+}
+
+#[derive(Clone, Default)]
+pub struct HostRules {
+    inner: Option<Arc<Inner>>,
+}
+
+impl HostRules {
+    pub fn from_file(path: Option<&Path>) -> AppResult<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let raw = fs::read(path).map_err(|e| AppError::io("read host rules file", e))?;
+        let file: HostRulesFile = serde_json::from_slice(&raw)
+            .map_err(|e| AppError::internal(format!("parse host rules file: {e}")))?;
+        Self::from_parts(file)
+    }
+
+    pub fn from_parts(file: HostRulesFile) -> AppResult<Self> {
+        for rule in &file.rules {
+            let pattern = rule.host.strip_prefix("*.").unwrap_or(&rule.host);
+            if pattern.is_empty() || pattern.contains('*') {
+                return Err(AppError::internal(format!(
+                    "invalid host rule pattern '{}'",
+                    rule.host
+                )));
+            }
+        }
+        let mut by_addr: HashMap<IpAddr, Vec<String>> = HashMap::new();
+        for (name, addrs) in file.hosts {
+            for addr in addrs {
+                by_addr
+                    .entry(addr.to_canonical())
+                    .or_default()
+                    .push(normalize(&name));
+            }
+        }
+        Ok(Self {
+            inner: Some(Arc::new(Inner {
+                by_addr,
+                reverse_dns: file.reverse_dns,
+                rules: file.rules,
+                cache: Mutex::new(HashMap::new()),
+            })),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    fn has_allow_rules(&self) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|i| i.rules.iter().any(|r| r.action == RuleAction::Allow))
+    }
+
+    /// Hostnames known for `ip`: static entries first, then a cached,
+    /// forward-confirmed PTR lookup when reverse DNS is on.
+    pub async fn hostnames(&self, ip: IpAddr) -> Vec<String> {
+        let Some(inner) = &self.inner else {
+            return Vec::new();
+        };
+        let ip = ip.to_canonical();
+        let mut names = inner.by_addr.get(&ip).cloned().unwrap_or_default();
+        if !inner.reverse_dns {
+            return names;
+        }
+
+        let cached = inner.cache.lock().ok().and_then(|cache| {
+            cache
+                .get(&ip)
+                .filter(|(at, _)| at.elapsed() < REVERSE_DNS_TTL)
+                .map(|(_, names)| names.clone())
+        });
+        let resolved = match cached {
+            Some(resolved) => resolved,
+            None => {
+                let resolved = reverse_lookup(ip).await;
+                if let Ok(mut cache) = inner.cache.lock() {
+                    cache.insert(ip, (Instant::now(), resolved.clone()));
+                }
+                resolved
+            }
+        };
+        for name in resolved {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// First rule (in file order) matching any of `names` decides.
+    pub fn evaluate(&self, names: &[String]) -> Verdict {
+        let Some(inner) = &self.inner else {
+            return Verdict::NoMatch;
+        };
+        for rule in &inner.rules {
+            if let Some(name) = names.iter().find(|n| host_matches(&rule.host, n)) {
+                return match rule.action {
+                    RuleAction::Allow => Verdict::Allow,
+                    RuleAction::Deny => Verdict::Deny(name.clone()),
+                };
+            }
+        }
+        Verdict::NoMatch
+    }
+}
+
+/// Network policy for writers. Hostname rules are checked first; when none
+/// match, the CIDR allowlist decides. Allow rules make the policy closed even
+/// without `--allow-cidr`.
+pub async fn check_client(rules: &HostRules, allow: &[IpNet], ip: IpAddr) -> AppResult<()> {
+    if rules.enabled() {
+        let names = rules.hostnames(ip).await;
+        match rules.evaluate(&names) {
+            Verdict::Allow => return Ok(()),
+            Verdict::Deny(host) => {
+                return Err(AppError::Forbidden(format!("client host {host} denied")));
+            }
+            Verdict::NoMatch if allow.is_empty() && rules.has_allow_rules() => {
+                return Err(AppError::Forbidden(
+                    "client host not in allowlist".to_string(),
+                ));
+            }
+            Verdict::NoMatch => {}
+        }
+    }
+    store::check_cidr(allow, Some(ip))
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn host_matches(pattern: &str, name: &str) -> bool {
+    let pattern = normalize(pattern);
+    match pattern.strip_prefix("*.") {
+        Some(zone) => name
+            .strip_suffix(zone)
+            .is_some_and(|head| head.ends_with('.') && head.len() > 1),
+        None => pattern == name,
+    }
+}
+
+/// PTR lookup whose answer is only trusted if the name resolves back to `ip`.
+async fn reverse_lookup(ip: IpAddr) -> Vec<String> {
+    let name = match tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip)).await {
+        Ok(Ok(name)) if name.parse::<IpAddr>().is_err() => normalize(&name),
+        _ => return Vec::new(),
+    };
+    let confirmed = tokio::net::lookup_host((name.as_str(), 0))
+        .await
+        .is_ok_and(|mut addrs| addrs.any(|a| a.ip().to_canonical() == ip));
+    if !confirmed {
+        debug!("reverse dns for {ip} ({name}) is not forward-confirmed");
+        return Vec::new();
+    }
+    vec![name]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(json: serde_json::Value) -> HostRules {
+        HostRules::from_parts(serde_json::from_value(json).expect("file")).expect("rules")
+    }
+
+    #[test]
+    fn zone_patterns_match_subdomains_only() {
+        assert!(host_matches("*.build.lan", "ci-01.build.lan"));
+        assert!(host_matches("*.Build.LAN.", "a.b.build.lan"));
+        assert!(!host_matches("*.build.lan", "build.lan"));
+        assert!(!host_matches("*.build.lan", "evilbuild.lan"));
+        assert!(host_matches("printer.lan", "printer.lan"));
+    }
+
+    #[tokio::test]
+    async fn static_hosts_feed_first_match_rules() {
+        let rules = rules(serde_json::json!({
+            "hosts": {
+                "ci-01.build.lan": ["10.0.5.7"],
+                "printer.build.lan": ["10.0.5.9"]
+            },
+            "rules": [
+                { "action": "deny", "host": "printer.build.lan" },
+                { "action": "allow", "host": "*.build.lan" }
+            ]
+        }));
+        let verdict = |ip: &str| {
+            let rules = rules.clone();
+            let ip: IpAddr = ip.parse().expect("ip");
+            async move { rules.evaluate(&rules.hostnames(ip).await) }
+        };
+        assert_eq!(verdict("10.0.5.7").await, Verdict::Allow);
+        assert_eq!(verdict("::ffff:10.0.5.7").await, Verdict::Allow);
+        assert_eq!(
+            verdict("10.0.5.9").await,
+            Verdict::Deny("printer.build.lan".to_string())
+        );
+        assert_eq!(verdict("10.0.5.8").await, Verdict::NoMatch);
+
+        assert!(
+            check_client(&rules, &[], "10.0.5.7".parse().expect("ip"))
+                .await
+                .is_ok()
+        );
+        assert!(
+            check_client(&rules, &[], "10.0.5.8".parse().expect("ip"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_inner_wildcards() {
+        let file: HostRulesFile = serde_json::from_value(serde_json::json!({
+            "rules": [{ "action": "allow", "host": "ci-*.lan" }]
+        }))
+        .expect("file");
+        assert!(HostRules::from_parts(file).is_err());
+    }
+}
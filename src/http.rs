@@ -1,45 +1,263 @@
 use std::{
+    convert::Infallible,
     net::{IpAddr, SocketAddr},
-    sync::Arc,
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
     Router,
     body::Body,
-    extract::{ConnectInfo, Multipart, Path, Query, State},
-    http::{HeaderMap, StatusCode, header},
-    response::{Html, IntoResponse, Response},
+    extract::Request,
+    extract::{
+        ConnectInfo, FromRequestParts, Multipart, Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    handler::Handler,
+    http::{Extensions, HeaderMap, StatusCode, Version, header, request::Parts},
+    middleware::{self, Next},
+    response::{
+        Html, IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, post},
 };
+use futures_util::{StreamExt, future::BoxFuture};
 use serde::{Deserialize, Serialize};
-use tokio::net::TcpListener;
-use tracing::warn;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::broadcast,
+};
+use tokio_util::io::ReaderStream;
+use tower::{Layer, ServiceExt};
+use tower_http::{
+    compression::{CompressionLayer, Predicate, predicate::SizeAbove},
+    decompression::RequestDecompressionLayer,
+};
+use tracing::{info, warn};
+use ulid::Ulid;
 
 use crate::{
+    admin::{self, RecordedError},
+    archive, assets,
     auth::{self, Scope},
-    errors::{AppError, AppResult},
+    authlog::Reason,
+    backup, blobs, collections,
+    config::{self, VerifyMode},
+    cors,
+    crypt::PasteCipher,
+    digest, enroll,
+    errors::{AppError, AppResult, ErrorMessage},
+    events::{EventKind, PasteEvent},
+    export::{self, ExportFormat},
+    gc, githttp,
     gitops::{self, FileLock},
-    render, store,
-    types::{AppState, CreatePasteInput, CreatePasteResponse, IdempotencyRecord, RecentItem},
+    highlight, hostrules, instance, namespaces, pdf, preflight, proxy,
+    ratelimit::Failure,
+    readiness, render,
+    rendercache::CacheKey,
+    security::SecurityHeaders,
+    selftest::SelfTestReport,
+    settings, stars, stats, store, systemd, token,
+    types::{
+        AddToCollection, AppState, AppendResponse, CREATE_RESPONSE_VERSION, Collection,
+        CollectionDraft, CollectionResponse, CreatePasteInput, CreatePasteParams,
+        CreatePasteResponse, IdempotencyFields, IdempotencyRecord, MetaResponse, MineParams,
+        NewCollection, PasteBody, PasteMeta, PasteViews, RecentItem, RecentParams, StagedBody,
+        Visibility, push_mode_label,
+    },
+    views::{self, Hit},
+    webdav,
 };
 
+/// The client's address: the TCP peer, or the address a trusted proxy
+/// forwarded for it (`--trusted-proxies`).
+struct ClientIp(IpAddr);
+
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self(proxy::client_ip(
+            client_ip(peer),
+            &parts.headers,
+            &state.cfg.trusted_proxies,
+        )))
+    }
+}
+
+/// Paste id from the `{id}` path segment. Short ids are swapped for the
+/// full id here, so handlers, leases and kv keys only ever see full ids.
+struct PasteId(String);
+
+impl FromRequestParts<Arc<AppState>> for PasteId {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Path(raw) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        match store::resolve_short_id(&state.paths.repo, &raw) {
+            Ok(full) => Ok(Self(full.unwrap_or(raw))),
+            Err(err) => Err(err.into_response()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PatchPaste {
+    /// `null` clears the link; leaving the field out keeps it.
+    #[serde(default, deserialize_with = "present")]
+    supersedes: Option<Option<String>>,
+}
+
+/// Tells a field sent as `null` (`Some(None)`) apart from a missing one.
+fn present<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(de).map(Some)
+}
+
 #[derive(Debug, Deserialize)]
-struct CreateParams {
-    name: Option<String>,
-    msg: Option<String>,
+struct EventsParams {
+    /// Only events for pastes with this tag.
     tag: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-struct RecentParams {
-    n: Option<usize>,
+struct WsParams {
+    tag: Option<String>,
+    /// Last event seen before reconnecting; buffered events after it are
+    /// sent first.
+    since: Option<u64>,
+}
+
+/// A paste event as sent over `/ws`. There are no SSE ids to carry the
+/// sequence number, so it goes in the message.
+#[derive(Serialize)]
+struct WsEvent<'a> {
+    seq: u64,
+    #[serde(flatten)]
+    event: &'a PasteEvent,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawParams {
+    ct: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportParams {
     tag: Option<String>,
+    collection: Option<String>,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockParams {
+    ttl: Option<String>,
+    holder: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TailParams {
+    lines: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FollowParams {
+    from: Option<u64>,
+    idle: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct DashboardParams {
     n: Option<usize>,
     tag: Option<String>,
+    superseded: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DigestParams {
+    day: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DigestRun {
+    digest: digest::Digest,
+    paste_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchiveParams {
+    older_than_days: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApproveBody {
+    code: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RotateTokenBody {
+    /// The token to replace; defaults to the one the request carries, or
+    /// the only one.
+    replace: Option<String>,
+    /// The new token; a random one when absent.
+    token: Option<String>,
+    /// How long the old token keeps working, e.g. `30m`.
+    grace: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceForm {
+    on: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginForm {
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UiLoginForm {
+    key: String,
+    #[serde(default)]
+    next: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginParams {
+    next: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct StarResponse {
+    id: String,
+    stars: usize,
+    starred: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ViewsResponse {
+    id: String,
+    #[serde(flatten)]
+    views: PasteViews,
 }
 
 #[derive(Debug, Serialize)]
@@ -50,64 +268,609 @@ struct ApiIndex {
 }
 
 const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const LEASE_HEADER: &str = "X-Paste-Lease";
+const PASTE_ID_HEADER: header::HeaderName = header::HeaderName::from_static("x-paste-id");
+const LEASE_DEFAULT_TTL: Duration = Duration::from_secs(120);
+const LEASE_MAX_TTL: Duration = Duration::from_secs(3600);
+const MAX_TAIL_LINES: usize = 10_000;
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const FOLLOW_DEFAULT_IDLE: Duration = Duration::from_secs(300);
+const FOLLOW_MAX_IDLE: Duration = Duration::from_secs(3600);
+const FOLLOW_CHUNK: u64 = 64 * 1024;
 
 pub fn app(state: Arc<AppState>) -> Router {
-    Router::new()
+    let compress = state.cfg.compress;
+    let writes = middleware::from_fn_with_state(state.clone(), limit_writes);
+    // What people browse; `--protect-ui` puts all of it behind `/login`.
+    let pages = Router::new()
         .route("/", get(dashboard))
         .route("/dashboard", get(dashboard))
         .route("/recent", get(dashboard))
-        .route("/api", get(api_index))
-        .route("/api/v1/paste", post(create_paste))
-        .route("/api/v1/upload", post(upload_file))
-        .route("/api/v1/p/{id}", get(get_meta))
-        .route("/api/v1/p/{id}/raw", get(get_raw))
-        .route("/api/v1/recent", get(recent))
+        .route("/new", get(new_paste_page))
+        .route("/ws", get(dashboard_ws))
         .route("/files/{name}", get(get_file))
         .route("/p/{id}/md", get(render_view_markdown))
+        .route("/p/{id}/qr", get(render_qr))
+        .route("/p/{id}/print", get(render_print))
+        .route("/p/{id}/pdf", get(render_pdf))
+        .route("/p/{id}/raw.txt", get(render_plain))
         .route("/p/{id}/{slug}", get(render_view_with_slug))
         .route("/p/{id}", get(render_view))
+        .route("/s/{slug}", get(resolve_vanity_slug))
+        .route("/c/{id}", get(render_collection));
+    let pages = if state.cfg.protect_ui {
+        pages.route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_ui_login,
+        ))
+    } else {
+        pages
+    };
+    // API reads; `--require-token-for-read` makes them take the write token.
+    let reads = Router::new()
+        .route("/api", get(api_index))
+        .route(
+            "/api/v1/p/{id}",
+            get(get_meta).patch(patch_paste.layer(writes.clone())),
+        )
+        .route("/api/v1/p/{id}/raw", get(get_raw))
+        .route("/api/v1/p/{id}/raw/{filename}", get(get_raw_named))
+        .route("/api/v1/p/{id}/stats", get(get_views))
+        .route("/api/v1/blob/{sha256}", get(get_blob))
+        .route("/api/v1/p/{id}/tail", get(get_tail))
+        .route("/api/v1/p/{id}/follow", get(follow_paste))
+        .route("/api/v1/recent", get(recent))
+        .route("/api/v1/events", get(paste_events))
+        .route("/api/v1/stars", get(my_stars))
+        .route("/api/v1/mine", get(my_pastes))
+        .route("/api/v1/collections/{id}", get(get_collection))
+        .route("/api/v1/export", get(export_pastes))
+        .route("/api/v1/stats", get(server_stats));
+    let reads = if state.cfg.require_token_for_read {
+        reads.route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_read_token,
+        ))
+    } else {
+        reads
+    };
+    let routes = Router::new()
+        .route("/api/docs", get(api_docs))
+        .route("/openapi.yaml", get(openapi_spec))
+        .route(
+            "/api/v1/paste",
+            post(create_paste)
+                .layer(
+                    RequestDecompressionLayer::new()
+                        .gzip(true)
+                        .zstd(true)
+                        .no_br()
+                        .no_deflate(),
+                )
+                .layer(writes.clone()),
+        )
+        .route("/api/v1/upload", post(upload_file).layer(writes.clone()))
+        .route(
+            "/api/v1/p/{id}/append",
+            post(append_paste).layer(writes.clone()),
+        )
+        .route(
+            "/api/v1/p/{id}/lock",
+            post(acquire_lock).delete(release_lock),
+        )
+        .route("/api/v1/p/{id}/star", post(star_paste).delete(unstar_paste))
+        .route(
+            "/api/v1/collections",
+            post(create_collection).layer(writes.clone()),
+        )
+        .route(
+            "/api/v1/collections/{id}/pastes",
+            post(add_to_collection).layer(writes),
+        )
+        .route("/api/v1/enroll", post(enroll_request))
+        .route("/api/v1/enroll/{id}", get(enroll_poll))
+        .route("/api/v1/admin/enrollments", get(admin_enrollments))
+        .route(
+            "/api/v1/admin/enrollments/approve",
+            post(admin_approve_enrollment),
+        )
+        .route(
+            "/api/v1/admin/enrollments/{id}/reject",
+            post(admin_reject_enrollment),
+        )
+        .route("/api/v1/admin/status", get(admin_status))
+        .route("/api/v1/admin/keys", get(admin_keys))
+        .route("/api/v1/admin/keys/{name}/disable", post(admin_disable_key))
+        .route("/api/v1/admin/keys/{name}/enable", post(admin_enable_key))
+        .route("/api/v1/admin/token/rotate", post(admin_rotate_token))
+        .route(
+            "/api/v1/admin/digest",
+            get(admin_digest_export).post(admin_digest_run),
+        )
+        .route("/api/v1/admin/archive", post(admin_archive))
+        .route("/api/v1/admin/gc", post(admin_gc))
+        .route("/api/v1/admin/reload", post(admin_reload))
+        .route(
+            "/api/v1/admin/backup",
+            get(admin_backup_status).post(admin_backup_run),
+        )
+        .route("/admin", get(admin_dashboard))
+        .route("/admin/reload-keys", post(admin_reload_keys))
+        .route("/admin/maintenance", post(admin_maintenance))
+        .route("/admin/sync", post(admin_sync))
+        .route("/admin/login", get(admin_login_page).post(admin_login))
+        .route("/admin/logout", post(admin_logout))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route(
+            "/admin/enroll",
+            get(admin_enroll_page).post(admin_enroll_approve),
+        )
+        .route("/admin/enroll/{id}/reject", post(admin_enroll_reject))
+        .route("/assets/{file}", get(get_asset))
         .route("/healthz", get(healthz))
         .route("/readyz", get(readyz))
-        .layer(axum::extract::DefaultBodyLimit::max(state.cfg.max_bytes))
-        .with_state(state)
+        .route("/statusz", get(statusz))
+        .merge(reads)
+        .merge(pages);
+    let routes = if state.cfg.webdav {
+        routes.merge(webdav::routes())
+    } else {
+        routes
+    };
+    let routes = if state.cfg.git_http {
+        routes.merge(githttp::routes())
+    } else {
+        routes
+    };
+    let mounts = state.namespaces.clone();
+    let html_headers = Arc::new(SecurityHeaders::from_config(&state.cfg));
+    let router = routes
+        .layer(middleware::from_fn_with_state(
+            html_headers,
+            security_headers,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            live_body_limit,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), limit_keyless))
+        .layer(middleware::from_fn_with_state(state.clone(), guard_auth))
+        .layer(middleware::from_fn_with_state(state.clone(), record_errors))
+        .layer(middleware::from_fn_with_state(state.clone(), shed_load));
+    // Outermost, so preflights are answered before auth and limits run.
+    let router = match cors::layer(&state.cfg) {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+    // Outside even that: a rebound name gets nothing, preflights included.
+    let router = if state.cfg.allowed_hosts.is_empty() {
+        router
+    } else {
+        router.layer(middleware::from_fn_with_state(state.clone(), check_host))
+    }
+    .with_state(state);
+    let router = if compress {
+        router.layer(CompressionLayer::new().compress_when(SizeAbove::new(256).and(compressible)))
+    } else {
+        router
+    };
+    // Each namespace brings its own state, limits and layers.
+    mounts.into_iter().fold(router, |router, (name, ns)| {
+        router.nest(&namespaces::mount_path(&name), app(ns))
+    })
 }
 
-pub async fn run_server(state: Arc<AppState>) -> AppResult<()> {
-    let listener = TcpListener::bind(state.cfg.bind)
+/// `--allowed-hosts`: the request must name one of them, in its `Host`
+/// header or, over HTTP/2, its URI. A page on a name rebound to this
+/// server's address sends that name, and is turned away.
+async fn check_host(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let authority = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().authority().map(|a| a.as_str()))
+        .unwrap_or_default();
+    if state
+        .cfg
+        .allowed_hosts
+        .iter()
+        .any(|allowed| allowed.matches(authority))
+    {
+        return next.run(req).await;
+    }
+    AppError::BadRequest("Host header names a host this server does not answer to".to_string())
+        .into_response()
+}
+
+/// `DefaultBodyLimit` with the `max_bytes` in effect for this request, so a
+/// settings reload applies to the next upload.
+async fn live_body_limit(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let limit = state.settings.current().max_bytes;
+    let inner = tower::service_fn(move |req| {
+        let next = next.clone();
+        async move { Ok::<_, Infallible>(next.run(req).await) }
+    });
+    match axum::extract::DefaultBodyLimit::max(limit)
+        .layer(inner)
+        .oneshot(req)
         .await
-        .map_err(|e| AppError::internal(format!("bind failed: {e}")))?;
-    axum::serve(
-        listener,
-        app(state).into_make_service_with_connect_info::<SocketAddr>(),
+    {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+/// CSP, framing and referrer headers on HTML pages.
+async fn security_headers(
+    State(headers): State<Arc<SecurityHeaders>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(req).await;
+    headers.apply(&mut response);
+    response
+}
+
+/// `--max-in-flight`: requests beyond the cap are turned away with `503`
+/// rather than queued. A streamed response gives its slot back once the
+/// handler returns, so `follow`, events and sockets do not hold one.
+async fn shed_load(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(slots) = &state.limits.in_flight else {
+        return next.run(req).await;
+    };
+    if unmetered(req.uri().path()) {
+        return next.run(req).await;
+    }
+    match slots.clone().try_acquire_owned() {
+        Ok(_slot) => next.run(req).await,
+        Err(_) => retry_later(
+            AppError::ServiceUnavailable("server busy; try again shortly".to_string()),
+            Duration::from_secs(1),
+        ),
+    }
+}
+
+/// `--ip-rate` for requests no API key accounts for: shared-token writes and
+/// anonymous reads. Keyed requests have their own limit.
+async fn limit_keyless(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    let live = state.settings.current();
+    let keyed = state.api_keys.enabled()
+        && (req.headers().contains_key(auth::API_KEY_HEADER)
+            || req.headers().contains_key(header::AUTHORIZATION));
+    let (Some(rate), false, false) = (live.ip_rate, keyed, unmetered(req.uri().path())) else {
+        return next.run(req).await;
+    };
+    let now = std::time::Instant::now();
+    match state.limits.clients.check(client, rate, live.ip_burst, now) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => retry_later(
+            AppError::TooManyRequests("rate limit exceeded for this client".to_string()),
+            wait,
+        ),
+    }
+}
+
+/// `--auth-fail-limit`: a client that keeps presenting bad credentials is
+/// locked out for a while, so a device on the LAN cannot grind through
+/// tokens unnoticed. Only requests that carried a token, key or login form
+/// count; a missing session cookie is not a guess.
+async fn guard_auth(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    if state.cfg.auth_fail_limit == 0 || unmetered(req.uri().path()) {
+        return next.run(req).await;
+    }
+    if let Some(wait) = auth_banned(&state, client) {
+        return retry_later(locked_out(), wait);
+    }
+    let Some(reason) = attempted_credential(&req) else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path().to_string();
+    let (response, verified) = auth::watch_verification(next.run(req)).await;
+    let unauthorized = response.status() == StatusCode::UNAUTHORIZED;
+    account_attempt(&state, client, reason, &path, unauthorized, verified);
+    response
+}
+
+/// How long `client` is still locked out by `--auth-fail-limit`, if it is.
+pub(crate) fn auth_banned(state: &AppState, client: IpAddr) -> Option<Duration> {
+    if state.cfg.auth_fail_limit == 0 {
+        return None;
+    }
+    state
+        .limits
+        .auth_failures
+        .banned(client, std::time::Instant::now())
+}
+
+pub(crate) fn locked_out() -> AppError {
+    AppError::TooManyRequests(
+        "too many failed authentication attempts; try again later".to_string(),
     )
-    .await
-    .map_err(|e| AppError::internal(format!("server failed: {e}")))
 }
 
+/// Counts a finished attempt that carried a credential: a failure when it
+/// was refused as unauthenticated, a success only when the credential was
+/// verified. Anything else leaves the count to run out with its window.
+pub(crate) fn account_attempt(
+    state: &AppState,
+    client: IpAddr,
+    reason: Reason,
+    path: &str,
+    unauthorized: bool,
+    verified: bool,
+) {
+    let limit = state.cfg.auth_fail_limit;
+    if limit == 0 {
+        return;
+    }
+    let failures = &state.limits.auth_failures;
+    if unauthorized {
+        state.auth_log.failure(client, reason, path);
+        let now = std::time::Instant::now();
+        if let Failure::Banned { ban } = failures.fail(client, limit, state.cfg.auth_ban, now) {
+            state.auth_log.lockout(client, ban);
+        }
+    } else if verified {
+        failures.succeed(client);
+    }
+}
+
+/// The credential a request carries, if any; the log names it when the
+/// request fails.
+fn attempted_credential(req: &Request) -> Option<Reason> {
+    credential_in(req.headers()).or_else(|| {
+        (req.method() == axum::http::Method::POST && req.uri().path().ends_with("/login"))
+            .then_some(Reason::BadLogin)
+    })
+}
+
+/// The credential header a request or gRPC call carries, if any.
+pub(crate) fn credential_in(headers: &HeaderMap) -> Option<Reason> {
+    if headers.contains_key(auth::API_KEY_HEADER) {
+        Some(Reason::BadApiKey)
+    } else if headers.contains_key("X-Paste-Token") {
+        Some(Reason::BadToken)
+    } else if headers.contains_key(header::AUTHORIZATION) {
+        Some(Reason::BadBasic)
+    } else {
+        None
+    }
+}
+
+/// In front of the routes that commit: `--min-free-bytes`, so a full disk
+/// is a `507` before the body is read rather than a git error halfway
+/// through, then `--write-rate`, shared by every client.
+async fn limit_writes(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    if let Err(err) = preflight::check_free_space(&state.paths.base, state.cfg.min_free_bytes) {
+        return err.into_response();
+    }
+    let live = state.settings.current();
+    let Some(rate) = live.write_rate else {
+        return next.run(req).await;
+    };
+    match state
+        .limits
+        .writes
+        .check((), rate, live.write_burst, std::time::Instant::now())
+    {
+        Ok(()) => next.run(req).await,
+        Err(wait) => retry_later(
+            AppError::TooManyRequests("server-wide write rate exceeded".to_string()),
+            wait,
+        ),
+    }
+}
+
+/// Probes and bundled assets, which no limit holds back.
+fn unmetered(path: &str) -> bool {
+    matches!(path, "/healthz" | "/readyz" | "/statusz") || path.starts_with("/assets/")
+}
+
+/// `err` with `Retry-After` in whole seconds, rounded up.
+fn retry_later(err: AppError, wait: Duration) -> Response {
+    let mut response = err.into_response();
+    let secs = (wait.as_secs_f64().ceil() as u64).max(1);
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, secs.into());
+    response
+}
+
+/// Only HTML, JSON and text bodies are worth compressing. Partial content and
+/// live streams (marked `X-Accel-Buffering: no`) pass through untouched so
+/// ranges and `follow` keep their byte offsets and latency.
+fn compressible(
+    status: StatusCode,
+    _version: Version,
+    headers: &HeaderMap,
+    _extensions: &Extensions,
+) -> bool {
+    if status == StatusCode::PARTIAL_CONTENT
+        || headers.get("X-Accel-Buffering").is_some_and(|v| v == "no")
+    {
+        return false;
+    }
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/") || essence == "application/json"
+}
+
+/// How long a shutdown waits for requests in flight, such as a paste being
+/// committed. Streams that never end (`follow`, events) are cut off after it.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Serves on the sockets systemd passed when socket activated, else on
+/// every `--bind`, until `SIGTERM` or Ctrl-C.
+pub async fn run_server(state: Arc<AppState>) -> AppResult<()> {
+    let activated = systemd::listen_fds()?;
+    let listeners = if activated.is_empty() {
+        let mut listeners = Vec::with_capacity(state.cfg.bind.len());
+        for &addr in &state.cfg.bind {
+            listeners.push(
+                TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| AppError::internal(format!("bind {addr} failed: {e}")))?,
+            );
+        }
+        listeners
+    } else {
+        activated
+            .into_iter()
+            .map(TcpListener::from_std)
+            .collect::<Result<_, _>>()
+            .map_err(|e| AppError::io("register activated socket", e))?
+    };
+    let bound = listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::io("read bound address", e))?;
+    #[cfg(feature = "grpc")]
+    let grpc = match state.cfg.grpc_bind {
+        Some(addr) => Some(crate::grpc::bind(addr).await?),
+        None => None,
+    };
+    #[cfg(not(feature = "grpc"))]
+    if state.cfg.grpc_bind.is_some() {
+        return Err(AppError::BadRequest(
+            "--grpc-bind: this build has no gRPC support (cargo feature `grpc`)".to_string(),
+        ));
+    }
+    let shutdown = shutdown_signal();
+    instance::announce(&state, &bound)?;
+    let addrs: Vec<String> = bound.iter().map(ToString::to_string).collect();
+    systemd::notify(&format!("READY=1\nSTATUS=serving on {}", addrs.join(", ")));
+
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let stop_requested = move || {
+        let mut stopped = stopped.clone();
+        async move {
+            let _ = stopped.wait_for(|stop| *stop).await;
+        }
+    };
+    let service = app(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+    let mut servers: Vec<BoxFuture<'static, AppResult<()>>> = Vec::new();
+    #[cfg(feature = "grpc")]
+    if let Some(listener) = grpc {
+        let serve = crate::grpc::serve(state.clone(), listener, stop_requested());
+        servers.push(Box::pin(serve));
+    }
+    servers.extend(listeners.into_iter().map(|listener| {
+        let serve = axum::serve(listener, service.clone())
+            .with_graceful_shutdown(stop_requested())
+            .into_future();
+        Box::pin(async move {
+            serve
+                .await
+                .map_err(|e| AppError::internal(format!("server failed: {e}")))
+        }) as BoxFuture<'static, _>
+    }));
+    let servers = futures_util::future::try_join_all(servers);
+    tokio::pin!(servers);
+    tokio::select! {
+        served = &mut servers => {
+            served?;
+        }
+        () = shutdown => {
+            systemd::notify("STOPPING=1");
+            info!("shutting down; waiting up to {}s for open requests", SHUTDOWN_GRACE.as_secs());
+            let _ = stop.send(true);
+            if tokio::time::timeout(SHUTDOWN_GRACE, servers).await.is_err() {
+                warn!("closing connections still open after {}s", SHUTDOWN_GRACE.as_secs());
+            }
+        }
+    }
+    instance::retire(&state);
+    Ok(())
+}
+
+/// `SIGTERM`, as sent by `systemctl stop` and most supervisors, or Ctrl-C.
+/// The handlers are installed before this returns, so a signal sent right
+/// after the server reports ready is not missed.
+fn shutdown_signal() -> impl Future<Output = ()> {
+    #[cfg(unix)]
+    let handlers = {
+        use tokio::signal::unix::{SignalKind, signal};
+        signal(SignalKind::terminate())
+            .and_then(|term| Ok((term, signal(SignalKind::interrupt())?)))
+    };
+    async move {
+        #[cfg(unix)]
+        match handlers {
+            Ok((mut term, mut interrupt)) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = interrupt.recv() => {}
+                }
+                return;
+            }
+            Err(err) => warn!("SIGTERM handling disabled: {err}"),
+        }
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Served from the in-memory index, so a page left open and polling costs
+/// no git work unless the repo moved, and an unchanged one only a `304`.
 async fn dashboard(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Query(params): Query<DashboardParams>,
-) -> AppResult<impl IntoResponse> {
+) -> Response {
+    state.index.sync(&state.paths.repo, &state.cfg);
+    let validators = Validators {
+        etag: state.index.etag(),
+        last_modified: None,
+    };
+    if validators.is_fresh(&headers) {
+        return validators.not_modified();
+    }
     let n = params.n.unwrap_or(50).min(500);
-    let (list, tags) =
-        store::read_recent_with_tags(&state.paths.repo, &state.cfg, n, params.tag.as_deref())?;
-    let out: Vec<RecentItem> = list
-        .into_iter()
-        .map(|m| RecentItem {
-            id: m.id,
-            created_at: m.created_at,
-            path: m.path,
-            commit: m.commit,
-            tag: m.tag,
-            size: m.size,
-            content_type: m.content_type,
-        })
-        .collect();
-    Ok(Html(render::render_dashboard(
-        &out,
+    let (items, tags) =
+        state
+            .index
+            .recent(n, params.tag.as_deref(), params.superseded.unwrap_or(false));
+    let mut response = Html(render::render_dashboard(
+        &state.cfg.base_path,
+        &items,
         &tags,
         params.tag.as_deref(),
-    )))
+    ))
+    .into_response();
+    validators.apply(&mut response);
+    response
+}
+
+async fn new_paste_page(State(state): State<Arc<AppState>>) -> Html<String> {
+    let auth_header = if state.api_keys.enabled() {
+        Some(auth::API_KEY_HEADER)
+    } else if state.token.is_set() {
+        Some("X-Paste-Token")
+    } else {
+        None
+    };
+    Html(render::render_new_paste(&state.cfg.base_path, auth_header))
 }
 
 async fn api_index(
@@ -123,73 +886,251 @@ async fn api_index(
             "/api/v1/upload (POST)",
             "/api/v1/p/{id} (GET)",
             "/api/v1/p/{id}/raw (GET)",
+            "/api/v1/p/{id}/raw/{filename} (GET)",
+            "/api/v1/p/{id}/stats (GET)",
+            "/api/v1/blob/{sha256} (GET)",
+            "/api/v1/p/{id}/tail?lines=200 (GET)",
+            "/api/v1/p/{id}/follow?from=0 (GET, streaming)",
+            "/api/v1/p/{id}/append (POST)",
+            "/api/v1/p/{id}/lock?ttl=2m (POST, DELETE)",
             "/api/v1/recent?n=50&tag=... (GET)",
+            "/api/v1/events?tag=... (GET, server-sent events)",
+            "/api/v1/p/{id}/star (POST, DELETE)",
+            "/api/v1/stars (GET)",
+            "/api/v1/mine?n=50 (GET)",
+            "/api/v1/collections (POST)",
+            "/api/v1/collections/{id} (GET)",
+            "/api/v1/collections/{id}/pastes (POST)",
+            "/api/v1/export?tag=...|collection=...&format=zip|tar.gz (GET)",
+            "/api/v1/stats (GET)",
+            "/api/v1/enroll (POST)",
+            "/api/v1/enroll/{id} (GET)",
             "/files/{name} (GET)",
+            "/openapi.yaml (GET)",
+            "/api/docs (GET, HTML console)",
         ],
     }))
 }
 
+/// The spec the contract tests check, compiled in so a running server
+/// describes itself.
+const OPENAPI_SPEC: &str = include_str!("../openapi.yaml");
+
+async fn openapi_spec() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "application/yaml; charset=utf-8"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        OPENAPI_SPEC,
+    )
+}
+
+async fn api_docs(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Html<String> {
+    let server_url = format!(
+        "{}{}",
+        request_origin(&state, &headers),
+        state.cfg.base_path
+    );
+    Html(render::render_api_docs(&state.cfg.base_path, &server_url))
+}
+
 async fn create_paste(
     State(state): State<Arc<AppState>>,
-    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
-    Query(params): Query<CreateParams>,
+    ClientIp(client): ClientIp,
+    Query(params): Query<CreatePasteParams>,
     headers: HeaderMap,
-    body: axum::body::Bytes,
+    body: Body,
 ) -> AppResult<impl IntoResponse> {
-    authorize_create(&state, &headers)?;
-
-    let ip = Some(client_ip(ConnectInfo(remote_addr)));
-    store::check_cidr(&state.cfg.allow_cidr, ip)?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let mut create = NewPaste::check(&state, client, &headers, params, content_type).await?;
+    let _write = state.ops.track_write();
 
-    if body.len() > state.cfg.max_bytes {
+    let declared_len = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared_len.is_some_and(|len| len > state.settings.current().max_bytes) {
         return Err(AppError::TooLarge(
             "request body exceeds max-bytes".to_string(),
         ));
     }
+    let staged = stage_body(&state, body, create.fingerprint.as_mut()).await?;
+    let (status, resp) = create.commit(&state, PasteBody::Staged(staged)).await?;
+    Ok((status, axum::Json(resp)))
+}
 
-    let content_type = headers
-        .get(header::CONTENT_TYPE)
-        .and_then(|v| v.to_str().ok())
-        .map(ToString::to_string);
-    let user_agent = headers
-        .get(header::USER_AGENT)
-        .and_then(|v| v.to_str().ok())
-        .map(ToString::to_string);
+/// A create whose caller and options have been checked, waiting for its
+/// body. The HTTP and gRPC front ends both go through it.
+pub(crate) struct NewPaste {
+    params: CreatePasteParams,
+    content_type: Option<String>,
+    user_agent: Option<String>,
+    client_ip: IpAddr,
+    e2e: bool,
+    visibility: Visibility,
+    allow_keys: Vec<String>,
+    owner: Option<String>,
+    lang: Option<String>,
+    /// Look for an identical paste before committing.
+    dedupe: bool,
+    idempotency_key: Option<String>,
+    /// Hash of the options so far; feed it the body as it arrives.
+    pub(crate) fingerprint: Option<Sha256>,
+}
 
-    let input = CreatePasteInput {
-        name: params.name,
-        msg: params.msg,
-        tag: params.tag,
-        content_type,
-        bytes: body.to_vec(),
-        client_ip: ip,
-        user_agent,
-    };
+impl NewPaste {
+    pub(crate) async fn check(
+        state: &AppState,
+        client: IpAddr,
+        headers: &HeaderMap,
+        params: CreatePasteParams,
+        content_type: Option<String>,
+    ) -> AppResult<Self> {
+        let owner = authorize_create(state, headers)?;
+        check_client(state, client).await?;
+        let e2e = params.encrypted.unwrap_or(false);
+        let visibility = params.visibility.unwrap_or_default();
+        let allow_keys = allow_keys(state, visibility, params.allow_keys.as_deref())?;
+        let lang = highlight::normalize_lang(params.lang.as_deref())?;
+        if let Some(old) = params.supersedes.as_deref() {
+            store::read_meta(&state.paths.repo, &state.cfg, old).map_err(|err| match err {
+                AppError::NotFound(_) => {
+                    AppError::BadRequest(format!("supersedes: paste {old} not found"))
+                }
+                other => other,
+            })?;
+        }
+        // A paste that replaces another, or claims a slug, is new by
+        // definition. Pastes sealed with the server key hash their
+        // ciphertext, which never repeats.
+        let dedupe = params.dedupe.unwrap_or(state.cfg.dedupe)
+            && params.supersedes.is_none()
+            && params.slug.is_none()
+            && (e2e || state.cipher.is_none());
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        let idempotency_key = headers
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(ToString::to_string);
+        let fingerprint = idempotency_key.as_ref().map(|_| {
+            store::idempotency_hasher(&IdempotencyFields {
+                name: params.name.as_deref(),
+                msg: params.msg.as_deref(),
+                tag: params.tag.as_deref(),
+                content_type: content_type.as_deref(),
+                e2e,
+                visibility,
+                allow_keys: &allow_keys,
+                supersedes: params.supersedes.as_deref(),
+                slug: params.slug.as_deref(),
+                lang: lang.as_deref(),
+            })
+        });
+        Ok(Self {
+            params,
+            content_type,
+            user_agent,
+            client_ip: client,
+            e2e,
+            visibility,
+            allow_keys,
+            owner,
+            lang,
+            dedupe,
+            idempotency_key,
+            fingerprint,
+        })
+    }
 
-    let idempotency_key = headers
-        .get(IDEMPOTENCY_KEY_HEADER)
-        .and_then(|v| v.to_str().ok())
-        .map(str::trim)
-        .filter(|v| !v.is_empty())
-        .map(ToString::to_string);
+    /// Queues the commit. `201 Created`, or `200 OK` for an idempotent
+    /// replay or a deduplicated upload.
+    pub(crate) async fn commit(
+        self,
+        state: &Arc<AppState>,
+        body: PasteBody,
+    ) -> AppResult<(StatusCode, CreatePasteResponse)> {
+        let request_fingerprint = self.fingerprint.map(|h| hex::encode(h.finalize()));
+        let params = self.params;
+        let input = CreatePasteInput {
+            name: params.name,
+            msg: params.msg,
+            tag: params.tag,
+            content_type: self.content_type,
+            body,
+            e2e: self.e2e,
+            visibility: self.visibility,
+            allow_keys: self.allow_keys,
+            slug: params.slug,
+            client_ip: Some(self.client_ip),
+            user_agent: self.user_agent,
+            owner: self.owner,
+            lang: self.lang,
+        };
+        let supersedes = params.supersedes;
+        let idempotency_key = self.idempotency_key;
+        let dedupe = self.dedupe;
+        let job = {
+            let state = state.clone();
+            move || {
+                commit_new_paste(
+                    &state,
+                    input,
+                    supersedes.as_deref(),
+                    idempotency_key.as_deref().zip(request_fingerprint),
+                    dedupe,
+                )
+            }
+        };
+        state.committer.run(job).await
+    }
+}
 
-    let _git_lock = FileLock::acquire(&state.paths.git_lock)?;
-    let request_fingerprint = idempotency_key
-        .as_deref()
-        .map(|_| store::idempotency_fingerprint(&input));
-    if let (Some(key), Some(fingerprint)) =
-        (idempotency_key.as_deref(), request_fingerprint.as_deref())
-        && let Some(record) = store::read_idempotency_record(&state.paths.idempotency, key)?
+/// The part of a create that runs on the commit thread: the idempotency
+/// lookup has to happen there too, or two retries queued back to back would
+/// both commit.
+fn commit_new_paste(
+    state: &AppState,
+    input: CreatePasteInput,
+    supersedes: Option<&str>,
+    idempotency: Option<(&str, String)>,
+    dedupe: bool,
+) -> AppResult<(StatusCode, CreatePasteResponse)> {
+    if let Some((key, fingerprint)) = &idempotency
+        && let Some(record) = store::read_idempotency_record(&state.kv, key)?
     {
-        if record.request_fingerprint != fingerprint {
+        if record.request_fingerprint != *fingerprint {
             return Err(AppError::Conflict(
                 "idempotency key reuse with different payload".to_string(),
             ));
         }
-        return Ok((StatusCode::OK, axum::Json(record.response)));
+        let mut response = record.response;
+        if response.version < CREATE_RESPONSE_VERSION {
+            let meta = store::read_meta(&state.paths.repo, &state.cfg, &response.id)?;
+            let pushed = gitops::is_pushed(&state.paths.repo, &state.cfg, &meta.commit);
+            response = create_response(state, &meta, response.commit, pushed);
+        }
+        return Ok((StatusCode::OK, response));
     }
 
-    let draft = store::build_paste_draft(&state.paths.repo, &state.cfg, input)?;
+    if dedupe && let Some(meta) = find_duplicate(state, &input)? {
+        let pushed = gitops::is_pushed(&state.paths.repo, &state.cfg, &meta.commit);
+        let mut resp = create_response(state, &meta, meta.commit.clone(), pushed);
+        resp.deduplicated = true;
+        remember_response(state, idempotency, &resp)?;
+        return Ok((StatusCode::OK, resp));
+    }
+
+    let draft =
+        store::build_paste_draft(&state.paths.repo, &state.cfg, state.cipher.as_ref(), input)?;
     let commit = gitops::commit_paste(
         &state.paths.repo,
         &state.cfg,
@@ -197,46 +1138,379 @@ async fn create_paste(
         state.cfg.push,
         &state.cfg.remote,
     )?;
+    state.ops.record_commit(state.cfg.push, &commit);
 
     if let Some(err) = commit.push_error {
         warn!("best-effort push failed: {err}");
     }
-
-    let resp = CreatePasteResponse {
-        id: draft.id.clone(),
-        path: draft.rel_path.clone(),
-        commit: commit.commit,
-        raw_url: format!("/api/v1/p/{}/raw", draft.id),
-        view_url: format!("/p/{}", draft.id),
-        meta_url: format!("/api/v1/p/{}", draft.id),
-    };
-
-    if let (Some(key), Some(fingerprint)) = (idempotency_key.as_deref(), request_fingerprint) {
-        store::write_idempotency_record(
-            &state.paths.idempotency,
-            key,
-            &IdempotencyRecord {
-                request_fingerprint: fingerprint,
-                response: resp.clone(),
-            },
-        )?;
+    if let Err(err) = blobs::record(&state.kv, &draft.meta) {
+        warn!("index blob {}: {err:?}", draft.id);
+    }
+    state
+        .index
+        .upsert_committed(draft.meta.clone(), &commit.commit);
+    state
+        .events
+        .publish(EventKind::Created, &draft.meta, &commit.commit);
+    if let Some(owner) = &draft.meta.owner {
+        state.api_keys.record_upload(owner, draft.meta.size);
+    }
+    // The link rewrites the older paste's meta too, so it is its own commit.
+    let pushed = commit.pushed;
+    let mut commit = commit.commit;
+    if let Some(old) = supersedes {
+        commit = link_supersedes(state, &draft.id, Some(old))?.unwrap_or(commit);
     }
 
-    Ok((StatusCode::CREATED, axum::Json(resp)))
+    let resp = create_response(state, &draft.meta, commit, pushed);
+    remember_response(state, idempotency, &resp)?;
+    Ok((StatusCode::CREATED, resp))
 }
 
-async fn upload_file(
-    State(state): State<Arc<AppState>>,
-    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
-    headers: HeaderMap,
-    mut multipart: Multipart,
-) -> AppResult<impl IntoResponse> {
-    authorize_create(&state, &headers)?;
+/// Stores the response under the request's idempotency key, if it has one.
+fn remember_response(
+    state: &AppState,
+    idempotency: Option<(&str, String)>,
+    resp: &CreatePasteResponse,
+) -> AppResult<()> {
+    let Some((key, fingerprint)) = idempotency else {
+        return Ok(());
+    };
+    store::write_idempotency_record(
+        &state.kv,
+        key,
+        &IdempotencyRecord {
+            request_fingerprint: fingerprint,
+            response: resp.clone(),
+        },
+    )
+}
 
-    let ip = Some(client_ip(ConnectInfo(remote_addr)));
-    store::check_cidr(&state.cfg.allow_cidr, ip)?;
+/// The newest current paste with the same bytes as `input` and the same
+/// tag, visibility, readers and encryption, so answering with it shows the
+/// caller nothing a fresh paste would not.
+fn find_duplicate(state: &AppState, input: &CreatePasteInput) -> AppResult<Option<PasteMeta>> {
+    let sha256 = match &input.body {
+        PasteBody::Bytes(bytes) => hex::encode(Sha256::digest(bytes)),
+        PasteBody::Staged(staged) => staged.sha256.clone(),
+    };
+    let Some(id) = state.index.newest_with_sha256(&sha256, |meta| {
+        !meta.sealed_at_rest()
+            && meta.e2e == input.e2e
+            && meta.superseded_by.is_none()
+            && meta.visibility == input.visibility
+            && meta.allow_keys == input.allow_keys
+            && meta.tag == input.tag
+            && meta.lang == input.lang
+            && meta.owner == input.owner
+    }) else {
+        return Ok(None);
+    };
+    match store::read_meta(&state.paths.repo, &state.cfg, &id) {
+        Ok(meta) if meta.sha256 == sha256 => Ok(Some(meta)),
+        Ok(_) | Err(AppError::NotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
 
-    let mut file_bytes: Option<Vec<u8>> = None;
+fn create_response(
+    state: &AppState,
+    meta: &PasteMeta,
+    commit: String,
+    pushed: bool,
+) -> CreatePasteResponse {
+    CreatePasteResponse {
+        version: CREATE_RESPONSE_VERSION,
+        id: meta.id.clone(),
+        path: meta.path.clone(),
+        commit,
+        raw_url: public_link(state, &format!("/api/v1/p/{}/raw", meta.id)),
+        view_url: public_link(state, &format!("/p/{}", meta.id)),
+        meta_url: public_link(state, &format!("/api/v1/p/{}", meta.id)),
+        short_url: meta
+            .short_id
+            .as_ref()
+            .map(|short| public_link(state, &format!("/p/{short}"))),
+        short_id: meta.short_id.clone(),
+        visibility: meta.visibility,
+        pushed,
+        size: meta.size,
+        size_human: render::human_size(meta.size),
+        expires_at: state.settings.current().retention().expires_at(meta),
+        deduplicated: false,
+    }
+}
+
+/// The ACL for a new paste: key names from `allow_keys`, which only private
+/// pastes take and which they need at least one of.
+fn allow_keys(
+    state: &AppState,
+    visibility: Visibility,
+    raw: Option<&str>,
+) -> AppResult<Vec<String>> {
+    let mut keys: Vec<String> = raw
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(str::to_string)
+        .collect();
+    keys.sort();
+    keys.dedup();
+    match (visibility, keys.is_empty()) {
+        (Visibility::Private, true) => Err(AppError::BadRequest(
+            "visibility=private needs allow_keys".to_string(),
+        )),
+        (Visibility::Private, false) if !state.api_keys.enabled() => Err(AppError::BadRequest(
+            "private pastes need --api-keys-file".to_string(),
+        )),
+        (Visibility::Listed | Visibility::Unlisted, false) => Err(AppError::BadRequest(
+            "allow_keys needs visibility=private".to_string(),
+        )),
+        _ => Ok(keys),
+    }
+}
+
+/// Streams a request body into `tmp/`, hashing as it goes and giving up with
+/// 413 as soon as it passes `max_bytes`, so uploads never sit in memory.
+/// Compressed uploads arrive already decoded, so the limit applies to the
+/// decompressed size.
+async fn stage_body(
+    state: &AppState,
+    body: Body,
+    mut fingerprint: Option<&mut Sha256>,
+) -> AppResult<StagedBody> {
+    let _staging = state.ops.track_staging();
+    let mut staged = StagedBody {
+        path: state.paths.tmp.join(format!("upload-{}.part", Ulid::new())),
+        size: 0,
+        sha256: String::new(),
+    };
+    let mut file = tokio::fs::File::create(&staged.path)
+        .await
+        .map_err(|e| AppError::io("create staged upload", e))?;
+    let mut hasher = Sha256::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| AppError::BadRequest(format!("read body: {e}")))?;
+        staged.size += chunk.len();
+        if staged.size > state.settings.current().max_bytes {
+            return Err(AppError::TooLarge(
+                "request body exceeds max-bytes".to_string(),
+            ));
+        }
+        hasher.update(&chunk);
+        if let Some(fingerprint) = fingerprint.as_deref_mut() {
+            fingerprint.update(&chunk);
+        }
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| AppError::io("write staged upload", e))?;
+    }
+    file.flush()
+        .await
+        .map_err(|e| AppError::io("flush staged upload", e))?;
+    staged.sha256 = hex::encode(hasher.finalize());
+    Ok(staged)
+}
+
+/// Commits a supersede link under the held git lock. Returns the new commit,
+/// or `None` when the link was already in place.
+fn link_supersedes(
+    state: &AppState,
+    new_id: &str,
+    old_id: Option<&str>,
+) -> AppResult<Option<String>> {
+    let draft = store::build_supersede_draft(&state.paths.repo, new_id, old_id)?;
+    if draft.files.is_empty() {
+        return Ok(None);
+    }
+    let commit = gitops::commit_meta_update(
+        &state.paths.repo,
+        &state.cfg,
+        &draft,
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.ops.record_commit(state.cfg.push, &commit);
+    if let Some(err) = commit.push_error {
+        warn!("best-effort push failed: {err}");
+    }
+    let ids: Vec<&str> = draft
+        .files
+        .iter()
+        .filter_map(|file| file.rel_path.strip_prefix("meta/")?.strip_suffix(".json"))
+        .collect();
+    state.index.refresh(&state.paths.repo, &ids);
+    Ok(Some(commit.commit))
+}
+
+/// Updates paste metadata; today only the `supersedes` link can change.
+async fn patch_paste(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+    axum::Json(patch): axum::Json<PatchPaste>,
+) -> AppResult<impl IntoResponse> {
+    authorize_create(&state, &headers)?;
+    check_client(&state, client).await?;
+    let _write = state.ops.track_write();
+
+    let lease = lease_header(&headers).map(str::to_string);
+    let job = {
+        let state = state.clone();
+        move || {
+            store::check_lease(&state.kv, &id, lease.as_deref())?;
+            if let Some(supersedes) = patch.supersedes {
+                link_supersedes(&state, &id, supersedes.as_deref())?;
+            }
+            store::read_meta(&state.paths.repo, &state.cfg, &id)
+        }
+    };
+    let meta = state.committer.run(job).await?;
+    Ok(axum::Json(meta))
+}
+
+async fn append_paste(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+    body: axum::body::Bytes,
+) -> AppResult<impl IntoResponse> {
+    let owner = authorize_create(&state, &headers)?;
+
+    check_client(&state, client).await?;
+    let _write = state.ops.track_write();
+
+    if body.is_empty() {
+        return Err(AppError::BadRequest("empty append body".to_string()));
+    }
+    if body.len() > state.settings.current().max_bytes {
+        return Err(AppError::TooLarge(
+            "request body exceeds max-bytes".to_string(),
+        ));
+    }
+
+    // One commit thread means each append lands at a well-defined offset.
+    let lease = lease_header(&headers).map(str::to_string);
+    let job = {
+        let state = state.clone();
+        move || commit_append(&state, &id, lease.as_deref(), &body)
+    };
+    let resp = state.committer.run(job).await?;
+    if let Some(owner) = owner {
+        state.api_keys.record_upload(&owner, resp.appended);
+    }
+    Ok(axum::Json(resp))
+}
+
+fn commit_append(
+    state: &AppState,
+    id: &str,
+    lease: Option<&str>,
+    body: &[u8],
+) -> AppResult<AppendResponse> {
+    store::check_lease(&state.kv, id, lease)?;
+    let draft = store::build_append_draft(
+        &state.paths.repo,
+        &state.cfg,
+        state.cipher.as_ref(),
+        id,
+        body,
+    )?;
+    let commit = gitops::commit_append(
+        &state.paths.repo,
+        &state.cfg,
+        &draft,
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.ops.record_commit(state.cfg.push, &commit);
+
+    if let Some(err) = commit.push_error {
+        warn!("best-effort push failed: {err}");
+    }
+    if let Err(err) = blobs::record(&state.kv, &draft.meta) {
+        warn!("index blob {}: {err:?}", draft.id);
+    }
+    state
+        .index
+        .upsert_committed(draft.meta.clone(), &commit.commit);
+
+    Ok(AppendResponse {
+        id: draft.id,
+        offset: draft.previous_len,
+        appended: body.len(),
+        size: draft.meta.size,
+        sha256: draft.meta.sha256,
+        commit: commit.commit,
+    })
+}
+
+async fn acquire_lock(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+    Query(q): Query<LockParams>,
+) -> AppResult<impl IntoResponse> {
+    authorize_create(&state, &headers)?;
+    check_client(&state, client).await?;
+
+    let ttl = match q.ttl.as_deref() {
+        Some(raw) => config::parse_duration(raw).map_err(AppError::BadRequest)?,
+        None => LEASE_DEFAULT_TTL,
+    };
+    if ttl.is_zero() || ttl > LEASE_MAX_TTL {
+        return Err(AppError::BadRequest(
+            "ttl must be between 1s and 1h".to_string(),
+        ));
+    }
+    let meta = store::read_meta(&state.paths.repo, &state.cfg, &id)?;
+    let holder = q.holder.filter(|h| !h.trim().is_empty());
+
+    let _git_lock = FileLock::acquire_async(&state.paths.git_lock, state.cfg.lock_timeout).await?;
+    let lease = store::acquire_lease(&state.kv, &meta.id, lease_header(&headers), holder, ttl)?;
+    Ok(axum::Json(lease))
+}
+
+async fn release_lock(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<StatusCode> {
+    authorize_create(&state, &headers)?;
+    check_client(&state, client).await?;
+    let meta = store::read_meta(&state.paths.repo, &state.cfg, &id)?;
+
+    let _git_lock = FileLock::acquire_async(&state.paths.git_lock, state.cfg.lock_timeout).await?;
+    store::release_lease(&state.kv, &meta.id, lease_header(&headers))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn lease_header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(LEASE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> AppResult<impl IntoResponse> {
+    let owner = authorize_create(&state, &headers)?;
+
+    check_client(&state, client).await?;
+    let _write = state.ops.track_write();
+
+    let mut file_bytes: Option<Vec<u8>> = None;
     let mut name: Option<String> = None;
     let mut tag: Option<String> = None;
 
@@ -285,203 +1559,2236 @@ async fn upload_file(
     }
 
     let bytes = file_bytes.ok_or_else(|| AppError::BadRequest("missing file field".to_string()))?;
-    if bytes.len() > state.cfg.max_bytes {
+    if bytes.len() > state.settings.current().max_bytes {
         return Err(AppError::TooLarge(
             "request body exceeds max-bytes".to_string(),
         ));
     }
 
-    let uploaded = store::persist_upload(&state.paths, &bytes, name, tag)?;
+    let mut uploaded = store::persist_upload(&state.paths, &bytes, name, tag)?;
+    if let Some(owner) = owner {
+        state.api_keys.record_upload(&owner, bytes.len());
+    }
+    uploaded.url = link(&state, &uploaded.url);
     Ok((StatusCode::OK, axum::Json(uploaded)))
 }
 
 async fn get_meta(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Path(id): Path<String>,
-) -> AppResult<impl IntoResponse> {
-    auth::authorize(&state.api_keys, &headers, Scope::PasteRead)?;
-    let meta = store::read_meta(&state.paths.repo, &state.cfg, &id)?;
-    Ok(axum::Json(meta))
-}
-
-async fn get_raw(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    Path(id): Path<String>,
+    PasteId(id): PasteId,
 ) -> AppResult<Response> {
-    auth::authorize(&state.api_keys, &headers, Scope::PasteRead)?;
-    let meta = store::read_meta(&state.paths.repo, &state.cfg, &id)?;
-    let bytes = store::read_paste(&state.paths.repo, &meta)?;
-    let mut response = Response::new(Body::from(bytes));
-    response.headers_mut().insert(
-        header::CONTENT_TYPE,
-        header::HeaderValue::from_static("application/octet-stream"),
-    );
-    response.headers_mut().insert(
-        header::CONTENT_DISPOSITION,
-        header::HeaderValue::from_static("attachment"),
-    );
-    response.headers_mut().insert(
-        header::X_CONTENT_TYPE_OPTIONS,
-        header::HeaderValue::from_static("nosniff"),
-    );
+    let meta = linked_meta(&state, &headers, &id)?;
+    let views = views::get(&state.kv, &meta.id)?;
+    let validators = Validators::for_paste(&state.paths.repo, &meta, false)
+        .with_links(&state.paths.repo, &meta)
+        .with_views(&views);
+    if validators.is_fresh(&headers) {
+        return Ok(validators.not_modified());
+    }
+    let body = MetaResponse {
+        expires_at: state.settings.current().retention().expires_at(&meta),
+        pushed: gitops::is_pushed(&state.paths.repo, &state.cfg, &meta.commit),
+        size_human: render::human_size(meta.size),
+        meta,
+        views,
+    };
+    let mut response = axum::Json(body).into_response();
+    validators.apply(&mut response);
     Ok(response)
 }
 
-async fn recent(
+/// The view counters of a paste, with the same access rules as its meta.
+async fn get_views(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Query(q): Query<RecentParams>,
+    PasteId(id): PasteId,
 ) -> AppResult<impl IntoResponse> {
-    auth::authorize(&state.api_keys, &headers, Scope::RecentRead)?;
-    let n = q.n.unwrap_or(50).min(500);
-    let list = store::read_recent(&state.paths.repo, &state.cfg, n, q.tag.as_deref())?;
-    let out: Vec<RecentItem> = list
-        .into_iter()
-        .map(|m| RecentItem {
-            id: m.id,
-            created_at: m.created_at,
-            path: m.path,
-            commit: m.commit,
-            tag: m.tag,
-            size: m.size,
-            content_type: m.content_type,
-        })
-        .collect();
-    Ok(axum::Json(out))
+    let meta = linked_meta(&state, &headers, &id)?;
+    let views = views::get(&state.kv, &meta.id)?;
+    Ok(axum::Json(ViewsResponse { id: meta.id, views }))
 }
 
-async fn get_file(
+async fn get_raw(
     State(state): State<Arc<AppState>>,
-    Path(name): Path<String>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+    Query(q): Query<RawParams>,
 ) -> AppResult<Response> {
-    let (bytes, content_type) = store::read_uploaded_file(&state.paths, &name)?;
-    let mut response = Response::new(Body::from(bytes));
-    let content_type = header::HeaderValue::from_str(&content_type)
-        .map_err(|e| AppError::internal(format!("invalid content type header: {e}")))?;
-    response
-        .headers_mut()
-        .insert(header::CONTENT_TYPE, content_type);
-    response.headers_mut().insert(
-        header::CACHE_CONTROL,
-        header::HeaderValue::from_static("public, max-age=31536000, immutable"),
-    );
-    response.headers_mut().insert(
-        header::X_CONTENT_TYPE_OPTIONS,
-        header::HeaderValue::from_static("nosniff"),
-    );
-    Ok(response)
+    serve_raw(&state, &headers, &id, &q, false).await
 }
 
-async fn render_view(
+/// `/api/v1/p/{id}/raw/{filename}`: the raw body as a download. The name in
+/// the path is only there for tools that save under the last URL segment;
+/// `Content-Disposition` always carries the stored `<slug>.<ext>`.
+async fn get_raw_named(
     State(state): State<Arc<AppState>>,
-    Path(key): Path<String>,
+    headers: HeaderMap,
+    Path((id, _filename)): Path<(String, String)>,
+    Query(q): Query<RawParams>,
 ) -> AppResult<Response> {
-    match store::read_meta(&state.paths.repo, &state.cfg, &key) {
-        Ok(_) => {
-            let html = render_view_by_id(&state, &key).await?;
-            Ok(html.into_response())
+    let id = store::resolve_short_id(&state.paths.repo, &id)?.unwrap_or(id);
+    serve_raw(&state, &headers, &id, &q, true).await
+}
+
+async fn serve_raw(
+    state: &AppState,
+    headers: &HeaderMap,
+    id: &str,
+    q: &RawParams,
+    download: bool,
+) -> AppResult<Response> {
+    let original = match q.ct.as_deref() {
+        None | Some("octet") => false,
+        Some("original") => true,
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "unsupported ct '{other}' (expected original or octet)"
+            )));
         }
-        Err(AppError::NotFound(_)) => {
-            if let Some(id) = store::resolve_slug_id(&state.paths.repo, &key)? {
-                let target = format!("/p/{id}");
-                return redirect_to(&target);
+    };
+    let meta = linked_meta(state, headers, id)?;
+    count_hit(state, &meta.id, Hit::Raw);
+    let validators = Validators::for_paste(&state.paths.repo, &meta, false);
+    if validators.is_fresh(headers) {
+        return Ok(validators.not_modified());
+    }
+
+    // Partial reads skip --verify-reads: hashing would require the full body.
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| validators.range_applies(headers));
+    if let Some(range) = range {
+        let len = store::paste_len(&state.paths.repo, &meta)?;
+        match parse_range(range, len) {
+            Some(Ok((start, end))) => {
+                let body = paste_range_body(state, &meta, start, end - start + 1).await?;
+                let mut response = raw_response(body, end - start + 1, original.then_some(&meta));
+                validators.apply(&mut response);
+                if download {
+                    attach(&mut response, &meta);
+                }
+                *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+                insert_header(
+                    &mut response,
+                    header::CONTENT_RANGE,
+                    &format!("bytes {start}-{end}/{len}"),
+                );
+                return Ok(response);
             }
-            Err(AppError::NotFound("paste not found".to_string()))
+            Some(Err(())) => {
+                let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+                insert_header(
+                    &mut response,
+                    header::CONTENT_RANGE,
+                    &format!("bytes */{len}"),
+                );
+                return Ok(response);
+            }
+            None => {}
         }
-        Err(err) => Err(err),
     }
+
+    let (body, len) = paste_body(state, &meta).await?;
+    let mut response = raw_response(body, len, original.then_some(&meta));
+    validators.apply(&mut response);
+    if download {
+        attach(&mut response, &meta);
+    }
+    Ok(response)
 }
 
-async fn render_view_with_slug(
-    State(state): State<Arc<AppState>>,
-    Path((id, _slug)): Path<(String, String)>,
-) -> AppResult<impl IntoResponse> {
-    render_view_by_id(&state, &id).await
+/// Marks a raw response as a download named after the stored file.
+fn attach(response: &mut Response, meta: &PasteMeta) {
+    insert_header(
+        response,
+        header::CONTENT_DISPOSITION,
+        &content_disposition("attachment", &store::download_file_name(meta)),
+    );
 }
 
-async fn render_view_markdown(
+/// Raw bytes of whichever paste currently holds content with this sha256;
+/// otherwise behaves exactly like `GET /api/v1/p/{id}/raw`.
+async fn get_blob(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-) -> AppResult<impl IntoResponse> {
-    render_view_by_id_with_mode(&state, &id, true).await
+    headers: HeaderMap,
+    Path(sha256): Path<String>,
+    query: Query<RawParams>,
+) -> AppResult<Response> {
+    auth::authorize(&state.api_keys, &headers, Scope::PasteRead)?;
+    let sha256 = blobs::parse_sha256(&sha256)?;
+    let meta = blobs::find(
+        &state.paths.repo,
+        &state.cfg,
+        &state.index,
+        &state.kv,
+        &sha256,
+    )?
+    .ok_or_else(|| AppError::NotFound("no paste with that sha256".to_string()))?;
+    let mut response = get_raw(State(state), headers, PasteId(meta.id.clone()), query).await?;
+    insert_header(&mut response, PASTE_ID_HEADER, &meta.id);
+    Ok(response)
 }
 
-async fn render_view_by_id(state: &AppState, id: &str) -> AppResult<Html<String>> {
-    render_view_by_id_with_mode(state, id, false).await
+/// Counts a read for `/api/v1/p/{id}/stats`. A counter that cannot be
+/// written is logged; the read itself goes ahead.
+fn count_hit(state: &AppState, id: &str, hit: Hit) {
+    if let Err(err) = views::record(&state.kv, id, hit) {
+        warn!("count {hit:?} of {id}: {err:?}");
+    }
 }
 
-async fn render_view_by_id_with_mode(
-    state: &AppState,
-    id: &str,
-    force_markdown: bool,
-) -> AppResult<Html<String>> {
-    let meta = store::read_meta(&state.paths.repo, &state.cfg, id)?;
-    let bytes = store::read_paste(&state.paths.repo, &meta)?;
-    let body = String::from_utf8_lossy(&bytes);
-    let html = if force_markdown
-        || meta.content_type.contains("markdown")
-        || meta.path.ends_with(".md")
-        || render::looks_like_markdown(&body)
-    {
-        render::render_markdown(&body)
-    } else {
-        format!("<pre>{}</pre>", render::html_escape(&body))
-    };
-    let page_body = render::render_view_shell(&meta.id, &html, &body);
-    let canonical_url = format!("/p/{}", meta.id);
-    Ok(Html(render::render_page(
-        &meta.id,
-        &page_body,
-        Some(&canonical_url),
-    )))
+/// Meta for an API read by id. Needs `paste:read`, except for unlisted
+/// pastes: like a secret gist, holding the link is enough to read one.
+pub(crate) fn linked_meta(state: &AppState, headers: &HeaderMap, id: &str) -> AppResult<PasteMeta> {
+    if let Err(err) = auth::authorize(&state.api_keys, headers, Scope::PasteRead) {
+        return match read_meta(state, id) {
+            Ok(meta) if meta.visibility == Visibility::Unlisted => Ok(meta),
+            _ => Err(err),
+        };
+    }
+    readable_meta(state, headers, id)
 }
 
-async fn healthz() -> impl IntoResponse {
-    (StatusCode::OK, "ok")
+/// Meta for a read, once the paste's own access list lets the caller in.
+fn readable_meta(state: &AppState, headers: &HeaderMap, id: &str) -> AppResult<PasteMeta> {
+    let meta = read_meta(state, id)?;
+    auth::authorize_paste(&state.api_keys, headers, &meta)?;
+    Ok(meta)
 }
 
-async fn readyz(State(state): State<Arc<AppState>>) -> AppResult<impl IntoResponse> {
-    if let Err(err) = gitops::ready(&state.paths.repo, &state.paths.git_lock, &state.cfg) {
-        return Err(AppError::ServiceUnavailable(format!("{err:?}")));
+/// [`store::read_meta`], taking the commit from the index when it has it.
+fn read_meta(state: &AppState, id: &str) -> AppResult<PasteMeta> {
+    let repo = &state.paths.repo;
+    let mut meta = store::read_stored_meta(repo, id)?;
+    if meta.commit.is_empty() {
+        state.index.sync(repo, &state.cfg);
+        meta.commit = match state.index.commit_of(&meta.id, &meta.sha256) {
+            Some(commit) => commit,
+            None => store::lookup_commit(repo, &state.cfg, &meta.path)?,
+        };
     }
-    Ok((StatusCode::OK, "ok"))
+    Ok(meta)
 }
 
-fn client_ip(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> IpAddr {
-    addr.ip()
+/// Body for a full paste read, streamed from disk so large pastes do not sit
+/// in memory. When `--verify-reads` is due the bytes are buffered instead,
+/// since they must be hashed before anything is sent; sealed and compressed
+/// pastes are always buffered to be decoded.
+pub(crate) async fn paste_body(state: &AppState, meta: &PasteMeta) -> AppResult<(Body, u64)> {
+    let verify = verify_due(state);
+    if verify || !meta.stored_as_is() {
+        let bytes = store::read_paste(&state.paths.repo, meta)?;
+        if verify {
+            store::verify_integrity(meta, &bytes)?;
+        }
+        let bytes = store::unseal(state.cipher.as_ref(), meta, bytes)?;
+        let len = bytes.len() as u64;
+        return Ok((Body::from(bytes), len));
+    }
+    let file = open_paste_file(&state.paths.repo, meta).await?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(|e| AppError::io("stat paste", e))?
+        .len();
+    Ok((Body::from_stream(ReaderStream::new(file.take(len))), len))
 }
 
-fn redirect_to(location: &str) -> AppResult<Response> {
-    let mut response = Response::new(Body::empty());
-    *response.status_mut() = StatusCode::FOUND;
-    let location = header::HeaderValue::from_str(location)
-        .map_err(|e| AppError::internal(format!("invalid redirect location: {e}")))?;
-    response.headers_mut().insert(header::LOCATION, location);
-    Ok(response)
+async fn paste_range_body(
+    state: &AppState,
+    meta: &PasteMeta,
+    start: u64,
+    len: u64,
+) -> AppResult<Body> {
+    if !meta.stored_as_is() {
+        let bytes =
+            store::read_paste_range(&state.paths.repo, state.cipher.as_ref(), meta, start, len)?;
+        return Ok(Body::from(bytes));
+    }
+    let mut file = open_paste_file(&state.paths.repo, meta).await?;
+    file.seek(std::io::SeekFrom::Start(start))
+        .await
+        .map_err(|e| AppError::io("seek paste", e))?;
+    Ok(Body::from_stream(ReaderStream::new(file.take(len))))
 }
 
-fn authorize_create(state: &AppState, headers: &HeaderMap) -> AppResult<()> {
-    if state.api_keys.enabled() {
-        auth::authorize(&state.api_keys, headers, Scope::PasteCreate)?;
-    } else {
-        let provided_token = headers.get("X-Paste-Token").and_then(|v| v.to_str().ok());
-        store::verify_token(state.cfg.token.as_deref(), provided_token)?;
+async fn open_paste_file(repo: &std::path::Path, meta: &PasteMeta) -> AppResult<tokio::fs::File> {
+    tokio::fs::File::open(repo.join(&meta.path))
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::NotFound("paste file missing".to_string()),
+            _ => AppError::io("open paste", e),
+        })
+}
+
+/// Builds the raw download response. With `original` set, the recorded
+/// content type and file name are used; only types that cannot execute
+/// script are served inline, and everything else stays an attachment.
+pub(crate) fn raw_response(body: Body, len: u64, original: Option<&PasteMeta>) -> Response {
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, header::HeaderValue::from(len));
+    let content_type = original
+        .map(|meta| meta.content_type.trim())
+        .filter(|ct| ct.contains('/'))
+        .and_then(|ct| header::HeaderValue::from_str(ct).ok());
+    match (original, content_type) {
+        (Some(meta), Some(content_type)) => {
+            let disposition = if is_inline_safe(content_type.to_str().unwrap_or_default()) {
+                "inline"
+            } else {
+                "attachment"
+            };
+            let file_name = store::download_file_name(meta);
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, content_type);
+            insert_header(
+                &mut response,
+                header::CONTENT_DISPOSITION,
+                &content_disposition(disposition, &file_name),
+            );
+            response.headers_mut().insert(
+                header::CONTENT_SECURITY_POLICY,
+                header::HeaderValue::from_static("sandbox"),
+            );
+        }
+        _ => {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/octet-stream"),
+            );
+            response.headers_mut().insert(
+                header::CONTENT_DISPOSITION,
+                header::HeaderValue::from_static("attachment"),
+            );
+        }
     }
-    Ok(())
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    response.headers_mut().insert(
+        header::ACCEPT_RANGES,
+        header::HeaderValue::from_static("bytes"),
+    );
+    response
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn is_inline_safe(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    // Source code types (`text/x-rust`, `text/x-diff`, ...) show as text.
+    essence.starts_with("text/x-")
+        || matches!(
+            essence.as_str(),
+            "text/plain"
+                | "text/markdown"
+                | "text/csv"
+                | "application/json"
+                | "application/yaml"
+                | "application/toml"
+                | "image/png"
+                | "image/jpeg"
+                | "image/gif"
+                | "image/webp"
+        )
+}
 
-    #[test]
-    fn client_ip_from_connect_info() {
+/// Cache validators for a paste: its sha256 as `ETag` and the paste file's
+/// mtime as `Last-Modified`, so appends invalidate both.
+struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    fn for_paste(repo: &std::path::Path, meta: &PasteMeta, weak: bool) -> Self {
+        let etag = if weak {
+            format!("W/\"{}\"", meta.sha256)
+        } else {
+            format!("\"{}\"", meta.sha256)
+        };
+        Self {
+            etag,
+            last_modified: store::paste_modified(repo, meta),
+        }
+    }
+
+    /// For responses that also show supersede links (meta, views): those
+    /// change without the paste bytes changing.
+    fn with_links(mut self, repo: &std::path::Path, meta: &PasteMeta) -> Self {
+        for (mark, link) in [("s", &meta.supersedes), ("b", &meta.superseded_by)] {
+            if let Some(id) = link {
+                let end = self.etag.len() - 1;
+                self.etag.insert_str(end, &format!("-{mark}{id}"));
+            }
+        }
+        self.last_modified = self.last_modified.max(store::meta_modified(repo, &meta.id));
+        self
+    }
+
+    /// For meta responses, which carry the view counters.
+    fn with_views(mut self, views: &PasteViews) -> Self {
+        let end = self.etag.len() - 1;
+        self.etag
+            .insert_str(end, &format!("-v{}.{}", views.views, views.raw));
+        self
+    }
+
+    /// `If-None-Match` wins over `If-Modified-Since`, as in RFC 9110.
+    fn is_fresh(&self, headers: &HeaderMap) -> bool {
+        if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+            let ours = strip_weak(&self.etag);
+            return value.to_str().is_ok_and(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || strip_weak(tag) == ours)
+            });
+        }
+        let since = headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+        match (self.last_modified, since) {
+            (Some(modified), Some(since)) => unix_secs(modified) <= unix_secs(since),
+            _ => false,
+        }
+    }
+
+    /// `If-Range` lets a resuming client ask for the range only if the paste
+    /// is unchanged; otherwise the full body is sent. Dates must match exactly
+    /// and entity tags compare strongly.
+    fn range_applies(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) else {
+            return true;
+        };
+        let value = value.trim();
+        if value.starts_with('"') {
+            return !self.etag.starts_with("W/") && value == self.etag;
+        }
+        match (self.last_modified, httpdate::parse_http_date(value).ok()) {
+            (Some(modified), Some(date)) => unix_secs(modified) == unix_secs(date),
+            _ => false,
+        }
+    }
+
+    fn apply(&self, response: &mut Response) {
+        insert_header(response, header::ETAG, &self.etag);
+        if let Some(modified) = self.last_modified {
+            insert_header(
+                response,
+                header::LAST_MODIFIED,
+                &httpdate::fmt_http_date(modified),
+            );
+        }
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("no-cache"),
+        );
+    }
+
+    fn not_modified(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.apply(&mut response);
+        response
+    }
+}
+
+fn strip_weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+fn unix_secs(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// `Content-Disposition` with a file name. Names beyond ASCII go in an RFC
+/// 6266 `filename*`, after a `filename` with `_` in their place.
+fn content_disposition(disposition: &str, file_name: &str) -> String {
+    if file_name.is_ascii() {
+        return format!("{disposition}; filename=\"{file_name}\"");
+    }
+    let fallback: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        webdav::percent_encode(file_name)
+    )
+}
+
+fn insert_header(response: &mut Response, name: header::HeaderName, value: &str) {
+    if let Ok(value) = header::HeaderValue::from_str(value) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+/// Parses a single `bytes=` range against a body of `len` bytes into an
+/// inclusive `(start, end)` pair. `None` means the header should be ignored
+/// (malformed or multi-range) and the full body served; `Some(Err(()))` means
+/// the range is well-formed but unsatisfiable.
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len - 1)));
+    }
+    let start: u64 = start.parse().ok()?;
+    let end: u64 = if end.is_empty() {
+        u64::MAX
+    } else {
+        end.parse().ok()?
+    };
+    if end < start {
+        return None;
+    }
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end.min(len - 1))))
+}
+
+async fn get_tail(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+    Query(q): Query<TailParams>,
+) -> AppResult<Response> {
+    let meta = linked_meta(&state, &headers, &id)?;
+    let lines = q.lines.unwrap_or(200).min(MAX_TAIL_LINES);
+    let bytes = store::read_paste_tail(&state.paths.repo, state.cipher.as_ref(), &meta, lines)?;
+    let mut response = Response::new(Body::from(bytes));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+/// Streams bytes as they are appended to a paste, like `tail -f`. The stream
+/// starts at `from` (default: the current end) and closes once the paste has
+/// not grown for `idle`, or when the client disconnects.
+enum FollowPoll {
+    Grew(Vec<u8>),
+    Unchanged,
+    Stop,
+}
+
+/// One poll of a follow stream, on a blocking thread: the bytes past
+/// `offset`, at most [`FOLLOW_CHUNK`] of them so a `from=0` on a large log
+/// goes out a chunk at a time. Sealed and compressed pastes are decoded whole
+/// anyway, so their remainder is sent in one piece.
+fn poll_follow(
+    repo: &std::path::Path,
+    cipher: Option<&PasteCipher>,
+    meta: &mut PasteMeta,
+    offset: u64,
+) -> FollowPoll {
+    // A compressed paste's length is in its meta, which appends rewrite.
+    if meta.stored.is_some() {
+        match store::read_stored_meta(repo, &meta.id) {
+            Ok(fresh) => *meta = fresh,
+            Err(err) => {
+                warn!(id = %meta.id, "follow stopped: {err:?}");
+                return FollowPoll::Stop;
+            }
+        }
+    }
+    let len = match store::paste_len(repo, meta) {
+        Ok(len) => len,
+        Err(err) => {
+            warn!(id = %meta.id, "follow stopped: {err:?}");
+            return FollowPoll::Stop;
+        }
+    };
+    // A shrinking paste was rewritten underneath us; stop following.
+    if len < offset {
+        return FollowPoll::Stop;
+    }
+    if len == offset {
+        return FollowPoll::Unchanged;
+    }
+    let want = match meta.stored_as_is() {
+        true => (len - offset).min(FOLLOW_CHUNK),
+        false => len - offset,
+    };
+    match store::read_paste_range(repo, cipher, meta, offset, want) {
+        Ok(bytes) if bytes.is_empty() => FollowPoll::Unchanged,
+        Ok(bytes) => FollowPoll::Grew(bytes),
+        // An append re-encoding the paste replaced the file but not yet the
+        // meta (or the other way round); the next poll sees both.
+        Err(_) if !meta.stored_as_is() => FollowPoll::Unchanged,
+        Err(err) => {
+            warn!(id = %meta.id, "follow stopped: {err:?}");
+            FollowPoll::Stop
+        }
+    }
+}
+
+async fn follow_paste(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+    Query(q): Query<FollowParams>,
+) -> AppResult<Response> {
+    let meta = linked_meta(&state, &headers, &id)?;
+    let idle = match q.idle.as_deref() {
+        Some(raw) => config::parse_duration(raw).map_err(AppError::BadRequest)?,
+        None => FOLLOW_DEFAULT_IDLE,
+    };
+    if idle > FOLLOW_MAX_IDLE {
+        return Err(AppError::BadRequest("idle must be at most 1h".to_string()));
+    }
+    let len = store::paste_len(&state.paths.repo, &meta)?;
+    let offset = q.from.unwrap_or(len).min(len);
+
+    let repo = state.paths.repo.clone();
+    let cipher = state.cipher.clone();
+    let stream = futures_util::stream::unfold(
+        (repo, cipher, meta, offset, tokio::time::Instant::now()),
+        move |(repo, cipher, mut meta, offset, last_growth)| async move {
+            loop {
+                let polled = tokio::task::spawn_blocking({
+                    let (repo, cipher) = (repo.clone(), cipher.clone());
+                    move || {
+                        let poll = poll_follow(&repo, cipher.as_ref(), &mut meta, offset);
+                        (meta, poll)
+                    }
+                })
+                .await;
+                let poll;
+                (meta, poll) = match polled {
+                    Ok(polled) => polled,
+                    Err(err) => {
+                        warn!("follow stopped: {err}");
+                        return None;
+                    }
+                };
+                match poll {
+                    FollowPoll::Grew(bytes) => {
+                        let next = offset + bytes.len() as u64;
+                        let now = tokio::time::Instant::now();
+                        let state = (repo, cipher, meta, next, now);
+                        return Some((Ok::<_, std::io::Error>(bytes), state));
+                    }
+                    FollowPoll::Unchanged => {}
+                    FollowPoll::Stop => return None,
+                }
+                if last_growth.elapsed() >= idle {
+                    return None;
+                }
+                tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+            }
+        },
+    );
+
+    let mut response = Response::new(Body::from_stream(stream));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-cache"),
+    );
+    response
+        .headers_mut()
+        .insert("X-Accel-Buffering", header::HeaderValue::from_static("no"));
+    Ok(response)
+}
+
+/// Server-sent events for pastes being created or deleted. A reconnecting
+/// `EventSource` sends `Last-Event-ID` and first gets what it missed, as far
+/// back as the replay buffer reaches.
+async fn paste_events(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<EventsParams>,
+) -> AppResult<Response> {
+    auth::authorize(&state.api_keys, &headers, Scope::RecentRead)?;
+    let last_seen = headers
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    let stream = state.events.stream(last_seen, q.tag).map(|event| {
+        Event::default()
+            .event(event.kind.as_str())
+            .id(event.seq.to_string())
+            .json_data(&event)
+    });
+
+    let mut response = Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response();
+    // Marks the stream live, which also keeps it out of compression.
+    response
+        .headers_mut()
+        .insert("X-Accel-Buffering", header::HeaderValue::from_static("no"));
+    Ok(response)
+}
+
+/// The dashboard's live feed: the same events as `/api/v1/events` over a
+/// WebSocket. Like the dashboard it updates, it needs no API key.
+async fn dashboard_ws(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<WsParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| push_events(socket, state, q))
+}
+
+async fn push_events(mut socket: WebSocket, state: Arc<AppState>, q: WsParams) {
+    let (missed, mut rx) = state.events.subscribe(q.since);
+    let tag = q.tag.as_deref();
+    for event in missed {
+        if event.matches_tag(tag) && send_event(&mut socket, &event).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(event) => {
+                    if event.matches_tag(tag) && send_event(&mut socket, &event).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("websocket subscriber fell behind; skipped {skipped} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // Clients only ever close; anything else they send is ignored.
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send_event(socket: &mut WebSocket, event: &PasteEvent) -> Result<(), axum::Error> {
+    let json = serde_json::to_string(&WsEvent {
+        seq: event.seq,
+        event,
+    })
+    .map_err(axum::Error::new)?;
+    socket.send(Message::Text(json.into())).await
+}
+
+async fn recent(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<RecentParams>,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize(&state.api_keys, &headers, Scope::RecentRead)?;
+    Ok(axum::Json(recent_items(&state, &q)?))
+}
+
+pub(crate) fn recent_items(state: &AppState, q: &RecentParams) -> AppResult<Vec<RecentItem>> {
+    let n = q.n.unwrap_or(50).min(500);
+    state.index.sync(&state.paths.repo, &state.cfg);
+    state.index.recent_with_commits(
+        &state.paths.repo,
+        &state.cfg,
+        n,
+        q.tag.as_deref(),
+        q.owner.as_deref(),
+        q.superseded.unwrap_or(false),
+    )
+}
+
+async fn server_stats(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize(&state.api_keys, &headers, Scope::RecentRead)?;
+    let stats = tokio::task::spawn_blocking(move || stats::collect(&state))
+        .await
+        .map_err(|e| AppError::internal(format!("stats task failed: {e}")))?;
+    Ok(axum::Json(stats))
+}
+
+async fn star_paste(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<impl IntoResponse> {
+    let who = auth::authorize_caller(&state.api_keys, &headers, Scope::PasteRead)?;
+    let meta = readable_meta(&state, &headers, &id)?;
+    let stars = stars::star(&state.kv, &meta.id, &who)?;
+    state.index.set_stars(&meta.id, stars);
+    Ok(axum::Json(StarResponse {
+        id: meta.id,
+        stars,
+        starred: true,
+    }))
+}
+
+async fn unstar_paste(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<impl IntoResponse> {
+    let who = auth::authorize_caller(&state.api_keys, &headers, Scope::PasteRead)?;
+    let meta = readable_meta(&state, &headers, &id)?;
+    let stars = stars::unstar(&state.kv, &meta.id, &who)?;
+    state.index.set_stars(&meta.id, stars);
+    Ok(axum::Json(StarResponse {
+        id: meta.id,
+        stars,
+        starred: false,
+    }))
+}
+
+async fn create_collection(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    axum::Json(new): axum::Json<NewCollection>,
+) -> AppResult<impl IntoResponse> {
+    authorize_create(&state, &headers)?;
+    check_client(&state, client).await?;
+    let _write = state.ops.track_write();
+
+    let job = {
+        let state = state.clone();
+        move || {
+            let draft = collections::build_create_draft(
+                &state.paths.repo,
+                &new.title,
+                new.description.as_deref(),
+                &new.pastes,
+            )?;
+            let commit = commit_collection(&state, &draft)?;
+            Ok((draft.collection, commit))
+        }
+    };
+    let (collection, commit) = state.committer.run(job).await?;
+    let resp = collection_response(&state, &headers, collection, Some(commit))?;
+    Ok((StatusCode::CREATED, axum::Json(resp)))
+}
+
+async fn add_to_collection(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    axum::Json(add): axum::Json<AddToCollection>,
+) -> AppResult<impl IntoResponse> {
+    authorize_create(&state, &headers)?;
+    check_client(&state, client).await?;
+    let _write = state.ops.track_write();
+
+    let job = {
+        let state = state.clone();
+        move || {
+            let draft = collections::build_add_draft(&state.paths.repo, &id, &add.pastes)?;
+            let commit = commit_collection(&state, &draft)?;
+            Ok((draft.collection, commit))
+        }
+    };
+    let (collection, commit) = state.committer.run(job).await?;
+    let resp = collection_response(&state, &headers, collection, Some(commit))?;
+    Ok(axum::Json(resp))
+}
+
+async fn get_collection(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize(&state.api_keys, &headers, Scope::PasteRead)?;
+    let collection = collections::read(&state.paths.repo, &id)?;
+    let resp = collection_response(&state, &headers, collection, None)?;
+    Ok(axum::Json(resp))
+}
+
+/// The shareable page; like `/p/{id}`, it needs no key, and private members
+/// show only to the keys they name.
+async fn render_collection(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> AppResult<Html<String>> {
+    let collection = collections::read(&state.paths.repo, &id)?;
+    let members = collection_members(&state, &headers, &collection)?;
+    Ok(Html(render::render_collection(
+        &state.cfg.base_path,
+        &collection,
+        &members,
+    )))
+}
+
+/// Commits a collection draft under the held git lock and returns the commit.
+fn commit_collection(state: &AppState, draft: &CollectionDraft) -> AppResult<String> {
+    let commit = gitops::commit_collection(
+        &state.paths.repo,
+        &state.cfg,
+        draft,
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.ops.record_commit(state.cfg.push, &commit);
+    if let Some(err) = commit.push_error {
+        warn!("best-effort push failed: {err}");
+    }
+    Ok(commit.commit)
+}
+
+fn collection_response(
+    state: &AppState,
+    headers: &HeaderMap,
+    collection: Collection,
+    commit: Option<String>,
+) -> AppResult<CollectionResponse> {
+    let members = collection_members(state, headers, &collection)?;
+    Ok(CollectionResponse {
+        url: link(state, &format!("/c/{}", collection.id)),
+        collection,
+        commit,
+        members,
+    })
+}
+
+/// Members the caller may read, in collection order. Deleted pastes and
+/// private ones for other keys are left out.
+fn collection_members(
+    state: &AppState,
+    headers: &HeaderMap,
+    collection: &Collection,
+) -> AppResult<Vec<RecentItem>> {
+    let counts = stars::counts(&state.kv)?;
+    Ok(collection
+        .pastes
+        .iter()
+        .filter_map(|id| read_meta(state, id).ok())
+        .filter(|m| auth::authorize_paste(&state.api_keys, headers, m).is_ok())
+        .map(|m| {
+            let count = counts.get(&m.id).copied().unwrap_or_default();
+            RecentItem::from_meta(m, count)
+        })
+        .collect())
+}
+
+/// An archive of a tag's listed pastes, newest first, or of the members of
+/// a collection the caller may read.
+async fn export_pastes(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<ExportParams>,
+) -> AppResult<Response> {
+    auth::authorize(&state.api_keys, &headers, Scope::PasteRead)?;
+    let (metas, tag, collection): (Vec<PasteMeta>, _, _) = match (q.tag, q.collection) {
+        (Some(tag), None) => {
+            state.index.sync(&state.paths.repo, &state.cfg);
+            let (items, _) = state.index.recent(export::MAX_PASTES + 1, Some(&tag), true);
+            let metas = items
+                .iter()
+                .filter_map(|item| read_meta(&state, &item.id).ok())
+                .collect();
+            (metas, Some(tag), None)
+        }
+        (None, Some(id)) => {
+            let collection = collections::read(&state.paths.repo, &id)?;
+            let metas = collection
+                .pastes
+                .iter()
+                .filter_map(|id| read_meta(&state, id).ok())
+                .filter(|m| auth::authorize_paste(&state.api_keys, &headers, m).is_ok())
+                .collect();
+            (metas, None, Some(collection.id))
+        }
+        _ => {
+            return Err(AppError::BadRequest(
+                "export needs either tag or collection".to_string(),
+            ));
+        }
+    };
+    if metas.is_empty() {
+        return Err(AppError::NotFound("no pastes to export".to_string()));
+    }
+    let file_name = format!(
+        "lanpaste-{}.{}",
+        download_stem(tag.as_deref().or(collection.as_deref()).unwrap_or_default()),
+        q.format.extension()
+    );
+    let manifest = export::Manifest::new(tag, collection, metas);
+    let path = state
+        .paths
+        .tmp
+        .join(format!("export-{}.{}", Ulid::new(), q.format.extension()));
+    let written = {
+        let (state, path) = (state.clone(), path.clone());
+        tokio::task::spawn_blocking(move || {
+            export::write(
+                &state.paths.repo,
+                state.cipher.as_ref(),
+                &manifest,
+                q.format,
+                &path,
+            )
+        })
+        .await
+        .map_err(|e| AppError::internal(format!("export task failed: {e}")))?
+    };
+    let file = match written {
+        Ok(()) => tokio::fs::File::open(&path).await,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(err);
+        }
+    };
+    // The open handle keeps the bytes readable once the name is gone.
+    let _ = tokio::fs::remove_file(&path).await;
+    let file = file.map_err(|e| AppError::io("open export", e))?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(|e| AppError::io("stat export", e))?
+        .len();
+    let mut response = Response::new(Body::from_stream(ReaderStream::new(file)));
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, header::HeaderValue::from(len));
+    insert_header(&mut response, header::CONTENT_TYPE, q.format.content_type());
+    insert_header(
+        &mut response,
+        header::CONTENT_DISPOSITION,
+        &format!("attachment; filename=\"{file_name}\""),
+    );
+    Ok(response)
+}
+
+/// A tag or id made safe for a download file name.
+fn download_stem(raw: &str) -> String {
+    raw.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// The caller's starred pastes, newest first. Stars on pastes that no
+/// longer exist are skipped.
+async fn my_stars(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let who = auth::authorize_caller(&state.api_keys, &headers, Scope::PasteRead)?;
+    let counts = stars::counts(&state.kv)?;
+    let mut out: Vec<RecentItem> = stars::bookmarks(&state.kv, &who)?
+        .into_iter()
+        .filter_map(|id| store::read_meta(&state.paths.repo, &state.cfg, &id).ok())
+        .map(|m| {
+            let count = counts.get(&m.id).copied().unwrap_or_default();
+            RecentItem::from_meta(m, count)
+        })
+        .collect();
+    out.sort_by_key(|item| std::cmp::Reverse(item.created_at));
+    Ok(axum::Json(out))
+}
+
+/// The caller's own pastes, private and unlisted ones included.
+async fn my_pastes(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<MineParams>,
+) -> AppResult<impl IntoResponse> {
+    let who = auth::authorize_caller(&state.api_keys, &headers, Scope::RecentRead)?;
+    let n = q.n.unwrap_or(50).min(500);
+    state.index.sync(&state.paths.repo, &state.cfg);
+    let items = state
+        .index
+        .owned_with_commits(&state.paths.repo, &state.cfg, &who, n)?;
+    Ok(axum::Json(items))
+}
+
+async fn get_file(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> AppResult<Response> {
+    let (bytes, content_type) = store::read_uploaded_file(&state.paths, &name)?;
+    let mut response = Response::new(Body::from(bytes));
+    let content_type = header::HeaderValue::from_str(&content_type)
+        .map_err(|e| AppError::internal(format!("invalid content type header: {e}")))?;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, content_type);
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("public, max-age=31536000, immutable"),
+    );
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+/// Bundled CSS/JS. Fingerprinted names are immutable; the bare name is
+/// served too, uncached, for debugging.
+async fn get_asset(headers: HeaderMap, Path(file): Path<String>) -> AppResult<Response> {
+    let (asset, immutable) =
+        assets::lookup(&file).ok_or_else(|| AppError::NotFound("asset not found".to_string()))?;
+    let etag = format!("\"{}\"", asset.fingerprint);
+    let cache = if immutable {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    let fresh = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    let mut response = if fresh {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut response = Response::new(Body::from(asset.body));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(asset.content_type),
+        );
+        response
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static(cache),
+    );
+    headers.insert(
+        header::ETAG,
+        header::HeaderValue::from_str(&etag)
+            .map_err(|e| AppError::internal(format!("invalid etag header: {e}")))?,
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+async fn render_view(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(key): PasteId,
+) -> AppResult<Response> {
+    match store::read_meta(&state.paths.repo, &state.cfg, &key) {
+        Ok(_) => render_view_by_id(&state, &headers, &key).await,
+        Err(AppError::NotFound(_)) => {
+            if let Some(id) = slug_target(&state, &headers, &key)? {
+                let target = format!("/p/{id}");
+                return redirect_to(&state, &target);
+            }
+            Err(AppError::NotFound("paste not found".to_string()))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Stable URL for a slug; unlike `/p/{slug}` it never matches an id.
+async fn resolve_vanity_slug(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(slug): Path<String>,
+) -> AppResult<Response> {
+    match slug_target(&state, &headers, &slug)? {
+        Some(id) => redirect_to(&state, &format!("/p/{id}")),
+        None => Err(AppError::NotFound("slug not found".to_string())),
+    }
+}
+
+/// Id a slug redirects to. Slugs are guessable, so they only lead to pastes
+/// the caller could find without the link: listed ones, and private ones
+/// their key may read. An unlisted paste's id is its secret link.
+fn slug_target(state: &AppState, headers: &HeaderMap, slug: &str) -> AppResult<Option<String>> {
+    let Some(id) = store::resolve_slug_id(&state.paths.repo, slug)? else {
+        return Ok(None);
+    };
+    match readable_meta(state, headers, &id) {
+        Ok(meta) if meta.visibility != Visibility::Unlisted => Ok(Some(meta.id)),
+        Ok(_) | Err(AppError::NotFound(_) | AppError::Unauthorized(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn render_view_with_slug(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((id, _slug)): Path<(String, String)>,
+) -> AppResult<Response> {
+    let id = store::resolve_short_id(&state.paths.repo, &id)?.unwrap_or(id);
+    render_view_by_id(&state, &headers, &id).await
+}
+
+async fn render_view_markdown(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<Response> {
+    render_view_by_id_with_mode(&state, &headers, &id, true).await
+}
+
+async fn render_print(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<Response> {
+    let (meta, page) = print_page(&state, &headers, &id).await?;
+    let validators = Validators::for_paste(&state.paths.repo, &meta, true);
+    let mut response = Html(page).into_response();
+    validators.apply(&mut response);
+    Ok(response)
+}
+
+async fn render_pdf(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<Response> {
+    let Some(command) = state.cfg.pdf_command.as_deref() else {
+        return Err(AppError::NotFound(
+            "PDF export is off; start the server with --pdf-command, or print /p/{id}/print"
+                .to_string(),
+        ));
+    };
+    let meta = readable_meta(&state, &headers, &id)?;
+    let validators = Validators::for_paste(&state.paths.repo, &meta, true);
+    if validators.is_fresh(&headers) {
+        return Ok(validators.not_modified());
+    }
+    let (meta, page) = print_page(&state, &headers, &meta.id).await?;
+    let pdf = pdf::render(command, page).await?;
+    let file_name = store::download_file_name(&meta);
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name.as_str(), |(stem, _)| stem);
+    let mut response = Response::new(Body::from(pdf));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/pdf"),
+    );
+    insert_header(
+        &mut response,
+        header::CONTENT_DISPOSITION,
+        &content_disposition("inline", &format!("{stem}.pdf")),
+    );
+    validators.apply(&mut response);
+    Ok(response)
+}
+
+/// The standalone print page of a paste, which `/p/{id}/pdf` hands to the
+/// PDF renderer. End-to-end pastes have no server-side rendering to print.
+async fn print_page(
+    state: &AppState,
+    headers: &HeaderMap,
+    id: &str,
+) -> AppResult<(PasteMeta, String)> {
+    let meta = readable_meta(state, headers, id)?;
+    if meta.e2e {
+        return Err(AppError::Conflict(
+            "end-to-end encrypted pastes can only be printed from the browser view".to_string(),
+        ));
+    }
+    count_hit(state, &meta.id, Hit::View);
+    let (html, _) = render_content(state, &meta, false).await?;
+    let page = render::render_print_page(
+        &store::download_file_name(&meta),
+        &format!("{} · {}", meta.id, meta.created_at.date()),
+        &html,
+    );
+    Ok((meta, page))
+}
+
+async fn render_qr(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<Response> {
+    let meta = readable_meta(&state, &headers, &id)?;
+    let view_url = format!(
+        "{}{}",
+        request_origin(&state, &headers),
+        link(&state, &format!("/p/{}", meta.id))
+    );
+    let svg = render::render_qr_svg(&view_url)
+        .map_err(|e| AppError::internal(format!("qr encode failed: {e}")))?;
+    let mut response = Response::new(Body::from(svg));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("image/svg+xml"),
+    );
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+async fn render_plain(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    PasteId(id): PasteId,
+) -> AppResult<Response> {
+    let meta = readable_meta(&state, &headers, &id)?;
+    count_hit(&state, &meta.id, Hit::Raw);
+    let validators = Validators::for_paste(&state.paths.repo, &meta, false);
+    if validators.is_fresh(&headers) {
+        return Ok(validators.not_modified());
+    }
+    let (body, len) = paste_body(&state, &meta).await?;
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, header::HeaderValue::from(len));
+    // End-to-end pastes are ciphertext; the view page decrypts them.
+    let content_type = if meta.e2e {
+        "application/octet-stream"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type),
+    );
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        header::HeaderValue::from_static("inline"),
+    );
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    validators.apply(&mut response);
+    Ok(response)
+}
+
+async fn render_view_by_id(state: &AppState, headers: &HeaderMap, id: &str) -> AppResult<Response> {
+    render_view_by_id_with_mode(state, headers, id, false).await
+}
+
+async fn render_view_by_id_with_mode(
+    state: &AppState,
+    headers: &HeaderMap,
+    id: &str,
+    force_markdown: bool,
+) -> AppResult<Response> {
+    let meta = readable_meta(state, headers, id)?;
+    count_hit(state, &meta.id, Hit::View);
+    // Rendered HTML also depends on the renderer, so views get a weak tag.
+    let validators =
+        Validators::for_paste(&state.paths.repo, &meta, true).with_links(&state.paths.repo, &meta);
+    if validators.is_fresh(headers) {
+        return Ok(validators.not_modified());
+    }
+    let banner = render::render_supersede_banner(
+        &state.cfg.base_path,
+        meta.supersedes.as_deref(),
+        meta.superseded_by.as_deref(),
+    );
+    if meta.e2e {
+        let canonical_url = link(state, &format!("/p/{}", meta.id));
+        let page = render::render_page(
+            &state.cfg.base_path,
+            &meta.id,
+            &format!(
+                "{banner}{}",
+                render::render_e2e_shell(&state.cfg.base_path, &meta.id)
+            ),
+            Some(&canonical_url),
+        );
+        let mut response = Html(page).into_response();
+        validators.apply(&mut response);
+        return Ok(response);
+    }
+    let (html, body) = render_content(state, &meta, force_markdown).await?;
+    let page_body = format!(
+        "{banner}{}",
+        render::render_view_shell(
+            &state.cfg.base_path,
+            &meta.id,
+            meta.short_id.as_deref(),
+            &html,
+            &body
+        )
+    );
+    let canonical_url = link(state, &format!("/p/{}", meta.id));
+    let mut response = Html(render::render_page(
+        &state.cfg.base_path,
+        &meta.id,
+        &page_body,
+        Some(&canonical_url),
+    ))
+    .into_response();
+    validators.apply(&mut response);
+    Ok(response)
+}
+
+/// A paste's content as HTML for the view and print pages, and its text:
+/// highlighted code for a `lang`, markdown when it reads as markdown,
+/// escaped text otherwise.
+async fn render_content(
+    state: &AppState,
+    meta: &PasteMeta,
+    force_markdown: bool,
+) -> AppResult<(String, String)> {
+    let bytes = store::read_paste(&state.paths.repo, meta)?;
+    verify_read(state, meta, &bytes)?;
+    let bytes = store::unseal(state.cipher.as_ref(), meta, bytes)?;
+    let body = String::from_utf8_lossy(&bytes).into_owned();
+    // A `lang` other than markdown settles it: the paste is code, whatever
+    // its lines look like.
+    let code_lang = meta
+        .lang
+        .clone()
+        .filter(|lang| !force_markdown && !highlight::is_markdown(lang));
+    let markdown = code_lang.is_none()
+        && (force_markdown
+            || meta.lang.is_some()
+            || meta.content_type.contains("markdown")
+            || meta.content_path().ends_with(".md")
+            || render::looks_like_markdown(&body));
+    let key = CacheKey {
+        id: meta.id.clone(),
+        sha256: meta.sha256.clone(),
+        kind: match (&code_lang, markdown) {
+            (Some(_), _) => "code",
+            (None, true) => "markdown",
+            (None, false) => "plain",
+        },
+    };
+    let html = if let Some(lang) = code_lang {
+        state
+            .renders
+            .render_cached(key, &body, move |text| highlight::render_code(text, &lang))
+            .await?
+    } else if markdown {
+        state
+            .renders
+            .render_cached(key, &body, render::render_markdown)
+            .await?
+    } else {
+        state
+            .renders
+            .render_cached(key, &body, |text| {
+                format!("<pre>{}</pre>", render::html_escape(text))
+            })
+            .await?
+    };
+    Ok((html, body))
+}
+
+async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    let report = readiness::check(state).await;
+    let status = if report.ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, axum::Json(report)).into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct StatusBody {
+    status: &'static str,
+    version: &'static str,
+    push: &'static str,
+    maintenance: bool,
+    self_test: Option<SelfTestReport>,
+}
+
+async fn statusz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let self_test = state.self_test.lock().ok().and_then(|last| last.clone());
+    let status = match &self_test {
+        Some(report) if !report.ok => "degraded",
+        _ => "ok",
+    };
+    axum::Json(StatusBody {
+        status,
+        version: env!("CARGO_PKG_VERSION"),
+        push: push_mode_label(state.cfg.push),
+        maintenance: state.ops.maintenance(),
+        self_test,
+    })
+}
+
+/// `--verify-reads` on the stored bytes of a paste about to be served.
+pub(crate) fn verify_read(state: &AppState, meta: &PasteMeta, bytes: &[u8]) -> AppResult<()> {
+    if verify_due(state) {
+        store::verify_integrity(meta, bytes)?;
+    }
+    Ok(())
+}
+
+fn verify_due(state: &AppState) -> bool {
+    match state.cfg.verify_reads {
+        VerifyMode::Off => false,
+        VerifyMode::Always => true,
+        VerifyMode::Sampled => {
+            let n = state.read_counter.fetch_add(1, Ordering::Relaxed);
+            n.is_multiple_of(state.cfg.verify_sample_every.max(1))
+        }
+    }
+}
+
+async fn enroll_request(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client): ClientIp,
+    body: axum::body::Bytes,
+) -> AppResult<impl IntoResponse> {
+    check_client(&state, client).await?;
+    let req: enroll::EnrollRequest = serde_json::from_slice(&body)
+        .map_err(|e| AppError::BadRequest(format!("invalid enrollment request: {e}")))?;
+    let ticket = enroll::request(&state.api_keys, &state.kv, req, Some(client))?;
+    Ok((StatusCode::CREATED, axum::Json(ticket)))
+}
+
+async fn enroll_poll(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let token = headers
+        .get(enroll::ENROLL_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AppError::Unauthorized("missing enrollment token".to_string()))?;
+    let poll = enroll::poll(&state.kv, &id, token)?;
+    let status = if poll.status == enroll::EnrollStatus::Pending {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::OK
+    };
+    let mut response = (status, axum::Json(poll)).into_response();
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+    Ok(response)
+}
+
+async fn admin_enrollments(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    Ok(axum::Json(enroll::pending(&state.kv)?))
+}
+
+async fn admin_approve_enrollment(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let body: ApproveBody = serde_json::from_slice(&body)
+        .map_err(|e| AppError::BadRequest(format!("invalid approval: {e}")))?;
+    Ok(axum::Json(enroll::approve(
+        &state.api_keys,
+        &state.kv,
+        &body.code,
+    )?))
+}
+
+async fn admin_reject_enrollment(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    enroll::reject(&state.kv, &id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn admin_login_page(State(state): State<Arc<AppState>>) -> Html<String> {
+    Html(render::render_admin_login(&state.cfg.base_path, None))
+}
+
+async fn admin_login(
+    State(state): State<Arc<AppState>>,
+    axum::Form(form): axum::Form<LoginForm>,
+) -> AppResult<Response> {
+    let token = match auth::start_admin_session(&state.api_keys, &state.kv, &form.key) {
+        Ok(token) => token,
+        Err(AppError::Unauthorized(_)) | Err(AppError::Forbidden(_)) => {
+            let page = render::render_admin_login(
+                &state.cfg.base_path,
+                Some("That key is not an admin key."),
+            );
+            return Ok((StatusCode::UNAUTHORIZED, Html(page)).into_response());
+        }
+        Err(err) => return Err(err),
+    };
+    let mut response = redirect_to(&state, "/admin")?;
+    let cookie = format!(
+        "{}={token}; HttpOnly; SameSite=Strict; Path={}; Max-Age={}",
+        auth::SESSION_COOKIE,
+        link(&state, "/admin"),
+        auth::SESSION_TTL.as_secs()
+    );
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        header::HeaderValue::from_str(&cookie)
+            .map_err(|e| AppError::internal(format!("invalid session cookie: {e}")))?,
+    );
+    Ok(response)
+}
+
+async fn admin_logout(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    auth::end_admin_session(&state.kv, &headers)?;
+    let mut response = redirect_to(&state, "/admin/login")?;
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        header::HeaderValue::from_str(&format!(
+            "{}=; HttpOnly; SameSite=Strict; Path={}; Max-Age=0",
+            auth::SESSION_COOKIE,
+            link(&state, "/admin"),
+        ))
+        .map_err(|e| AppError::internal(format!("invalid session cookie: {e}")))?,
+    );
+    Ok(response)
+}
+
+/// `--require-token-for-read`: with only `--token` set, reads need it too,
+/// in `X-Paste-Token` or as the password of HTTP Basic credentials (WebDAV,
+/// git). A key file already gates reads by scope.
+pub(crate) fn check_read_token(state: &AppState, headers: &HeaderMap) -> AppResult<()> {
+    if !state.cfg.require_token_for_read || state.api_keys.enabled() {
+        return Ok(());
+    }
+    let provided = headers
+        .get("X-Paste-Token")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| auth::basic_password(headers));
+    state.token.verify(provided.as_deref())
+}
+
+async fn require_read_token(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match check_read_token(&state, req.headers()) {
+        Ok(()) => next.run(req).await,
+        Err(err) => err.into_response(),
+    }
+}
+
+/// `--protect-ui`: pages need the token, a `paste:read` key or a login.
+/// Browsers asking for a page are sent to `/login` and brought back after.
+async fn require_ui_login(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match auth::authorize_ui(&state.api_keys, &state.kv, &state.token, req.headers()) {
+        Ok(()) => next.run(req).await,
+        Err(AppError::Unauthorized(_)) if accepts_html(req.headers()) => {
+            let back = req.uri().path_and_query().map_or("/", |p| p.as_str());
+            redirect_to(
+                &state,
+                &format!("/login?next={}", webdav::percent_encode(back)),
+            )
+            .unwrap_or_else(IntoResponse::into_response)
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+/// Where `/login` may send someone back to: a path on this server, never
+/// another host.
+fn login_target(next: Option<&str>) -> &str {
+    match next {
+        Some(path) if path.starts_with('/') && !path.starts_with("//") && !path.contains('\\') => {
+            path
+        }
+        _ => "/",
+    }
+}
+
+fn login_credential(state: &AppState) -> &'static str {
+    if state.api_keys.enabled() {
+        "API key"
+    } else {
+        "Token"
+    }
+}
+
+async fn login_page(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<LoginParams>,
+) -> Html<String> {
+    Html(render::render_login(
+        &state.cfg.base_path,
+        login_credential(&state),
+        login_target(params.next.as_deref()),
+        None,
+    ))
+}
+
+async fn login(
+    State(state): State<Arc<AppState>>,
+    axum::Form(form): axum::Form<UiLoginForm>,
+) -> AppResult<Response> {
+    let next = login_target(Some(&form.next));
+    let session = match auth::start_ui_session(&state.api_keys, &state.kv, &state.token, &form.key)
+    {
+        Ok(session) => session,
+        Err(AppError::Unauthorized(_)) | Err(AppError::Forbidden(_)) => {
+            let page = render::render_login(
+                &state.cfg.base_path,
+                login_credential(&state),
+                next,
+                Some("That did not work; check it and try again."),
+            );
+            return Ok((StatusCode::UNAUTHORIZED, Html(page)).into_response());
+        }
+        Err(err) => return Err(err),
+    };
+    let mut response = redirect_to(&state, next)?;
+    insert_ui_cookie(&mut response, &state, &session, auth::SESSION_TTL.as_secs())?;
+    Ok(response)
+}
+
+async fn logout(State(state): State<Arc<AppState>>, headers: HeaderMap) -> AppResult<Response> {
+    auth::end_ui_session(&state.kv, &headers)?;
+    let mut response = redirect_to(&state, "/login")?;
+    insert_ui_cookie(&mut response, &state, "", 0)?;
+    Ok(response)
+}
+
+/// `Lax` rather than `Strict`, so a paste link opened from chat or mail
+/// still arrives logged in.
+fn insert_ui_cookie(
+    response: &mut Response,
+    state: &AppState,
+    value: &str,
+    max_age: u64,
+) -> AppResult<()> {
+    let path = if state.cfg.base_path.is_empty() {
+        "/"
+    } else {
+        &state.cfg.base_path
+    };
+    let cookie = format!(
+        "{}={value}; HttpOnly; SameSite=Lax; Path={path}; Max-Age={max_age}",
+        auth::UI_SESSION_COOKIE,
+    );
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        header::HeaderValue::from_str(&cookie)
+            .map_err(|e| AppError::internal(format!("invalid session cookie: {e}")))?,
+    );
+    Ok(())
+}
+
+/// Keeps server errors for the admin dashboard.
+async fn record_errors(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let response = next.run(req).await;
+    if response.status().is_server_error() {
+        let message = response
+            .extensions()
+            .get::<ErrorMessage>()
+            .map(|m| m.0.clone())
+            .unwrap_or_default();
+        state.ops.record_error(RecordedError {
+            at: time::OffsetDateTime::now_utc(),
+            method,
+            path,
+            status: response.status().as_u16(),
+            message,
+        });
+    }
+    response
+}
+
+async fn admin_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    Ok(axum::Json(admin::status(&state)?))
+}
+
+/// Usage per configured key, kept across restarts.
+async fn admin_keys(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    Ok(axum::Json(
+        serde_json::json!({ "keys": state.api_keys.usage() }),
+    ))
+}
+
+/// Shuts a key off without removing it, so its name and usage stay on
+/// record.
+async fn admin_disable_key(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let usage = state.api_keys.set_enabled(&name, false)?;
+    info!("api key {name} disabled");
+    Ok(axum::Json(usage))
+}
+
+async fn admin_enable_key(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let usage = state.api_keys.set_enabled(&name, true)?;
+    info!("api key {name} enabled");
+    Ok(axum::Json(usage))
+}
+
+/// Replaces a shared token. Without a key file there is no admin key, so
+/// a token in effect authorizes the rotation, and is the one replaced
+/// unless the body names another.
+async fn admin_rotate_token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> AppResult<impl IntoResponse> {
+    let provided = headers.get("X-Paste-Token").and_then(|v| v.to_str().ok());
+    let caller = if state.api_keys.enabled() {
+        auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+        None
+    } else {
+        state.token.verify(provided)?;
+        provided
+    };
+    let body: RotateTokenBody = if body.is_empty() {
+        RotateTokenBody::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| AppError::BadRequest(format!("invalid rotation: {e}")))?
+    };
+    let grace = match body.grace.as_deref() {
+        Some(raw) => config::parse_duration(raw).map_err(AppError::BadRequest)?,
+        None => token::DEFAULT_GRACE,
+    };
+    let target = body.replace.as_deref().or(caller);
+    let rotated = state.token.rotate(target, body.token, grace)?;
+    info!(
+        "shared token rotated; the old one works until {}",
+        rotated.previous_valid_until
+    );
+    Ok(axum::Json(rotated))
+}
+
+async fn admin_digest_export(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<DigestParams>,
+) -> AppResult<Response> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let day = digest::parse_day(params.day.as_deref())?;
+    let summary = digest::build(&state.paths.repo, day)?;
+    match params.format.as_deref() {
+        None | Some("json") => Ok(axum::Json(summary).into_response()),
+        Some("markdown" | "md") => Ok((
+            [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            digest::to_markdown(&summary),
+        )
+            .into_response()),
+        Some(other) => Err(AppError::BadRequest(format!(
+            "unknown format '{other}'; use json or markdown"
+        ))),
+    }
+}
+
+/// Runs the digest job now: stores the digest paste and sends notifications.
+async fn admin_digest_run(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<DigestParams>,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let day = digest::parse_day(params.day.as_deref())?;
+    let (digest, paste_id) = digest::run(&state, day).await?;
+    Ok((
+        StatusCode::CREATED,
+        axum::Json(DigestRun { digest, paste_id }),
+    ))
+}
+
+/// Moves pastes older than `older_than_days` into a tarball under the
+/// archive directory and prunes them from the repo.
+async fn admin_archive(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<ArchiveParams>,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    if params.older_than_days == 0 {
+        return Err(AppError::BadRequest(
+            "older_than_days must be at least 1".to_string(),
+        ));
+    }
+    let report = tokio::task::spawn_blocking(move || {
+        let dest = archive::default_dir(&state.cfg, &state.paths);
+        archive::run(&state, params.older_than_days, &dest)
+    })
+    .await
+    .map_err(|e| AppError::internal(format!("archive task failed: {e}")))??;
+    Ok(axum::Json(report))
+}
+
+async fn admin_gc(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let report = tokio::task::spawn_blocking(move || gc::run(&state))
+        .await
+        .map_err(|e| AppError::internal(format!("gc task failed: {e}")))??;
+    Ok(axum::Json(report))
+}
+
+/// Same as `SIGHUP`: re-reads `--config-file` and `--api-keys-file`.
+async fn admin_reload(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let report = tokio::task::spawn_blocking(move || settings::reload(&state))
+        .await
+        .map_err(|e| AppError::internal(format!("reload task failed: {e}")))??;
+    Ok(axum::Json(report))
+}
+
+#[derive(Debug, Serialize)]
+struct BackupView {
+    configured: bool,
+    target: Option<String>,
+    format: Option<backup::BackupFormat>,
+    interval_secs: Option<u64>,
+    #[serde(flatten)]
+    status: backup::BackupStatus,
+}
+
+async fn admin_backup_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    let target = state.backup.as_ref();
+    Ok(axum::Json(BackupView {
+        configured: target.is_some(),
+        target: target.map(backup::S3Target::location),
+        format: target.map(|t| t.format),
+        interval_secs: target.map(|t| t.interval.as_secs()),
+        status: backup::read_status(&state.kv)?,
+    }))
+}
+
+/// Runs a backup now and waits for the upload.
+async fn admin_backup_run(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    auth::authorize_admin(&state.api_keys, &state.kv, &headers)?;
+    Ok(axum::Json(backup::run_once(&state).await?))
+}
+
+fn dashboard_page(state: &AppState, notice: Option<&str>) -> AppResult<Response> {
+    let status = admin::status(state)?;
+    Ok(Html(render::render_admin_dashboard(
+        &state.cfg.base_path,
+        &status,
+        notice,
+    ))
+    .into_response())
+}
+
+async fn admin_dashboard(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    admin_page(&state, &headers, || dashboard_page(&state, None))
+}
+
+async fn admin_reload_keys(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    admin_page(&state, &headers, || match state.api_keys.reload() {
+        Ok(count) => dashboard_page(&state, Some(&format!("Reloaded {count} API keys."))),
+        Err(err) => dashboard_page(&state, Some(&format!("Reload failed: {err:?}"))),
+    })
+}
+
+async fn admin_maintenance(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Form(form): axum::Form<MaintenanceForm>,
+) -> AppResult<Response> {
+    admin_page(&state, &headers, || {
+        state.ops.set_maintenance(form.on);
+        let notice = if form.on {
+            "Maintenance mode on: writes return 503."
+        } else {
+            "Maintenance mode off."
+        };
+        dashboard_page(&state, Some(notice))
+    })
+}
+
+async fn admin_sync(State(state): State<Arc<AppState>>, headers: HeaderMap) -> AppResult<Response> {
+    admin_page(&state, &headers, || match admin::sync_now(&state) {
+        Ok(()) => dashboard_page(&state, Some("Pushed to remote.")),
+        Err(AppError::ServiceUnavailable(m)) | Err(AppError::Conflict(m)) => {
+            dashboard_page(&state, Some(&m))
+        }
+        Err(err) => Err(err),
+    })
+}
+
+/// Runs `f` for a logged-in operator, sending everyone else to the login form.
+fn admin_page(
+    state: &AppState,
+    headers: &HeaderMap,
+    f: impl FnOnce() -> AppResult<Response>,
+) -> AppResult<Response> {
+    match auth::authorize_admin(&state.api_keys, &state.kv, headers) {
+        Ok(()) => f(),
+        Err(AppError::Unauthorized(_)) => redirect_to(state, "/admin/login"),
+        Err(err) => Err(err),
+    }
+}
+
+fn enroll_page(state: &AppState, notice: Option<&str>) -> AppResult<Response> {
+    let pending = enroll::pending(&state.kv)?;
+    Ok(Html(render::render_enrollments(
+        &state.cfg.base_path,
+        &pending,
+        notice,
+    ))
+    .into_response())
+}
+
+async fn admin_enroll_page(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    admin_page(&state, &headers, || enroll_page(&state, None))
+}
+
+async fn admin_enroll_approve(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Form(form): axum::Form<ApproveBody>,
+) -> AppResult<Response> {
+    admin_page(&state, &headers, || {
+        match enroll::approve(&state.api_keys, &state.kv, &form.code) {
+            Ok(approved) => enroll_page(&state, Some(&format!("Approved {}.", approved.name))),
+            Err(AppError::NotFound(m)) => enroll_page(&state, Some(&m)),
+            Err(err) => Err(err),
+        }
+    })
+}
+
+async fn admin_enroll_reject(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    admin_page(&state, &headers, || {
+        enroll::reject(&state.kv, &id)?;
+        redirect_to(&state, "/admin/enroll")
+    })
+}
+
+fn client_ip(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> IpAddr {
+    addr.ip().to_canonical()
+}
+
+async fn check_client(state: &AppState, client: IpAddr) -> AppResult<()> {
+    hostrules::check_client(&state.host_rules, &state.cfg.allow_cidr, client).await
+}
+
+/// `--public-url`, or failing that a guess from the `Host` header.
+fn request_origin(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(origin) = &state.cfg.public_url {
+        return origin.clone();
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .unwrap_or("localhost");
+    format!("http://{host}")
+}
+
+/// `path` under `--base-url` and this repo's `--namespace` mount, if any.
+fn link(state: &AppState, path: &str) -> String {
+    format!("{}{path}", state.cfg.base_path)
+}
+
+/// [`link`], made absolute with `--public-url` when it is set.
+fn public_link(state: &AppState, path: &str) -> String {
+    let origin = state.cfg.public_url.as_deref().unwrap_or_default();
+    format!("{origin}{}", link(state, path))
+}
+
+fn redirect_to(state: &AppState, path: &str) -> AppResult<Response> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::FOUND;
+    let location = header::HeaderValue::from_str(&link(state, path))
+        .map_err(|e| AppError::internal(format!("invalid redirect location: {e}")))?;
+    response.headers_mut().insert(header::LOCATION, location);
+    Ok(response)
+}
+
+/// Checks a write and names the key that made it, if keys are in use.
+fn authorize_create(state: &AppState, headers: &HeaderMap) -> AppResult<Option<String>> {
+    if state.ops.maintenance() {
+        return Err(AppError::ServiceUnavailable(
+            "server is in maintenance mode; writes are paused".to_string(),
+        ));
+    }
+    if state.api_keys.enabled() {
+        return auth::authorize_caller(&state.api_keys, headers, Scope::PasteCreate).map(Some);
+    }
+    let provided_token = headers.get("X-Paste-Token").and_then(|v| v.to_str().ok());
+    state.token.verify(provided_token)?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ip_from_connect_info() {
         let addr: SocketAddr = "192.168.1.2:4321".parse().expect("addr");
         assert_eq!(
             client_ip(ConnectInfo(addr)).to_string(),
             "192.168.1.2".to_string()
         );
+        let mapped: SocketAddr = "[::ffff:192.168.1.2]:4321".parse().expect("addr");
+        assert_eq!(client_ip(ConnectInfo(mapped)).to_string(), "192.168.1.2");
+    }
+
+    #[test]
+    fn login_only_returns_to_local_paths() {
+        assert_eq!(login_target(Some("/p/01TEST?x=1")), "/p/01TEST?x=1");
+        assert_eq!(login_target(Some("//evil.example/")), "/");
+        assert_eq!(login_target(Some("/\\evil.example")), "/");
+        assert_eq!(login_target(Some("https://evil.example/")), "/");
+        assert_eq!(login_target(None), "/");
+    }
+
+    #[test]
+    fn parse_range_handles_common_forms() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some(Ok((0, 9))));
+        assert_eq!(parse_range("bytes=90-", 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range("bytes=-10", 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range("bytes=-500", 100), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=50-500", 100), Some(Ok((50, 99))));
+        assert_eq!(parse_range("bytes=100-", 100), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+        assert_eq!(parse_range("bytes=9-1", 100), None);
+    }
+
+    #[test]
+    fn inline_safe_excludes_active_content() {
+        assert!(is_inline_safe("text/plain; charset=utf-8"));
+        assert!(is_inline_safe("Application/JSON"));
+        assert!(!is_inline_safe("text/html"));
+        assert!(!is_inline_safe("image/svg+xml"));
+    }
+
+    #[test]
+    fn validators_prefer_if_none_match() {
+        let validators = Validators {
+            etag: "\"abc\"".to_string(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(1_000_000)),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, "\"zzz\", W/\"abc\"".parse().unwrap());
+        assert!(validators.is_fresh(&headers));
+
+        headers.insert(header::IF_NONE_MATCH, "\"zzz\"".parse().unwrap());
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(2_000_000))
+                .parse()
+                .unwrap(),
+        );
+        assert!(!validators.is_fresh(&headers));
+
+        headers.remove(header::IF_NONE_MATCH);
+        assert!(validators.is_fresh(&headers));
+        assert!(validators.range_applies(&headers));
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            httpdate::fmt_http_date(UNIX_EPOCH).parse().unwrap(),
+        );
+        assert!(!validators.is_fresh(&headers));
+
+        headers.insert(header::IF_RANGE, "\"abc\"".parse().unwrap());
+        assert!(validators.range_applies(&headers));
+        headers.insert(header::IF_RANGE, "W/\"abc\"".parse().unwrap());
+        assert!(!validators.range_applies(&headers));
+        headers.insert(
+            header::IF_RANGE,
+            httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(1_000_000))
+                .parse()
+                .unwrap(),
+        );
+        assert!(validators.range_applies(&headers));
     }
 }
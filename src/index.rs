@@ -0,0 +1,595 @@
+//! In-memory copy of every paste's metadata and star count. It is loaded once
+//! at startup and updated by each write path after its commit lands, so the
+//! dashboard and `/api/v1/recent` are answered without touching the disk.
+//!
+//! Commits made behind the server's back, such as a `git pull` in the repo,
+//! are picked up by [`MetaIndex::sync`], which readers call first.
+//!
+//! Metas in the repo carry no `commit`: a commit cannot name itself. The
+//! index keeps each paste's last commit beside its meta instead, recorded by
+//! the write that made it or found by one `git log` walk at startup, and
+//! looked up per paste only for pastes that walk has not seen.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::Path,
+    sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::{
+    auth,
+    config::ServeCmd,
+    errors::AppResult,
+    gitops,
+    kv::Kv,
+    stars, store,
+    types::{PasteMeta, RecentItem},
+};
+
+pub type TagCounts = Vec<(String, usize)>;
+
+/// Totals over every paste, listed or not. Tags count listed pastes only,
+/// so unlisted ones stay out of sight, as on the dashboard.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub pastes: usize,
+    pub bytes: u64,
+    pub oldest: Option<OffsetDateTime>,
+    pub newest: Option<OffsetDateTime>,
+    pub tags: BTreeMap<String, usize>,
+}
+
+#[derive(Debug)]
+pub struct MetaIndex {
+    /// Random per process, so validators from a previous run never match.
+    epoch: String,
+    entries: RwLock<Entries>,
+    /// The `HEAD` the entries match, as of the last [`MetaIndex::sync`].
+    head: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_id: HashMap<String, PasteMeta>,
+    newest: BTreeSet<(Reverse<OffsetDateTime>, String)>,
+    /// Pastes by content hash, newest first.
+    by_sha256: HashMap<String, BTreeSet<(Reverse<OffsetDateTime>, String)>>,
+    /// Tags of listed pastes that nothing supersedes.
+    tags_current: HashMap<String, usize>,
+    /// Tags of all listed pastes.
+    tags_listed: HashMap<String, usize>,
+    stars: HashMap<String, usize>,
+    /// Last commit that touched each paste's file, for the pastes it is
+    /// known for.
+    commits: HashMap<String, String>,
+    generation: u64,
+}
+
+impl Entries {
+    /// Stores `meta`, moving its `commit`, if any, into `commits`. A meta
+    /// without one keeps the known commit while its file is unchanged, as
+    /// after a commit that only rewrote the meta.
+    fn insert(&mut self, mut meta: PasteMeta) {
+        let unchanged = self
+            .by_id
+            .get(&meta.id)
+            .is_some_and(|old| old.path == meta.path && old.sha256 == meta.sha256);
+        let kept = self.commits.get(&meta.id).filter(|_| unchanged).cloned();
+        self.remove(&meta.id);
+        if let Some(commit) = Some(std::mem::take(&mut meta.commit))
+            .filter(|c| !c.is_empty())
+            .or(kept)
+        {
+            self.commits.insert(meta.id.clone(), commit);
+        }
+        self.count_tag(&meta, true);
+        self.newest
+            .insert((Reverse(meta.created_at), meta.id.clone()));
+        self.by_sha256
+            .entry(meta.sha256.clone())
+            .or_default()
+            .insert((Reverse(meta.created_at), meta.id.clone()));
+        self.by_id.insert(meta.id.clone(), meta);
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some(old) = self.by_id.remove(id) else {
+            return;
+        };
+        self.commits.remove(id);
+        self.count_tag(&old, false);
+        let key = (Reverse(old.created_at), old.id);
+        if let Some(ids) = self.by_sha256.get_mut(&old.sha256) {
+            ids.remove(&key);
+            if ids.is_empty() {
+                self.by_sha256.remove(&old.sha256);
+            }
+        }
+        self.newest.remove(&key);
+    }
+
+    /// Drops the cached commits of pastes whose file is among `paths`.
+    fn forget_commits(&mut self, paths: &[String]) {
+        let Self { by_id, commits, .. } = self;
+        commits.retain(|id, _| {
+            by_id
+                .get(id)
+                .is_some_and(|meta| !paths.contains(&meta.path))
+        });
+    }
+
+    fn listed(&self, n: usize, tag: Option<&str>, include_superseded: bool) -> Vec<RecentItem> {
+        self.select(n, |meta| in_recent(meta, tag, include_superseded))
+    }
+
+    /// The newest `n` pastes that `keep` accepts.
+    fn select(&self, n: usize, keep: impl Fn(&PasteMeta) -> bool) -> Vec<RecentItem> {
+        self.newest
+            .iter()
+            .filter_map(|(_, id)| self.by_id.get(id))
+            .filter(|meta| keep(meta))
+            .take(n)
+            .map(|meta| {
+                let stars = self.stars.get(&meta.id).copied().unwrap_or_default();
+                RecentItem::from_meta(meta.clone(), stars)
+            })
+            .collect()
+    }
+
+    fn count_tag(&mut self, meta: &PasteMeta, add: bool) {
+        let Some(tag) = meta.tag.as_deref().filter(|t| !t.trim().is_empty()) else {
+            return;
+        };
+        if !meta.visibility.is_listed() {
+            return;
+        }
+        let mut maps = vec![&mut self.tags_listed];
+        if meta.superseded_by.is_none() {
+            maps.push(&mut self.tags_current);
+        }
+        for counts in maps {
+            if add {
+                *counts.entry(tag.to_string()).or_default() += 1;
+            } else if let Some(n) = counts.get_mut(tag) {
+                *n -= 1;
+                if *n == 0 {
+                    counts.remove(tag);
+                }
+            }
+        }
+    }
+}
+
+/// Whether a paste shows up in recents: listed, on `tag` if one is given,
+/// and current unless superseded pastes are wanted too.
+fn in_recent(meta: &PasteMeta, tag: Option<&str>, include_superseded: bool) -> bool {
+    meta.visibility.is_listed()
+        && (include_superseded || meta.superseded_by.is_none())
+        && (tag.is_none() || meta.tag.as_deref() == tag)
+}
+
+impl MetaIndex {
+    pub fn new(metas: Vec<PasteMeta>, star_counts: HashMap<String, usize>) -> Self {
+        let mut entries = Entries {
+            stars: star_counts,
+            ..Entries::default()
+        };
+        for meta in metas {
+            entries.insert(meta);
+        }
+        Self {
+            epoch: auth::random_token(4),
+            entries: RwLock::new(entries),
+            head: Mutex::default(),
+        }
+    }
+
+    /// Reads every meta in the repo and the star counts from the kv store,
+    /// then backfills commits with one walk of the history.
+    pub fn load(repo: &Path, kv: &Kv) -> AppResult<Self> {
+        let head = gitops::head_oid(repo);
+        let index = Self::new(store::read_all_metas(repo)?, stars::counts(kv)?);
+        *index.head.lock().unwrap_or_else(PoisonError::into_inner) = head;
+        index.backfill_commits(repo);
+        Ok(index)
+    }
+
+    /// Fills in the commit of every paste that has none, reading the history
+    /// once from the newest commit back until each paste's file is found.
+    pub fn backfill_commits(&self, repo: &Path) {
+        let wanted: HashSet<String> = {
+            let entries = self.read();
+            entries
+                .by_id
+                .values()
+                .filter(|meta| !entries.commits.contains_key(&meta.id))
+                .map(|meta| meta.path.clone())
+                .collect()
+        };
+        if wanted.is_empty() {
+            return;
+        }
+        let found = match gitops::last_commits(repo, &wanted) {
+            Ok(found) => found,
+            Err(err) => {
+                warn!("backfill paste commits: {err:?}");
+                return;
+            }
+        };
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        let Entries { by_id, commits, .. } = &mut *entries;
+        for meta in by_id.values() {
+            if let Some(commit) = found.get(&meta.path) {
+                commits
+                    .entry(meta.id.clone())
+                    .or_insert_with(|| commit.clone());
+            }
+        }
+    }
+
+    /// Catches up with the repo when `HEAD` has moved since the last call:
+    /// the metas the new commits touched are read again, or every meta when
+    /// the old `HEAD` is gone. Costs two small file reads when nothing moved.
+    /// The server's own commits move `HEAD` too, so each is read back once.
+    pub fn sync(&self, repo: &Path, cfg: &ServeCmd) {
+        let current = gitops::head_oid(repo);
+        let mut head = self.head.lock().unwrap_or_else(PoisonError::into_inner);
+        if *head == current {
+            return;
+        }
+        let changed = match (head.as_deref(), current.as_deref()) {
+            (Some(from), Some(to)) => gitops::changed_paths(repo, cfg, from, to).ok(),
+            _ => None,
+        };
+        match changed {
+            Some(paths) => {
+                let ids: Vec<&str> = paths
+                    .iter()
+                    .filter_map(|p| p.strip_prefix("meta/")?.strip_suffix(".json"))
+                    .collect();
+                self.refresh(repo, &ids);
+                self.write().forget_commits(&paths);
+            }
+            None => match store::read_all_metas(repo) {
+                Ok(metas) => self.replace(metas),
+                Err(err) => {
+                    warn!("reload paste index: {err:?}");
+                    return;
+                }
+            },
+        }
+        *head = current;
+    }
+
+    /// Swaps in a fresh set of metas, keeping star counts.
+    fn replace(&self, metas: Vec<PasteMeta>) {
+        let mut entries = self.write();
+        let stars = std::mem::take(&mut entries.stars);
+        let generation = entries.generation;
+        *entries = Entries {
+            stars,
+            generation,
+            ..Entries::default()
+        };
+        for meta in metas {
+            entries.insert(meta);
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Entries> {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Entries> {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        entries.generation += 1;
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Changes with every update; a strong validator for anything rendered
+    /// from the index.
+    pub fn etag(&self) -> String {
+        format!("\"idx-{}-{}\"", self.epoch, self.read().generation)
+    }
+
+    pub fn upsert(&self, meta: PasteMeta) {
+        self.write().insert(meta);
+    }
+
+    /// [`MetaIndex::upsert`] for a write that just committed the paste's
+    /// file as `commit`.
+    pub fn upsert_committed(&self, mut meta: PasteMeta, commit: &str) {
+        meta.commit = commit.to_string();
+        self.upsert(meta);
+    }
+
+    /// The last commit of a paste, when known and the index holds the same
+    /// content as `sha256`.
+    pub fn commit_of(&self, id: &str, sha256: &str) -> Option<String> {
+        let entries = self.read();
+        entries
+            .by_id
+            .get(id)
+            .filter(|meta| meta.sha256 == sha256)
+            .and_then(|_| entries.commits.get(id).cloned())
+    }
+
+    /// Id of the newest paste whose content hashes to `sha256` and that
+    /// `keep` accepts.
+    pub fn newest_with_sha256(
+        &self,
+        sha256: &str,
+        keep: impl Fn(&PasteMeta) -> bool,
+    ) -> Option<String> {
+        let entries = self.read();
+        entries
+            .by_sha256
+            .get(sha256)?
+            .iter()
+            .filter_map(|(_, id)| entries.by_id.get(id))
+            .find(|meta| keep(meta))
+            .map(|meta| meta.id.clone())
+    }
+
+    pub fn remove<S: AsRef<str>>(&self, ids: &[S]) {
+        let mut entries = self.write();
+        for id in ids {
+            entries.remove(id.as_ref());
+            entries.stars.remove(id.as_ref());
+        }
+    }
+
+    /// Re-reads the metas of `ids` from the repo, dropping any that are gone.
+    /// For writes that rewrite metas of pastes the caller does not hold.
+    pub fn refresh<S: AsRef<str>>(&self, repo: &Path, ids: &[S]) {
+        let metas: Vec<(&str, Option<PasteMeta>)> = ids
+            .iter()
+            .map(|id| {
+                let id = id.as_ref();
+                let meta = std::fs::read(repo.join("meta").join(format!("{id}.json")))
+                    .ok()
+                    .and_then(|data| serde_json::from_slice(&data).ok());
+                (id, meta)
+            })
+            .collect();
+        let mut entries = self.write();
+        for (id, meta) in metas {
+            match meta {
+                Some(meta) => entries.insert(meta),
+                None => entries.remove(id),
+            }
+        }
+    }
+
+    pub fn set_stars(&self, id: &str, count: usize) {
+        let mut entries = self.write();
+        if count == 0 {
+            entries.stars.remove(id);
+        } else {
+            entries.stars.insert(id.to_string(), count);
+        }
+    }
+
+    pub fn totals(&self) -> Totals {
+        let entries = self.read();
+        Totals {
+            pastes: entries.by_id.len(),
+            bytes: entries.by_id.values().map(|m| m.size as u64).sum(),
+            oldest: entries.newest.last().map(|(Reverse(at), _)| *at),
+            newest: entries.newest.first().map(|(Reverse(at), _)| *at),
+            tags: entries
+                .tags_listed
+                .iter()
+                .map(|(tag, n)| (tag.clone(), *n))
+                .collect(),
+        }
+    }
+
+    /// Listed pastes newest first, optionally one tag only, with tag counts.
+    /// Items carry no commit; see [`MetaIndex::recent_with_commits`].
+    pub fn recent(
+        &self,
+        n: usize,
+        tag: Option<&str>,
+        include_superseded: bool,
+    ) -> (Vec<RecentItem>, TagCounts) {
+        let entries = self.read();
+        let items = entries.listed(n, tag, include_superseded);
+        let counts = if include_superseded {
+            &entries.tags_listed
+        } else {
+            &entries.tags_current
+        };
+        let mut tags: TagCounts = counts.iter().map(|(t, n)| (t.clone(), *n)).collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        (items, tags)
+    }
+
+    /// The `/api/v1/recent` listing: [`MetaIndex::recent`], optionally one
+    /// owner's pastes only, plus the last commit of each paste. A commit not
+    /// known yet costs one `git log`.
+    pub fn recent_with_commits(
+        &self,
+        repo: &Path,
+        cfg: &ServeCmd,
+        n: usize,
+        tag: Option<&str>,
+        owner: Option<&str>,
+        include_superseded: bool,
+    ) -> AppResult<Vec<RecentItem>> {
+        self.with_commits(repo, cfg, |entries| {
+            entries.select(n, |meta| {
+                in_recent(meta, tag, include_superseded)
+                    && (owner.is_none() || meta.owner.as_deref() == owner)
+            })
+        })
+    }
+
+    /// Every paste `owner` created, newest first, whatever its visibility
+    /// and whether or not something supersedes it.
+    pub fn owned_with_commits(
+        &self,
+        repo: &Path,
+        cfg: &ServeCmd,
+        owner: &str,
+        n: usize,
+    ) -> AppResult<Vec<RecentItem>> {
+        self.with_commits(repo, cfg, |entries| {
+            entries.select(n, |meta| meta.owner.as_deref() == Some(owner))
+        })
+    }
+
+    fn with_commits(
+        &self,
+        repo: &Path,
+        cfg: &ServeCmd,
+        pick: impl FnOnce(&Entries) -> Vec<RecentItem>,
+    ) -> AppResult<Vec<RecentItem>> {
+        let (mut items, generation) = {
+            let entries = self.read();
+            let mut items = pick(&entries);
+            for item in &mut items {
+                if let Some(commit) = entries.commits.get(&item.id) {
+                    item.commit.clone_from(commit);
+                }
+            }
+            (items, entries.generation)
+        };
+        let mut found = Vec::new();
+        for item in items.iter_mut().filter(|item| item.commit.is_empty()) {
+            item.commit = store::lookup_commit(repo, cfg, &item.path)?;
+            found.push((item.id.clone(), item.commit.clone()));
+        }
+        // Not a change to the listing, so no new generation; and skipped if
+        // a write landed meanwhile, since the commit may predate it.
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        if entries.generation == generation {
+            entries.commits.extend(found);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+    use crate::types::Visibility;
+
+    fn meta(id: &str, age_mins: i64, tag: Option<&str>) -> PasteMeta {
+        PasteMeta {
+            created_at: OffsetDateTime::UNIX_EPOCH + Duration::days(1)
+                - Duration::minutes(age_mins),
+            path: format!("pastes/{id}__x.txt"),
+            size: 1,
+            tag: tag.map(str::to_string),
+            ..PasteMeta::for_test(id)
+        }
+    }
+
+    #[test]
+    fn updates_keep_order_tags_and_validators_in_step() {
+        let index = MetaIndex::new(
+            vec![meta("A", 3, Some("ci")), meta("B", 2, Some("ci"))],
+            HashMap::from([("A".to_string(), 2)]),
+        );
+        let before = index.etag();
+        let mut c = meta("C", 1, Some("ops"));
+        c.visibility = Visibility::Unlisted;
+        index.upsert(c);
+        assert_ne!(index.etag(), before);
+
+        let (items, tags) = index.recent(10, None, false);
+        let ids: Vec<&str> = items.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["B", "A"]);
+        assert_eq!(items[1].stars, 2);
+        assert_eq!(tags, [("ci".to_string(), 2)]);
+        let totals = index.totals();
+        assert_eq!((totals.pastes, totals.bytes), (3, 3));
+        assert_eq!(totals.tags, BTreeMap::from([("ci".to_string(), 2)]));
+        assert_eq!(totals.oldest, Some(meta("A", 3, None).created_at));
+        assert_eq!(totals.newest, Some(meta("C", 1, None).created_at));
+
+        let mut a = meta("A", 3, Some("ci"));
+        a.superseded_by = Some("B".to_string());
+        index.upsert(a);
+        index.set_stars("B", 1);
+        let (items, tags) = index.recent(10, Some("ci"), false);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].stars, 1);
+        assert_eq!(tags, [("ci".to_string(), 1)]);
+        assert_eq!(index.recent(10, None, true).1, [("ci".to_string(), 2)]);
+
+        index.remove(&["A", "B"]);
+        let (items, tags) = index.recent(10, None, true);
+        assert!(items.is_empty() && tags.is_empty());
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn sha256_lookups_follow_content_changes() {
+        let with_sha = |id: &str, age_mins: i64, sha256: &str| PasteMeta {
+            sha256: sha256.to_string(),
+            ..meta(id, age_mins, None)
+        };
+        let index = MetaIndex::new(
+            vec![with_sha("A", 3, "aa"), with_sha("B", 2, "aa")],
+            HashMap::new(),
+        );
+        assert_eq!(
+            index.newest_with_sha256("aa", |_| true).as_deref(),
+            Some("B")
+        );
+        assert_eq!(
+            index.newest_with_sha256("aa", |m| m.id == "A").as_deref(),
+            Some("A")
+        );
+
+        index.upsert(with_sha("B", 2, "bb"));
+        assert_eq!(
+            index.newest_with_sha256("aa", |_| true).as_deref(),
+            Some("A")
+        );
+        assert_eq!(
+            index.newest_with_sha256("bb", |_| true).as_deref(),
+            Some("B")
+        );
+        index.remove(&["A"]);
+        assert_eq!(index.newest_with_sha256("aa", |_| true), None);
+        assert!(!index.read().by_sha256.contains_key("aa"));
+    }
+
+    #[test]
+    fn commits_stay_until_the_paste_content_changes() {
+        let index = MetaIndex::new(vec![meta("A", 1, None)], HashMap::new());
+        assert!(index.commit_of("A", "").is_none());
+        index.upsert_committed(meta("A", 1, None), "c1");
+        assert_eq!(index.commit_of("A", "").as_deref(), Some("c1"));
+        assert!(index.recent(1, None, false).0[0].commit.is_empty());
+
+        let mut linked = meta("A", 1, None);
+        linked.superseded_by = Some("B".to_string());
+        index.upsert(linked);
+        assert_eq!(index.commit_of("A", "").as_deref(), Some("c1"));
+        assert!(index.commit_of("A", "other").is_none());
+
+        let mut appended = meta("A", 1, None);
+        appended.sha256 = "other".to_string();
+        index.upsert(appended);
+        assert!(index.commit_of("A", "other").is_none());
+    }
+}
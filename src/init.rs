@@ -0,0 +1,226 @@
+//! `lanpaste init`: everything a host needs before the first `serve`. It
+//! creates the data directory and repo, then writes a config file, an API
+//! key file with one admin key, and a systemd unit that starts `serve` with
+//! both. A file that already exists is kept, so a provisioning script can
+//! run it on every deploy.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::json;
+
+use crate::{
+    auth::{self, ApiKeyStore},
+    config::{InitCmd, ServeCmd},
+    errors::{AppError, AppResult},
+    preflight,
+    settings::Settings,
+};
+
+pub const CONFIG_FILE: &str = "config.json";
+pub const API_KEYS_FILE: &str = "api-keys.json";
+pub const UNIT_FILE: &str = "lanpaste.service";
+
+#[derive(Debug, Clone, Default)]
+pub struct InitReport {
+    pub dir: PathBuf,
+    pub written: Vec<PathBuf>,
+    pub kept: Vec<PathBuf>,
+    /// The admin key, when this run created the key file.
+    pub admin_key: Option<String>,
+    pub unit: PathBuf,
+}
+
+impl InitReport {
+    pub fn summary(&self) -> String {
+        let mut out = format!("initialized {}\n", self.dir.display());
+        for path in &self.written {
+            out.push_str(&format!("  wrote {}\n", path.display()));
+        }
+        for path in &self.kept {
+            out.push_str(&format!("  kept  {}\n", path.display()));
+        }
+        if let Some(key) = &self.admin_key {
+            out.push_str(&format!(
+                "admin API key (shown once; it is also in {API_KEYS_FILE}): {key}\n"
+            ));
+        }
+        let name = self
+            .unit
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| UNIT_FILE.to_string());
+        if self.unit.starts_with("/etc/systemd/system") {
+            out.push_str(&format!(
+                "start it with: sudo systemctl daemon-reload && sudo systemctl enable --now {name}\n"
+            ));
+        } else {
+            out.push_str(&format!(
+                "install the unit with: sudo cp {} /etc/systemd/system/ && sudo systemctl daemon-reload && sudo systemctl enable --now {name}\n",
+                self.unit.display()
+            ));
+        }
+        out
+    }
+}
+
+pub fn run(cmd: &InitCmd) -> AppResult<InitReport> {
+    let cfg = ServeCmd::offline(&cmd.dir, &cmd.git_author_name, &cmd.git_author_email)
+        .map_err(|e| AppError::internal(format!("build serve config: {e}")))?;
+    preflight::run_preflight(&cfg)?;
+    let dir = fs::canonicalize(&cmd.dir).map_err(|e| AppError::io("resolve --dir", e))?;
+    let mut report = InitReport {
+        dir: dir.clone(),
+        ..InitReport::default()
+    };
+
+    let config = dir.join(CONFIG_FILE);
+    let starter = json!({
+        "max_bytes": cfg.max_bytes,
+        "retain_days": null,
+        "retain_count": null,
+        "retain_max_bytes": null,
+        "log_level": null,
+    });
+    write_once(&config, &pretty(&starter)?, false, &mut report)?;
+
+    let keys = dir.join(API_KEYS_FILE);
+    let admin_key = auth::new_api_key();
+    let key_file = json!({
+        "keys": [{ "name": "admin", "key": admin_key, "scopes": ["*"] }],
+    });
+    if write_once(&keys, &pretty(&key_file)?, true, &mut report)? {
+        report.admin_key = Some(admin_key);
+    }
+
+    // Kept files must still be ones `serve` accepts.
+    ApiKeyStore::from_file(Some(&keys))?;
+    let mut check = cfg.clone();
+    check.config_file = Some(config.clone());
+    Settings::load(&check)?;
+
+    let exe = match &cmd.exe {
+        Some(exe) => exe.clone(),
+        None => std::env::current_exe().map_err(|e| AppError::io("locate lanpaste binary", e))?,
+    };
+    let unit = cmd.unit_path.clone().unwrap_or_else(|| dir.join(UNIT_FILE));
+    if let Some(parent) = unit.parent() {
+        fs::create_dir_all(parent).map_err(|e| AppError::io("create unit dir", e))?;
+    }
+    let text = unit_file(&exe, &dir, cmd, &keys, &config);
+    write_once(&unit, text.as_bytes(), false, &mut report)?;
+    report.unit = unit;
+    Ok(report)
+}
+
+/// Writes `bytes` to `path` unless it exists. Returns whether it wrote.
+/// `private` files are readable by their owner only.
+fn write_once(
+    path: &Path,
+    bytes: &[u8],
+    private: bool,
+    report: &mut InitReport,
+) -> AppResult<bool> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            report.kept.push(path.to_path_buf());
+            return Ok(false);
+        }
+        Err(err) => return Err(AppError::io(&format!("create {}", path.display()), err)),
+    };
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| AppError::io(&format!("write {}", path.display()), e))?;
+    report.written.push(path.to_path_buf());
+    Ok(true)
+}
+
+fn pretty(value: &serde_json::Value) -> AppResult<Vec<u8>> {
+    let mut bytes = serde_json::to_vec_pretty(value)
+        .map_err(|e| AppError::internal(format!("serialize starter file: {e}")))?;
+    bytes.push(b'\n');
+    Ok(bytes)
+}
+
+fn unit_file(exe: &Path, dir: &Path, cmd: &InitCmd, keys: &Path, config: &Path) -> String {
+    let exec = [
+        exe.display().to_string(),
+        "serve".to_string(),
+        "--dir".to_string(),
+        dir.display().to_string(),
+        "--bind".to_string(),
+        cmd.bind.to_string(),
+        "--api-keys-file".to_string(),
+        keys.display().to_string(),
+        "--config-file".to_string(),
+        config.display().to_string(),
+    ]
+    .iter()
+    .map(|arg| unit_arg(arg))
+    .collect::<Vec<_>>()
+    .join(" ");
+    let user = cmd
+        .user
+        .as_ref()
+        .map(|user| format!("User={user}\n"))
+        .unwrap_or_default();
+    format!(
+        "[Unit]
+Description=LAN Paste
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+ExecStart={exec}
+ExecReload=/bin/kill -HUP $MAINPID
+{user}Restart=on-failure
+NoNewPrivileges=true
+PrivateTmp=true
+ProtectSystem=strict
+ProtectHome=read-only
+ReadWritePaths={}
+
+[Install]
+WantedBy=multi-user.target
+",
+        unit_arg(&dir.display().to_string())
+    )
+}
+
+/// One argument in systemd's command-line syntax: `%` and `$` are escaped,
+/// and anything with spaces, quotes or backslashes is double-quoted.
+fn unit_arg(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '\'') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_arguments_survive_systemd_parsing() {
+        assert_eq!(unit_arg("/srv/lanpaste"), "/srv/lanpaste");
+        assert_eq!(unit_arg("/srv/my pastes"), "\"/srv/my pastes\"");
+        assert_eq!(unit_arg("/srv/50%$"), "/srv/50%%$$");
+        assert_eq!(unit_arg("a\"b\\c"), "\"a\\\"b\\\\c\"");
+    }
+}
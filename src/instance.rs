@@ -0,0 +1,260 @@
+//! What a server says about itself once it is listening: a banner in the
+//! log, `run/instance.json` for tools on the same host, and with
+//! `--startup-json` the same document as one line on stdout.
+//!
+//! The file is replaced at every start and not removed at exit, so readers
+//! should check that `pid` is still alive before trusting it.
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+use ulid::Ulid;
+
+use crate::{
+    config::VerifyMode,
+    errors::{AppError, AppResult},
+    mdns,
+    types::{AppPaths, AppState, push_mode_label},
+};
+
+pub const FILE_NAME: &str = "instance.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance {
+    pub version: String,
+    pub pid: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    /// Addresses actually bound, with any `:0` port resolved.
+    pub bind: Vec<SocketAddr>,
+    /// Where other machines reach the server, e.g. `http://192.168.1.20:8090/`.
+    pub base_url: String,
+    pub dir: PathBuf,
+    /// `api_keys`, `token` or `open`.
+    pub auth: String,
+    pub storage: Storage,
+    pub push: String,
+    pub remote: String,
+    /// Optional subsystems that are switched on, by flag name.
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Storage {
+    pub backend: String,
+    pub repo: PathBuf,
+    /// Paste bodies sealed with `--encrypt-key-file`.
+    pub encrypted: bool,
+}
+
+impl Instance {
+    pub fn describe(state: &AppState, bound: &[SocketAddr]) -> Self {
+        let cfg = &state.cfg;
+        let auth = if state.api_keys.enabled() {
+            "api_keys"
+        } else if cfg.has_token() {
+            "token"
+        } else {
+            "open"
+        };
+        let live = state.settings.current();
+        let features = [
+            ("compress", cfg.compress),
+            ("zstd_at_rest", cfg.zstd_above.is_some()),
+            ("webdav", cfg.webdav),
+            ("git_http", cfg.git_http),
+            ("pdf", cfg.pdf_command.is_some()),
+            ("protect_ui", cfg.protect_ui),
+            ("mdns", cfg.mdns),
+            ("webhooks", state.webhooks.is_some()),
+            ("chat", state.chat.is_some()),
+            ("backup", state.backup.is_some()),
+            ("digest", cfg.digest_at.is_some()),
+            ("retention", live.retention().enabled()),
+            (
+                "self_test",
+                cfg.self_test || cfg.self_test_interval.is_some(),
+            ),
+            ("verify_reads", cfg.verify_reads != VerifyMode::Off),
+            ("host_rules", cfg.host_rules_file.is_some()),
+            ("ip_rate", live.ip_rate.is_some()),
+            ("write_rate", live.write_rate.is_some()),
+            ("max_in_flight", cfg.max_in_flight.is_some()),
+            ("config_file", cfg.config_file.is_some()),
+            ("cors", !cfg.cors_origin.is_empty()),
+            ("namespace", !cfg.namespace.is_empty()),
+        ]
+        .into_iter()
+        .filter(|(_, on)| *on)
+        .map(|(name, _)| name.to_string())
+        .collect();
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            started_at: OffsetDateTime::now_utc(),
+            bind: bound.to_vec(),
+            base_url: base_url(
+                bound
+                    .first()
+                    .or(cfg.bind.first())
+                    .copied()
+                    .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0))),
+            ),
+            dir: cfg.dir.clone(),
+            auth: auth.to_string(),
+            storage: Storage {
+                backend: "git".to_string(),
+                repo: state.paths.repo.clone(),
+                encrypted: state.cipher.is_some(),
+            },
+            push: push_mode_label(cfg.push).to_string(),
+            remote: cfg.remote.clone(),
+            features,
+        }
+    }
+
+    /// The startup banner, one log line per topic.
+    pub fn log(&self) {
+        let bind: Vec<String> = self.bind.iter().map(ToString::to_string).collect();
+        info!(
+            "lanpaste {} listening on {} ({})",
+            self.version,
+            bind.join(", "),
+            self.base_url
+        );
+        info!(
+            "auth: {}; storage: {} at {}{}; push: {}",
+            self.auth,
+            self.storage.backend,
+            self.storage.repo.display(),
+            if self.storage.encrypted {
+                " (encrypted)"
+            } else {
+                ""
+            },
+            self.push
+        );
+        let features = if self.features.is_empty() {
+            "none".to_string()
+        } else {
+            self.features.join(", ")
+        };
+        info!("features: {features}");
+    }
+
+    pub fn to_json(&self) -> AppResult<String> {
+        serde_json::to_string(self)
+            .map_err(|e| AppError::internal(format!("serialize instance summary: {e}")))
+    }
+
+    /// Replaces `run/instance.json`.
+    pub fn write(&self, paths: &AppPaths) -> AppResult<()> {
+        let path = paths.run.join(FILE_NAME);
+        let tmp = paths.run.join(format!(".{FILE_NAME}.{}.tmp", Ulid::new()));
+        let mut bytes = serde_json::to_vec_pretty(self)
+            .map_err(|e| AppError::internal(format!("serialize instance summary: {e}")))?;
+        bytes.push(b'\n');
+        fs::write(&tmp, bytes).map_err(|e| AppError::io("write instance summary", e))?;
+        fs::rename(&tmp, &path).map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            AppError::io("replace instance summary", e)
+        })
+    }
+
+    /// The summary a server left in `paths.run`, if any.
+    pub fn read(paths: &AppPaths) -> AppResult<Option<Self>> {
+        let raw = match fs::read(paths.run.join(FILE_NAME)) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(AppError::io("read instance summary", err)),
+        };
+        serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|e| AppError::internal(format!("parse instance summary: {e}")))
+    }
+}
+
+/// Logs the banner, writes `run/instance.json` and `--pid-file` and, with
+/// `--startup-json`, prints the summary to stdout.
+pub fn announce(state: &AppState, bound: &[SocketAddr]) -> AppResult<Instance> {
+    let instance = Instance::describe(state, bound);
+    instance.log();
+    instance.write(&state.paths)?;
+    if let Some(path) = &state.cfg.pid_file {
+        write_pid_file(path, instance.pid)?;
+    }
+    if state.cfg.startup_json {
+        println!("{}", instance.to_json()?);
+    }
+    Ok(instance)
+}
+
+/// At a clean shutdown: removes `--pid-file`, unless another process has
+/// claimed it since.
+pub fn retire(state: &AppState) {
+    if let Some(path) = &state.cfg.pid_file {
+        remove_pid_file(path, std::process::id());
+    }
+}
+
+fn write_pid_file(path: &Path, pid: u32) -> AppResult<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", Ulid::new()));
+    fs::write(&tmp, format!("{pid}\n")).map_err(|e| AppError::io("write pid file", e))?;
+    fs::rename(&tmp, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        AppError::io("replace pid file", e)
+    })
+}
+
+fn remove_pid_file(path: &Path, pid: u32) {
+    let ours = fs::read_to_string(path).is_ok_and(|raw| raw.trim() == pid.to_string());
+    if ours {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// A wildcard bind is reached through the host's LAN address, falling back
+/// to loopback when there is none.
+fn base_url(addr: SocketAddr) -> String {
+    let ip = if addr.ip().is_unspecified() {
+        mdns::primary_ipv4().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    } else {
+        addr.ip()
+    };
+    format!("http://{}/", SocketAddr::new(ip, addr.port()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_file_is_only_removed_by_its_writer() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("lanpaste.pid");
+        write_pid_file(&path, 4242).expect("write");
+        assert_eq!(fs::read_to_string(&path).expect("read"), "4242\n");
+        remove_pid_file(&path, 4243);
+        assert!(path.exists());
+        remove_pid_file(&path, 4242);
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(dir.path()).expect("ls").count(), 0);
+    }
+
+    #[test]
+    fn base_url_uses_the_bound_address() {
+        let v4: SocketAddr = "192.168.1.20:8090".parse().unwrap();
+        assert_eq!(base_url(v4), "http://192.168.1.20:8090/");
+        let v6: SocketAddr = "[fd00::1]:8090".parse().unwrap();
+        assert_eq!(base_url(v6), "http://[fd00::1]:8090/");
+        let any: SocketAddr = "0.0.0.0:8090".parse().unwrap();
+        assert!(!base_url(any).contains("0.0.0.0"));
+    }
+}
@@ -0,0 +1,235 @@
+//! Write-ahead journal for strict-mode commits. A strict write records its
+//! intent before touching the index, marks it committed once the commit
+//! exists and drops it after the push lands; a failed push rolls the commit
+//! back. Every step of [`roll_back`] is safe to repeat, and [`reconcile`]
+//! runs at startup to finish or undo whatever a crash left behind.
+//!
+//! Entries live in the `journal` kv namespace and are written durably.
+
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use ulid::Ulid;
+
+use crate::{
+    config::{PushMode, ServeCmd},
+    errors::{AppError, AppResult},
+    gitops::run_git,
+    kv::Kv,
+    types::AppPaths,
+};
+
+const NAMESPACE: &str = "journal";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Files are written and about to be committed.
+    Prepared,
+    /// `commit` exists on top of `parent` but has not been pushed.
+    Committed,
+    /// Undoing the commit and restoring `paths` from `parent`.
+    RollingBack,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Intent {
+    pub id: String,
+    pub subject: String,
+    /// Full sha of `HEAD` before the write.
+    pub parent: String,
+    /// Full sha of the write's commit, once it exists.
+    pub commit: Option<String>,
+    /// Repo-relative paths the write touches.
+    pub paths: Vec<String>,
+    pub stage: Stage,
+    pub started_at: OffsetDateTime,
+}
+
+/// What happened to an interrupted write during [`reconcile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The commit was pushed, or kept because push is no longer strict.
+    Completed,
+    RolledBack,
+    /// `HEAD` moved past the write; the entry is kept for an operator.
+    Stuck,
+}
+
+#[derive(Debug, Clone)]
+pub struct Journal {
+    kv: Kv,
+}
+
+impl Journal {
+    pub fn open(kv: Kv) -> Self {
+        Self { kv }
+    }
+
+    /// The journal for the data directory `cfg` serves.
+    pub fn for_config(cfg: &ServeCmd) -> Self {
+        Self::open(Kv::open(AppPaths::from_base(cfg.dir.clone()).kv))
+    }
+
+    /// Records a new intent to commit `paths` on top of the current `HEAD`.
+    pub fn begin(
+        &self,
+        repo: &Path,
+        cfg: &ServeCmd,
+        subject: &str,
+        paths: &[&str],
+    ) -> AppResult<Intent> {
+        let intent = Intent {
+            id: Ulid::new().to_string(),
+            subject: subject.to_string(),
+            parent: run_git(repo, &["rev-parse", "HEAD"], cfg)?,
+            commit: None,
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            stage: Stage::Prepared,
+            started_at: OffsetDateTime::now_utc(),
+        };
+        self.record(&intent)?;
+        Ok(intent)
+    }
+
+    pub fn record(&self, intent: &Intent) -> AppResult<()> {
+        self.kv.put_durable(NAMESPACE, &intent.id, intent)
+    }
+
+    pub fn finish(&self, intent: &Intent) -> AppResult<()> {
+        self.kv.delete(NAMESPACE, &intent.id).map(|_| ())
+    }
+
+    /// Unfinished intents, oldest first.
+    pub fn pending(&self) -> AppResult<Vec<Intent>> {
+        let mut intents: Vec<Intent> = self.kv.list(NAMESPACE)?;
+        intents.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(intents)
+    }
+}
+
+/// Undoes `intent`: moves `HEAD` back to its parent if it still points at
+/// the write's commit, then restores each path to its state in the parent.
+/// Refuses, keeping the entry, if `HEAD` is neither.
+pub fn roll_back(
+    repo: &Path,
+    cfg: &ServeCmd,
+    journal: &Journal,
+    intent: &mut Intent,
+) -> AppResult<()> {
+    if intent.stage != Stage::RollingBack {
+        intent.stage = Stage::RollingBack;
+        journal.record(intent)?;
+    }
+    let head = run_git(repo, &["rev-parse", "HEAD"], cfg)?;
+    if intent.commit.as_deref() == Some(head.as_str()) {
+        run_git(repo, &["reset", "-q", "--soft", &intent.parent], cfg)?;
+    } else if head != intent.parent {
+        return Err(AppError::Conflict(format!(
+            "cannot roll back '{}': HEAD moved to {head}",
+            intent.subject
+        )));
+    }
+    for rel in &intent.paths {
+        let spec = format!("{}:{rel}", intent.parent);
+        if run_git(repo, &["cat-file", "-e", &spec], cfg).is_ok() {
+            run_git(repo, &["checkout", &intent.parent, "--", rel], cfg)?;
+        } else {
+            run_git(
+                repo,
+                &["rm", "-q", "--cached", "--ignore-unmatch", "--", rel],
+                cfg,
+            )?;
+            match fs::remove_file(repo.join(rel)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(AppError::io("remove rolled back file", e));
+                }
+                _ => {}
+            }
+        }
+    }
+    journal.finish(intent)
+}
+
+/// Settles every intent a previous run left in the journal. Call with the
+/// git lock held, before serving.
+pub fn reconcile(
+    repo: &Path,
+    cfg: &ServeCmd,
+    journal: &Journal,
+) -> AppResult<Vec<(Intent, Outcome)>> {
+    let mut settled = Vec::new();
+    for mut intent in journal.pending()? {
+        let outcome = match settle(repo, cfg, journal, &mut intent) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                warn!(
+                    "journal: could not settle '{}' ({}): {err:?}",
+                    intent.subject, intent.id
+                );
+                Outcome::Stuck
+            }
+        };
+        if outcome != Outcome::Stuck {
+            info!("journal: '{}' {outcome:?}", intent.subject);
+        }
+        settled.push((intent, outcome));
+    }
+    Ok(settled)
+}
+
+fn settle(
+    repo: &Path,
+    cfg: &ServeCmd,
+    journal: &Journal,
+    intent: &mut Intent,
+) -> AppResult<Outcome> {
+    if intent.stage == Stage::Prepared {
+        // The process may have died after `git commit` but before the
+        // journal heard about it; adopt the commit if it is ours.
+        let head = run_git(repo, &["rev-parse", "HEAD"], cfg)?;
+        let head_parent = run_git(repo, &["rev-parse", "HEAD^"], cfg).ok();
+        let head_subject = run_git(repo, &["log", "-1", "--format=%s", "HEAD"], cfg)?;
+        if head_parent.as_deref() == Some(intent.parent.as_str()) && head_subject == intent.subject
+        {
+            intent.commit = Some(head);
+            intent.stage = Stage::Committed;
+            journal.record(intent)?;
+        }
+    }
+    match intent.stage {
+        Stage::Committed => {
+            if cfg.push != PushMode::Strict {
+                journal.finish(intent)?;
+                return Ok(Outcome::Completed);
+            }
+            let head = run_git(repo, &["rev-parse", "HEAD"], cfg)?;
+            if intent.commit.as_deref() != Some(head.as_str()) {
+                return Err(AppError::Conflict(format!(
+                    "cannot push '{}': HEAD moved to {head}",
+                    intent.subject
+                )));
+            }
+            match run_git(repo, &["push", &cfg.remote, "HEAD"], cfg) {
+                Ok(_) => {
+                    journal.finish(intent)?;
+                    Ok(Outcome::Completed)
+                }
+                Err(err) => {
+                    warn!(
+                        "journal: push for '{}' still failing: {err:?}",
+                        intent.subject
+                    );
+                    roll_back(repo, cfg, journal, intent)?;
+                    Ok(Outcome::RolledBack)
+                }
+            }
+        }
+        Stage::Prepared | Stage::RollingBack => {
+            roll_back(repo, cfg, journal, intent)?;
+            Ok(Outcome::RolledBack)
+        }
+    }
+}
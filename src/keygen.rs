@@ -0,0 +1,114 @@
+//! `lanpaste keygen`: secrets from the OS random source, so nobody has to
+//! make one up. With `--add-to` the new key also goes into an API key file.
+
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use crate::{
+    auth::{self, ApiKeyEntry, ApiKeyStore, Scope},
+    config::KeygenCmd,
+    errors::{AppError, AppResult},
+};
+
+/// Returns the new secret, after adding it to `--add-to` if given.
+pub fn run(cmd: &KeygenCmd) -> AppResult<String> {
+    let secret = auth::random_token(cmd.bytes.into());
+    if cmd.raw {
+        return Ok(secret);
+    }
+    let key = format!("lp_{secret}");
+    let Some(path) = &cmd.add_to else {
+        return Ok(key);
+    };
+    if let Some(bad) = cmd.scope.iter().find(|s| !Scope::is_valid(s)) {
+        let known: Vec<_> = Scope::ALL
+            .iter()
+            .map(|s| format!("{} ({})", s.as_str(), s.description()))
+            .collect();
+        return Err(AppError::BadRequest(format!(
+            "unknown scope '{bad}'; use *, a prefix such as paste:*, or one of {}",
+            known.join(", ")
+        )));
+    }
+    create_key_file(path)?;
+    ApiKeyStore::from_file(Some(path))?.add_key(ApiKeyEntry {
+        name: cmd.name.clone(),
+        key: key.clone(),
+        scopes: cmd.scope.clone(),
+        max_requests_per_minute: cmd.max_requests_per_minute,
+        enabled: true,
+    })?;
+    Ok(key)
+}
+
+/// An empty key file readable by its owner only, unless one exists.
+fn create_key_file(path: &Path) -> AppResult<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    match options.open(path) {
+        Ok(mut file) => file
+            .write_all(b"{\"keys\": []}\n")
+            .map_err(|e| AppError::io("write api key file", e)),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(()),
+        Err(err) => Err(AppError::io("create api key file", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(add_to: Option<&Path>, scope: &[&str]) -> KeygenCmd {
+        KeygenCmd {
+            bytes: 24,
+            raw: false,
+            add_to: add_to.map(Path::to_path_buf),
+            name: Some("ci".to_string()),
+            scope: scope.iter().map(|s| s.to_string()).collect(),
+            max_requests_per_minute: None,
+        }
+    }
+
+    #[test]
+    fn keys_are_random_and_land_in_the_key_file() {
+        let first = run(&cmd(None, &[])).expect("key");
+        assert!(first.starts_with("lp_") && first.len() == 3 + 48);
+        assert_ne!(first, run(&cmd(None, &[])).expect("key"));
+        let raw = run(&KeygenCmd {
+            raw: true,
+            bytes: 32,
+            ..cmd(None, &[])
+        })
+        .expect("token");
+        assert!(raw.len() == 64 && raw.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("keys.json");
+        let added = run(&cmd(Some(&path), &["paste:create", "recent:read"])).expect("add");
+        let second = run(&cmd(Some(&path), &["*"])).expect("add again");
+        let store = ApiKeyStore::from_file(Some(&path)).expect("valid file");
+        assert!(store.enabled());
+        let file = std::fs::read_to_string(&path).expect("read");
+        assert!(file.contains(&added) && file.contains(&second));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).expect("meta").permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let err = run(&cmd(Some(&path), &["paste:delete"])).expect_err("bad scope");
+        assert!(matches!(err, AppError::BadRequest(_)));
+        run(&cmd(Some(&path), &["paste:*"])).expect("wildcard");
+        let err = run(&cmd(Some(&path), &["pastes:*"])).expect_err("wildcard granting nothing");
+        assert!(matches!(err, AppError::BadRequest(_)));
+    }
+}
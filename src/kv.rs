@@ -0,0 +1,253 @@
+//! Small namespaced key-value store for runtime state under `<dir>/run/kv`.
+//!
+//! Each namespace is a directory and each key a JSON file named by the
+//! sha256 of the key, so arbitrary client-supplied keys are safe to store.
+//! Writes go to a temp file and are renamed into place, and entries may
+//! carry a TTL after which they read as absent.
+
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+use crate::errors::{AppError, AppResult};
+
+#[derive(Debug, Clone)]
+pub struct Kv {
+    root: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    expires_at: Option<OffsetDateTime>,
+    value: T,
+}
+
+impl Entry<serde_json::Value> {
+    fn expired(&self, now: OffsetDateTime) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+impl Kv {
+    pub fn open(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, ns: &str, key: &str) -> AppResult<Option<T>> {
+        let path = self.entry_path(ns, key)?;
+        let Some(entry) = read_entry(&path)? else {
+            return Ok(None);
+        };
+        if entry.expired(OffsetDateTime::now_utc()) {
+            let _ = fs::remove_file(&path);
+            return Ok(None);
+        }
+        serde_json::from_value(entry.value)
+            .map(Some)
+            .map_err(|e| AppError::internal(format!("parse kv {ns} entry: {e}")))
+    }
+
+    pub fn put<T: Serialize>(
+        &self,
+        ns: &str,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> AppResult<()> {
+        self.write(ns, key, value, ttl, false)
+    }
+
+    /// Like [`Kv::put`], but syncs the entry and its namespace directory
+    /// before returning, for state that must survive a power cut.
+    pub fn put_durable<T: Serialize>(&self, ns: &str, key: &str, value: &T) -> AppResult<()> {
+        self.write(ns, key, value, None, true)
+    }
+
+    fn write<T: Serialize>(
+        &self,
+        ns: &str,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+        durable: bool,
+    ) -> AppResult<()> {
+        let path = self.entry_path(ns, key)?;
+        let entry = Entry {
+            expires_at: ttl.map(|ttl| OffsetDateTime::now_utc() + ttl),
+            value,
+        };
+        let bytes = serde_json::to_vec_pretty(&entry)
+            .map_err(|e| AppError::internal(format!("serialize kv {ns} entry: {e}")))?;
+        let dir = self.root.join(ns);
+        fs::create_dir_all(&dir).map_err(|e| AppError::io("create kv namespace", e))?;
+        let tmp = dir.join(format!(".{}.tmp", Ulid::new()));
+        let written = if durable {
+            fs::File::create(&tmp).and_then(|mut file| {
+                file.write_all(&bytes)?;
+                file.sync_all()
+            })
+        } else {
+            fs::write(&tmp, bytes)
+        };
+        written.map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            AppError::io("write kv entry", e)
+        })?;
+        fs::rename(&tmp, &path).map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            AppError::io("commit kv entry", e)
+        })?;
+        if durable {
+            fs::File::open(&dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| AppError::io("sync kv namespace", e))?;
+        }
+        Ok(())
+    }
+
+    /// Removes `key`, returning whether an entry existed.
+    pub fn delete(&self, ns: &str, key: &str) -> AppResult<bool> {
+        match fs::remove_file(self.entry_path(ns, key)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(AppError::io("delete kv entry", e)),
+        }
+    }
+
+    /// Live values in `ns`, in no particular order. Keys are stored hashed, so
+    /// values that need to be found again should carry their own id.
+    pub fn list<T: DeserializeOwned>(&self, ns: &str) -> AppResult<Vec<T>> {
+        let dir = self.entry_path(ns, "")?;
+        let dir = dir.parent().unwrap_or(&self.root);
+        let files = match fs::read_dir(dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(AppError::io("read kv namespace", e)),
+        };
+        let now = OffsetDateTime::now_utc();
+        let mut out = Vec::new();
+        for file in files.flatten() {
+            if file.file_name().to_string_lossy().ends_with(".tmp") {
+                continue;
+            }
+            let Some(entry) = read_entry(&file.path())? else {
+                continue;
+            };
+            if entry.expired(now) {
+                continue;
+            }
+            out.push(
+                serde_json::from_value(entry.value)
+                    .map_err(|e| AppError::internal(format!("parse kv {ns} entry: {e}")))?,
+            );
+        }
+        Ok(out)
+    }
+
+    /// Deletes expired entries and stray temp files in every namespace.
+    pub fn purge_expired(&self) -> AppResult<usize> {
+        let namespaces = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(AppError::io("read kv root", e)),
+        };
+        let now = OffsetDateTime::now_utc();
+        let mut purged = 0;
+        for ns in namespaces.flatten() {
+            let Ok(files) = fs::read_dir(ns.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                let stale_tmp = file.file_name().to_string_lossy().ends_with(".tmp");
+                let expired = !stale_tmp
+                    && read_entry(&path)
+                        .ok()
+                        .flatten()
+                        .is_some_and(|entry| entry.expired(now));
+                if (stale_tmp || expired) && fs::remove_file(&path).is_ok() {
+                    purged += 1;
+                }
+            }
+        }
+        Ok(purged)
+    }
+
+    fn entry_path(&self, ns: &str, key: &str) -> AppResult<PathBuf> {
+        if ns.is_empty()
+            || !ns
+                .chars()
+                .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || matches!(ch, '-' | '_'))
+        {
+            return Err(AppError::internal(format!("invalid kv namespace '{ns}'")));
+        }
+        let digest = hex::encode(Sha256::digest(key.as_bytes()));
+        Ok(self.root.join(ns).join(format!("{digest}.json")))
+    }
+}
+
+fn read_entry(path: &Path) -> AppResult<Option<Entry<serde_json::Value>>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(AppError::io("read kv entry", e)),
+    };
+    serde_json::from_slice(&data)
+        .map(Some)
+        .map_err(|e| AppError::internal(format!("parse kv entry: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_delete_round_trip() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let kv = Kv::open(td.path());
+        kv.put("sessions", "../odd key", &vec![1, 2, 3], None)
+            .expect("put");
+        assert_eq!(
+            kv.get::<Vec<u8>>("sessions", "../odd key").expect("get"),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(kv.get::<Vec<u8>>("other", "../odd key").expect("get"), None);
+        assert!(kv.delete("sessions", "../odd key").expect("delete"));
+        assert!(!kv.delete("sessions", "../odd key").expect("delete again"));
+        assert!(kv.put("Bad/NS", "k", &1, None).is_err());
+    }
+
+    #[test]
+    fn ttl_expires_and_purge_cleans_up() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let kv = Kv::open(td.path());
+        kv.put("leases", "a", &"gone", Some(Duration::ZERO))
+            .expect("put");
+        kv.put("leases", "b", &"kept", Some(Duration::from_secs(60)))
+            .expect("put");
+        fs::write(td.path().join("leases").join(".stale.tmp"), b"x").expect("tmp");
+
+        assert_eq!(kv.purge_expired().expect("purge"), 2);
+        assert_eq!(kv.get::<String>("leases", "a").expect("get"), None);
+        assert_eq!(
+            kv.get::<String>("leases", "b").expect("get").as_deref(),
+            Some("kept")
+        );
+        let left = fs::read_dir(td.path().join("leases")).expect("dir").count();
+        assert_eq!(left, 1);
+        assert_eq!(kv.list::<String>("leases").expect("list"), vec!["kept"]);
+        assert!(kv.list::<String>("empty").expect("list").is_empty());
+    }
+}
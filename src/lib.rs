@@ -1,9 +1,62 @@
+pub mod admin;
+pub mod archive;
+pub mod assets;
 pub mod auth;
+pub mod authlog;
+pub mod backup;
+pub mod blobs;
+pub mod chat;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod collections;
+pub mod committer;
 pub mod config;
+pub mod cors;
+pub mod crypt;
+pub mod digest;
+pub mod doctor;
+pub mod enroll;
 pub mod errors;
+pub mod events;
+pub mod export;
+pub mod gc;
+pub mod githttp;
 pub mod gitops;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod highlight;
+pub mod hostrules;
 pub mod http;
+pub mod index;
+pub mod init;
+pub mod instance;
+pub mod journal;
+pub mod keygen;
+pub mod kv;
+pub mod mdns;
+pub mod migrate;
+pub mod namespaces;
+pub mod notify;
+pub mod pdf;
 pub mod preflight;
+pub mod proxy;
+pub mod ratelimit;
+pub mod readiness;
 pub mod render;
+pub mod rendercache;
+pub mod retention;
+pub mod sandbox;
+pub mod security;
+pub mod selftest;
+pub mod settings;
+pub mod soak;
+pub mod stars;
+pub mod stats;
 pub mod store;
+pub mod systemd;
+pub mod token;
 pub mod types;
+pub mod views;
+pub mod webdav;
+pub mod webhooks;
+pub mod zst;
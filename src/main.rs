@@ -2,20 +2,50 @@ use std::sync::Arc;
 
 use clap::Parser;
 use lanpaste::{
-    config::{Cli, Commands},
-    http, preflight,
+    archive, backup, chat, client,
+    config::{
+        AdminCmd, ArchiveCmd, Cli, Commands, EnrollCmd, GcCmd, InitCmd, KeygenCmd, MigrateCmd,
+        PasteCmd, RotateTokenCmd, ServeCmd, SoakCmd,
+    },
+    digest, doctor, enroll, gc, http, init, keygen, mdns, migrate, preflight, retention, selftest,
+    settings, soak, webhooks,
 };
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{
+    EnvFilter, Registry, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+type LogHandle = reload::Handle<EnvFilter, Registry>;
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
+    let (filter, log) = reload::Layer::new(default_filter());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
     let cli = Cli::parse();
-    let Commands::Serve(cfg) = cli.command;
+    match cli.command {
+        Commands::Serve(cfg) => serve(*cfg, log).await,
+        Commands::Doctor(cfg) => run_doctor(*cfg).await,
+        Commands::Init(cmd) => run_init(cmd),
+        Commands::Keygen(cmd) => run_keygen(cmd),
+        Commands::Soak(cmd) => run_soak(cmd).await,
+        Commands::Enroll(cmd) => run_enroll(cmd).await,
+        Commands::Migrate(cmd) => run_migrate(cmd),
+        Commands::Archive(cmd) => run_archive(cmd),
+        Commands::Paste(cmd) => run_paste(cmd).await,
+        Commands::Admin(AdminCmd::Gc(cmd)) => run_gc(cmd).await,
+        Commands::Admin(AdminCmd::RotateToken(cmd)) => run_rotate_token(cmd).await,
+    }
+}
+
+/// `RUST_LOG`, or this crate's info messages (such as the startup banner).
+fn default_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("lanpaste=info"))
+}
 
+async fn serve(cfg: ServeCmd, log: LogHandle) {
     if let Err(err) = preflight::run_preflight(&cfg) {
         eprintln!("{err:?}");
         std::process::exit(1);
@@ -28,9 +58,188 @@ async fn main() {
             std::process::exit(1);
         }
     });
+    let reloaded = state.settings.set_log_reloader(Box::new(move |level| {
+        let filter = match level {
+            Some(level) => EnvFilter::try_new(level).map_err(|e| e.to_string())?,
+            None => default_filter(),
+        };
+        log.reload(filter).map_err(|e| e.to_string())
+    }));
+    if let Err(err) = reloaded {
+        eprintln!("{err:?}");
+        std::process::exit(1);
+    }
+
+    if state.cfg.self_test {
+        let check = state.clone();
+        let report = tokio::task::spawn_blocking(move || selftest::run_self_test(&check))
+            .await
+            .unwrap_or_else(|err| {
+                eprintln!("self-test task failed: {err}");
+                std::process::exit(1);
+            });
+        eprint!("{}", report.summary());
+        if !report.ok {
+            std::process::exit(1);
+        }
+    }
+    if let Some(every) = state.cfg.self_test_interval {
+        selftest::spawn_periodic(state.clone(), every);
+    }
+    if let Some(at) = state.cfg.digest_at {
+        digest::spawn_daily(state.clone(), at);
+    }
+    if state.backup.is_some() {
+        backup::spawn_periodic(state.clone());
+    }
+    if let Some(hooks) = state.webhooks.clone() {
+        webhooks::spawn_worker(state.clone(), hooks);
+    }
+    if let Some(chat) = state.chat.clone() {
+        chat::spawn_worker(state.clone(), chat);
+    }
+    if state.cfg.mdns {
+        mdns::spawn(&state.cfg);
+    }
+    retention::spawn_periodic(state.clone());
+    #[cfg(unix)]
+    settings::spawn_sighup(state.clone());
+    // Namespaces keep their own retention and reloadable settings.
+    for (name, ns) in &state.namespaces {
+        tracing::info!("namespace {name} at {}", ns.cfg.dir.display());
+        retention::spawn_periodic(ns.clone());
+        #[cfg(unix)]
+        settings::spawn_sighup(ns.clone());
+    }
 
     if let Err(err) = http::run_server(state).await {
         eprintln!("{err:?}");
         std::process::exit(1);
     }
 }
+
+async fn run_soak(cmd: SoakCmd) {
+    let report = match soak::run_soak(cmd).await {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    };
+    print!("{}", report.summary());
+    if !report.violations.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+async fn run_enroll(cmd: EnrollCmd) {
+    match enroll::run_enroll(cmd).await {
+        Ok(path) => eprintln!("Approved; key saved to {}", path.display()),
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_migrate(cmd: MigrateCmd) {
+    let report = match migrate::run_migrate(&cmd) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    };
+    print!("{}", report.summary());
+    if !report.ok() {
+        std::process::exit(1);
+    }
+}
+
+fn run_init(cmd: InitCmd) {
+    match init::run(&cmd) {
+        Ok(report) => print!("{}", report.summary()),
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_keygen(cmd: KeygenCmd) {
+    match keygen::run(&cmd) {
+        Ok(key) => {
+            println!("{key}");
+            if let Some(path) = &cmd.add_to {
+                eprintln!(
+                    "added to {}; a running server picks it up on SIGHUP or POST /api/v1/admin/reload",
+                    path.display()
+                );
+            }
+        }
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_archive(cmd: ArchiveCmd) {
+    match archive::run_offline(&cmd) {
+        Ok(report) => print!("{}", report.summary()),
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_paste(cmd: PasteCmd) {
+    match client::run_paste(cmd).await {
+        Ok(url) => println!("{url}"),
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_gc(cmd: GcCmd) {
+    let report = match (&cmd.dir, &cmd.server) {
+        (Some(dir), _) => gc::run_offline(dir, &cmd),
+        (None, Some(server)) => client::run_remote_gc(&cmd, server).await,
+        (None, None) => unreachable!("clap requires --dir or --server"),
+    };
+    let report = match report {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    };
+    print!("{}", report.summary());
+    if !report.ok() {
+        std::process::exit(1);
+    }
+}
+
+async fn run_rotate_token(cmd: RotateTokenCmd) {
+    match client::run_rotate_token(&cmd).await {
+        Ok(rotated) => {
+            println!("{}", rotated.token);
+            eprintln!("the old token works until {}", rotated.previous_valid_until);
+        }
+        Err(err) => {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_doctor(cfg: ServeCmd) {
+    let report = doctor::run(&cfg).await;
+    print!("{}", report.summary());
+    if !report.ok() {
+        std::process::exit(1);
+    }
+}
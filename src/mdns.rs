@@ -0,0 +1,505 @@
+//! `--mdns`: advertises the server on the LAN as a `_lanpaste._tcp` DNS-SD
+//! service, so `avahi-browse _lanpaste._tcp`, `dns-sd -B _lanpaste._tcp` or a
+//! client can find it without anyone remembering the address.
+//!
+//! A minimal multicast DNS responder (RFC 6762/6763): it announces its
+//! records at startup and answers queries for the service type, the
+//! instance, the host name and the DNS-SD service enumeration. It shares
+//! UDP 5353 with any system responder such as avahi or mDNSResponder.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket as StdUdpSocket},
+    time::Duration,
+};
+
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+};
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const SERVICE: &str = "_lanpaste._tcp.local";
+const SERVICES_META: &str = "_services._dns-sd._udp.local";
+/// RFC 6762 §10: records naming a host live two minutes, others 75 minutes.
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+/// Replies to one-shot (legacy unicast) queriers must not be cached long.
+const LEGACY_TTL: u32 = 10;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records only we answer for, so caches replace older copies.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// What the responder advertises.
+#[derive(Debug, Clone)]
+pub struct Service {
+    /// Instance label, e.g. `lanpaste on build-box`.
+    pub instance: String,
+    /// Host label; the records use `<host>.local`.
+    pub host: String,
+    pub addr: IpAddr,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Record {
+    name: String,
+    rtype: u16,
+    unique: bool,
+    ttl: u32,
+    data: Vec<u8>,
+}
+
+impl Service {
+    /// The service for `cfg`, or why there is nothing to advertise.
+    pub fn from_config(cfg: &ServeCmd) -> AppResult<Self> {
+        let host = dns_lookup::get_hostname()
+            .ok()
+            .and_then(|name| host_label(&name))
+            .unwrap_or_else(|| "lanpaste".to_string());
+        // The first --bind that is not loopback, as several may be given.
+        let Some(bind) = cfg.bind.iter().find(|bind| !bind.ip().is_loopback()) else {
+            return Err(AppError::internal(
+                "--mdns needs a LAN address; --bind is loopback",
+            ));
+        };
+        let addr = match bind.ip() {
+            ip if ip.is_unspecified() => primary_ipv4()?,
+            ip => ip,
+        };
+        let port = bind.port();
+        Ok(Self {
+            instance: cfg
+                .mdns_name
+                .clone()
+                .unwrap_or_else(|| format!("lanpaste on {host}")),
+            txt: vec![
+                format!("port={port}"),
+                "path=/".to_string(),
+                format!("url=http://{host}.local:{port}/"),
+                "api=/api".to_string(),
+                format!("version={}", env!("CARGO_PKG_VERSION")),
+            ],
+            host,
+            addr,
+            port,
+        })
+    }
+
+    fn instance_name(&self) -> String {
+        format!("{}.{SERVICE}", self.instance)
+    }
+
+    fn host_name(&self) -> String {
+        format!("{}.local", self.host)
+    }
+
+    fn records(&self) -> Vec<Record> {
+        let instance = self.instance_name();
+        let mut srv = Vec::new();
+        srv.extend_from_slice(&0u16.to_be_bytes());
+        srv.extend_from_slice(&0u16.to_be_bytes());
+        srv.extend_from_slice(&self.port.to_be_bytes());
+        encode_name(&mut srv, &self.host_name());
+        let mut txt = Vec::new();
+        for entry in &self.txt {
+            let bytes = &entry.as_bytes()[..entry.len().min(255)];
+            txt.push(bytes.len() as u8);
+            txt.extend_from_slice(bytes);
+        }
+        let (addr_type, addr) = match self.addr {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        let mut service_ptr = Vec::new();
+        encode_name(&mut service_ptr, SERVICE);
+        let mut instance_ptr = Vec::new();
+        encode_name(&mut instance_ptr, &instance);
+        vec![
+            record(SERVICE, TYPE_PTR, false, OTHER_TTL, instance_ptr),
+            record(&instance, TYPE_SRV, true, HOST_TTL, srv),
+            record(&instance, TYPE_TXT, true, OTHER_TTL, txt),
+            record(&self.host_name(), addr_type, true, HOST_TTL, addr),
+            record(SERVICES_META, TYPE_PTR, false, OTHER_TTL, service_ptr),
+        ]
+    }
+
+    /// The reply to `packet`, if it is a query about any of our records.
+    /// `legacy` queriers (not sending from port 5353) get a unicast-style
+    /// reply echoing their id and questions.
+    pub fn answer(&self, packet: &[u8], legacy: bool) -> Option<Vec<u8>> {
+        let query = parse_query(packet)?;
+        let records = self.records();
+        let asked = |r: &Record| {
+            query.questions.iter().any(|q| {
+                q.name.eq_ignore_ascii_case(&r.name) && (q.qtype == r.rtype || q.qtype == TYPE_ANY)
+            })
+        };
+        if !records.iter().any(asked) {
+            return None;
+        }
+        // Whatever was asked goes in the answers; the rest of the service
+        // rides along as additional records, saving the querier a round trip.
+        let (answers, additional): (Vec<&Record>, Vec<&Record>) =
+            records.iter().partition(|r| asked(r));
+        let additional: Vec<&Record> = additional
+            .into_iter()
+            .filter(|r| r.name != SERVICES_META)
+            .collect();
+        Some(if legacy {
+            encode_response(query.id, &query.raw_questions, &answers, &additional, true)
+        } else {
+            encode_response(0, &[], &answers, &additional, false)
+        })
+    }
+
+    /// An unsolicited response announcing every record.
+    pub fn announcement(&self) -> Vec<u8> {
+        let records = self.records();
+        let all: Vec<&Record> = records.iter().collect();
+        encode_response(0, &[], &all, &[], false)
+    }
+}
+
+/// Starts the responder in the background. Problems are logged: discovery
+/// is a convenience and never stops the server.
+pub fn spawn(cfg: &ServeCmd) {
+    let service = match Service::from_config(cfg) {
+        Ok(service) => service,
+        Err(err) => {
+            warn!("mdns disabled: {err:?}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        if let Err(err) = respond(service).await {
+            warn!("mdns responder stopped: {err:?}");
+        }
+    });
+}
+
+async fn respond(service: Service) -> AppResult<()> {
+    let socket = bind_multicast().map_err(|e| AppError::io("bind mdns socket", e))?;
+    let group = SocketAddr::V4(SocketAddrV4::new(MDNS_ADDR, MDNS_PORT));
+    info!(
+        "mdns: advertising '{}' at {}.local ({}:{})",
+        service.instance, service.host, service.addr, service.port
+    );
+    // RFC 6762 §8.3: announce at least twice, a second apart.
+    let announcement = service.announcement();
+    for delay in [0, 1] {
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        socket
+            .send_to(&announcement, group)
+            .await
+            .map_err(|e| AppError::io("send mdns announcement", e))?;
+    }
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let (len, from) = socket
+            .recv_from(&mut buf)
+            .await
+            .map_err(|e| AppError::io("receive mdns packet", e))?;
+        let legacy = from.port() != MDNS_PORT;
+        if let Some(reply) = service.answer(&buf[..len], legacy) {
+            let to = if legacy { from } else { group };
+            if let Err(err) = socket.send_to(&reply, to).await {
+                warn!("mdns: reply to {from} failed: {err}");
+            }
+        }
+    }
+}
+
+fn bind_multicast() -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+    socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// The address the host would use to reach the mDNS group, which is the
+/// one LAN peers can reach it on. Nothing is sent.
+pub(crate) fn primary_ipv4() -> AppResult<IpAddr> {
+    let probe = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|s| s.connect((MDNS_ADDR, MDNS_PORT)).map(|_| s))
+        .and_then(|s| s.local_addr())
+        .map_err(|e| AppError::io("find a LAN address for mdns", e))?;
+    Ok(probe.ip())
+}
+
+/// First label of `hostname`, reduced to what a DNS host label allows.
+fn host_label(hostname: &str) -> Option<String> {
+    let label: String = hostname
+        .split('.')
+        .next()?
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(63)
+        .collect();
+    let label = label.trim_matches('-');
+    (!label.is_empty()).then(|| label.to_string())
+}
+
+fn record(name: &str, rtype: u16, unique: bool, ttl: u32, data: Vec<u8>) -> Record {
+    Record {
+        name: name.to_string(),
+        rtype,
+        unique,
+        ttl,
+        data,
+    }
+}
+
+/// Writes `name` as uncompressed labels. Instance labels may contain dots
+/// of their own, so only the fixed suffixes are split.
+fn encode_name(out: &mut Vec<u8>, name: &str) {
+    let labels: Vec<&str> = match name.strip_suffix(SERVICE).and_then(|n| n.strip_suffix('.')) {
+        Some(instance) => std::iter::once(instance)
+            .chain(SERVICE.split('.'))
+            .collect(),
+        None => name.split('.').collect(),
+    };
+    for label in labels {
+        let bytes = &label.as_bytes()[..label.len().min(63)];
+        out.push(bytes.len() as u8);
+        out.extend_from_slice(bytes);
+    }
+    out.push(0);
+}
+
+fn encode_response(
+    id: u16,
+    questions: &[u8],
+    answers: &[&Record],
+    additional: &[&Record],
+    legacy: bool,
+) -> Vec<u8> {
+    let question_count = if questions.is_empty() {
+        0
+    } else {
+        parse_query_count(questions)
+    };
+    let mut out = Vec::with_capacity(512);
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x8400u16.to_be_bytes());
+    for count in [question_count, answers.len(), 0, additional.len()] {
+        out.extend_from_slice(&(count as u16).to_be_bytes());
+    }
+    out.extend_from_slice(questions);
+    for r in answers.iter().chain(additional) {
+        encode_name(&mut out, &r.name);
+        out.extend_from_slice(&r.rtype.to_be_bytes());
+        // Legacy queriers are not mDNS-aware and would misread the flag.
+        let class = if r.unique && !legacy {
+            CLASS_IN | CACHE_FLUSH
+        } else {
+            CLASS_IN
+        };
+        out.extend_from_slice(&class.to_be_bytes());
+        let ttl = if legacy { r.ttl.min(LEGACY_TTL) } else { r.ttl };
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(r.data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&r.data);
+    }
+    out
+}
+
+#[derive(Debug)]
+struct Question {
+    name: String,
+    qtype: u16,
+}
+
+#[derive(Debug)]
+struct Query {
+    id: u16,
+    questions: Vec<Question>,
+    /// The question section as received, rewritten without compression so
+    /// it can be echoed on its own.
+    raw_questions: Vec<u8>,
+}
+
+/// Questions of a DNS query; `None` for responses and malformed packets.
+fn parse_query(packet: &[u8]) -> Option<Query> {
+    let header = packet.get(..12)?;
+    let id = u16::from_be_bytes([header[0], header[1]]);
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    let count = u16::from_be_bytes([header[4], header[5]]);
+    let mut pos = 12;
+    let mut questions = Vec::new();
+    let mut raw_questions = Vec::new();
+    for _ in 0..count {
+        let (name, next) = decode_name(packet, pos)?;
+        let fields = packet.get(next..next + 4)?;
+        let qtype = u16::from_be_bytes([fields[0], fields[1]]);
+        encode_name(&mut raw_questions, &name);
+        // Echoed without the unicast-response bit.
+        raw_questions.extend_from_slice(&qtype.to_be_bytes());
+        raw_questions.extend_from_slice(&CLASS_IN.to_be_bytes());
+        questions.push(Question { name, qtype });
+        pos = next + 4;
+    }
+    Some(Query {
+        id,
+        questions,
+        raw_questions,
+    })
+}
+
+/// How many questions an encoded question section holds.
+fn parse_query_count(questions: &[u8]) -> usize {
+    let mut pos = 0;
+    let mut count = 0;
+    while pos < questions.len() {
+        match decode_name(questions, pos) {
+            Some((_, next)) => pos = next + 4,
+            None => break,
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Reads a possibly compressed name at `pos`; returns it dotted, without
+/// the trailing dot, and the offset just past it.
+fn decode_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    // Bounds pointer loops in hostile packets.
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xC0 == 0xC0 => {
+                let low = *packet.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = ((l & 0x3F) << 8) | low;
+            }
+            l if l < 64 => {
+                let label = packet.get(pos + 1..pos + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service {
+            instance: "lanpaste on box.lab".to_string(),
+            host: "box".to_string(),
+            addr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)),
+            port: 8090,
+            txt: vec!["port=8090".to_string(), "path=/".to_string()],
+        }
+    }
+
+    fn query(id: u16, names: &[(&str, u16)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.extend_from_slice(&(names.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0; 6]);
+        for (name, qtype) in names {
+            encode_name(&mut out, name);
+            out.extend_from_slice(&qtype.to_be_bytes());
+            out.extend_from_slice(&(CLASS_IN | 0x8000).to_be_bytes());
+        }
+        out
+    }
+
+    fn counts(packet: &[u8]) -> [u16; 4] {
+        [4, 6, 8, 10].map(|i| u16::from_be_bytes([packet[i], packet[i + 1]]))
+    }
+
+    #[test]
+    fn answers_browse_queries_with_the_whole_service() {
+        let service = service();
+        let reply = service
+            .answer(&query(7, &[("_LANPASTE._tcp.local", TYPE_PTR)]), false)
+            .expect("reply");
+        assert_eq!(&reply[..2], &[0, 0]);
+        assert_eq!(counts(&reply), [0, 1, 0, 3]);
+        let (name, _) = decode_name(&reply, 12).expect("answer name");
+        assert_eq!(name, SERVICE);
+        let find = |needle: &[u8]| reply.windows(needle.len()).any(|w| w == needle);
+        assert!(find(
+            b"\x13lanpaste on box.lab\x09_lanpaste\x04_tcp\x05local\x00"
+        ));
+        assert!(find(&[0, 0, 0, 0, 0x1F, 0x9A, 3, b'b', b'o', b'x']));
+        assert!(find(b"\x09port=8090\x06path=/"));
+        assert!(find(&[192, 168, 1, 20]));
+
+        assert!(
+            service
+                .answer(&query(1, &[("_http._tcp.local", TYPE_PTR)]), false)
+                .is_none()
+        );
+        let mut response = query(1, &[(SERVICE, TYPE_PTR)]);
+        response[2] = 0x84;
+        assert!(service.answer(&response, false).is_none());
+    }
+
+    #[test]
+    fn legacy_queries_get_their_id_and_question_back() {
+        let reply = service()
+            .answer(&query(0xBEEF, &[("box.local", TYPE_A)]), true)
+            .expect("reply");
+        assert_eq!(&reply[..2], &[0xBE, 0xEF]);
+        assert_eq!(counts(&reply), [1, 1, 0, 3]);
+        let (question, next) = decode_name(&reply, 12).expect("question");
+        assert_eq!(question, "box.local");
+        assert_eq!(&reply[next..next + 4], &[0, 1, 0, 1]);
+        let (answer, next) = decode_name(&reply, next + 4).expect("answer");
+        assert_eq!(answer, "box.local");
+        // Plain class IN and a capped TTL.
+        assert_eq!(&reply[next + 2..next + 8], &[0, 1, 0, 0, 0, 10]);
+    }
+
+    #[test]
+    fn names_decode_through_compression_pointers() {
+        let mut packet = query(0, &[(SERVICE, TYPE_PTR)]);
+        let second = packet.len();
+        packet.extend_from_slice(b"\x03box\xC0\x0C");
+        let (name, next) = decode_name(&packet, second).expect("name");
+        assert_eq!(name, format!("box.{SERVICE}"));
+        assert_eq!(next, packet.len());
+        packet.extend_from_slice(b"\xC0\x00");
+        let looped = packet.len() - 2;
+        packet[looped + 1] = looped as u8;
+        assert!(decode_name(&packet, looped).is_none());
+        assert_eq!(
+            host_label("Build_Box.corp.lan").as_deref(),
+            Some("Build-Box")
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! `lanpaste migrate`: upgrades a data directory written by an older release
+//! in place. The directory is copied aside first, then the layout is brought
+//! up to date and every paste is checked against the new indexes.
+//!
+//! Older layouts handled here:
+//! - runtime state directly under `run/` (`run/idempotency/`, `run/leases/`)
+//!   rather than in `run/kv/` namespaces;
+//! - pastes without a slug map entry, or listed pastes without a short id;
+//! - a repo missing the `slugs/` or `short/` directories.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use fs2::FileExt;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{
+    blobs,
+    config::{MigrateCmd, ServeCmd},
+    errors::{AppError, AppResult},
+    gitops,
+    kv::Kv,
+    preflight, store,
+    types::{AppPaths, PasteLease, PasteMeta},
+};
+
+const LEASES_NS: &str = "leases";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrateReport {
+    pub backup: PathBuf,
+    /// Unexpired leases moved from `run/leases/` into the kv store.
+    pub leases_moved: usize,
+    /// Idempotency records dropped from `run/idempotency/`.
+    pub idempotency_retired: usize,
+    pub slugs_added: usize,
+    pub short_ids_added: usize,
+    pub commit: Option<String>,
+    pub pastes_verified: usize,
+    pub problems: Vec<String>,
+}
+
+impl MigrateReport {
+    pub fn ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "migration {}\n  backup:              {}\n  leases moved:        {}\n  idempotency retired: {}\n  slugs added:         {}\n  short ids added:     {}\n  commit:              {}\n  pastes verified:     {}\n",
+            if self.ok() {
+                "complete"
+            } else {
+                "FAILED verification"
+            },
+            self.backup.display(),
+            self.leases_moved,
+            self.idempotency_retired,
+            self.slugs_added,
+            self.short_ids_added,
+            self.commit.as_deref().unwrap_or("none"),
+            self.pastes_verified,
+        );
+        for problem in &self.problems {
+            out.push_str(&format!("  problem: {problem}\n"));
+        }
+        out
+    }
+}
+
+pub fn run_migrate(cmd: &MigrateCmd) -> AppResult<MigrateReport> {
+    let cfg = ServeCmd::offline(&cmd.from, &cmd.git_author_name, &cmd.git_author_email)
+        .map_err(|e| AppError::internal(format!("build serve config: {e}")))?;
+    let paths = AppPaths::from_base(cfg.dir.clone());
+    if !paths.repo.join("meta").is_dir() {
+        return Err(AppError::BadRequest(format!(
+            "{} does not look like a lanpaste directory (no repo/meta)",
+            cfg.dir.display()
+        )));
+    }
+
+    // Same lock a running server holds, so a live instance is never migrated.
+    fs::create_dir_all(&paths.run).map_err(|e| AppError::io("create run dir", e))?;
+    let daemon_lock = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(paths.run.join("daemon.lock"))
+        .map_err(|e| AppError::io("open daemon lock", e))?;
+    daemon_lock
+        .try_lock_exclusive()
+        .map_err(|_| AppError::Conflict("a server is running on this directory".to_string()))?;
+
+    let mut report = MigrateReport {
+        backup: cmd
+            .backup
+            .clone()
+            .unwrap_or_else(|| default_backup_path(&cfg.dir)),
+        ..MigrateReport::default()
+    };
+    if report.backup.starts_with(&cfg.dir) {
+        return Err(AppError::BadRequest(
+            "the backup must live outside the directory being migrated".to_string(),
+        ));
+    }
+    if report.backup.exists() {
+        return Err(AppError::Conflict(format!(
+            "backup path {} already exists",
+            report.backup.display()
+        )));
+    }
+    copy_tree(&cfg.dir, &report.backup, &paths.tmp)?;
+
+    preflight::run_preflight(&cfg)?;
+    let kv = Kv::open(paths.kv.clone());
+    report.leases_moved = move_leases(&paths.run.join("leases"), &kv)?;
+    report.idempotency_retired = retire_dir(&paths.run.join("idempotency"))?;
+
+    let ids = meta_ids(&paths.repo)?;
+    let mut changed = Vec::new();
+    // Bootstrapping appends any ignore rules older releases did not write.
+    if !gitops::run_git(
+        &paths.repo,
+        &["status", "--porcelain", "--", ".gitignore"],
+        &cfg,
+    )?
+    .is_empty()
+    {
+        changed.push(".gitignore".to_string());
+    }
+    for id in &ids {
+        let written = store::backfill_paste_maps(&paths.repo, id)?;
+        report.slugs_added += written.iter().filter(|p| p.starts_with("slugs/")).count();
+        report.short_ids_added += written.iter().filter(|p| p.starts_with("short/")).count();
+        changed.extend(written);
+    }
+    if !changed.is_empty() {
+        let mut add = vec!["add", "--"];
+        add.extend(changed.iter().map(String::as_str));
+        gitops::run_git(&paths.repo, &add, &cfg)?;
+        gitops::run_git(
+            &paths.repo,
+            &["commit", "-m", "migrate: upgrade repo layout"],
+            &cfg,
+        )?;
+        report.commit = Some(gitops::run_git(
+            &paths.repo,
+            &["rev-parse", "--short=12", "HEAD"],
+            &cfg,
+        )?);
+    }
+
+    verify(&paths, &cfg, &kv, &ids, &mut report)?;
+    Ok(report)
+}
+
+fn default_backup_path(dir: &Path) -> PathBuf {
+    let mut name = dir
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_else(|| "lanpaste".into());
+    name.push(format!(
+        ".pre-migrate-{}",
+        OffsetDateTime::now_utc().unix_timestamp()
+    ));
+    dir.with_file_name(name)
+}
+
+/// Copies `from` to `to`, skipping the scratch directory `skip` and the
+/// daemon lock file.
+fn copy_tree(from: &Path, to: &Path, skip: &Path) -> AppResult<()> {
+    fs::create_dir_all(to).map_err(|e| AppError::io("create backup dir", e))?;
+    for entry in fs::read_dir(from).map_err(|e| AppError::io("read dir for backup", e))? {
+        let entry = entry.map_err(|e| AppError::io("read dir entry for backup", e))?;
+        let path = entry.path();
+        let target = to.join(entry.file_name());
+        let kind = entry
+            .file_type()
+            .map_err(|e| AppError::io("stat for backup", e))?;
+        if path == skip || entry.file_name() == "daemon.lock" {
+            continue;
+        }
+        if kind.is_dir() {
+            copy_tree(&path, &target, skip)?;
+        } else if kind.is_file() {
+            fs::copy(&path, &target).map_err(|e| AppError::io("copy for backup", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves lease files from the pre-kv layout, keeping the time they had left.
+fn move_leases(dir: &Path, kv: &Kv) -> AppResult<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(AppError::io("read legacy leases", e)),
+    };
+    let now = OffsetDateTime::now_utc();
+    let mut moved = 0;
+    for entry in entries.flatten() {
+        let Ok(data) = fs::read(entry.path()) else {
+            continue;
+        };
+        let Ok(lease) = serde_json::from_slice::<PasteLease>(&data) else {
+            continue;
+        };
+        let Ok(left) = std::time::Duration::try_from(lease.expires_at - now) else {
+            continue;
+        };
+        if left.is_zero() {
+            continue;
+        }
+        kv.put(LEASES_NS, &lease.id, &lease, Some(left))?;
+        moved += 1;
+    }
+    fs::remove_dir_all(dir).map_err(|e| AppError::io("remove legacy leases", e))?;
+    Ok(moved)
+}
+
+/// Drops pre-kv idempotency records. Their fingerprints were computed
+/// differently, so a replay would be refused as a conflicting reuse; without
+/// them a retry creates a fresh paste, and the backup still holds them.
+fn retire_dir(dir: &Path) -> AppResult<usize> {
+    let count = match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().count(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(AppError::io("read legacy idempotency records", e)),
+    };
+    fs::remove_dir_all(dir).map_err(|e| AppError::io("remove legacy idempotency records", e))?;
+    Ok(count)
+}
+
+fn meta_ids(repo: &Path) -> AppResult<Vec<String>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(repo.join("meta")).map_err(|e| AppError::io("read meta dir", e))? {
+        let entry = entry.map_err(|e| AppError::io("read meta entry", e))?;
+        let name = entry.file_name();
+        if let Some(id) = name.to_string_lossy().strip_suffix(".json") {
+            ids.push(id.to_string());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Every paste must parse, match its recorded sha256 and, when listed,
+/// resolve through its short id; the repo must be clean and no legacy state left. Verified pastes
+/// are added to the blob index on the way.
+fn verify(
+    paths: &AppPaths,
+    cfg: &ServeCmd,
+    kv: &Kv,
+    ids: &[String],
+    report: &mut MigrateReport,
+) -> AppResult<()> {
+    for id in ids {
+        match verify_paste(&paths.repo, cfg, id) {
+            Ok(meta) => {
+                blobs::record(kv, &meta)?;
+                report.pastes_verified += 1;
+            }
+            Err(err) => report.problems.push(format!("paste {id}: {err:?}")),
+        }
+    }
+    let status = gitops::run_git(&paths.repo, &["status", "--porcelain"], cfg)?;
+    if !status.is_empty() {
+        report
+            .problems
+            .push(format!("repo has uncommitted changes:\n{status}"));
+    }
+    for legacy in ["idempotency", "leases"] {
+        if paths.run.join(legacy).exists() {
+            report
+                .problems
+                .push(format!("run/{legacy}/ is still present"));
+        }
+    }
+    Ok(())
+}
+
+fn verify_paste(repo: &Path, cfg: &ServeCmd, id: &str) -> AppResult<PasteMeta> {
+    let meta = store::read_meta(repo, cfg, id)?;
+    let bytes = store::read_paste(repo, &meta)?;
+    store::verify_integrity(&meta, &bytes)?;
+    let Some(short) = meta.short_id.as_deref() else {
+        if meta.visibility.is_listed() {
+            return Err(AppError::internal("no short id"));
+        }
+        return Ok(meta);
+    };
+    if store::resolve_short_id(repo, short)?.as_deref() != Some(id) {
+        return Err(AppError::internal(format!(
+            "short id {short} does not resolve"
+        )));
+    }
+    Ok(meta)
+}
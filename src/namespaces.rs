@@ -0,0 +1,96 @@
+//! `--namespace NAME=DIR`: one daemon serving several teams. Each namespace
+//! is a data directory of its own, laid out like `--dir` and served under
+//! `/ns/NAME/`, so its pastes, git lock, runtime state and retention never
+//! mix with another's. Keys and settings come from the files `lanpaste init`
+//! writes in the directory, `api-keys.json` and `config.json`, read like
+//! `--api-keys-file` and `--config-file`.
+//!
+//! The remaining flags are shared. Integrations that announce or ship pastes
+//! elsewhere (webhooks, chat, digests, backups, mDNS) and the gRPC listener
+//! stay with the main repo.
+
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use crate::{
+    config::{Namespace, ServeCmd},
+    errors::{AppError, AppResult},
+    init::{API_KEYS_FILE, CONFIG_FILE},
+    preflight,
+    types::AppState,
+};
+
+/// `/ns/NAME`, where a namespace's routes are mounted.
+pub fn mount_path(name: &str) -> String {
+    format!("/ns/{name}")
+}
+
+/// The serve config of one namespace, derived from the main one.
+pub fn config_for(main: &ServeCmd, ns: &Namespace) -> AppResult<ServeCmd> {
+    let keys_file = ns.dir.join(API_KEYS_FILE);
+    let config_file = ns.dir.join(CONFIG_FILE);
+    // Falling back to the main key file would let one team's keys into
+    // another's repo.
+    if main.api_keys_file.is_some() && !keys_file.exists() {
+        return Err(AppError::BadRequest(format!(
+            "namespace '{}': --api-keys-file is set, so {} must exist",
+            ns.name,
+            keys_file.display()
+        )));
+    }
+    let mut cfg = main.clone();
+    cfg.dir = ns.dir.clone();
+    cfg.base_path = format!("{}{}", main.base_path, mount_path(&ns.name));
+    cfg.namespace = Vec::new();
+    cfg.api_keys_file = keys_file.exists().then_some(keys_file);
+    cfg.config_file = config_file.exists().then_some(config_file);
+    cfg.archive_dir = None;
+    cfg.grpc_bind = None;
+    cfg.pid_file = None;
+    cfg.startup_json = false;
+    cfg.mdns = false;
+    cfg.digest_at = None;
+    cfg.backup_s3_endpoint = None;
+    cfg.webhook_url = Vec::new();
+    cfg.chat_slack_url = Vec::new();
+    cfg.chat_discord_url = Vec::new();
+    cfg.chat_matrix_homeserver = None;
+    cfg.self_test_interval = None;
+    Ok(cfg)
+}
+
+/// Prepares and opens every namespace of `main`, in flag order.
+pub fn build(main: &ServeCmd) -> AppResult<Vec<(String, Arc<AppState>)>> {
+    let mut names = HashSet::new();
+    let mut dirs = vec![main.dir.as_path()];
+    for ns in &main.namespace {
+        if !names.insert(ns.name.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "namespace '{}' is given twice",
+                ns.name
+            )));
+        }
+        if dirs.iter().any(|dir| same_dir(dir, &ns.dir)) {
+            return Err(AppError::BadRequest(format!(
+                "namespace '{}' needs a directory of its own",
+                ns.name
+            )));
+        }
+        dirs.push(&ns.dir);
+    }
+
+    let mut built = Vec::with_capacity(main.namespace.len());
+    for ns in &main.namespace {
+        let cfg = config_for(main, ns)?;
+        preflight::run_preflight(&cfg)?;
+        let state = preflight::build_state(cfg)?;
+        built.push((ns.name.clone(), Arc::new(state)));
+    }
+    Ok(built)
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
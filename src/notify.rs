@@ -0,0 +1,262 @@
+//! Outbound notifications: a JSON webhook and/or plain-text email through a
+//! LAN SMTP relay. Each configured channel is tried; the first failure is
+//! returned after all of them have been attempted.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tracing::warn;
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub event: &'static str,
+    pub subject: String,
+    pub text: String,
+    pub data: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+struct SmtpTarget {
+    relay: String,
+    from: String,
+    to: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    webhook: Option<String>,
+    smtp: Option<SmtpTarget>,
+}
+
+impl Notifier {
+    pub fn from_config(cfg: &ServeCmd) -> AppResult<Self> {
+        let smtp = match &cfg.notify_smtp {
+            None => None,
+            Some(_) if cfg.notify_email_to.is_empty() => {
+                return Err(AppError::internal(
+                    "--notify-smtp needs at least one --notify-email-to",
+                ));
+            }
+            Some(relay) => Some(SmtpTarget {
+                relay: relay.clone(),
+                from: cfg.notify_email_from.clone(),
+                to: cfg.notify_email_to.clone(),
+            }),
+        };
+        for addr in cfg.notify_email_to.iter().chain([&cfg.notify_email_from]) {
+            if !addr.contains('@') || addr.contains(['<', '>', '\r', '\n']) {
+                return Err(AppError::internal(format!(
+                    "invalid email address '{addr}'"
+                )));
+            }
+        }
+        Ok(Self {
+            webhook: cfg.notify_webhook.clone(),
+            smtp,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.webhook.is_some() || self.smtp.is_some()
+    }
+
+    pub async fn send(&self, note: &Notification) -> AppResult<()> {
+        let mut first_err = None;
+        if let Some(url) = &self.webhook
+            && let Err(err) = post_webhook(url, note).await
+        {
+            warn!("webhook notification failed: {err:?}");
+            first_err.get_or_insert(err);
+        }
+        if let Some(smtp) = &self.smtp {
+            let sent = tokio::time::timeout(SEND_TIMEOUT, send_mail(smtp, note))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(AppError::ServiceUnavailable("smtp timed out".to_string()))
+                });
+            if let Err(err) = sent {
+                warn!("email notification failed: {err:?}");
+                first_err.get_or_insert(err);
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+}
+
+async fn post_webhook(url: &str, note: &Notification) -> AppResult<()> {
+    let client = reqwest::Client::builder()
+        .timeout(SEND_TIMEOUT)
+        .build()
+        .map_err(|e| AppError::internal(format!("build http client: {e}")))?;
+    let response = client
+        .post(url)
+        .json(note)
+        .send()
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("webhook request failed: {e}")))?;
+    if !response.status().is_success() {
+        return Err(AppError::ServiceUnavailable(format!(
+            "webhook returned {}",
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+/// Minimal SMTP client: EHLO, MAIL, RCPT, DATA, QUIT over plain TCP.
+async fn send_mail(target: &SmtpTarget, note: &Notification) -> AppResult<()> {
+    let stream = TcpStream::connect(&target.relay)
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("connect smtp relay: {e}")))?;
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+
+    expect_reply(&mut read, 220).await?;
+    command(&mut write, &mut read, "EHLO lanpaste", 250).await?;
+    command(
+        &mut write,
+        &mut read,
+        &format!("MAIL FROM:<{}>", target.from),
+        250,
+    )
+    .await?;
+    for to in &target.to {
+        command(&mut write, &mut read, &format!("RCPT TO:<{to}>"), 250).await?;
+    }
+    command(&mut write, &mut read, "DATA", 354).await?;
+
+    let subject: String = note.subject.chars().filter(|c| !c.is_control()).collect();
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {subject}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        target.from,
+        target.to.join(", "),
+        httpdate::fmt_http_date(std::time::SystemTime::now()),
+    );
+    for line in note.text.lines() {
+        // Dot-stuffing keeps a lone "." in the body from ending DATA early.
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+    write
+        .write_all(message.as_bytes())
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("smtp write: {e}")))?;
+    expect_reply(&mut read, 250).await?;
+    command(&mut write, &mut read, "QUIT", 221).await
+}
+
+async fn command<R, W>(write: &mut W, read: &mut R, line: &str, code: u16) -> AppResult<()>
+where
+    R: AsyncBufReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    write
+        .write_all(format!("{line}\r\n").as_bytes())
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("smtp write: {e}")))?;
+    expect_reply(read, code).await
+}
+
+/// Reads a (possibly multi-line) reply and checks its status code.
+async fn expect_reply<R: AsyncBufReadExt + Unpin>(read: &mut R, code: u16) -> AppResult<()> {
+    loop {
+        let mut line = String::new();
+        let n = read
+            .read_line(&mut line)
+            .await
+            .map_err(|e| AppError::ServiceUnavailable(format!("smtp read: {e}")))?;
+        if n == 0 {
+            return Err(AppError::ServiceUnavailable(
+                "smtp relay closed the connection".to_string(),
+            ));
+        }
+        let got = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+        if got != Some(code) && !(code == 250 && got == Some(251)) {
+            return Err(AppError::ServiceUnavailable(format!(
+                "smtp relay replied '{}'",
+                line.trim_end()
+            )));
+        }
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn smtp_dialog_delivers_dot_stuffed_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let relay = listener.local_addr().expect("addr").to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("accept");
+            let (read, mut write) = stream.into_split();
+            let mut read = BufReader::new(read);
+            write.write_all(b"220 relay\r\n").await.expect("greet");
+            let mut transcript = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if read.read_line(&mut line).await.expect("read") == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-relay\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go\r\n"
+                } else if line.starts_with("QUIT") {
+                    write.write_all(b"221 bye\r\n").await.expect("bye");
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                write.write_all(reply).await.expect("reply");
+            }
+            transcript
+        });
+
+        let target = SmtpTarget {
+            relay,
+            from: "paste@lan".to_string(),
+            to: vec!["ops@lan".to_string(), "dev@lan".to_string()],
+        };
+        let note = Notification {
+            event: "digest",
+            subject: "Daily\r\nBcc: x".to_string(),
+            text: "line one\n.hidden\n".to_string(),
+            data: serde_json::Value::Null,
+        };
+        send_mail(&target, &note).await.expect("send");
+        let transcript = server.await.expect("server");
+        assert!(transcript.contains("RCPT TO:<dev@lan>\r\n"));
+        assert!(transcript.contains("Subject: DailyBcc: x\r\n"));
+        assert!(transcript.contains("\r\n..hidden\r\n"));
+    }
+}
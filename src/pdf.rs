@@ -0,0 +1,90 @@
+//! `GET /p/{id}/pdf`: a paste's print page turned into a PDF by an outside
+//! program, set with `--pdf-command`, so meeting notes can be archived or
+//! printed without screenshotting the browser.
+//!
+//! The program gets the HTML of `/p/{id}/print` on stdin and writes the PDF
+//! to stdout; `weasyprint - -` and `wkhtmltopdf --quiet - -` both work as
+//! is. It runs with the server's privileges, so it is configured on the
+//! command line and never chosen by a request.
+
+use std::{process::Stdio, time::Duration};
+
+use tokio::{io::AsyncWriteExt, process::Command, sync::Semaphore, time::timeout};
+
+use crate::errors::{AppError, AppResult};
+
+/// Renders at once; more get `503` rather than a pile of browsers.
+const MAX_JOBS: usize = 2;
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest PDF accepted from the program.
+const MAX_PDF_BYTES: usize = 64 * 1024 * 1024;
+
+static JOBS: Semaphore = Semaphore::const_new(MAX_JOBS);
+
+/// Runs `command` over `html`, returning the PDF.
+pub async fn render(command: &str, html: String) -> AppResult<Vec<u8>> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| AppError::internal("--pdf-command is empty"))?;
+    let _job = JOBS
+        .try_acquire()
+        .map_err(|_| AppError::Busy("PDF renderer is busy; retry shortly".to_string()))?;
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AppError::internal(format!("start PDF renderer {program}: {e}")))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    // Written alongside the wait, so a renderer that streams output before
+    // reading all its input cannot deadlock on a full pipe.
+    let feed = async move {
+        let written = stdin.write_all(html.as_bytes()).await;
+        drop(stdin);
+        written
+    };
+    let (fed, out) = timeout(TIMEOUT, async {
+        tokio::join!(feed, child.wait_with_output())
+    })
+    .await
+    .map_err(|_| AppError::ServiceUnavailable("PDF renderer timed out".to_string()))?;
+    let out = out.map_err(|e| AppError::internal(format!("PDF renderer {program}: {e}")))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(AppError::internal(format!(
+            "PDF renderer {program} failed ({}): {}",
+            out.status,
+            stderr.trim().chars().take(500).collect::<String>()
+        )));
+    }
+    fed.map_err(|e| AppError::internal(format!("PDF renderer {program} stdin: {e}")))?;
+    if !out.stdout.starts_with(b"%PDF-") {
+        return Err(AppError::internal(format!(
+            "PDF renderer {program} did not write a PDF"
+        )));
+    }
+    if out.stdout.len() > MAX_PDF_BYTES {
+        return Err(AppError::internal(format!(
+            "PDF renderer {program} wrote more than {MAX_PDF_BYTES} bytes"
+        )));
+    }
+    Ok(out.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn output_must_be_a_pdf() {
+        let pdf = render("sed s/^<p>/%PDF-1.4\\n/", "<p>notes".to_string())
+            .await
+            .expect("render");
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(render("cat", "<p>notes".to_string()).await.is_err());
+        assert!(render("false", String::new()).await.is_err());
+    }
+}
@@ -1,26 +1,51 @@
-use std::{fs, fs::OpenOptions, path::PathBuf, sync::Arc};
+use std::{
+    fs,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::AtomicU64},
+};
 
 use fs2::FileExt;
+use tracing::{info, warn};
 
 use crate::{
+    admin::Ops,
     auth::ApiKeyStore,
+    authlog::AuthLog,
+    backup::S3Target,
+    chat::Chat,
+    committer::Committer,
     config::ServeCmd,
+    crypt::PasteCipher,
     errors::{AppError, AppResult},
-    gitops,
+    events::Events,
+    gitops::{self, FileLock},
+    hostrules::HostRules,
+    index::MetaIndex,
+    journal::{self, Journal, Outcome},
+    kv::Kv,
+    namespaces,
+    notify::Notifier,
+    ratelimit::Limits,
+    sandbox::RenderPool,
+    settings::Settings,
+    token::SharedToken,
     types::{AppPaths, AppState},
+    webhooks::Webhooks,
 };
 
 pub fn run_preflight(cfg: &ServeCmd) -> AppResult<()> {
     gitops::check_git_installed()?;
     let paths = AppPaths::from_base(cfg.dir.clone());
     fs::create_dir_all(&paths.run).map_err(|e| AppError::io("create run dir", e))?;
-    fs::create_dir_all(&paths.idempotency)
-        .map_err(|e| AppError::io("create idempotency dir", e))?;
+    fs::create_dir_all(&paths.kv).map_err(|e| AppError::io("create kv dir", e))?;
     fs::create_dir_all(&paths.tmp).map_err(|e| AppError::io("create tmp dir", e))?;
     fs::create_dir_all(&paths.repo).map_err(|e| AppError::io("create repo dir", e))?;
     fs::create_dir_all(&paths.files).map_err(|e| AppError::io("create files dir", e))?;
     fs::create_dir_all(&paths.files_meta).map_err(|e| AppError::io("create files meta dir", e))?;
 
+    check_free_space(&paths.base, cfg.min_free_bytes)?;
+
     let write_test = paths.run.join(".write_test");
     fs::write(&write_test, b"ok").map_err(|e| AppError::io("write test file", e))?;
     fs::remove_file(&write_test).map_err(|e| AppError::io("cleanup write test", e))?;
@@ -29,9 +54,43 @@ pub fn run_preflight(cfg: &ServeCmd) -> AppResult<()> {
     Ok(())
 }
 
-pub fn build_state(cfg: ServeCmd) -> AppResult<AppState> {
+/// Free bytes on the filesystem holding `dir`, or `InsufficientStorage` when
+/// that is under `min` (`0` skips the check).
+pub fn check_free_space(dir: &Path, min: u64) -> AppResult<u64> {
+    if min == 0 {
+        return Ok(u64::MAX);
+    }
+    let free = fs2::available_space(dir).map_err(|e| AppError::io("check free space", e))?;
+    if free < min {
+        return Err(AppError::InsufficientStorage(format!(
+            "only {free} bytes free on {}; at least {min} are needed (--min-free-bytes)",
+            dir.display()
+        )));
+    }
+    Ok(free)
+}
+
+pub fn build_state(mut cfg: ServeCmd) -> AppResult<AppState> {
+    cfg.allow_cidr = cfg.allowlist();
     let paths = AppPaths::from_base(cfg.dir.clone());
     let api_keys = ApiKeyStore::from_file(cfg.api_keys_file.as_deref())?;
+    if cfg.require_token_for_read && !cfg.has_token() {
+        return Err(AppError::BadRequest(
+            "--require-token-for-read needs --token".to_string(),
+        ));
+    }
+    if cfg.protect_ui && !cfg.has_token() && !api_keys.enabled() {
+        return Err(AppError::BadRequest(
+            "--protect-ui needs --token or --api-keys-file to log in with".to_string(),
+        ));
+    }
+    let host_rules = HostRules::from_file(cfg.host_rules_file.as_deref())?;
+    let cipher = PasteCipher::from_file(cfg.encrypt_key_file.as_deref())?;
+    let notifier = Notifier::from_config(&cfg)?;
+    let backup = S3Target::from_config(&cfg)?;
+    let settings = Settings::load(&cfg)?;
+    let webhooks = Webhooks::from_config(&cfg, &settings.current().webhook_url)?;
+    let chat = Chat::from_config(&cfg)?;
     let lock_path = paths.run.join("daemon.lock");
     let daemon_lock = OpenOptions::new()
         .create(true)
@@ -44,11 +103,57 @@ pub fn build_state(cfg: ServeCmd) -> AppResult<AppState> {
         .try_lock_exclusive()
         .map_err(|_| AppError::Conflict("already running".to_string()))?;
 
+    let kv = Kv::open(paths.kv.clone());
+    let purged = kv.purge_expired()?;
+    if purged > 0 {
+        info!("purged {purged} expired runtime kv entries");
+    }
+    let api_keys = api_keys.persist_usage(kv.clone())?;
+    let token = Arc::new(SharedToken::from_config(&cfg)?.persisted(kv.clone())?);
+    let auth_log = AuthLog::open(cfg.auth_log.as_deref())?;
+
+    let journal = Journal::open(kv.clone());
+    if !journal.pending()?.is_empty() {
+        let _git_lock = FileLock::acquire(&paths.git_lock)?;
+        let stuck = journal::reconcile(&paths.repo, &cfg, &journal)?
+            .into_iter()
+            .filter(|(_, outcome)| *outcome == Outcome::Stuck)
+            .count();
+        if stuck > 0 {
+            warn!("{stuck} interrupted strict-mode writes need attention; see run/kv/journal");
+        }
+    }
+
+    let renders = Arc::new(RenderPool::from_config(&cfg));
+    let limits = Arc::new(Limits::from_config(&cfg));
+    let index = Arc::new(MetaIndex::load(&paths.repo, &kv)?);
+    let committer = Arc::new(Committer::start(&cfg, &paths)?);
+    let namespaces = namespaces::build(&cfg)?;
     Ok(AppState {
         cfg,
         paths,
         _daemon_lock: Arc::new(daemon_lock),
         api_keys,
+        token,
+        auth_log,
+        cipher,
+        host_rules,
+        read_counter: Arc::new(AtomicU64::new(0)),
+        kv,
+        self_test: Arc::new(Mutex::new(None)),
+        ops: Arc::new(Ops::default()),
+        notifier,
+        renders,
+        backup,
+        index,
+        events: Events::default(),
+        webhooks,
+        chat,
+        settings: Arc::new(settings),
+        limits,
+        remote_probe: Arc::default(),
+        committer,
+        namespaces,
     })
 }
 
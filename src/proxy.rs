@@ -0,0 +1,125 @@
+//! Client addresses behind a reverse proxy. `Forwarded` and
+//! `X-Forwarded-For` are only believed when the TCP peer is one of
+//! `--trusted-proxies`; otherwise anyone could claim any address and walk
+//! past `--allow-cidr`.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::{HeaderMap, HeaderName, header};
+use ipnet::IpNet;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// The address a request came from. With a trusted `peer`, the forwarding
+/// chain is read right to left, skipping trusted proxies, and the first
+/// other hop is the client. A hop that cannot be parsed ends the walk at the
+/// last trusted proxy.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let hops = forwarded_hops(headers);
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        let Some(ip) = parse_hop(hop) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// `for=` values of `Forwarded`, or else the `X-Forwarded-For` list, in
+/// order from the original client to the nearest proxy.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<String> {
+    let joined = |name: &HeaderName| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let forwarded = joined(&header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .split(',')
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.trim().split_once('=')?;
+                    key.eq_ignore_ascii_case("for")
+                        .then(|| value.trim().to_string())
+                })
+            })
+            .collect();
+    }
+    joined(&X_FORWARDED_FOR)
+        .split(',')
+        .map(|hop| hop.trim().to_string())
+        .filter(|hop| !hop.is_empty())
+        .collect()
+}
+
+/// `192.0.2.1`, `192.0.2.1:4711`, `"[2001:db8::1]:4711"` or `2001:db8::1`.
+fn parse_hop(raw: &str) -> Option<IpAddr> {
+    let raw = raw.trim().trim_matches('"');
+    let ip = if let Some(rest) = raw.strip_prefix('[') {
+        rest.split_once(']')?.0.parse().ok()?
+    } else {
+        raw.parse::<IpAddr>()
+            .ok()
+            .or_else(|| raw.parse::<SocketAddr>().ok().map(|addr| addr.ip()))?
+    };
+    Some(ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        map
+    }
+
+    #[test]
+    fn forwarding_headers_count_only_from_trusted_peers() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/24".parse().unwrap()];
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let stranger: IpAddr = "192.168.1.9".parse().unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let xff = headers(&[("x-forwarded-for", "192.168.1.50")]);
+        assert_eq!(client_ip(stranger, &xff, &trusted), stranger);
+        assert_eq!(client_ip(proxy, &xff, &trusted), ip("192.168.1.50"));
+        assert_eq!(client_ip(proxy, &HeaderMap::new(), &trusted), proxy);
+
+        // A spoofed entry to the left of the real client is never reached.
+        let chain = headers(&[
+            ("x-forwarded-for", "127.0.0.1, 192.168.1.50"),
+            ("x-forwarded-for", "10.0.0.7"),
+        ]);
+        assert_eq!(client_ip(proxy, &chain, &trusted), ip("192.168.1.50"));
+
+        let forwarded = headers(&[(
+            "forwarded",
+            r#"for=192.0.2.60;proto=http, for="[2001:db8::1]:4711";by=10.0.0.2"#,
+        )]);
+        assert_eq!(client_ip(proxy, &forwarded, &trusted), ip("2001:db8::1"));
+        let mapped = headers(&[("forwarded", "for=\"[::ffff:192.168.1.3]\"")]);
+        assert_eq!(client_ip(proxy, &mapped, &trusted), ip("192.168.1.3"));
+
+        let hidden = headers(&[("x-forwarded-for", "unknown, 10.0.0.7")]);
+        assert_eq!(client_ip(proxy, &hidden, &trusted), ip("10.0.0.7"));
+    }
+}
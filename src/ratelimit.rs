@@ -0,0 +1,256 @@
+//! Server-wide limits on top of the per-key `max_requests_per_minute`:
+//!
+//! - `--ip-rate`: a token bucket per client IP for requests without an API
+//!   key, i.e. the shared `--token` and anonymous reads
+//! - `--write-rate`: one bucket shared by every create, upload, append and
+//!   edit, so a burst of CI uploads cannot queue up git commits
+//! - `--max-in-flight`: requests being handled at once; the rest get `503`
+//! - `--auth-fail-limit`: failed token or key attempts per client IP before
+//!   it is locked out for `--auth-ban`, doubling with each lockout
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Semaphore;
+
+use crate::config::ServeCmd;
+
+/// Past this many tracked keys, full buckets are dropped; a dropped
+/// key starts again with a full bucket, so nothing is lost.
+const MAX_TRACKED: usize = 4096;
+
+/// Failures further apart than this do not add up.
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// A client that stays out of trouble this long starts again from the
+/// first lockout length.
+const STRIKE_MEMORY: Duration = Duration::from_secs(24 * 3600);
+/// The longest lockout, however often a client comes back.
+const MAX_BAN: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    /// Takes a token from `key`'s bucket, which refills at `rate` per second
+    /// up to `burst`. When it is empty, returns how long until one is back.
+    pub fn check(&self, key: K, rate: f64, burst: u32, now: Instant) -> Result<(), Duration> {
+        let burst = f64::from(burst.max(1));
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_TRACKED && !buckets.contains_key(&key) {
+            buckets.retain(|_, b| refill(b, rate, burst, now).tokens < burst);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        *bucket = refill(bucket, rate, burst, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Strikes {
+    /// Failures since the last lockout, within [`FAILURE_WINDOW`].
+    failures: u32,
+    last_failure: Instant,
+    /// Lockouts so far, within [`STRIKE_MEMORY`].
+    bans: u32,
+    banned_until: Option<Instant>,
+}
+
+/// What a failed attempt did to its client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// `count` of the limit, no lockout yet.
+    Counted { count: u32 },
+    /// The client is locked out for this long.
+    Banned { ban: Duration },
+}
+
+/// Failed credential attempts per client IP, for `--auth-fail-limit`.
+#[derive(Debug, Default)]
+pub struct AuthFailures {
+    clients: Mutex<HashMap<IpAddr, Strikes>>,
+}
+
+impl AuthFailures {
+    /// How long `client` is still locked out, if it is.
+    pub fn banned(&self, client: IpAddr, now: Instant) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        clients
+            .get(&client)
+            .and_then(|s| s.banned_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Counts a failed attempt from `client`. The `limit`th failure within
+    /// [`FAILURE_WINDOW`] locks it out for `ban`, doubled for each earlier
+    /// lockout.
+    pub fn fail(&self, client: IpAddr, limit: u32, ban: Duration, now: Instant) -> Failure {
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        if clients.len() >= MAX_TRACKED && !clients.contains_key(&client) {
+            clients.retain(|_, s| now.saturating_duration_since(s.last_failure) < STRIKE_MEMORY);
+        }
+        let strikes = clients.entry(client).or_insert(Strikes {
+            failures: 0,
+            last_failure: now,
+            bans: 0,
+            banned_until: None,
+        });
+        let quiet = now.saturating_duration_since(strikes.last_failure);
+        if quiet >= STRIKE_MEMORY {
+            strikes.bans = 0;
+        }
+        if quiet >= FAILURE_WINDOW {
+            strikes.failures = 0;
+        }
+        strikes.failures += 1;
+        strikes.last_failure = now;
+        if strikes.failures < limit {
+            return Failure::Counted {
+                count: strikes.failures,
+            };
+        }
+        let ban = ban
+            .saturating_mul(2u32.saturating_pow(strikes.bans))
+            .min(MAX_BAN);
+        strikes.failures = 0;
+        strikes.bans += 1;
+        strikes.banned_until = Some(now + ban);
+        Failure::Banned { ban }
+    }
+
+    /// A successful attempt clears the failures, but not earlier lockouts:
+    /// a client that guessed right once should not get a fresh budget.
+    pub fn succeed(&self, client: IpAddr) {
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(strikes) = clients.get_mut(&client) {
+            strikes.failures = 0;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Limits {
+    pub clients: RateLimiter<IpAddr>,
+    pub writes: RateLimiter<()>,
+    pub in_flight: Option<Arc<Semaphore>>,
+    pub auth_failures: AuthFailures,
+}
+
+impl Limits {
+    /// The rates come from the live settings at each request; only the
+    /// in-flight cap is fixed here.
+    pub fn from_config(cfg: &ServeCmd) -> Self {
+        Self {
+            in_flight: cfg.max_in_flight.map(|n| Arc::new(Semaphore::new(n))),
+            ..Self::default()
+        }
+    }
+}
+
+fn refill(bucket: &Bucket, rate: f64, burst: f64, now: Instant) -> Bucket {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    Bucket {
+        tokens: (bucket.tokens + elapsed * rate).min(burst),
+        updated: now.max(bucket.updated),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_client_gets_a_burst_then_the_steady_rate() {
+        let limiter = RateLimiter::default();
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(a, 2.0, 3, start).is_ok());
+        }
+        let wait = limiter.check(a, 2.0, 3, start).expect_err("bucket empty");
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(limiter.check(b, 2.0, 3, start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check(a, 2.0, 3, later).is_ok());
+        assert!(limiter.check(a, 2.0, 3, later).is_err());
+
+        // Idle time refills no further than the burst.
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check(a, 2.0, 3, much_later).is_ok());
+        }
+        assert!(limiter.check(a, 2.0, 3, much_later).is_err());
+    }
+
+    #[test]
+    fn repeated_failures_lock_a_client_out_for_longer_each_time() {
+        let failures = AuthFailures::default();
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let ban = Duration::from_secs(60);
+        let start = Instant::now();
+
+        for count in 1..3 {
+            assert_eq!(failures.fail(a, 3, ban, start), Failure::Counted { count });
+        }
+        assert_eq!(failures.fail(a, 3, ban, start), Failure::Banned { ban });
+        assert_eq!(failures.banned(a, start), Some(ban));
+        assert_eq!(failures.banned(b, start), None);
+
+        let after = start + ban;
+        assert_eq!(failures.banned(a, after), None);
+        for _ in 0..2 {
+            failures.fail(a, 3, ban, after);
+        }
+        assert_eq!(
+            failures.fail(a, 3, ban, after),
+            Failure::Banned { ban: ban * 2 }
+        );
+
+        // A success resets the count, not the lockout length.
+        let later = after + ban * 2;
+        failures.fail(a, 3, ban, later);
+        failures.succeed(a);
+        assert_eq!(
+            failures.fail(a, 3, ban, later),
+            Failure::Counted { count: 1 }
+        );
+
+        // So does time, eventually.
+        let next_day = later + STRIKE_MEMORY;
+        for _ in 0..2 {
+            failures.fail(a, 3, ban, next_day);
+        }
+        assert_eq!(failures.fail(a, 3, ban, next_day), Failure::Banned { ban });
+    }
+}
@@ -0,0 +1,224 @@
+//! `/readyz`: one check per thing writes depend on, so a failing probe says
+//! which part is degraded instead of an opaque `503`.
+//!
+//! A `fail` makes the server not ready. A `warn` leaves it serving: a busy
+//! git lock, unpushed commits or, with `--push best_effort`, an unreachable
+//! remote.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::PushMode,
+    errors::AppError,
+    gitops::{self, FileLock},
+    types::AppState,
+};
+
+/// How long a probe waits for a write to release the git lock.
+const LOCK_WAIT: Duration = Duration::from_secs(1);
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Orchestrators probe every few seconds; the remote is asked at most this
+/// often.
+const REMOTE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Skipped,
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Free bytes for `disk`, unpushed commits for `push_queue`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self::new(CheckStatus::Ok, None)
+    }
+
+    fn new(status: CheckStatus, detail: Option<String>) -> Self {
+        Self {
+            status,
+            detail,
+            value: None,
+        }
+    }
+
+    fn with_value(mut self, value: u64) -> Self {
+        self.value = Some(value);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// `ready`, `degraded` when a check warns, or `not_ready` when one fails.
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Report {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let worst = checks.values().map(|c| c.status).max();
+        let status = match worst {
+            Some(CheckStatus::Fail) => "not_ready",
+            Some(CheckStatus::Warn) => "degraded",
+            _ => "ready",
+        };
+        Self { status, checks }
+    }
+
+    pub fn ready(&self) -> bool {
+        self.status != "not_ready"
+    }
+}
+
+/// The last remote probe, reused for [`REMOTE_TTL`].
+#[derive(Debug, Default)]
+pub struct RemoteProbe {
+    last: Mutex<Option<(Instant, Check)>>,
+}
+
+pub async fn check(state: Arc<AppState>) -> Report {
+    let remote = remote(&state).await;
+    let local = tokio::task::spawn_blocking(move || local(&state)).await;
+    let mut checks = local.unwrap_or_else(|err| {
+        BTreeMap::from([(
+            "repo",
+            Check::new(CheckStatus::Fail, Some(format!("check panicked: {err}"))),
+        )])
+    });
+    checks.insert("remote", remote);
+    Report::new(checks)
+}
+
+/// Checks that touch only this host. Blocks the thread.
+fn local(state: &AppState) -> BTreeMap<&'static str, Check> {
+    let cfg = &state.cfg;
+    let repo = if gitops::is_git_repo(&state.paths.repo, cfg) {
+        Check::ok()
+    } else {
+        Check::new(CheckStatus::Fail, Some("repo not ready".to_string()))
+    };
+    let lock = match FileLock::acquire_within(&state.paths.git_lock, LOCK_WAIT) {
+        Ok(_lock) => Check::ok(),
+        Err(AppError::Conflict(_)) => Check::new(
+            CheckStatus::Warn,
+            Some(format!("held by a write for over {}s", LOCK_WAIT.as_secs())),
+        ),
+        Err(err) => Check::new(CheckStatus::Fail, Some(format!("{err:?}"))),
+    };
+    let disk = match fs2::available_space(&state.paths.base) {
+        Ok(free) if free < cfg.min_free_bytes => Check::new(
+            CheckStatus::Fail,
+            Some(format!(
+                "{free} bytes free, below --min-free-bytes {}",
+                cfg.min_free_bytes
+            )),
+        )
+        .with_value(free),
+        Ok(free) => Check::ok().with_value(free),
+        Err(err) => Check::new(CheckStatus::Fail, Some(format!("statvfs: {err}"))),
+    };
+    let push_queue = if cfg.push == PushMode::Off {
+        Check::new(CheckStatus::Skipped, None)
+    } else {
+        match gitops::unpushed_count(&state.paths.repo, cfg) {
+            Ok(0) => Check::ok().with_value(0),
+            Ok(n) => {
+                Check::new(CheckStatus::Warn, Some(format!("{n} commits not pushed"))).with_value(n)
+            }
+            Err(err) => Check::new(CheckStatus::Warn, Some(format!("{err:?}"))),
+        }
+    };
+    BTreeMap::from([
+        ("repo", repo),
+        ("lock", lock),
+        ("disk", disk),
+        ("push_queue", push_queue),
+    ])
+}
+
+/// `git ls-remote` against `--remote`, cached. Only `--push strict` needs
+/// the remote to accept writes, so elsewhere an outage is a warning.
+async fn remote(state: &AppState) -> Check {
+    if state.cfg.push == PushMode::Off {
+        return Check::new(CheckStatus::Skipped, None);
+    }
+    let probe = &state.remote_probe;
+    if let Some((at, check)) = probe
+        .last
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        && at.elapsed() < REMOTE_TTL
+    {
+        return check.clone();
+    }
+    let output = tokio::process::Command::new("git")
+        .args(["ls-remote", "--heads", &state.cfg.remote])
+        .current_dir(&state.paths.repo)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .kill_on_drop(true)
+        .output();
+    let failure = match tokio::time::timeout(REMOTE_TIMEOUT, output).await {
+        Ok(Ok(out)) if out.status.success() => None,
+        Ok(Ok(out)) => Some(String::from_utf8_lossy(&out.stderr).trim().to_string()),
+        Ok(Err(err)) => Some(format!("git ls-remote: {err}")),
+        Err(_) => Some(format!("no answer within {}s", REMOTE_TIMEOUT.as_secs())),
+    };
+    let check = match failure {
+        None => Check::ok(),
+        Some(detail) if state.cfg.push == PushMode::Strict => {
+            Check::new(CheckStatus::Fail, Some(detail))
+        }
+        Some(detail) => Check::new(CheckStatus::Warn, Some(detail)),
+    };
+    *probe.last.lock().unwrap_or_else(PoisonError::into_inner) =
+        Some((Instant::now(), check.clone()));
+    check
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_worst_check_decides() {
+        let report = |statuses: &[CheckStatus]| {
+            let names = ["a", "b", "c"];
+            Report::new(
+                names
+                    .iter()
+                    .zip(statuses)
+                    .map(|(name, status)| (*name, Check::new(*status, None)))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            report(&[CheckStatus::Ok, CheckStatus::Skipped]).status,
+            "ready"
+        );
+        let degraded = report(&[CheckStatus::Ok, CheckStatus::Warn]);
+        assert_eq!(degraded.status, "degraded");
+        assert!(degraded.ready());
+        let failed = report(&[CheckStatus::Warn, CheckStatus::Fail, CheckStatus::Ok]);
+        assert_eq!(failed.status, "not_ready");
+        assert!(!failed.ready());
+    }
+}
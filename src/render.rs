@@ -1,453 +1,16 @@
 use pulldown_cmark::{Options, Parser, html};
+use qrcode::{QrCode, render::svg};
 
-use crate::types::RecentItem;
-
-const PAGE_CSS: &str = r#"
-:root {
-  --bg: #f2f4f7;
-  --panel: #ffffff;
-  --panel-muted: #f7f9fc;
-  --text: #122033;
-  --text-dim: #4a5a70;
-  --border: #d7e0ea;
-  --link: #1f5fae;
-  --link-hover: #15457e;
-  --code-bg: #0f1726;
-  --code-fg: #f8fbff;
-}
-
-* {
-  box-sizing: border-box;
-}
-
-body {
-  margin: 0;
-  background: radial-gradient(circle at 100% 0%, #e8eef8 0%, var(--bg) 45%);
-  color: var(--text);
-  font-family: "IBM Plex Sans", "Segoe UI", "Helvetica Neue", Arial, sans-serif;
-  font-size: clamp(1rem, 0.94rem + 0.25vw, 1.125rem);
-  line-height: 1.5;
-}
-
-.shell {
-  max-width: 900px;
-  margin: 0 auto;
-  padding: 0.75rem 0.9rem 1.5rem;
-}
-
-@media (min-width: 768px) {
-  .shell {
-    padding: 1rem;
-  }
-}
-
-.card {
-  background: var(--panel);
-  border: 1px solid var(--border);
-  border-radius: 0.9rem;
-  box-shadow: 0 8px 18px rgba(0, 0, 0, 0.05);
-  padding: 1rem;
-}
-
-.paste-header {
-  display: flex;
-  flex-wrap: wrap;
-  gap: 0.75rem;
-  justify-content: space-between;
-  align-items: center;
-  margin-bottom: 0.9rem;
-}
-
-.paste-meta {
-  color: var(--text-dim);
-  font-size: 0.95em;
-}
-
-.paste-meta code {
-  background: #edf2f8;
-  padding: 0.15rem 0.35rem;
-  border-radius: 0.3rem;
-}
-
-.toolbar {
-  display: flex;
-  flex-wrap: wrap;
-  gap: 0.5rem;
-}
-
-button,
-.button-link {
-  min-height: 2.75rem;
-  border-radius: 0.55rem;
-  border: 1px solid var(--border);
-  background: var(--panel);
-  color: var(--text);
-  font: inherit;
-  padding: 0.45rem 0.8rem;
-  cursor: pointer;
-  text-decoration: none;
-}
-
-button:hover,
-.button-link:hover {
-  border-color: #b8c8da;
-  background: var(--panel-muted);
-}
-
-.content {
-  overflow-wrap: break-word;
-}
-
-.content h1,
-.content h2,
-.content h3,
-.content h4,
-.content h5,
-.content h6 {
-  line-height: 1.25;
-  margin-top: 1.1em;
-  margin-bottom: 0.5em;
-}
-
-.content p,
-.content li {
-  max-width: 75ch;
-}
-
-.content a {
-  color: var(--link);
-  text-decoration-color: color-mix(in srgb, var(--link), transparent 45%);
-  text-underline-offset: 0.12em;
-}
-
-.content a:hover {
-  color: var(--link-hover);
-}
-
-pre {
-  background: var(--code-bg);
-  color: var(--code-fg);
-  padding: 0.75rem;
-  border-radius: 0.65rem;
-  overflow-x: auto;
-  white-space: pre;
-  tab-size: 4;
-  position: relative;
-}
-
-pre code {
-  background: transparent;
-  color: inherit;
-  white-space: inherit;
-  font-family: "JetBrains Mono", "SFMono-Regular", Consolas, "Liberation Mono", Menlo, monospace;
-  tab-size: 4;
-}
-
-p code,
-li code,
-td code,
-th code {
-  background: #edf2f8;
-  color: #1f2c3f;
-  border-radius: 0.3rem;
-  padding: 0.1em 0.32em;
-  font-family: "JetBrains Mono", "SFMono-Regular", Consolas, "Liberation Mono", Menlo, monospace;
-}
+use crate::{
+    admin::AdminStatus,
+    assets, auth,
+    enroll::PendingEnrollment,
+    types::{Collection, RecentItem},
+};
 
-.code-copy {
-  position: absolute;
-  top: 0.4rem;
-  right: 0.4rem;
-  min-height: 2.1rem;
-  font-size: 0.85em;
-  border-color: #4d6078;
-  background: rgba(18, 34, 51, 0.85);
-  color: #ffffff;
-}
-
-.code-copy:hover {
-  background: rgba(18, 34, 51, 1);
-}
-
-.table-wrap {
-  overflow-x: auto;
-  margin: 0.85rem 0;
-}
-
-table {
-  border-collapse: collapse;
-  min-width: 100%;
-}
-
-th,
-td {
-  border: 1px solid var(--border);
-  padding: 0.5rem;
-  text-align: left;
-  vertical-align: top;
-}
-
-thead th {
-  background: var(--panel-muted);
-}
-
-blockquote {
-  margin: 1em 0;
-  padding: 0.2em 0.9em;
-  border-left: 4px solid #a9bfd7;
-  color: var(--text-dim);
-  background: #f8fbff;
-}
-
-details {
-  border: 1px solid var(--border);
-  border-radius: 0.6rem;
-  padding: 0.45rem 0.7rem;
-  margin: 0.8rem 0;
-  background: var(--panel-muted);
-}
-
-summary {
-  cursor: pointer;
-  font-weight: 600;
-}
-
-img {
-  max-width: 100%;
-  height: auto;
-  border-radius: 0.45rem;
-}
-
-#lightbox {
-  position: fixed;
-  inset: 0;
-  background: rgba(0, 0, 0, 0.86);
-  display: none;
-  align-items: center;
-  justify-content: center;
-  padding: 1rem;
-  z-index: 9999;
-}
-
-#lightbox.open {
-  display: flex;
-}
-
-#lightbox img {
-  max-width: min(96vw, 1400px);
-  max-height: 92vh;
-  border-radius: 0.5rem;
-  background: #ffffff;
-}
-
-.helper-text {
-  color: var(--text-dim);
-  font-size: 0.92em;
-}
-
-.tag-list {
-  display: flex;
-  flex-wrap: wrap;
-  gap: 0.45rem;
-  margin: 0.45rem 0 1rem;
-}
-
-.tag-chip {
-  display: inline-flex;
-  align-items: center;
-  gap: 0.32rem;
-  border: 1px solid var(--border);
-  border-radius: 999px;
-  padding: 0.28rem 0.65rem;
-  color: var(--text);
-  text-decoration: none;
-  background: var(--panel);
-}
-
-.tag-chip.active {
-  border-color: #87a8cf;
-  background: #edf5ff;
-}
-
-.dashboard-table {
-  width: 100%;
-}
-
-.dashboard-table td,
-.dashboard-table th {
-  white-space: nowrap;
-}
-
-.dashboard-table td.links {
-  white-space: normal;
-}
-"#;
-
-const PAGE_SCRIPTS: &str = r#"
+const KATEX_SCRIPTS: &str = r#"
 <script defer src="https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/katex.min.js"></script>
 <script defer src="https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/contrib/auto-render.min.js"></script>
-<script type="module">
-import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@11/dist/mermaid.esm.min.mjs';
-mermaid.initialize({ startOnLoad: true, securityLevel: 'strict' });
-</script>
-<script>
-window.addEventListener('DOMContentLoaded', function () {
-  if (window.renderMathInElement) {
-    window.renderMathInElement(document.body, {
-      delimiters: [
-        { left: '$$', right: '$$', display: true },
-        { left: '$', right: '$', display: false },
-        { left: '\\(', right: '\\)', display: false },
-        { left: '\\[', right: '\\]', display: true }
-      ],
-      throwOnError: false
-    });
-  }
-
-  wrapTables();
-  installCodeCopyButtons();
-  installToolbar();
-  installImageLightbox();
-});
-
-function copyText(value) {
-  if (!value) {
-    return Promise.resolve(false);
-  }
-  if (navigator.clipboard && navigator.clipboard.writeText) {
-    return navigator.clipboard.writeText(value).then(function () { return true; }).catch(function () { return fallbackCopy(value); });
-  }
-  return Promise.resolve(fallbackCopy(value));
-}
-
-function fallbackCopy(value) {
-  var ta = document.createElement('textarea');
-  ta.value = value;
-  ta.style.position = 'fixed';
-  ta.style.opacity = '0';
-  document.body.appendChild(ta);
-  ta.focus();
-  ta.select();
-  var ok = false;
-  try {
-    ok = document.execCommand('copy');
-  } catch (_err) {
-    ok = false;
-  }
-  ta.remove();
-  return ok;
-}
-
-function setButtonTextTemporarily(button, message) {
-  if (!button) {
-    return;
-  }
-  var original = button.textContent;
-  button.textContent = message;
-  window.setTimeout(function () {
-    button.textContent = original;
-  }, 1200);
-}
-
-function installToolbar() {
-  var rawButton = document.getElementById('copy-raw');
-  var renderedButton = document.getElementById('copy-rendered');
-  var linkButton = document.getElementById('copy-link');
-  var source = document.getElementById('raw-markdown');
-  var rendered = document.getElementById('paste-content');
-  var canonical = document.body.getAttribute('data-canonical-url');
-
-  if (rawButton) {
-    rawButton.addEventListener('click', function () {
-      copyText(source ? source.value : '').then(function (ok) {
-        setButtonTextTemporarily(rawButton, ok ? 'Copied' : 'Failed');
-      });
-    });
-  }
-
-  if (renderedButton) {
-    renderedButton.addEventListener('click', function () {
-      copyText(rendered ? rendered.innerText : '').then(function (ok) {
-        setButtonTextTemporarily(renderedButton, ok ? 'Copied' : 'Failed');
-      });
-    });
-  }
-
-  if (linkButton) {
-    linkButton.addEventListener('click', function () {
-      var path = canonical || window.location.pathname;
-      copyText(window.location.origin + path).then(function (ok) {
-        setButtonTextTemporarily(linkButton, ok ? 'Copied' : 'Failed');
-      });
-    });
-  }
-}
-
-function installCodeCopyButtons() {
-  var codeBlocks = document.querySelectorAll('pre > code');
-  codeBlocks.forEach(function (code) {
-    var pre = code.parentElement;
-    if (!pre || pre.querySelector(':scope > .code-copy')) {
-      return;
-    }
-
-    var button = document.createElement('button');
-    button.type = 'button';
-    button.className = 'code-copy';
-    button.textContent = 'Copy';
-    button.addEventListener('click', function () {
-      copyText(code.textContent || '').then(function (ok) {
-        setButtonTextTemporarily(button, ok ? 'Copied' : 'Failed');
-      });
-    });
-    pre.appendChild(button);
-  });
-}
-
-function wrapTables() {
-  var root = document.getElementById('paste-content') || document.body;
-  var tables = root.querySelectorAll('table');
-  tables.forEach(function (table) {
-    if (table.parentElement && table.parentElement.classList.contains('table-wrap')) {
-      return;
-    }
-    var wrapper = document.createElement('div');
-    wrapper.className = 'table-wrap';
-    table.parentNode.insertBefore(wrapper, table);
-    wrapper.appendChild(table);
-  });
-}
-
-function installImageLightbox() {
-  var root = document.getElementById('paste-content');
-  if (!root) {
-    return;
-  }
-
-  var images = root.querySelectorAll('img');
-  if (!images.length) {
-    return;
-  }
-
-  var lightbox = document.createElement('div');
-  lightbox.id = 'lightbox';
-  lightbox.innerHTML = '<img alt="Expanded image">';
-  var lightboxImg = lightbox.querySelector('img');
-  lightbox.addEventListener('click', function () {
-    lightbox.classList.remove('open');
-  });
-  document.body.appendChild(lightbox);
-
-  images.forEach(function (img) {
-    img.style.cursor = 'zoom-in';
-    img.addEventListener('click', function () {
-      lightboxImg.src = img.currentSrc || img.src;
-      lightboxImg.alt = img.alt || 'Expanded image';
-      lightbox.classList.add('open');
-    });
-  });
-}
-</script>
 "#;
 
 pub fn render_markdown(md: &str) -> String {
@@ -482,28 +45,132 @@ pub fn looks_like_markdown(text: &str) -> bool {
     })
 }
 
-pub fn render_page(title: &str, body_html: &str, canonical_url: Option<&str>) -> String {
+/// `base` is the path links start with, such as `--base-url`; assets are
+/// served under it too.
+pub fn render_page(
+    base: &str,
+    title: &str,
+    body_html: &str,
+    canonical_url: Option<&str>,
+) -> String {
+    let base = html_escape(base);
     let canonical_attr = canonical_url
         .map(|v| format!(" data-canonical-url=\"{}\"", html_escape(v)))
         .unwrap_or_default();
     format!(
-        "<!doctype html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{}</title><link rel=\"stylesheet\" href=\"https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/katex.min.css\"><style>{}</style></head><body{}><main class=\"shell\">{}</main>{}</body></html>",
+        "<!doctype html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{}</title><link rel=\"stylesheet\" href=\"https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/katex.min.css\"><link rel=\"stylesheet\" href=\"{base}{}\"><link rel=\"stylesheet\" media=\"print\" href=\"{base}{}\"></head><body{}><main class=\"shell\">{}</main>{}<script type=\"module\" src=\"{base}{}\"></script><script defer src=\"{base}{}\"></script></body></html>",
         html_escape(title),
-        PAGE_CSS,
+        assets::url("app.css"),
+        assets::url("print.css"),
         canonical_attr,
         body_html,
-        PAGE_SCRIPTS,
+        KATEX_SCRIPTS,
+        assets::url("mermaid.js"),
+        assets::url("app.js"),
+    )
+}
+
+/// A paste on its own for printing: the print stylesheet inlined, and no
+/// scripts or external requests, so a headless PDF renderer without network
+/// access draws it the same as a browser.
+pub fn render_print_page(title: &str, subtitle: &str, content_html: &str) -> String {
+    format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><title>{title}</title><style>{}</style></head><body><header class=\"print-header\">{title} · {}</header><article class=\"content\">{content_html}</article></body></html>",
+        assets::body("print.css"),
+        html_escape(subtitle),
+        title = html_escape(title),
     )
 }
 
-pub fn render_view_shell(id: &str, content_html: &str, raw_markdown: &str) -> String {
+pub fn render_view_shell(
+    base: &str,
+    id: &str,
+    short_id: Option<&str>,
+    content_html: &str,
+    raw_markdown: &str,
+) -> String {
+    let base = html_escape(base);
     let id_escaped = html_escape(id);
     let raw_escaped = html_escape(raw_markdown);
+    let short = short_id
+        .map(|s| {
+            format!(
+                " · short: <a href=\"{base}/p/{0}\"><code>{0}</code></a>",
+                html_escape(s)
+            )
+        })
+        .unwrap_or_default();
     format!(
-        "<section class=\"card\"><header class=\"paste-header\"><div><h1 style=\"margin:0\">Paste</h1><div class=\"paste-meta\">ID: <code>{id_escaped}</code></div></div><div class=\"toolbar\"><button id=\"copy-raw\" type=\"button\">Copy raw markdown</button><button id=\"copy-rendered\" type=\"button\">Copy rendered text</button><button id=\"copy-link\" type=\"button\">Copy link</button></div></header><article id=\"paste-content\" class=\"content\">{content_html}</article><textarea id=\"raw-markdown\" hidden>{raw_escaped}</textarea></section>",
+        "<section class=\"card\"><header class=\"paste-header\"><div><h1 style=\"margin:0\">Paste</h1><div class=\"paste-meta\">ID: <code>{id_escaped}</code>{short}</div></div><div class=\"toolbar\"><button id=\"copy-plain\" type=\"button\" data-src=\"{base}/p/{id_escaped}/raw.txt\">Copy</button><a class=\"button-link\" href=\"{base}/p/{id_escaped}/raw.txt\">Plain text</a><button id=\"copy-raw\" type=\"button\">Copy raw markdown</button><button id=\"copy-rendered\" type=\"button\">Copy rendered text</button><button id=\"copy-link\" type=\"button\">Copy link</button></div></header><details class=\"qr-share\"><summary>Open on phone</summary><img src=\"{base}/p/{id_escaped}/qr\" alt=\"QR code for this paste\" loading=\"lazy\"></details><article id=\"paste-content\" class=\"content\">{content_html}</article><textarea id=\"raw-markdown\" hidden>{raw_escaped}</textarea></section>",
     )
 }
 
+/// Shown above a view whose rendering hit a limit.
+pub fn render_truncation_notice(base: &str, id: &str, notice: &str) -> String {
+    let base = html_escape(base);
+    format!(
+        "<p class=\"render-notice\">{} <a href=\"{base}/p/{}/raw.txt\">Full text</a></p>",
+        html_escape(notice),
+        html_escape(id)
+    )
+}
+
+/// Notes above a paste that is replaced by, or replaces, another one.
+pub fn render_supersede_banner(
+    base: &str,
+    supersedes: Option<&str>,
+    superseded_by: Option<&str>,
+) -> String {
+    let base = html_escape(base);
+    let mut out = String::new();
+    if let Some(newer) = superseded_by {
+        let newer = html_escape(newer);
+        out.push_str(&format!(
+            "<p class=\"supersede-banner\"><strong>Superseded.</strong> A newer version of this paste exists: <a href=\"{base}/p/{newer}\">{newer}</a></p>"
+        ));
+    }
+    if let Some(older) = supersedes {
+        let older = html_escape(older);
+        out.push_str(&format!(
+            "<p class=\"supersede-banner older\">This paste replaces <a href=\"{base}/p/{older}\">{older}</a>.</p>"
+        ));
+    }
+    out
+}
+
+pub fn render_e2e_shell(base: &str, id: &str) -> String {
+    let base = html_escape(base);
+    let id_escaped = html_escape(id);
+    format!(
+        "<section class=\"card\" id=\"e2e-paste\" data-src=\"{base}/p/{id_escaped}/raw.txt\"><header class=\"paste-header\"><div><h1 style=\"margin:0\">Paste</h1><div class=\"paste-meta\">ID: <code>{id_escaped}</code> · end-to-end encrypted</div></div></header><p id=\"e2e-status\" class=\"helper-text\">Decrypting…</p><pre id=\"e2e-plain\" hidden></pre></section><script defer src=\"{base}{}\"></script>",
+        assets::url("e2e.js"),
+    )
+}
+
+pub fn render_qr_svg(data: &str) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(data.as_bytes())?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(240, 240)
+        .quiet_zone(true)
+        .build())
+}
+
+/// Byte count for people: `512 B`, `1.5 KiB`, `3.0 MiB`.
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
 pub fn html_escape(input: &str) -> String {
     input
         .replace('&', "&amp;")
@@ -575,14 +242,16 @@ pub fn slug_from_rel_path(rel_path: &str) -> Option<String> {
 }
 
 pub fn render_dashboard(
+    base: &str,
     recent: &[RecentItem],
     tag_counts: &[(String, usize)],
     selected_tag: Option<&str>,
 ) -> String {
+    let base = html_escape(base);
     let mut rows = String::new();
     if recent.is_empty() {
         rows.push_str(
-            "<tr><td colspan=\"6\">No pastes yet. POST to <code>/api/v1/paste</code> to create one.</td></tr>",
+            "<tr class=\"empty\"><td colspan=\"7\">No pastes yet. POST to <code>/api/v1/paste</code> to create one.</td></tr>",
         );
     } else {
         for item in recent {
@@ -594,15 +263,17 @@ pub fn render_dashboard(
             let created = html_escape(&item.created_at.to_string());
             let ctype = html_escape(&item.content_type);
             rows.push_str(&format!(
-                "<tr>\
-                    <td><a href=\"/p/{id}\">{id}</a></td>\
+                "<tr data-id=\"{id}\">\
+                    <td><a href=\"{base}/p/{id}\">{id}</a></td>\
                     <td>{created}</td>\
                     <td>{tag}</td>\
                     <td>{ctype}</td>\
                     <td>{}</td>\
-                    <td class=\"links\"><a href=\"/api/v1/p/{id}\">meta</a> · <a href=\"/p/{id}/md\">md</a> · <a href=\"/p/{id}/{slug}\">legacy</a> · <code>{path}</code></td>\
+                    <td>{}</td>\
+                    <td class=\"links\"><a href=\"{base}/api/v1/p/{id}\">meta</a> · <a href=\"{base}/p/{id}/md\">md</a> · <a href=\"{base}/p/{id}/{slug}\">legacy</a> · <code>{path}</code></td>\
                 </tr>",
-                item.size
+                item.size,
+                item.stars
             ));
         }
     }
@@ -615,7 +286,7 @@ pub fn render_dashboard(
         "tag-chip"
     };
     tags.push_str(&format!(
-        "<a class=\"{}\" href=\"/recent\">all <strong>{}</strong></a>",
+        "<a class=\"{}\" href=\"{base}/recent\">all <strong>{}</strong></a>",
         all_class,
         recent.len()
     ));
@@ -627,7 +298,7 @@ pub fn render_dashboard(
         };
         let encoded_tag = url_encode_component(tag);
         tags.push_str(&format!(
-            "<a class=\"{}\" href=\"/recent?tag={}\">{} <strong>{}</strong></a>",
+            "<a class=\"{}\" href=\"{base}/recent?tag={}\">{} <strong>{}</strong></a>",
             class,
             html_escape(&encoded_tag),
             html_escape(tag),
@@ -635,13 +306,23 @@ pub fn render_dashboard(
         ));
     }
 
+    // New pastes are added to the table as they arrive; see `app.js`.
+    let live = match selected_tag {
+        Some(tag) => format!(
+            "{base}{}",
+            html_escape(&format!("/ws?tag={}", url_encode_component(tag)))
+        ),
+        None => format!("{base}/ws"),
+    };
+
     let body = format!(
         "<section class=\"card\"><h1 style=\"margin-top:0\">LAN Paste Dashboard</h1>\
          <p class=\"helper-text\">LAN-only recents feed with quick filters.</p>\
+         <p><a class=\"button-link\" href=\"{base}/new\">New paste</a></p>\
          <p>Quick API entry points:</p>\
          <ul>\
-           <li><a href=\"/api\">/api</a> (index)</li>\
-           <li><a href=\"/api/v1/recent?n=20\">/api/v1/recent?n=20</a></li>\
+           <li><a href=\"{base}/api\">/api</a> (index)</li>\
+           <li><a href=\"{base}/api/v1/recent?n=20\">/api/v1/recent?n=20</a></li>\
            <li><code>POST /api/v1/paste?name=note.md&amp;tag=demo</code></li>\
          </ul>\
          <h2>Recent Pastes</h2>\
@@ -649,13 +330,361 @@ pub fn render_dashboard(
          <div class=\"table-wrap\">\
          <table class=\"dashboard-table\">\
            <thead>\
-             <tr><th>ID</th><th>Created</th><th>Tag</th><th>Content-Type</th><th>Bytes</th><th>Links</th></tr>\
+             <tr><th>ID</th><th>Created</th><th>Tag</th><th>Content-Type</th><th>Bytes</th><th>Stars</th><th>Links</th></tr>\
            </thead>\
-           <tbody>{rows}</tbody>\
+           <tbody id=\"recent-rows\" data-base=\"{base}\" data-live=\"{live}\">{rows}</tbody>\
          </table>\
          </div></section>"
     );
-    render_page("LAN Paste Dashboard", &body, None)
+    render_page(&base, "LAN Paste Dashboard", &body, None)
+}
+
+pub fn render_collection(base: &str, collection: &Collection, members: &[RecentItem]) -> String {
+    let base = html_escape(base);
+    let mut rows = String::new();
+    if members.is_empty() {
+        rows.push_str("<tr class=\"empty\"><td colspan=\"5\">No pastes to show.</td></tr>");
+    }
+    for item in members {
+        let id = html_escape(&item.id);
+        let name = slug_from_rel_path(&item.path).unwrap_or_else(|| "paste".to_string());
+        rows.push_str(&format!(
+            "<tr data-id=\"{id}\">\
+                <td><a href=\"{base}/p/{id}\">{}</a></td>\
+                <td>{}</td>\
+                <td>{}</td>\
+                <td>{}</td>\
+                <td class=\"links\"><a href=\"{base}/api/v1/p/{id}/raw\">raw</a> · <a href=\"{base}/api/v1/p/{id}\">meta</a></td>\
+            </tr>",
+            html_escape(&name),
+            html_escape(&item.created_at.to_string()),
+            html_escape(item.tag.as_deref().unwrap_or("-")),
+            html_escape(&item.content_type),
+        ));
+    }
+    let description = collection
+        .description
+        .as_deref()
+        .map(|d| format!("<p>{}</p>", html_escape(d)))
+        .unwrap_or_default();
+    let hidden = collection.pastes.len().saturating_sub(members.len());
+    let hidden = if hidden > 0 {
+        format!(
+            "<p class=\"helper-text\">{hidden} more not shown: deleted, or private to other keys.</p>"
+        )
+    } else {
+        String::new()
+    };
+    let body = format!(
+        "<section class=\"card\"><h1 style=\"margin-top:0\">{}</h1>{description}\
+         <p class=\"helper-text\">Collection <code>{}</code> · updated {}</p>\
+         <div class=\"table-wrap\">\
+         <table class=\"dashboard-table\">\
+           <thead><tr><th>Paste</th><th>Created</th><th>Tag</th><th>Content-Type</th><th>Links</th></tr></thead>\
+           <tbody>{rows}</tbody>\
+         </table>\
+         </div>{hidden}</section>",
+        html_escape(&collection.title),
+        html_escape(&collection.id),
+        html_escape(&collection.updated_at.to_string()),
+    );
+    render_page(&base, &collection.title, &body, None)
+}
+
+fn fmt_time(at: Option<time::OffsetDateTime>) -> String {
+    at.map(|t| html_escape(&t.to_string()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn render_admin_dashboard(base: &str, status: &AdminStatus, notice: Option<&str>) -> String {
+    let base = html_escape(base);
+    let notice = notice
+        .map(|n| {
+            format!(
+                "<p class=\"helper-text\"><strong>{}</strong></p>",
+                html_escape(n)
+            )
+        })
+        .unwrap_or_default();
+    let health = match &status.self_test {
+        Some(report) if !report.ok => "degraded (self-test failed)",
+        Some(_) => "ok (self-test passed)",
+        None => "ok",
+    };
+    let mode = if status.maintenance {
+        "<strong>maintenance</strong> (writes paused)"
+    } else {
+        "serving"
+    };
+    let push = &status.push;
+    let push_error = push
+        .last_error
+        .as_deref()
+        .map(|e| format!("<br><code>{}</code>", html_escape(e)))
+        .unwrap_or_default();
+
+    let mut keys = String::new();
+    if status.keys.is_empty() {
+        keys.push_str("<tr><td colspan=\"5\">No API keys configured.</td></tr>");
+    }
+    for key in &status.keys {
+        let disabled = if key.enabled { "" } else { " (disabled)" };
+        keys.push_str(&format!(
+            "<tr><td>{}{disabled}</td><td>{}</td><td>{}</td><td>{} bytes</td><td>{}</td></tr>",
+            html_escape(&key.name),
+            html_escape(&key.scopes.join(", ")),
+            key.requests,
+            key.bytes_uploaded,
+            fmt_time(key.last_used),
+        ));
+    }
+
+    let mut errors = String::new();
+    if status.recent_errors.is_empty() {
+        errors.push_str("<tr><td colspan=\"4\">No server errors since start.</td></tr>");
+    }
+    for err in &status.recent_errors {
+        errors.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td><code>{} {}</code></td><td>{}</td></tr>",
+            html_escape(&err.at.to_string()),
+            err.status,
+            html_escape(&err.method),
+            html_escape(&err.path),
+            html_escape(&err.message),
+        ));
+    }
+
+    let storage = &status.storage;
+    let (toggle_value, toggle_label) = if status.maintenance {
+        ("false", "Leave maintenance mode")
+    } else {
+        ("true", "Enter maintenance mode")
+    };
+    let body = format!(
+        "<section class=\"card\"><h1 style=\"margin-top:0\">LAN Paste Admin</h1>{notice}\
+         <p><a href=\"{base}/admin/enroll\">Device enrollment</a> ({pending} pending) · <a href=\"{base}/api/v1/admin/status\">status JSON</a></p>\
+         <div class=\"toolbar\">\
+           <form method=\"post\" action=\"{base}/admin/reload-keys\"><button type=\"submit\">Reload keys</button></form>\
+           <form method=\"post\" action=\"{base}/admin/maintenance\"><input type=\"hidden\" name=\"on\" value=\"{toggle_value}\"><button type=\"submit\">{toggle_label}</button></form>\
+           <form method=\"post\" action=\"{base}/admin/sync\"><button type=\"submit\">Push to {remote} now</button></form>\
+           <form method=\"post\" action=\"{base}/admin/logout\"><button type=\"submit\">Log out</button></form>\
+         </div>\
+         <h2>Server</h2>\
+         <table class=\"dashboard-table\"><tbody>\
+           <tr><th>Status</th><td>{health}</td></tr>\
+           <tr><th>Mode</th><td>{mode}</td></tr>\
+           <tr><th>Version</th><td>{version}</td></tr>\
+           <tr><th>Started</th><td>{started} ({uptime}s ago)</td></tr>\
+         </tbody></table>\
+         <h2>Queues</h2>\
+         <table class=\"dashboard-table\"><tbody>\
+           <tr><th>Writes in flight</th><td>{writes}</td></tr>\
+           <tr><th>Uploads staging</th><td>{staging}</td></tr>\
+           <tr><th>Commits queued</th><td>{commits_queued}</td></tr>\
+           <tr><th>Renders running / queued</th><td>{renders_running} / {renders_queued}</td></tr>\
+           <tr><th>Pending enrollments</th><td>{pending}</td></tr>\
+         </tbody></table>\
+         <h2>Push</h2>\
+         <table class=\"dashboard-table\"><tbody>\
+           <tr><th>Mode</th><td>{push_mode} → <code>{remote}</code></td></tr>\
+           <tr><th>Last success</th><td>{last_success}</td></tr>\
+           <tr><th>Last failure</th><td>{last_failure}{push_error}</td></tr>\
+           <tr><th>Failures</th><td>{failures}</td></tr>\
+         </tbody></table>\
+         <h2>Storage</h2>\
+         <table class=\"dashboard-table\"><tbody>\
+           <tr><th>Pastes</th><td>{pastes} ({paste_bytes} bytes)</td></tr>\
+           <tr><th>Oldest / newest</th><td>{oldest} / {newest}</td></tr>\
+           <tr><th>Uploaded files</th><td>{files} ({file_bytes} bytes)</td></tr>\
+         </tbody></table>\
+         <h2>API keys</h2>\
+         <div class=\"table-wrap\"><table class=\"dashboard-table\">\
+           <thead><tr><th>Name</th><th>Scopes</th><th>Requests</th><th>Uploaded</th><th>Last used</th></tr></thead>\
+           <tbody>{keys}</tbody>\
+         </table></div>\
+         <h2>Recent errors</h2>\
+         <div class=\"table-wrap\"><table class=\"dashboard-table\">\
+           <thead><tr><th>When</th><th>Status</th><th>Request</th><th>Message</th></tr></thead>\
+           <tbody>{errors}</tbody>\
+         </table></div></section>",
+        pending = status.queues.pending_enrollments,
+        remote = html_escape(&status.remote),
+        version = status.version,
+        started = html_escape(&status.started_at.to_string()),
+        uptime = status.uptime_secs,
+        writes = status.queues.writes_in_flight,
+        staging = status.queues.uploads_staging,
+        commits_queued = status.queues.commits_queued,
+        renders_running = status.queues.renders_running,
+        renders_queued = status.queues.renders_queued,
+        push_mode = status.push_mode,
+        last_success = fmt_time(push.last_success),
+        last_failure = fmt_time(push.last_failure),
+        failures = push.failures,
+        pastes = storage.pastes,
+        paste_bytes = storage.paste_bytes,
+        oldest = fmt_time(storage.oldest),
+        newest = fmt_time(storage.newest),
+        files = storage.files,
+        file_bytes = storage.file_bytes,
+    );
+    render_page(&base, "LAN Paste Admin", &body, None)
+}
+
+/// The `/new` form. `app.js` posts it to `/api/v1/paste`, sending the key or
+/// token in `auth_header` when the server asks for one.
+pub fn render_new_paste(base: &str, auth_header: Option<&str>) -> String {
+    let base = html_escape(base);
+    let credential = auth_header
+        .map(|header| {
+            let label = if header == auth::API_KEY_HEADER {
+                "API key"
+            } else {
+                "Token"
+            };
+            format!(
+                "<div class=\"field-row\">\
+                   <label>{label} <input type=\"password\" name=\"credential\" autocomplete=\"off\" required></label>\
+                   <label class=\"check\"><input type=\"checkbox\" name=\"remember\"> Remember on this device</label>\
+                 </div>"
+            )
+        })
+        .unwrap_or_default();
+    let body = format!(
+        "<section class=\"card\"><h1 style=\"margin-top:0\">New paste</h1>\
+         <form id=\"new-paste\" class=\"new-paste\" data-base=\"{base}\" data-auth-header=\"{}\">\
+           <label>Content <textarea name=\"content\" rows=\"16\" spellcheck=\"false\" placeholder=\"Paste text here, or drop a file anywhere on this form\"></textarea></label>\
+           <div class=\"drop-zone\"><label>File <input type=\"file\" name=\"file\"></label><span class=\"helper-text\" id=\"new-paste-file\">A file is uploaded as is, in place of the text.</span></div>\
+           <div class=\"field-row\">\
+             <label>Name <input name=\"name\" placeholder=\"notes.md\" autocomplete=\"off\"></label>\
+             <label>Tag <input name=\"tag\" autocomplete=\"off\"></label>\
+           </div>\
+           {credential}\
+           <div class=\"toolbar\"><button type=\"submit\">Create paste</button></div>\
+           <p class=\"helper-text\" id=\"new-paste-status\" role=\"status\"></p>\
+         </form>\
+         <noscript><p>This form needs JavaScript. Without it, <code>POST {base}/api/v1/paste?name=note.md</code> with the text as the body.</p></noscript>\
+         </section>",
+        html_escape(auth_header.unwrap_or_default()),
+    );
+    render_page(&base, "LAN Paste: New paste", &body, None)
+}
+
+pub fn render_admin_login(base: &str, error: Option<&str>) -> String {
+    let base = html_escape(base);
+    let error = error
+        .map(|e| {
+            format!(
+                "<p class=\"helper-text\"><strong>{}</strong></p>",
+                html_escape(e)
+            )
+        })
+        .unwrap_or_default();
+    let body = format!(
+        "<section class=\"card\"><h1 style=\"margin-top:0\">Admin login</h1>{error}\
+         <form method=\"post\" action=\"{base}/admin/login\">\
+           <label>API key with <code>admin</code> scope <input type=\"password\" name=\"key\" autocomplete=\"off\" required></label>\
+           <button type=\"submit\">Log in</button>\
+         </form></section>"
+    );
+    render_page(&base, "LAN Paste Admin", &body, None)
+}
+
+/// `/login` for `--protect-ui`; `next` is where to go once logged in.
+pub fn render_login(base: &str, credential: &str, next: &str, error: Option<&str>) -> String {
+    let base = html_escape(base);
+    let error = error
+        .map(|e| {
+            format!(
+                "<p class=\"helper-text\"><strong>{}</strong></p>",
+                html_escape(e)
+            )
+        })
+        .unwrap_or_default();
+    let body = format!(
+        "<section class=\"card\"><h1 style=\"margin-top:0\">Log in</h1>{error}\
+         <p class=\"helper-text\">Pastes on this server are only shown to people with access.</p>\
+         <form method=\"post\" action=\"{base}/login\">\
+           <input type=\"hidden\" name=\"next\" value=\"{}\">\
+           <label>{} <input type=\"password\" name=\"key\" autocomplete=\"off\" required></label>\
+           <button type=\"submit\">Log in</button>\
+         </form></section>",
+        html_escape(next),
+        html_escape(credential),
+    );
+    render_page(&base, "LAN Paste: Log in", &body, None)
+}
+
+/// The API console: RapiDoc, from the same CDN as KaTeX, reading the spec at
+/// `{base}/openapi.yaml` and sending "try it" requests to `server_url`. RapiDoc
+/// needs no inline script, so the default CSP allows it.
+pub fn render_api_docs(base: &str, server_url: &str) -> String {
+    let base = html_escape(base);
+    let server_url = html_escape(server_url);
+    format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>LAN Paste API</title><script type=\"module\" src=\"https://cdn.jsdelivr.net/npm/rapidoc@9.3.8/dist/rapidoc-min.js\"></script></head><body>\
+         <rapi-doc spec-url=\"{base}/openapi.yaml\" server-url=\"{server_url}\" default-api-server=\"{server_url}\" render-style=\"read\" show-header=\"false\" allow-spec-url-load=\"false\" allow-spec-file-load=\"false\">\
+         <p slot=\"overview\">The raw spec is at <a href=\"{base}/openapi.yaml\">/openapi.yaml</a>.</p>\
+         </rapi-doc><noscript><p>The console needs JavaScript. Read the spec at <a href=\"{base}/openapi.yaml\">/openapi.yaml</a>.</p></noscript></body></html>"
+    )
+}
+
+pub fn render_enrollments(
+    base: &str,
+    pending: &[PendingEnrollment],
+    notice: Option<&str>,
+) -> String {
+    let base = html_escape(base);
+    let mut rows = String::new();
+    if pending.is_empty() {
+        rows.push_str("<tr><td colspan=\"5\">No devices are waiting. Run <code>lanpaste enroll --server URL</code> on one.</td></tr>");
+    }
+    for item in pending {
+        let id = html_escape(&item.id);
+        let client = item
+            .client_ip
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        rows.push_str(&format!(
+            "<tr>\
+                <td>{}</td>\
+                <td>{}</td>\
+                <td>{}</td>\
+                <td>{}</td>\
+                <td><form method=\"post\" action=\"{base}/admin/enroll/{id}/reject\"><button type=\"submit\">Reject</button></form></td>\
+            </tr>",
+            html_escape(&item.name),
+            html_escape(&item.scopes.join(", ")),
+            html_escape(&client),
+            html_escape(&item.requested_at.to_string()),
+        ));
+    }
+    let notice = notice
+        .map(|n| {
+            format!(
+                "<p class=\"helper-text\"><strong>{}</strong></p>",
+                html_escape(n)
+            )
+        })
+        .unwrap_or_default();
+    let body = format!(
+        "<section class=\"card\"><h1 style=\"margin-top:0\">Device enrollment</h1>{notice}\
+         <p><a href=\"{base}/admin\">&larr; Admin</a></p>\
+         <p class=\"helper-text\">Approve a device by typing the code it printed. Codes are never shown here.</p>\
+         <form method=\"post\" action=\"{base}/admin/enroll\">\
+           <label>Code <input name=\"code\" inputmode=\"numeric\" pattern=\"[0-9]{{6}}\" autocomplete=\"off\" required></label>\
+           <button type=\"submit\">Approve</button>\
+         </form>\
+         <h2>Pending</h2>\
+         <div class=\"table-wrap\">\
+         <table class=\"dashboard-table\">\
+           <thead><tr><th>Name</th><th>Scopes</th><th>Client</th><th>Requested</th><th></th></tr></thead>\
+           <tbody>{rows}</tbody>\
+         </table>\
+         </div>\
+         <form method=\"post\" action=\"{base}/admin/logout\"><button type=\"submit\">Log out</button></form></section>"
+    );
+    render_page(&base, "LAN Paste Enrollment", &body, None)
 }
 
 #[cfg(test)]
@@ -700,19 +729,33 @@ mod tests {
 
     #[test]
     fn page_wraps_body() {
-        let out = render_page("x", "<p>ok</p>", Some("/p/id"));
+        let out = render_page("", "x", "<p>ok</p>", Some("/p/id"));
         assert!(out.contains("<title>x</title>"));
         assert!(out.contains("<p>ok</p>"));
         assert!(out.contains("katex"));
-        assert!(out.contains("mermaid"));
+        assert!(out.contains(&format!("src=\"{}\"", assets::url("mermaid.js"))));
+        assert!(!out.contains("<script>") && !out.contains("<script type=\"module\">"));
         assert!(out.contains("data-canonical-url=\"/p/id\""));
+        assert!(out.contains(&format!("href=\"{}\"", assets::url("app.css"))));
+        assert!(out.contains(&format!("src=\"{}\"", assets::url("app.js"))));
     }
 
     #[test]
     fn view_shell_contains_copy_controls() {
-        let out = render_view_shell("01TEST", "<h1>x</h1>", "# raw");
+        let out = render_view_shell("", "01TEST", Some("3xK9aBc"), "<h1>x</h1>", "# raw");
         assert!(out.contains("Copy raw markdown"));
         assert!(out.contains("id=\"raw-markdown\""));
+        assert!(out.contains("src=\"/p/01TEST/qr\""));
+        assert!(out.contains("id=\"copy-plain\""));
+        assert!(out.contains("href=\"/p/01TEST/raw.txt\""));
+        assert!(out.contains("<a href=\"/p/3xK9aBc\"><code>3xK9aBc</code></a>"));
+    }
+
+    #[test]
+    fn qr_svg_renders() {
+        let svg = render_qr_svg("http://paste.lan:8090/p/01TEST").expect("qr");
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("<svg"));
     }
 
     #[test]
@@ -720,9 +763,17 @@ mod tests {
         assert_eq!(html_escape("<>&\"'"), "&lt;&gt;&amp;&quot;&#39;");
     }
 
+    #[test]
+    fn sizes_read_in_binary_units() {
+        assert_eq!(human_size(12), "12 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
     #[test]
     fn dashboard_contains_api_links() {
         let out = render_dashboard(
+            "",
             &[RecentItem {
                 id: "01TEST".to_string(),
                 created_at: OffsetDateTime::now_utc(),
@@ -731,24 +782,47 @@ mod tests {
                 tag: Some("demo".to_string()),
                 size: 12,
                 content_type: "text/markdown".to_string(),
+                stars: 3,
+                owner: None,
             }],
             &[("demo".to_string(), 1)],
             Some("demo"),
         );
         assert!(out.contains("LAN Paste Dashboard"));
+        assert!(out.contains("<td>12</td><td>3</td>"));
         assert!(out.contains("/api/v1/paste"));
         assert!(out.contains("/p/01TEST/md"));
         assert!(out.contains("/recent?tag=demo"));
+        assert!(out.contains("<tr data-id=\"01TEST\">"));
     }
 
     #[test]
     fn dashboard_url_encodes_tag_links() {
-        let out = render_dashboard(
-            &[],
-            &[("a&b c+1".to_string(), 2)],
-            Some("a&b c+1"),
-        );
+        let out = render_dashboard("", &[], &[("a&b c+1".to_string(), 2)], Some("a&b c+1"));
         assert!(out.contains("/recent?tag=a%26b%20c%2B1"));
+        assert!(out.contains("data-live=\"/ws?tag=a%26b%20c%2B1\""));
+    }
+
+    #[test]
+    fn dashboard_links_stay_inside_a_namespace() {
+        let out = render_dashboard("/ns/team-a", &[], &[], Some("x"));
+        assert!(out.contains("href=\"/ns/team-a/recent\""));
+        assert!(out.contains("data-base=\"/ns/team-a\""));
+        assert!(out.contains("data-live=\"/ns/team-a/ws?tag=x\""));
+    }
+
+    #[test]
+    fn new_paste_form_asks_for_the_credential_the_server_checks() {
+        let keyed = render_new_paste("/ns/team-a", Some(auth::API_KEY_HEADER));
+        assert!(keyed.contains("data-base=\"/ns/team-a\""));
+        assert!(keyed.contains("data-auth-header=\"X-API-Key\""));
+        assert!(keyed.contains("<label>API key <input type=\"password\" name=\"credential\""));
+        assert!(keyed.contains("name=\"file\""));
+
+        let open = render_new_paste("", None);
+        assert!(open.contains("data-auth-header=\"\""));
+        assert!(!open.contains("name=\"credential\""));
+        assert!(render_dashboard("", &[], &[], None).contains("href=\"/new\""));
     }
 
     #[test]
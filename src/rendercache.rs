@@ -0,0 +1,126 @@
+//! Rendered view HTML, kept so that a paste viewed again is not rendered and
+//! sanitized again. Entries are keyed by the paste's `sha256`, so an append
+//! or a rewrite simply misses and the stale entry ages out. The cache holds
+//! at most `--render-cache-bytes` of HTML and drops the least recently used
+//! entries first.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub id: String,
+    pub sha256: String,
+    /// Which renderer produced the HTML, such as `markdown`.
+    pub kind: &'static str,
+}
+
+#[derive(Debug)]
+pub struct RenderCache {
+    budget: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<CacheKey, (Arc<str>, u64)>,
+    /// Last use of each entry, oldest first.
+    order: BTreeMap<u64, CacheKey>,
+}
+
+impl RenderCache {
+    /// A budget of `0` caches nothing.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes of HTML held.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn get(&mut self, key: &CacheKey) -> Option<Arc<str>> {
+        self.tick += 1;
+        let (html, used_at) = self.entries.get_mut(key)?;
+        let key = self.order.remove(used_at).expect("entry in order");
+        *used_at = self.tick;
+        self.order.insert(self.tick, key);
+        Some(html.clone())
+    }
+
+    /// Keeps `html`, evicting as needed. HTML larger than the whole budget is
+    /// not kept.
+    pub fn insert(&mut self, key: CacheKey, html: Arc<str>) {
+        if self.budget == 0 || html.len() > self.budget {
+            return;
+        }
+        self.remove(&key);
+        while self.used + html.len() > self.budget {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.used -= evicted.len();
+            }
+        }
+        self.tick += 1;
+        self.used += html.len();
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (html, self.tick));
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((html, used_at)) = self.entries.remove(key) {
+            self.order.remove(&used_at);
+            self.used -= html.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str) -> CacheKey {
+        CacheKey {
+            id: id.to_string(),
+            sha256: "00".to_string(),
+            kind: "markdown",
+        }
+    }
+
+    #[test]
+    fn least_recently_used_entries_go_first() {
+        let mut cache = RenderCache::new(10);
+        cache.insert(key("a"), Arc::from("aaaa"));
+        cache.insert(key("b"), Arc::from("bbbb"));
+        assert_eq!(cache.get(&key("a")).as_deref(), Some("aaaa"));
+        cache.insert(key("c"), Arc::from("cccc"));
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("a")).is_some() && cache.get(&key("c")).is_some());
+        assert_eq!((cache.len(), cache.used()), (2, 8));
+
+        cache.insert(key("a"), Arc::from("a"));
+        assert_eq!((cache.len(), cache.used()), (2, 5));
+        cache.insert(key("huge"), Arc::from("x".repeat(11)));
+        assert!(cache.get(&key("huge")).is_none());
+        assert_eq!(cache.len(), 2);
+
+        let mut off = RenderCache::new(0);
+        off.insert(key("a"), Arc::from("a"));
+        assert!(off.is_empty());
+    }
+}
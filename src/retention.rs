@@ -0,0 +1,210 @@
+//! Retention: a background sweep that deletes the oldest pastes once
+//! `--retain-days`, `--retain-count` or `--retain-max-bytes` is exceeded,
+//! removing them from the working tree in a single commit.
+
+use std::{sync::Arc, time::Duration};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::{
+    config::ServeCmd,
+    errors::AppResult,
+    events::EventKind,
+    gitops::{self, FileLock},
+    store,
+    types::{AppState, PasteMeta},
+};
+
+const SWEEP_EVERY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Policy {
+    pub max_age: Option<time::Duration>,
+    pub max_count: Option<usize>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Policy {
+    pub fn from_config(cfg: &ServeCmd) -> Self {
+        Self::from_limits(cfg.retain_days, cfg.retain_count, cfg.retain_max_bytes)
+    }
+
+    pub fn from_limits(days: Option<u64>, count: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Self {
+            max_age: days
+                .map(|days| time::Duration::days(days.min(i64::MAX as u64 / 86_400) as i64)),
+            max_count: count.map(|n| n as usize),
+            max_bytes,
+        }
+    }
+
+    /// When the age limit makes `meta` due for removal. The count and byte
+    /// budgets depend on later pastes, so they give no date.
+    pub fn expires_at(&self, meta: &PasteMeta) -> Option<OffsetDateTime> {
+        self.max_age.map(|age| meta.created_at + age)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_count.is_some() || self.max_bytes.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SweepReport {
+    pub removed: Vec<String>,
+    pub bytes: u64,
+    pub commit: Option<String>,
+}
+
+/// Picks the pastes `policy` evicts. Newest pastes are kept first, so once a
+/// paste falls outside the count or byte budget every older one does too.
+pub fn select(mut metas: Vec<PasteMeta>, policy: &Policy, now: OffsetDateTime) -> Vec<PasteMeta> {
+    metas.sort_by_key(|m| std::cmp::Reverse(m.created_at));
+    let mut kept = 0usize;
+    let mut kept_bytes = 0u64;
+    let mut evicted = Vec::new();
+    for meta in metas {
+        let size = meta.size as u64;
+        let too_old = policy
+            .max_age
+            .is_some_and(|age| now - meta.created_at > age);
+        let over_count = policy.max_count.is_some_and(|max| kept >= max);
+        let over_bytes = policy
+            .max_bytes
+            .is_some_and(|max| kept_bytes.saturating_add(size) > max);
+        if too_old || over_count || over_bytes || !evicted.is_empty() {
+            evicted.push(meta);
+        } else {
+            kept += 1;
+            kept_bytes += size;
+        }
+    }
+    evicted
+}
+
+/// Applies `policy` once. Blocks on git and disk I/O.
+pub fn sweep(state: &AppState, policy: &Policy) -> AppResult<SweepReport> {
+    let _git_lock = FileLock::acquire_waiting(&state.paths.git_lock)?;
+    let repo = &state.paths.repo;
+    let metas = store::read_all_metas(repo)?;
+    let evicted = select(metas, policy, OffsetDateTime::now_utc());
+    if evicted.is_empty() {
+        return Ok(SweepReport::default());
+    }
+
+    let mut report = SweepReport::default();
+    let mut files = Vec::new();
+    for meta in &evicted {
+        files.extend(store::paste_files(repo, meta)?);
+        report.removed.push(meta.id.clone());
+        report.bytes += meta.size as u64;
+    }
+    let files: Vec<&str> = files.iter().map(String::as_str).collect();
+    let subject = format!("retention: remove {} pastes", evicted.len());
+    let commit = gitops::commit_removal(
+        repo,
+        &state.cfg,
+        &files,
+        &subject,
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.ops.record_commit(state.cfg.push, &commit);
+    state.index.remove(&report.removed);
+    for meta in &evicted {
+        state
+            .events
+            .publish(EventKind::Deleted, meta, &commit.commit);
+    }
+    if let Some(err) = &commit.push_error {
+        warn!("best-effort push failed: {err}");
+    }
+    report.commit = Some(commit.commit);
+    Ok(report)
+}
+
+/// Sweeps at startup and then hourly, with the policy in effect at each
+/// tick, so a reload can enable, change or disable retention.
+pub fn spawn_periodic(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SWEEP_EVERY);
+        loop {
+            ticker.tick().await;
+            let policy = state.settings.current().retention();
+            if !policy.enabled() {
+                continue;
+            }
+            let state = state.clone();
+            match tokio::task::spawn_blocking(move || sweep(&state, &policy)).await {
+                Ok(Ok(report)) if report.removed.is_empty() => {}
+                Ok(Ok(report)) => info!(
+                    "retention removed {} pastes ({} bytes)",
+                    report.removed.len(),
+                    report.bytes
+                ),
+                Ok(Err(err)) => warn!("retention sweep failed: {err:?}"),
+                Err(err) => warn!("retention task failed: {err}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(id: &str, age_days: i64, size: usize, now: OffsetDateTime) -> PasteMeta {
+        PasteMeta {
+            created_at: now - time::Duration::days(age_days),
+            size,
+            ..PasteMeta::for_test(id)
+        }
+    }
+
+    fn ids(metas: Vec<PasteMeta>) -> Vec<String> {
+        metas.into_iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn evicts_oldest_beyond_each_limit() {
+        let now = OffsetDateTime::now_utc();
+        let metas = || {
+            vec![
+                meta("old", 40, 10, now),
+                meta("mid", 5, 30, now),
+                meta("new", 1, 50, now),
+                meta("newest", 0, 20, now),
+            ]
+        };
+
+        let by_age = Policy {
+            max_age: Some(time::Duration::days(30)),
+            ..Policy::default()
+        };
+        assert_eq!(ids(select(metas(), &by_age, now)), vec!["old"]);
+        assert_eq!(
+            by_age.expires_at(&meta("mid", 5, 30, now)),
+            Some(now + time::Duration::days(25))
+        );
+
+        let by_count = Policy {
+            max_count: Some(2),
+            ..Policy::default()
+        };
+        assert_eq!(ids(select(metas(), &by_count, now)), vec!["mid", "old"]);
+
+        // "mid" would overflow 90 bytes, so it and everything older goes,
+        // even though "old" alone would still fit.
+        let by_bytes = Policy {
+            max_bytes: Some(90),
+            ..Policy::default()
+        };
+        assert_eq!(ids(select(metas(), &by_bytes, now)), vec!["mid", "old"]);
+
+        assert!(select(metas(), &Policy::default(), now).is_empty());
+        assert!(!Policy::default().enabled());
+        assert!(by_count.expires_at(&meta("new", 1, 50, now)).is_none());
+    }
+}
@@ -0,0 +1,360 @@
+//! Rendering for the HTML view, kept off the request threads. Renders run on
+//! a fixed number of blocking workers with a bounded queue in front; each one
+//! only sees input up to a byte cap, has a deadline that includes its time in
+//! the queue, and has its output size checked. When a limit trips the view
+//! falls back to escaped plain text with a notice linking the raw paste.
+//!
+//! Finished renders are kept in a [`RenderCache`]; fallbacks caused by load
+//! (a timeout or a full pool) are not, so the next view tries again.
+
+use std::{
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{sync::Semaphore, time::Instant};
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    render,
+    rendercache::{CacheKey, RenderCache},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderLimits {
+    pub max_input_bytes: usize,
+    pub timeout: Duration,
+    pub max_output_bytes: usize,
+}
+
+impl RenderLimits {
+    pub fn from_config(cfg: &ServeCmd) -> Self {
+        Self {
+            max_input_bytes: cfg.render_max_input_bytes,
+            timeout: cfg.render_timeout,
+            max_output_bytes: cfg.render_max_output_bytes,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RenderPool {
+    limits: RenderLimits,
+    workers: usize,
+    permits: Arc<Semaphore>,
+    max_queue: usize,
+    queued: AtomicUsize,
+    cache: Mutex<RenderCache>,
+    /// `--namespace` path that the "Full text" link of a notice starts with.
+    base_path: String,
+}
+
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RenderPool {
+    pub fn new(limits: RenderLimits, workers: usize, max_queue: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            limits,
+            workers,
+            permits: Arc::new(Semaphore::new(workers)),
+            max_queue,
+            queued: AtomicUsize::new(0),
+            cache: Mutex::new(RenderCache::new(0)),
+            base_path: String::new(),
+        }
+    }
+
+    /// Keeps up to `bytes` of rendered HTML for [`RenderPool::render_cached`].
+    pub fn with_cache(self, bytes: usize) -> Self {
+        Self {
+            cache: Mutex::new(RenderCache::new(bytes)),
+            ..self
+        }
+    }
+
+    /// `--render-workers` (default: one per CPU), `--render-queue` and
+    /// `--render-cache-bytes`.
+    pub fn from_config(cfg: &ServeCmd) -> Self {
+        let workers = cfg
+            .render_workers
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(2, |n| n.get()));
+        Self {
+            base_path: cfg.base_path.clone(),
+            ..Self::new(RenderLimits::from_config(cfg), workers, cfg.render_queue)
+                .with_cache(cfg.render_cache_bytes)
+        }
+    }
+
+    /// Renders running right now, including timed-out ones still finishing.
+    pub fn running(&self) -> usize {
+        self.workers - self.permits.available_permits()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Entries and bytes of HTML in the cache.
+    pub fn cached(&self) -> (usize, usize) {
+        let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        (cache.len(), cache.used())
+    }
+
+    /// [`RenderPool::render`] through the cache. `key` must change whenever
+    /// `text` or `renderer` would.
+    pub async fn render_cached<F>(
+        &self,
+        key: CacheKey,
+        text: &str,
+        renderer: F,
+    ) -> AppResult<String>
+    where
+        F: FnOnce(&str) -> String + Send + 'static,
+    {
+        let hit = self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key);
+        if let Some(html) = hit {
+            return Ok(html.to_string());
+        }
+        let (html, settled) = self.render_once(&key.id, text, renderer).await?;
+        if settled {
+            self.cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(key, Arc::from(html.as_str()));
+        }
+        Ok(html)
+    }
+
+    /// Runs `renderer` over `text` and returns HTML for the view's content
+    /// area. Fails with `503` only when the queue is full. A timed-out render
+    /// is abandoned, not cancelled: it keeps its worker until it finishes,
+    /// bounded by the input cap.
+    pub async fn render<F>(&self, id: &str, text: &str, renderer: F) -> AppResult<String>
+    where
+        F: FnOnce(&str) -> String + Send + 'static,
+    {
+        Ok(self.render_once(id, text, renderer).await?.0)
+    }
+
+    /// The HTML, and whether rendering the same text again would give the
+    /// same result: `false` when load, not the text, forced plain text.
+    async fn render_once<F>(&self, id: &str, text: &str, renderer: F) -> AppResult<(String, bool)>
+    where
+        F: FnOnce(&str) -> String + Send + 'static,
+    {
+        let limits = self.limits;
+        let deadline = Instant::now() + limits.timeout;
+        let cut = text.floor_char_boundary(limits.max_input_bytes);
+        let input: Arc<str> = Arc::from(&text[..cut]);
+        let mut notice =
+            (cut < text.len()).then(|| format!("Showing the first {cut} of {} bytes.", text.len()));
+
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                if self.queued.fetch_add(1, Ordering::Relaxed) >= self.max_queue {
+                    self.queued.fetch_sub(1, Ordering::Relaxed);
+                    return Err(AppError::ServiceUnavailable(
+                        "render queue is full; retry shortly".to_string(),
+                    ));
+                }
+                let _queued = Queued(&self.queued);
+                match tokio::time::timeout_at(deadline, self.permits.clone().acquire_owned()).await
+                {
+                    Ok(Ok(permit)) => permit,
+                    _ => {
+                        let notice =
+                            "The server is busy rendering other pages; showing plain text.";
+                        let html = plain(&input, limits.max_output_bytes);
+                        return Ok((self.with_notice(id, Some(notice), html), false));
+                    }
+                }
+            }
+        };
+
+        let task = {
+            let input = input.clone();
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                renderer(&input)
+            })
+        };
+        let mut settled = true;
+        let html = match tokio::time::timeout_at(deadline, task).await {
+            Ok(Ok(html)) if html.len() <= limits.max_output_bytes => html,
+            Ok(Ok(_)) => {
+                notice = Some("The rendered page was too large; showing plain text.".to_string());
+                plain(&input, limits.max_output_bytes)
+            }
+            Ok(Err(_)) => {
+                notice = Some("Rendering failed; showing plain text.".to_string());
+                plain(&input, limits.max_output_bytes)
+            }
+            Err(_) => {
+                settled = false;
+                notice = Some(format!(
+                    "Rendering took longer than {}ms; showing plain text.",
+                    limits.timeout.as_millis()
+                ));
+                plain(&input, limits.max_output_bytes)
+            }
+        };
+        Ok((self.with_notice(id, notice.as_deref(), html), settled))
+    }
+
+    fn with_notice(&self, id: &str, notice: Option<&str>, html: String) -> String {
+        match notice {
+            Some(notice) => format!(
+                "{}{html}",
+                render::render_truncation_notice(&self.base_path, id, notice)
+            ),
+            None => html,
+        }
+    }
+}
+
+/// Escaped `<pre>` text, cut to about `max_bytes` without splitting an entity.
+fn plain(text: &str, max_bytes: usize) -> String {
+    let mut escaped = render::html_escape(text);
+    if escaped.len() > max_bytes {
+        let mut end = escaped.floor_char_boundary(max_bytes);
+        if let Some(amp) = escaped[..end].rfind('&')
+            && !escaped[amp..end].contains(';')
+        {
+            end = amp;
+        }
+        escaped.truncate(end);
+    }
+    format!("<pre>{escaped}</pre>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(max_input_bytes: usize, timeout_ms: u64, max_output_bytes: usize) -> RenderPool {
+        let limits = RenderLimits {
+            max_input_bytes,
+            timeout: Duration::from_millis(timeout_ms),
+            max_output_bytes,
+        };
+        RenderPool::new(limits, 2, 4)
+    }
+
+    #[tokio::test]
+    async fn limits_fall_back_to_plain_text_with_a_notice() {
+        let ok = pool(1024, 1000, 1024)
+            .render("ID", "# hi", render::render_markdown)
+            .await
+            .expect("render");
+        assert!(ok.contains("<h1>hi</h1>") && !ok.contains("render-notice"));
+
+        let cut = pool(4, 1000, 1024)
+            .render("ID", "abcdéfgh", |t| format!("<p>{t}</p>"))
+            .await
+            .expect("render");
+        assert!(cut.contains("Showing the first 4 of 9 bytes."));
+        assert!(cut.contains("href=\"/p/ID/raw.txt\""));
+        assert!(cut.ends_with("<p>abcd</p>"));
+
+        let slow = pool(1024, 20, 1024)
+            .render("ID", "<b>", |t| {
+                std::thread::sleep(Duration::from_millis(300));
+                t.to_string()
+            })
+            .await
+            .expect("render");
+        assert!(slow.contains("took longer than 20ms"));
+        assert!(slow.ends_with("<pre>&lt;b&gt;</pre>"));
+
+        let big = pool(1024, 1000, 10)
+            .render("ID", "a&b&c", |t| t.repeat(100))
+            .await
+            .expect("render");
+        assert!(big.contains("too large"));
+        assert!(big.ends_with("<pre>a&amp;b</pre>"));
+
+        let panicked = pool(1024, 1000, 1024)
+            .render("ID", "x", |_| panic!("boom"))
+            .await
+            .expect("render");
+        assert!(panicked.contains("Rendering failed"));
+    }
+
+    #[tokio::test]
+    async fn finished_renders_are_cached_and_timeouts_are_not() {
+        let pool = pool(1024, 50, 1024).with_cache(1024);
+        let key = |sha: &str| CacheKey {
+            id: "ID".to_string(),
+            sha256: sha.to_string(),
+            kind: "markdown",
+        };
+        let first = pool
+            .render_cached(key("1"), "# hi", render::render_markdown)
+            .await
+            .expect("render");
+        let again = pool
+            .render_cached(key("1"), "# hi", |_| panic!("rendered twice"))
+            .await
+            .expect("render");
+        assert_eq!(first, again);
+        assert_eq!(pool.cached(), (1, first.len()));
+
+        let slow = |t: &str| {
+            std::thread::sleep(Duration::from_millis(200));
+            t.to_string()
+        };
+        let timed_out = pool
+            .render_cached(key("2"), "x", slow)
+            .await
+            .expect("render");
+        assert!(timed_out.contains("took longer than"));
+        assert_eq!(pool.cached().0, 1);
+    }
+
+    #[tokio::test]
+    async fn busy_workers_queue_then_shed() {
+        let limits = RenderLimits {
+            max_input_bytes: 1024,
+            timeout: Duration::from_millis(100),
+            max_output_bytes: 1024,
+        };
+        let pool = Arc::new(RenderPool::new(limits, 1, 1));
+        let slow = |t: &str| {
+            std::thread::sleep(Duration::from_millis(400));
+            t.to_string()
+        };
+
+        // Times out, but keeps the only worker busy until it finishes.
+        pool.render("A", "a", slow).await.expect("render");
+        assert_eq!(pool.running(), 1);
+
+        let waiting = {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.render("B", "b", |t| t.to_string()).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(pool.queued(), 1);
+        let shed = pool.render("C", "c", |t| t.to_string()).await;
+        assert!(matches!(shed, Err(AppError::ServiceUnavailable(_))));
+
+        let busy = waiting.await.expect("join").expect("render");
+        assert!(busy.contains("busy rendering other pages"));
+        assert_eq!(pool.queued(), 0);
+    }
+}
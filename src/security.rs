@@ -0,0 +1,195 @@
+//! Headers sent with every HTML page (`--csp`, `--frame-ancestors`,
+//! `--referrer-policy`). Markdown is sanitized before it is rendered; the
+//! policy keeps anything that slips through from loading scripts, posting
+//! forms elsewhere or being framed by another site.
+
+use axum::{
+    http::{HeaderMap, HeaderValue, header},
+    response::Response,
+};
+
+use crate::config::ServeCmd;
+
+/// Scripts and styles are the bundled assets plus KaTeX and mermaid from
+/// jsdelivr. Mermaid writes `<style>` into the diagrams it draws, hence the
+/// inline styles; no inline script is allowed.
+pub const DEFAULT_POLICY: &str = "default-src 'self'; \
+script-src 'self' https://cdn.jsdelivr.net; \
+style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+font-src 'self' https://cdn.jsdelivr.net; \
+img-src 'self' data:; \
+connect-src 'self'; \
+object-src 'none'; \
+base-uri 'none'; \
+form-action 'self'";
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    csp: Option<HeaderValue>,
+    referrer_policy: HeaderValue,
+    /// `X-Frame-Options` for browsers without `frame-ancestors`; only set
+    /// when the sources can be expressed with it.
+    frame_options: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    /// The values were checked when the flags were parsed.
+    pub fn from_config(cfg: &ServeCmd) -> Self {
+        let csp = policy(cfg).and_then(|p| HeaderValue::from_str(&p).ok());
+        let frame_options = match cfg.frame_ancestors.as_slice() {
+            [only] if only == "'none'" => Some(HeaderValue::from_static("DENY")),
+            [only] if only == "'self'" => Some(HeaderValue::from_static("SAMEORIGIN")),
+            _ => None,
+        };
+        Self {
+            csp,
+            referrer_policy: HeaderValue::from_str(&cfg.referrer_policy)
+                .unwrap_or(HeaderValue::from_static("same-origin")),
+            frame_options,
+        }
+    }
+
+    /// Adds the headers to an HTML response. Headers a handler already set
+    /// win, such as the `sandbox` policy on raw downloads.
+    pub fn apply(&self, response: &mut Response) {
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| {
+                ct.trim_start()
+                    .to_ascii_lowercase()
+                    .starts_with("text/html")
+            });
+        if !is_html {
+            return;
+        }
+        let headers = response.headers_mut();
+        if let Some(csp) = &self.csp {
+            insert_missing(headers, header::CONTENT_SECURITY_POLICY, csp);
+        }
+        if let Some(frame_options) = &self.frame_options {
+            insert_missing(headers, header::X_FRAME_OPTIONS, frame_options);
+        }
+        insert_missing(headers, header::REFERRER_POLICY, &self.referrer_policy);
+        insert_missing(
+            headers,
+            header::X_CONTENT_TYPE_OPTIONS,
+            &HeaderValue::from_static("nosniff"),
+        );
+    }
+}
+
+/// `--csp` or the default, with `frame-ancestors` added unless the policy
+/// already has one. `None` for `--csp off`.
+fn policy(cfg: &ServeCmd) -> Option<String> {
+    let base = match cfg.csp.as_deref() {
+        Some(p) if p.eq_ignore_ascii_case("off") => return None,
+        Some(p) => p.trim().trim_end_matches(';').to_string(),
+        None => DEFAULT_POLICY.to_string(),
+    };
+    let has_frame_ancestors = base.split(';').any(|directive| {
+        directive
+            .trim()
+            .to_ascii_lowercase()
+            .starts_with("frame-ancestors")
+    });
+    if has_frame_ancestors {
+        Some(base)
+    } else {
+        Some(format!(
+            "{base}; frame-ancestors {}",
+            cfg.frame_ancestors.join(" ")
+        ))
+    }
+}
+
+fn insert_missing(headers: &mut HeaderMap, name: header::HeaderName, value: &HeaderValue) {
+    if !headers.contains_key(&name) {
+        headers.insert(name, value.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::{Cli, Commands};
+
+    fn serve(args: &[&str]) -> ServeCmd {
+        let argv = ["lanpaste", "serve", "--dir", "/tmp/x"]
+            .iter()
+            .chain(args)
+            .copied();
+        let cli = Cli::try_parse_from(argv).expect("parse");
+        let Commands::Serve(cmd) = cli.command else {
+            panic!("expected serve");
+        };
+        *cmd
+    }
+
+    #[test]
+    fn frame_ancestors_join_the_policy_unless_it_has_its_own() {
+        let default = SecurityHeaders::from_config(&serve(&[]));
+        let csp = default.csp.expect("csp");
+        assert!(
+            csp.to_str()
+                .unwrap()
+                .ends_with("form-action 'self'; frame-ancestors 'none'")
+        );
+        assert_eq!(default.frame_options.expect("xfo"), "DENY");
+
+        let wiki = SecurityHeaders::from_config(&serve(&[
+            "--frame-ancestors",
+            "'self'",
+            "--frame-ancestors",
+            "https://wiki.lan",
+            "--csp",
+            "default-src 'self';",
+        ]));
+        assert_eq!(
+            wiki.csp.expect("csp"),
+            "default-src 'self'; frame-ancestors 'self' https://wiki.lan"
+        );
+        assert!(wiki.frame_options.is_none());
+
+        let own = serve(&["--csp", "default-src 'none'; frame-ancestors 'self'"]);
+        assert_eq!(
+            policy(&own).expect("csp"),
+            "default-src 'none'; frame-ancestors 'self'"
+        );
+        assert!(policy(&serve(&["--csp", "off"])).is_none());
+    }
+
+    #[test]
+    fn only_html_responses_get_the_headers() {
+        let headers = SecurityHeaders::from_config(&serve(&["--referrer-policy", "no-referrer"]));
+        let mut page = Response::new(axum::body::Body::empty());
+        page.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        headers.apply(&mut page);
+        assert!(page.headers().contains_key(header::CONTENT_SECURITY_POLICY));
+        assert_eq!(page.headers()[header::REFERRER_POLICY], "no-referrer");
+
+        let mut raw = Response::new(axum::body::Body::empty());
+        raw.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        raw.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox"),
+        );
+        headers.apply(&mut raw);
+        assert_eq!(raw.headers()[header::CONTENT_SECURITY_POLICY], "sandbox");
+
+        let mut json = Response::new(axum::body::Body::empty());
+        json.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.apply(&mut json);
+        assert_eq!(json.headers().len(), 1);
+    }
+}
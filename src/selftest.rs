@@ -0,0 +1,221 @@
+//! End-to-end self-test of the write path: create, read, render, and delete a
+//! canary paste through the same store/git code that serves users, including
+//! the configured push mode.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use ulid::Ulid;
+
+use crate::{
+    config::PushMode,
+    errors::{AppError, AppResult},
+    gitops::{self, FileLock},
+    render, store,
+    types::{AppState, CreatePasteInput, PasteBody, Visibility},
+};
+
+const CANARY_TAG: &str = "selftest";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestReport {
+    pub ok: bool,
+    pub ran_at: OffsetDateTime,
+    pub duration_ms: u64,
+    pub steps: Vec<SelfTestStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestStep {
+    pub name: &'static str,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+impl SelfTestReport {
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "self-test {} in {}ms\n",
+            if self.ok { "passed" } else { "FAILED" },
+            self.duration_ms
+        );
+        for step in &self.steps {
+            match &step.error {
+                None => out.push_str(&format!("  ok    {}\n", step.name)),
+                Some(err) => out.push_str(&format!("  FAIL  {}: {err}\n", step.name)),
+            }
+        }
+        out
+    }
+}
+
+/// Runs one self-test and records the report on `state` for `/statusz`.
+/// Blocks on git and disk I/O; call from a blocking context.
+pub fn run_self_test(state: &AppState) -> SelfTestReport {
+    let started = Instant::now();
+    let ran_at = OffsetDateTime::now_utc();
+    let mut steps = Vec::new();
+    let mut record = |name: &'static str, res: AppResult<()>| {
+        let ok = res.is_ok();
+        steps.push(SelfTestStep {
+            name,
+            ok,
+            error: res.err().map(|e| format!("{e:?}")),
+        });
+        ok
+    };
+
+    match FileLock::acquire_waiting(&state.paths.git_lock) {
+        Ok(_git_lock) => {
+            let marker = format!("canary {}", Ulid::new());
+            let body = format!("# lanpaste self-test\n\n{marker}\n");
+            match create_canary(state, body.as_bytes()) {
+                Ok((draft, push_res)) => {
+                    record("create", push_res);
+                    record("read", read_canary(state, &draft.id, body.as_bytes()));
+                    record("render", render_canary(&body, &marker));
+                    let mut files = vec![
+                        draft.rel_path.as_str(),
+                        draft.meta_rel_path.as_str(),
+                        draft.slug_rel_path.as_str(),
+                    ];
+                    files.extend(draft.short_rel_path.as_deref());
+                    record("delete", delete_canary(state, &draft.id, &files));
+                }
+                Err(err) => {
+                    record("create", Err(err));
+                }
+            }
+        }
+        Err(err) => {
+            record("lock", Err(err));
+        }
+    }
+
+    let report = SelfTestReport {
+        ok: steps.iter().all(|s| s.ok),
+        ran_at,
+        duration_ms: started.elapsed().as_millis() as u64,
+        steps,
+    };
+    if let Ok(mut last) = state.self_test.lock() {
+        *last = Some(report.clone());
+    }
+    report
+}
+
+/// Re-runs the self-test every `every`, logging failures.
+pub fn spawn_periodic(state: Arc<AppState>, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let state = state.clone();
+            match tokio::task::spawn_blocking(move || run_self_test(&state)).await {
+                Ok(report) if report.ok => info!("self-test passed in {}ms", report.duration_ms),
+                Ok(report) => warn!("{}", report.summary().trim_end()),
+                Err(err) => warn!("self-test task failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Creates and commits the canary. A best-effort push failure still leaves a
+/// canary to clean up, so it is returned alongside the draft.
+fn create_canary(
+    state: &AppState,
+    bytes: &[u8],
+) -> AppResult<(crate::types::PasteDraft, AppResult<()>)> {
+    let input = CreatePasteInput {
+        name: Some("selftest-canary.md".to_string()),
+        msg: None,
+        tag: Some(CANARY_TAG.to_string()),
+        content_type: Some("text/markdown".to_string()),
+        body: PasteBody::Bytes(bytes.to_vec()),
+        e2e: false,
+        visibility: Visibility::Listed,
+        allow_keys: Vec::new(),
+        slug: None,
+        client_ip: None,
+        user_agent: Some("lanpaste-selftest".to_string()),
+        owner: None,
+        lang: None,
+    };
+    let mut draft =
+        store::build_paste_draft(&state.paths.repo, &state.cfg, state.cipher.as_ref(), input)?;
+    draft.subject = format!("selftest: create canary {}", draft.id);
+    let commit = gitops::commit_paste(
+        &state.paths.repo,
+        &state.cfg,
+        &draft,
+        state.cfg.push,
+        &state.cfg.remote,
+    );
+    match commit {
+        Ok(commit) => {
+            state.index.upsert(draft.meta.clone());
+            let push_res = match commit.push_error {
+                Some(err) => Err(AppError::internal(format!("push failed: {err}"))),
+                None => Ok(()),
+            };
+            Ok((draft, push_res))
+        }
+        Err(err) => {
+            if state.cfg.push != PushMode::Strict {
+                let _ = gitops::run_git(&state.paths.repo, &["reset"], &state.cfg);
+                let mut files = vec![
+                    draft.abs_path.clone(),
+                    draft.meta_path.clone(),
+                    draft.slug_path.clone(),
+                ];
+                files.extend(draft.short_path.clone());
+                store::remove_files(&files);
+            }
+            Err(err)
+        }
+    }
+}
+
+fn read_canary(state: &AppState, id: &str, expected: &[u8]) -> AppResult<()> {
+    let meta = store::read_meta(&state.paths.repo, &state.cfg, id)?;
+    let bytes = store::read_paste(&state.paths.repo, &meta)?;
+    store::verify_integrity(&meta, &bytes)?;
+    let bytes = store::unseal(state.cipher.as_ref(), &meta, bytes)?;
+    if bytes != expected {
+        return Err(AppError::Integrity(
+            "canary bytes differ from what was written".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn render_canary(body: &str, marker: &str) -> AppResult<()> {
+    let html = render::render_markdown(body);
+    if html.contains("<h1") && html.contains(marker) {
+        Ok(())
+    } else {
+        Err(AppError::internal("rendered canary is missing content"))
+    }
+}
+
+fn delete_canary(state: &AppState, id: &str, files: &[&str]) -> AppResult<()> {
+    let commit = gitops::commit_removal(
+        &state.paths.repo,
+        &state.cfg,
+        files,
+        &format!("selftest: remove canary {id}"),
+        state.cfg.push,
+        &state.cfg.remote,
+    )?;
+    state.index.remove(&[id]);
+    match commit.push_error {
+        Some(err) => Err(AppError::internal(format!("push failed: {err}"))),
+        None => Ok(()),
+    }
+}
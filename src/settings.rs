@@ -0,0 +1,383 @@
+//! Settings that can change while serving. `--config-file` names a JSON file
+//! whose entries override the matching flags; it is read at startup and
+//! again on `SIGHUP` or `POST /api/v1/admin/reload`. Only the keys in
+//! [`RELOADABLE`] may appear in it: everything else is fixed for the life of
+//! the process, and a reload says so by listing it in `restart_required`.
+//!
+//! A reload also re-reads `--api-keys-file`, which holds the per-key rate
+//! limits. It is all or nothing: a file that fails to parse or validate
+//! leaves the running settings untouched.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, PoisonError, RwLock},
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use tracing::{info, warn};
+
+use crate::{
+    config::{self, ServeCmd},
+    errors::{AppError, AppResult},
+    retention::Policy,
+    types::AppState,
+    webhooks,
+};
+
+/// Config file keys that take effect without a restart.
+pub const RELOADABLE: &[&str] = &[
+    "max_bytes",
+    "ip_rate",
+    "ip_burst",
+    "write_rate",
+    "write_burst",
+    "webhook_url",
+    "retain_days",
+    "retain_count",
+    "retain_max_bytes",
+    "log_level",
+];
+
+/// Applies a new log filter (`None` restores the startup one).
+pub type LogReloader = Box<dyn Fn(Option<&str>) -> Result<(), String> + Send + Sync>;
+
+/// The reloadable settings in effect.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Live {
+    pub max_bytes: usize,
+    /// Requests per second.
+    pub ip_rate: Option<f64>,
+    pub ip_burst: u32,
+    pub write_rate: Option<f64>,
+    pub write_burst: u32,
+    pub webhook_url: Vec<String>,
+    pub retain_days: Option<u64>,
+    pub retain_count: Option<u64>,
+    pub retain_max_bytes: Option<u64>,
+    pub log_level: Option<String>,
+}
+
+impl Live {
+    fn from_flags(cfg: &ServeCmd) -> Self {
+        Self {
+            max_bytes: cfg.max_bytes,
+            ip_rate: cfg.ip_rate,
+            ip_burst: cfg.ip_burst,
+            write_rate: cfg.write_rate,
+            write_burst: cfg.write_burst,
+            webhook_url: cfg.webhook_url.clone(),
+            retain_days: cfg.retain_days,
+            retain_count: cfg.retain_count,
+            retain_max_bytes: cfg.retain_max_bytes,
+            log_level: cfg.log_level.clone(),
+        }
+    }
+
+    pub fn retention(&self) -> Policy {
+        Policy::from_limits(self.retain_days, self.retain_count, self.retain_max_bytes)
+    }
+
+    /// Names of the settings that differ from `other`.
+    fn changed(&self, other: &Live) -> Vec<&'static str> {
+        let (a, b) = (
+            serde_json::to_value(self).unwrap_or_default(),
+            serde_json::to_value(other).unwrap_or_default(),
+        );
+        RELOADABLE
+            .iter()
+            .copied()
+            .filter(|key| a.get(key) != b.get(key))
+            .collect()
+    }
+}
+
+/// Config file contents; every entry is optional.
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    max_bytes: Option<usize>,
+    /// Same syntax as `--ip-rate`, e.g. `"10/s"`.
+    ip_rate: Option<String>,
+    ip_burst: Option<u32>,
+    write_rate: Option<String>,
+    write_burst: Option<u32>,
+    webhook_url: Option<Vec<String>>,
+    retain_days: Option<u64>,
+    retain_count: Option<u64>,
+    retain_max_bytes: Option<u64>,
+    log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadReport {
+    /// Settings whose value changed with this reload.
+    pub changed: Vec<&'static str>,
+    /// Keys now loaded from `--api-keys-file` (0 without one).
+    pub api_keys: usize,
+    /// Shared tokens now configured, from `--token` and `--token-file`.
+    pub tokens: usize,
+    /// Serve options that only a restart picks up.
+    pub restart_required: Vec<String>,
+}
+
+pub struct Settings {
+    file: Option<PathBuf>,
+    /// The flag values, which apply wherever the file is silent.
+    flags: Live,
+    current: RwLock<Arc<Live>>,
+    log: OnceLock<LogReloader>,
+}
+
+impl Settings {
+    /// The flags in `cfg`, overridden by `--config-file` if one is set.
+    pub fn load(cfg: &ServeCmd) -> AppResult<Self> {
+        let flags = Live::from_flags(cfg);
+        let current = match &cfg.config_file {
+            Some(path) => read(path, &flags)?,
+            None => flags.clone(),
+        };
+        Ok(Self {
+            file: cfg.config_file.clone(),
+            flags,
+            current: RwLock::new(Arc::new(current)),
+            log: OnceLock::new(),
+        })
+    }
+
+    pub fn current(&self) -> Arc<Live> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Installs the hook that changes the log filter and applies the
+    /// current `log_level` through it.
+    pub fn set_log_reloader(&self, reloader: LogReloader) -> AppResult<()> {
+        let level = self.current().log_level.clone();
+        if level.is_some() {
+            reloader(level.as_deref()).map_err(AppError::internal)?;
+        }
+        let _ = self.log.set(reloader);
+        Ok(())
+    }
+}
+
+/// Re-reads `--config-file`, `--api-keys-file` and `--token-file` and
+/// applies what changed.
+pub fn reload(state: &AppState) -> AppResult<ReloadReport> {
+    let settings = &state.settings;
+    let next = match &settings.file {
+        Some(path) => read(path, &settings.flags).map_err(|err| match err {
+            AppError::Internal(msg) => AppError::BadRequest(msg),
+            other => other,
+        })?,
+        None => settings.flags.clone(),
+    };
+    let current = settings.current();
+    let changed = next.changed(&current);
+
+    // Check everything before applying anything.
+    let urls = if changed.contains(&"webhook_url") {
+        if state.webhooks.is_none() && !next.webhook_url.is_empty() {
+            return Err(AppError::BadRequest(
+                "webhook_url needs --webhook-secret-file at startup".to_string(),
+            ));
+        }
+        Some(
+            webhooks::parse_urls(&next.webhook_url).map_err(|err| match err {
+                AppError::Internal(msg) => AppError::BadRequest(msg),
+                other => other,
+            })?,
+        )
+    } else {
+        None
+    };
+    if changed.contains(&"log_level") && settings.log.get().is_none() {
+        return Err(AppError::BadRequest(
+            "log_level cannot be changed in this process".to_string(),
+        ));
+    }
+    let api_keys = state.api_keys.reload()?;
+    let tokens = state.token.reload()?;
+
+    if let (Some(urls), Some(hooks)) = (urls, &state.webhooks) {
+        hooks.set_urls(urls);
+    }
+    if changed.contains(&"log_level")
+        && let Some(apply) = settings.log.get()
+    {
+        apply(next.log_level.as_deref()).map_err(AppError::BadRequest)?;
+    }
+    *settings
+        .current
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Arc::new(next);
+    if !changed.is_empty() {
+        info!("settings reloaded: {}", changed.join(", "));
+    }
+    Ok(ReloadReport {
+        changed,
+        api_keys,
+        tokens,
+        restart_required: restart_required(),
+    })
+}
+
+/// Reloads on every `SIGHUP`, logging failures; the old settings stay.
+#[cfg(unix)]
+pub fn spawn_sighup(state: Arc<AppState>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("SIGHUP reload disabled: {err}");
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            let state = state.clone();
+            match tokio::task::spawn_blocking(move || reload(&state)).await {
+                Ok(Ok(report)) if report.changed.is_empty() => {
+                    info!("SIGHUP: settings unchanged, {} API keys", report.api_keys)
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => warn!("SIGHUP reload failed: {err:?}"),
+                Err(err) => warn!("reload task failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Every serve option outside [`RELOADABLE`].
+pub fn restart_required() -> Vec<String> {
+    ServeCmd::augment_args(clap::Command::new("serve"))
+        .get_arguments()
+        .map(|arg| arg.get_id().as_str().to_string())
+        .filter(|id| !RELOADABLE.contains(&id.as_str()) && id != "help")
+        .collect()
+}
+
+fn read(path: &Path, flags: &Live) -> AppResult<Live> {
+    let raw = fs::read(path).map_err(|e| AppError::io("read config file", e))?;
+    let entries: Map<String, serde_json::Value> = serde_json::from_slice(&raw)
+        .map_err(|e| AppError::internal(format!("parse config file: {e}")))?;
+    let fixed = restart_required();
+    for key in entries.keys() {
+        if fixed.contains(key) {
+            return Err(AppError::internal(format!(
+                "config file: '{key}' cannot be reloaded; set it with --{} and restart",
+                key.replace('_', "-")
+            )));
+        }
+        if !RELOADABLE.contains(&key.as_str()) {
+            return Err(AppError::internal(format!(
+                "config file: unknown setting '{key}'"
+            )));
+        }
+    }
+    let file: ConfigFile = serde_json::from_value(serde_json::Value::Object(entries))
+        .map_err(|e| AppError::internal(format!("config file: {e}")))?;
+    let live = Live {
+        max_bytes: file.max_bytes.unwrap_or(flags.max_bytes),
+        ip_rate: rate(file.ip_rate, flags.ip_rate, "ip_rate")?,
+        ip_burst: file.ip_burst.unwrap_or(flags.ip_burst),
+        write_rate: rate(file.write_rate, flags.write_rate, "write_rate")?,
+        write_burst: file.write_burst.unwrap_or(flags.write_burst),
+        webhook_url: file
+            .webhook_url
+            .unwrap_or_else(|| flags.webhook_url.clone()),
+        retain_days: file.retain_days.or(flags.retain_days),
+        retain_count: file.retain_count.or(flags.retain_count),
+        retain_max_bytes: file.retain_max_bytes.or(flags.retain_max_bytes),
+        log_level: file.log_level.or_else(|| flags.log_level.clone()),
+    };
+    if live.max_bytes == 0 {
+        return Err(AppError::internal(
+            "config file: max_bytes must be at least 1",
+        ));
+    }
+    if live.ip_burst == 0 || live.write_burst == 0 {
+        return Err(AppError::internal(
+            "config file: ip_burst and write_burst must be at least 1",
+        ));
+    }
+    if live.retain_days == Some(0) || live.retain_count == Some(0) {
+        return Err(AppError::internal(
+            "config file: retain_days and retain_count must be at least 1",
+        ));
+    }
+    if let Some(level) = &live.log_level {
+        tracing_subscriber::EnvFilter::try_new(level)
+            .map_err(|e| AppError::internal(format!("config file: log_level: {e}")))?;
+    }
+    webhooks::parse_urls(&live.webhook_url)?;
+    Ok(live)
+}
+
+/// A rate from the file in `--ip-rate` syntax, else the flag's.
+fn rate(raw: Option<String>, flag: Option<f64>, key: &str) -> AppResult<Option<f64>> {
+    match raw {
+        Some(raw) => config::parse_rate(&raw)
+            .map(Some)
+            .map_err(|e| AppError::internal(format!("config file: {key}: {e}"))),
+        None => Ok(flag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_file_overrides_flags_and_refuses_fixed_settings() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("lanpaste.json");
+        let flags = Live {
+            max_bytes: 1024,
+            ip_rate: None,
+            ip_burst: 20,
+            write_rate: None,
+            write_burst: 10,
+            webhook_url: Vec::new(),
+            retain_days: Some(30),
+            retain_count: None,
+            retain_max_bytes: None,
+            log_level: None,
+        };
+
+        fs::write(
+            &path,
+            r#"{"max_bytes": 10, "ip_rate": "120/m", "retain_count": 5}"#,
+        )
+        .expect("write");
+        let live = read(&path, &flags).expect("read");
+        assert_eq!(live.max_bytes, 10);
+        assert_eq!(live.ip_rate, Some(2.0));
+        assert_eq!(live.retain_days, Some(30));
+        assert_eq!(live.retain_count, Some(5));
+        assert_eq!(
+            live.changed(&flags),
+            ["max_bytes", "ip_rate", "retain_count"]
+        );
+
+        for (body, needle) in [
+            (r#"{"bind": "0.0.0.0:1"}"#, "--bind and restart"),
+            (r#"{"colour": true}"#, "unknown setting 'colour'"),
+            (r#"{"log_level": "lanpaste=loud"}"#, "log_level"),
+            (r#"{"webhook_url": ["ftp://x"]}"#, "webhook-url"),
+        ] {
+            fs::write(&path, body).expect("write");
+            let err = format!("{:?}", read(&path, &flags).expect_err(body));
+            assert!(err.contains(needle), "{body}: {err}");
+        }
+
+        let fixed = restart_required();
+        assert!(fixed.iter().any(|k| k == "bind") && fixed.iter().any(|k| k == "dir"));
+        assert!(!fixed.iter().any(|k| k == "max_bytes" || k == "help"));
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::{
+    config::SoakCmd,
+    errors::{AppError, AppResult},
+    types::{CreatePasteResponse, PasteMeta, RecentItem},
+};
+
+/// Weighted paste-size buckets in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeDist {
+    buckets: Vec<(usize, u32)>,
+}
+
+/// Operations soak can mix. There is no delete: the server has no endpoint
+/// that removes a single paste (only retention and archiving do).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SoakOp {
+    Create,
+    Read,
+    Recent,
+}
+
+/// Weighted operation mix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpMix {
+    weights: Vec<(SoakOp, u32)>,
+}
+
+#[derive(Debug, Default)]
+pub struct SoakReport {
+    pub creates: u64,
+    pub reads: u64,
+    pub recents: u64,
+    pub errors: u64,
+    pub violations: Vec<String>,
+    latencies_ms: Vec<(SoakOp, f64)>,
+}
+
+impl SoakOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            SoakOp::Create => "create",
+            SoakOp::Read => "read",
+            SoakOp::Recent => "recent",
+        }
+    }
+}
+
+impl SizeDist {
+    fn pick(&self, rng: &mut Rng) -> usize {
+        pick_weighted(&self.buckets, rng)
+    }
+}
+
+impl OpMix {
+    fn pick(&self, rng: &mut Rng) -> SoakOp {
+        pick_weighted(&self.weights, rng)
+    }
+}
+
+impl SoakReport {
+    pub fn percentile_ms(&self, op: SoakOp, pct: f64) -> Option<f64> {
+        let mut samples: Vec<f64> = self
+            .latencies_ms
+            .iter()
+            .filter(|(o, _)| *o == op)
+            .map(|(_, ms)| *ms)
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f64::total_cmp);
+        let idx = ((samples.len() as f64 - 1.0) * pct).round() as usize;
+        samples.get(idx).copied()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "soak: create={} read={} recent={} errors={} violations={}\n",
+            self.creates,
+            self.reads,
+            self.recents,
+            self.errors,
+            self.violations.len()
+        );
+        for op in [SoakOp::Create, SoakOp::Read, SoakOp::Recent] {
+            if let (Some(p50), Some(p99)) =
+                (self.percentile_ms(op, 0.5), self.percentile_ms(op, 0.99))
+            {
+                out.push_str(&format!(
+                    "  {:<6} p50={p50:.1}ms p99={p99:.1}ms\n",
+                    op.as_str()
+                ));
+            }
+        }
+        for v in &self.violations {
+            out.push_str(&format!("  violation: {v}\n"));
+        }
+        out
+    }
+}
+
+fn pick_weighted<T: Copy>(items: &[(T, u32)], rng: &mut Rng) -> T {
+    let total: u64 = items.iter().map(|(_, w)| u64::from(*w)).sum();
+    let mut roll = rng.next() % total.max(1);
+    for (item, w) in items {
+        if roll < u64::from(*w) {
+            return *item;
+        }
+        roll -= u64::from(*w);
+    }
+    items[0].0
+}
+
+fn parse_size(raw: &str) -> Result<usize, String> {
+    let raw = raw.trim().to_ascii_lowercase();
+    let (num, mult) = if let Some(n) = raw.strip_suffix('k') {
+        (n, 1024)
+    } else if let Some(n) = raw.strip_suffix('m') {
+        (n, 1024 * 1024)
+    } else {
+        (raw.as_str(), 1)
+    };
+    num.parse::<usize>()
+        .map(|n| n * mult)
+        .map_err(|_| format!("invalid size '{raw}'"))
+}
+
+fn parse_weighted<T>(
+    raw: &str,
+    parse_item: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<(T, u32)>, String> {
+    let mut out = Vec::new();
+    for part in raw.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (item, weight) = part.split_once('=').unwrap_or((part, "1"));
+        let weight: u32 = weight
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid weight in '{part}'"))?;
+        if weight > 0 {
+            out.push((parse_item(item)?, weight));
+        }
+    }
+    if out.is_empty() {
+        return Err("at least one entry with a positive weight is required".to_string());
+    }
+    Ok(out)
+}
+
+pub fn parse_size_dist(raw: &str) -> Result<SizeDist, String> {
+    let buckets = parse_weighted(raw, parse_size)?;
+    if buckets.iter().any(|(size, _)| *size == 0) {
+        return Err("paste size must be positive".to_string());
+    }
+    Ok(SizeDist { buckets })
+}
+
+pub fn parse_mix(raw: &str) -> Result<OpMix, String> {
+    let weights = parse_weighted(raw, |item| match item.trim() {
+        "create" => Ok(SoakOp::Create),
+        "read" => Ok(SoakOp::Read),
+        "recent" => Ok(SoakOp::Recent),
+        "delete" => Err(
+            "soak op 'delete' is not supported: the server cannot delete a single paste"
+                .to_string(),
+        ),
+        other => Err(format!("unknown soak op '{other}'")),
+    })?;
+    Ok(OpMix { weights })
+}
+
+/// Small xorshift generator; load shaping does not need a CSPRNG.
+struct Rng(u64);
+
+impl Rng {
+    fn seeded() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15);
+        Self(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+fn synthetic_body(rng: &mut Rng, size: usize) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789 ";
+    let mut out = Vec::with_capacity(size);
+    while out.len() < size {
+        if out.len() % 80 == 79 {
+            out.push(b'\n');
+        } else {
+            out.push(ALPHABET[(rng.next() % ALPHABET.len() as u64) as usize]);
+        }
+    }
+    out
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize())
+}
+
+struct Shared {
+    cmd: SoakCmd,
+    client: reqwest::Client,
+    base: String,
+    created: Mutex<Vec<(String, String)>>,
+    report: Mutex<SoakReport>,
+}
+
+impl Shared {
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let mut req = self.client.request(method, format!("{}{path}", self.base));
+        if let Some(token) = &self.cmd.token {
+            req = req.header("X-Paste-Token", token);
+        }
+        if let Some(key) = &self.cmd.api_key {
+            req = req.header(crate::auth::API_KEY_HEADER, key);
+        }
+        req
+    }
+
+    fn record(&self, op: SoakOp, elapsed: Duration, outcome: Result<(), String>) {
+        let Ok(mut report) = self.report.lock() else {
+            return;
+        };
+        match op {
+            SoakOp::Create => report.creates += 1,
+            SoakOp::Read => report.reads += 1,
+            SoakOp::Recent => report.recents += 1,
+        }
+        report
+            .latencies_ms
+            .push((op, elapsed.as_secs_f64() * 1000.0));
+        if let Err(err) = outcome {
+            report.errors += 1;
+            warn!("soak {} failed: {err}", op.as_str());
+        }
+    }
+
+    fn violation(&self, msg: String) {
+        if let Ok(mut report) = self.report.lock() {
+            report.violations.push(msg);
+        }
+    }
+
+    fn pick_created(&self, rng: &mut Rng) -> Option<(String, String)> {
+        let created = self.created.lock().ok()?;
+        if created.is_empty() {
+            return None;
+        }
+        created
+            .get((rng.next() % created.len() as u64) as usize)
+            .cloned()
+    }
+}
+
+async fn op_create(shared: &Shared, rng: &mut Rng) -> Result<(), String> {
+    let size = shared.cmd.size_dist.pick(rng);
+    let body = synthetic_body(rng, size);
+    let expected = sha256_hex(&body);
+    let resp = shared
+        .request(reqwest::Method::POST, "/api/v1/paste")
+        .query(&[("name", "soak.txt"), ("tag", shared.cmd.tag.as_str())])
+        .header("Content-Type", "text/plain")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("create returned {}", resp.status()));
+    }
+    let created: CreatePasteResponse = resp.json().await.map_err(|e| e.to_string())?;
+    if let Ok(mut list) = shared.created.lock() {
+        list.push((created.id, expected));
+    }
+    Ok(())
+}
+
+async fn op_read(shared: &Shared, rng: &mut Rng) -> Result<(), String> {
+    let Some((id, expected)) = shared.pick_created(rng) else {
+        return op_create(shared, rng).await;
+    };
+    let raw = shared
+        .request(reqwest::Method::GET, &format!("/api/v1/p/{id}/raw"))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !raw.status().is_success() {
+        return Err(format!("raw read returned {}", raw.status()));
+    }
+    let bytes = raw.bytes().await.map_err(|e| e.to_string())?;
+    if sha256_hex(&bytes) != expected {
+        shared.violation(format!("sha256 mismatch on raw read of {id}"));
+    }
+
+    let meta = shared
+        .request(reqwest::Method::GET, &format!("/api/v1/p/{id}"))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !meta.status().is_success() {
+        return Err(format!("meta read returned {}", meta.status()));
+    }
+    let meta: PasteMeta = meta.json().await.map_err(|e| e.to_string())?;
+    if meta.sha256 != expected {
+        shared.violation(format!("meta sha256 mismatch for {id}"));
+    }
+    if meta.size != bytes.len() {
+        shared.violation(format!(
+            "meta size {} != raw length {} for {id}",
+            meta.size,
+            bytes.len()
+        ));
+    }
+    Ok(())
+}
+
+async fn op_recent(shared: &Shared) -> Result<(), String> {
+    let resp = shared
+        .request(reqwest::Method::GET, "/api/v1/recent")
+        .query(&[("n", "50"), ("tag", shared.cmd.tag.as_str())])
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("recent returned {}", resp.status()));
+    }
+    let items: Vec<RecentItem> = resp.json().await.map_err(|e| e.to_string())?;
+    if items
+        .windows(2)
+        .any(|pair| pair[0].created_at < pair[1].created_at)
+    {
+        shared.violation("recent listing is not ordered by created_at desc".to_string());
+    }
+    Ok(())
+}
+
+async fn run_op(shared: Arc<Shared>, op: SoakOp, seed: u64) {
+    let mut rng = Rng(seed | 1);
+    let started = Instant::now();
+    let outcome = match op {
+        SoakOp::Create => op_create(&shared, &mut rng).await,
+        SoakOp::Read => op_read(&shared, &mut rng).await,
+        SoakOp::Recent => op_recent(&shared).await,
+    };
+    shared.record(op, started.elapsed(), outcome);
+}
+
+pub async fn run_soak(cmd: SoakCmd) -> AppResult<SoakReport> {
+    if cmd.concurrency == 0 {
+        return Err(AppError::BadRequest(
+            "concurrency must be positive".to_string(),
+        ));
+    }
+    let base = cmd.target.trim_end_matches('/').to_string();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()
+        .map_err(|e| AppError::internal(format!("build http client: {e}")))?;
+    let deadline = Instant::now() + cmd.duration;
+    let period = Duration::from_secs_f64(1.0 / cmd.rate);
+    let permits = Arc::new(Semaphore::new(cmd.concurrency));
+    let mix = cmd.mix.clone();
+    let shared = Arc::new(Shared {
+        cmd,
+        client,
+        base,
+        created: Mutex::new(Vec::new()),
+        report: Mutex::new(SoakReport::default()),
+    });
+
+    let mut rng = Rng::seeded();
+    let mut ticker = tokio::time::interval(period);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut tasks = tokio::task::JoinSet::new();
+    while Instant::now() < deadline {
+        ticker.tick().await;
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| AppError::internal("soak semaphore closed"))?;
+        let op = mix.pick(&mut rng);
+        let shared = shared.clone();
+        let seed = rng.next();
+        tasks.spawn(async move {
+            run_op(shared, op, seed).await;
+            drop(permit);
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+
+    // Final ordering check once all writes have settled. If it cannot run,
+    // the ordering was never checked, which must not pass quietly.
+    if let Err(err) = op_recent(&shared).await {
+        shared.violation(format!("final recent check failed: {err}"));
+    }
+
+    let report = std::mem::take(
+        &mut *shared
+            .report
+            .lock()
+            .map_err(|_| AppError::internal("soak report lock poisoned"))?,
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_size_dist_and_mix() {
+        let dist = parse_size_dist("1k=70, 64k=25,2m=5").expect("dist");
+        assert_eq!(
+            dist.buckets,
+            vec![(1024, 70), (64 * 1024, 25), (2 * 1024 * 1024, 5)]
+        );
+        let mix = parse_mix("create=1,read=0,recent").expect("mix");
+        assert_eq!(mix.weights, vec![(SoakOp::Create, 1), (SoakOp::Recent, 1)]);
+        let err = parse_mix("delete=1").expect_err("delete");
+        assert!(err.contains("not supported"), "{err}");
+        assert!(parse_size_dist("0=1").is_err());
+    }
+
+    #[test]
+    fn weighted_pick_respects_zero_free_buckets() {
+        let mix = parse_mix("recent=1").expect("mix");
+        let mut rng = Rng::seeded();
+        for _ in 0..16 {
+            assert_eq!(mix.pick(&mut rng), SoakOp::Recent);
+        }
+        let body = synthetic_body(&mut rng, 300);
+        assert_eq!(body.len(), 300);
+    }
+}
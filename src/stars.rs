@@ -0,0 +1,98 @@
+//! Per-key stars (bookmarks) on pastes, kept in the runtime kv store.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AppResult, kv::Kv};
+
+const STARS_NS: &str = "stars";
+
+/// Serialises read-modify-write of star records.
+static WRITE: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StarRecord {
+    id: String,
+    by: BTreeSet<String>,
+}
+
+/// Stars paste `id` for key `who`; starring twice is a no-op. Returns the
+/// new star count.
+pub fn star(kv: &Kv, id: &str, who: &str) -> AppResult<usize> {
+    update(kv, id, |by| {
+        by.insert(who.to_string());
+    })
+}
+
+/// Removes `who`'s star from paste `id`. Returns the new star count.
+pub fn unstar(kv: &Kv, id: &str, who: &str) -> AppResult<usize> {
+    update(kv, id, |by| {
+        by.remove(who);
+    })
+}
+
+fn update(kv: &Kv, id: &str, change: impl FnOnce(&mut BTreeSet<String>)) -> AppResult<usize> {
+    let _guard = WRITE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut record = kv
+        .get::<StarRecord>(STARS_NS, id)?
+        .unwrap_or_else(|| StarRecord {
+            id: id.to_string(),
+            by: BTreeSet::new(),
+        });
+    change(&mut record.by);
+    if record.by.is_empty() {
+        kv.delete(STARS_NS, id)?;
+    } else {
+        kv.put(STARS_NS, id, &record, None)?;
+    }
+    Ok(record.by.len())
+}
+
+/// Star count per paste id, for pastes with at least one star.
+pub fn counts(kv: &Kv) -> AppResult<HashMap<String, usize>> {
+    Ok(kv
+        .list::<StarRecord>(STARS_NS)?
+        .into_iter()
+        .map(|record| (record.id, record.by.len()))
+        .collect())
+}
+
+/// Ids of the pastes `who` has starred.
+pub fn bookmarks(kv: &Kv, who: &str) -> AppResult<Vec<String>> {
+    Ok(kv
+        .list::<StarRecord>(STARS_NS)?
+        .into_iter()
+        .filter(|record| record.by.contains(who))
+        .map(|record| record.id)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_are_per_key_and_idempotent() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let kv = Kv::open(td.path());
+        assert_eq!(star(&kv, "A", "alice").expect("star"), 1);
+        assert_eq!(star(&kv, "A", "alice").expect("star"), 1);
+        assert_eq!(star(&kv, "A", "bob").expect("star"), 2);
+        assert_eq!(star(&kv, "B", "bob").expect("star"), 1);
+
+        assert_eq!(counts(&kv).expect("counts").get("A"), Some(&2));
+        let mut bob = bookmarks(&kv, "bob").expect("bookmarks");
+        bob.sort();
+        assert_eq!(bob, vec!["A", "B"]);
+
+        assert_eq!(unstar(&kv, "B", "bob").expect("unstar"), 0);
+        assert_eq!(unstar(&kv, "B", "bob").expect("unstar"), 0);
+        assert!(!counts(&kv).expect("counts").contains_key("B"));
+    }
+}
@@ -0,0 +1,65 @@
+//! `GET /api/v1/stats`: paste totals from the in-memory index and what the
+//! data directory takes up, for dashboards and capacity planning.
+
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{index::Totals, types::AppState};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub totals: Totals,
+    /// Everything under `repo/`, `.git` included.
+    pub repo_bytes: u64,
+    /// Free space on the filesystem holding `--dir`.
+    pub free_bytes: Option<u64>,
+    pub started_at: OffsetDateTime,
+    pub uptime_secs: i64,
+}
+
+/// Walks the repo on disk, so blocks the thread.
+pub fn collect(state: &AppState) -> Stats {
+    let started_at = state.ops.started_at();
+    state.index.sync(&state.paths.repo, &state.cfg);
+    Stats {
+        totals: state.index.totals(),
+        repo_bytes: dir_size(&state.paths.repo),
+        free_bytes: fs2::available_space(&state.paths.base).ok(),
+        started_at,
+        uptime_secs: (OffsetDateTime::now_utc() - started_at).whole_seconds(),
+    }
+}
+
+/// Bytes in regular files below `dir`. Symlinks are not followed and
+/// entries that vanish or cannot be read are skipped.
+pub(crate) fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map_or(0, |m| m.len()),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_size_adds_up_nested_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        fs::write(dir.path().join("a"), b"12345").expect("write");
+        fs::create_dir_all(dir.path().join("x/y")).expect("mkdir");
+        fs::write(dir.path().join("x/y/b"), b"123").expect("write");
+        assert_eq!(dir_size(dir.path()), 8);
+        assert_eq!(dir_size(&dir.path().join("missing")), 0);
+    }
+}
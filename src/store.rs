@@ -1,23 +1,40 @@
 use std::{
-    fs::{self, OpenOptions},
-    io::{ErrorKind, Write},
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use sha2::{Digest, Sha256};
 use subtle::ConstantTimeEq;
 use time::OffsetDateTime;
+use tracing::error;
 use ulid::Ulid;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::{
     config::ServeCmd,
+    crypt::{self, PasteCipher},
     errors::{AppError, AppResult},
     gitops,
-    types::{AppPaths, CreatePasteInput, FileMeta, IdempotencyRecord, PasteDraft, PasteMeta, UploadResponse},
+    kv::Kv,
+    types::{
+        AppPaths, AppendDraft, CreatePasteInput, FileMeta, IdempotencyFields, IdempotencyRecord,
+        MetaRewrite, MetaUpdateDraft, PasteBody, PasteDraft, PasteLease, PasteMeta, UploadResponse,
+        Visibility,
+    },
+    zst,
 };
 
+/// Longest slug, in characters.
 const MAX_SLUG_LEN: usize = 80;
-pub type RecentWithTags = (Vec<PasteMeta>, Vec<(String, usize)>);
+/// Longest slug in bytes, which keeps `<id>__<slug>.<ext>.zst` inside the
+/// 255-byte file name limit when every character takes three or four.
+const MAX_SLUG_BYTES: usize = 200;
+const MAX_SUPERSEDE_CHAIN: usize = 1000;
+const SHORT_ID_LEN: usize = 7;
+/// Bitcoin-style base58: no `0`, `O`, `I` or `l` to mishear or misread.
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct SlugRecord {
@@ -26,6 +43,12 @@ struct SlugRecord {
     created_at: OffsetDateTime,
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ShortIdRecord {
+    short_id: String,
+    id: String,
+}
+
 pub fn verify_token(expected: Option<&str>, provided: Option<&str>) -> AppResult<()> {
     match expected {
         None => Ok(()),
@@ -46,8 +69,19 @@ pub fn check_cidr(allow: &[ipnet::IpNet], ip: Option<std::net::IpAddr>) -> AppRe
     if allow.is_empty() {
         return Ok(());
     }
-    let addr = ip.ok_or_else(|| AppError::Forbidden("client IP unavailable".to_string()))?;
-    if allow.iter().any(|n| n.contains(&addr)) {
+    let addr = ip
+        .ok_or_else(|| AppError::Forbidden("client IP unavailable".to_string()))?
+        .to_canonical();
+    // Dual-stack listeners report IPv4 clients as `::ffff:a.b.c.d`; match the
+    // IPv4 form against IPv4 ranges and the mapped form against IPv6 ones.
+    let mapped = match addr {
+        std::net::IpAddr::V4(v4) => Some(std::net::IpAddr::V6(v4.to_ipv6_mapped())),
+        std::net::IpAddr::V6(_) => None,
+    };
+    if allow
+        .iter()
+        .any(|n| n.contains(&addr) || mapped.is_some_and(|m| n.contains(&m)))
+    {
         Ok(())
     } else {
         Err(AppError::Forbidden(
@@ -56,36 +90,27 @@ pub fn check_cidr(allow: &[ipnet::IpNet], ip: Option<std::net::IpAddr>) -> AppRe
     }
 }
 
+/// Letters and digits of any script, `.`, `_` and `-` survive; anything
+/// else becomes `-`. The name is NFKC-normalized first, so composed and
+/// decomposed accents, and full-width forms, give the same result. It is
+/// checked for path tricks after normalizing, since `．．／` becomes `../`.
 pub fn sanitize_name(name: &str) -> AppResult<String> {
-    if name.contains('/') || name.contains('\\') || name.contains("..") {
+    let normalized: String = name.trim().nfkc().collect();
+    if normalized.contains('/') || normalized.contains('\\') || normalized.contains("..") {
         return Err(AppError::BadRequest("invalid name".to_string()));
     }
-    if name.starts_with('.') {
+    if normalized.starts_with('.') {
         return Err(AppError::BadRequest("invalid name".to_string()));
     }
-    let normalized = name.trim().replace(' ', "-");
-    if normalized.is_empty() {
-        return Ok("paste".to_string());
-    }
     let mut out = String::new();
     for ch in normalized.chars() {
-        if ch.is_ascii_alphanumeric() || matches!(ch, '.' | '_' | '-') {
+        if ch.is_alphanumeric() || matches!(ch, '.' | '_' | '-') {
             out.push(ch);
         } else {
             out.push('-');
         }
     }
-    while out.contains("--") {
-        out = out.replace("--", "-");
-    }
-    out = out.trim_matches('-').to_string();
-    if out.is_empty() {
-        out = "paste".to_string();
-    }
-    if out.len() > MAX_SLUG_LEN {
-        out.truncate(MAX_SLUG_LEN);
-    }
-    Ok(out)
+    Ok(limit_slug(&collapse_dashes(&out)).unwrap_or_else(|| "paste".to_string()))
 }
 
 fn sanitize_slug_candidate(name: &str) -> AppResult<String> {
@@ -96,43 +121,91 @@ fn sanitize_slug_candidate(name: &str) -> AppResult<String> {
         .unwrap_or(sanitized.as_str());
     let mut out = String::new();
     for ch in stem.chars() {
-        if ch.is_ascii_alphanumeric() {
-            out.push(ch.to_ascii_lowercase());
+        if ch.is_alphanumeric() {
+            // Some capitals lowercase to a letter plus a combining mark;
+            // only the letter is kept.
+            out.extend(ch.to_lowercase().filter(|c| is_slug_char(*c)));
         } else if matches!(ch, '-' | '_') {
             out.push(ch);
         } else {
             out.push('-');
         }
     }
+    Ok(limit_slug(&collapse_dashes(&out)).unwrap_or_else(|| "paste".to_string()))
+}
+
+/// Letters and digits of any script, `-` and `_`.
+fn is_slug_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '-' | '_')
+}
+
+fn is_slug(slug: &str) -> bool {
+    !slug.is_empty() && slug.chars().all(is_slug_char)
+}
+
+fn collapse_dashes(raw: &str) -> String {
+    let mut out = raw.to_string();
     while out.contains("--") {
         out = out.replace("--", "-");
     }
-    out = out.trim_matches('-').to_string();
-    if out.is_empty() {
-        out = "paste".to_string();
-    }
-    if out.len() > MAX_SLUG_LEN {
-        out.truncate(MAX_SLUG_LEN);
+    out.trim_matches('-').to_string()
+}
+
+/// Cuts a slug to [`MAX_SLUG_LEN`] characters and [`MAX_SLUG_BYTES`] bytes,
+/// on a character boundary; `None` when nothing is left.
+fn limit_slug(slug: &str) -> Option<String> {
+    let mut out = String::new();
+    for ch in slug.chars().take(MAX_SLUG_LEN) {
+        if out.len() + ch.len_utf8() > MAX_SLUG_BYTES {
+            break;
+        }
+        out.push(ch);
     }
-    Ok(out)
+    let out = out.trim_end_matches('-');
+    (!out.is_empty()).then(|| out.to_string())
 }
 
 fn slug_record_path(repo: &Path, slug: &str) -> PathBuf {
     repo.join("slugs").join(format!("{slug}.json"))
 }
 
+/// Validates a client-chosen slug and checks it is free. Vanity slugs must
+/// already be in canonical form; rewriting them would hand out a URL other
+/// than the one asked for.
+fn reserve_custom_slug(repo: &Path, slug: &str) -> AppResult<String> {
+    let canonical = is_slug(slug)
+        && limit_slug(slug).as_deref() == Some(slug)
+        && slug.chars().all(|c| !c.is_uppercase())
+        && slug.nfc().eq(slug.chars())
+        && !slug.starts_with('-');
+    if !canonical {
+        return Err(AppError::BadRequest(format!(
+            "invalid slug '{slug}'; use up to {MAX_SLUG_LEN} lowercase letters, digits, '-' or '_'"
+        )));
+    }
+    if slug_record_path(repo, slug).exists() {
+        return Err(AppError::Conflict(format!(
+            "slug '{slug}' is already taken"
+        )));
+    }
+    Ok(slug.to_string())
+}
+
 fn unique_slug(repo: &Path, base_slug: &str) -> String {
     if !slug_record_path(repo, base_slug).exists() {
         return base_slug.to_string();
     }
     for suffix in 2.. {
         let suffix_text = format!("-{suffix}");
-        let max_base = MAX_SLUG_LEN.saturating_sub(suffix_text.len());
-        let trimmed = if base_slug.len() > max_base {
-            &base_slug[..max_base]
-        } else {
-            base_slug
-        };
+        let max_chars = MAX_SLUG_LEN.saturating_sub(suffix_text.len());
+        let max_bytes = MAX_SLUG_BYTES.saturating_sub(suffix_text.len());
+        let mut trimmed = String::new();
+        for ch in base_slug.chars().take(max_chars) {
+            if trimmed.len() + ch.len_utf8() > max_bytes {
+                break;
+            }
+            trimmed.push(ch);
+        }
         let candidate = format!("{trimmed}{suffix_text}");
         if !slug_record_path(repo, &candidate).exists() {
             return candidate;
@@ -141,19 +214,172 @@ fn unique_slug(repo: &Path, base_slug: &str) -> String {
     base_slug.to_string()
 }
 
+fn short_record_path(repo: &Path, short_id: &str) -> PathBuf {
+    repo.join("short").join(format!("{short_id}.json"))
+}
+
+fn is_short_id(key: &str) -> bool {
+    key.len() == SHORT_ID_LEN && key.bytes().all(|b| BASE58.contains(&b))
+}
+
+/// A random unused short id. The caller holds the git lock, so nobody else
+/// can claim it before the map file is written.
+fn new_short_id(repo: &Path) -> String {
+    loop {
+        let mut out = String::with_capacity(SHORT_ID_LEN);
+        while out.len() < SHORT_ID_LEN {
+            let mut buf = [0u8; 16];
+            getrandom::getrandom(&mut buf).expect("os random source");
+            // Rejecting bytes past the last whole multiple of 58 keeps the
+            // alphabet evenly weighted.
+            out.extend(
+                buf.iter()
+                    .filter(|&&b| (b as usize) < BASE58.len() * 4)
+                    .map(|&b| BASE58[b as usize % BASE58.len()] as char)
+                    .take(SHORT_ID_LEN - out.len()),
+            );
+        }
+        if !short_record_path(repo, &out).exists() {
+            return out;
+        }
+    }
+}
+
+/// Full id for a short id, if `key` is one that has been handed out.
+pub fn resolve_short_id(repo: &Path, key: &str) -> AppResult<Option<String>> {
+    if !is_short_id(key) {
+        return Ok(None);
+    }
+    let data = match fs::read(short_record_path(repo, key)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(AppError::io("read short id map", e)),
+    };
+    let record = serde_json::from_slice::<ShortIdRecord>(&data)
+        .map_err(|e| AppError::internal(format!("parse short id map: {e}")))?;
+    Ok(Some(record.id))
+}
+
+/// Extensions a paste is stored under, with the content type each implies.
+/// A content type maps back to the first extension listing it.
+const PASTE_TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("json", "application/json"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("xml", "application/xml"),
+    ("csv", "text/csv"),
+    ("patch", "text/x-diff"),
+    ("diff", "text/x-diff"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("ts", "text/x-typescript"),
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("go", "text/x-go"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("java", "text/x-java"),
+    ("rb", "text/x-ruby"),
+    ("sql", "application/sql"),
+    ("sh", "application/x-sh"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+];
+
+/// Content types clients send without meaning anything by them: curl's
+/// default for `-d`, and plain text.
+fn is_generic_content_type(essence: &str) -> bool {
+    matches!(essence, "text/plain" | "application/x-www-form-urlencoded")
+}
+
+/// `text/*`, and the `application/*` types of [`PASTE_TYPES`], which are
+/// text too.
+fn is_text_content_type(content_type: &str) -> bool {
+    let essence = content_type_essence(content_type);
+    essence.starts_with("text/")
+        || PASTE_TYPES
+            .iter()
+            .any(|(_, known)| *known == essence && known.starts_with("application/"))
+}
+
+/// The lowercase `type/subtype` of a content type, with other spellings of
+/// the types in [`PASTE_TYPES`] mapped onto them.
+fn content_type_essence(content_type: &str) -> String {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let canonical = match essence.as_str() {
+        "text/x-markdown" => "text/markdown",
+        "text/yaml" | "text/x-yaml" | "application/x-yaml" => "application/yaml",
+        "text/json" => "application/json",
+        "text/xml" => "application/xml",
+        "text/x-patch" | "text/x-diff-patch" => "text/x-diff",
+        "application/javascript" | "application/x-javascript" => "text/javascript",
+        "text/x-shellscript" | "application/x-shellscript" | "text/x-sh" => "application/x-sh",
+        _ => return essence,
+    };
+    canonical.to_string()
+}
+
+/// The extension a paste is stored under: the upload name's, when it is one
+/// of [`PASTE_TYPES`], else the one the content type implies, else `txt`.
 pub fn choose_ext(name: Option<&str>, content_type: Option<&str>) -> &'static str {
-    let is_md_ct = content_type
-        .map(|v| v.to_ascii_lowercase().contains("text/markdown"))
-        .unwrap_or(false);
-    let is_md_name = name
-        .map(|n| n.to_ascii_lowercase().ends_with(".md"))
-        .unwrap_or(false);
-    if is_md_ct || is_md_name { "md" } else { "txt" }
+    let from_name = name
+        .and_then(|n| n.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .and_then(|ext| PASTE_TYPES.iter().find(|(known, _)| *known == ext));
+    let from_content_type = content_type.map(content_type_essence).and_then(|essence| {
+        PASTE_TYPES
+            .iter()
+            .find(|(_, content_type)| *content_type == essence)
+    });
+    from_name
+        .or(from_content_type)
+        .map_or("txt", |(ext, _)| ext)
+}
+
+/// The content type recorded for a paste stored as `ext`: the one the
+/// extension implies, with a UTF-8 charset on text. The client's own is kept
+/// when it spells out that very type (markdown aside, which always gets the
+/// charset), or names one no extension stands for on a `txt` paste.
+fn paste_content_type(ext: &str, content_type: Option<String>) -> String {
+    let implied = PASTE_TYPES
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map_or("text/plain", |(_, content_type)| content_type);
+    if let Some(content_type) = content_type.filter(|ct| {
+        let exact = ct.split(';').next().unwrap_or_default().trim();
+        (exact.eq_ignore_ascii_case(implied) && ext != "md")
+            || (ext == "txt" && !is_generic_content_type(&content_type_essence(ct)))
+    }) {
+        return content_type;
+    }
+    if implied.starts_with("image/") && implied != "image/svg+xml" {
+        implied.to_string()
+    } else {
+        format!("{implied}; charset=utf-8")
+    }
 }
 
 pub fn build_paste_draft(
     repo: &Path,
     cfg: &ServeCmd,
+    cipher: Option<&PasteCipher>,
     input: CreatePasteInput,
 ) -> AppResult<PasteDraft> {
     let id = Ulid::new().to_string();
@@ -166,22 +392,59 @@ pub fn build_paste_draft(
         .map_err(|e| AppError::internal(format!("date format failed: {e}")))?;
 
     let name = input.name.as_deref().unwrap_or("paste");
-    let slug = unique_slug(repo, &sanitize_slug_candidate(name)?);
-    let ext = choose_ext(input.name.as_deref(), input.content_type.as_deref());
+    let slug = match input.slug.as_deref() {
+        Some(custom) => reserve_custom_slug(repo, custom)?,
+        None => unique_slug(repo, &sanitize_slug_candidate(name)?),
+    };
+    // The link of an unlisted or private paste is its id; a short id would
+    // be a second, guessable one.
+    let short_id = input.visibility.is_listed().then(|| new_short_id(repo));
+    let ext = if input.e2e {
+        "bin"
+    } else {
+        choose_ext(input.name.as_deref(), input.content_type.as_deref())
+    };
     let file_name = format!("{id}__{slug}.{ext}");
-    let rel_path = format!("pastes/{date_path}/{file_name}");
-    let abs_path = repo.join(&rel_path);
 
-    let mut hasher = Sha256::new();
-    hasher.update(&input.bytes);
-    let sha256 = hex::encode(hasher.finalize());
+    // Client-side ciphertext is stored as is; sealing it again gains nothing.
+    let cipher = cipher.filter(|_| !input.e2e);
+    // Sealed pastes record the plaintext size but hash the stored bytes, so
+    // the committed meta does not fingerprint the secret.
+    let sealed = match (cipher, &input.body) {
+        (None, _) => None,
+        (Some(cipher), PasteBody::Bytes(bytes)) => Some((bytes.len(), cipher.seal(&id, bytes)?)),
+        (Some(cipher), PasteBody::Staged(staged)) => {
+            let plaintext =
+                fs::read(&staged.path).map_err(|e| AppError::io("read staged paste", e))?;
+            Some((plaintext.len(), cipher.seal(&id, &plaintext)?))
+        }
+    };
+    let (size, sha256) = match (&sealed, &input.body) {
+        (Some((size, stored)), _) => (*size, hex::encode(Sha256::digest(stored))),
+        (None, PasteBody::Bytes(bytes)) => (bytes.len(), hex::encode(Sha256::digest(bytes))),
+        (None, PasteBody::Staged(staged)) => (staged.size, staged.sha256.clone()),
+    };
+    // Compressed pastes keep the content's size and hash too; the file's go
+    // in `stored`.
+    let packed = match cfg.zstd_above {
+        Some(limit) if size > limit && sealed.is_none() && !input.e2e => match &input.body {
+            PasteBody::Bytes(bytes) => zst::compress(bytes)?,
+            PasteBody::Staged(staged) => zst::compress(
+                &fs::read(&staged.path).map_err(|e| AppError::io("read staged paste", e))?,
+            )?,
+        },
+        _ => None,
+    };
+    let rel_path = match packed {
+        Some(_) => format!("pastes/{date_path}/{file_name}{}", zst::SUFFIX),
+        None => format!("pastes/{date_path}/{file_name}"),
+    };
+    let abs_path = repo.join(&rel_path);
 
-    let content_type = if ext == "md" {
-        "text/markdown; charset=utf-8".to_string()
+    let content_type = if input.e2e {
+        "application/octet-stream".to_string()
     } else {
-        input
-            .content_type
-            .unwrap_or_else(|| "text/plain; charset=utf-8".to_string())
+        paste_content_type(ext, input.content_type)
     };
 
     let mut subject = format!("paste: {id} {slug}");
@@ -199,13 +462,23 @@ pub fn build_paste_draft(
         created_at,
         path: rel_path.clone(),
         slug: Some(slug.clone()),
-        size: input.bytes.len(),
+        short_id: short_id.clone(),
+        size,
         content_type: content_type.clone(),
         commit: String::new(),
         sha256: sha256.clone(),
         tag: input.tag,
         client_ip: input.client_ip,
         user_agent: input.user_agent,
+        encrypted: sealed.is_some() || input.e2e,
+        e2e: input.e2e,
+        visibility: input.visibility,
+        allow_keys: input.allow_keys,
+        supersedes: None,
+        superseded_by: None,
+        owner: input.owner,
+        stored: packed.as_ref().map(|(_, stored)| stored.clone()),
+        lang: input.lang,
     };
 
     if let Some(parent) = abs_path.parent() {
@@ -213,7 +486,15 @@ pub fn build_paste_draft(
     }
     fs::create_dir_all(repo.join("meta")).map_err(|e| AppError::io("create meta dir", e))?;
     fs::create_dir_all(repo.join("slugs")).map_err(|e| AppError::io("create slugs dir", e))?;
-    fs::write(&abs_path, &input.bytes).map_err(|e| AppError::io("write paste", e))?;
+    fs::create_dir_all(repo.join("short")).map_err(|e| AppError::io("create short dir", e))?;
+    match (&packed, &sealed, &input.body) {
+        (Some((stored, _)), _, _)
+        | (None, Some((_, stored)), _)
+        | (None, None, PasteBody::Bytes(stored)) => {
+            fs::write(&abs_path, stored).map_err(|e| AppError::io("write paste", e))?
+        }
+        (None, None, PasteBody::Staged(staged)) => move_staged(&staged.path, &abs_path)?,
+    }
     fs::write(
         &meta_path,
         serde_json::to_vec_pretty(&meta)
@@ -233,8 +514,23 @@ pub fn build_paste_draft(
             .map_err(|e| AppError::internal(format!("serialize slug map: {e}")))?,
     )
     .map_err(|e| AppError::io("write slug map", e))?;
+    let short_rel_path = short_id
+        .as_ref()
+        .map(|short_id| format!("short/{short_id}.json"));
+    let short_path = short_rel_path.as_ref().map(|rel| repo.join(rel));
+    if let (Some(short_id), Some(short_path)) = (&short_id, &short_path) {
+        let short_record = ShortIdRecord {
+            short_id: short_id.clone(),
+            id: id.clone(),
+        };
+        fs::write(
+            short_path,
+            serde_json::to_vec_pretty(&short_record)
+                .map_err(|e| AppError::internal(format!("serialize short id map: {e}")))?,
+        )
+        .map_err(|e| AppError::io("write short id map", e))?;
+    }
 
-    let _ = cfg;
     Ok(PasteDraft {
         id,
         slug,
@@ -244,15 +540,244 @@ pub fn build_paste_draft(
         meta_rel_path,
         slug_rel_path,
         slug_path,
+        short_id,
+        short_rel_path,
+        short_path,
         content_type,
-        size: input.bytes.len(),
+        size,
         sha256,
         subject,
         meta,
     })
 }
 
-fn lookup_commit(repo: &Path, cfg: &ServeCmd, rel_path: &str) -> AppResult<String> {
+/// Appends `bytes` to an existing text paste and rewrites its metadata. The
+/// caller must hold the git lock; the returned draft carries what is needed
+/// to commit or roll the append back.
+/// Swaps `path` for a file holding `bytes` in one rename, so readers see the
+/// old contents or the new ones, never a truncated file, and a crash midway
+/// leaves the old file whole.
+pub fn replace_file(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", Ulid::new()));
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+pub fn build_append_draft(
+    repo: &Path,
+    cfg: &ServeCmd,
+    cipher: Option<&PasteCipher>,
+    id: &str,
+    bytes: &[u8],
+) -> AppResult<AppendDraft> {
+    if !is_valid_paste_id(id) {
+        return Err(AppError::NotFound("paste not found".to_string()));
+    }
+    let meta_rel_path = format!("meta/{id}.json");
+    let meta_path = repo.join(&meta_rel_path);
+    let previous_meta = match fs::read(&meta_path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(AppError::NotFound("paste not found".to_string()));
+        }
+        Err(e) => return Err(AppError::io("read meta", e)),
+    };
+    let mut meta = serde_json::from_slice::<PasteMeta>(&previous_meta)
+        .map_err(|e| AppError::internal(format!("parse meta: {e}")))?;
+
+    if !is_text_content_type(&meta.content_type) {
+        return Err(AppError::BadRequest(
+            "append is only supported for text pastes".to_string(),
+        ));
+    }
+    if meta.size + bytes.len() > cfg.max_total_bytes {
+        return Err(AppError::TooLarge(
+            "append would exceed max-total-bytes".to_string(),
+        ));
+    }
+
+    let abs_path = repo.join(&meta.path);
+    if !meta.stored_as_is() {
+        // A sealed or compressed paste cannot grow in place: decode it,
+        // extend the content and encode the whole body again, sealed under a
+        // fresh nonce or compressed.
+        let previous_body = fs::read(&abs_path).map_err(|e| AppError::io("read paste", e))?;
+        let mut plaintext = unseal(cipher, &meta, previous_body.clone())?;
+        plaintext.extend_from_slice(bytes);
+        let stored = if meta.sealed_at_rest() {
+            let stored = require_cipher(cipher)?.seal(&meta.id, &plaintext)?;
+            meta.sha256 = hex::encode(Sha256::digest(&stored));
+            stored
+        } else {
+            let (stored, file) = zst::pack(&plaintext)?;
+            meta.sha256 = hex::encode(Sha256::digest(&plaintext));
+            meta.stored = Some(file);
+            stored
+        };
+        replace_file(&abs_path, &stored).map_err(|e| AppError::io("write paste", e))?;
+        meta.size = plaintext.len();
+        fs::write(
+            &meta_path,
+            serde_json::to_vec_pretty(&meta)
+                .map_err(|e| AppError::internal(format!("serialize meta: {e}")))?,
+        )
+        .map_err(|e| AppError::io("write meta", e))?;
+        return Ok(AppendDraft {
+            id: meta.id.clone(),
+            rel_path: meta.path.clone(),
+            abs_path,
+            meta_rel_path,
+            meta_path,
+            previous_len: previous_body.len() as u64,
+            previous_body: Some(previous_body),
+            previous_meta,
+            subject: format!("append: {id} (+{} bytes)", bytes.len()),
+            meta,
+        });
+    }
+
+    let previous_len = paste_len(repo, &meta)?;
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(&abs_path)
+        .map_err(|e| AppError::io("open paste for append", e))?;
+    file.write_all(bytes)
+        .map_err(|e| AppError::io("append paste", e))?;
+
+    let mut hasher = Sha256::new();
+    let mut reader = File::open(&abs_path).map_err(|e| AppError::io("open paste", e))?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0usize;
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| AppError::io("hash paste", e))?;
+        if n == 0 {
+            break;
+        }
+        size += n;
+        hasher.update(&buf[..n]);
+    }
+    meta.size = size;
+    meta.sha256 = hex::encode(hasher.finalize());
+    fs::write(
+        &meta_path,
+        serde_json::to_vec_pretty(&meta)
+            .map_err(|e| AppError::internal(format!("serialize meta: {e}")))?,
+    )
+    .map_err(|e| AppError::io("write meta", e))?;
+
+    Ok(AppendDraft {
+        id: meta.id.clone(),
+        rel_path: meta.path.clone(),
+        abs_path,
+        meta_rel_path,
+        meta_path,
+        previous_len,
+        previous_body: None,
+        previous_meta,
+        subject: format!("append: {id} (+{} bytes)", bytes.len()),
+        meta,
+    })
+}
+
+/// Records that paste `new_id` replaces `old_id`, or with `None` that it no
+/// longer replaces anything. Both metas are rewritten so each side links to
+/// the other; a link `new_id` had before is undone. The caller must hold the
+/// git lock and commit the draft (an empty draft means nothing changed).
+pub fn build_supersede_draft(
+    repo: &Path,
+    new_id: &str,
+    old_id: Option<&str>,
+) -> AppResult<MetaUpdateDraft> {
+    if old_id == Some(new_id) {
+        return Err(AppError::BadRequest(
+            "a paste cannot supersede itself".to_string(),
+        ));
+    }
+    let (mut new, new_raw) = read_raw_meta(repo, new_id)?
+        .ok_or_else(|| AppError::NotFound("paste not found".to_string()))?;
+    let mut changed: Vec<(PasteMeta, Vec<u8>)> = Vec::new();
+    if new.supersedes.as_deref() == old_id {
+        return Ok(MetaUpdateDraft {
+            files: Vec::new(),
+            subject: String::new(),
+        });
+    }
+
+    if let Some(old_id) = old_id {
+        let (mut old, old_raw) = read_raw_meta(repo, old_id)?
+            .ok_or_else(|| AppError::BadRequest(format!("supersedes: paste {old_id} not found")))?;
+        // Following what `old` replaces must never lead back to `new`.
+        let mut cursor = old.supersedes.clone();
+        for _ in 0..MAX_SUPERSEDE_CHAIN {
+            let Some(id) = cursor else { break };
+            if id == new.id {
+                return Err(AppError::BadRequest(
+                    "supersedes would create a cycle".to_string(),
+                ));
+            }
+            cursor = read_raw_meta(repo, &id)?.and_then(|(m, _)| m.supersedes);
+        }
+        old.superseded_by = Some(new.id.clone());
+        changed.push((old, old_raw));
+    }
+    if let Some(previous_id) = new.supersedes.take()
+        && let Some((mut previous, raw)) = read_raw_meta(repo, &previous_id)?
+        && previous.superseded_by.as_deref() == Some(new.id.as_str())
+    {
+        previous.superseded_by = None;
+        changed.push((previous, raw));
+    }
+    new.supersedes = old_id.map(str::to_string);
+    let subject = match old_id {
+        Some(old_id) => format!("supersede: {old_id} by {}", new.id),
+        None => format!("supersede: clear for {}", new.id),
+    };
+    changed.push((new, new_raw));
+
+    let mut files: Vec<MetaRewrite> = Vec::with_capacity(changed.len());
+    for (meta, previous) in changed {
+        let rel_path = format!("meta/{}.json", meta.id);
+        let abs_path = repo.join(&rel_path);
+        let written = serde_json::to_vec_pretty(&meta)
+            .map_err(|e| AppError::internal(format!("serialize meta: {e}")))
+            .and_then(|data| fs::write(&abs_path, data).map_err(|e| AppError::io("write meta", e)));
+        if let Err(err) = written {
+            for file in &files {
+                let _ = fs::write(&file.abs_path, &file.previous);
+            }
+            return Err(err);
+        }
+        files.push(MetaRewrite {
+            rel_path,
+            abs_path,
+            previous,
+        });
+    }
+    Ok(MetaUpdateDraft { files, subject })
+}
+
+/// Meta as stored (without the hydrated commit) plus its raw bytes.
+fn read_raw_meta(repo: &Path, id: &str) -> AppResult<Option<(PasteMeta, Vec<u8>)>> {
+    if !is_valid_paste_id(id) {
+        return Ok(None);
+    }
+    let raw = match fs::read(repo.join("meta").join(format!("{id}.json"))) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(AppError::io("read meta", e)),
+    };
+    let meta =
+        serde_json::from_slice(&raw).map_err(|e| AppError::internal(format!("parse meta: {e}")))?;
+    Ok(Some((meta, raw)))
+}
+
+/// The last commit that touched `rel_path`, abbreviated.
+pub fn lookup_commit(repo: &Path, cfg: &ServeCmd, rel_path: &str) -> AppResult<String> {
     let full = gitops::run_git(
         repo,
         &["log", "-n", "1", "--format=%H", "--", rel_path],
@@ -261,7 +786,7 @@ fn lookup_commit(repo: &Path, cfg: &ServeCmd, rel_path: &str) -> AppResult<Strin
     Ok(full.chars().take(12).collect())
 }
 
-fn is_valid_paste_id(id: &str) -> bool {
+pub(crate) fn is_valid_paste_id(id: &str) -> bool {
     id.len() == 26
         && id
             .chars()
@@ -276,6 +801,11 @@ fn hydrate_commit(repo: &Path, cfg: &ServeCmd, mut meta: PasteMeta) -> AppResult
 }
 
 pub fn read_meta(repo: &Path, cfg: &ServeCmd, id: &str) -> AppResult<PasteMeta> {
+    hydrate_commit(repo, cfg, read_stored_meta(repo, id)?)
+}
+
+/// The meta as stored, which normally has an empty `commit`.
+pub fn read_stored_meta(repo: &Path, id: &str) -> AppResult<PasteMeta> {
     if !is_valid_paste_id(id) {
         return Err(AppError::NotFound("paste not found".to_string()));
     }
@@ -284,64 +814,177 @@ pub fn read_meta(repo: &Path, cfg: &ServeCmd, id: &str) -> AppResult<PasteMeta>
         return Err(AppError::NotFound("paste not found".to_string()));
     }
     let data = fs::read(&path).map_err(|e| AppError::io("read meta", e))?;
-    let meta = serde_json::from_slice(&data)
-        .map_err(|e| AppError::internal(format!("parse meta: {e}")))?;
-    hydrate_commit(repo, cfg, meta)
+    serde_json::from_slice(&data).map_err(|e| AppError::internal(format!("parse meta: {e}")))
 }
 
-pub fn read_recent_with_tags(
+/// Reads the stored bytes of a paste; for sealed pastes this is ciphertext,
+/// which is what `sha256` covers, and for compressed ones zstd frames. Pass
+/// the result through [`unseal`].
+pub fn read_paste(repo: &Path, meta: &PasteMeta) -> AppResult<Vec<u8>> {
+    fs::read(repo.join(&meta.path)).map_err(|e| AppError::io("read paste", e))
+}
+
+/// Turns stored bytes into paste content, decrypting sealed pastes and
+/// decompressing compressed ones.
+pub fn unseal(
+    cipher: Option<&PasteCipher>,
+    meta: &PasteMeta,
+    stored: Vec<u8>,
+) -> AppResult<Vec<u8>> {
+    if meta.stored.is_some() {
+        return zst::decompress(&stored, meta.size);
+    }
+    if !meta.sealed_at_rest() {
+        return Ok(stored);
+    }
+    require_cipher(cipher)?.open(&meta.id, &stored)
+}
+
+fn require_cipher(cipher: Option<&PasteCipher>) -> AppResult<&PasteCipher> {
+    cipher.ok_or_else(|| {
+        AppError::ServiceUnavailable(
+            "paste is encrypted and no --encrypt-key-file is configured".to_string(),
+        )
+    })
+}
+
+/// Content length of a paste; sealed files are shorter by a fixed overhead,
+/// and compressed ones have it in the meta.
+pub fn paste_len(repo: &Path, meta: &PasteMeta) -> AppResult<u64> {
+    if meta.stored.is_some() {
+        return Ok(meta.size as u64);
+    }
+    let stored = fs::metadata(repo.join(&meta.path))
+        .map(|m| m.len())
+        .map_err(|e| AppError::io("stat paste", e))?;
+    Ok(if meta.sealed_at_rest() {
+        stored.saturating_sub(crypt::OVERHEAD)
+    } else {
+        stored
+    })
+}
+
+pub fn paste_modified(repo: &Path, meta: &PasteMeta) -> Option<std::time::SystemTime> {
+    fs::metadata(repo.join(&meta.path))
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+pub fn meta_modified(repo: &Path, id: &str) -> Option<std::time::SystemTime> {
+    fs::metadata(repo.join("meta").join(format!("{id}.json")))
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Reads `len` bytes starting at `start` without loading the whole paste
+/// (sealed and compressed pastes have to be decoded in full first).
+pub fn read_paste_range(
     repo: &Path,
-    cfg: &ServeCmd,
-    n: usize,
-    tag: Option<&str>,
-) -> AppResult<RecentWithTags> {
-    let meta_dir = repo.join("meta");
-    if !meta_dir.exists() {
-        return Ok((Vec::new(), Vec::new()));
-    }
-    let mut metas = Vec::new();
-    let mut counts = std::collections::HashMap::<String, usize>::new();
-    for entry in fs::read_dir(meta_dir).map_err(|e| AppError::io("read meta dir", e))? {
-        let entry = entry.map_err(|e| AppError::io("read meta entry", e))?;
-        let p = entry.path();
-        if p.extension().and_then(|s| s.to_str()) != Some("json") {
-            continue;
-        }
-        let data = fs::read(&p).map_err(|e| AppError::io("read meta file", e))?;
-        if let Ok(meta) = serde_json::from_slice::<PasteMeta>(&data) {
-            if let Some(t) = meta.tag.as_ref()
-                && !t.trim().is_empty()
-            {
-                *counts.entry(t.clone()).or_insert(0) += 1;
+    cipher: Option<&PasteCipher>,
+    meta: &PasteMeta,
+    start: u64,
+    len: u64,
+) -> AppResult<Vec<u8>> {
+    if !meta.stored_as_is() {
+        let plaintext = unseal(cipher, meta, read_paste(repo, meta)?)?;
+        let start = (start as usize).min(plaintext.len());
+        let end = start.saturating_add(len as usize).min(plaintext.len());
+        return Ok(plaintext[start..end].to_vec());
+    }
+    let mut file = File::open(repo.join(&meta.path)).map_err(|e| AppError::io("open paste", e))?;
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| AppError::io("seek paste", e))?;
+    let mut out = Vec::with_capacity(len as usize);
+    file.take(len)
+        .read_to_end(&mut out)
+        .map_err(|e| AppError::io("read paste range", e))?;
+    Ok(out)
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Returns the last `lines` lines of a paste, scanning backwards from the end
+/// in fixed-size chunks so large logs are never read in full.
+pub fn read_paste_tail(
+    repo: &Path,
+    cipher: Option<&PasteCipher>,
+    meta: &PasteMeta,
+    lines: usize,
+) -> AppResult<Vec<u8>> {
+    const CHUNK: u64 = 64 * 1024;
+    let mut file: Box<dyn ReadSeek> = if !meta.stored_as_is() {
+        let plaintext = unseal(cipher, meta, read_paste(repo, meta)?)?;
+        Box::new(std::io::Cursor::new(plaintext))
+    } else {
+        Box::new(File::open(repo.join(&meta.path)).map_err(|e| AppError::io("open paste", e))?)
+    };
+    let len = file
+        .seek(SeekFrom::End(0))
+        .map_err(|e| AppError::io("stat paste", e))?;
+    if lines == 0 || len == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut pos = len;
+    let mut buf: Vec<u8> = Vec::new();
+    let mut newlines = 0usize;
+    // A trailing newline terminates the last line rather than starting a new one.
+    let mut skip_trailing = true;
+    while pos > 0 {
+        let step = CHUNK.min(pos);
+        pos -= step;
+        file.seek(SeekFrom::Start(pos))
+            .map_err(|e| AppError::io("seek paste", e))?;
+        let mut chunk = vec![0u8; step as usize];
+        file.read_exact(&mut chunk)
+            .map_err(|e| AppError::io("read paste tail", e))?;
+        for idx in (0..chunk.len()).rev() {
+            if chunk[idx] != b'\n' {
+                skip_trailing = false;
+                continue;
             }
-            if let Some(expected) = tag
-                && meta.tag.as_deref() != Some(expected)
-            {
+            if skip_trailing {
+                skip_trailing = false;
                 continue;
             }
-            metas.push(hydrate_commit(repo, cfg, meta)?);
+            newlines += 1;
+            if newlines == lines {
+                chunk.drain(..=idx);
+                chunk.extend_from_slice(&buf);
+                return Ok(chunk);
+            }
         }
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
     }
-    metas.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    metas.truncate(n);
-
-    let mut tags: Vec<(String, usize)> = counts.into_iter().collect();
-    tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
-    Ok((metas, tags))
+    Ok(buf)
 }
 
-pub fn read_recent(
-    repo: &Path,
-    cfg: &ServeCmd,
-    n: usize,
-    tag: Option<&str>,
-) -> AppResult<Vec<PasteMeta>> {
-    let (metas, _) = read_recent_with_tags(repo, cfg, n, tag)?;
-    Ok(metas)
-}
-
-pub fn read_paste(repo: &Path, meta: &PasteMeta) -> AppResult<Vec<u8>> {
-    fs::read(repo.join(&meta.path)).map_err(|e| AppError::io("read paste", e))
+/// Checks the stored bytes of a paste against the hash recorded for them:
+/// `stored.sha256` for a compressed file, `sha256` otherwise.
+pub fn verify_integrity(meta: &PasteMeta, bytes: &[u8]) -> AppResult<()> {
+    let expected = meta
+        .stored
+        .as_ref()
+        .map_or(meta.sha256.as_str(), |stored| stored.sha256.as_str());
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    let actual = hex::encode(hasher.finalize());
+    if actual.as_bytes().ct_eq(expected.as_bytes()).into() {
+        return Ok(());
+    }
+    error!(
+        paste_id = %meta.id,
+        path = %meta.path,
+        expected = %expected,
+        actual = %actual,
+        "INTEGRITY FAILURE: stored paste bytes do not match recorded sha256 (bitrot or manual edit)"
+    );
+    Err(AppError::Integrity(format!(
+        "paste {} failed sha256 verification",
+        meta.id
+    )))
 }
 
 fn detect_image_type(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
@@ -473,6 +1116,7 @@ pub fn read_uploaded_file(paths: &AppPaths, file_name: &str) -> AppResult<(Vec<u
 
 pub fn slug_from_rel_path(rel_path: &str) -> Option<String> {
     let file_name = Path::new(rel_path).file_name()?.to_str()?;
+    let file_name = file_name.strip_suffix(zst::SUFFIX).unwrap_or(file_name);
     let stem = file_name
         .rsplit_once('.')
         .map(|(s, _)| s)
@@ -485,15 +1129,30 @@ pub fn slug_from_rel_path(rel_path: &str) -> Option<String> {
     }
 }
 
+/// File name offered to clients downloading a paste: the `<slug>.<ext>` part
+/// of the stored path, falling back to `<id>.<ext>`.
+pub fn download_file_name(meta: &PasteMeta) -> String {
+    let file_name = Path::new(meta.content_path())
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    match file_name.split_once("__") {
+        Some((_, rest)) if !rest.is_empty() => rest.to_string(),
+        _ => {
+            let ext = file_name.rsplit_once('.').map(|(_, e)| e).unwrap_or("bin");
+            format!("{}.{ext}", meta.id)
+        }
+    }
+}
+
+/// The paste a slug maps to. The slug is NFC-normalized first, as macOS
+/// sends decomposed accents.
 pub fn resolve_slug_id(repo: &Path, slug: &str) -> AppResult<Option<String>> {
-    if slug.is_empty()
-        || !slug
-            .chars()
-            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_'))
-    {
+    let slug: String = slug.nfc().collect();
+    if !is_slug(&slug) || slug.len() > MAX_SLUG_BYTES {
         return Ok(None);
     }
-    let path = slug_record_path(repo, slug);
+    let path = slug_record_path(repo, &slug);
     if !path.exists() {
         return Ok(None);
     }
@@ -503,73 +1162,239 @@ pub fn resolve_slug_id(repo: &Path, slug: &str) -> AppResult<Option<String>> {
     Ok(Some(record.id))
 }
 
+/// Gives a paste written by an older release the slug and (if listed) short
+/// id maps newer code expects, recording them in its meta. Returns the repo-relative
+/// paths written; empty when the paste was already complete.
+pub fn backfill_paste_maps(repo: &Path, id: &str) -> AppResult<Vec<String>> {
+    let Some((mut meta, _)) = read_raw_meta(repo, id)? else {
+        return Err(AppError::NotFound("paste not found".to_string()));
+    };
+    let mut written = Vec::new();
+    if meta.slug.is_none() {
+        let from_path = slug_from_rel_path(&meta.path).unwrap_or_else(|| "paste".to_string());
+        meta.slug = Some(unique_slug(repo, &sanitize_slug_candidate(&from_path)?));
+    }
+    // Slugs with characters the resolver rejects could never be looked up.
+    if let Some(slug) = meta.slug.clone()
+        && !slug.is_empty()
+        && is_slug(&slug)
+        && !slug_record_path(repo, &slug).exists()
+    {
+        let record = SlugRecord {
+            slug: slug.clone(),
+            id: meta.id.clone(),
+            created_at: meta.created_at,
+        };
+        fs::create_dir_all(repo.join("slugs")).map_err(|e| AppError::io("create slugs dir", e))?;
+        fs::write(
+            slug_record_path(repo, &slug),
+            serde_json::to_vec_pretty(&record)
+                .map_err(|e| AppError::internal(format!("serialize slug map: {e}")))?,
+        )
+        .map_err(|e| AppError::io("write slug map", e))?;
+        written.push(format!("slugs/{slug}.json"));
+    }
+    if meta.short_id.is_none() && meta.visibility.is_listed() {
+        let short_id = new_short_id(repo);
+        let record = ShortIdRecord {
+            short_id: short_id.clone(),
+            id: meta.id.clone(),
+        };
+        fs::create_dir_all(repo.join("short")).map_err(|e| AppError::io("create short dir", e))?;
+        fs::write(
+            short_record_path(repo, &short_id),
+            serde_json::to_vec_pretty(&record)
+                .map_err(|e| AppError::internal(format!("serialize short id map: {e}")))?,
+        )
+        .map_err(|e| AppError::io("write short id map", e))?;
+        written.push(format!("short/{short_id}.json"));
+        meta.short_id = Some(short_id);
+    }
+    if !written.is_empty() {
+        let meta_rel_path = format!("meta/{}.json", meta.id);
+        fs::write(
+            repo.join(&meta_rel_path),
+            serde_json::to_vec_pretty(&meta)
+                .map_err(|e| AppError::internal(format!("serialize meta: {e}")))?,
+        )
+        .map_err(|e| AppError::io("write meta", e))?;
+        written.push(meta_rel_path);
+    }
+    Ok(written)
+}
+
+/// Every readable paste meta in the repo, in no particular order; files
+/// that fail to parse are skipped.
+pub fn read_all_metas(repo: &Path) -> AppResult<Vec<PasteMeta>> {
+    let entries = match fs::read_dir(repo.join("meta")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(AppError::io("read meta dir", e)),
+    };
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| fs::read(entry.path()).ok())
+        .filter_map(|data| serde_json::from_slice::<PasteMeta>(&data).ok())
+        .collect())
+}
+
+/// Repo-relative paths of everything committed for a paste: body, meta, and
+/// the slug and short id maps that point at it.
+pub fn paste_files(repo: &Path, meta: &PasteMeta) -> AppResult<Vec<String>> {
+    let mut files = vec![meta.path.clone(), format!("meta/{}.json", meta.id)];
+    if let Some(slug) = meta.slug.as_deref()
+        && resolve_slug_id(repo, slug)?.as_deref() == Some(meta.id.as_str())
+    {
+        files.push(format!("slugs/{slug}.json"));
+    }
+    if let Some(short) = meta.short_id.as_deref()
+        && resolve_short_id(repo, short)?.as_deref() == Some(meta.id.as_str())
+    {
+        files.push(format!("short/{short}.json"));
+    }
+    Ok(files)
+}
+
 pub fn remove_files(paths: &[PathBuf]) {
     for path in paths {
         let _ = fs::remove_file(path);
     }
 }
 
-fn idempotency_record_path(idempotency_dir: &Path, key: &str) -> PathBuf {
-    let mut hasher = Sha256::new();
-    hasher.update(key.as_bytes());
-    let file = format!("{}.json", hex::encode(hasher.finalize()));
-    idempotency_dir.join(file)
+/// Moves a staged upload into the repo, copying when `tmp/` sits on another
+/// filesystem.
+fn move_staged(from: &Path, to: &Path) -> AppResult<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).map_err(|e| AppError::io("copy staged paste", e))?;
+    let _ = fs::remove_file(from);
+    Ok(())
 }
 
-pub fn idempotency_fingerprint(input: &CreatePasteInput) -> String {
+/// Starts the idempotency fingerprint for a create request; the caller feeds
+/// the body into the returned hasher as it arrives.
+pub fn idempotency_hasher(fields: &IdempotencyFields<'_>) -> Sha256 {
     let mut hasher = Sha256::new();
-    hasher.update(input.name.as_deref().unwrap_or_default().as_bytes());
-    hasher.update(b"\0");
-    hasher.update(input.msg.as_deref().unwrap_or_default().as_bytes());
-    hasher.update(b"\0");
-    hasher.update(input.tag.as_deref().unwrap_or_default().as_bytes());
-    hasher.update(b"\0");
-    hasher.update(input.content_type.as_deref().unwrap_or_default().as_bytes());
-    hasher.update(b"\0");
-    hasher.update(&input.bytes);
-    hex::encode(hasher.finalize())
-}
-
-pub fn read_idempotency_record(
-    idempotency_dir: &Path,
-    key: &str,
-) -> AppResult<Option<IdempotencyRecord>> {
-    let path = idempotency_record_path(idempotency_dir, key);
-    if !path.exists() {
-        return Ok(None);
+    for field in [fields.name, fields.msg, fields.tag, fields.content_type] {
+        hasher.update(field.unwrap_or_default().as_bytes());
+        hasher.update(b"\0");
     }
-    let data = fs::read(path).map_err(|e| AppError::io("read idempotency record", e))?;
-    let record = serde_json::from_slice::<IdempotencyRecord>(&data)
-        .map_err(|e| AppError::internal(format!("parse idempotency record: {e}")))?;
-    Ok(Some(record))
+    hasher.update([u8::from(fields.e2e)]);
+    // Newer options only add bytes when set, so fingerprints of plain
+    // creates stay what they were before those options existed.
+    match fields.visibility {
+        Visibility::Listed => {}
+        Visibility::Unlisted => hasher.update(b"unlisted"),
+        Visibility::Private => {
+            hasher.update(b"private");
+            for key in fields.allow_keys {
+                hasher.update(b"\0");
+                hasher.update(key.as_bytes());
+            }
+        }
+    }
+    if let Some(old) = fields.supersedes {
+        hasher.update(b"supersedes\0");
+        hasher.update(old.as_bytes());
+    }
+    if let Some(slug) = fields.slug {
+        hasher.update(b"slug\0");
+        hasher.update(slug.as_bytes());
+    }
+    if let Some(lang) = fields.lang {
+        hasher.update(b"lang\0");
+        hasher.update(lang.as_bytes());
+    }
+    hasher
 }
 
-pub fn write_idempotency_record(
-    idempotency_dir: &Path,
-    key: &str,
-    record: &IdempotencyRecord,
-) -> AppResult<()> {
-    fs::create_dir_all(idempotency_dir).map_err(|e| AppError::io("create idempotency dir", e))?;
-    let path = idempotency_record_path(idempotency_dir, key);
-    let bytes = serde_json::to_vec_pretty(record)
-        .map_err(|e| AppError::internal(format!("serialize idempotency record: {e}")))?;
-    fs::write(path, bytes).map_err(|e| AppError::io("write idempotency record", e))
+const IDEMPOTENCY_NS: &str = "idempotency";
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const LEASES_NS: &str = "leases";
+
+pub fn read_idempotency_record(kv: &Kv, key: &str) -> AppResult<Option<IdempotencyRecord>> {
+    kv.get(IDEMPOTENCY_NS, key)
+}
+
+pub fn write_idempotency_record(kv: &Kv, key: &str, record: &IdempotencyRecord) -> AppResult<()> {
+    kv.put(IDEMPOTENCY_NS, key, record, Some(IDEMPOTENCY_TTL))
+}
+
+fn locked_error(lease: &PasteLease) -> AppError {
+    let holder = lease.holder.as_deref().unwrap_or("another client");
+    AppError::Locked(format!(
+        "paste is locked by {holder} until {}",
+        lease.expires_at.unix_timestamp()
+    ))
+}
+
+/// Grants a new lease, or renews `presented` if it is the active one. Fails
+/// with `Locked` while someone else holds an unexpired lease.
+pub fn acquire_lease(
+    kv: &Kv,
+    id: &str,
+    presented: Option<&str>,
+    holder: Option<String>,
+    ttl: Duration,
+) -> AppResult<PasteLease> {
+    let lease = match kv.get::<PasteLease>(LEASES_NS, id)? {
+        Some(active) if presented.is_some_and(|p| lease_matches(&active, p)) => active.lease,
+        Some(active) => return Err(locked_error(&active)),
+        None => Ulid::new().to_string(),
+    };
+    let record = PasteLease {
+        id: id.to_string(),
+        lease,
+        holder,
+        expires_at: OffsetDateTime::now_utc() + ttl,
+    };
+    kv.put(LEASES_NS, id, &record, Some(ttl))?;
+    Ok(record)
+}
+
+pub fn release_lease(kv: &Kv, id: &str, presented: Option<&str>) -> AppResult<()> {
+    check_lease(kv, id, presented)?;
+    kv.delete(LEASES_NS, id)?;
+    Ok(())
+}
+
+/// Writers call this before mutating a paste: passes when no lease is active
+/// or when `presented` is the active lease.
+pub fn check_lease(kv: &Kv, id: &str, presented: Option<&str>) -> AppResult<()> {
+    match kv.get::<PasteLease>(LEASES_NS, id)? {
+        Some(active) if !presented.is_some_and(|p| lease_matches(&active, p)) => {
+            Err(locked_error(&active))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn lease_matches(active: &PasteLease, presented: &str) -> bool {
+    active.lease.as_bytes().ct_eq(presented.as_bytes()).into()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{PushMode, ServeCmd};
-    use crate::types::AppPaths;
+    use crate::config::ServeCmd;
+    use crate::types::{AppPaths, Visibility};
 
     const ONE_PX_PNG: &[u8] = &[
-        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48,
-        0x44, 0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x04, 0x00, 0x00,
-        0x00, 0xB5, 0x1C, 0x0C, 0x02, 0x00, 0x00, 0x00, 0x0B, 0x49, 0x44, 0x41, 0x54, 0x78,
-        0xDA, 0x63, 0xFC, 0x5F, 0x0F, 0x00, 0x02, 0x7F, 0x01, 0xF5, 0x90, 0xA1, 0x8D, 0xA5,
-        0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x04, 0x00, 0x00, 0x00, 0xB5,
+        0x1C, 0x0C, 0x02, 0x00, 0x00, 0x00, 0x0B, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0xFC,
+        0x5F, 0x0F, 0x00, 0x02, 0x7F, 0x01, 0xF5, 0x90, 0xA1, 0x8D, 0xA5, 0x00, 0x00, 0x00, 0x00,
+        0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
     ];
 
+    fn test_cfg(dir: &Path) -> ServeCmd {
+        let mut cfg = ServeCmd::offline(dir, "LAN Paste", "paste@lan").expect("serve defaults");
+        cfg.max_bytes = 1024;
+        cfg.min_free_bytes = 0;
+        cfg
+    }
+
     #[test]
     fn sanitize_ok() {
         assert_eq!(sanitize_name("my note.md").expect("sanitize"), "my-note.md");
@@ -578,6 +1403,23 @@ mod tests {
     #[test]
     fn sanitize_rejects_path() {
         assert!(sanitize_name("../a").is_err());
+        assert!(sanitize_name("．．／etc").is_err());
+    }
+
+    #[test]
+    fn slugs_keep_letters_of_any_script() {
+        let slug = |name: &str| sanitize_slug_candidate(name).expect("sanitize");
+        assert_eq!(slug("日本語 メモ.md"), "日本語-メモ");
+        assert_eq!(slug("Größe Übersicht.txt"), "größe-übersicht");
+        assert_eq!(slug("Cafe\u{301}.md"), slug("Café.md"));
+        assert_eq!(slug("ＲＥＡＤＭＥ.md"), "readme");
+        assert_eq!(slug("🚀 launch.md"), "launch");
+
+        let long = slug(&"长".repeat(300));
+        assert!(long.len() <= MAX_SLUG_BYTES);
+        assert_eq!(long.chars().count(), MAX_SLUG_BYTES / 3);
+        let long = slug(&"é".repeat(300));
+        assert_eq!(long.chars().count(), MAX_SLUG_LEN);
     }
 
     #[test]
@@ -585,6 +1427,51 @@ mod tests {
         assert_eq!(choose_ext(Some("a.md"), None), "md");
         assert_eq!(choose_ext(None, Some("text/markdown")), "md");
         assert_eq!(choose_ext(Some("a.txt"), Some("text/plain")), "txt");
+        assert_eq!(choose_ext(Some("Cargo.TOML"), None), "toml");
+        assert_eq!(choose_ext(Some("ci.yml"), Some("text/plain")), "yml");
+        assert_eq!(choose_ext(Some("fix.patch"), None), "patch");
+        assert_eq!(
+            choose_ext(None, Some("application/json; charset=utf-8")),
+            "json"
+        );
+        assert_eq!(choose_ext(None, Some("application/x-yaml")), "yaml");
+        assert_eq!(
+            choose_ext(Some("notes"), Some("application/octet-stream")),
+            "txt"
+        );
+        assert_eq!(choose_ext(Some("archive.tar.exe"), None), "txt");
+    }
+
+    #[test]
+    fn content_type_follows_the_extension() {
+        assert_eq!(
+            paste_content_type("json", Some("text/plain".to_string())),
+            "application/json; charset=utf-8"
+        );
+        assert_eq!(
+            paste_content_type("md", None),
+            "text/markdown; charset=utf-8"
+        );
+        assert_eq!(paste_content_type("png", None), "image/png");
+        assert_eq!(paste_content_type("txt", None), "text/plain; charset=utf-8");
+        assert_eq!(
+            paste_content_type("txt", Some("application/x-ndjson".to_string())),
+            "application/x-ndjson"
+        );
+        assert_eq!(
+            paste_content_type("txt", Some("application/x-www-form-urlencoded".to_string())),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            paste_content_type("json", Some("application/json".to_string())),
+            "application/json"
+        );
+        assert_eq!(
+            paste_content_type("txt", Some("application/octet-stream".to_string())),
+            "application/octet-stream"
+        );
+        assert!(is_text_content_type("application/x-yaml"));
+        assert!(!is_text_content_type("application/octet-stream"));
     }
 
     #[test]
@@ -598,6 +1485,34 @@ mod tests {
         let allow: Vec<ipnet::IpNet> = vec!["192.168.1.0/24".parse().expect("parse")];
         assert!(check_cidr(&allow, Some("192.168.1.8".parse().expect("parse"))).is_ok());
         assert!(check_cidr(&allow, Some("10.0.0.1".parse().expect("parse"))).is_err());
+        assert!(check_cidr(&allow, Some("::ffff:192.168.1.8".parse().expect("parse"))).is_ok());
+        assert!(check_cidr(&allow, Some("::ffff:10.0.0.1".parse().expect("parse"))).is_err());
+
+        let mapped: Vec<ipnet::IpNet> = vec!["::ffff:192.168.1.0/120".parse().expect("parse")];
+        assert!(check_cidr(&mapped, Some("192.168.1.8".parse().expect("parse"))).is_ok());
+
+        let private: Vec<ipnet::IpNet> = crate::config::PRIVATE_NETS
+            .iter()
+            .map(|n| n.parse().expect("parse"))
+            .collect();
+        for ok in [
+            "10.1.2.3",
+            "172.20.0.1",
+            "fd12::1",
+            "fe80::1",
+            "::ffff:192.168.0.9",
+        ] {
+            assert!(
+                check_cidr(&private, Some(ok.parse().expect("parse"))).is_ok(),
+                "{ok}"
+            );
+        }
+        for blocked in ["8.8.8.8", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(
+                check_cidr(&private, Some(blocked.parse().expect("parse"))).is_err(),
+                "{blocked}"
+            );
+        }
     }
 
     #[test]
@@ -605,29 +1520,25 @@ mod tests {
         let td = tempfile::tempdir().expect("tempdir");
         let repo = td.path().join("repo");
         std::fs::create_dir_all(&repo).expect("mkdir");
-        let cfg = ServeCmd {
-            dir: td.path().to_path_buf(),
-            bind: "127.0.0.1:0".parse().expect("bind"),
-            token: None,
-            api_keys_file: None,
-            max_bytes: 1024,
-            push: PushMode::Off,
-            remote: "origin".to_string(),
-            allow_cidr: vec![],
-            git_author_name: "LAN Paste".to_string(),
-            git_author_email: "paste@lan".to_string(),
-        };
+        let cfg = test_cfg(td.path());
         let draft = build_paste_draft(
             &repo,
             &cfg,
+            None,
             CreatePasteInput {
                 name: Some("n.md".to_string()),
                 msg: None,
                 tag: Some("t".to_string()),
                 content_type: Some("text/markdown".to_string()),
-                bytes: b"hello".to_vec(),
+                body: PasteBody::Bytes(b"hello".to_vec()),
+                e2e: false,
+                visibility: Visibility::Listed,
+                allow_keys: Vec::new(),
+                slug: None,
                 client_ip: None,
                 user_agent: None,
+                owner: None,
+                lang: None,
             },
         )
         .expect("draft");
@@ -639,6 +1550,164 @@ mod tests {
         assert!(draft.meta_path.exists());
         assert!(draft.slug_path.exists());
         assert_eq!(draft.slug, "n");
+
+        let short_id = draft.short_id.as_deref().expect("short id");
+        assert!(draft.short_path.as_ref().expect("short path").exists());
+        assert!(is_short_id(short_id));
+        assert_eq!(
+            resolve_short_id(&repo, short_id).expect("resolve"),
+            Some(draft.id.clone())
+        );
+        assert_eq!(resolve_short_id(&repo, "zzzzzzz").expect("resolve"), None);
+        assert_eq!(resolve_short_id(&repo, &draft.id).expect("resolve"), None);
+    }
+
+    #[test]
+    fn supersede_links_both_sides_and_rejects_cycles() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let repo = td.path();
+        std::fs::create_dir_all(repo.join("meta")).expect("meta dir");
+        let ids = [
+            "01AAAAAAAAAAAAAAAAAAAAAAAA",
+            "01BBBBBBBBBBBBBBBBBBBBBBBB",
+            "01CCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        for id in ids {
+            let meta = PasteMeta {
+                created_at: OffsetDateTime::now_utc(),
+                ..PasteMeta::for_test(id)
+            };
+            std::fs::write(
+                repo.join(format!("meta/{id}.json")),
+                serde_json::to_vec(&meta).expect("json"),
+            )
+            .expect("write meta");
+        }
+        let [a, b, c] = ids;
+        let meta = |id: &str| read_raw_meta(repo, id).expect("read").expect("exists").0;
+
+        let draft = build_supersede_draft(repo, b, Some(a)).expect("link");
+        assert_eq!(draft.files.len(), 2);
+        assert_eq!(meta(a).superseded_by.as_deref(), Some(b));
+        assert_eq!(meta(b).supersedes.as_deref(), Some(a));
+        assert!(
+            build_supersede_draft(repo, b, Some(a))
+                .expect("again")
+                .files
+                .is_empty()
+        );
+
+        // C replaces B; B replacing C would loop.
+        build_supersede_draft(repo, c, Some(b)).expect("chain");
+        assert!(matches!(
+            build_supersede_draft(repo, a, Some(c)),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            build_supersede_draft(repo, a, Some(a)),
+            Err(AppError::BadRequest(_))
+        ));
+
+        // Pointing B elsewhere (here: nowhere) releases A.
+        build_supersede_draft(repo, b, None).expect("clear");
+        assert_eq!(meta(a).superseded_by, None);
+        assert_eq!(meta(b).supersedes, None);
+        assert_eq!(meta(b).superseded_by.as_deref(), Some(c));
+    }
+
+    #[test]
+    fn verify_integrity_detects_mismatch() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let repo = td.path().join("repo");
+        std::fs::create_dir_all(&repo).expect("mkdir");
+        let cfg = test_cfg(td.path());
+        let draft = build_paste_draft(
+            &repo,
+            &cfg,
+            None,
+            CreatePasteInput {
+                name: Some("n.txt".to_string()),
+                msg: None,
+                tag: None,
+                content_type: None,
+                body: PasteBody::Bytes(b"hello".to_vec()),
+                e2e: false,
+                visibility: Visibility::Listed,
+                allow_keys: Vec::new(),
+                slug: None,
+                client_ip: None,
+                user_agent: None,
+                owner: None,
+                lang: None,
+            },
+        )
+        .expect("draft");
+        assert!(verify_integrity(&draft.meta, b"hello").is_ok());
+        let err = verify_integrity(&draft.meta, b"hellO").expect_err("mismatch");
+        assert!(matches!(err, AppError::Integrity(_)));
+    }
+
+    #[test]
+    fn replace_file_swaps_contents_without_leftovers() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let path = td.path().join("paste.txt");
+        std::fs::write(&path, b"old body").expect("write");
+        replace_file(&path, b"new").expect("replace");
+        assert_eq!(std::fs::read(&path).expect("read"), b"new");
+        let names: Vec<_> = std::fs::read_dir(td.path())
+            .expect("read dir")
+            .map(|entry| entry.expect("entry").file_name())
+            .collect();
+        assert_eq!(names, ["paste.txt"]);
+    }
+
+    #[test]
+    fn tail_and_range_read_without_full_load() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let repo = td.path().to_path_buf();
+        let body: String = (1..=500).map(|n| format!("line {n}\n")).collect();
+        std::fs::write(repo.join("log.txt"), &body).expect("write");
+        let meta = PasteMeta {
+            created_at: OffsetDateTime::now_utc(),
+            path: "log.txt".to_string(),
+            size: body.len(),
+            ..PasteMeta::for_test("01TEST")
+        };
+        let tail = read_paste_tail(&repo, None, &meta, 2).expect("tail");
+        assert_eq!(tail, b"line 499\nline 500\n");
+        let all = read_paste_tail(&repo, None, &meta, 10_000).expect("tail all");
+        assert_eq!(all, body.as_bytes());
+        let range = read_paste_range(&repo, None, &meta, 5, 3).expect("range");
+        assert_eq!(range, b"1\nl");
+    }
+
+    #[test]
+    fn lease_blocks_others_until_released_or_expired() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let dir = &Kv::open(td.path());
+        let ttl = Duration::from_secs(60);
+        let id = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+
+        let first = acquire_lease(dir, id, None, Some("alice".to_string()), ttl).expect("lease");
+        assert!(matches!(
+            acquire_lease(dir, id, None, None, ttl),
+            Err(AppError::Locked(_))
+        ));
+        assert!(matches!(
+            check_lease(dir, id, Some("wrong")),
+            Err(AppError::Locked(_))
+        ));
+        check_lease(dir, id, Some(&first.lease)).expect("holder may write");
+
+        let renewed = acquire_lease(dir, id, Some(&first.lease), None, ttl).expect("renew");
+        assert_eq!(renewed.lease, first.lease);
+        assert!(renewed.expires_at >= first.expires_at);
+
+        release_lease(dir, id, Some(&first.lease)).expect("release");
+        check_lease(dir, id, None).expect("unlocked");
+
+        acquire_lease(dir, id, None, None, Duration::ZERO).expect("short lease");
+        check_lease(dir, id, None).expect("expired lease is ignored");
     }
 
     #[test]
@@ -652,18 +1721,7 @@ mod tests {
         let td = tempfile::tempdir().expect("tempdir");
         let repo = td.path().join("repo");
         std::fs::create_dir_all(&repo).expect("mkdir");
-        let cfg = ServeCmd {
-            dir: td.path().to_path_buf(),
-            bind: "127.0.0.1:0".parse().expect("bind"),
-            token: None,
-            api_keys_file: None,
-            max_bytes: 1024,
-            push: PushMode::Off,
-            remote: "origin".to_string(),
-            allow_cidr: vec![],
-            git_author_name: "LAN Paste".to_string(),
-            git_author_email: "paste@lan".to_string(),
-        };
+        let cfg = test_cfg(td.path());
         let err = read_meta(&repo, &cfg, "../meta/01KHA55MQ0NRF2FGCSR0A1H3B5")
             .expect_err("invalid id should fail");
         assert!(matches!(err, AppError::NotFound(_)));
@@ -708,52 +1766,78 @@ mod tests {
         let td = tempfile::tempdir().expect("tempdir");
         let repo = td.path().join("repo");
         std::fs::create_dir_all(&repo).expect("mkdir");
-        let cfg = ServeCmd {
-            dir: td.path().to_path_buf(),
-            bind: "127.0.0.1:0".parse().expect("bind"),
-            token: None,
-            api_keys_file: None,
-            max_bytes: 1024,
-            push: PushMode::Off,
-            remote: "origin".to_string(),
-            allow_cidr: vec![],
-            git_author_name: "LAN Paste".to_string(),
-            git_author_email: "paste@lan".to_string(),
-        };
+        let cfg = test_cfg(td.path());
 
         let first = build_paste_draft(
             &repo,
             &cfg,
+            None,
             CreatePasteInput {
                 name: Some("brief-2026-03-03.md".to_string()),
                 msg: None,
                 tag: None,
                 content_type: Some("text/markdown".to_string()),
-                bytes: b"one".to_vec(),
+                body: PasteBody::Bytes(b"one".to_vec()),
+                e2e: false,
+                visibility: Visibility::Listed,
+                allow_keys: Vec::new(),
+                slug: None,
                 client_ip: None,
                 user_agent: None,
+                owner: None,
+                lang: None,
             },
         )
         .expect("first");
         let second = build_paste_draft(
             &repo,
             &cfg,
+            None,
             CreatePasteInput {
                 name: Some("brief-2026-03-03.md".to_string()),
                 msg: None,
                 tag: None,
                 content_type: Some("text/markdown".to_string()),
-                bytes: b"two".to_vec(),
+                body: PasteBody::Bytes(b"two".to_vec()),
+                e2e: false,
+                visibility: Visibility::Listed,
+                allow_keys: Vec::new(),
+                slug: None,
                 client_ip: None,
                 user_agent: None,
+                owner: None,
+                lang: None,
             },
         )
         .expect("second");
 
         assert_eq!(first.slug, "brief-2026-03-03");
         assert_eq!(second.slug, "brief-2026-03-03-2");
-        let mapped =
-            resolve_slug_id(&repo, "brief-2026-03-03-2").expect("resolve").expect("id");
+        let mapped = resolve_slug_id(&repo, "brief-2026-03-03-2")
+            .expect("resolve")
+            .expect("id");
         assert_eq!(mapped, second.id);
+
+        assert!(matches!(
+            reserve_custom_slug(&repo, "brief-2026-03-03"),
+            Err(AppError::Conflict(_))
+        ));
+        for bad in ["", "Weekly", "-x", "a/b", "a.md", "Über", "cafe\u{301}"] {
+            assert!(
+                matches!(
+                    reserve_custom_slug(&repo, bad),
+                    Err(AppError::BadRequest(_))
+                ),
+                "{bad}"
+            );
+        }
+        assert_eq!(
+            reserve_custom_slug(&repo, "weekly_standup-2").expect("free"),
+            "weekly_standup-2"
+        );
+        assert_eq!(
+            reserve_custom_slug(&repo, "über-notizen").expect("free"),
+            "über-notizen"
+        );
     }
 }
@@ -0,0 +1,166 @@
+//! The service manager protocols systemd defines, which other supervisors
+//! speak too.
+//!
+//! Socket activation: under `LISTEN_FDS`, the manager binds the sockets
+//! itself and hands them over from fd 3 up. It can then bind port 80 for an
+//! unprivileged service and start lanpaste on the first connection.
+//!
+//! Readiness: with `NOTIFY_SOCKET` set (`Type=notify`), the server sends
+//! `READY=1` once it is listening and `STOPPING=1` when it begins to shut
+//! down.
+
+use std::net::TcpListener;
+
+use tracing::{debug, info};
+
+use crate::errors::{AppError, AppResult};
+
+/// First fd the service manager passes (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: i32 = 3;
+
+/// Listening sockets passed to this process, in order; empty when it was
+/// not socket activated. They are non-blocking and closed on exec.
+pub fn listen_fds() -> AppResult<Vec<TcpListener>> {
+    let count = activated_count(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )?;
+    if count > 0 {
+        info!("socket activated with {count} listener(s); --bind is ignored");
+    }
+    (0..count as i32)
+        .map(|n| adopt(LISTEN_FDS_START + n))
+        .collect()
+}
+
+/// The variables count only when `LISTEN_PID` is this process: children
+/// inherit them, and must not take the sockets for theirs.
+fn activated_count(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    pid: u32,
+) -> AppResult<usize> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(0);
+    };
+    if listen_pid.trim().parse::<u32>().ok() != Some(pid) {
+        return Ok(0);
+    }
+    listen_fds
+        .trim()
+        .parse()
+        .map_err(|_| AppError::internal(format!("LISTEN_FDS is not a count: '{listen_fds}'")))
+}
+
+#[cfg(unix)]
+fn adopt(fd: i32) -> AppResult<TcpListener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // SAFETY: the service manager passed this fd to this process (checked
+    // through LISTEN_PID) for it to own, and nothing else here uses it.
+    let socket = socket2::Socket::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    let not_tcp = || {
+        AppError::internal(format!(
+            "socket activation: fd {fd} is not a TCP socket; use ListenStream= with a port or address"
+        ))
+    };
+    let is_stream = socket.r#type().map_err(|_| not_tcp())? == socket2::Type::STREAM;
+    let is_inet = socket
+        .local_addr()
+        .map_err(|_| not_tcp())?
+        .as_socket()
+        .is_some();
+    if !is_stream || !is_inet {
+        return Err(not_tcp());
+    }
+    // The git processes spawned for each write must not inherit it.
+    socket
+        .set_cloexec(true)
+        .and_then(|_| socket.set_nonblocking(true))
+        .map_err(|e| AppError::io("prepare activated socket", e))?;
+    Ok(socket.into())
+}
+
+#[cfg(not(unix))]
+fn adopt(_fd: i32) -> AppResult<TcpListener> {
+    Err(AppError::internal(
+        "socket activation is only supported on Unix",
+    ))
+}
+
+/// Sends `state` (such as `READY=1`) to the service manager. Does nothing
+/// without `NOTIFY_SOCKET`; a failed send is logged and otherwise ignored,
+/// as the manager then times the service out on its own.
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send_notify(&socket.to_string_lossy(), state) {
+        debug!("sd_notify {state:?} to {}: {err}", socket.to_string_lossy());
+    }
+}
+
+#[cfg(unix)]
+fn send_notify(socket: &str, state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    // `@name` is a Linux abstract socket.
+    let addr = match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)?
+        }
+        _ => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_notify(_socket: &str, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_variables_meant_for_this_process_count() {
+        assert_eq!(activated_count(None, None, 42).unwrap(), 0);
+        assert_eq!(activated_count(Some("42"), Some("2"), 42).unwrap(), 2);
+        assert_eq!(activated_count(Some("41"), Some("2"), 42).unwrap(), 0);
+        assert_eq!(activated_count(Some("42"), None, 42).unwrap(), 0);
+        assert!(activated_count(Some("42"), Some("two"), 42).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn adopted_sockets_accept_connections() {
+        use std::os::fd::IntoRawFd;
+
+        let bound = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = bound.local_addr().expect("addr");
+        let listener = adopt(bound.into_raw_fd()).expect("adopt");
+        assert_eq!(listener.local_addr().expect("addr"), addr);
+        std::net::TcpStream::connect(addr).expect("connect");
+
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").expect("udp");
+        assert!(adopt(udp.into_raw_fd()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notify_sends_one_datagram_per_state() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("notify.sock");
+        let manager = std::os::unix::net::UnixDatagram::bind(&path).expect("bind");
+        send_notify(path.to_str().expect("utf-8"), "READY=1").expect("send");
+        let mut buf = [0u8; 64];
+        let n = manager.recv(&mut buf).expect("recv");
+        assert_eq!(&buf[..n], b"READY=1");
+        assert!(send_notify("/nonexistent/notify.sock", "READY=1").is_err());
+    }
+}
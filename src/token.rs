@@ -0,0 +1,365 @@
+//! The shared tokens: each `--token` and each line of `--token-file`, so
+//! teams and scripts can have their own without an API key file. Any of
+//! them is accepted wherever the token is; dropping a line from the file
+//! and reloading (`SIGHUP` or `POST /api/v1/admin/reload`) revokes it.
+//!
+//! `POST /api/v1/admin/token/rotate` (or `lanpaste admin rotate-token`)
+//! replaces one of them while serving. The token it replaces keeps working
+//! for a grace window, so the scripts using it can move to the new one one
+//! at a time instead of all at once.
+//!
+//! A rotation is kept in the kv store and outlives a restart, as long as
+//! the token it started from is still configured. Dropping that token drops
+//! the rotation: the operator picked the tokens on purpose.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::{
+    auth,
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    kv::Kv,
+    store,
+};
+
+/// How long the replaced token keeps working when a rotation names no
+/// grace window.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(3600);
+
+const TOKEN_NS: &str = "token";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Rotation {
+    /// sha256 of the configured token the first rotation replaced.
+    flag_sha256: String,
+    token: String,
+    previous: String,
+    previous_until: OffsetDateTime,
+}
+
+/// What a rotation hands back: the token to use from now on, and until when
+/// the old one is still accepted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rotated {
+    pub token: String,
+    pub previous_valid_until: OffsetDateTime,
+}
+
+/// One configured token, as rotated since.
+#[derive(Debug, Clone)]
+struct Slot {
+    configured: String,
+    rotation: Option<Rotation>,
+}
+
+impl Slot {
+    fn current(&self) -> &str {
+        self.rotation
+            .as_ref()
+            .map_or(&self.configured, |r| &r.token)
+    }
+
+    fn accepts(&self, provided: &str) -> bool {
+        let matches = |token: &str| store::verify_token(Some(token), Some(provided)).is_ok();
+        match &self.rotation {
+            None => matches(&self.configured),
+            Some(r) => {
+                matches(&r.token)
+                    || (OffsetDateTime::now_utc() < r.previous_until && matches(&r.previous))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SharedToken {
+    flags: Vec<String>,
+    file: Option<PathBuf>,
+    slots: RwLock<Vec<Slot>>,
+    kv: Option<Kv>,
+}
+
+impl SharedToken {
+    /// Tokens from the command line, which nothing has rotated yet.
+    pub fn new(flags: Vec<String>) -> Self {
+        let slots = flags
+            .iter()
+            .map(|configured| Slot {
+                configured: configured.clone(),
+                rotation: None,
+            })
+            .collect();
+        Self {
+            flags,
+            file: None,
+            slots: RwLock::new(slots),
+            kv: None,
+        }
+    }
+
+    /// `--token` and `--token-file`.
+    pub fn from_config(cfg: &ServeCmd) -> AppResult<Self> {
+        let mut token = Self::new(cfg.token.clone());
+        if let Some(path) = &cfg.token_file {
+            token.file = Some(path.clone());
+            token.reload()?;
+        }
+        Ok(token)
+    }
+
+    /// Keeps rotations in `kv`, picking up the ones an earlier run made
+    /// from tokens that are still configured.
+    pub fn persisted(mut self, kv: Kv) -> AppResult<Self> {
+        self.kv = Some(kv);
+        // Start from what the store has, not from unrotated slots.
+        self.slots = RwLock::new(Vec::new());
+        let configured = self.configured()?;
+        self.apply(configured)?;
+        Ok(self)
+    }
+
+    /// Re-reads `--token-file`; on error the current tokens stay in effect.
+    /// Returns how many tokens are configured.
+    pub fn reload(&self) -> AppResult<usize> {
+        let configured = self.configured()?;
+        let count = configured.len();
+        self.apply(configured)?;
+        Ok(count)
+    }
+
+    /// Whether the server has tokens at all. A `--token-file` counts even
+    /// when it lists none, so emptying it locks writers out rather than
+    /// opening the server.
+    pub fn is_set(&self) -> bool {
+        !self.flags.is_empty() || self.file.is_some()
+    }
+
+    /// Like [`store::verify_token`], accepting any configured token in
+    /// effect, or one a rotation replaced while its grace window is open.
+    pub fn verify(&self, provided: Option<&str>) -> AppResult<()> {
+        if !self.is_set() {
+            return Ok(());
+        }
+        let slots = self
+            .slots
+            .read()
+            .map_err(|_| AppError::internal("token lock poisoned"))?;
+        let provided = provided.unwrap_or_default();
+        if slots.iter().any(|slot| slot.accepts(provided)) {
+            auth::mark_verified();
+            return Ok(());
+        }
+        Err(AppError::Unauthorized(
+            "missing or invalid token".to_string(),
+        ))
+    }
+
+    /// Replaces the token `target` names (one in effect, or one still in
+    /// its grace window) with `new`, or a random one, and keeps accepting
+    /// the old one for `grace`. `target` may be left out when there is only
+    /// one token.
+    pub fn rotate(
+        &self,
+        target: Option<&str>,
+        new: Option<String>,
+        grace: Duration,
+    ) -> AppResult<Rotated> {
+        if !self.is_set() {
+            return Err(AppError::Conflict(
+                "the server has no --token to rotate".to_string(),
+            ));
+        }
+        let token = match new.map(|t| t.trim().to_string()) {
+            Some(t) if t.is_empty() => {
+                return Err(AppError::BadRequest("new token is empty".to_string()));
+            }
+            Some(t) => t,
+            None => auth::random_token(24),
+        };
+        let mut slots = self
+            .slots
+            .write()
+            .map_err(|_| AppError::internal("token lock poisoned"))?;
+        if slots.iter().any(|slot| slot.accepts(&token)) {
+            return Err(AppError::BadRequest(
+                "new token is already accepted".to_string(),
+            ));
+        }
+        let slot = match target {
+            Some(target) => slots
+                .iter_mut()
+                .find(|slot| slot.accepts(target))
+                .ok_or_else(|| AppError::NotFound("no such token".to_string()))?,
+            None if slots.len() == 1 => &mut slots[0],
+            None => {
+                return Err(AppError::BadRequest(
+                    "the server has several tokens; name the one to replace".to_string(),
+                ));
+            }
+        };
+        let next = Rotation {
+            flag_sha256: sha256(&slot.configured),
+            token,
+            previous: slot.current().to_string(),
+            previous_until: OffsetDateTime::now_utc() + grace,
+        };
+        if let Some(kv) = &self.kv {
+            kv.put(TOKEN_NS, &next.flag_sha256, &next, None)?;
+        }
+        let rotated = Rotated {
+            token: next.token.clone(),
+            previous_valid_until: next.previous_until,
+        };
+        slot.rotation = Some(next);
+        Ok(rotated)
+    }
+
+    /// `--token` values followed by the lines of `--token-file`.
+    fn configured(&self) -> AppResult<Vec<String>> {
+        let mut tokens = self.flags.clone();
+        if let Some(path) = &self.file {
+            tokens.extend(read_file(path)?);
+        }
+        let mut seen = HashSet::new();
+        tokens.retain(|token| seen.insert(token.clone()));
+        Ok(tokens)
+    }
+
+    /// Makes `configured` the token set, keeping the rotations of tokens
+    /// that stay and forgetting the rest.
+    fn apply(&self, configured: Vec<String>) -> AppResult<()> {
+        let mut slots = self
+            .slots
+            .write()
+            .map_err(|_| AppError::internal("token lock poisoned"))?;
+        let mut next = Vec::with_capacity(configured.len());
+        for token in configured {
+            let rotation = match slots.iter().find(|slot| slot.configured == token) {
+                Some(slot) => slot.rotation.clone(),
+                None => match &self.kv {
+                    Some(kv) => kv.get::<Rotation>(TOKEN_NS, &sha256(&token))?,
+                    None => None,
+                },
+            };
+            next.push(Slot {
+                configured: token,
+                rotation,
+            });
+        }
+        if let Some(kv) = &self.kv {
+            for stale in kv.list::<Rotation>(TOKEN_NS)? {
+                if !next
+                    .iter()
+                    .any(|slot| sha256(&slot.configured) == stale.flag_sha256)
+                {
+                    kv.delete(TOKEN_NS, &stale.flag_sha256)?;
+                }
+            }
+        }
+        *slots = next;
+        Ok(())
+    }
+}
+
+/// One token per line; blank lines and `#` comments are skipped.
+fn read_file(path: &Path) -> AppResult<Vec<String>> {
+    let raw = fs::read_to_string(path).map_err(|e| AppError::io("read token file", e))?;
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+fn sha256(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_token_works_until_the_grace_window_closes() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let kv = Kv::open(td.path().join("kv"));
+        let token = SharedToken::new(vec!["old".to_string()])
+            .persisted(kv.clone())
+            .expect("token");
+        let rotated = token
+            .rotate(None, Some("new".to_string()), DEFAULT_GRACE)
+            .expect("rotate");
+        assert_eq!(rotated.token, "new");
+        assert!(token.verify(Some("new")).is_ok());
+        assert!(token.verify(Some("old")).is_ok());
+        assert!(token.verify(Some("other")).is_err());
+
+        token
+            .rotate(Some("new"), Some("newer".to_string()), Duration::ZERO)
+            .expect("rotate");
+        assert!(token.verify(Some("new")).is_err());
+        assert!(token.verify(Some("old")).is_err());
+
+        let restarted = SharedToken::new(vec!["old".to_string()])
+            .persisted(kv.clone())
+            .expect("token");
+        assert!(restarted.verify(Some("newer")).is_ok());
+        assert!(restarted.verify(Some("old")).is_err());
+
+        let reset = SharedToken::new(vec!["picked".to_string()])
+            .persisted(kv)
+            .expect("token");
+        assert!(reset.verify(Some("picked")).is_ok());
+        assert!(reset.verify(Some("newer")).is_err());
+    }
+
+    #[test]
+    fn any_configured_token_works_and_reload_revokes() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let file = td.path().join("tokens");
+        fs::write(&file, "# ci\nci-token\n\nbackup-token\n").expect("write");
+        let mut token = SharedToken::new(vec!["ops-token".to_string()]);
+        token.file = Some(file.clone());
+        assert_eq!(token.reload().expect("load"), 3);
+        for accepted in ["ops-token", "ci-token", "backup-token"] {
+            assert!(token.verify(Some(accepted)).is_ok(), "{accepted}");
+        }
+        assert!(token.verify(Some("# ci")).is_err());
+        assert!(matches!(
+            token.rotate(None, None, DEFAULT_GRACE),
+            Err(AppError::BadRequest(_))
+        ));
+        token
+            .rotate(Some("ci-token"), Some("ci-2".to_string()), DEFAULT_GRACE)
+            .expect("rotate");
+
+        fs::write(&file, "ci-token\n").expect("write");
+        assert_eq!(token.reload().expect("reload"), 2);
+        assert!(token.verify(Some("backup-token")).is_err());
+        assert!(token.verify(Some("ci-2")).is_ok(), "rotation kept");
+
+        fs::write(&file, "").expect("write");
+        token.flags.clear();
+        token.reload().expect("reload");
+        assert!(token.verify(None).is_err(), "an empty file is not open");
+    }
+
+    #[test]
+    fn nothing_to_rotate_without_a_token() {
+        assert!(matches!(
+            SharedToken::default().rotate(None, None, DEFAULT_GRACE),
+            Err(AppError::Conflict(_))
+        ));
+    }
+}
@@ -1,10 +1,33 @@
 use serde::{Deserialize, Serialize};
-use std::{fs::File, net::IpAddr, path::PathBuf, sync::Arc};
+use std::{
+    fs::File,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, atomic::AtomicU64},
+};
 use time::OffsetDateTime;
 
 use crate::{
+    admin::Ops,
     auth::ApiKeyStore,
+    authlog::AuthLog,
+    backup::S3Target,
+    chat::Chat,
+    committer::Committer,
     config::{PushMode, ServeCmd},
+    crypt::PasteCipher,
+    events::Events,
+    hostrules::HostRules,
+    index::MetaIndex,
+    kv::Kv,
+    notify::Notifier,
+    ratelimit::Limits,
+    readiness::RemoteProbe,
+    sandbox::RenderPool,
+    selftest::SelfTestReport,
+    settings::Settings,
+    token::SharedToken,
+    webhooks::Webhooks,
 };
 
 #[derive(Clone)]
@@ -13,6 +36,28 @@ pub struct AppState {
     pub paths: AppPaths,
     pub _daemon_lock: Arc<File>,
     pub api_keys: ApiKeyStore,
+    /// `--token`, as rotated since.
+    pub token: Arc<SharedToken>,
+    pub auth_log: AuthLog,
+    pub cipher: Option<PasteCipher>,
+    pub host_rules: HostRules,
+    pub read_counter: Arc<AtomicU64>,
+    pub kv: Kv,
+    pub self_test: Arc<Mutex<Option<SelfTestReport>>>,
+    pub ops: Arc<Ops>,
+    pub notifier: Notifier,
+    pub renders: Arc<RenderPool>,
+    pub backup: Option<S3Target>,
+    pub index: Arc<MetaIndex>,
+    pub events: Events,
+    pub webhooks: Option<Webhooks>,
+    pub chat: Option<Chat>,
+    pub settings: Arc<Settings>,
+    pub limits: Arc<Limits>,
+    pub remote_probe: Arc<RemoteProbe>,
+    pub committer: Arc<Committer>,
+    /// `--namespace` repos by name, each served under `/ns/{name}/`.
+    pub namespaces: Vec<(String, Arc<AppState>)>,
 }
 
 #[derive(Clone, Debug)]
@@ -24,7 +69,7 @@ pub struct AppPaths {
     pub files: PathBuf,
     pub files_meta: PathBuf,
     pub git_lock: PathBuf,
-    pub idempotency: PathBuf,
+    pub kv: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +79,10 @@ pub struct PasteMeta {
     pub path: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub slug: Option<String>,
+    /// Seven-character base58 alias of a listed paste; unlisted and private
+    /// pastes, and pastes from before short ids, have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_id: Option<String>,
     pub size: usize,
     pub content_type: String,
     pub commit: String,
@@ -44,16 +93,219 @@ pub struct PasteMeta {
     pub client_ip: Option<IpAddr>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_agent: Option<String>,
+    /// Stored sealed with `--encrypt-key-file`; `sha256` covers the ciphertext.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    /// Encrypted by the client before upload (implies `encrypted`). The
+    /// server only ever holds ciphertext and never has the key.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub e2e: bool,
+    #[serde(default, skip_serializing_if = "Visibility::is_listed")]
+    pub visibility: Visibility,
+    /// Names of the API keys that may read a private paste.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_keys: Vec<String>,
+    /// Id of the older paste this one replaces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supersedes: Option<String>,
+    /// Id of the newer paste that replaces this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded_by: Option<String>,
+    /// Name of the API key that created the paste; unset without a key
+    /// file, since a shared token names nobody.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Set when the repo file is compressed (`--zstd-above`); `size` and
+    /// `sha256` still describe the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored: Option<StoredFile>,
+    /// Language the uploader named for highlighting, e.g. `rust`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+/// The repo file of a paste kept compressed at rest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub encoding: StoredEncoding,
+    /// Size of the file in the repo.
+    pub size: usize,
+    /// Hash of the file in the repo, which integrity checks compare.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoredEncoding {
+    Zstd,
+}
+
+/// Whether a paste shows up in recents and the dashboard. Unlisted pastes
+/// are still served to anyone who has the id; private ones only to the API
+/// keys named in the paste's `allow_keys`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Listed,
+    Unlisted,
+    Private,
+}
+
+impl Visibility {
+    pub fn is_listed(&self) -> bool {
+        *self == Visibility::Listed
+    }
+}
+
+impl PasteMeta {
+    /// Whether the stored bytes are sealed with the server's own key.
+    pub fn sealed_at_rest(&self) -> bool {
+        self.encrypted && !self.e2e
+    }
+
+    /// Whether the repo file holds the content byte for byte, so it can be
+    /// streamed and sliced without decoding it first.
+    pub fn stored_as_is(&self) -> bool {
+        !self.sealed_at_rest() && self.stored.is_none()
+    }
+
+    /// `path` without the `.zst` of a compressed file: the name the content
+    /// goes by.
+    pub fn content_path(&self) -> &str {
+        match self.stored {
+            Some(_) => self.path.strip_suffix(".zst").unwrap_or(&self.path),
+            None => &self.path,
+        }
+    }
+
+    /// A listed, empty `text/plain` paste at the Unix epoch, for tests and
+    /// benches to fill in the fields they care about.
+    #[doc(hidden)]
+    pub fn for_test(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            path: format!("pastes/{id}.txt"),
+            slug: None,
+            short_id: None,
+            size: 0,
+            content_type: "text/plain".to_string(),
+            commit: String::new(),
+            sha256: String::new(),
+            tag: None,
+            client_ip: None,
+            user_agent: None,
+            encrypted: false,
+            e2e: false,
+            visibility: Visibility::Listed,
+            allow_keys: Vec::new(),
+            supersedes: None,
+            superseded_by: None,
+            owner: None,
+            stored: None,
+            lang: None,
+        }
+    }
+}
+
+/// Query string of `POST /api/v1/paste`; the body is the content.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreatePasteParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// The body was encrypted by the client (end-to-end mode).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    /// Comma-separated API key names that may read a private paste.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_keys: Option<String>,
+    /// Id of an older paste this one replaces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supersedes: Option<String>,
+    /// Vanity slug, served at `/s/{slug}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    /// Answer with an existing paste of the same content and options instead
+    /// of committing a copy; defaults to `--dedupe`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedupe: Option<bool>,
+    /// Language to highlight the paste as, e.g. `rust` or `py`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
 }
 
+/// Shape of [`CreatePasteResponse`]. Bump when fields are added or change
+/// meaning; fields are never removed within a version.
+pub const CREATE_RESPONSE_VERSION: u32 = 3;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreatePasteResponse {
+    /// Idempotency records stored before versioning read back as `1` and
+    /// are rebuilt before they are replayed.
+    #[serde(default = "first_response_version")]
+    pub version: u32,
     pub id: String,
     pub path: String,
     pub commit: String,
     pub raw_url: String,
     pub view_url: String,
     pub meta_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_id: Option<String>,
+    /// View URL using the short id, handy to read out loud.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_url: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Whether the commit reached the remote before the response was sent.
+    #[serde(default)]
+    pub pushed: bool,
+    #[serde(default)]
+    pub size: usize,
+    #[serde(default)]
+    pub size_human: String,
+    /// When `--retain-days` removes the paste; `null` without an age limit.
+    #[serde(default)]
+    pub expires_at: Option<OffsetDateTime>,
+    /// The paste already existed with this content, so nothing was committed.
+    #[serde(default)]
+    pub deduplicated: bool,
+}
+
+fn first_response_version() -> u32 {
+    1
+}
+
+/// `GET /api/v1/p/{id}`: the stored meta plus fields computed per request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetaResponse {
+    #[serde(flatten)]
+    pub meta: PasteMeta,
+    /// When `--retain-days` removes the paste; `null` when no age limit is set.
+    pub expires_at: Option<OffsetDateTime>,
+    /// Whether the paste's commit is on the remote.
+    pub pushed: bool,
+    pub size_human: String,
+    #[serde(default)]
+    pub views: PasteViews,
+}
+
+/// How often a paste was opened, as counted by [`crate::views`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasteViews {
+    /// Loads of the `/p/{id}` page.
+    pub views: u64,
+    /// Reads of the raw bytes.
+    pub raw: u64,
+    pub first_at: Option<OffsetDateTime>,
+    pub last_at: Option<OffsetDateTime>,
 }
 
 #[derive(Debug)]
@@ -62,9 +314,43 @@ pub struct CreatePasteInput {
     pub msg: Option<String>,
     pub tag: Option<String>,
     pub content_type: Option<String>,
-    pub bytes: Vec<u8>,
+    pub body: PasteBody,
+    /// The body is client-side ciphertext; store it untouched.
+    pub e2e: bool,
+    pub visibility: Visibility,
+    /// API key names allowed to read a private paste.
+    pub allow_keys: Vec<String>,
+    /// Vanity slug chosen by the client; unlike a slug derived from `name`
+    /// it is never suffixed, so a taken one is a conflict.
+    pub slug: Option<String>,
     pub client_ip: Option<IpAddr>,
     pub user_agent: Option<String>,
+    /// The creating API key, recorded as the meta's `owner`.
+    pub owner: Option<String>,
+    /// Highlighting language, already validated.
+    pub lang: Option<String>,
+}
+
+/// Paste content, either held in memory or already streamed to disk.
+#[derive(Debug)]
+pub enum PasteBody {
+    Bytes(Vec<u8>),
+    Staged(StagedBody),
+}
+
+/// An upload streamed into `tmp/`, hashed while it was written. The file is
+/// removed on drop unless it has been moved into the repo.
+#[derive(Debug)]
+pub struct StagedBody {
+    pub path: PathBuf,
+    pub size: usize,
+    pub sha256: String,
+}
+
+impl Drop for StagedBody {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
 }
 
 #[derive(Debug)]
@@ -77,6 +363,10 @@ pub struct PasteDraft {
     pub meta_rel_path: String,
     pub slug_rel_path: String,
     pub slug_path: PathBuf,
+    /// Unset for unlisted and private pastes, which only go by their id.
+    pub short_id: Option<String>,
+    pub short_rel_path: Option<String>,
+    pub short_path: Option<PathBuf>,
     pub content_type: String,
     pub size: usize,
     pub sha256: String,
@@ -84,19 +374,164 @@ pub struct PasteDraft {
     pub meta: PasteMeta,
 }
 
+#[derive(Debug)]
+pub struct AppendDraft {
+    pub id: String,
+    pub rel_path: String,
+    pub abs_path: PathBuf,
+    pub meta_rel_path: String,
+    pub meta_path: PathBuf,
+    pub previous_len: u64,
+    /// Whole previous file for sealed pastes, which are rewritten rather than extended.
+    pub previous_body: Option<Vec<u8>>,
+    pub previous_meta: Vec<u8>,
+    pub subject: String,
+    pub meta: PasteMeta,
+}
+
+/// Rewritten meta files that have not been committed yet, with their old
+/// contents for rollback.
+#[derive(Debug)]
+pub struct MetaUpdateDraft {
+    pub files: Vec<MetaRewrite>,
+    pub subject: String,
+}
+
+#[derive(Debug)]
+pub struct MetaRewrite {
+    pub rel_path: String,
+    pub abs_path: PathBuf,
+    pub previous: Vec<u8>,
+}
+
+/// A titled list of pastes, stored as `collections/<id>.json` in the repo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Member paste ids, in the order they were added.
+    #[serde(default)]
+    pub pastes: Vec<String>,
+}
+
+/// `POST /api/v1/collections`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NewCollection {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Full or short paste ids to start with.
+    #[serde(default)]
+    pub pastes: Vec<String>,
+}
+
+/// `POST /api/v1/collections/{id}/pastes`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddToCollection {
+    pub pastes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionResponse {
+    #[serde(flatten)]
+    pub collection: Collection,
+    /// Page listing the members, to share.
+    pub url: String,
+    /// The commit that wrote this version; only on writes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Members the caller may read that still exist, in collection order.
+    pub members: Vec<RecentItem>,
+}
+
+/// A collection file written but not committed yet, with what it replaced.
+#[derive(Debug)]
+pub struct CollectionDraft {
+    pub collection: Collection,
+    pub rel_path: String,
+    pub abs_path: PathBuf,
+    /// The previous file, or `None` for a new collection.
+    pub previous: Option<Vec<u8>>,
+    pub subject: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendResponse {
+    pub id: String,
+    pub offset: u64,
+    pub appended: usize,
+    pub size: usize,
+    pub sha256: String,
+    pub commit: String,
+}
+
+/// Short-lived exclusive edit lease on a paste. Holders present `lease` in
+/// the `X-Paste-Lease` header to renew, release, or write while it is active.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasteLease {
+    pub id: String,
+    pub lease: String,
+    pub holder: Option<String>,
+    pub expires_at: OffsetDateTime,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ApiErrorBody {
     pub error: String,
     pub message: String,
 }
 
+/// Create options that go into an idempotency fingerprint, alongside the
+/// body bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdempotencyFields<'a> {
+    pub name: Option<&'a str>,
+    pub msg: Option<&'a str>,
+    pub tag: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    pub e2e: bool,
+    pub visibility: Visibility,
+    pub allow_keys: &'a [String],
+    pub supersedes: Option<&'a str>,
+    pub slug: Option<&'a str>,
+    pub lang: Option<&'a str>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdempotencyRecord {
     pub request_fingerprint: String,
     pub response: CreatePasteResponse,
 }
 
-#[derive(Debug, Serialize)]
+/// Query string of `GET /api/v1/recent`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecentParams {
+    /// At most this many, newest first; 50 when unset, capped at 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Also list pastes that a newer paste supersedes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded: Option<bool>,
+    /// Only pastes created with this API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// Query of `/api/v1/mine`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MineParams {
+    /// At most this many, newest first; 50 when unset, capped at 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecentItem {
     pub id: String,
     pub created_at: OffsetDateTime,
@@ -105,6 +540,27 @@ pub struct RecentItem {
     pub tag: Option<String>,
     pub size: usize,
     pub content_type: String,
+    /// Number of API keys that starred the paste.
+    #[serde(default)]
+    pub stars: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl RecentItem {
+    pub fn from_meta(m: PasteMeta, stars: usize) -> Self {
+        Self {
+            id: m.id,
+            created_at: m.created_at,
+            path: m.path,
+            commit: m.commit,
+            tag: m.tag,
+            size: m.size,
+            content_type: m.content_type,
+            stars,
+            owner: m.owner,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -150,7 +606,7 @@ impl AppPaths {
         let files = base.join("files");
         let files_meta = files.join("meta");
         let git_lock = run.join("git.lock");
-        let idempotency = run.join("idempotency");
+        let kv = run.join("kv");
         Self {
             base,
             repo,
@@ -159,7 +615,7 @@ impl AppPaths {
             files,
             files_meta,
             git_lock,
-            idempotency,
+            kv,
         }
     }
 }
@@ -0,0 +1,65 @@
+//! Per-paste hit counters, kept in the runtime kv store rather than the repo:
+//! a commit per page view would bury the pastes in noise.
+//!
+//! The `/p/{id}` pages count as views and `/raw` and `/raw.txt` as raw reads.
+//! Conditional requests answered `304` count too, since someone still opened
+//! the link; API meta reads and follow/tail polling do not.
+
+use std::sync::Mutex;
+
+use time::OffsetDateTime;
+
+use crate::{errors::AppResult, kv::Kv, types::PasteViews};
+
+const VIEWS_NS: &str = "views";
+
+/// Serialises read-modify-write of view records.
+static WRITE: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    View,
+    Raw,
+}
+
+/// Counts one hit on paste `id`.
+pub fn record(kv: &Kv, id: &str, hit: Hit) -> AppResult<PasteViews> {
+    let _guard = WRITE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut views = get(kv, id)?;
+    match hit {
+        Hit::View => views.views += 1,
+        Hit::Raw => views.raw += 1,
+    }
+    let now = OffsetDateTime::now_utc();
+    views.first_at.get_or_insert(now);
+    views.last_at = Some(now);
+    kv.put(VIEWS_NS, id, &views, None)?;
+    Ok(views)
+}
+
+/// The counters of paste `id`; all zero before its first hit.
+pub fn get(kv: &Kv, id: &str) -> AppResult<PasteViews> {
+    Ok(kv.get(VIEWS_NS, id)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_are_counted_by_kind() {
+        let td = tempfile::tempdir().expect("tempdir");
+        let kv = Kv::open(td.path());
+        assert_eq!(get(&kv, "A").expect("get"), PasteViews::default());
+
+        record(&kv, "A", Hit::View).expect("view");
+        record(&kv, "A", Hit::View).expect("view");
+        let views = record(&kv, "A", Hit::Raw).expect("raw");
+        assert_eq!((views.views, views.raw), (2, 1));
+        assert!(views.first_at <= views.last_at);
+        assert_eq!(get(&kv, "A").expect("get"), views);
+        assert_eq!(get(&kv, "B").expect("get").views, 0);
+    }
+}
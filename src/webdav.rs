@@ -0,0 +1,341 @@
+//! Read-only WebDAV view of the repo's `pastes/` tree at `/dav/`, enabled by
+//! `--webdav`, so Finder, Explorer and editors can mount the server and open
+//! pastes as files. Only listed pastes appear; sealed ones are decrypted on
+//! the way out and client-side encrypted ones are left out, since they are
+//! unreadable without the key anyway. Compressed pastes appear without their
+//! `.zst`, holding the content.
+//!
+//! Implements the class 1 subset a read-only mount needs: `OPTIONS`,
+//! `PROPFIND` (depth 0 or 1) and `GET`/`HEAD`. Everything else is `405`.
+
+use std::{fmt::Write as _, fs, path::PathBuf, sync::Arc, time::SystemTime};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::any,
+};
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+    auth::{self, Scope},
+    errors::{AppError, AppResult},
+    http, render, store,
+    types::{AppState, PasteMeta},
+    zst,
+};
+
+const ROOT: &str = "/dav/";
+const ALLOW_COLLECTION: &str = "OPTIONS, PROPFIND";
+const ALLOW_FILE: &str = "OPTIONS, PROPFIND, GET, HEAD";
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/dav", any(dav_root))
+        .route("/dav/", any(dav_root))
+        .route("/dav/{*path}", any(dav_path))
+}
+
+async fn dav_root(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    dav(&state, &method, &headers, "").await
+}
+
+async fn dav_path(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+    Path(path): Path<String>,
+) -> Response {
+    dav(&state, &method, &headers, &path).await
+}
+
+async fn dav(state: &AppState, method: &Method, headers: &HeaderMap, path: &str) -> Response {
+    match serve(state, method, headers, path).await {
+        Ok(response) => response,
+        Err(err @ AppError::Unauthorized(_)) => {
+            let mut response = err.into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Basic realm=\"lanpaste\""),
+            );
+            response
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+/// A resource under `/dav/`: a directory of `pastes/` or one paste file.
+enum Entry {
+    Collection {
+        segments: Vec<String>,
+        modified: Option<SystemTime>,
+    },
+    File {
+        segments: Vec<String>,
+        meta: Box<PasteMeta>,
+    },
+}
+
+async fn serve(
+    state: &AppState,
+    method: &Method,
+    headers: &HeaderMap,
+    path: &str,
+) -> AppResult<Response> {
+    // Clients probe capabilities before sending credentials.
+    if method == Method::OPTIONS {
+        return Ok((
+            StatusCode::OK,
+            [
+                (header::HeaderName::from_static("dav"), "1"),
+                (header::ALLOW, ALLOW_FILE),
+                (header::HeaderName::from_static("ms-author-via"), "DAV"),
+            ],
+        )
+            .into_response());
+    }
+    auth::authorize_basic(&state.api_keys, headers, Scope::PasteRead)?;
+    http::check_read_token(state, headers)?;
+
+    let entry = resolve(state, parse_path(path)?)?;
+    match (method.as_str(), &entry) {
+        ("PROPFIND", _) => propfind(state, headers, entry),
+        ("GET" | "HEAD", Entry::File { meta, .. }) => {
+            let (body, len) = if method == Method::HEAD {
+                (Body::empty(), store::paste_len(&state.paths.repo, meta)?)
+            } else {
+                http::paste_body(state, meta).await?
+            };
+            let mut response = http::raw_response(body, len, Some(meta));
+            let headers = response.headers_mut();
+            if let Ok(etag) = header::HeaderValue::from_str(&etag(meta)) {
+                headers.insert(header::ETAG, etag);
+            }
+            if let Ok(modified) =
+                header::HeaderValue::from_str(&httpdate::fmt_http_date(modified(state, meta)))
+            {
+                headers.insert(header::LAST_MODIFIED, modified);
+            }
+            Ok(response)
+        }
+        (_, Entry::Collection { .. }) => Ok(not_allowed(ALLOW_COLLECTION)),
+        (_, Entry::File { .. }) => Ok(not_allowed(ALLOW_FILE)),
+    }
+}
+
+fn not_allowed(allow: &'static str) -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(header::ALLOW, allow)],
+        "the WebDAV view is read-only\n",
+    )
+        .into_response()
+}
+
+/// Splits the request path into segments, refusing anything that could step
+/// outside `pastes/` or name a hidden file.
+fn parse_path(path: &str) -> AppResult<Vec<String>> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            if segment.starts_with('.') || segment.contains('\\') || segment.contains('\0') {
+                Err(AppError::NotFound("no such resource".to_string()))
+            } else {
+                Ok(segment.to_string())
+            }
+        })
+        .collect()
+}
+
+fn resolve(state: &AppState, segments: Vec<String>) -> AppResult<Entry> {
+    let abs: PathBuf = segments
+        .iter()
+        .fold(state.paths.repo.join("pastes"), |path, s| path.join(s));
+    let not_found = || AppError::NotFound("no such resource".to_string());
+    let stat = fs::metadata(&abs)
+        .or_else(|_| {
+            let mut packed = abs.clone().into_os_string();
+            packed.push(zst::SUFFIX);
+            fs::metadata(packed)
+        })
+        .map_err(|_| not_found())?;
+    if stat.is_dir() {
+        return Ok(Entry::Collection {
+            segments,
+            modified: stat.modified().ok(),
+        });
+    }
+    let meta = paste_at(state, &segments).ok_or_else(not_found)?;
+    Ok(Entry::File {
+        segments,
+        meta: Box::new(meta),
+    })
+}
+
+/// The paste stored at `pastes/<segments>`, if it is one and may be listed.
+fn paste_at(state: &AppState, segments: &[String]) -> Option<PasteMeta> {
+    let (id, _) = segments.last()?.split_once("__")?;
+    let meta = store::read_meta(&state.paths.repo, &state.cfg, id).ok()?;
+    let visible = meta.visibility.is_listed() && !meta.e2e;
+    (visible && meta.content_path() == format!("pastes/{}", segments.join("/"))).then_some(meta)
+}
+
+fn propfind(state: &AppState, headers: &HeaderMap, entry: Entry) -> AppResult<Response> {
+    let depth = headers
+        .get("Depth")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("1")
+        .trim();
+    let mut entries = Vec::new();
+    if depth != "0"
+        && let Entry::Collection { segments, .. } = &entry
+    {
+        entries = children(state, segments)?;
+    }
+    entries.insert(0, entry);
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for entry in &entries {
+        write_response(state, &mut xml, entry)?;
+    }
+    xml.push_str("</D:multistatus>\n");
+    Ok((
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response())
+}
+
+fn children(state: &AppState, segments: &[String]) -> AppResult<Vec<Entry>> {
+    let dir: PathBuf = segments
+        .iter()
+        .fold(state.paths.repo.join("pastes"), |path, s| path.join(s));
+    let mut names: Vec<String> = fs::read_dir(&dir)
+        .map_err(|e| AppError::io("read pastes dir", e))?
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match name.strip_suffix(zst::SUFFIX) {
+                Some(content) => content.to_string(),
+                None => name,
+            }
+        })
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    Ok(names
+        .into_iter()
+        .filter_map(|name| {
+            let mut child = segments.to_vec();
+            child.push(name);
+            resolve(state, child).ok()
+        })
+        .collect())
+}
+
+fn write_response(state: &AppState, xml: &mut String, entry: &Entry) -> AppResult<()> {
+    let fmt = |e: std::fmt::Error| AppError::internal(format!("propfind xml: {e}"));
+    let (segments, collection) = match entry {
+        Entry::Collection { segments, .. } => (segments, true),
+        Entry::File { segments, .. } => (segments, false),
+    };
+    let mut href = String::from(ROOT);
+    for segment in segments {
+        href.push_str(&percent_encode(segment));
+        href.push('/');
+    }
+    if !collection {
+        href.pop();
+    }
+    let name = segments.last().map_or("pastes", String::as_str);
+    write!(
+        xml,
+        "<D:response><D:href>{href}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+        render::html_escape(name)
+    )
+    .map_err(fmt)?;
+    match entry {
+        Entry::Collection { modified, .. } => {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+            if let Some(modified) = modified {
+                write!(
+                    xml,
+                    "<D:getlastmodified>{}</D:getlastmodified>",
+                    httpdate::fmt_http_date(*modified)
+                )
+                .map_err(fmt)?;
+            }
+        }
+        Entry::File { meta, .. } => {
+            let created = meta
+                .created_at
+                .format(&Rfc3339)
+                .map_err(|e| AppError::internal(format!("propfind date: {e}")))?;
+            write!(
+                xml,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+                 <D:getcontenttype>{}</D:getcontenttype><D:getetag>{}</D:getetag>\
+                 <D:creationdate>{created}</D:creationdate>\
+                 <D:getlastmodified>{}</D:getlastmodified>",
+                store::paste_len(&state.paths.repo, meta)?,
+                render::html_escape(&meta.content_type),
+                render::html_escape(&etag(meta)),
+                httpdate::fmt_http_date(modified(state, meta)),
+            )
+            .map_err(fmt)?;
+        }
+    }
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    Ok(())
+}
+
+fn etag(meta: &PasteMeta) -> String {
+    format!("\"{}\"", meta.sha256)
+}
+
+/// File time, which moves on appends, else the creation time.
+fn modified(state: &AppState, meta: &PasteMeta) -> SystemTime {
+    store::paste_modified(&state.paths.repo, meta).unwrap_or_else(|| meta.created_at.into())
+}
+
+/// Percent-encodes everything outside the RFC 3986 unreserved set.
+pub(crate) fn percent_encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_inside_the_pastes_tree() {
+        assert_eq!(
+            parse_path("2026/10/16/").expect("path"),
+            vec!["2026", "10", "16"]
+        );
+        assert!(parse_path("").expect("root").is_empty());
+        assert!(parse_path("2026/../../meta").is_err());
+        assert!(parse_path(".git/config").is_err());
+        assert!(parse_path("a\\b").is_err());
+        assert_eq!(percent_encode("01AB__my notes.md"), "01AB__my%20notes.md");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+    }
+}
@@ -0,0 +1,427 @@
+//! Outgoing webhooks for `--webhook-url`. A worker subscribes to the paste
+//! event stream and queues one delivery per event and URL in the `webhooks`
+//! kv namespace, so pending deliveries survive a restart. A second task
+//! posts them, signed with HMAC-SHA256, and retries failures with
+//! exponential backoff until [`MAX_ATTEMPTS`] is reached.
+//!
+//! Like `/api/v1/events`, only listed pastes are announced.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::sync::{Notify, broadcast};
+use tracing::{info, warn};
+use ulid::Ulid;
+
+use crate::{
+    config::ServeCmd,
+    errors::{AppError, AppResult},
+    events::PasteEvent,
+    kv::Kv,
+    types::AppState,
+};
+
+const NAMESPACE: &str = "webhooks";
+pub const MAX_ATTEMPTS: u32 = 8;
+const RETRY_BASE: Duration = Duration::from_secs(10);
+const RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the delivery task sleeps when nothing is queued.
+const IDLE: Duration = Duration::from_secs(60);
+
+pub const SIGNATURE_HEADER: &str = "X-Lanpaste-Signature";
+pub const EVENT_HEADER: &str = "X-Lanpaste-Event";
+pub const DELIVERY_HEADER: &str = "X-Lanpaste-Delivery";
+
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    /// Replaced on a settings reload; see [`Webhooks::set_urls`].
+    urls: Arc<RwLock<Vec<reqwest::Url>>>,
+    secret: Arc<Vec<u8>>,
+    retry_base: Duration,
+    /// `--base-url` path, which the payload's `urls` start with.
+    base_path: String,
+}
+
+/// What receivers get, as the JSON request body.
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    id: &'a str,
+    tag: Option<&'a str>,
+    size: usize,
+    sha256: &'a str,
+    content_type: &'a str,
+    /// RFC 3339, unlike the metas, since receivers are not lanpaste.
+    created_at: String,
+    commit: &'a str,
+    urls: Urls,
+}
+
+#[derive(Debug, Serialize)]
+struct Urls {
+    view: String,
+    raw: String,
+    meta: String,
+}
+
+/// A queued POST. The body is fixed when queued so every retry carries the
+/// same bytes and signature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: String,
+    pub url: String,
+    pub event: String,
+    pub body: String,
+    pub attempts: u32,
+    pub next_attempt: OffsetDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl Webhooks {
+    /// Enabled by `--webhook-secret-file`; `urls` may start empty and be
+    /// filled in by a reload.
+    pub fn from_config(cfg: &ServeCmd, urls: &[String]) -> AppResult<Option<Self>> {
+        let Some(secret_file) = &cfg.webhook_secret_file else {
+            if urls.is_empty() {
+                return Ok(None);
+            }
+            return Err(AppError::internal(
+                "--webhook-url needs --webhook-secret-file",
+            ));
+        };
+        let urls = parse_urls(urls)?;
+        let mut hooks = Self::new(urls, read_secret(secret_file)?, RETRY_BASE);
+        hooks.base_path = cfg.base_path.clone();
+        Ok(Some(hooks))
+    }
+
+    pub fn new(urls: Vec<reqwest::Url>, secret: Vec<u8>, retry_base: Duration) -> Self {
+        Self {
+            urls: Arc::new(RwLock::new(urls)),
+            secret: Arc::new(secret),
+            retry_base,
+            base_path: String::new(),
+        }
+    }
+
+    /// Targets later events; deliveries already queued keep their URL.
+    pub fn set_urls(&self, urls: Vec<reqwest::Url>) {
+        *self.urls.write().unwrap_or_else(PoisonError::into_inner) = urls;
+    }
+
+    /// Queues one delivery of `event` per configured URL.
+    pub fn enqueue(&self, kv: &Kv, event: &PasteEvent) -> AppResult<()> {
+        let paste = &event.paste;
+        let payload = Payload {
+            event: event.kind.as_str(),
+            id: &paste.id,
+            tag: paste.tag.as_deref(),
+            size: paste.size,
+            sha256: &event.sha256,
+            content_type: &paste.content_type,
+            created_at: paste
+                .created_at
+                .format(&Rfc3339)
+                .map_err(|e| AppError::internal(format!("format webhook date: {e}")))?,
+            commit: &paste.commit,
+            urls: Urls {
+                view: format!("{}/p/{}", self.base_path, paste.id),
+                raw: format!("{}/api/v1/p/{}/raw", self.base_path, paste.id),
+                meta: format!("{}/api/v1/p/{}", self.base_path, paste.id),
+            },
+        };
+        let body = serde_json::to_string(&payload)
+            .map_err(|e| AppError::internal(format!("serialize webhook payload: {e}")))?;
+        let urls = self
+            .urls
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for url in &urls {
+            let delivery = Delivery {
+                id: Ulid::new().to_string(),
+                url: url.to_string(),
+                event: event.kind.as_str().to_string(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: OffsetDateTime::now_utc(),
+                last_error: None,
+            };
+            kv.put(NAMESPACE, &delivery.id, &delivery, None)?;
+        }
+        Ok(())
+    }
+
+    /// Deliveries still waiting to go out, oldest first.
+    pub fn pending(kv: &Kv) -> AppResult<Vec<Delivery>> {
+        let mut queued: Vec<Delivery> = kv.list(NAMESPACE)?;
+        queued.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(queued)
+    }
+
+    /// Attempts every delivery that is due. Returns when the next remaining
+    /// one is, if any.
+    pub async fn deliver_due(
+        &self,
+        kv: &Kv,
+        client: &reqwest::Client,
+    ) -> AppResult<Option<OffsetDateTime>> {
+        let mut next = None;
+        for mut delivery in Self::pending(kv)? {
+            if delivery.next_attempt > OffsetDateTime::now_utc() {
+                next = earliest(next, delivery.next_attempt);
+                continue;
+            }
+            match self.post(client, &delivery).await {
+                Ok(()) => {
+                    kv.delete(NAMESPACE, &delivery.id)?;
+                }
+                Err(err) => {
+                    delivery.attempts += 1;
+                    if delivery.attempts >= MAX_ATTEMPTS {
+                        warn!(
+                            "webhook {} to {} dropped after {} attempts: {err:?}",
+                            delivery.id, delivery.url, delivery.attempts
+                        );
+                        kv.delete(NAMESPACE, &delivery.id)?;
+                        continue;
+                    }
+                    delivery.next_attempt =
+                        OffsetDateTime::now_utc() + self.backoff(delivery.attempts);
+                    delivery.last_error = Some(format!("{err:?}"));
+                    info!(
+                        "webhook {} to {} failed (attempt {}), retrying: {err:?}",
+                        delivery.id, delivery.url, delivery.attempts
+                    );
+                    next = earliest(next, delivery.next_attempt);
+                    kv.put(NAMESPACE, &delivery.id, &delivery, None)?;
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.retry_base
+            .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+            .min(RETRY_MAX)
+    }
+
+    async fn post(&self, client: &reqwest::Client, delivery: &Delivery) -> AppResult<()> {
+        let response = client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .header(DELIVERY_HEADER, &delivery.id)
+            .header(
+                SIGNATURE_HEADER,
+                signature(&self.secret, delivery.body.as_bytes()),
+            )
+            .body(delivery.body.clone())
+            .send()
+            .await
+            .map_err(|e| AppError::ServiceUnavailable(format!("webhook request failed: {e}")))?;
+        if !response.status().is_success() {
+            return Err(AppError::ServiceUnavailable(format!(
+                "webhook returned {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The signature header value for `body`: `sha256=<hex hmac>`.
+pub fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn read_secret(path: &Path) -> AppResult<Vec<u8>> {
+    let secret = fs::read_to_string(path)
+        .map_err(|e| AppError::io("read webhook secret file", e))?
+        .trim()
+        .to_string();
+    if secret.is_empty() {
+        return Err(AppError::internal("webhook secret file is empty"));
+    }
+    Ok(secret.into_bytes())
+}
+
+fn earliest(current: Option<OffsetDateTime>, at: OffsetDateTime) -> Option<OffsetDateTime> {
+    Some(current.map_or(at, |c| c.min(at)))
+}
+
+/// Checks `--webhook-url` values: absolute http(s) URLs with a host.
+pub fn parse_urls(raw: &[String]) -> AppResult<Vec<reqwest::Url>> {
+    raw.iter()
+        .map(|raw| {
+            reqwest::Url::parse(raw)
+                .ok()
+                .filter(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some())
+                .ok_or_else(|| AppError::internal(format!("invalid --webhook-url '{raw}'")))
+        })
+        .collect()
+}
+
+/// Starts the queueing and delivery tasks. Call before serving so no event
+/// is published before the worker subscribes.
+pub fn spawn_worker(state: Arc<AppState>, hooks: Webhooks) {
+    let wake = Arc::new(Notify::new());
+    let (_, mut rx) = state.events.subscribe(None);
+
+    let kv = state.kv.clone();
+    let queue = hooks.clone();
+    let queued = wake.clone();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Err(err) = queue.enqueue(&kv, &event) {
+                        warn!("could not queue webhook for {}: {err:?}", event.paste.id);
+                    }
+                    queued.notify_one();
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("webhook worker fell behind; {skipped} events not delivered");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(SEND_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("webhooks disabled: build http client: {err}");
+                return;
+            }
+        };
+        loop {
+            let next = hooks
+                .deliver_due(&state.kv, &client)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!("webhook delivery pass failed: {err:?}");
+                    None
+                });
+            let wait = next
+                .map(|at| {
+                    Duration::try_from(at - OffsetDateTime::now_utc()).unwrap_or(Duration::ZERO)
+                })
+                .unwrap_or(IDLE);
+            tokio::select! {
+                _ = wake.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{Router, http::StatusCode, routing::post};
+
+    use super::*;
+    use crate::{
+        events::EventKind,
+        types::{PasteMeta, RecentItem},
+    };
+
+    fn event() -> PasteEvent {
+        let meta = PasteMeta {
+            path: "pastes/01HOOK__x.txt".to_string(),
+            size: 5,
+            sha256: "ab".repeat(32),
+            tag: Some("ci".to_string()),
+            ..PasteMeta::for_test("01HOOK")
+        };
+        PasteEvent {
+            seq: 1,
+            kind: EventKind::Created,
+            sha256: meta.sha256.clone(),
+            paste: RecentItem::from_meta(meta, 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried_with_the_same_signature() {
+        let seen = Arc::new(Mutex::new(Vec::<(String, String)>::new()));
+        let log = seen.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: axum::http::HeaderMap, body: String| {
+                let log = log.clone();
+                async move {
+                    let signature = headers[SIGNATURE_HEADER].to_str().unwrap_or_default();
+                    let mut log = log.lock().expect("log");
+                    log.push((signature.to_string(), body));
+                    if log.len() == 1 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let url = format!("http://{}/hook", listener.local_addr().expect("addr"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let td = tempfile::tempdir().expect("tempdir");
+        let kv = Kv::open(td.path());
+        let hooks = Webhooks::new(
+            vec![reqwest::Url::parse(&url).expect("url")],
+            b"s3cret".to_vec(),
+            Duration::ZERO,
+        );
+        hooks.enqueue(&kv, &event()).expect("enqueue");
+        let client = reqwest::Client::new();
+
+        let next = hooks.deliver_due(&kv, &client).await.expect("first pass");
+        assert!(next.is_some());
+        let queued = Webhooks::pending(&kv).expect("pending");
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].attempts, 1);
+        assert!(queued[0].last_error.is_some());
+
+        assert_eq!(hooks.deliver_due(&kv, &client).await.expect("retry"), None);
+        assert!(Webhooks::pending(&kv).expect("pending").is_empty());
+
+        let seen = seen.lock().expect("log");
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0], seen[1]);
+        let (sig, body) = &seen[0];
+        assert_eq!(sig, &signature(b"s3cret", body.as_bytes()));
+        let body: serde_json::Value = serde_json::from_str(body).expect("json");
+        assert_eq!(body["event"], "created");
+        assert_eq!(body["id"], "01HOOK");
+        assert_eq!(body["sha256"], "ab".repeat(32));
+        assert_eq!(body["urls"]["raw"], "/api/v1/p/01HOOK/raw");
+        assert_eq!(body["created_at"], "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let hooks = Webhooks::new(Vec::new(), b"k".to_vec(), RETRY_BASE);
+        assert_eq!(hooks.backoff(1), RETRY_BASE);
+        assert_eq!(hooks.backoff(3), RETRY_BASE * 4);
+        assert_eq!(hooks.backoff(30), RETRY_MAX);
+    }
+}
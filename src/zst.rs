@@ -0,0 +1,81 @@
+//! zstd compression of large pastes at rest, enabled by `--zstd-above`.
+//!
+//! A paste bigger than the threshold is written to the repo as
+//! `<file>.zst`, and its meta gets a [`StoredFile`] with the size and hash of
+//! the compressed file, while `size` and `sha256` keep describing the
+//! content. Reads decode it transparently. Pastes that would not shrink are
+//! stored as is, and so are sealed and end-to-end encrypted ones, whose
+//! bytes do not compress.
+
+use std::io::Read;
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    errors::{AppError, AppResult},
+    types::{StoredEncoding, StoredFile},
+};
+
+/// Suffix of a compressed paste file.
+pub const SUFFIX: &str = ".zst";
+const LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+/// Compresses `content`, or `None` when that would not make it smaller.
+pub fn compress(content: &[u8]) -> AppResult<Option<(Vec<u8>, StoredFile)>> {
+    let (packed, stored) = pack(content)?;
+    Ok((packed.len() < content.len()).then_some((packed, stored)))
+}
+
+/// Compresses `content` whatever the result, for a file already stored as
+/// `.zst`.
+pub fn pack(content: &[u8]) -> AppResult<(Vec<u8>, StoredFile)> {
+    let packed = zstd::encode_all(content, LEVEL)
+        .map_err(|e| AppError::internal(format!("zstd compress: {e}")))?;
+    let stored = StoredFile {
+        encoding: StoredEncoding::Zstd,
+        size: packed.len(),
+        sha256: hex::encode(Sha256::digest(&packed)),
+    };
+    Ok((packed, stored))
+}
+
+/// The content of a compressed file that should hold `size` bytes. A file
+/// that inflates past that is refused rather than read to the end.
+pub fn decompress(stored: &[u8], size: usize) -> AppResult<Vec<u8>> {
+    let mut content = Vec::with_capacity(size);
+    zstd::Decoder::new(stored)
+        .map_err(|e| AppError::internal(format!("zstd decompress: {e}")))?
+        .take(size as u64 + 1)
+        .read_to_end(&mut content)
+        .map_err(|e| AppError::internal(format!("zstd decompress: {e}")))?;
+    if content.len() != size {
+        return Err(AppError::Integrity(format!(
+            "compressed paste inflates to {} bytes, expected {size}",
+            content.len()
+        )));
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_shrink_and_inflate_back() {
+        let log = b"2026-10-16 build step ok\n".repeat(400);
+        let (packed, stored) = compress(&log).expect("compress").expect("smaller");
+        assert!(packed.len() < log.len() / 10);
+        assert_eq!(stored.size, packed.len());
+        assert_eq!(decompress(&packed, log.len()).expect("decompress"), log);
+        assert!(decompress(&packed, log.len() - 1).is_err());
+    }
+
+    #[test]
+    fn incompressible_content_is_left_alone() {
+        let noise: Vec<u8> = (0..128u32)
+            .flat_map(|i| Sha256::digest(i.to_le_bytes()))
+            .collect();
+        assert!(compress(&noise).expect("compress").is_none());
+    }
+}
@@ -0,0 +1,13 @@
+//! Fixtures shared by the integration test binaries.
+
+use lanpaste::config::ServeCmd;
+
+/// `lanpaste serve --dir <dir>` with its stock defaults, bound to an
+/// ephemeral loopback port and without the free-space floor, which a
+/// temp dir on a busy CI disk can trip.
+pub fn serve_cfg(dir: &std::path::Path) -> ServeCmd {
+    let mut cfg = ServeCmd::offline(dir, "LAN Paste", "paste@lan").expect("serve defaults");
+    cfg.bind = vec!["127.0.0.1:0".parse().expect("bind")];
+    cfg.min_free_bytes = 0;
+    cfg
+}
@@ -2,25 +2,17 @@ use std::{net::SocketAddr, sync::Arc};
 
 use axum::{extract::connect_info::MockConnectInfo, http::StatusCode};
 use axum_test::TestServer;
-use lanpaste::{
-    config::{PushMode, ServeCmd},
-    http, preflight,
-};
+use lanpaste::{config::ServeCmd, http, preflight};
 use serde_yaml::Value as YamlValue;
 
+mod common;
+
 fn cfg(base: &std::path::Path) -> ServeCmd {
-    ServeCmd {
-        dir: base.to_path_buf(),
-        bind: "127.0.0.1:0".parse().expect("bind"),
-        token: Some("tok".to_string()),
-        api_keys_file: None,
-        max_bytes: 1024 * 1024,
-        push: PushMode::Off,
-        remote: "origin".to_string(),
-        allow_cidr: vec!["127.0.0.0/8".parse().expect("cidr")],
-        git_author_name: "LAN Paste".to_string(),
-        git_author_email: "paste@lan".to_string(),
-    }
+    let mut cfg = common::serve_cfg(base);
+    cfg.token = vec!["tok".to_string()];
+    cfg.allow_cidr = vec!["127.0.0.0/8".parse().expect("cidr")];
+    cfg.render_cache_bytes = 0;
+    cfg
 }
 
 fn mapping_has_key(map: &serde_yaml::Mapping, key: &str) -> bool {
@@ -36,18 +28,62 @@ fn openapi_spec_declares_core_routes_and_security() {
         "/",
         "/dashboard",
         "/recent",
+        "/new",
         "/api",
+        "/openapi.yaml",
+        "/api/docs",
         "/api/v1/paste",
         "/api/v1/upload",
         "/api/v1/p/{id}",
         "/api/v1/p/{id}/raw",
+        "/api/v1/p/{id}/raw/{filename}",
+        "/api/v1/p/{id}/stats",
+        "/api/v1/blob/{sha256}",
+        "/api/v1/p/{id}/tail",
+        "/api/v1/p/{id}/follow",
+        "/api/v1/p/{id}/append",
+        "/api/v1/p/{id}/lock",
+        "/api/v1/p/{id}/star",
+        "/api/v1/stars",
+        "/api/v1/mine",
+        "/api/v1/collections",
+        "/api/v1/collections/{id}",
+        "/api/v1/collections/{id}/pastes",
+        "/api/v1/export",
+        "/api/v1/stats",
         "/api/v1/recent",
+        "/api/v1/events",
+        "/ws",
+        "/api/v1/enroll",
+        "/api/v1/enroll/{id}",
+        "/api/v1/admin/status",
+        "/api/v1/admin/keys",
+        "/api/v1/admin/keys/{name}/disable",
+        "/api/v1/admin/keys/{name}/enable",
+        "/api/v1/admin/token/rotate",
+        "/api/v1/admin/digest",
+        "/api/v1/admin/archive",
+        "/api/v1/admin/gc",
+        "/api/v1/admin/reload",
+        "/api/v1/admin/backup",
+        "/api/v1/admin/enrollments",
+        "/api/v1/admin/enrollments/approve",
+        "/api/v1/admin/enrollments/{id}/reject",
         "/files/{name}",
+        "/assets/{file}",
+        "/dav/{path}",
+        "/git/info/refs",
+        "/git/git-upload-pack",
         "/p/{id}/md",
+        "/p/{id}/qr",
+        "/p/{id}/raw.txt",
         "/p/{id}",
         "/p/{id}/{slug}",
+        "/s/{slug}",
+        "/c/{id}",
         "/healthz",
         "/readyz",
+        "/statusz",
     ] {
         assert!(mapping_has_key(paths, path), "missing path {path}");
     }
@@ -90,9 +126,23 @@ async fn runtime_contract_matches_openapi_critical_shapes() {
         .await;
     created.assert_status(StatusCode::CREATED);
     let created_json: serde_json::Value = created.json();
-    for key in ["id", "path", "commit", "raw_url", "view_url", "meta_url"] {
+    for key in [
+        "version",
+        "id",
+        "path",
+        "commit",
+        "raw_url",
+        "view_url",
+        "meta_url",
+        "visibility",
+        "pushed",
+        "size",
+        "size_human",
+        "expires_at",
+    ] {
         assert!(created_json.get(key).is_some(), "missing create key {key}");
     }
+    assert_eq!(created_json["version"], 3);
     let id = created_json["id"].as_str().expect("id");
     assert!(
         created_json["view_url"].as_str().expect("view_url") == format!("/p/{id}"),
@@ -110,6 +160,9 @@ async fn runtime_contract_matches_openapi_critical_shapes() {
         "content_type",
         "commit",
         "sha256",
+        "expires_at",
+        "pushed",
+        "size_human",
     ] {
         assert!(meta_json.get(key).is_some(), "missing meta key {key}");
     }
@@ -125,3 +178,42 @@ async fn runtime_contract_matches_openapi_critical_shapes() {
         assert!(first.get(key).is_some(), "missing recent key {key}");
     }
 }
+
+#[tokio::test]
+async fn served_spec_is_the_checked_one() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = cfg(dir.path());
+    cfg.api_keys_file = Some(dir.path().join("keys.json"));
+    std::fs::write(
+        dir.path().join("keys.json"),
+        r#"{"keys":[{"key":"lp_only","scopes":["paste:read"]}]}"#,
+    )
+    .expect("write keys");
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4220)))),
+    )
+    .expect("server");
+
+    // Both are public, even with API keys enabled.
+    let spec = server.get("/openapi.yaml").await;
+    spec.assert_status(StatusCode::OK);
+    assert!(
+        spec.header("content-type")
+            .to_str()
+            .expect("content type")
+            .starts_with("application/yaml")
+    );
+    assert_eq!(spec.text(), include_str!("../openapi.yaml"));
+
+    let docs = server
+        .get("/api/docs")
+        .add_header("Host", "paste.lan:8090")
+        .await;
+    docs.assert_status(StatusCode::OK);
+    let page = docs.text();
+    assert!(page.contains("spec-url=\"/openapi.yaml\""));
+    assert!(page.contains("server-url=\"http://paste.lan:8090\""));
+    assert!(docs.maybe_header("content-security-policy").is_some());
+}
@@ -1,15 +1,26 @@
 use std::{fs, net::SocketAddr, sync::Arc};
 
-use axum::{extract::connect_info::MockConnectInfo, http::StatusCode};
+use axum::{
+    extract::connect_info::MockConnectInfo,
+    http::{HeaderMap, Method, StatusCode},
+};
 use axum_test::{
     TestServer,
     multipart::{MultipartForm, Part},
 };
 use lanpaste::{
-    config::{PushMode, ServeCmd},
+    assets,
+    client::{self, Client},
+    config::{self, PushMode, ServeCmd, VerifyMode},
+    errors::AppError,
     gitops::FileLock,
-    http, preflight,
+    http, init, instance, preflight, settings,
+    types::{CreatePasteParams, RecentParams, Visibility},
+    webhooks,
 };
+use sha2::{Digest, Sha256};
+
+mod common;
 
 const ONE_PX_PNG: &[u8] = &[
     0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
@@ -20,18 +31,10 @@ const ONE_PX_PNG: &[u8] = &[
 ];
 
 fn test_cfg(base: &std::path::Path) -> ServeCmd {
-    ServeCmd {
-        dir: base.to_path_buf(),
-        bind: "127.0.0.1:0".parse().expect("bind"),
-        token: Some("tok".to_string()),
-        api_keys_file: None,
-        max_bytes: 1024 * 1024,
-        push: PushMode::Off,
-        remote: "origin".to_string(),
-        allow_cidr: vec!["127.0.0.0/8".parse().expect("cidr")],
-        git_author_name: "LAN Paste".to_string(),
-        git_author_email: "paste@lan".to_string(),
-    }
+    let mut cfg = common::serve_cfg(base);
+    cfg.token = vec!["tok".to_string()];
+    cfg.allow_cidr = vec!["127.0.0.0/8".parse().expect("cidr")];
+    cfg
 }
 
 fn write_api_keys_file(path: &std::path::Path) {
@@ -165,6 +168,39 @@ async fn create_and_read_endpoints_work() {
         .get(&format!("/p/{id}/note"))
         .await
         .assert_status(StatusCode::OK);
+    let plain = server.get(&format!("/p/{id}/raw.txt")).await;
+    plain.assert_status(StatusCode::OK);
+    assert_eq!(
+        plain.header("content-type").to_str().expect("content-type"),
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(
+        plain
+            .header("content-disposition")
+            .to_str()
+            .expect("disposition"),
+        "inline"
+    );
+    assert_eq!(
+        plain
+            .header("x-content-type-options")
+            .to_str()
+            .expect("nosniff"),
+        "nosniff"
+    );
+    assert_eq!(plain.text(), "# hello\n\nworld");
+
+    let qr = server.get(&format!("/p/{id}/qr")).await;
+    qr.assert_status(StatusCode::OK);
+    assert_eq!(
+        qr.header("content-type").to_str().expect("content-type"),
+        "image/svg+xml"
+    );
+    assert!(qr.text().contains("<svg"));
+    server
+        .get("/p/01ZZZZZZZZZZZZZZZZZZZZZZZZ/qr")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
 
     server
         .get(&format!("/p/%2e%2e%2fmeta%2f{id}"))
@@ -178,6 +214,9 @@ async fn create_and_read_endpoints_work() {
 
     server.get("/healthz").await.assert_status(StatusCode::OK);
     server.get("/readyz").await.assert_status(StatusCode::OK);
+    let statusz: serde_json::Value = server.get("/statusz").await.json();
+    assert_eq!(statusz["status"], "ok");
+    assert!(statusz["self_test"].is_null());
 }
 
 #[tokio::test]
@@ -318,6 +357,499 @@ async fn upload_image_and_embed_render_works() {
     assert!(body.contains("Copy raw markdown"));
 }
 
+#[tokio::test]
+async fn short_ids_resolve_on_paste_routes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4110)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=call.txt")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Type", "text/plain")
+        .text("read me out loud")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let body = created.json::<serde_json::Value>();
+    let id = body["id"].as_str().expect("id").to_string();
+    let short = body["short_id"].as_str().expect("short_id").to_string();
+    assert_eq!(short.len(), 7);
+    assert_eq!(body["short_url"], format!("/p/{short}"));
+
+    let meta = server.get(&format!("/api/v1/p/{short}")).await;
+    meta.assert_status_ok();
+    let meta = meta.json::<serde_json::Value>();
+    assert_eq!(meta["id"], id);
+    assert_eq!(meta["short_id"], short);
+
+    let raw = server.get(&format!("/api/v1/p/{short}/raw")).await;
+    raw.assert_status_ok();
+    assert_eq!(raw.text(), "read me out loud");
+    let plain = server.get(&format!("/p/{short}/raw.txt")).await;
+    plain.assert_status_ok();
+    assert_eq!(plain.text(), "read me out loud");
+    let view = server.get(&format!("/p/{short}")).await;
+    view.assert_status_ok();
+    assert!(view.text().contains(&format!("<code>{short}</code>")));
+
+    server
+        .get("/api/v1/p/zzzzzzz")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unicode_names_keep_their_letters_in_slugs_and_downloads() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4228)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=%E5%A0%B1%E5%91%8A%20Q3.md")
+        .add_header("X-Paste-Token", "tok")
+        .text("# 第三四半期")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id").to_string();
+    assert!(
+        created["path"]
+            .as_str()
+            .expect("path")
+            .ends_with("__報告-q3.md")
+    );
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["slug"], "報告-q3");
+
+    let resolved = server.get("/s/%E5%A0%B1%E5%91%8A-q3").await;
+    resolved.assert_status(StatusCode::FOUND);
+    assert_eq!(
+        resolved.header("location").to_str().expect("location"),
+        format!("/p/{id}")
+    );
+
+    let raw = server.get(&format!("/api/v1/p/{id}/raw?ct=original")).await;
+    raw.assert_status_ok();
+    let disposition = raw
+        .header("content-disposition")
+        .to_str()
+        .expect("ascii header")
+        .to_string();
+    assert!(
+        disposition.ends_with("filename*=UTF-8''%E5%A0%B1%E5%91%8A-q3.md"),
+        "{disposition}"
+    );
+
+    server
+        .post("/api/v1/paste?name=%EF%BC%8E%EF%BC%8E%EF%BC%8Fetc")
+        .add_header("X-Paste-Token", "tok")
+        .text("x")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn pastes_are_stored_under_the_extension_their_name_or_type_implies() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4229)))),
+    )
+    .expect("server");
+
+    for (query, content_type, ext, stored_type) in [
+        (
+            "name=ci.yml",
+            "application/x-www-form-urlencoded",
+            ".yml",
+            "application/yaml; charset=utf-8",
+        ),
+        (
+            "name=main.rs",
+            "text/plain",
+            ".rs",
+            "text/x-rust; charset=utf-8",
+        ),
+        ("", "text/json", ".json", "application/json; charset=utf-8"),
+        ("name=page.html", "text/html", ".html", "text/html"),
+    ] {
+        let created: serde_json::Value = server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-Paste-Token", "tok")
+            .text("key: value")
+            .content_type(content_type)
+            .await
+            .json();
+        let path = created["path"].as_str().expect("path");
+        assert!(path.ends_with(ext), "{path}");
+        let id = created["id"].as_str().expect("id");
+        let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+        assert_eq!(meta["content_type"], stored_type);
+
+        let raw = server.get(&format!("/api/v1/p/{id}/raw?ct=original")).await;
+        assert_eq!(
+            raw.header("content-type").to_str().expect("type"),
+            stored_type
+        );
+        let disposition = raw.header("content-disposition");
+        let disposition = disposition.to_str().expect("disposition");
+        // Pages and images that could run script still download.
+        assert_eq!(disposition.starts_with("inline"), ext != ".html");
+    }
+}
+
+#[tokio::test]
+async fn lang_hint_renders_code_instead_of_sniffed_markdown() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4230)))),
+    )
+    .expect("server");
+
+    // The attribute line reads as a markdown heading to the sniffer.
+    let source = "#[derive(Debug)]\nstruct Point { x: i32 }\n";
+    let create = |query: &'static str| {
+        server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-Paste-Token", "tok")
+            .text(source)
+    };
+    let hinted: serde_json::Value = create("name=point&lang=Rust").await.json();
+    let id = hinted["id"].as_str().expect("id");
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["lang"], "rust");
+    let page = server.get(&format!("/p/{id}")).await.text();
+    assert!(page.contains("<code class=\"language-rust\">"), "{page}");
+    assert!(page.contains("hl-storage hl-type"));
+    assert!(!page.contains("<p>#[derive"));
+
+    let sniffed: serde_json::Value = create("name=point").await.json();
+    let id = sniffed["id"].as_str().expect("id");
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert!(meta.get("lang").is_none());
+    let page = server.get(&format!("/p/{id}")).await.text();
+    assert!(page.contains("<p>#[derive(Debug)]"), "{page}");
+
+    create("lang=%3Cscript%3E")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn pastes_print_standalone_and_export_through_the_pdf_command() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    // Stands in for `weasyprint - -`: the print page behind a PDF header.
+    cfg.pdf_command = Some("sed 1s/^/%PDF-1.7\\n/".to_string());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4231)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=standup.md")
+        .add_header("X-Paste-Token", "tok")
+        .text("# Agenda\n\n- ship it\n")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+
+    let view = server.get(&format!("/p/{id}")).await.text();
+    assert!(view.contains("media=\"print\""));
+
+    let print = server.get(&format!("/p/{id}/print")).await;
+    print.assert_status_ok();
+    let page = print.text();
+    assert!(page.contains("<h1>Agenda</h1>"));
+    assert!(page.contains("<style>") && page.contains("@page"));
+    assert!(!page.contains("<script") && !page.contains("https://"));
+
+    let pdf = server.get(&format!("/p/{id}/pdf")).await;
+    pdf.assert_status_ok();
+    assert_eq!(
+        pdf.header("content-type").to_str().expect("type"),
+        "application/pdf"
+    );
+    assert_eq!(
+        pdf.header("content-disposition")
+            .to_str()
+            .expect("disposition"),
+        "inline; filename=\"standup.pdf\""
+    );
+    let bytes = pdf.as_bytes();
+    assert!(bytes.starts_with(b"%PDF-1.7\n<!doctype html>"));
+    assert!(String::from_utf8_lossy(bytes).contains("<h1>Agenda</h1>"));
+
+    let etag = pdf.header("etag").to_str().expect("etag").to_string();
+    server
+        .get(&format!("/p/{id}/pdf"))
+        .add_header("If-None-Match", etag)
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn protect_ui_sends_browsers_to_login_and_keeps_the_session() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.protect_ui = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4233)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=minutes.md")
+        .add_header("X-Paste-Token", "tok")
+        .text("# Minutes\n")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let bounced = server
+        .get(&format!("/p/{id}"))
+        .add_header("Accept", "text/html")
+        .await;
+    bounced.assert_status(StatusCode::FOUND);
+    assert_eq!(
+        bounced.header("location"),
+        format!("/login?next=%2Fp%2F{id}")
+    );
+    server
+        .get(&format!("/p/{id}/raw.txt"))
+        .await
+        .assert_status_unauthorized();
+    server.get("/").await.assert_status_unauthorized();
+    server
+        .get(&format!("/p/{id}"))
+        .add_header("X-Paste-Token", "tok")
+        .await
+        .assert_status_ok();
+
+    // The API, health checks and the login page itself stay reachable.
+    server.get("/healthz").await.assert_status_ok();
+    server
+        .get(&format!("/api/v1/p/{id}"))
+        .await
+        .assert_status_ok();
+    let page = server.get("/login?next=/recent").await;
+    page.assert_status_ok();
+    assert!(page.text().contains("name=\"next\" value=\"/recent\""));
+    assert!(page.text().contains("Token <input"));
+
+    server
+        .post("/login")
+        .form(&serde_json::json!({ "key": "wrong", "next": "/recent" }))
+        .await
+        .assert_status_unauthorized();
+    let login = server
+        .post("/login")
+        .form(&serde_json::json!({ "key": "tok", "next": format!("/p/{id}") }))
+        .await;
+    login.assert_status(StatusCode::FOUND);
+    assert_eq!(login.header("location"), format!("/p/{id}"));
+    let set_cookie = login.header("set-cookie");
+    let set_cookie = set_cookie.to_str().expect("cookie");
+    assert!(set_cookie.contains("HttpOnly") && set_cookie.contains("SameSite=Lax"));
+    let cookie = set_cookie.split(';').next().expect("pair").to_string();
+
+    let view = server
+        .get(&format!("/p/{id}"))
+        .add_header("Accept", "text/html")
+        .add_header("Cookie", &cookie)
+        .await;
+    view.assert_status_ok();
+    assert!(view.text().contains("Minutes"));
+    server
+        .get("/")
+        .add_header("Cookie", &cookie)
+        .await
+        .assert_status_ok();
+
+    let login = server
+        .post("/login")
+        .form(&serde_json::json!({ "key": "tok", "next": "//evil.example/" }))
+        .await;
+    assert_eq!(login.header("location"), "/");
+
+    server
+        .post("/logout")
+        .add_header("Cookie", &cookie)
+        .await
+        .assert_status(StatusCode::FOUND);
+    server
+        .get("/")
+        .add_header("Cookie", &cookie)
+        .await
+        .assert_status_unauthorized();
+}
+
+#[tokio::test]
+async fn require_token_for_read_closes_api_reads_to_anonymous_clients() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.require_token_for_read = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4234)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=secret.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("salaries\n")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    for path in [
+        format!("/api/v1/p/{id}"),
+        format!("/api/v1/p/{id}/raw"),
+        format!("/api/v1/p/{id}/tail"),
+        "/api/v1/recent".to_string(),
+        "/api".to_string(),
+    ] {
+        server.get(&path).await.assert_status_unauthorized();
+        server
+            .get(&path)
+            .add_header("X-Paste-Token", "wrong")
+            .await
+            .assert_status_unauthorized();
+        server
+            .get(&path)
+            .add_header("X-Paste-Token", "tok")
+            .await
+            .assert_status_ok();
+    }
+    // Tools that only speak HTTP Basic send the token as the password.
+    let raw = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("Authorization", "Basic OnRvaw==")
+        .await;
+    raw.assert_status_ok();
+    assert_eq!(raw.text(), "salaries\n");
+
+    server.get("/healthz").await.assert_status_ok();
+    server.get("/openapi.yaml").await.assert_status_ok();
+}
+
+#[test]
+fn require_token_for_read_needs_a_token() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.require_token_for_read = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    assert!(matches!(
+        preflight::build_state(cfg),
+        Err(AppError::BadRequest(_))
+    ));
+}
+
+#[test]
+fn protect_ui_needs_something_to_log_in_with() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.protect_ui = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    assert!(matches!(
+        preflight::build_state(cfg),
+        Err(AppError::BadRequest(_))
+    ));
+}
+
+#[tokio::test]
+async fn vanity_slug_is_reserved_and_served_under_s() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4111)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=notes.md&slug=weekly-standup")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Type", "text/markdown")
+        .text("# standup")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["slug"], "weekly-standup");
+
+    let resolved = server.get("/s/weekly-standup").await;
+    resolved.assert_status(StatusCode::FOUND);
+    assert_eq!(
+        resolved.header("location").to_str().expect("location"),
+        format!("/p/{id}")
+    );
+
+    server
+        .post("/api/v1/paste?slug=weekly-standup")
+        .add_header("X-Paste-Token", "tok")
+        .text("again")
+        .await
+        .assert_status(StatusCode::CONFLICT);
+    server
+        .post("/api/v1/paste?slug=Weekly%20Standup")
+        .add_header("X-Paste-Token", "tok")
+        .text("again")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    // A derived slug steps around the reserved one.
+    let derived = server
+        .post("/api/v1/paste?name=weekly-standup.md")
+        .add_header("X-Paste-Token", "tok")
+        .text("other")
+        .await;
+    derived.assert_status(StatusCode::CREATED);
+    let derived_id = derived.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let derived_meta: serde_json::Value =
+        server.get(&format!("/api/v1/p/{derived_id}")).await.json();
+    assert_eq!(derived_meta["slug"], "weekly-standup-2");
+
+    server
+        .get("/s/nobody-took-this")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
 #[tokio::test]
 async fn slug_alias_redirects_to_canonical_id() {
     let dir = tempfile::tempdir().expect("tempdir");
@@ -431,7 +963,13 @@ async fn readyz_stays_ok_during_git_lock_contention() {
     )
     .expect("server");
 
-    server.get("/readyz").await.assert_status(StatusCode::OK);
+    let ready = server.get("/readyz").await;
+    ready.assert_status(StatusCode::OK);
+    let body: serde_json::Value = ready.json();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["checks"]["lock"]["status"], "warn");
+    assert_eq!(body["checks"]["repo"]["status"], "ok");
+    assert_eq!(body["checks"]["remote"]["status"], "skipped");
 }
 
 #[tokio::test]
@@ -475,27 +1013,139 @@ async fn idempotency_key_replays_and_conflicts_on_payload_mismatch() {
 }
 
 #[tokio::test]
-async fn api_keys_enforce_scopes_and_rate_limits() {
+async fn dedupe_returns_the_existing_paste_for_identical_uploads() {
     let dir = tempfile::tempdir().expect("tempdir");
     let mut cfg = test_cfg(dir.path());
-    cfg.token = None;
-    let keys_path = dir.path().join("keys.json");
-    write_api_keys_file(&keys_path);
-    cfg.api_keys_file = Some(keys_path);
-
+    cfg.dedupe = true;
     preflight::run_preflight(&cfg).expect("preflight");
     let state = Arc::new(preflight::build_state(cfg).expect("state"));
     let server = TestServer::new(
-        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4107)))),
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4226)))),
     )
     .expect("server");
 
-    server
-        .get("/api")
-        .await
-        .assert_status(StatusCode::UNAUTHORIZED);
-    server
-        .get("/api")
+    let first = server
+        .post("/api/v1/paste?name=ci.log&tag=ci")
+        .add_header("X-Paste-Token", "tok")
+        .text("build failed\n")
+        .await;
+    first.assert_status(StatusCode::CREATED);
+    let first: serde_json::Value = first.json();
+    assert_eq!(first["deduplicated"], false);
+
+    let retry = server
+        .post("/api/v1/paste?name=ci-retry.log&tag=ci")
+        .add_header("X-Paste-Token", "tok")
+        .text("build failed\n")
+        .await;
+    retry.assert_status(StatusCode::OK);
+    let retry: serde_json::Value = retry.json();
+    assert_eq!(retry["id"], first["id"]);
+    assert_eq!(retry["commit"], first["commit"]);
+    assert_eq!(retry["deduplicated"], true);
+
+    // Other options, or opting out, still make a new paste.
+    for query in [
+        "name=ci.log&tag=nightly",
+        "name=ci.log&tag=ci&visibility=unlisted",
+        "name=ci.log&tag=ci&dedupe=false",
+    ] {
+        let created = server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-Paste-Token", "tok")
+            .text("build failed\n")
+            .await;
+        created.assert_status(StatusCode::CREATED);
+        assert_ne!(created.json::<serde_json::Value>()["id"], first["id"]);
+    }
+
+    let newest: serde_json::Value = server
+        .post("/api/v1/paste?name=ci.log&tag=ci")
+        .add_header("X-Paste-Token", "tok")
+        .text("build failed\n")
+        .await
+        .json();
+    assert_eq!(newest["deduplicated"], true);
+    assert_ne!(newest["id"], first["id"], "the newest match wins");
+
+    let log = std::process::Command::new("git")
+        .args(["rev-list", "--count", "HEAD"])
+        .current_dir(dir.path().join("repo"))
+        .output()
+        .expect("git rev-list");
+    assert_eq!(String::from_utf8_lossy(&log.stdout).trim(), "5");
+}
+
+#[tokio::test]
+async fn dedupe_keeps_each_keys_pastes_apart() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.dedupe = true;
+    cfg.token.clear();
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"a","key":"a-key","scopes":["paste:create","paste:read","recent:read"]},{"name":"b","key":"b-key","scopes":["paste:create","paste:read","recent:read"]}]}"#,
+    )
+    .expect("write keys");
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4244)))),
+    )
+    .expect("server");
+    let upload = |key: &'static str| {
+        server
+            .post("/api/v1/paste?name=ci.log")
+            .add_header("X-API-Key", key)
+            .text("build failed\n")
+    };
+
+    let from_a = upload("a-key").await;
+    from_a.assert_status(StatusCode::CREATED);
+    let from_a: serde_json::Value = from_a.json();
+    let from_b = upload("b-key").await;
+    from_b.assert_status(StatusCode::CREATED);
+    let from_b: serde_json::Value = from_b.json();
+    assert_ne!(from_b["id"], from_a["id"]);
+    assert_eq!(from_b["deduplicated"], false);
+
+    let again = upload("b-key").await;
+    again.assert_status(StatusCode::OK);
+    assert_eq!(again.json::<serde_json::Value>()["id"], from_b["id"]);
+
+    let mine: serde_json::Value = server
+        .get("/api/v1/mine")
+        .add_header("X-API-Key", "b-key")
+        .await
+        .json();
+    assert_eq!(mine.as_array().expect("list").len(), 1, "{mine}");
+    assert_eq!(mine[0]["id"], from_b["id"]);
+}
+
+#[tokio::test]
+async fn api_keys_enforce_scopes_and_rate_limits() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    let keys_path = dir.path().join("keys.json");
+    write_api_keys_file(&keys_path);
+    cfg.api_keys_file = Some(keys_path);
+
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4107)))),
+    )
+    .expect("server");
+
+    server
+        .get("/api")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .get("/api")
         .add_header("X-API-Key", "reader-key")
         .await
         .assert_status(StatusCode::OK);
@@ -542,3 +1192,4465 @@ async fn api_keys_enforce_scopes_and_rate_limits() {
         .await
         .assert_status(StatusCode::TOO_MANY_REQUESTS);
 }
+
+#[tokio::test]
+async fn soak_round_trips_against_live_server() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            http::app(state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .expect("serve");
+    });
+
+    let report = lanpaste::soak::run_soak(lanpaste::config::SoakCmd {
+        target: format!("http://{addr}"),
+        rate: 20.0,
+        duration: std::time::Duration::from_millis(800),
+        size_dist: lanpaste::soak::parse_size_dist("256=1,4k=1").expect("dist"),
+        mix: lanpaste::soak::parse_mix("create=2,read=2,recent=1").expect("mix"),
+        concurrency: 4,
+        tag: "soak".to_string(),
+        token: Some("tok".to_string()),
+        api_key: None,
+    })
+    .await
+    .expect("soak");
+
+    assert!(report.creates + report.reads + report.recents > 0);
+    assert_eq!(report.errors, 0, "{}", report.summary());
+    assert!(report.violations.is_empty(), "{}", report.summary());
+}
+
+#[tokio::test]
+async fn verify_reads_flags_tampered_paste() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.verify_reads = VerifyMode::Always;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4111)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=integrity.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("original bytes")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let created_json: serde_json::Value = created.json();
+    let id = created_json["id"].as_str().expect("id");
+    let path = created_json["path"].as_str().expect("path");
+
+    server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_status(StatusCode::OK);
+
+    fs::write(dir.path().join("repo").join(path), b"tampered bytes").expect("tamper");
+    let raw = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    raw.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(raw.json::<serde_json::Value>()["error"], "integrity_error");
+    server
+        .get(&format!("/p/{id}"))
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn large_pastes_are_stored_compressed_and_read_back_transparently() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.zstd_above = Some(1024);
+    cfg.verify_reads = VerifyMode::Always;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4227)))),
+    )
+    .expect("server");
+
+    let log: String = (0..2000).map(|i| format!("step {i}: ok\n")).collect();
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=build.log")
+        .add_header("X-Paste-Token", "tok")
+        .text(log.clone())
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id").to_string();
+    let path = created["path"].as_str().expect("path").to_string();
+    assert!(path.ends_with(".log.zst"), "{path}");
+    assert_eq!(created["size"], log.len());
+
+    let on_disk = fs::read(dir.path().join("repo").join(&path)).expect("paste file");
+    assert!(on_disk.len() < log.len() / 4);
+    assert_eq!(
+        zstd::decode_all(on_disk.as_slice()).expect("zstd"),
+        log.as_bytes()
+    );
+
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["sha256"], hex::encode(Sha256::digest(log.as_bytes())));
+    assert_eq!(meta["stored"]["encoding"], "zstd");
+    assert_eq!(meta["stored"]["size"], on_disk.len());
+
+    let raw = server.get(&format!("/api/v1/p/{id}/raw?ct=original")).await;
+    raw.assert_status_ok();
+    assert_eq!(raw.text(), log);
+    assert!(
+        raw.header("content-disposition")
+            .to_str()
+            .expect("disposition")
+            .ends_with(".log\"")
+    );
+    let range = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("Range", "bytes=0-11")
+        .await;
+    range.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert_eq!(range.text(), "step 0: ok\ns");
+    let tail = server
+        .get(&format!("/api/v1/p/{id}/tail?lines=1"))
+        .await
+        .text();
+    assert_eq!(tail, "step 1999: ok\n");
+
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("done\n")
+        .await
+        .assert_status_ok();
+    let grown = server.get(&format!("/api/v1/p/{id}/raw")).await.text();
+    assert_eq!(grown, format!("{log}done\n"));
+
+    // Small pastes are not worth it.
+    let small: serde_json::Value = server
+        .post("/api/v1/paste?name=short.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("hello")
+        .await
+        .json();
+    assert!(!small["path"].as_str().expect("path").ends_with(".zst"));
+}
+
+#[tokio::test]
+async fn tail_and_range_preview_large_paste() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4112)))),
+    )
+    .expect("server");
+
+    let body: String = (1..=1000).map(|n| format!("log line {n}\n")).collect();
+    let created = server
+        .post("/api/v1/paste?name=build.log")
+        .add_header("X-Paste-Token", "tok")
+        .text(body.clone())
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let tail = server.get(&format!("/api/v1/p/{id}/tail?lines=3")).await;
+    tail.assert_status(StatusCode::OK);
+    assert_eq!(tail.text(), "log line 998\nlog line 999\nlog line 1000\n");
+    assert!(
+        tail.header("content-type")
+            .to_str()
+            .expect("content-type")
+            .starts_with("text/plain")
+    );
+    let default_tail = server.get(&format!("/api/v1/p/{id}/tail")).await;
+    assert_eq!(default_tail.text().lines().count(), 200);
+
+    let len = body.len();
+    let partial = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("Range", "bytes=-14")
+        .await;
+    partial.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert_eq!(partial.text(), "log line 1000\n");
+    assert_eq!(
+        partial.header("content-range").to_str().expect("range"),
+        format!("bytes {}-{}/{len}", len - 14, len - 1)
+    );
+
+    let first = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("Range", "bytes=0-9")
+        .await;
+    first.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert_eq!(first.text(), "log line 1");
+
+    let unsatisfiable = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("Range", format!("bytes={len}-"))
+        .await;
+    unsatisfiable.assert_status(StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        unsatisfiable
+            .header("content-range")
+            .to_str()
+            .expect("range"),
+        format!("bytes */{len}")
+    );
+
+    let full = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    full.assert_status(StatusCode::OK);
+    assert_eq!(
+        full.header("accept-ranges").to_str().expect("accept"),
+        "bytes"
+    );
+    assert_eq!(
+        full.header("content-length").to_str().expect("length"),
+        len.to_string()
+    );
+    assert_eq!(full.text(), body);
+}
+
+#[tokio::test]
+async fn follow_streams_appended_bytes_until_idle() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4113)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=job.log")
+        .add_header("X-Paste-Token", "tok")
+        .text("start\n")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let created_json: serde_json::Value = created.json();
+    let id = created_json["id"].as_str().expect("id").to_string();
+    let file = dir
+        .path()
+        .join("repo")
+        .join(created_json["path"].as_str().expect("path"));
+    let log = file.clone();
+
+    let writer = tokio::spawn(async move {
+        for line in ["step 1\n", "step 2\n"] {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            let mut f = fs::OpenOptions::new()
+                .append(true)
+                .open(&file)
+                .expect("open");
+            std::io::Write::write_all(&mut f, line.as_bytes()).expect("append");
+        }
+    });
+
+    let follow = server.get(&format!("/api/v1/p/{id}/follow?idle=1s")).await;
+    writer.await.expect("writer");
+    follow.assert_status(StatusCode::OK);
+    assert_eq!(follow.text(), "step 1\nstep 2\n");
+
+    let replay = server
+        .get(&format!("/api/v1/p/{id}/follow?from=0&idle=0s"))
+        .await;
+    assert_eq!(replay.text(), "start\nstep 1\nstep 2\n");
+
+    // More than one read's worth still arrives whole, a chunk at a time.
+    let big = "0123456789abcdef\n".repeat(12_000);
+    let mut f = fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .expect("open");
+    std::io::Write::write_all(&mut f, big.as_bytes()).expect("append");
+    let replay = server
+        .get(&format!("/api/v1/p/{id}/follow?from=0&idle=0s"))
+        .await;
+    assert_eq!(replay.text(), format!("start\nstep 1\nstep 2\n{big}"));
+
+    for idle in ["soon", "2h"] {
+        server
+            .get(&format!("/api/v1/p/{id}/follow?idle={idle}"))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn raw_ct_original_serves_recorded_content_type() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4114)))),
+    )
+    .expect("server");
+
+    let json_paste = server
+        .post("/api/v1/paste?name=report.json")
+        .add_header("X-Paste-Token", "tok")
+        .text("{\"ok\":true}")
+        .content_type("application/json")
+        .await;
+    json_paste.assert_status(StatusCode::CREATED);
+    let id = json_paste.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let default_raw = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    assert_eq!(
+        default_raw.header("content-type").to_str().expect("ct"),
+        "application/octet-stream"
+    );
+
+    let original = server.get(&format!("/api/v1/p/{id}/raw?ct=original")).await;
+    original.assert_status(StatusCode::OK);
+    assert_eq!(
+        original.header("content-type").to_str().expect("ct"),
+        "application/json"
+    );
+    assert_eq!(
+        original
+            .header("content-disposition")
+            .to_str()
+            .expect("disposition"),
+        "inline; filename=\"report.json\""
+    );
+    assert_eq!(original.json::<serde_json::Value>()["ok"], true);
+
+    let html_paste = server
+        .post("/api/v1/paste?name=page.html")
+        .add_header("X-Paste-Token", "tok")
+        .text("<script>alert(1)</script>")
+        .content_type("text/html")
+        .await;
+    let html_id = html_paste.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let html = server
+        .get(&format!("/api/v1/p/{html_id}/raw?ct=original"))
+        .await;
+    assert_eq!(
+        html.header("content-type").to_str().expect("ct"),
+        "text/html"
+    );
+    assert!(
+        html.header("content-disposition")
+            .to_str()
+            .expect("disposition")
+            .starts_with("attachment;")
+    );
+    assert_eq!(
+        html.header("content-security-policy")
+            .to_str()
+            .expect("csp"),
+        "sandbox"
+    );
+
+    server
+        .get(&format!("/api/v1/p/{id}/raw?ct=guess"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn raw_with_a_file_name_downloads_under_the_stored_slug() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4232)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=report.json")
+        .add_header("X-Paste-Token", "tok")
+        .text("{\"ok\":true}")
+        .content_type("application/json")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    // The name in the path is cosmetic; the header comes from the slug.
+    let named = server
+        .get(&format!("/api/v1/p/{id}/raw/whatever.bin"))
+        .await;
+    named.assert_status(StatusCode::OK);
+    assert_eq!(
+        named.header("content-type").to_str().expect("ct"),
+        "application/octet-stream"
+    );
+    assert_eq!(
+        named
+            .header("content-disposition")
+            .to_str()
+            .expect("disposition"),
+        "attachment; filename=\"report.json\""
+    );
+    assert_eq!(named.text(), "{\"ok\":true}");
+
+    let original = server
+        .get(&format!("/api/v1/p/{id}/raw/report.json?ct=original"))
+        .await;
+    assert_eq!(
+        original.header("content-type").to_str().expect("ct"),
+        "application/json"
+    );
+    assert_eq!(
+        original
+            .header("content-disposition")
+            .to_str()
+            .expect("disposition"),
+        "attachment; filename=\"report.json\""
+    );
+
+    let partial = server
+        .get(&format!("/api/v1/p/{id}/raw/report.json"))
+        .add_header("Range", "bytes=0-4")
+        .await;
+    partial.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert_eq!(partial.text(), "{\"ok\"");
+    assert!(
+        partial
+            .header("content-disposition")
+            .to_str()
+            .expect("disposition")
+            .starts_with("attachment; filename=")
+    );
+
+    server
+        .get("/api/v1/p/01ARZ3NDEKTSV4RRFFQ69G5FAV/raw/report.json")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn blob_route_resolves_current_content_by_sha256() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4116)))),
+    )
+    .expect("server");
+    let sha = |bytes: &str| hex::encode(Sha256::digest(bytes.as_bytes()));
+
+    let created = server
+        .post("/api/v1/paste?name=artifact.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("build 42\n")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let blob = server
+        .get(&format!("/api/v1/blob/{}", sha("build 42\n")))
+        .await;
+    blob.assert_status_ok();
+    assert_eq!(blob.text(), "build 42\n");
+    assert_eq!(blob.header("x-paste-id").to_str().expect("id"), id);
+    server
+        .get(&format!(
+            "/api/v1/blob/{}",
+            sha("build 42\n").to_ascii_uppercase()
+        ))
+        .await
+        .assert_status_ok();
+
+    // The old hash stops resolving once the paste's content moves on.
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("done\n")
+        .await
+        .assert_status_ok();
+    server
+        .get(&format!("/api/v1/blob/{}", sha("build 42\n")))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    let grown = server
+        .get(&format!("/api/v1/blob/{}", sha("build 42\ndone\n")))
+        .await;
+    grown.assert_status_ok();
+    assert_eq!(grown.text(), "build 42\ndone\n");
+
+    server
+        .get("/api/v1/blob/not-a-hash")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn append_grows_text_paste_with_one_commit_per_append() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.max_total_bytes = 64;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4115)))),
+    )
+    .expect("server");
+    let repo = dir.path().join("repo");
+    let commit_count = || {
+        let out = std::process::Command::new("git")
+            .args(["rev-list", "--count", "HEAD"])
+            .current_dir(&repo)
+            .output()
+            .expect("git rev-list");
+        String::from_utf8_lossy(&out.stdout)
+            .trim()
+            .parse::<u32>()
+            .expect("count")
+    };
+
+    let created = server
+        .post("/api/v1/paste?name=job.log")
+        .add_header("X-Paste-Token", "tok")
+        .text("boot\n")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let before = commit_count();
+
+    let first = server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("step 1\n")
+        .await;
+    first.assert_status(StatusCode::OK);
+    let first_json: serde_json::Value = first.json();
+    assert_eq!(first_json["offset"], 5);
+    assert_eq!(first_json["size"], 12);
+
+    let second = server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("step 2\n")
+        .await;
+    second.assert_status(StatusCode::OK);
+    let second_json: serde_json::Value = second.json();
+    assert_eq!(second_json["offset"], 12);
+    assert_ne!(second_json["commit"], first_json["commit"]);
+    assert_eq!(commit_count(), before + 2);
+
+    let raw = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    assert_eq!(raw.text(), "boot\nstep 1\nstep 2\n");
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["size"], 19);
+    assert_eq!(meta["sha256"], second_json["sha256"]);
+    assert_eq!(meta["commit"], second_json["commit"]);
+
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .text("unauthorized\n")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("x".repeat(64))
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    server
+        .post("/api/v1/p/01ZZZZZZZZZZZZZZZZZZZZZZZZ/append")
+        .add_header("X-Paste-Token", "tok")
+        .text("nope\n")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    assert_eq!(commit_count(), before + 2);
+    assert_eq!(
+        server.get(&format!("/api/v1/p/{id}/raw")).await.text(),
+        "boot\nstep 1\nstep 2\n"
+    );
+
+    let binary = server
+        .post("/api/v1/paste?name=blob.bin")
+        .add_header("X-Paste-Token", "tok")
+        .bytes(vec![0u8, 1, 2].into())
+        .content_type("application/octet-stream")
+        .await;
+    let binary_id = binary.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    server
+        .post(&format!("/api/v1/p/{binary_id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("more")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn conditional_requests_return_not_modified() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4116)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=poll.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("v1\n")
+        .await;
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    for path in [
+        format!("/api/v1/p/{id}/raw"),
+        format!("/api/v1/p/{id}"),
+        format!("/p/{id}"),
+        format!("/p/{id}/raw.txt"),
+    ] {
+        let first = server.get(&path).await;
+        first.assert_status(StatusCode::OK);
+        let etag = first.header("etag").to_str().expect("etag").to_string();
+        let last_modified = first
+            .header("last-modified")
+            .to_str()
+            .expect("last-modified")
+            .to_string();
+
+        let by_etag = server.get(&path).add_header("If-None-Match", etag).await;
+        by_etag.assert_status(StatusCode::NOT_MODIFIED);
+        assert!(by_etag.as_bytes().is_empty());
+
+        server
+            .get(&path)
+            .add_header("If-Modified-Since", last_modified)
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+        server
+            .get(&path)
+            .add_header("If-None-Match", "\"stale\"")
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    let raw = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    let etag = raw.header("etag").to_str().expect("etag").to_string();
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("v2\n")
+        .await
+        .assert_status(StatusCode::OK);
+    let after = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("If-None-Match", etag)
+        .await;
+    after.assert_status(StatusCode::OK);
+    assert_eq!(after.text(), "v1\nv2\n");
+}
+
+#[tokio::test]
+async fn range_resume_honors_if_range_and_head() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4117)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=big.log")
+        .add_header("X-Paste-Token", "tok")
+        .text("0123456789abcdef")
+        .await;
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let raw_path = format!("/api/v1/p/{id}/raw");
+
+    let head = server.method(axum::http::Method::HEAD, &raw_path).await;
+    head.assert_status(StatusCode::OK);
+    assert_eq!(head.header("content-length").to_str().expect("len"), "16");
+    assert_eq!(
+        head.header("accept-ranges").to_str().expect("ranges"),
+        "bytes"
+    );
+    assert!(head.as_bytes().is_empty());
+    let etag = head.header("etag").to_str().expect("etag").to_string();
+
+    let resumed = server
+        .get(&raw_path)
+        .add_header("Range", "bytes=10-")
+        .add_header("If-Range", etag.clone())
+        .await;
+    resumed.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resumed.text(), "abcdef");
+    assert_eq!(resumed.header("content-length").to_str().expect("len"), "6");
+    assert_eq!(
+        resumed.header("content-range").to_str().expect("range"),
+        "bytes 10-15/16"
+    );
+
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("ghij")
+        .await
+        .assert_status(StatusCode::OK);
+    let changed = server
+        .get(&raw_path)
+        .add_header("Range", "bytes=10-")
+        .add_header("If-Range", etag)
+        .await;
+    changed.assert_status(StatusCode::OK);
+    assert_eq!(changed.text(), "0123456789abcdefghij");
+}
+
+#[tokio::test]
+async fn lock_lease_gates_appends_until_released() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4118)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=runbook.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("1. drain\n")
+        .await;
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let lock_path = format!("/api/v1/p/{id}/lock");
+    let append_path = format!("/api/v1/p/{id}/append");
+
+    let lock = server
+        .post(&format!("{lock_path}?ttl=5m&holder=alice"))
+        .add_header("X-Paste-Token", "tok")
+        .await;
+    lock.assert_status(StatusCode::OK);
+    let lock_json: serde_json::Value = lock.json();
+    let lease = lock_json["lease"].as_str().expect("lease").to_string();
+    assert_eq!(lock_json["holder"], "alice");
+
+    let blocked = server
+        .post(&append_path)
+        .add_header("X-Paste-Token", "tok")
+        .text("2. reboot\n")
+        .await;
+    blocked.assert_status(StatusCode::LOCKED);
+    assert_eq!(blocked.json::<serde_json::Value>()["error"], "locked");
+    server
+        .post(&lock_path)
+        .add_header("X-Paste-Token", "tok")
+        .await
+        .assert_status(StatusCode::LOCKED);
+
+    server
+        .post(&append_path)
+        .add_header("X-Paste-Token", "tok")
+        .add_header("X-Paste-Lease", lease.clone())
+        .text("2. reboot\n")
+        .await
+        .assert_status(StatusCode::OK);
+    let renewed = server
+        .post(&lock_path)
+        .add_header("X-Paste-Token", "tok")
+        .add_header("X-Paste-Lease", lease.clone())
+        .await;
+    renewed.assert_status(StatusCode::OK);
+    assert_eq!(renewed.json::<serde_json::Value>()["lease"], lease.as_str());
+
+    server
+        .delete(&lock_path)
+        .add_header("X-Paste-Token", "tok")
+        .add_header("X-Paste-Lease", "not-the-lease")
+        .await
+        .assert_status(StatusCode::LOCKED);
+    server
+        .delete(&lock_path)
+        .add_header("X-Paste-Token", "tok")
+        .add_header("X-Paste-Lease", lease)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .post(&append_path)
+        .add_header("X-Paste-Token", "tok")
+        .text("3. verify\n")
+        .await
+        .assert_status(StatusCode::OK);
+    server
+        .post(&format!("{lock_path}?ttl=2h"))
+        .add_header("X-Paste-Token", "tok")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn streamed_upload_hashes_and_cleans_up_staging() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.max_bytes = 64 * 1024;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4140)))),
+    )
+    .expect("server");
+    let staging = dir.path().join("tmp");
+
+    let body = "0123456789abcdef\n".repeat(2048);
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=big.txt")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Idempotency-Key", "stream-1")
+        .text(body.clone())
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["size"], body.len());
+    assert_eq!(meta["sha256"], hex::encode(Sha256::digest(body.as_bytes())));
+    assert_eq!(
+        server.get(&format!("/api/v1/p/{id}/raw")).await.text(),
+        body
+    );
+
+    let replay = server
+        .post("/api/v1/paste?name=big.txt")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Idempotency-Key", "stream-1")
+        .text(body.clone())
+        .await;
+    replay.assert_status_ok();
+    assert_eq!(replay.json::<serde_json::Value>()["id"], id);
+
+    server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("x".repeat(64 * 1024 + 1))
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+    let leftovers: Vec<_> = fs::read_dir(&staging).expect("tmp dir").collect();
+    assert!(leftovers.is_empty(), "staging not cleaned: {leftovers:?}");
+}
+
+#[tokio::test]
+async fn allowlist_matches_mapped_and_private_clients() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.allow_cidr = vec![];
+    cfg.allow_private = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let post = |addr: &str| {
+        let addr: SocketAddr = addr.parse().expect("addr");
+        let server =
+            TestServer::new(http::app(state.clone()).layer(MockConnectInfo(addr))).expect("server");
+        async move {
+            server
+                .post("/api/v1/paste")
+                .add_header("X-Paste-Token", "tok")
+                .text("hi")
+                .await
+                .status_code()
+        }
+    };
+
+    assert_eq!(post("[::ffff:192.168.4.2]:4150").await, StatusCode::CREATED);
+    assert_eq!(post("[fd00::12]:4151").await, StatusCode::CREATED);
+    assert_eq!(post("[fe80::1%3]:4152").await, StatusCode::CREATED);
+    assert_eq!(post("[::ffff:8.8.8.8]:4153").await, StatusCode::FORBIDDEN);
+    assert_eq!(post("[2001:db8::1]:4154").await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn compression_applies_to_text_but_not_ranges() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.compress = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4160)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=log.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("line of build output\n".repeat(500))
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+
+    for path in [
+        format!("/api/v1/p/{id}/raw?ct=original"),
+        format!("/p/{id}/raw.txt"),
+        format!("/p/{id}"),
+        "/api".to_string(),
+    ] {
+        let response = server
+            .get(&path)
+            .add_header("Accept-Encoding", "gzip")
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.header("content-encoding"), "gzip", "{path}");
+    }
+
+    let ranged = server
+        .get(&format!("/api/v1/p/{id}/raw?ct=original"))
+        .add_header("Accept-Encoding", "gzip")
+        .add_header("Range", "bytes=0-99")
+        .await;
+    ranged.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert!(ranged.maybe_header("content-encoding").is_none());
+    assert_eq!(ranged.as_bytes().len(), 100);
+
+    let plain = server.get(&format!("/p/{id}/raw.txt")).await;
+    assert!(plain.maybe_header("content-encoding").is_none());
+}
+
+#[tokio::test]
+async fn compressed_uploads_are_decoded_and_limited() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.max_bytes = 4096;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4170)))),
+    )
+    .expect("server");
+    let gzip = |data: &[u8]| {
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(data).expect("gzip");
+        enc.finish().expect("gzip")
+    };
+
+    let log = "ok: step passed\n".repeat(200);
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=ci.log")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Encoding", "gzip")
+        .bytes(gzip(log.as_bytes()).into())
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["size"], log.len());
+    assert_eq!(meta["sha256"], hex::encode(Sha256::digest(log.as_bytes())));
+
+    let zstd_body = zstd::encode_all(log.as_bytes(), 3).expect("zstd");
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=ci.log")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Encoding", "zstd")
+        .bytes(zstd_body.into())
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    assert_eq!(server.get(&format!("/api/v1/p/{id}/raw")).await.text(), log);
+
+    // Tiny on the wire, over max-bytes once decoded.
+    server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Encoding", "gzip")
+        .bytes(gzip(&[b'a'; 64 * 1024]).into())
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+    server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Encoding", "gzip")
+        .bytes(b"not gzip at all".to_vec().into())
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Content-Encoding", "br")
+        .bytes(b"whatever".to_vec().into())
+        .await
+        .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn host_rules_allow_zone_and_deny_named_hosts() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let rules_path = dir.path().join("host-rules.json");
+    fs::write(
+        &rules_path,
+        serde_json::to_vec(&serde_json::json!({
+            "hosts": {
+                "ci-01.build.lan": ["10.20.0.7"],
+                "kiosk.lan": ["127.0.0.9"]
+            },
+            "rules": [
+                { "action": "deny", "host": "kiosk.lan" },
+                { "action": "allow", "host": "*.build.lan" }
+            ]
+        }))
+        .expect("rules"),
+    )
+    .expect("write rules");
+    let mut cfg = test_cfg(dir.path());
+    cfg.host_rules_file = Some(rules_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let post = |addr: [u8; 4]| {
+        let server = TestServer::new(
+            http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from((addr, 4180)))),
+        )
+        .expect("server");
+        async move {
+            server
+                .post("/api/v1/paste")
+                .add_header("X-Paste-Token", "tok")
+                .text("hi")
+                .await
+                .status_code()
+        }
+    };
+
+    // Outside --allow-cidr, but named by an allow rule.
+    assert_eq!(post([10, 20, 0, 7]).await, StatusCode::CREATED);
+    // Unnamed hosts fall back to the CIDR allowlist.
+    assert_eq!(post([127, 0, 0, 1]).await, StatusCode::CREATED);
+    assert_eq!(post([10, 20, 0, 8]).await, StatusCode::FORBIDDEN);
+    // Deny rules win even inside the allowlist.
+    assert_eq!(post([127, 0, 0, 9]).await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn encrypted_pastes_are_sealed_on_disk_and_readable_over_http() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let key_path = dir.path().join("paste.key");
+    fs::write(&key_path, "42".repeat(32)).expect("write key");
+    let mut cfg = test_cfg(dir.path());
+    cfg.encrypt_key_file = Some(key_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4190)))),
+    )
+    .expect("server");
+
+    let secret = "db_password=hunter2\nline two\n";
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=secret.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text(secret)
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    let path = created["path"].as_str().expect("path");
+
+    let stored = fs::read(dir.path().join("repo").join(path)).expect("stored");
+    assert!(stored.starts_with(b"LPE1"));
+    assert!(!String::from_utf8_lossy(&stored).contains("hunter2"));
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["encrypted"], true);
+    assert_eq!(meta["size"], secret.len());
+    assert_eq!(meta["sha256"], hex::encode(Sha256::digest(&stored)));
+
+    assert_eq!(
+        server.get(&format!("/api/v1/p/{id}/raw")).await.text(),
+        secret
+    );
+    assert!(
+        server
+            .get(&format!("/p/{id}"))
+            .await
+            .text()
+            .contains("hunter2")
+    );
+    let ranged = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("Range", "bytes=3-10")
+        .await;
+    ranged.assert_status(StatusCode::PARTIAL_CONTENT);
+    assert_eq!(ranged.text(), &secret[3..=10]);
+
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("line three\n")
+        .await
+        .assert_status_ok();
+    assert_eq!(
+        server
+            .get(&format!("/api/v1/p/{id}/tail?lines=2"))
+            .await
+            .text(),
+        "line two\nline three\n"
+    );
+    let stored = fs::read(dir.path().join("repo").join(path)).expect("stored");
+    assert!(!String::from_utf8_lossy(&stored).contains("line three"));
+}
+
+#[tokio::test]
+async fn enrollment_issues_a_key_after_admin_confirms_the_code() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path.clone());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4191)))),
+    )
+    .expect("server");
+
+    server
+        .post("/api/v1/enroll")
+        .json(&serde_json::json!({ "name": "laptop", "scopes": ["admin"] }))
+        .await
+        .assert_status_bad_request();
+    let ticket = server
+        .post("/api/v1/enroll")
+        .json(&serde_json::json!({ "name": "laptop" }))
+        .await;
+    ticket.assert_status(StatusCode::CREATED);
+    let ticket: serde_json::Value = ticket.json();
+    let id = ticket["id"].as_str().expect("id");
+    let code = ticket["code"].as_str().expect("code");
+    let poll_token = ticket["poll_token"].as_str().expect("poll token");
+    let poll = || {
+        server
+            .get(&format!("/api/v1/enroll/{id}"))
+            .add_header("X-Enroll-Token", poll_token)
+    };
+    poll().await.assert_status(StatusCode::ACCEPTED);
+
+    server
+        .get("/api/v1/admin/enrollments")
+        .await
+        .assert_status_unauthorized();
+    let pending: serde_json::Value = server
+        .get("/api/v1/admin/enrollments")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(pending[0]["name"], "laptop");
+    assert!(pending[0].get("code").is_none());
+
+    server
+        .get("/admin/enroll")
+        .await
+        .assert_status(StatusCode::FOUND);
+    server
+        .post("/admin/login")
+        .form(&serde_json::json!({ "key": "wrong" }))
+        .await
+        .assert_status_unauthorized();
+    let login = server
+        .post("/admin/login")
+        .form(&serde_json::json!({ "key": "ops-key" }))
+        .await;
+    login.assert_status(StatusCode::FOUND);
+    let set_cookie = login.header("set-cookie");
+    let set_cookie = set_cookie.to_str().expect("cookie");
+    assert!(set_cookie.contains("HttpOnly") && set_cookie.contains("SameSite=Strict"));
+    let cookie = set_cookie.split(';').next().expect("pair").to_string();
+    let page = server
+        .get("/admin/enroll")
+        .add_header("Cookie", &cookie)
+        .await;
+    page.assert_status_ok();
+    assert!(page.text().contains("laptop"));
+    assert!(!page.text().contains(code));
+
+    let approved = server
+        .post("/admin/enroll")
+        .add_header("Cookie", &cookie)
+        .form(&serde_json::json!({ "code": code }))
+        .await;
+    approved.assert_status_ok();
+    assert!(approved.text().contains("Approved laptop"));
+
+    let issued = poll().await;
+    issued.assert_status_ok();
+    let issued: serde_json::Value = issued.json();
+    let api_key = issued["api_key"].as_str().expect("api key");
+    poll().await.assert_status_not_found();
+
+    server
+        .post("/api/v1/paste?name=enrolled.txt")
+        .add_header("X-API-Key", api_key)
+        .text("from an enrolled device")
+        .await
+        .assert_status(StatusCode::CREATED);
+    assert!(
+        fs::read_to_string(&keys_path)
+            .expect("keys")
+            .contains(api_key)
+    );
+
+    server
+        .post("/admin/logout")
+        .add_header("Cookie", &cookie)
+        .await
+        .assert_status(StatusCode::FOUND);
+    server
+        .get("/api/v1/admin/enrollments")
+        .add_header("Cookie", &cookie)
+        .await
+        .assert_status_unauthorized();
+}
+
+#[tokio::test]
+async fn admin_dashboard_reports_status_and_pauses_writes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]},{"name":"bot","key":"bot-key","scopes":["paste:create"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path.clone());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4192)))),
+    )
+    .expect("server");
+
+    server.get("/admin").await.assert_status(StatusCode::FOUND);
+    server
+        .get("/api/v1/admin/status")
+        .add_header("X-API-Key", "bot-key")
+        .await
+        .assert_status_forbidden();
+    server
+        .post("/api/v1/paste?name=a.txt")
+        .add_header("X-API-Key", "bot-key")
+        .text("hello")
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let login = server
+        .post("/admin/login")
+        .form(&serde_json::json!({ "key": "ops-key" }))
+        .await;
+    assert_eq!(login.header("location"), "/admin");
+    let cookie = login
+        .header("set-cookie")
+        .to_str()
+        .expect("cookie")
+        .split(';')
+        .next()
+        .expect("pair")
+        .to_string();
+
+    let page = server.get("/admin").add_header("Cookie", &cookie).await;
+    page.assert_status_ok();
+    assert!(page.text().contains("Recent errors"));
+
+    let paused = server
+        .post("/admin/maintenance")
+        .add_header("Cookie", &cookie)
+        .form(&serde_json::json!({ "on": true }))
+        .await;
+    assert!(paused.text().contains("Leave maintenance mode"));
+    server
+        .post("/api/v1/paste?name=b.txt")
+        .add_header("X-API-Key", "bot-key")
+        .text("paused")
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    let statusz: serde_json::Value = server.get("/statusz").await.json();
+    assert_eq!(statusz["maintenance"], true);
+
+    let status: serde_json::Value = server
+        .get("/api/v1/admin/status")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(status["maintenance"], true);
+    assert_eq!(status["storage"]["pastes"], 1);
+    assert_eq!(status["queues"]["writes_in_flight"], 0);
+    assert_eq!(status["queues"]["commits_queued"], 0);
+    let bot = status["keys"]
+        .as_array()
+        .expect("keys")
+        .iter()
+        .find(|k| k["name"] == "bot")
+        .expect("bot usage");
+    assert_eq!(bot["requests"], 1);
+    assert_eq!(status["recent_errors"][0]["status"], 503);
+    assert_eq!(status["recent_errors"][0]["path"], "/api/v1/paste");
+
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]}]}"#,
+    )
+    .expect("rewrite keys");
+    let reloaded = server
+        .post("/admin/reload-keys")
+        .add_header("Cookie", &cookie)
+        .await;
+    assert!(reloaded.text().contains("Reloaded 1 API keys."));
+    server
+        .post("/admin/maintenance")
+        .add_header("Cookie", &cookie)
+        .form(&serde_json::json!({ "on": false }))
+        .await
+        .assert_status_ok();
+    server
+        .post("/api/v1/paste?name=c.txt")
+        .add_header("X-API-Key", "bot-key")
+        .text("bot is gone")
+        .await
+        .assert_status_unauthorized();
+
+    let synced = server
+        .post("/admin/sync")
+        .add_header("Cookie", &cookie)
+        .await;
+    synced.assert_status_ok();
+    assert!(synced.text().contains("push failed"));
+}
+
+#[tokio::test]
+async fn admin_keys_reports_usage_across_restarts() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]},{"name":"bot","key":"bot-key","scopes":["paste:create","paste:read"]},{"name":"idle","key":"idle-key","scopes":["paste:read"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let serve = |cfg| {
+        let state = Arc::new(preflight::build_state(cfg).expect("state"));
+        TestServer::new(
+            http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4235)))),
+        )
+        .expect("server")
+    };
+
+    let server = serve(cfg.clone());
+    let created = server
+        .post("/api/v1/paste?name=log.txt")
+        .add_header("X-API-Key", "bot-key")
+        .text("hello")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-API-Key", "bot-key")
+        .text(" world")
+        .await
+        .assert_status_ok();
+    server
+        .get("/api/v1/admin/keys")
+        .add_header("X-API-Key", "bot-key")
+        .await
+        .assert_status_forbidden();
+    drop(server);
+
+    let server = serve(cfg);
+    let usage: serde_json::Value = server
+        .get("/api/v1/admin/keys")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    let keys = usage["keys"].as_array().expect("keys");
+    let names: Vec<_> = keys.iter().map(|k| k["name"].clone()).collect();
+    assert_eq!(names, ["ops", "bot", "idle"]);
+    assert_eq!(keys[1]["requests"], 2);
+    assert_eq!(keys[1]["bytes_uploaded"], 11);
+    assert!(!keys[1]["last_used"].is_null());
+    assert_eq!(keys[2]["requests"], 0);
+    assert!(keys[2]["last_used"].is_null());
+}
+
+#[tokio::test]
+async fn e2e_pastes_store_client_ciphertext_untouched() {
+    use aes_gcm::{
+        Aes256Gcm, KeyInit, Nonce,
+        aead::{Aead, OsRng},
+    };
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let key_path = dir.path().join("paste.key");
+    fs::write(&key_path, "42".repeat(32)).expect("write key");
+    let mut cfg = test_cfg(dir.path());
+    cfg.encrypt_key_file = Some(key_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4193)))),
+    )
+    .expect("server");
+
+    let client_key = Aes256Gcm::generate_key(&mut OsRng);
+    let cipher = Aes256Gcm::new(&client_key);
+    let iv = [9u8; 12];
+    let mut upload = iv.to_vec();
+    upload.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&iv), b"# private notes".as_slice())
+            .expect("encrypt"),
+    );
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=notes.md&encrypted=true")
+        .add_header("X-Paste-Token", "tok")
+        .bytes(upload.clone().into())
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    let path = created["path"].as_str().expect("path");
+    assert!(path.ends_with(".bin"));
+    assert_eq!(
+        fs::read(dir.path().join("repo").join(path)).expect("stored"),
+        upload
+    );
+
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["encrypted"], true);
+    assert_eq!(meta["e2e"], true);
+    assert_eq!(meta["content_type"], "application/octet-stream");
+    assert_eq!(meta["size"], upload.len());
+
+    let view = server.get(&format!("/p/{id}")).await;
+    view.assert_status_ok();
+    assert!(view.text().contains("e2e-plain"));
+    assert!(!view.text().contains("private notes"));
+
+    let raw = server.get(&format!("/p/{id}/raw.txt")).await;
+    assert_eq!(raw.header("content-type"), "application/octet-stream");
+    let fetched = raw.as_bytes().to_vec();
+    assert_eq!(
+        cipher
+            .decrypt(Nonce::from_slice(&fetched[..12]), &fetched[12..])
+            .expect("decrypt"),
+        b"# private notes"
+    );
+
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("plaintext tail")
+        .await
+        .assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn digest_is_stored_as_a_paste_and_posted_to_the_webhook() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let hook = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let tx = tx.clone();
+            async move {
+                tx.send(body).expect("capture");
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind hook");
+    let hook_addr = listener.local_addr().expect("hook addr");
+    tokio::spawn(async move { axum::serve(listener, hook).await });
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin","paste:create","paste:read"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    cfg.allow_cidr.push("10.0.0.0/8".parse().expect("cidr"));
+    cfg.notify_webhook = Some(format!("http://{hook_addr}/hook"));
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 7], 4192)))),
+    )
+    .expect("server");
+
+    for (name, tag) in [("a.txt", "ci"), ("b.txt", "ci"), ("c.txt", "notes")] {
+        server
+            .post(&format!("/api/v1/paste?name={name}&tag={tag}"))
+            .add_header("X-API-Key", "ops-key")
+            .text("twelve bytes")
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    let today = time::OffsetDateTime::now_utc().date().to_string();
+
+    let export = server
+        .get(&format!("/api/v1/admin/digest?day={today}&format=markdown"))
+        .add_header("X-API-Key", "ops-key")
+        .await;
+    export.assert_status_ok();
+    assert!(export.text().contains("| ci | 2 |"));
+
+    let run = server
+        .post(&format!("/api/v1/admin/digest?day={today}"))
+        .add_header("X-API-Key", "ops-key")
+        .await;
+    run.assert_status(StatusCode::CREATED);
+    let run: serde_json::Value = run.json();
+    assert_eq!(run["digest"]["pastes"], 3);
+    assert_eq!(run["digest"]["bytes"], 36);
+    assert_eq!(run["digest"]["top_creators"][0][0], "10.0.0.7");
+    let paste_id = run["paste_id"].as_str().expect("paste id");
+
+    let meta: serde_json::Value = server
+        .get(&format!("/api/v1/p/{paste_id}"))
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(meta["tag"], "digest");
+    let raw = server.get(&format!("/p/{paste_id}/raw.txt")).await.text();
+    assert!(raw.contains(&format!("# lanpaste digest for {today}")));
+
+    let posted = rx.recv().await.expect("webhook call");
+    assert_eq!(posted["event"], "digest");
+    assert_eq!(posted["data"]["paste_id"], paste_id);
+
+    // The stored digest itself is not counted by the next one.
+    let again: serde_json::Value = server
+        .get(&format!("/api/v1/admin/digest?day={today}"))
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(again["pastes"], 3);
+}
+
+#[tokio::test]
+async fn stars_are_tracked_per_api_key() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[
+            {"name":"alice","key":"alice-key","scopes":["paste:create","paste:read","recent:read"]},
+            {"name":"bob","key":"bob-key","scopes":["paste:read"]},
+            {"name":"bot","key":"bot-key","scopes":["paste:create"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4193)))),
+    )
+    .expect("server");
+
+    let mut ids = Vec::new();
+    for name in ["useful.sh", "other.txt"] {
+        let created: serde_json::Value = server
+            .post(&format!("/api/v1/paste?name={name}"))
+            .add_header("X-API-Key", "alice-key")
+            .text("echo hi")
+            .await
+            .json();
+        ids.push(created["id"].as_str().expect("id").to_string());
+    }
+    let useful = &ids[0];
+
+    for key in ["alice-key", "bob-key", "bob-key"] {
+        server
+            .post(&format!("/api/v1/p/{useful}/star"))
+            .add_header("X-API-Key", key)
+            .await
+            .assert_status_ok();
+    }
+    server
+        .post(&format!("/api/v1/p/{useful}/star"))
+        .add_header("X-API-Key", "bot-key")
+        .await
+        .assert_status_forbidden();
+    server
+        .post("/api/v1/p/01HZZZZZZZZZZZZZZZZZZZZZZZ/star")
+        .add_header("X-API-Key", "bob-key")
+        .await
+        .assert_status_not_found();
+
+    let recent: serde_json::Value = server
+        .get("/api/v1/recent")
+        .add_header("X-API-Key", "alice-key")
+        .await
+        .json();
+    let starred = recent
+        .as_array()
+        .expect("list")
+        .iter()
+        .find(|item| item["id"] == useful.as_str())
+        .expect("useful paste listed");
+    assert_eq!(starred["stars"], 2);
+    assert!(server.get("/").await.text().contains("<td>2</td>"));
+
+    let bob: serde_json::Value = server
+        .get("/api/v1/stars")
+        .add_header("X-API-Key", "bob-key")
+        .await
+        .json();
+    assert_eq!(bob.as_array().expect("list").len(), 1);
+    assert_eq!(bob[0]["id"], useful.as_str());
+
+    let unstarred: serde_json::Value = server
+        .delete(&format!("/api/v1/p/{useful}/star"))
+        .add_header("X-API-Key", "bob-key")
+        .await
+        .json();
+    assert_eq!(unstarred["stars"], 1);
+    assert_eq!(unstarred["starred"], false);
+    let bob: serde_json::Value = server
+        .get("/api/v1/stars")
+        .add_header("X-API-Key", "bob-key")
+        .await
+        .json();
+    assert!(bob.as_array().expect("list").is_empty());
+}
+
+#[tokio::test]
+async fn pastes_record_the_creating_key_as_owner() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[
+            {"name":"alice","key":"alice-key","scopes":["paste:create","paste:read","recent:read"]},
+            {"name":"bob","key":"bob-key","scopes":["paste:create","recent:read"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4223)))),
+    )
+    .expect("server");
+
+    let mut ids = Vec::new();
+    for (key, query) in [
+        ("alice-key", "name=a.txt"),
+        ("bob-key", "name=b.txt"),
+        ("alice-key", "name=hidden.txt&visibility=unlisted"),
+    ] {
+        let created: serde_json::Value = server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-API-Key", key)
+            .text("hello")
+            .await
+            .json();
+        ids.push(created["id"].as_str().expect("id").to_string());
+    }
+
+    let meta: serde_json::Value = server
+        .get(&format!("/api/v1/p/{}", ids[0]))
+        .add_header("X-API-Key", "alice-key")
+        .await
+        .json();
+    assert_eq!(meta["owner"], "alice");
+
+    let mine: serde_json::Value = server
+        .get("/api/v1/mine")
+        .add_header("X-API-Key", "alice-key")
+        .await
+        .json();
+    let mine: Vec<&str> = mine
+        .as_array()
+        .expect("list")
+        .iter()
+        .map(|item| item["id"].as_str().expect("id"))
+        .collect();
+    assert_eq!(mine, [ids[2].as_str(), ids[0].as_str()]);
+
+    let bobs: serde_json::Value = server
+        .get("/api/v1/recent?owner=bob")
+        .add_header("X-API-Key", "alice-key")
+        .await
+        .json();
+    assert_eq!(bobs.as_array().expect("list").len(), 1);
+    assert_eq!(bobs[0]["id"], ids[1].as_str());
+    assert_eq!(bobs[0]["owner"], "bob");
+}
+
+#[tokio::test]
+async fn views_and_raw_reads_are_counted() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4224)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=shared.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("did anyone open this?")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+
+    let untouched: serde_json::Value = server.get(&format!("/api/v1/p/{id}/stats")).await.json();
+    assert_eq!(untouched["views"], 0);
+    assert!(untouched["last_at"].is_null());
+
+    let meta = server.get(&format!("/api/v1/p/{id}")).await;
+    let etag = meta.header("etag").to_str().expect("etag").to_string();
+    assert_eq!(meta.json::<serde_json::Value>()["views"]["views"], 0);
+
+    server.get(&format!("/p/{id}")).await.assert_status_ok();
+    server.get(&format!("/p/{id}")).await.assert_status_ok();
+    server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_status_ok();
+
+    let stats: serde_json::Value = server.get(&format!("/api/v1/p/{id}/stats")).await.json();
+    assert_eq!(stats["id"], id);
+    assert_eq!(stats["views"], 2);
+    assert_eq!(stats["raw"], 1);
+    assert!(!stats["last_at"].is_null());
+
+    // The counters are part of the meta, so a cached copy goes stale.
+    let meta = server
+        .get(&format!("/api/v1/p/{id}"))
+        .add_header("If-None-Match", etag)
+        .await;
+    meta.assert_status_ok();
+    assert_eq!(meta.json::<serde_json::Value>()["views"]["raw"], 1);
+}
+
+#[tokio::test]
+async fn a_tag_exports_as_one_archive_with_a_manifest() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4225)))),
+    )
+    .expect("server");
+
+    let mut ids = Vec::new();
+    for (query, body) in [
+        ("name=build.log&tag=ci", "build ok"),
+        ("name=test.log&tag=ci", "tests ok"),
+        ("name=hidden.log&tag=ci&visibility=unlisted", "secret"),
+        ("name=notes.md&tag=docs", "# notes"),
+    ] {
+        let created: serde_json::Value = server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-Paste-Token", "tok")
+            .text(body)
+            .await
+            .json();
+        ids.push(created["id"].as_str().expect("id").to_string());
+    }
+
+    let tarball = server.get("/api/v1/export?tag=ci&format=tar.gz").await;
+    tarball.assert_status_ok();
+    assert_eq!(tarball.header("content-type"), "application/gzip");
+    assert_eq!(
+        tarball.header("content-disposition"),
+        "attachment; filename=\"lanpaste-ci.tar.gz\""
+    );
+    let bytes = tarball.as_bytes().to_vec();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bytes.as_slice()));
+    let mut entries = std::collections::BTreeMap::new();
+    for entry in archive.entries().expect("entries") {
+        let mut entry = entry.expect("entry");
+        let name = entry.path().expect("path").to_string_lossy().into_owned();
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut entry, &mut content).expect("read");
+        entries.insert(name, content);
+    }
+    assert_eq!(entries.len(), 3, "{:?}", entries.keys());
+    let manifest: serde_json::Value =
+        serde_json::from_str(&entries["manifest.json"]).expect("manifest");
+    assert_eq!(manifest["tag"], "ci");
+    let pastes = manifest["pastes"].as_array().expect("pastes");
+    assert_eq!(pastes.len(), 2);
+    assert_eq!(pastes[0]["id"], ids[1].as_str());
+    let file = pastes[1]["file"].as_str().expect("file");
+    assert_eq!(entries[file], "build ok");
+
+    let zip = server.get("/api/v1/export?tag=ci").await;
+    zip.assert_status_ok();
+    assert_eq!(zip.header("content-type"), "application/zip");
+    assert!(zip.as_bytes().starts_with(b"PK\x03\x04"));
+
+    server
+        .get("/api/v1/export")
+        .await
+        .assert_status_bad_request();
+    server
+        .get("/api/v1/export?tag=nope")
+        .await
+        .assert_status_not_found();
+}
+
+#[tokio::test]
+async fn git_http_serves_read_only_clones_to_repo_readers() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[
+            {"name":"ci","key":"ci-key","scopes":["paste:create","paste:read"]},
+            {"name":"mirror","key":"mirror-key","scopes":["repo:read"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    cfg.git_http = true;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            http::app(state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    let client = reqwest::Client::new();
+    let created: serde_json::Value = client
+        .post(format!("http://{addr}/api/v1/paste?name=cloned.txt"))
+        .header("X-API-Key", "ci-key")
+        .body("over git")
+        .send()
+        .await
+        .expect("create")
+        .json()
+        .await
+        .expect("json");
+    let path = created["path"].as_str().expect("path").to_string();
+
+    let refs = format!("http://{addr}/git/info/refs?service=git-upload-pack");
+    let anonymous = client.get(&refs).send().await.expect("refs");
+    assert_eq!(anonymous.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert!(anonymous.headers().contains_key("www-authenticate"));
+    let reader = client
+        .get(&refs)
+        .basic_auth("ci", Some("ci-key"))
+        .send()
+        .await
+        .expect("refs");
+    assert_eq!(reader.status(), reqwest::StatusCode::FORBIDDEN);
+    let push = client
+        .get(format!(
+            "http://{addr}/git/info/refs?service=git-receive-pack"
+        ))
+        .basic_auth("mirror", Some("mirror-key"))
+        .send()
+        .await
+        .expect("refs");
+    assert_eq!(push.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let checkout = dir.path().join("clone");
+    let url = format!("http://mirror:mirror-key@{addr}/git");
+    let target = checkout.clone();
+    let cloned = tokio::task::spawn_blocking(move || {
+        std::process::Command::new("git")
+            .args(["clone", "--quiet", &url])
+            .arg(&target)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .output()
+            .expect("run git clone")
+    })
+    .await
+    .expect("clone task");
+    assert!(
+        cloned.status.success(),
+        "{}",
+        String::from_utf8_lossy(&cloned.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(checkout.join(&path)).expect("cloned paste"),
+        "over git"
+    );
+}
+
+#[tokio::test]
+async fn namespaces_serve_isolated_repos_under_their_own_prefix() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let team_dir = dir.path().join("team-a");
+    fs::create_dir_all(&team_dir).expect("team dir");
+    fs::write(
+        team_dir.join(init::API_KEYS_FILE),
+        r#"{"keys":[{"name":"a","key":"a-key","scopes":["paste:create","paste:read","recent:read"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(&dir.path().join("main"));
+    cfg.namespace = vec![
+        config::parse_namespace(&format!("team-a={}", team_dir.display())).expect("namespace"),
+    ];
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4222)))),
+    )
+    .expect("server");
+
+    // The namespace has keys of its own; the main token does not open it.
+    server
+        .post("/ns/team-a/api/v1/paste?name=team.txt")
+        .add_header("X-Paste-Token", "tok")
+        .text("team only")
+        .await
+        .assert_status_unauthorized();
+    let created = server
+        .post("/ns/team-a/api/v1/paste?name=team.txt&slug=team-notes")
+        .add_header("X-API-Key", "a-key")
+        .text("team only")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let created: serde_json::Value = created.json();
+    let id = created["id"].as_str().expect("id").to_string();
+    assert_eq!(created["view_url"], format!("/ns/team-a/p/{id}"));
+    assert_eq!(created["raw_url"], format!("/ns/team-a/api/v1/p/{id}/raw"));
+    assert!(
+        team_dir
+            .join("repo")
+            .join(created["path"].as_str().expect("path"))
+            .exists()
+    );
+
+    let raw = server
+        .get(&format!("/ns/team-a/api/v1/p/{id}/raw"))
+        .add_header("X-API-Key", "a-key")
+        .await;
+    raw.assert_status_ok();
+    assert_eq!(raw.text(), "team only");
+    server
+        .get(&format!("/api/v1/p/{id}"))
+        .await
+        .assert_status_not_found();
+    let recent: serde_json::Value = server.get("/api/v1/recent").await.json();
+    assert!(recent.as_array().expect("list").is_empty());
+
+    let slug = server.get("/ns/team-a/s/team-notes").await;
+    slug.assert_status(StatusCode::FOUND);
+    assert_eq!(
+        slug.header("location"),
+        format!("/ns/team-a/p/{id}").as_str()
+    );
+    let page = server.get("/ns/team-a/dashboard").await.text();
+    assert!(page.contains(&format!("href=\"/ns/team-a/p/{id}\"")));
+    assert!(page.contains("data-live=\"/ns/team-a/ws\""));
+}
+
+#[tokio::test]
+async fn base_url_prefixes_every_generated_link() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.base_path = config::parse_base_url("https://nas.lan/paste/").expect("base url");
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4240)))),
+    )
+    .expect("server");
+
+    // The proxy strips the prefix, so routes stay where they were.
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=notes.md&slug=proxied")
+        .add_header("X-Paste-Token", "tok")
+        .text("# behind nginx")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    assert_eq!(created["view_url"], format!("/paste/p/{id}"));
+    assert_eq!(created["raw_url"], format!("/paste/api/v1/p/{id}/raw"));
+    assert_eq!(created["meta_url"], format!("/paste/api/v1/p/{id}"));
+
+    let slug = server.get("/s/proxied").await;
+    assert_eq!(slug.header("location"), format!("/paste/p/{id}").as_str());
+    let page = server.get("/dashboard").await.text();
+    assert!(page.contains(&format!("href=\"/paste/p/{id}\"")));
+    assert!(page.contains(&format!("href=\"/paste{}\"", assets::url("app.css"))));
+    let view = server.get(&format!("/p/{id}")).await.text();
+    assert!(view.contains(&format!("src=\"/paste{}\"", assets::url("app.js"))));
+
+    let client = Client::new("https://nas.lan/paste/", client::DEFAULT_TIMEOUT).expect("client");
+    assert_eq!(
+        client.link(created["view_url"].as_str().expect("view")),
+        format!("https://nas.lan/paste/p/{id}")
+    );
+}
+
+#[tokio::test]
+async fn public_url_makes_create_urls_absolute() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.public_url = Some(config::parse_public_url("http://paste.lan:8090").expect("url"));
+    cfg.base_path = "/paste".to_string();
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4241)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste?name=build.log")
+        .add_header("X-Paste-Token", "tok")
+        .text("ok")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    let short = created["short_id"].as_str().expect("short id");
+    let base = "http://paste.lan:8090/paste";
+    assert_eq!(created["view_url"], format!("{base}/p/{id}"));
+    assert_eq!(created["raw_url"], format!("{base}/api/v1/p/{id}/raw"));
+    assert_eq!(created["meta_url"], format!("{base}/api/v1/p/{id}"));
+    assert_eq!(created["short_url"], format!("{base}/p/{short}"));
+
+    // Pages keep linking by path, so they work under any host name.
+    let page = server.get("/dashboard").await.text();
+    assert!(page.contains(&format!("href=\"/paste/p/{id}\"")));
+
+    let client = Client::new("http://10.0.0.5:8090", client::DEFAULT_TIMEOUT).expect("client");
+    assert_eq!(
+        client.link(created["view_url"].as_str().expect("view")),
+        format!("{base}/p/{id}")
+    );
+}
+
+#[tokio::test]
+async fn requests_for_other_hosts_are_refused_when_hosts_are_listed() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.allowed_hosts = ["paste.lan", "10.0.0.5:8090"]
+        .into_iter()
+        .map(|host| config::parse_allowed_host(host).expect("host"))
+        .collect();
+    cfg.cors_origin = vec!["http://wiki.lan".to_string()];
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4242)))),
+    )
+    .expect("server");
+
+    for host in ["paste.lan", "Paste.lan:8090", "10.0.0.5:8090"] {
+        server
+            .get("/healthz")
+            .add_header("Host", host)
+            .await
+            .assert_status_ok();
+    }
+    // A name rebound to this server, or a listed address on the wrong port.
+    for host in ["attacker.example", "10.0.0.5", "paste.lan.attacker.example"] {
+        let res = server
+            .post("/api/v1/paste?name=x.txt")
+            .add_header("Host", host)
+            .add_header("X-Paste-Token", "tok")
+            .text("x")
+            .await;
+        res.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(res.json::<serde_json::Value>()["error"], "bad_request");
+    }
+    let preflight = server
+        .method(Method::OPTIONS, "/api/v1/paste")
+        .add_header("Host", "attacker.example")
+        .add_header("Origin", "http://wiki.lan")
+        .add_header("Access-Control-Request-Method", "POST")
+        .await;
+    preflight.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn namespaces_need_their_own_keys_when_the_main_repo_has_some() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(&keys_path, r#"{"keys":[{"key":"k","scopes":["*"]}]}"#).expect("write keys");
+    let mut cfg = test_cfg(&dir.path().join("main"));
+    cfg.api_keys_file = Some(keys_path);
+    cfg.namespace = vec![
+        config::parse_namespace(&format!("b={}", dir.path().join("b").display()))
+            .expect("namespace"),
+    ];
+    preflight::run_preflight(&cfg).expect("preflight");
+    let err = preflight::build_state(cfg).err().expect("refused");
+    assert!(format!("{err:?}").contains(init::API_KEYS_FILE));
+}
+
+#[tokio::test]
+async fn collections_group_pastes_under_one_committed_page() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4221)))),
+    )
+    .expect("server");
+
+    let mut pastes = Vec::new();
+    for name in ["crash.log", "app.toml", "notes.md"] {
+        let created: serde_json::Value = server
+            .post(&format!("/api/v1/paste?name={name}"))
+            .add_header("X-Paste-Token", "tok")
+            .text("x")
+            .await
+            .json();
+        pastes.push(created);
+    }
+    let id_of = |i: usize| pastes[i]["id"].as_str().expect("id").to_string();
+
+    server
+        .post("/api/v1/collections")
+        .json(&serde_json::json!({"title": "bug 42"}))
+        .await
+        .assert_status_unauthorized();
+    server
+        .post("/api/v1/collections")
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({"title": "bug 42", "pastes": ["01HZZZZZZZZZZZZZZZZZZZZZZZ"]}))
+        .await
+        .assert_status_bad_request();
+
+    let created = server
+        .post("/api/v1/collections")
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({
+            "title": "bug 42",
+            "description": "logs & config",
+            "pastes": [id_of(0), pastes[1]["short_id"]],
+        }))
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let created: serde_json::Value = created.json();
+    let cid = created["id"].as_str().expect("collection id").to_string();
+    assert_eq!(created["url"], format!("/c/{cid}"));
+    assert_eq!(created["pastes"], serde_json::json!([id_of(0), id_of(1)]));
+    assert_eq!(created["members"].as_array().expect("members").len(), 2);
+    assert!(created["commit"].is_string());
+
+    let added: serde_json::Value = server
+        .post(&format!("/api/v1/collections/{cid}/pastes"))
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({"pastes": [id_of(2), id_of(0)]}))
+        .await
+        .json();
+    assert_eq!(
+        added["pastes"],
+        serde_json::json!([id_of(0), id_of(1), id_of(2)])
+    );
+
+    let got: serde_json::Value = server
+        .get(&format!("/api/v1/collections/{cid}"))
+        .await
+        .json();
+    let members: Vec<&str> = got["members"]
+        .as_array()
+        .expect("members")
+        .iter()
+        .map(|m| m["id"].as_str().expect("member id"))
+        .collect();
+    assert_eq!(members, [id_of(0), id_of(1), id_of(2)]);
+    assert!(got.get("commit").is_none());
+    server
+        .get("/api/v1/collections/01HZZZZZZZZZZZZZZZZZZZZZZZ")
+        .await
+        .assert_status_not_found();
+
+    let page = server.get(&format!("/c/{cid}")).await.text();
+    assert!(page.contains("<h1 style=\"margin-top:0\">bug 42</h1>"));
+    assert!(page.contains("logs &amp; config"));
+    assert!(page.contains(&format!("href=\"/p/{}\"", id_of(2))));
+
+    let repo = dir.path().join("repo");
+    let stored: serde_json::Value = serde_json::from_slice(
+        &fs::read(repo.join(format!("collections/{cid}.json"))).expect("collection file"),
+    )
+    .expect("collection json");
+    assert_eq!(stored["pastes"].as_array().expect("pastes").len(), 3);
+    let log = std::process::Command::new("git")
+        .args(["log", "--format=%s", "-n", "2"])
+        .current_dir(&repo)
+        .output()
+        .expect("git log");
+    assert_eq!(
+        String::from_utf8_lossy(&log.stdout),
+        format!("collection: add 1 to {cid}\ncollection: create {cid}\n")
+    );
+}
+
+#[tokio::test]
+async fn unlisted_pastes_stay_out_of_listings_but_resolve_by_id() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4194)))),
+    )
+    .expect("server");
+
+    let listed: serde_json::Value = server
+        .post("/api/v1/paste?name=public.txt&tag=team")
+        .add_header("X-Paste-Token", "tok")
+        .text("for everyone")
+        .await
+        .json();
+    let hidden: serde_json::Value = server
+        .post("/api/v1/paste?name=private.txt&tag=secret-project&visibility=unlisted")
+        .add_header("X-Paste-Token", "tok")
+        .text("only with the link")
+        .await
+        .json();
+    let hidden_id = hidden["id"].as_str().expect("id");
+    server
+        .post("/api/v1/paste?name=x.txt&visibility=hidden")
+        .add_header("X-Paste-Token", "tok")
+        .text("x")
+        .await
+        .assert_status_bad_request();
+
+    let recent: serde_json::Value = server.get("/api/v1/recent").await.json();
+    let ids: Vec<&str> = recent
+        .as_array()
+        .expect("list")
+        .iter()
+        .filter_map(|item| item["id"].as_str())
+        .collect();
+    assert_eq!(ids, vec![listed["id"].as_str().expect("id")]);
+    let dashboard = server.get("/").await.text();
+    assert!(!dashboard.contains(hidden_id));
+    assert!(!dashboard.contains("secret-project"));
+
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{hidden_id}")).await.json();
+    assert_eq!(meta["visibility"], "unlisted");
+    assert_eq!(
+        server
+            .get(&format!("/api/v1/p/{hidden_id}/raw"))
+            .await
+            .text(),
+        "only with the link"
+    );
+    server
+        .get(&format!("/p/{hidden_id}"))
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn superseded_pastes_link_to_the_newer_version() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4195)))),
+    )
+    .expect("server");
+
+    let create = |query: String, body: &'static str| {
+        let server = &server;
+        async move {
+            let created: serde_json::Value = server
+                .post(&format!("/api/v1/paste?{query}"))
+                .add_header("X-Paste-Token", "tok")
+                .text(body)
+                .await
+                .json();
+            created["id"].as_str().expect("id").to_string()
+        }
+    };
+    let v1 = create("name=nginx.conf".to_string(), "listen 80;").await;
+    let v1_etag = server
+        .get(&format!("/api/v1/p/{v1}"))
+        .await
+        .header("etag")
+        .to_str()
+        .expect("etag")
+        .to_string();
+    let v2 = create(format!("name=nginx.conf&supersedes={v1}"), "listen 8080;").await;
+    server
+        .post("/api/v1/paste?supersedes=01HZZZZZZZZZZZZZZZZZZZZZZZ")
+        .add_header("X-Paste-Token", "tok")
+        .text("x")
+        .await
+        .assert_status_bad_request();
+
+    let old: serde_json::Value = server.get(&format!("/api/v1/p/{v1}")).await.json();
+    assert_eq!(old["superseded_by"], v2.as_str());
+    server
+        .get(&format!("/api/v1/p/{v1}"))
+        .add_header("If-None-Match", &v1_etag)
+        .await
+        .assert_status_ok();
+    let page = server.get(&format!("/p/{v1}")).await.text();
+    assert!(page.contains("Superseded."));
+    assert!(page.contains(&format!("href=\"/p/{v2}\"")));
+
+    let ids = |list: serde_json::Value| -> Vec<String> {
+        list.as_array()
+            .expect("list")
+            .iter()
+            .filter_map(|item| item["id"].as_str().map(str::to_string))
+            .collect()
+    };
+    assert_eq!(
+        ids(server.get("/api/v1/recent").await.json()),
+        vec![v2.clone()]
+    );
+    assert_eq!(
+        ids(server.get("/api/v1/recent?superseded=true").await.json()).len(),
+        2
+    );
+
+    // A third version takes over via PATCH; clearing it restores v2.
+    let v3 = create("name=nginx.conf".to_string(), "listen 8443 ssl;").await;
+    let patched: serde_json::Value = server
+        .patch(&format!("/api/v1/p/{v3}"))
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({ "supersedes": v2 }))
+        .await
+        .json();
+    assert_eq!(patched["supersedes"], v2.as_str());
+    server
+        .patch(&format!("/api/v1/p/{v1}"))
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({ "supersedes": v3 }))
+        .await
+        .assert_status_bad_request();
+    server
+        .patch(&format!("/api/v1/p/{v3}"))
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({ "supersedes": null }))
+        .await
+        .assert_status_ok();
+    let middle: serde_json::Value = server.get(&format!("/api/v1/p/{v2}")).await.json();
+    assert!(middle.get("superseded_by").is_none());
+    assert_eq!(middle["supersedes"], v1.as_str());
+}
+
+#[tokio::test]
+async fn admin_archive_requires_admin_and_a_positive_age() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]},{"name":"dev","key":"dev-key","scopes":["paste:create"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4195)))),
+    )
+    .expect("server");
+
+    server
+        .post("/api/v1/paste?name=fresh.txt")
+        .add_header("X-API-Key", "dev-key")
+        .text("just made")
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    server
+        .post("/api/v1/admin/archive?older_than_days=30")
+        .add_header("X-API-Key", "dev-key")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    server
+        .post("/api/v1/admin/archive?older_than_days=0")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let res = server
+        .post("/api/v1/admin/archive?older_than_days=30")
+        .add_header("X-API-Key", "ops-key")
+        .await;
+    res.assert_status_ok();
+    let body: serde_json::Value = res.json();
+    assert_eq!(body["archived"], serde_json::json!([]));
+    assert!(body["tarball"].is_null());
+    assert!(!dir.path().join("archive").exists());
+}
+
+#[tokio::test]
+async fn oversized_views_render_a_prefix_with_a_notice() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.render_max_input_bytes = 32;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4196)))),
+    )
+    .expect("server");
+
+    let body = format!("# title\n\n{}", "> ".repeat(500));
+    let create = server
+        .post("/api/v1/paste?name=deep.md")
+        .add_header("X-Paste-Token", "tok")
+        .text(body.clone())
+        .await;
+    create.assert_status(StatusCode::CREATED);
+    let id = create.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let page = server.get(&format!("/p/{id}")).await;
+    page.assert_status_ok();
+    let html = page.text();
+    assert!(html.contains(&format!("Showing the first 32 of {} bytes.", body.len())));
+    assert!(html.contains(&format!("<a href=\"/p/{id}/raw.txt\">Full text</a>")));
+    assert!(html.contains("<h1>title</h1>"));
+
+    let raw = server.get(&format!("/p/{id}/raw.txt")).await.text();
+    assert_eq!(raw, body);
+}
+
+#[tokio::test]
+async fn admin_backup_uploads_a_signed_bundle_to_s3() {
+    type Put = (String, axum::http::HeaderMap, Vec<u8>);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Put>();
+    let s3 = axum::Router::new().route(
+        "/{*key}",
+        axum::routing::put(
+            move |uri: axum::http::Uri, headers: axum::http::HeaderMap, body: axum::body::Bytes| {
+                let tx = tx.clone();
+                async move {
+                    tx.send((uri.path().to_string(), headers, body.to_vec()))
+                        .expect("capture");
+                    StatusCode::OK
+                }
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind s3");
+    let s3_addr = listener.local_addr().expect("s3 addr");
+    tokio::spawn(async move { axum::serve(listener, s3).await });
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]}]}"#,
+    )
+    .expect("write keys");
+    let secret_path = dir.path().join("s3-secret");
+    fs::write(&secret_path, "minio-secret\n").expect("write secret");
+    let mut cfg = test_cfg(dir.path());
+    cfg.api_keys_file = Some(keys_path);
+    cfg.backup_s3_endpoint = Some(format!("http://{s3_addr}"));
+    cfg.backup_s3_bucket = Some("pastes".to_string());
+    cfg.backup_s3_prefix = "lan/".to_string();
+    cfg.backup_s3_access_key = Some("minio".to_string());
+    cfg.backup_s3_secret_key_file = Some(secret_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4197)))),
+    )
+    .expect("server");
+
+    let before: serde_json::Value = server
+        .get("/api/v1/admin/backup")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(before["configured"], true);
+    assert_eq!(before["target"], "s3://pastes/lan/");
+    assert_eq!(before["format"], "bundle");
+    assert!(before["last_success"].is_null());
+
+    let run = server
+        .post("/api/v1/admin/backup")
+        .add_header("X-API-Key", "ops-key")
+        .await;
+    run.assert_status_ok();
+    let run: serde_json::Value = run.json();
+    let key = run["last_key"].as_str().expect("key").to_string();
+    assert!(key.starts_with("lan/") && key.ends_with(".bundle"));
+
+    let (path, headers, body) = rx.recv().await.expect("upload");
+    assert_eq!(path, format!("/pastes/{key}"));
+    assert!(body.starts_with(b"# v2 git bundle") || body.starts_with(b"# v3 git bundle"));
+    assert_eq!(run["last_bytes"], body.len());
+    let auth = headers["authorization"].to_str().expect("auth");
+    assert!(auth.starts_with("AWS4-HMAC-SHA256 Credential=minio/"));
+    assert!(auth.contains(
+        "/us-east-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="
+    ));
+    assert_eq!(
+        headers["x-amz-content-sha256"].to_str().expect("hash"),
+        hex::encode(Sha256::digest(&body))
+    );
+
+    let after: serde_json::Value = server
+        .get("/api/v1/admin/backup")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(after["last_key"], key.as_str());
+    assert_eq!(after["failures"], 0);
+}
+
+#[tokio::test]
+async fn pages_link_fingerprinted_immutable_assets() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4198)))),
+    )
+    .expect("server");
+
+    let page = server.get("/").await.text();
+    let css = page
+        .split('"')
+        .find(|part| part.starts_with("/assets/app.") && part.ends_with(".css"))
+        .expect("stylesheet link")
+        .to_string();
+    assert!(!page.contains("<style>"));
+
+    let res = server.get(&css).await;
+    res.assert_status_ok();
+    assert_eq!(res.header("content-type"), "text/css; charset=utf-8");
+    assert_eq!(
+        res.header("cache-control"),
+        "public, max-age=31536000, immutable"
+    );
+    assert!(res.text().contains(".shell"));
+    let etag = res.header("etag").to_str().expect("etag").to_string();
+
+    server
+        .get(&css)
+        .add_header("If-None-Match", &etag)
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+
+    let bare = server.get("/assets/app.css").await;
+    bare.assert_status_ok();
+    assert_eq!(bare.header("cache-control"), "no-cache");
+    server
+        .get("/assets/app.0000000000.css")
+        .await
+        .assert_status_not_found();
+}
+
+#[tokio::test]
+async fn webdav_serves_listed_pastes_read_only() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.webdav = true;
+    let keys_path = dir.path().join("keys.json");
+    write_api_keys_file(&keys_path);
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4199)))),
+    )
+    .expect("server");
+    let propfind = Method::from_bytes(b"PROPFIND").expect("method");
+    // "reader:reader-key" as HTTP Basic credentials.
+    let basic = "Basic cmVhZGVyOnJlYWRlci1rZXk=";
+
+    let created = server
+        .post("/api/v1/paste?name=notes.md")
+        .add_header("X-API-Key", "writer-key")
+        .text("# dav notes")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let path = created.json::<serde_json::Value>()["path"]
+        .as_str()
+        .expect("path")
+        .to_string();
+    let hidden = server
+        .post("/api/v1/paste?name=secret.txt&visibility=unlisted")
+        .add_header("X-API-Key", "writer-key")
+        .text("not for browsing")
+        .await;
+    hidden.assert_status(StatusCode::CREATED);
+    let hidden_path = hidden.json::<serde_json::Value>()["path"]
+        .as_str()
+        .expect("path")
+        .to_string();
+
+    let options = server.method(Method::OPTIONS, "/dav/").await;
+    options.assert_status_ok();
+    assert_eq!(options.header("dav"), "1");
+
+    let denied = server.method(propfind.clone(), "/dav/").await;
+    denied.assert_status(StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        denied.header("www-authenticate"),
+        "Basic realm=\"lanpaste\""
+    );
+
+    let root = server
+        .method(propfind.clone(), "/dav/")
+        .add_header("Authorization", basic)
+        .add_header("Depth", "1")
+        .await;
+    root.assert_status(StatusCode::MULTI_STATUS);
+    let year = &path["pastes/".len()..][..4];
+    assert!(
+        root.text()
+            .contains(&format!("<D:href>/dav/{year}/</D:href>"))
+    );
+
+    let day_dir = path.rsplit_once('/').expect("dir").0;
+    let day = server
+        .method(
+            propfind.clone(),
+            &format!("/dav/{}/", &day_dir["pastes/".len()..]),
+        )
+        .add_header("Authorization", basic)
+        .await
+        .text();
+    let file_name = path.rsplit('/').next().expect("file name");
+    assert!(day.contains(&format!("<D:displayname>{file_name}</D:displayname>")));
+    assert!(day.contains("<D:getcontentlength>11</D:getcontentlength>"));
+    assert!(!day.contains("secret"));
+
+    let dav_path = format!("/dav/{}", &path["pastes/".len()..]);
+    let file = server
+        .get(&dav_path)
+        .add_header("X-API-Key", "reader-key")
+        .await;
+    file.assert_status_ok();
+    assert_eq!(file.text(), "# dav notes");
+    server
+        .get(&format!("/dav/{}", &hidden_path["pastes/".len()..]))
+        .add_header("Authorization", basic)
+        .await
+        .assert_status_not_found();
+    server
+        .get("/dav/../meta")
+        .add_header("Authorization", basic)
+        .await
+        .assert_status_not_found();
+
+    let put = server
+        .put(&dav_path)
+        .add_header("Authorization", basic)
+        .text("overwrite")
+        .await;
+    put.assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(put.header("allow"), "OPTIONS, PROPFIND, GET, HEAD");
+}
+
+#[tokio::test]
+async fn dashboard_revalidates_until_the_index_changes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4200)))),
+    )
+    .expect("server");
+
+    let first = server.get("/").await;
+    first.assert_status_ok();
+    assert_eq!(first.header("cache-control"), "no-cache");
+    let etag = first.header("etag").to_str().expect("etag").to_string();
+    server
+        .get("/")
+        .add_header("If-None-Match", &etag)
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+
+    let created = server
+        .post("/api/v1/paste?name=fresh.txt&tag=ops")
+        .add_header("X-Paste-Token", "tok")
+        .text("fresh")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let changed = server.get("/").add_header("If-None-Match", &etag).await;
+    changed.assert_status_ok();
+    assert_ne!(changed.header("etag").to_str().expect("etag"), etag);
+    let page = changed.text();
+    assert!(page.contains(&id));
+    assert!(page.contains("ops"));
+}
+
+#[tokio::test]
+async fn events_stream_created_pastes_and_replay_after_reconnect() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let base = format!("http://{}", listener.local_addr().expect("addr"));
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            http::app(state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    let client = reqwest::Client::new();
+
+    /// The next non-comment event block, leaving the rest in `buf`.
+    async fn next_event(res: &mut reqwest::Response, buf: &mut String) -> String {
+        loop {
+            if let Some(end) = buf.find("\n\n") {
+                let block: String = buf.drain(..end + 2).collect();
+                if !block.starts_with(':') {
+                    return block;
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), res.chunk())
+                .await
+                .expect("event in time")
+                .expect("read")
+                .expect("open stream");
+            buf.push_str(std::str::from_utf8(&chunk).expect("utf8"));
+        }
+    }
+    async fn create(client: &reqwest::Client, base: &str, name: &str) -> String {
+        let res = client
+            .post(format!("{base}/api/v1/paste?name={name}&tag=live"))
+            .header("X-Paste-Token", "tok")
+            .body("hello")
+            .send()
+            .await
+            .expect("create");
+        assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+        res.json::<serde_json::Value>().await.expect("json")["id"]
+            .as_str()
+            .expect("id")
+            .to_string()
+    }
+
+    let mut stream = client
+        .get(format!("{base}/api/v1/events?tag=live"))
+        .send()
+        .await
+        .expect("subscribe");
+    assert_eq!(stream.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        stream.headers()["content-type"].to_str().expect("ct"),
+        "text/event-stream"
+    );
+
+    let first = create(&client, &base, "one.txt").await;
+    let event = next_event(&mut stream, &mut String::new()).await;
+    assert!(event.contains("event: created"), "{event}");
+    assert!(event.contains(&format!("\"id\":\"{first}\"")), "{event}");
+    let seen = event
+        .lines()
+        .find_map(|line| line.strip_prefix("id: "))
+        .expect("event id")
+        .to_string();
+    drop(stream);
+
+    let second = create(&client, &base, "two.txt").await;
+    let mut resumed = client
+        .get(format!("{base}/api/v1/events"))
+        .header("Last-Event-ID", &seen)
+        .send()
+        .await
+        .expect("resubscribe");
+    let event = next_event(&mut resumed, &mut String::new()).await;
+    assert!(event.contains(&format!("\"id\":\"{second}\"")), "{event}");
+    assert!(event.contains("\"type\":\"created\""), "{event}");
+}
+
+#[tokio::test]
+async fn websocket_feeds_new_pastes_to_the_dashboard() {
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            http::app(state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    let client = reqwest::Client::new();
+    let create = |name: &'static str, tag: &'static str| {
+        let req = client
+            .post(format!("http://{addr}/api/v1/paste?name={name}&tag={tag}"))
+            .header("X-Paste-Token", "tok")
+            .body("hello");
+        async move {
+            let res = req.send().await.expect("create");
+            assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+            res.json::<serde_json::Value>().await.expect("json")["id"]
+                .as_str()
+                .expect("id")
+                .to_string()
+        }
+    };
+    async fn next_json<S>(socket: &mut S) -> serde_json::Value
+    where
+        S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+            + Unpin,
+    {
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .expect("message in time")
+            .expect("open socket")
+            .expect("read");
+        serde_json::from_str(msg.to_text().expect("text")).expect("json")
+    }
+
+    let page = client
+        .get(format!("http://{addr}/recent?tag=live"))
+        .send()
+        .await
+        .expect("dashboard")
+        .text()
+        .await
+        .expect("body");
+    assert!(page.contains("data-live=\"/ws?tag=live\""));
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws?tag=live"))
+        .await
+        .expect("connect");
+    create("other.txt", "ops").await;
+    let first = create("one.txt", "live").await;
+    let event = next_json(&mut socket).await;
+    assert_eq!(event["type"], "created");
+    assert_eq!(event["id"], first.as_str());
+    assert_eq!(event["tag"], "live");
+    let seq = event["seq"].as_u64().expect("seq");
+    drop(socket);
+
+    let second = create("two.txt", "live").await;
+    let (mut resumed, _) =
+        tokio_tungstenite::connect_async(format!("ws://{addr}/ws?tag=live&since={seq}"))
+            .await
+            .expect("reconnect");
+    assert_eq!(next_json(&mut resumed).await["id"], second.as_str());
+}
+
+#[tokio::test]
+async fn private_pastes_are_readable_only_by_allowed_keys() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"alice","key":"alice-key","scopes":["*"]},
+            {"name":"bob","key":"bob-key","scopes":["paste:read"]},
+            {"name":"carol","key":"carol-key","scopes":["paste:read","recent:read"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4201)))),
+    )
+    .expect("server");
+
+    server
+        .post("/api/v1/paste?name=creds.txt&visibility=private")
+        .add_header("X-API-Key", "alice-key")
+        .text("hunter2")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .post("/api/v1/paste?name=creds.txt&allow_keys=bob")
+        .add_header("X-API-Key", "alice-key")
+        .text("hunter2")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let created = server
+        .post("/api/v1/paste?name=creds.txt&visibility=private&allow_keys=alice,%20bob")
+        .add_header("X-API-Key", "alice-key")
+        .text("hunter2")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let meta = server
+        .get(&format!("/api/v1/p/{id}"))
+        .add_header("X-API-Key", "bob-key")
+        .await;
+    meta.assert_status_ok();
+    let meta = meta.json::<serde_json::Value>();
+    assert_eq!(meta["visibility"], "private");
+    assert_eq!(meta["allow_keys"], serde_json::json!(["alice", "bob"]));
+    let raw = server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .add_header("X-API-Key", "bob-key")
+        .await;
+    raw.assert_status_ok();
+    assert_eq!(raw.text(), "hunter2");
+
+    for path in [
+        format!("/api/v1/p/{id}"),
+        format!("/api/v1/p/{id}/raw"),
+        format!("/api/v1/p/{id}/tail"),
+        format!("/p/{id}"),
+        format!("/p/{id}/raw.txt"),
+    ] {
+        server
+            .get(&path)
+            .add_header("X-API-Key", "carol-key")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+    server
+        .post(&format!("/api/v1/p/{id}/star"))
+        .add_header("X-API-Key", "carol-key")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .delete(&format!("/api/v1/p/{id}/star"))
+        .add_header("X-API-Key", "carol-key")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .get(&format!("/p/{id}"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .get(&format!("/p/{id}"))
+        .add_header("X-API-Key", "alice-key")
+        .await
+        .assert_status_ok();
+
+    let recent = server
+        .get("/api/v1/recent")
+        .add_header("X-API-Key", "carol-key")
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(recent.as_array().expect("list").len(), 0);
+}
+
+#[tokio::test]
+async fn webhooks_post_signed_payloads_for_new_pastes() {
+    let (hook_tx, mut hook_rx) = tokio::sync::mpsc::unbounded_channel::<(HeaderMap, String)>();
+    let receiver = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move |headers: HeaderMap, body: String| {
+            let _ = hook_tx.send((headers, body));
+            async { StatusCode::OK }
+        }),
+    );
+    let hook_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let hook_url = format!("http://{}/hook", hook_listener.local_addr().expect("addr"));
+    tokio::spawn(async move { axum::serve(hook_listener, receiver).await });
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let secret_path = dir.path().join("webhook.secret");
+    fs::write(&secret_path, "shared-secret\n").expect("write secret");
+    let mut cfg = test_cfg(dir.path());
+    cfg.webhook_url = vec![hook_url];
+    cfg.webhook_secret_file = Some(secret_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let hooks = state.webhooks.clone().expect("webhooks configured");
+    webhooks::spawn_worker(state.clone(), hooks);
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4202)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste?name=hooked.txt&tag=ci")
+        .add_header("X-Paste-Token", "tok")
+        .text("hooked")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let (headers, body) = tokio::time::timeout(std::time::Duration::from_secs(5), hook_rx.recv())
+        .await
+        .expect("delivered in time")
+        .expect("delivery");
+    assert_eq!(headers[webhooks::EVENT_HEADER], "created");
+    assert_eq!(
+        headers[webhooks::SIGNATURE_HEADER].to_str().expect("sig"),
+        webhooks::signature(b"shared-secret", body.as_bytes())
+    );
+    let payload: serde_json::Value = serde_json::from_str(&body).expect("json");
+    assert_eq!(payload["id"], id.as_str());
+    assert_eq!(payload["tag"], "ci");
+    assert_eq!(payload["size"], 6);
+    assert_eq!(
+        payload["sha256"],
+        hex::encode(Sha256::digest(b"hooked")).as_str()
+    );
+    assert_eq!(payload["urls"]["view"], format!("/p/{id}"));
+}
+
+#[tokio::test]
+async fn unlisted_pastes_are_readable_by_link_without_a_key() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("api-keys.json");
+    write_api_keys_file(&keys_path);
+    let mut cfg = test_cfg(dir.path());
+    cfg.api_keys_file = Some(keys_path);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4203)))),
+    )
+    .expect("server");
+
+    let create = |query: &'static str, body: &'static str| {
+        server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-API-Key", "writer-key")
+            .text(body)
+    };
+    let listed: serde_json::Value = create("name=team.txt", "for keyholders").await.json();
+    let gist: serde_json::Value =
+        create("name=gist.txt&visibility=unlisted", "line one\nline two\n")
+            .await
+            .json();
+    let gist_id = gist["id"].as_str().expect("id");
+
+    let meta: serde_json::Value = server.get(&format!("/api/v1/p/{gist_id}")).await.json();
+    assert_eq!(meta["visibility"], "unlisted");
+    assert_eq!(
+        server.get(&format!("/api/v1/p/{gist_id}/raw")).await.text(),
+        "line one\nline two\n"
+    );
+    assert_eq!(
+        server
+            .get(&format!("/api/v1/p/{gist_id}/tail?lines=1"))
+            .await
+            .text(),
+        "line two\n"
+    );
+
+    // Everything else still needs a key, and a miss looks like any other.
+    let listed_id = listed["id"].as_str().expect("id");
+    server
+        .get(&format!("/api/v1/p/{listed_id}/raw"))
+        .await
+        .assert_status_unauthorized();
+    server
+        .get("/api/v1/p/01ARZ3NDEKTSV4RRFFQ69G5FAV")
+        .await
+        .assert_status_unauthorized();
+    server
+        .get(&format!(
+            "/api/v1/blob/{}",
+            meta["sha256"].as_str().expect("sha")
+        ))
+        .await
+        .assert_status_unauthorized();
+    server
+        .post(&format!("/api/v1/p/{gist_id}/append"))
+        .text("more")
+        .await
+        .assert_status_unauthorized();
+
+    let recent: serde_json::Value = server
+        .get("/api/v1/recent")
+        .add_header("X-API-Key", "reader-key")
+        .await
+        .json();
+    assert_eq!(recent.as_array().expect("list").len(), 1);
+}
+
+#[tokio::test]
+async fn admin_reload_applies_the_config_file_and_lists_fixed_settings() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]},{"name":"dev","key":"dev-key","scopes":["paste:create"]}]}"#,
+    )
+    .expect("write keys");
+    let config_path = dir.path().join("lanpaste.json");
+    fs::write(&config_path, r#"{"max_bytes": 8}"#).expect("write config");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    cfg.config_file = Some(config_path.clone());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4204)))),
+    )
+    .expect("server");
+
+    let paste = "more than eight bytes";
+    server
+        .post("/api/v1/paste")
+        .add_header("X-API-Key", "dev-key")
+        .text(paste)
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+    fs::write(&config_path, r#"{"max_bytes": 4096, "retain_days": 7}"#).expect("write config");
+    server
+        .post("/api/v1/admin/reload")
+        .add_header("X-API-Key", "dev-key")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    let res = server
+        .post("/api/v1/admin/reload")
+        .add_header("X-API-Key", "ops-key")
+        .await;
+    res.assert_status_ok();
+    let report: serde_json::Value = res.json();
+    assert_eq!(
+        report["changed"],
+        serde_json::json!(["max_bytes", "retain_days"])
+    );
+    assert_eq!(report["api_keys"], 2);
+    let fixed = report["restart_required"].as_array().expect("list");
+    assert!(fixed.contains(&serde_json::json!("bind")));
+    assert!(!fixed.contains(&serde_json::json!("max_bytes")));
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste")
+        .add_header("X-API-Key", "dev-key")
+        .text(paste)
+        .await
+        .json();
+    assert!(!created["expires_at"].is_null());
+
+    // A bad file is refused and the running settings stay.
+    fs::write(&config_path, r#"{"bind": "0.0.0.0:9000", "max_bytes": 8}"#).expect("write");
+    let res = server
+        .post("/api/v1/admin/reload")
+        .add_header("X-API-Key", "ops-key")
+        .await;
+    res.assert_status(StatusCode::BAD_REQUEST);
+    assert!(res.text().contains("--bind"));
+    server
+        .post("/api/v1/paste")
+        .add_header("X-API-Key", "dev-key")
+        .text(paste)
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn keyless_clients_are_rate_limited_per_ip() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.ip_rate = Some(1.0 / 60.0);
+    cfg.ip_burst = 2;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4205)))),
+    )
+    .expect("server");
+    let neighbour = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 2], 4206)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("one")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_status_ok();
+    let res = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("two")
+        .await;
+    res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.header("retry-after"), "60");
+    server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    server.get("/healthz").await.assert_status_ok();
+    neighbour
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_status_ok();
+
+    // With a key file, keyed requests only answer to their key's own limit.
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    write_api_keys_file(&keys_path);
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path);
+    cfg.ip_rate = Some(1.0 / 60.0);
+    cfg.ip_burst = 1;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4207)))),
+    )
+    .expect("server");
+    for _ in 0..3 {
+        server
+            .get("/api/v1/recent")
+            .add_header("X-API-Key", "reader-key")
+            .await
+            .assert_status_ok();
+    }
+    server
+        .get("/api/v1/recent")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .get("/api/v1/recent")
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn global_write_rate_and_in_flight_cap_are_enforced() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.write_rate = Some(1.0 / 60.0);
+    cfg.write_burst = 2;
+    cfg.max_in_flight = Some(1);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4208)))),
+    )
+    .expect("server");
+    let other = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 2], 4209)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("one")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text(" more")
+        .await
+        .assert_status_ok();
+    // The budget is shared: another client is refused too, but reads go on.
+    let res = other
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("two")
+        .await;
+    res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.header("retry-after"), "60");
+    other
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_text("one more");
+
+    let slots = state.limits.in_flight.clone().expect("in-flight cap");
+    let busy = slots.try_acquire_owned().expect("free slot");
+    let res = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    res.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.header("retry-after"), "1");
+    server.get("/healthz").await.assert_status_ok();
+    drop(busy);
+    server
+        .get(&format!("/api/v1/p/{id}/raw"))
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn startup_summary_is_written_to_the_run_dir() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.webdav = true;
+    cfg.retain_days = Some(30);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+    assert!(
+        instance::Instance::read(&state.paths)
+            .expect("read")
+            .is_none()
+    );
+
+    let bound: SocketAddr = "127.0.0.1:4210".parse().expect("addr");
+    instance::announce(&state, &[bound]).expect("announce");
+    let summary = instance::Instance::read(&state.paths)
+        .expect("read")
+        .expect("written");
+    assert_eq!(summary.pid, std::process::id());
+    assert_eq!(summary.bind, [bound]);
+    assert_eq!(summary.base_url, "http://127.0.0.1:4210/");
+    assert_eq!(summary.auth, "token");
+    assert_eq!(summary.storage.backend, "git");
+    assert!(!summary.storage.encrypted);
+    assert_eq!(summary.push, "off");
+    assert_eq!(summary.features, ["webdav", "retention"]);
+
+    let raw: serde_json::Value = serde_json::from_slice(
+        &fs::read(dir.path().join("run").join(instance::FILE_NAME)).expect("file"),
+    )
+    .expect("json");
+    assert!(raw["started_at"].as_str().expect("rfc3339").ends_with('Z'));
+}
+
+#[tokio::test]
+async fn forwarded_client_ips_are_honored_only_from_trusted_proxies() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.allow_cidr = vec!["192.168.1.0/24".parse().expect("cidr")];
+    cfg.trusted_proxies = vec!["127.0.0.1/32".parse().expect("cidr")];
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let proxy = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4211)))),
+    )
+    .expect("server");
+    let stranger = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 2], 4212)))),
+    )
+    .expect("server");
+
+    let created: serde_json::Value = proxy
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("X-Forwarded-For", "10.9.9.9, 192.168.1.50")
+        .text("via nginx")
+        .await
+        .json();
+    let id = created["id"].as_str().expect("id");
+    let meta: serde_json::Value = proxy.get(&format!("/api/v1/p/{id}")).await.json();
+    assert_eq!(meta["client_ip"], "192.168.1.50");
+
+    proxy
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("Forwarded", "for=192.168.1.51;proto=http")
+        .text("standard header")
+        .await
+        .assert_status(StatusCode::CREATED);
+    for forwarded_for in [None, Some("10.9.9.9")] {
+        let mut req = proxy
+            .post("/api/v1/paste")
+            .add_header("X-Paste-Token", "tok");
+        if let Some(ip) = forwarded_for {
+            req = req.add_header("X-Forwarded-For", ip);
+        }
+        req.text("outside")
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+    stranger
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .add_header("X-Forwarded-For", "192.168.1.50")
+        .text("spoofed")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn cors_lets_listed_origins_call_the_api() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.cors_origin = vec!["http://wiki.lan:3000".to_string()];
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4213)))),
+    )
+    .expect("server");
+
+    let res = server
+        .method(axum::http::Method::OPTIONS, "/api/v1/paste")
+        .add_header("Origin", "http://wiki.lan:3000")
+        .add_header("Access-Control-Request-Method", "POST")
+        .add_header(
+            "Access-Control-Request-Headers",
+            "x-paste-token, content-type",
+        )
+        .await;
+    res.assert_status_ok();
+    assert_eq!(
+        res.header("access-control-allow-origin"),
+        "http://wiki.lan:3000"
+    );
+    let allowed = res.header("access-control-allow-headers");
+    assert!(allowed.to_str().expect("ascii").contains("x-paste-token"));
+    assert!(
+        res.maybe_header("access-control-allow-credentials")
+            .is_none()
+    );
+
+    let res = server
+        .post("/api/v1/paste")
+        .add_header("Origin", "http://wiki.lan:3000")
+        .add_header("X-Paste-Token", "tok")
+        .text("from the wiki")
+        .await;
+    res.assert_status(StatusCode::CREATED);
+    assert_eq!(
+        res.header("access-control-allow-origin"),
+        "http://wiki.lan:3000"
+    );
+    let exposed = res.header("access-control-expose-headers");
+    assert!(exposed.to_str().expect("ascii").contains("x-paste-id"));
+
+    let res = server
+        .get("/api/v1/recent")
+        .add_header("Origin", "http://elsewhere.lan")
+        .await;
+    res.assert_status_ok();
+    assert!(res.maybe_header("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn html_pages_carry_a_content_security_policy() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.frame_ancestors = vec!["https://wiki.lan".to_string()];
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4214)))),
+    )
+    .expect("server");
+
+    let created = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("# framed\n\n<script>alert(1)</script>")
+        .await;
+    created.assert_status(StatusCode::CREATED);
+    assert!(created.maybe_header("content-security-policy").is_none());
+    let id = created.json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+
+    let view = server.get(&format!("/p/{id}")).await;
+    view.assert_status_ok();
+    let csp = view.header("content-security-policy");
+    let csp = csp.to_str().expect("ascii");
+    assert!(csp.starts_with("default-src 'self'; script-src 'self' "));
+    assert!(csp.ends_with("; frame-ancestors https://wiki.lan"));
+    assert!(!csp.contains("script-src 'self' 'unsafe-inline'"));
+    assert!(view.maybe_header("x-frame-options").is_none());
+    assert_eq!(view.header("referrer-policy"), "same-origin");
+    assert!(!view.text().contains("<script>"));
+
+    let dashboard = server.get("/").await;
+    assert!(dashboard.maybe_header("content-security-policy").is_some());
+    let raw = server.get(&format!("/api/v1/p/{id}/raw")).await;
+    assert!(raw.maybe_header("content-security-policy").is_none());
+}
+
+#[tokio::test]
+async fn stats_count_pastes_tags_and_disk_usage() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4215)))),
+    )
+    .expect("server");
+
+    let empty: serde_json::Value = server.get("/api/v1/stats").await.json();
+    assert_eq!(empty["pastes"], 0);
+    assert!(empty["oldest"].is_null());
+
+    for (tag, body) in [("ci", "first"), ("ci", "second"), ("ops", "third!")] {
+        server
+            .post(&format!("/api/v1/paste?tag={tag}"))
+            .add_header("X-Paste-Token", "tok")
+            .text(body)
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server
+        .post("/api/v1/paste?tag=secret&visibility=unlisted")
+        .add_header("X-Paste-Token", "tok")
+        .text("hidden")
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let res = server.get("/api/v1/stats").await;
+    res.assert_status_ok();
+    let stats: serde_json::Value = res.json();
+    assert_eq!(stats["pastes"], 4);
+    assert_eq!(stats["bytes"], 5 + 6 + 6 + 6);
+    assert_eq!(stats["tags"], serde_json::json!({"ci": 2, "ops": 1}));
+    assert!(!stats["oldest"].is_null() && !stats["newest"].is_null());
+    assert!(stats["repo_bytes"].as_u64().expect("repo bytes") > 0);
+    assert!(stats["uptime_secs"].as_i64().expect("uptime") >= 0);
+}
+
+#[tokio::test]
+async fn low_disk_space_refuses_new_pastes_with_507() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.min_free_bytes = u64::MAX;
+    let refused = preflight::run_preflight(&cfg).expect_err("no disk is this big");
+    assert!(matches!(
+        refused,
+        lanpaste::errors::AppError::InsufficientStorage(_)
+    ));
+
+    cfg.min_free_bytes = 0;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let created = {
+        let state = Arc::new(preflight::build_state(cfg.clone()).expect("state"));
+        let server = TestServer::new(
+            http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4216)))),
+        )
+        .expect("server");
+        let res = server
+            .post("/api/v1/paste")
+            .add_header("X-Paste-Token", "tok")
+            .text("before the disk filled up")
+            .await;
+        res.assert_status(StatusCode::CREATED);
+        res.json::<serde_json::Value>()["id"]
+            .as_str()
+            .expect("id")
+            .to_string()
+    };
+
+    cfg.min_free_bytes = u64::MAX;
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4216)))),
+    )
+    .expect("server");
+    let res = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("no room")
+        .await;
+    res.assert_status(StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(
+        res.json::<serde_json::Value>()["error"],
+        "insufficient_storage"
+    );
+    server
+        .get(&format!("/api/v1/p/{created}/raw"))
+        .await
+        .assert_status_ok();
+    let ready = server.get("/readyz").await;
+    ready.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        ready.json::<serde_json::Value>()["checks"]["disk"]["status"],
+        "fail"
+    );
+}
+
+#[tokio::test]
+async fn writes_wait_for_the_git_lock_then_give_up_with_503() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.lock_timeout = std::time::Duration::from_secs(2);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let lock_path = state.paths.git_lock.clone();
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4217)))),
+    )
+    .expect("server");
+
+    let held = FileLock::acquire(&lock_path).expect("git lock");
+    let release = tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        drop(held);
+    });
+    server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("waited my turn")
+        .await
+        .assert_status(StatusCode::CREATED);
+    release.await.expect("release");
+
+    let _held = FileLock::acquire(&lock_path).expect("git lock");
+    let started = std::time::Instant::now();
+    let res = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("gave up")
+        .await;
+    assert!(started.elapsed() >= std::time::Duration::from_secs(2));
+    res.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.header("retry-after"), "1");
+    assert_eq!(res.json::<serde_json::Value>()["error"], "busy");
+}
+
+#[tokio::test]
+async fn concurrent_writes_queue_on_the_commit_thread() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4218)))),
+    )
+    .expect("server");
+
+    let creates = (0..12).map(|n| {
+        server
+            .post("/api/v1/paste")
+            .add_header("X-Paste-Token", "tok")
+            .text(format!("paste {n}"))
+            .into_future()
+    });
+    let mut commits = std::collections::HashSet::new();
+    for res in futures_util::future::join_all(creates).await {
+        res.assert_status(StatusCode::CREATED);
+        assert!(commits.insert(res.json::<serde_json::Value>()["commit"].to_string()));
+    }
+
+    let id = server
+        .post("/api/v1/paste")
+        .add_header("X-Paste-Token", "tok")
+        .text("log\n")
+        .await
+        .json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let appends = (0..8).map(|_| {
+        server
+            .post(&format!("/api/v1/p/{id}/append"))
+            .add_header("X-Paste-Token", "tok")
+            .text("line\n")
+            .into_future()
+    });
+    let mut offsets: Vec<u64> = futures_util::future::join_all(appends)
+        .await
+        .into_iter()
+        .map(|res| {
+            res.assert_status_ok();
+            res.json::<serde_json::Value>()["offset"]
+                .as_u64()
+                .expect("offset")
+        })
+        .collect();
+    offsets.sort_unstable();
+    assert_eq!(offsets, (0..8).map(|n| 4 + 5 * n).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn views_are_rendered_once_until_the_paste_changes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4219)))),
+    )
+    .expect("server");
+
+    let id = server
+        .post("/api/v1/paste?name=notes.md")
+        .add_header("X-Paste-Token", "tok")
+        .text("# Notes\n")
+        .await
+        .json::<serde_json::Value>()["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let first = server.get(&format!("/p/{id}")).await.text();
+    assert!(first.contains("<h1>Notes</h1>"));
+    assert_eq!(state.renders.cached().0, 1);
+    assert_eq!(server.get(&format!("/p/{id}")).await.text(), first);
+    assert_eq!(state.renders.cached().0, 1);
+
+    server
+        .post(&format!("/api/v1/p/{id}/append"))
+        .add_header("X-Paste-Token", "tok")
+        .text("\n## More\n")
+        .await
+        .assert_status_ok();
+    let appended = server.get(&format!("/p/{id}")).await.text();
+    assert!(appended.contains("<h2>More</h2>"));
+    assert_eq!(state.renders.cached().0, 2);
+}
+
+#[tokio::test]
+async fn typed_client_round_trips_server_types() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let base = format!("http://{}/", listener.local_addr().expect("addr"));
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            http::app(state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    let client = Client::new(&base, client::DEFAULT_TIMEOUT)
+        .expect("client")
+        .with_token("tok");
+
+    let created = client
+        .create_paste(
+            &CreatePasteParams {
+                name: Some("notes.md".to_string()),
+                tag: Some("typed".to_string()),
+                visibility: Some(Visibility::Unlisted),
+                ..CreatePasteParams::default()
+            },
+            "# typed",
+        )
+        .await
+        .expect("create");
+    assert_eq!(created.visibility, Visibility::Unlisted);
+    assert_eq!(
+        client.link(&created.view_url),
+        format!("{}p/{}", base, created.id)
+    );
+
+    let meta = client.get_meta(&created.id).await.expect("meta");
+    assert_eq!(meta.meta.tag.as_deref(), Some("typed"));
+    assert_eq!(meta.meta.size, "# typed".len());
+
+    client
+        .create_paste(
+            &CreatePasteParams {
+                tag: Some("typed".to_string()),
+                ..CreatePasteParams::default()
+            },
+            "listed",
+        )
+        .await
+        .expect("create listed");
+    let recent = client
+        .recent(&RecentParams {
+            n: Some(10),
+            tag: Some("typed".to_string()),
+            ..RecentParams::default()
+        })
+        .await
+        .expect("recent");
+    assert_eq!(recent.len(), 1, "unlisted pastes stay out of recents");
+
+    let missing = client.get_meta("01ARZ3NDEKTSV4RRFFQ69G5FAV").await;
+    assert!(matches!(missing, Err(AppError::NotFound(_))), "{missing:?}");
+    let anonymous = Client::new(&base, client::DEFAULT_TIMEOUT).expect("client");
+    let denied = anonymous
+        .create_paste(&CreatePasteParams::default(), "x")
+        .await;
+    assert!(
+        matches!(denied, Err(AppError::Unauthorized(_))),
+        "{denied:?}"
+    );
+}
+
+#[tokio::test]
+async fn grpc_creates_reads_and_streams_pastes() {
+    use lanpaste::grpc::{self, proto};
+    use tonic::Code;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.verify_reads = VerifyMode::Always;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = grpc::bind("127.0.0.1:0".parse().expect("addr"))
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    tokio::spawn(grpc::serve(state, listener, std::future::pending()));
+    let channel = tonic::transport::Channel::from_shared(format!("http://{addr}"))
+        .expect("uri")
+        .connect()
+        .await
+        .expect("connect");
+    let mut client = proto::pastes_client::PastesClient::new(channel);
+    let create = |content: &str, key: Option<&str>| {
+        let mut request = tonic::Request::new(proto::CreateRequest {
+            content: content.as_bytes().to_vec(),
+            name: Some("notes.md".to_string()),
+            tag: Some("grpc".to_string()),
+            ..proto::CreateRequest::default()
+        });
+        request
+            .metadata_mut()
+            .insert("x-paste-token", "tok".parse().expect("token"));
+        if let Some(key) = key {
+            request
+                .metadata_mut()
+                .insert("idempotency-key", key.parse().expect("key"));
+        }
+        request
+    };
+
+    let mut watch = client
+        .watch(proto::WatchRequest {
+            tag: Some("grpc".to_string()),
+            since: None,
+        })
+        .await
+        .expect("watch")
+        .into_inner();
+    let created = client
+        .create(create("# over grpc", Some("once")))
+        .await
+        .expect("create")
+        .into_inner();
+    assert!(!created.replayed);
+    assert_eq!(created.view_url, format!("/p/{}", created.id));
+    let event = tokio::time::timeout(std::time::Duration::from_secs(5), watch.message())
+        .await
+        .expect("event in time")
+        .expect("stream")
+        .expect("event");
+    assert_eq!(event.kind(), proto::paste_event::Kind::Created);
+    assert_eq!(event.paste.expect("paste").id, created.id);
+
+    let replayed = client
+        .create(create("# over grpc", Some("once")))
+        .await
+        .expect("replay")
+        .into_inner();
+    assert!(replayed.replayed);
+    assert_eq!(replayed.id, created.id);
+
+    let paste = client
+        .get(proto::GetRequest {
+            id: created.short_id.clone().expect("short id"),
+            include_content: true,
+        })
+        .await
+        .expect("get")
+        .into_inner();
+    assert_eq!(paste.id, created.id);
+    assert_eq!(paste.content, b"# over grpc");
+    assert_eq!(paste.tag.as_deref(), Some("grpc"));
+
+    let recent = client
+        .recent(proto::RecentRequest {
+            n: Some(10),
+            tag: Some("grpc".to_string()),
+            superseded: false,
+            owner: None,
+        })
+        .await
+        .expect("recent")
+        .into_inner();
+    assert_eq!(recent.items.len(), 1);
+
+    let mut anonymous = create("no token", None);
+    anonymous.metadata_mut().remove("x-paste-token");
+    let denied = client.create(anonymous).await.expect_err("no token");
+    assert_eq!(denied.code(), Code::Unauthenticated);
+    let missing = client
+        .get(proto::GetRequest {
+            id: "01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string(),
+            include_content: false,
+        })
+        .await
+        .expect_err("missing");
+    assert_eq!(missing.code(), Code::NotFound);
+
+    // --verify-reads applies to content read over gRPC too.
+    fs::write(dir.path().join("repo").join(&paste.path), b"tampered").expect("tamper");
+    let tampered = client
+        .get(proto::GetRequest {
+            id: created.id.clone(),
+            include_content: true,
+        })
+        .await
+        .expect_err("tampered");
+    assert_eq!(tampered.code(), Code::DataLoss);
+}
+
+#[tokio::test]
+async fn paste_subcommand_posts_stdin_and_prints_the_url() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let base = format!("http://{}", listener.local_addr().expect("addr"));
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            http::app(state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    let home = tempfile::tempdir().expect("home");
+    let lanpaste = |args: &[&str]| {
+        let mut cmd = tokio::process::Command::new(env!("CARGO_BIN_EXE_lanpaste"));
+        cmd.arg("paste")
+            .args(args)
+            .env("HOME", home.path())
+            .env_remove("XDG_CONFIG_HOME")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        cmd
+    };
+
+    let mut child = lanpaste(&[
+        "--to",
+        &base,
+        "--name",
+        "build.log",
+        "--tag",
+        "ci",
+        "--token",
+        "tok",
+    ])
+    .spawn()
+    .expect("spawn");
+    let mut stdin = child.stdin.take().expect("stdin");
+    tokio::io::AsyncWriteExt::write_all(&mut stdin, b"build ok\n")
+        .await
+        .expect("write stdin");
+    drop(stdin);
+    let out = child.wait_with_output().await.expect("run");
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let url = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    let id = url.strip_prefix(&format!("{base}/p/")).expect("view url");
+    let meta: serde_json::Value = reqwest::get(format!("{base}/api/v1/p/{id}"))
+        .await
+        .expect("meta")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(meta["tag"], "ci");
+    assert!(meta["path"].as_str().expect("path").contains("__build"));
+
+    let file = home.path().join("notes.txt");
+    fs::write(&file, "from a file").expect("write file");
+    let out = lanpaste(&[
+        "--to",
+        &base,
+        "--token",
+        "tok",
+        "--raw",
+        file.to_str().unwrap(),
+    ])
+    .output()
+    .await
+    .expect("run");
+    let raw_url = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    assert!(raw_url.ends_with("/raw"), "{raw_url}");
+    let body = reqwest::get(&raw_url)
+        .await
+        .expect("raw")
+        .text()
+        .await
+        .expect("text");
+    assert_eq!(body, "from a file");
+
+    let out = lanpaste(&["--to", &base, file.to_str().unwrap()])
+        .output()
+        .await
+        .expect("run");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("401"));
+    let out = lanpaste(&[file.to_str().unwrap()])
+        .output()
+        .await
+        .expect("run");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no server"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_tells_its_supervisor_when_it_is_ready_and_stopping() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let notify = dir.path().join("notify.sock");
+    let supervisor = tokio::net::UnixDatagram::bind(&notify).expect("notify socket");
+    let pid_file = dir.path().join("lanpaste.pid");
+    let mut child = tokio::process::Command::new(env!("CARGO_BIN_EXE_lanpaste"))
+        .arg("serve")
+        .arg("--dir")
+        .arg(dir.path().join("data"))
+        .args(["--bind", "127.0.0.1:0", "--pid-file"])
+        .arg(&pid_file)
+        .env("NOTIFY_SOCKET", &notify)
+        .env_remove("LISTEN_FDS")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("spawn");
+    let next_state = || async {
+        let mut buf = [0u8; 256];
+        let n = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            supervisor.recv(&mut buf),
+        )
+        .await
+        .expect("notified in time")
+        .expect("recv");
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    };
+
+    let ready = next_state().await;
+    let addr = ready
+        .strip_prefix("READY=1\nSTATUS=serving on ")
+        .unwrap_or_else(|| panic!("unexpected notification {ready:?}"));
+    let pid = child.id().expect("pid");
+    let written = std::fs::read_to_string(&pid_file).expect("pid file");
+    assert_eq!(written.trim(), pid.to_string());
+    let health = reqwest::get(format!("http://{addr}/healthz"))
+        .await
+        .expect("healthz");
+    assert!(health.status().is_success());
+
+    let killed = std::process::Command::new("kill")
+        .args(["-TERM", &pid.to_string()])
+        .status()
+        .expect("kill");
+    assert!(killed.success());
+    assert_eq!(next_state().await, "STOPPING=1");
+    let status = tokio::time::timeout(std::time::Duration::from_secs(30), child.wait())
+        .await
+        .expect("exited in time")
+        .expect("wait");
+    assert!(status.success(), "{status}");
+    assert!(!pid_file.exists());
+}
+
+#[tokio::test]
+async fn a_disabled_key_is_refused_until_enabled_again() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keys_path = dir.path().join("keys.json");
+    fs::write(
+        &keys_path,
+        r#"{"keys":[{"name":"ops","key":"ops-key","scopes":["admin"]},{"name":"bot","key":"bot-key","scopes":["paste:create"]}]}"#,
+    )
+    .expect("write keys");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.clear();
+    cfg.api_keys_file = Some(keys_path.clone());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4236)))),
+    )
+    .expect("server");
+    let create = || {
+        server
+            .post("/api/v1/paste?name=a.txt")
+            .add_header("X-API-Key", "bot-key")
+            .text("hello")
+    };
+
+    create().await.assert_status(StatusCode::CREATED);
+    server
+        .post("/api/v1/admin/keys/bot/disable")
+        .add_header("X-API-Key", "bot-key")
+        .await
+        .assert_status_forbidden();
+    let disabled: serde_json::Value = server
+        .post("/api/v1/admin/keys/bot/disable")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(disabled["enabled"], false);
+    assert_eq!(disabled["requests"], 1);
+    create().await.assert_status_unauthorized();
+
+    let keys: serde_json::Value = server
+        .get("/api/v1/admin/keys")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .json();
+    assert_eq!(keys["keys"][1]["name"], "bot");
+    assert_eq!(keys["keys"][1]["enabled"], false);
+    let file: serde_json::Value =
+        serde_json::from_slice(&fs::read(&keys_path).expect("read keys")).expect("json");
+    assert_eq!(file["keys"][1]["enabled"], false);
+
+    server
+        .post("/api/v1/admin/keys/bot/enable")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .assert_status_ok();
+    create().await.assert_status(StatusCode::CREATED);
+    server
+        .post("/api/v1/admin/keys/nobody/disable")
+        .add_header("X-API-Key", "ops-key")
+        .await
+        .assert_status_not_found();
+}
+
+#[tokio::test]
+async fn rotating_the_token_keeps_the_old_one_for_the_grace_window() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4237)))),
+    )
+    .expect("server");
+    let create = |token: &str| {
+        server
+            .post("/api/v1/paste?name=a.txt")
+            .add_header("X-Paste-Token", token)
+            .text("hello")
+    };
+
+    server
+        .post("/api/v1/admin/token/rotate")
+        .add_header("X-Paste-Token", "wrong")
+        .await
+        .assert_status_unauthorized();
+    let rotated: serde_json::Value = server
+        .post("/api/v1/admin/token/rotate")
+        .add_header("X-Paste-Token", "tok")
+        .json(&serde_json::json!({ "token": "tok2" }))
+        .await
+        .json();
+    assert_eq!(rotated["token"], "tok2");
+    create("tok2").await.assert_status(StatusCode::CREATED);
+    create("tok").await.assert_status(StatusCode::CREATED);
+
+    // A rotation with no grace ends the old token's window at once.
+    let random: serde_json::Value = server
+        .post("/api/v1/admin/token/rotate")
+        .add_header("X-Paste-Token", "tok2")
+        .json(&serde_json::json!({ "grace": "0s" }))
+        .await
+        .json();
+    let fresh = random["token"].as_str().expect("token");
+    assert_eq!(fresh.len(), 48);
+    create(fresh).await.assert_status(StatusCode::CREATED);
+    create("tok2").await.assert_status_unauthorized();
+    create("tok").await.assert_status_unauthorized();
+    server
+        .post("/api/v1/admin/token/rotate")
+        .add_header("X-Paste-Token", fresh)
+        .json(&serde_json::json!({ "grace": "soon" }))
+        .await
+        .assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn each_shared_token_works_and_a_reload_revokes_dropped_ones() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let token_file = dir.path().join("tokens");
+    fs::write(&token_file, "# one per team\nci-token\nbackup-token\n").expect("write tokens");
+    let mut cfg = test_cfg(dir.path());
+    cfg.token.push("tok-b".to_string());
+    cfg.token_file = Some(token_file.clone());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4238)))),
+    )
+    .expect("server");
+    let create = |token: &str| {
+        server
+            .post("/api/v1/paste?name=a.txt")
+            .add_header("X-Paste-Token", token)
+            .text("hello")
+    };
+
+    for token in ["tok", "tok-b", "ci-token", "backup-token"] {
+        create(token).await.assert_status(StatusCode::CREATED);
+    }
+    create("nope").await.assert_status_unauthorized();
+
+    // Rotating with one token leaves the others alone.
+    let rotated: serde_json::Value = server
+        .post("/api/v1/admin/token/rotate")
+        .add_header("X-Paste-Token", "ci-token")
+        .json(&serde_json::json!({ "token": "ci-2", "grace": "0s" }))
+        .await
+        .json();
+    assert_eq!(rotated["token"], "ci-2");
+    create("ci-token").await.assert_status_unauthorized();
+    create("ci-2").await.assert_status(StatusCode::CREATED);
+    create("backup-token")
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    fs::write(&token_file, "ci-token\n").expect("write tokens");
+    let report = settings::reload(&state).expect("reload");
+    assert_eq!(report.tokens, 3);
+    create("backup-token").await.assert_status_unauthorized();
+    create("ci-2").await.assert_status(StatusCode::CREATED);
+    create("tok").await.assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn repeated_bad_tokens_lock_the_client_out() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let auth_log = dir.path().join("auth.log");
+    let mut cfg = test_cfg(dir.path());
+    cfg.auth_fail_limit = 3;
+    cfg.auth_log = Some(auth_log.clone());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let guesser = TestServer::new(
+        http::app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4239)))),
+    )
+    .expect("server");
+    let neighbour = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 2], 4239)))),
+    )
+    .expect("server");
+    let create = |server: &TestServer, token: &str| {
+        server
+            .post("/api/v1/paste?name=a.txt")
+            .add_header("X-Paste-Token", token)
+            .text("hello")
+    };
+
+    // Requests without credentials are not guesses.
+    for _ in 0..5 {
+        guesser
+            .post("/api/v1/paste?name=a.txt")
+            .text("hello")
+            .await
+            .assert_status_unauthorized();
+    }
+    for guess in ["a", "b"] {
+        create(&guesser, guess).await.assert_status_unauthorized();
+    }
+    create(&guesser, "tok")
+        .await
+        .assert_status(StatusCode::CREATED);
+    for guess in ["c", "d", "e"] {
+        create(&guesser, guess).await.assert_status_unauthorized();
+    }
+
+    let locked = create(&guesser, "tok").await;
+    locked.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    let retry: u64 = locked
+        .header("retry-after")
+        .to_str()
+        .expect("retry-after")
+        .parse()
+        .expect("seconds");
+    assert!((1..=60).contains(&retry), "{retry}");
+    guesser
+        .get("/api/v1/recent")
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    guesser.get("/healthz").await.assert_status_ok();
+    create(&neighbour, "tok")
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let log = fs::read_to_string(&auth_log).expect("auth log");
+    let events: Vec<_> = log
+        .lines()
+        .map(|line| line.split_once(' ').expect("timestamp").1)
+        .collect();
+    let failure = "lanpaste auth failure ip=127.0.0.1 reason=bad-token path=/api/v1/paste";
+    assert_eq!(events.len(), 6, "{log}");
+    assert!(events[..5].iter().all(|e| *e == failure), "{log}");
+    assert_eq!(events[5], "lanpaste auth lockout ip=127.0.0.1 seconds=60");
+}
+
+#[tokio::test]
+async fn unchecked_requests_do_not_reset_failed_attempts() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = test_cfg(dir.path());
+    cfg.auth_fail_limit = 3;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4243)))),
+    )
+    .expect("server");
+
+    // Reads need no token here, so a junk one rides along unchecked and the
+    // 200 it gets says nothing about the guesses in between.
+    for guess in ["a", "b", "c"] {
+        server
+            .get("/api/v1/recent")
+            .add_header("X-Paste-Token", "junk")
+            .await
+            .assert_status_ok();
+        server
+            .post("/api/v1/paste?name=a.txt")
+            .add_header("X-Paste-Token", guess)
+            .text("hello")
+            .await
+            .assert_status_unauthorized();
+    }
+    server
+        .get("/api/v1/recent")
+        .add_header("X-Paste-Token", "junk")
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn slugs_and_short_ids_do_not_lead_to_unlisted_pastes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = test_cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let server = TestServer::new(
+        http::app(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4245)))),
+    )
+    .expect("server");
+
+    let create = |query: &'static str| {
+        server
+            .post(&format!("/api/v1/paste?{query}"))
+            .add_header("X-Paste-Token", "tok")
+            .text("body")
+    };
+    let listed: serde_json::Value = create("slug=team-notes").await.json();
+    let hidden: serde_json::Value = create("slug=launch-plan&visibility=unlisted").await.json();
+    let derived: serde_json::Value = create("name=salaries.txt&visibility=unlisted").await.json();
+
+    // A guessable slug still finds a listed paste...
+    let listed_id = listed["id"].as_str().expect("id");
+    let found = server.get("/s/team-notes").await;
+    found.assert_status(StatusCode::FOUND);
+    assert_eq!(found.header("location"), format!("/p/{listed_id}"));
+    assert!(listed["short_id"].is_string());
+
+    // ...but not an unlisted one, whose id is its only link.
+    for slug in ["launch-plan", "salaries"] {
+        server
+            .get(&format!("/s/{slug}"))
+            .await
+            .assert_status_not_found();
+        server
+            .get(&format!("/p/{slug}"))
+            .await
+            .assert_status_not_found();
+    }
+    for created in [&hidden, &derived] {
+        assert!(created["short_id"].is_null(), "{created}");
+        assert!(created["short_url"].is_null(), "{created}");
+        let id = created["id"].as_str().expect("id");
+        let meta: serde_json::Value = server.get(&format!("/api/v1/p/{id}")).await.json();
+        assert!(meta.get("short_id").is_none(), "{meta}");
+        server.get(&format!("/p/{id}")).await.assert_status_ok();
+    }
+}
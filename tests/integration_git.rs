@@ -1,23 +1,23 @@
-use std::process::Command;
+use std::{collections::HashSet, process::Command, sync::Arc};
 
 use lanpaste::{
-    config::{PushMode, ServeCmd},
-    preflight,
+    archive,
+    config::{InitCmd, MigrateCmd, PushMode, ServeCmd},
+    doctor, gc, gitops, init,
+    journal::{self, Intent, Journal, Outcome, Stage},
+    kv::Kv,
+    migrate, preflight,
+    readiness::{self, CheckStatus},
+    retention, selftest, store,
+    types::{CreatePasteInput, PasteBody, PasteDraft, PasteLease, PasteMeta, Visibility},
 };
 
+mod common;
+
 fn cfg(base: &std::path::Path) -> ServeCmd {
-    ServeCmd {
-        dir: base.to_path_buf(),
-        bind: "127.0.0.1:0".parse().expect("bind"),
-        token: None,
-        api_keys_file: None,
-        max_bytes: 1024 * 1024,
-        push: PushMode::Off,
-        remote: "origin".to_string(),
-        allow_cidr: vec![],
-        git_author_name: "LAN Paste".to_string(),
-        git_author_email: "paste@lan".to_string(),
-    }
+    let mut cfg = common::serve_cfg(base);
+    cfg.render_cache_bytes = 0;
+    cfg
 }
 
 #[test]
@@ -56,3 +56,799 @@ fn single_instance_lock_blocks_second_state() {
     let state2 = preflight::build_state(cfg);
     assert!(state2.is_err());
 }
+
+#[test]
+fn self_test_round_trips_canary_and_cleans_up() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+
+    let report = selftest::run_self_test(&state);
+    assert!(report.ok, "{}", report.summary());
+    let names: Vec<_> = report.steps.iter().map(|s| s.name).collect();
+    assert_eq!(names, ["create", "read", "render", "delete"]);
+
+    let repo = dir.path().join("repo");
+    let metas = std::fs::read_dir(repo.join("meta"))
+        .expect("meta dir")
+        .count();
+    assert_eq!(metas, 0);
+    let log = Command::new("git")
+        .args(["log", "--format=%s", "-n", "2"])
+        .current_dir(&repo)
+        .output()
+        .expect("git log");
+    let log = String::from_utf8_lossy(&log.stdout);
+    assert!(log.starts_with("selftest: remove canary"));
+    assert!(log.contains("selftest: create canary"));
+    assert!(state.self_test.lock().expect("lock").is_some());
+}
+
+#[test]
+fn self_test_reports_broken_remote() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = cfg(dir.path());
+    cfg.push = PushMode::BestEffort;
+    cfg.remote = "missing".to_string();
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+
+    let report = selftest::run_self_test(&state);
+    assert!(!report.ok);
+    let create = &report.steps[0];
+    assert_eq!(create.name, "create");
+    assert!(
+        create
+            .error
+            .as_deref()
+            .unwrap_or_default()
+            .contains("push failed")
+    );
+    let metas = std::fs::read_dir(dir.path().join("repo").join("meta"))
+        .expect("meta dir")
+        .count();
+    assert_eq!(metas, 0);
+}
+
+#[tokio::test]
+async fn readiness_fails_on_an_unreachable_remote_only_with_strict_push() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = cfg(dir.path());
+    cfg.push = PushMode::Strict;
+    cfg.remote = "missing".to_string();
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+
+    let report = readiness::check(state.clone()).await;
+    assert_eq!(report.status, "not_ready");
+    assert_eq!(report.checks["remote"].status, CheckStatus::Fail);
+    assert_eq!(report.checks["repo"].status, CheckStatus::Ok);
+    assert_eq!(report.checks["lock"].status, CheckStatus::Ok);
+    assert!(report.checks["disk"].value.expect("free bytes") > 0);
+    assert_eq!(report.checks["push_queue"].status, CheckStatus::Warn);
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = self::cfg(dir.path());
+    cfg.push = PushMode::BestEffort;
+    cfg.remote = "missing".to_string();
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = Arc::new(preflight::build_state(cfg).expect("state"));
+    let report = readiness::check(state).await;
+    assert_eq!(report.status, "degraded");
+    assert_eq!(report.checks["remote"].status, CheckStatus::Warn);
+}
+
+fn git(repo: &std::path::Path, args: &[&str]) -> String {
+    let out = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .expect("git");
+    assert!(
+        out.status.success(),
+        "git {args:?}: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8_lossy(&out.stdout).trim().to_string()
+}
+
+fn paste_draft(repo: &std::path::Path, cfg: &ServeCmd, name: &str) -> PasteDraft {
+    let input = CreatePasteInput {
+        name: Some(name.to_string()),
+        msg: None,
+        tag: None,
+        content_type: None,
+        body: PasteBody::Bytes(format!("{name} body").into_bytes()),
+        e2e: false,
+        visibility: Visibility::Listed,
+        allow_keys: Vec::new(),
+        slug: None,
+        client_ip: None,
+        user_agent: None,
+        owner: None,
+        lang: None,
+    };
+    store::build_paste_draft(repo, cfg, None, input).expect("draft")
+}
+
+/// Writes a paste and journals it as `Prepared`, as a strict write does just
+/// before `git add`.
+fn prepare(repo: &std::path::Path, cfg: &ServeCmd, journal: &Journal, name: &str) -> Intent {
+    let draft = paste_draft(repo, cfg, name);
+    journal
+        .begin(
+            repo,
+            cfg,
+            &draft.subject,
+            &[
+                &draft.rel_path,
+                &draft.meta_rel_path,
+                &draft.slug_rel_path,
+                draft.short_rel_path.as_deref().expect("short id"),
+            ],
+        )
+        .expect("begin")
+}
+
+fn commit_intent(repo: &std::path::Path, cfg: &ServeCmd, intent: &Intent) -> String {
+    let mut add = vec!["add", "--"];
+    add.extend(intent.paths.iter().map(String::as_str));
+    gitops::run_git(repo, &add, cfg).expect("add");
+    gitops::run_git(repo, &["commit", "-q", "-m", &intent.subject], cfg).expect("commit");
+    git(repo, &["rev-parse", "HEAD"])
+}
+
+fn assert_rolled_back(repo: &std::path::Path, intent: &Intent) {
+    assert_eq!(git(repo, &["rev-parse", "HEAD"]), intent.parent);
+    assert_eq!(git(repo, &["status", "--porcelain"]), "");
+    for rel in &intent.paths {
+        assert!(!repo.join(rel).exists(), "{rel} left behind");
+    }
+}
+
+#[test]
+fn strict_push_failure_rolls_back_through_the_journal() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let repo = dir.path().join("repo");
+    let journal = Journal::open(Kv::open(dir.path().join("run").join("kv")));
+
+    let draft = paste_draft(&repo, &cfg, "kept.txt");
+    gitops::commit_paste(&repo, &cfg, &draft, PushMode::Off, "origin").expect("commit");
+    let head = git(&repo, &["rev-parse", "HEAD"]);
+    let original = std::fs::read(&draft.abs_path).expect("paste");
+
+    let lost = paste_draft(&repo, &cfg, "lost.txt");
+    let err = gitops::commit_paste(&repo, &cfg, &lost, PushMode::Strict, "missing")
+        .expect_err("strict push");
+    assert!(format!("{err:?}").contains("push failed in strict mode"));
+    assert!(!lost.abs_path.exists() && !lost.meta_path.exists());
+
+    let append = store::build_append_draft(&repo, &cfg, None, &draft.id, b" more").expect("append");
+    gitops::commit_append(&repo, &cfg, &append, PushMode::Strict, "missing")
+        .expect_err("strict push");
+    assert_eq!(std::fs::read(&draft.abs_path).expect("paste"), original);
+
+    assert_eq!(git(&repo, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(&repo, &["status", "--porcelain"]), "");
+    assert!(journal.pending().expect("pending").is_empty());
+}
+
+#[test]
+fn reconcile_settles_each_interrupted_strict_write() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = cfg(dir.path());
+    cfg.push = PushMode::Strict;
+    preflight::run_preflight(&cfg).expect("preflight");
+    let repo = dir.path().join("repo");
+    let remote = dir.path().join("remote.git");
+    git(
+        dir.path(),
+        &["init", "-q", "--bare", remote.to_str().expect("utf8")],
+    );
+    git(
+        &repo,
+        &["remote", "add", "origin", remote.to_str().expect("utf8")],
+    );
+    cfg.remote = "missing".to_string();
+    let journal = Journal::open(Kv::open(dir.path().join("run").join("kv")));
+    let settle = |cfg: &ServeCmd| {
+        let mut settled = journal::reconcile(&repo, cfg, &journal).expect("reconcile");
+        assert_eq!(settled.len(), 1);
+        settled.remove(0).1
+    };
+
+    // Crashed after writing files, before `git commit`.
+    let intent = prepare(&repo, &cfg, &journal, "a.txt");
+    assert_eq!(settle(&cfg), Outcome::RolledBack);
+    assert_rolled_back(&repo, &intent);
+
+    // Crashed after `git commit`, before the journal recorded it.
+    let intent = prepare(&repo, &cfg, &journal, "b.txt");
+    commit_intent(&repo, &cfg, &intent);
+    assert_eq!(settle(&cfg), Outcome::RolledBack);
+    assert_rolled_back(&repo, &intent);
+
+    // Crashed after recording the commit, before the push.
+    let mut intent = prepare(&repo, &cfg, &journal, "c.txt");
+    intent.commit = Some(commit_intent(&repo, &cfg, &intent));
+    intent.stage = Stage::Committed;
+    journal.record(&intent).expect("record");
+    assert_eq!(settle(&cfg), Outcome::RolledBack);
+    assert_rolled_back(&repo, &intent);
+
+    // Crashed mid-rollback, after `reset --soft` moved HEAD back: resuming
+    // must not move HEAD again.
+    let mut intent = prepare(&repo, &cfg, &journal, "d.txt");
+    intent.commit = Some(commit_intent(&repo, &cfg, &intent));
+    intent.stage = Stage::RollingBack;
+    journal.record(&intent).expect("record");
+    git(&repo, &["reset", "-q", "--soft", &intent.parent]);
+    assert_eq!(settle(&cfg), Outcome::RolledBack);
+    assert_rolled_back(&repo, &intent);
+    journal::roll_back(&repo, &cfg, &journal, &mut intent).expect("second rollback");
+    assert_rolled_back(&repo, &intent);
+
+    // A commit whose push now goes through is kept.
+    cfg.remote = "origin".to_string();
+    let intent = prepare(&repo, &cfg, &journal, "e.txt");
+    let commit = commit_intent(&repo, &cfg, &intent);
+    assert_eq!(settle(&cfg), Outcome::Completed);
+    assert_eq!(git(&remote, &["rev-parse", "HEAD"]), commit);
+    assert_eq!(git(&repo, &["rev-parse", "HEAD"]), commit);
+
+    // HEAD moved on since: left for an operator rather than guessed at.
+    let intent = prepare(&repo, &cfg, &journal, "f.txt");
+    commit_intent(&repo, &cfg, &intent);
+    let other = paste_draft(&repo, &cfg, "other.txt");
+    gitops::commit_paste(&repo, &cfg, &other, PushMode::Off, "origin").expect("commit");
+    assert_eq!(settle(&cfg), Outcome::Stuck);
+    let pending = journal.pending().expect("pending");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].id, intent.id);
+}
+
+#[test]
+fn startup_reconciles_the_journal_before_serving() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = cfg(dir.path());
+    cfg.push = PushMode::Strict;
+    cfg.remote = "missing".to_string();
+    preflight::run_preflight(&cfg).expect("preflight");
+    let repo = dir.path().join("repo");
+    let journal = Journal::open(Kv::open(dir.path().join("run").join("kv")));
+
+    let intent = prepare(&repo, &cfg, &journal, "crashed.txt");
+    commit_intent(&repo, &cfg, &intent);
+    let state = preflight::build_state(cfg).expect("state");
+    assert_rolled_back(&repo, &intent);
+    assert!(journal.pending().expect("pending").is_empty());
+    assert!(state.index.is_empty());
+}
+
+#[test]
+fn retention_sweep_removes_oldest_pastes_in_one_commit() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+    let repo = dir.path().join("repo");
+
+    let mut ids = Vec::new();
+    for n in 0..3 {
+        let input = CreatePasteInput {
+            name: Some(format!("note-{n}.txt")),
+            msg: None,
+            tag: None,
+            content_type: None,
+            body: PasteBody::Bytes(format!("paste {n}").into_bytes()),
+            e2e: false,
+            visibility: Visibility::Listed,
+            allow_keys: Vec::new(),
+            slug: None,
+            client_ip: None,
+            user_agent: None,
+            owner: None,
+            lang: None,
+        };
+        let draft = store::build_paste_draft(&repo, &state.cfg, None, input).expect("draft");
+        gitops::commit_paste(&repo, &state.cfg, &draft, PushMode::Off, "origin").expect("commit");
+        ids.push(draft.id);
+    }
+
+    let policy = retention::Policy {
+        max_count: Some(1),
+        ..retention::Policy::default()
+    };
+    let report = retention::sweep(&state, &policy).expect("sweep");
+    assert_eq!(report.removed, vec![ids[1].clone(), ids[0].clone()]);
+    assert!(store::read_meta(&repo, &state.cfg, &ids[2]).is_ok());
+    assert!(store::read_meta(&repo, &state.cfg, &ids[0]).is_err());
+    assert_eq!(store::resolve_slug_id(&repo, "note-0").expect("slug"), None);
+
+    let status = Command::new("git")
+        .args(["status", "--porcelain"])
+        .current_dir(&repo)
+        .output()
+        .expect("git status");
+    assert!(String::from_utf8_lossy(&status.stdout).trim().is_empty());
+    let log = Command::new("git")
+        .args(["log", "--format=%s", "-n", "1"])
+        .current_dir(&repo)
+        .output()
+        .expect("git log");
+    assert_eq!(
+        String::from_utf8_lossy(&log.stdout).trim(),
+        "retention: remove 2 pastes"
+    );
+
+    let again = retention::sweep(&state, &policy).expect("sweep");
+    assert!(again.removed.is_empty() && again.commit.is_none());
+}
+
+#[test]
+fn archive_moves_old_pastes_into_a_tarball() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+    let repo = dir.path().join("repo");
+
+    let mut drafts = Vec::new();
+    for name in ["old-log.txt", "fresh.txt"] {
+        let input = CreatePasteInput {
+            name: Some(name.to_string()),
+            msg: None,
+            tag: None,
+            content_type: None,
+            body: PasteBody::Bytes(format!("contents of {name}").into_bytes()),
+            e2e: false,
+            visibility: Visibility::Listed,
+            allow_keys: Vec::new(),
+            slug: None,
+            client_ip: None,
+            user_agent: None,
+            owner: None,
+            lang: None,
+        };
+        let draft = store::build_paste_draft(&repo, &state.cfg, None, input).expect("draft");
+        gitops::commit_paste(&repo, &state.cfg, &draft, PushMode::Off, "origin").expect("commit");
+        drafts.push(draft);
+    }
+    let old = &drafts[0];
+    let mut meta: PasteMeta =
+        serde_json::from_slice(&std::fs::read(&old.meta_path).expect("meta")).expect("json");
+    meta.created_at -= time::Duration::days(40);
+    std::fs::write(&old.meta_path, serde_json::to_vec(&meta).expect("json")).expect("write");
+    gitops::run_git(&repo, &["commit", "-qam", "backdate"], &state.cfg).expect("backdate");
+
+    let dest = dir.path().join("cold");
+    let report = archive::run(&state, 30, &dest).expect("archive");
+    assert_eq!(report.archived, vec![old.id.clone()]);
+    let tarball = report.tarball.expect("tarball");
+    assert!(tarball.starts_with(&dest));
+
+    let mut entries = tar::Archive::new(flate2::read::GzDecoder::new(
+        std::fs::File::open(&tarball).expect("open tarball"),
+    ));
+    let mut names = Vec::new();
+    for entry in entries.entries().expect("entries") {
+        let mut entry = entry.expect("entry");
+        let name = entry.path().expect("path").to_string_lossy().into_owned();
+        if name == old.rel_path {
+            let mut body = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut body).expect("read");
+            assert_eq!(body, "contents of old-log.txt");
+        }
+        names.push(name);
+    }
+    names.sort();
+    let mut expected = vec![
+        old.rel_path.clone(),
+        old.meta_rel_path.clone(),
+        old.slug_rel_path.clone(),
+        old.short_rel_path.clone().expect("short id"),
+    ];
+    expected.sort();
+    assert_eq!(names, expected);
+
+    assert!(store::read_meta(&repo, &state.cfg, &old.id).is_err());
+    assert!(store::read_meta(&repo, &state.cfg, &drafts[1].id).is_ok());
+    let log = Command::new("git")
+        .args(["log", "--format=%s", "-n", "1"])
+        .current_dir(&repo)
+        .output()
+        .expect("git log");
+    let name = tarball.file_name().expect("name").to_string_lossy();
+    assert_eq!(
+        String::from_utf8_lossy(&log.stdout).trim(),
+        format!("archive: move 1 pastes to {name}")
+    );
+
+    let again = archive::run(&state, 30, &dest).expect("archive");
+    assert!(again.archived.is_empty() && again.tarball.is_none());
+}
+
+#[test]
+fn gc_removes_leftovers_and_reports_drift() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+    let repo = dir.path().join("repo");
+
+    let mut drafts = Vec::new();
+    for name in ["intact.txt", "tampered.txt"] {
+        let draft = paste_draft(&repo, &state.cfg, name);
+        gitops::commit_paste(&repo, &state.cfg, &draft, PushMode::Off, "origin").expect("commit");
+        drafts.push(draft);
+    }
+    std::fs::write(repo.join(&drafts[1].rel_path), "edited by hand").expect("tamper");
+    let orphan = std::path::Path::new(&drafts[0].rel_path)
+        .with_file_name("orphan.txt")
+        .to_string_lossy()
+        .into_owned();
+    std::fs::write(repo.join(&orphan), "no meta").expect("orphan");
+    let crashed = std::fs::File::create(repo.join(".git/index.lock")).expect("lock file");
+    crashed
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600))
+        .expect("backdate");
+    drop(crashed);
+
+    let report = gc::run(&state).expect("gc");
+    assert!(!repo.join(".git/index.lock").exists());
+    assert_eq!(report.stale_files, ["repo/.git/index.lock"]);
+    assert!(report.git_bytes_before > 0 && report.git_bytes_after > 0);
+    assert_eq!(report.pastes_checked, 2);
+    let mut expected = vec![
+        format!("{}: does not match its sha256", drafts[1].rel_path),
+        format!("{orphan}: no meta refers to it"),
+    ];
+    expected.sort();
+    assert_eq!(report.problems, expected);
+    assert!(!report.ok());
+
+    std::fs::remove_file(repo.join(&orphan)).expect("remove orphan");
+    gitops::run_git(&repo, &["checkout", "--", &drafts[1].rel_path], &state.cfg).expect("restore");
+    let clean = gc::run(&state).expect("gc");
+    assert!(
+        clean.ok() && clean.stale_files.is_empty(),
+        "{}",
+        clean.summary()
+    );
+}
+
+#[tokio::test]
+async fn doctor_reports_each_problem_with_a_fix() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let fresh = doctor::run(&cfg(&dir.path().join("data"))).await;
+    assert!(fresh.ok(), "{}", fresh.summary());
+    assert_eq!(
+        fresh.get("repo").expect("repo").status,
+        CheckStatus::Skipped
+    );
+    assert!(!dir.path().join("data").exists());
+
+    let mut cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("listener");
+    let taken = listener.local_addr().expect("addr");
+    cfg.bind = vec!["127.0.0.1:0".parse().expect("free"), taken];
+    let keys = dir.path().join("keys.json");
+    std::fs::write(&keys, "not json").expect("keys");
+    cfg.api_keys_file = Some(keys);
+    cfg.push = PushMode::BestEffort;
+
+    let report = doctor::run(&cfg).await;
+    assert!(!report.ok());
+    for name in ["git", "repo", "disk", "permissions"] {
+        let finding = report.get(name).expect(name);
+        assert_eq!(
+            finding.status,
+            CheckStatus::Ok,
+            "{name}: {}",
+            finding.detail
+        );
+    }
+    for (name, status) in [
+        ("api_keys", CheckStatus::Fail),
+        ("port", CheckStatus::Fail),
+        ("remote", CheckStatus::Warn),
+    ] {
+        let finding = report.get(name).expect(name);
+        assert_eq!(finding.status, status, "{name}: {}", finding.detail);
+        assert!(finding.fix.is_some(), "{name} has no fix");
+    }
+    let port = report.get("port").expect("port");
+    assert!(port.detail.contains(&taken.to_string()), "{}", port.detail);
+    assert!(
+        report
+            .summary()
+            .ends_with("2 problem(s) would stop serve\n")
+    );
+
+    let _state = preflight::build_state(self::cfg(dir.path())).expect("state");
+    let running = doctor::run(&cfg).await;
+    assert_eq!(
+        running.get("port").expect("port").status,
+        CheckStatus::Skipped
+    );
+}
+
+#[test]
+fn init_provisions_a_host_once() {
+    let tmp = tempfile::tempdir().expect("tempdir");
+    let cmd = InitCmd {
+        dir: tmp.path().join("srv data"),
+        bind: "127.0.0.1:8099".parse().expect("bind"),
+        user: Some("lanpaste".to_string()),
+        unit_path: None,
+        exe: Some("/usr/local/bin/lanpaste".into()),
+        git_author_name: "LAN Paste".to_string(),
+        git_author_email: "paste@lan".to_string(),
+    };
+    let report = init::run(&cmd).expect("init");
+    let dir = std::fs::canonicalize(&cmd.dir).expect("dir");
+    assert!(dir.join("repo/.git").exists());
+    assert_eq!(report.written.len(), 3);
+    let admin_key = report.admin_key.clone().expect("new admin key");
+    assert!(admin_key.starts_with("lp_"));
+
+    let keys = dir.join(init::API_KEYS_FILE);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&keys).expect("keys").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    let unit = std::fs::read_to_string(&report.unit).expect("unit");
+    assert_eq!(report.unit, dir.join(init::UNIT_FILE));
+    let exec = format!(
+        "ExecStart=/usr/local/bin/lanpaste serve --dir \"{0}\" --bind 127.0.0.1:8099 --api-keys-file \"{1}\" --config-file \"{2}\"\n",
+        dir.display(),
+        keys.display(),
+        dir.join(init::CONFIG_FILE).display()
+    );
+    assert!(unit.contains(&exec), "{unit}");
+    assert!(unit.contains("User=lanpaste\n"));
+
+    let mut cfg = cfg(&dir);
+    cfg.api_keys_file = Some(keys.clone());
+    cfg.config_file = Some(dir.join(init::CONFIG_FILE));
+    let state = preflight::build_state(cfg).expect("serve accepts the generated files");
+    assert!(state.api_keys.enabled());
+    drop(state);
+
+    let again = init::run(&cmd).expect("init again");
+    assert!(again.written.is_empty() && again.admin_key.is_none());
+    assert_eq!(again.kept.len(), 3);
+    let file = std::fs::read_to_string(&keys).expect("keys");
+    assert!(file.contains(&admin_key));
+}
+
+#[test]
+fn migrate_upgrades_legacy_layout_with_backup() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let dir = root.path().join("data");
+    let cfg = cfg(&dir);
+    preflight::run_preflight(&cfg).expect("preflight");
+    let repo = dir.join("repo");
+
+    let input = CreatePasteInput {
+        name: Some("old-note.md".to_string()),
+        msg: None,
+        tag: None,
+        content_type: Some("text/markdown".to_string()),
+        body: PasteBody::Bytes(b"# from long ago".to_vec()),
+        e2e: false,
+        visibility: Visibility::Listed,
+        allow_keys: Vec::new(),
+        slug: None,
+        client_ip: None,
+        user_agent: None,
+        owner: None,
+        lang: None,
+    };
+    let draft = store::build_paste_draft(&repo, &cfg, None, input).expect("draft");
+    gitops::commit_paste(&repo, &cfg, &draft, PushMode::Off, "origin").expect("commit");
+
+    // Rewind to an older release: no slug/short maps, state loose in run/.
+    let mut meta: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&draft.meta_path).expect("meta")).expect("json");
+    let obj = meta.as_object_mut().expect("object");
+    obj.remove("slug");
+    obj.remove("short_id");
+    std::fs::write(&draft.meta_path, serde_json::to_vec(&meta).expect("json")).expect("write");
+    gitops::run_git(&repo, &["add", &draft.meta_rel_path], &cfg).expect("add");
+    gitops::commit_removal(
+        &repo,
+        &cfg,
+        &[
+            &draft.slug_rel_path,
+            draft.short_rel_path.as_deref().expect("short id"),
+        ],
+        "legacy",
+        PushMode::Off,
+        "origin",
+    )
+    .expect("strip maps");
+    std::fs::remove_dir_all(repo.join("short")).expect("drop short dir");
+    let run = dir.join("run");
+    std::fs::create_dir_all(run.join("leases")).expect("leases dir");
+    std::fs::create_dir_all(run.join("idempotency")).expect("idempotency dir");
+    std::fs::write(run.join("idempotency").join("abc.json"), b"{}").expect("record");
+    let lease = PasteLease {
+        id: draft.id.clone(),
+        lease: "L1".to_string(),
+        holder: Some("editor".to_string()),
+        expires_at: time::OffsetDateTime::now_utc() + time::Duration::minutes(10),
+    };
+    std::fs::write(
+        run.join("leases").join(format!("{}.json", draft.id)),
+        serde_json::to_vec(&lease).expect("json"),
+    )
+    .expect("lease");
+
+    let backup = root.path().join("backup");
+    let report = migrate::run_migrate(&MigrateCmd {
+        from: dir.clone(),
+        backup: Some(backup.clone()),
+        git_author_name: "LAN Paste".to_string(),
+        git_author_email: "paste@lan".to_string(),
+    })
+    .expect("migrate");
+    assert!(report.ok(), "{}", report.summary());
+    assert_eq!(report.leases_moved, 1);
+    assert_eq!(report.idempotency_retired, 1);
+    assert_eq!((report.slugs_added, report.short_ids_added), (1, 1));
+    assert_eq!(report.pastes_verified, 1);
+    assert!(report.commit.is_some());
+
+    assert!(backup.join("run/idempotency/abc.json").exists());
+    assert!(!run.join("idempotency").exists() && !run.join("leases").exists());
+    let meta = store::read_meta(&repo, &cfg, &draft.id).expect("meta");
+    assert_eq!(meta.slug.as_deref(), Some("old-note"));
+    let short = meta.short_id.expect("short id");
+    assert_eq!(
+        store::resolve_short_id(&repo, &short).expect("resolve"),
+        Some(draft.id.clone())
+    );
+    let kv = Kv::open(run.join("kv"));
+    assert!(store::check_lease(&kv, &draft.id, None).is_err());
+    assert!(store::check_lease(&kv, &draft.id, Some("L1")).is_ok());
+
+    // A second run finds nothing left to do.
+    let again = migrate::run_migrate(&MigrateCmd {
+        from: dir.clone(),
+        backup: Some(root.path().join("backup-2")),
+        git_author_name: "LAN Paste".to_string(),
+        git_author_email: "paste@lan".to_string(),
+    })
+    .expect("migrate again");
+    assert!(again.ok() && again.commit.is_none());
+}
+
+#[test]
+fn pushed_follows_the_remote_tracking_branch() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let repo = dir.path().join("repo");
+    let remote = dir.path().join("remote.git");
+    git(
+        dir.path(),
+        &["init", "-q", "--bare", remote.to_str().expect("utf8")],
+    );
+    git(
+        &repo,
+        &["remote", "add", "origin", remote.to_str().expect("utf8")],
+    );
+
+    let local = gitops::commit_paste(
+        &repo,
+        &cfg,
+        &paste_draft(&repo, &cfg, "local.txt"),
+        PushMode::Off,
+        "origin",
+    )
+    .expect("commit");
+    assert!(!local.pushed);
+    cfg.push = PushMode::BestEffort;
+    assert!(!gitops::is_pushed(&repo, &cfg, &local.commit));
+    // The bootstrap commit and this one.
+    assert_eq!(gitops::unpushed_count(&repo, &cfg).expect("count"), 2);
+
+    let shared = gitops::commit_paste(
+        &repo,
+        &cfg,
+        &paste_draft(&repo, &cfg, "shared.txt"),
+        PushMode::BestEffort,
+        "origin",
+    )
+    .expect("commit");
+    assert!(shared.pushed);
+    assert!(gitops::is_pushed(&repo, &cfg, &shared.commit));
+    // Pushing HEAD carried the earlier commit along.
+    assert!(gitops::is_pushed(&repo, &cfg, &local.commit));
+    assert_eq!(gitops::unpushed_count(&repo, &cfg).expect("count"), 0);
+
+    cfg.push = PushMode::Off;
+    assert!(!gitops::is_pushed(&repo, &cfg, &shared.commit));
+}
+
+#[test]
+fn paste_index_catches_up_with_commits_made_outside_the_server() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let state = preflight::build_state(cfg).expect("state");
+    let repo = state.paths.repo.clone();
+    let listed = |state: &lanpaste::types::AppState| {
+        state
+            .index
+            .recent_with_commits(&repo, &state.cfg, 10, None, None, false)
+            .expect("recent")
+    };
+    assert_eq!(
+        gitops::head_oid(&repo).as_deref(),
+        Some(git(&repo, &["rev-parse", "HEAD"]).as_str())
+    );
+
+    let draft = paste_draft(&repo, &state.cfg, "pulled.txt");
+    let commit =
+        gitops::commit_paste(&repo, &state.cfg, &draft, PushMode::Off, "origin").expect("commit");
+    assert!(listed(&state).is_empty());
+    state.index.sync(&repo, &state.cfg);
+    let items = listed(&state);
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].id, draft.id);
+    assert!(commit.commit.starts_with(&items[0].commit));
+
+    git(&repo, &["pack-refs", "--all"]);
+    assert_eq!(
+        gitops::head_oid(&repo).as_deref(),
+        Some(git(&repo, &["rev-parse", "HEAD"]).as_str())
+    );
+    git(&repo, &["rm", "-q", &draft.rel_path, &draft.meta_rel_path]);
+    gitops::run_git(
+        &repo,
+        &["commit", "-q", "-m", "drop pulled paste"],
+        &state.cfg,
+    )
+    .expect("commit");
+    state.index.sync(&repo, &state.cfg);
+    assert!(listed(&state).is_empty());
+}
+
+#[test]
+fn startup_backfills_paste_commits_in_one_walk() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cfg = cfg(dir.path());
+    preflight::run_preflight(&cfg).expect("preflight");
+    let repo = dir.path().join("repo");
+    let drafts: Vec<PasteDraft> = ["one.txt", "two.txt"]
+        .iter()
+        .map(|name| {
+            let draft = paste_draft(&repo, &cfg, name);
+            gitops::commit_paste(&repo, &cfg, &draft, PushMode::Off, "origin").expect("commit");
+            draft
+        })
+        .collect();
+
+    let state = preflight::build_state(cfg).expect("state");
+    for draft in &drafts {
+        let expected = git(
+            &repo,
+            &["log", "-n", "1", "--format=%H", "--", &draft.rel_path],
+        );
+        let commit = state
+            .index
+            .commit_of(&draft.id, &draft.meta.sha256)
+            .expect("backfilled");
+        assert_eq!(commit, expected[..12]);
+    }
+    let none = HashSet::from(["pastes/never.txt".to_string()]);
+    assert!(gitops::last_commits(&repo, &none).expect("walk").is_empty());
+}